@@ -1,9 +1,31 @@
 use crate::frontend::Token;
+use crate::intern::Symbol;
 use crate::span::Span;
-use crate::types::{Function, Scope, Type, Variable};
+use crate::types::{Enum, Function, Newtype, Scope, Type, TypeParam, Variable};
 use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::Rc;
 
+/// Unique identifier assigned by the parser to every expression and statement.
+///
+/// Ids are stable across passes, so analyses can keep their results in side
+/// tables instead of mutating the tree. A node that replaces another (e.g. a
+/// folded constant) takes over the id of the node it replaced, while nodes a
+/// pass adds or copies get ids from [`Program::next_node_id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    /// Returns this id and moves on to the next one, for a counter of the
+    /// ids not given out yet
+    pub fn take(&mut self) -> NodeId {
+        let id = *self;
+        self.0 += 1;
+        id
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Block {
     pub statements: Vec<Statement>,
@@ -27,11 +49,13 @@ pub enum Expression {
         value: f64,
         span: Span,
         typ: Option<Type>,
+        id: NodeId,
     },
     Boolean {
         value: bool,
         span: Span,
         typ: Option<Type>,
+        id: NodeId,
     },
     BinaryOp {
         left: Box<Expression>,
@@ -39,27 +63,84 @@ pub enum Expression {
         right: Box<Expression>,
         span: Span,
         typ: Option<Type>,
+        id: NodeId,
     },
     UnaryOp {
         left: Box<Expression>,
         op: Token,
         span: Span,
         typ: Option<Type>,
+        id: NodeId,
     },
     Call {
-        identifier: String, //@TODO : In the future this should be an expression to allow for higher-order functions.
+        identifier: Symbol, //@TODO : In the future this should be an expression to allow for higher-order functions.
         args: Vec<Expression>,
         span: Span,
         typ: Option<Type>,
+        id: NodeId,
     },
     Variable {
         name: String,
         span: Span,
         typ: Option<Type>,
-    }
+        id: NodeId,
+    },
+    /// `Enum::Variant`
+    Variant {
+        enum_name: String,
+        variant: String,
+        span: Span,
+        typ: Option<Type>,
+        id: NodeId,
+    },
+    /// `expression as T`: an explicit conversion between a newtype and
+    /// its underlying type
+    Cast {
+        expression: Box<Expression>,
+        target: Type,
+        span: Span,
+        typ: Option<Type>,
+        id: NodeId,
+    },
+    /// Stands in for code that did not parse. It has no type, and the
+    /// typechecker reports nothing about it.
+    Error {
+        span: Span,
+        id: NodeId,
+    },
 }
 
 impl Expression {
+    /// Get the node id of this expression
+    pub fn id(&self) -> NodeId {
+        match self {
+            Expression::Number { id, .. } => *id,
+            Expression::Boolean { id, .. } => *id,
+            Expression::BinaryOp { id, .. } => *id,
+            Expression::UnaryOp { id, .. } => *id,
+            Expression::Call { id, .. } => *id,
+            Expression::Variable { id, .. } => *id,
+            Expression::Variant { id, .. } => *id,
+            Expression::Cast { id, .. } => *id,
+            Expression::Error { id, .. } => *id,
+        }
+    }
+
+    /// The node id, for a pass to give a copy an id of its own
+    pub fn id_mut(&mut self) -> &mut NodeId {
+        match self {
+            Expression::Number { id, .. } => id,
+            Expression::Boolean { id, .. } => id,
+            Expression::BinaryOp { id, .. } => id,
+            Expression::UnaryOp { id, .. } => id,
+            Expression::Call { id, .. } => id,
+            Expression::Variable { id, .. } => id,
+            Expression::Variant { id, .. } => id,
+            Expression::Cast { id, .. } => id,
+            Expression::Error { id, .. } => id,
+        }
+    }
+
     /// Get the type of this expression (set by typechecker)
     pub fn typ(&self) -> &Option<Type> {
         match self {
@@ -69,10 +150,51 @@ impl Expression {
             Expression::UnaryOp { typ, .. } => typ,
             Expression::Call { typ, .. } => typ,
             Expression::Variable { typ, .. } => typ,
+            Expression::Variant { typ, .. } => typ,
+            Expression::Cast { typ, .. } => typ,
+            Expression::Error { .. } => &None,
         }
     }
 }
 
+/// `likely(c)` and `unlikely(c)`: built-in functions that return their
+/// condition and tell the compiler which way it usually goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchHint {
+    Likely,
+    Unlikely,
+}
+
+impl BranchHint {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "likely" => Some(BranchHint::Likely),
+            "unlikely" => Some(BranchHint::Unlikely),
+            _ => None,
+        }
+    }
+}
+
+/// An annotation written before a function definition, e.g. `@inline` or
+/// `@name(args)`. Which attributes exist is decided by the typechecker.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<Expression>,
+    pub span: Span,
+}
+
+/// An attribute written before a statement other than a function
+/// definition, which only `@allow` may be
+#[derive(Debug, Clone)]
+pub struct StatementAttribute {
+    pub attribute: Attribute,
+    /// The statement the attribute applies to
+    pub span: Span,
+    /// Name of the source the statement is in
+    pub source: String,
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     Assignment {
@@ -80,14 +202,21 @@ pub enum Statement {
         typ: Option<Type>,
         right: Option<Box<Expression>>,
         span: Span,
+        id: NodeId,
     },
 
     FunctionDefinition {
         name: String,
+        type_params: Vec<TypeParam>,
         args: Vec<Variable>,
         return_type: Type,
+        attrs: Vec<Attribute>,
         body: Block,
+        /// Declared with `extern fn`, defined outside the program. The
+        /// body is empty.
+        external: bool,
         span: Span,
+        id: NodeId,
     },
 
     If {
@@ -95,32 +224,428 @@ pub enum Statement {
         then: Block,
         els: Option<Block>,
         span: Span,
+        id: NodeId,
     },
 
+    /// `while cond { ... }`, or `do { ... } while cond` if `do_while` is
+    /// set, in which case the body runs once before the condition is tested
     While {
         condition: Box<Expression>,
         body: Block,
+        do_while: bool,
         span: Span,
+        id: NodeId,
     },
 
     Block {
         block: Block,
         span: Span,
+        id: NodeId,
     },
 
     Return {
         expression: Option<Box<Expression>>,
         span: Span,
+        id: NodeId,
+    },
+
+    Match {
+        scrutinee: Box<Expression>,
+        arms: Vec<MatchArm>,
+        span: Span,
+        id: NodeId,
     },
 
     Expression {
         expression: Box<Expression>,
         span: Span,
+        id: NodeId,
+    },
+
+    /// `assert condition` or `assert condition, "message"`: stops the
+    /// program if the condition is false
+    Assert {
+        condition: Box<Expression>,
+        message: Option<String>,
+        span: Span,
+        id: NodeId,
+    },
+
+    /// `newtype Name = T`, only allowed at the top level
+    Newtype {
+        name: String,
+        underlying: Type,
+        span: Span,
+        id: NodeId,
+    },
+
+    /// `enum Name { A, B, ... }`, only allowed at the top level
+    Enum {
+        name: String,
+        variants: Vec<String>,
+        span: Span,
+        id: NodeId,
+    },
+
+    /// Stands in for a statement that did not parse; the parser skipped
+    /// ahead to the next statement
+    Error {
+        span: Span,
+        id: NodeId,
     },
 }
 
+impl Statement {
+    /// Get the node id of this statement
+    pub fn id(&self) -> NodeId {
+        match self {
+            Statement::Assignment { id, .. } => *id,
+            Statement::FunctionDefinition { id, .. } => *id,
+            Statement::If { id, .. } => *id,
+            Statement::While { id, .. } => *id,
+            Statement::Block { id, .. } => *id,
+            Statement::Return { id, .. } => *id,
+            Statement::Match { id, .. } => *id,
+            Statement::Expression { id, .. } => *id,
+            Statement::Assert { id, .. } => *id,
+            Statement::Newtype { id, .. } => *id,
+            Statement::Enum { id, .. } => *id,
+            Statement::Error { id, .. } => *id,
+        }
+    }
+
+    /// The node id, for a pass to give a copy an id of its own
+    pub fn id_mut(&mut self) -> &mut NodeId {
+        match self {
+            Statement::Assignment { id, .. } => id,
+            Statement::FunctionDefinition { id, .. } => id,
+            Statement::If { id, .. } => id,
+            Statement::While { id, .. } => id,
+            Statement::Block { id, .. } => id,
+            Statement::Return { id, .. } => id,
+            Statement::Match { id, .. } => id,
+            Statement::Expression { id, .. } => id,
+            Statement::Assert { id, .. } => id,
+            Statement::Newtype { id, .. } => id,
+            Statement::Enum { id, .. } => id,
+            Statement::Error { id, .. } => id,
+        }
+    }
+
+    /// Get the source span of this statement
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Assignment { span, .. } => *span,
+            Statement::FunctionDefinition { span, .. } => *span,
+            Statement::If { span, .. } => *span,
+            Statement::While { span, .. } => *span,
+            Statement::Block { span, .. } => *span,
+            Statement::Return { span, .. } => *span,
+            Statement::Match { span, .. } => *span,
+            Statement::Expression { span, .. } => *span,
+            Statement::Assert { span, .. } => *span,
+            Statement::Newtype { span, .. } => *span,
+            Statement::Enum { span, .. } => *span,
+            Statement::Error { span, .. } => *span,
+        }
+    }
+}
+
+/// One `pattern => { ... }` arm of a match statement
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// Patterns a match arm can test the scrutinee against
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Number(f64),
+    Boolean(bool),
+    /// `Enum::Variant`
+    Variant { enum_name: String, variant: String },
+    /// `_`, matches any value
+    Wildcard,
+}
+
 #[derive(Debug)]
 pub struct Program {
     pub globals: Vec<Variable>,
     pub functions: Vec<Function>,
+    /// Functions declared with `extern fn`, which are defined outside the
+    /// program and have empty bodies
+    pub externs: Vec<Function>,
+    pub newtypes: Vec<Newtype>,
+    pub enums: Vec<Enum>,
+    /// Generic functions. The typechecker moves them here from `functions`
+    /// once it has added their instantiations, so later passes only see
+    /// functions with concrete types.
+    pub generics: Vec<Function>,
+    /// Attributes of statements inside functions and at the top level
+    pub statement_attrs: Vec<StatementAttribute>,
+    /// The first id no node of the program has
+    pub next_node_id: NodeId,
+}
+
+// Structural equality: two nodes are equal if they are the same code.
+// Spans, node ids, the types the typechecker sets on expressions and block
+// scopes are ignored, while types written in the source are compared.
+// Numbers are compared by their bits, so `0.0` and `-0.0` differ and a NaN
+// equals itself, which keeps equality consistent with hashing.
+
+/// Returns true if the two expressions are the same code, wherever they
+/// appear in the program
+pub fn structural_eq(a: &Expression, b: &Expression) -> bool {
+    a == b
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        use Expression as E;
+        match (self, other) {
+            (E::Number { value: a, .. }, E::Number { value: b, .. }) => a.to_bits() == b.to_bits(),
+            (E::Boolean { value: a, .. }, E::Boolean { value: b, .. }) => a == b,
+            (
+                E::BinaryOp { left: l1, op: o1, right: r1, .. },
+                E::BinaryOp { left: l2, op: o2, right: r2, .. },
+            ) => o1.tag == o2.tag && l1 == l2 && r1 == r2,
+            (E::UnaryOp { left: a, op: o1, .. }, E::UnaryOp { left: b, op: o2, .. }) => {
+                o1.tag == o2.tag && a == b
+            }
+            (E::Call { identifier: f, args: a, .. }, E::Call { identifier: g, args: b, .. }) => {
+                f == g && a == b
+            }
+            (E::Variable { name: a, .. }, E::Variable { name: b, .. }) => a == b,
+            (
+                E::Variant { enum_name: e1, variant: v1, .. },
+                E::Variant { enum_name: e2, variant: v2, .. },
+            ) => e1 == e2 && v1 == v2,
+            (E::Cast { expression: a, target: t1, .. }, E::Cast { expression: b, target: t2, .. }) => {
+                t1 == t2 && a == b
+            }
+            (E::Error { .. }, E::Error { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Expression {}
+
+impl Hash for Expression {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Expression::Number { value, .. } => value.to_bits().hash(state),
+            Expression::Boolean { value, .. } => value.hash(state),
+            Expression::BinaryOp { left, op, right, .. } => {
+                op.tag.hash(state);
+                left.hash(state);
+                right.hash(state);
+            }
+            Expression::UnaryOp { left, op, .. } => {
+                op.tag.hash(state);
+                left.hash(state);
+            }
+            Expression::Call { identifier, args, .. } => {
+                identifier.hash(state);
+                args.hash(state);
+            }
+            Expression::Variable { name, .. } => name.hash(state),
+            Expression::Variant { enum_name, variant, .. } => {
+                enum_name.hash(state);
+                variant.hash(state);
+            }
+            Expression::Cast { expression, target, .. } => {
+                target.hash(state);
+                expression.hash(state);
+            }
+            Expression::Error { .. } => {}
+        }
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        use Statement as S;
+        match (self, other) {
+            (
+                S::Assignment { left: n1, typ: t1, right: r1, .. },
+                S::Assignment { left: n2, typ: t2, right: r2, .. },
+            ) => n1 == n2 && t1 == t2 && r1 == r2,
+            (
+                S::FunctionDefinition {
+                    name: n1,
+                    type_params: p1,
+                    args: a1,
+                    return_type: t1,
+                    attrs: at1,
+                    body: b1,
+                    external: e1,
+                    ..
+                },
+                S::FunctionDefinition {
+                    name: n2,
+                    type_params: p2,
+                    args: a2,
+                    return_type: t2,
+                    attrs: at2,
+                    body: b2,
+                    external: e2,
+                    ..
+                },
+            ) => n1 == n2 && p1 == p2 && a1 == a2 && t1 == t2 && at1 == at2 && b1 == b2 && e1 == e2,
+            (
+                S::If { condition: c1, then: t1, els: e1, .. },
+                S::If { condition: c2, then: t2, els: e2, .. },
+            ) => c1 == c2 && t1 == t2 && e1 == e2,
+            (
+                S::While { condition: c1, body: b1, do_while: d1, .. },
+                S::While { condition: c2, body: b2, do_while: d2, .. },
+            ) => c1 == c2 && b1 == b2 && d1 == d2,
+            (S::Block { block: a, .. }, S::Block { block: b, .. }) => a == b,
+            (S::Return { expression: a, .. }, S::Return { expression: b, .. }) => a == b,
+            (
+                S::Match { scrutinee: s1, arms: a1, .. },
+                S::Match { scrutinee: s2, arms: a2, .. },
+            ) => s1 == s2 && a1 == a2,
+            (S::Expression { expression: a, .. }, S::Expression { expression: b, .. }) => a == b,
+            (
+                S::Assert { condition: c1, message: m1, .. },
+                S::Assert { condition: c2, message: m2, .. },
+            ) => c1 == c2 && m1 == m2,
+            (
+                S::Newtype { name: n1, underlying: u1, .. },
+                S::Newtype { name: n2, underlying: u2, .. },
+            ) => n1 == n2 && u1 == u2,
+            (S::Enum { name: n1, variants: v1, .. }, S::Enum { name: n2, variants: v2, .. }) => {
+                n1 == n2 && v1 == v2
+            }
+            (S::Error { .. }, S::Error { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Statement {}
+
+impl Hash for Statement {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Statement::Assignment { left, typ, right, .. } => {
+                left.hash(state);
+                typ.hash(state);
+                right.hash(state);
+            }
+            Statement::FunctionDefinition {
+                name,
+                type_params,
+                args,
+                return_type,
+                attrs,
+                body,
+                external,
+                ..
+            } => {
+                name.hash(state);
+                type_params.hash(state);
+                args.hash(state);
+                return_type.hash(state);
+                attrs.hash(state);
+                body.hash(state);
+                external.hash(state);
+            }
+            Statement::If { condition, then, els, .. } => {
+                condition.hash(state);
+                then.hash(state);
+                els.hash(state);
+            }
+            Statement::While { condition, body, do_while, .. } => {
+                condition.hash(state);
+                body.hash(state);
+                do_while.hash(state);
+            }
+            Statement::Block { block, .. } => block.hash(state),
+            Statement::Return { expression, .. } => expression.hash(state),
+            Statement::Match { scrutinee, arms, .. } => {
+                scrutinee.hash(state);
+                arms.hash(state);
+            }
+            Statement::Expression { expression, .. } => expression.hash(state),
+            Statement::Assert { condition, message, .. } => {
+                condition.hash(state);
+                message.hash(state);
+            }
+            Statement::Newtype { name, underlying, .. } => {
+                name.hash(state);
+                underlying.hash(state);
+            }
+            Statement::Enum { name, variants, .. } => {
+                name.hash(state);
+                variants.hash(state);
+            }
+            Statement::Error { .. } => {}
+        }
+    }
+}
+
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements == other.statements
+    }
+}
+
+impl Eq for Block {}
+
+impl Hash for Block {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.statements.hash(state);
+    }
+}
+
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args
+    }
+}
+
+impl Eq for Attribute {}
+
+impl Hash for Attribute {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.args.hash(state);
+    }
+}
+
+/// `Pattern`'s own equality takes `0.0` and `-0.0` to be the same number,
+/// as matching does, so arms compare patterns by bits instead
+impl PartialEq for MatchArm {
+    fn eq(&self, other: &Self) -> bool {
+        let same_pattern = match (&self.pattern, &other.pattern) {
+            (Pattern::Number(a), Pattern::Number(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        same_pattern && self.body == other.body
+    }
+}
+
+impl Eq for MatchArm {}
+
+impl Hash for MatchArm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(&self.pattern).hash(state);
+        match &self.pattern {
+            Pattern::Number(value) => value.to_bits().hash(state),
+            Pattern::Boolean(value) => value.hash(state),
+            Pattern::Variant { enum_name, variant } => {
+                enum_name.hash(state);
+                variant.hash(state);
+            }
+            Pattern::Wildcard => {}
+        }
+        self.body.hash(state);
+    }
 }
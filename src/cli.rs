@@ -1,48 +1,452 @@
-use crate::frontend::{LexerContext, ParserContext};
-use crate::hir::passes::ast_simplification::ASTSimplificationPass;
-use crate::hir::passes::counting::CountingPass;
-use crate::hir::passes::lowering::LoweringPass;
-use crate::hir::passes::print::PrintPass;
-use crate::hir::passes::typechecking::TypecheckingPass;
-use crate::hir::visitor::Visitor;
-use crate::mir::passes::print::MirPrintingPass;
-use crate::mir::passes::ssa::MirSSAPass;
-use crate::mir::visitor::MirVisitor;
+use crate::compiler::{Artifact, Compiler, DivisionByZero, OptLevel, SsaForm, StopPolicy, Target};
+use crate::codegen::triple::TargetTriple;
+use crate::coverage::{COVERAGE_FILE, decode_counts, encode_counts, render_report};
+use crate::frontend::{ComparisonChains, DEFAULT_MAX_NESTING_DEPTH};
+use crate::diagnostics::{Diagnostic, DiagnosticCollector, Severity};
+use crate::fix::{applicable_suggestions, apply_suggestions, unified_diff};
+use crate::manifest::{MANIFEST_FILE, Manifest};
+use crate::mir::analysis::escape::EscapeAnalysis;
+use crate::mir::history::{diff_mir, print_mir};
+use crate::registry::{DEFAULT_HIR_PASSES, DEFAULT_MIR_PASSES};
+use crate::term::{ColorChoice, Painter, Stream};
+use crate::testgen::ProgramGenerator;
+use crate::testing::run_tests_with_coverage;
 use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-/// Helper function to print diagnostics from a HIR visitor
-fn print_diagnostics<V: Visitor>(visitor: &V) {
-    let diagnostics = visitor.diagnostics();
+/// How many errors are printed unless `--error-limit` says otherwise
+const DEFAULT_ERROR_LIMIT: usize = 20;
 
-    // Print errors
-    for error in &diagnostics.errors {
-        eprintln!("Error: {}", error);
-    }
+/// How often `--watch` looks at the inputs for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What the CLI was asked to do with its inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Compile and print the requested stages
+    Compile,
+    /// Compile and run every `@test` function
+    Test,
+    /// Typecheck without simplifying or lowering, optionally fixing what
+    /// can be fixed
+    Check,
+    /// Print the sources with the coverage counts of an earlier run
+    CoverageReport,
+    /// Print what one pass changed in the MIR
+    IrDiff,
+    /// Print a generated program, or check that many of them compile and
+    /// optimize correctly
+    Testgen,
+}
+
+/// How errors and warnings are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    /// Readable lines, with suggestions as help below them
+    Human,
+    /// One JSON object per line, for tools
+    Json,
+}
+
+/// Options parsed from the command line
+struct CliOptions {
+    command: Command,
+    inputs: Vec<String>,
+    targets: Vec<Target>,
+    opt_level: OptLevel,
+    passes: Option<Vec<String>>,
+    ssa_form: SsaForm,
+    max_nesting_depth: usize,
+    fast_math: bool,
+    division_by_zero: DivisionByZero,
+    runtime_checks: bool,
+    coverage: bool,
+    verify_opts: bool,
+    dump_escape: bool,
+    keep_ir_history: bool,
+    /// The pass `ir-diff` shows the changes of
+    pass: Option<String>,
+    /// The first program `testgen` generates, 0 unless given
+    seed: Option<u64>,
+    /// How many programs `testgen` checks, if it is not to print one
+    count: Option<u64>,
+    debug_info: bool,
+    script_mode: bool,
+    comparison_chains: ComparisonChains,
+    fix: bool,
+    dry_run: bool,
+    error_format: ErrorFormat,
+    error_limit: Option<usize>,
+    stop_policy: StopPolicy,
+    watch: bool,
+    color: ColorChoice,
+    out_dir: Option<String>,
+    output: Option<String>,
+    target_triple: Option<TargetTriple>,
+    scheduling: bool,
+}
 
-    // Print warnings
-    for warning in &diagnostics.warnings {
-        eprintln!("Warning: {}", warning);
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {} [build|test|check|cov report|ir-diff] [options] <input-file>...\n       \
+                {} testgen [--seed <n>] [--count <n>] [options]\n\
+         Commands:\n  \
+           build              Compile and print the requested stages (the default)\n  \
+           test               Run the @test functions and report which of them fail\n  \
+           check              Report problems without compiling\n  \
+           cov report         Print the inputs with how often each line ran, from the {} of a --coverage run\n  \
+           ir-diff            Print what the pass given with --pass changed in the MIR, each time it ran\n  \
+           testgen            Print a random program, or with --count, check that programs from that many seeds\n                     \
+                              compile and run the same once optimized, as with --verify-opts\n\
+         Options:\n  \
+           --emit <tokens|tokens-json|ast|spans|mir|asm|obj|rust>\n                     \
+                              Print the given stage (may be repeated, default: mir unless -o is given)\n  \
+           -o <file>          Build an executable that runs the program's main\n  \
+           --target <triple>  Generate code for the given machine and system, as in x86_64-unknown-linux-gnu\n                     \
+                              (default: the host)\n  \
+           --out-dir <dir>    Write each emitted stage to a file in the directory instead of printing it,\n                     \
+                              along with a manifest.json listing the inputs and the files written\n  \
+           -O0, -O1           Set the optimization level (default: -O1)\n  \
+           -Osat              Optimize as -O1 and rewrite float arithmetic by equality saturation\n                     \
+                              (needs iris built with the 'saturation' feature)\n  \
+           --passes <name,...>\n                     \
+                              Optimize with these passes, in this order, instead of those of the optimization level\n                     \
+                              (the passes of -O1: {})\n  \
+           --ssa <phi|block-params>\n                     \
+                              Represent SSA merges with phi nodes or block parameters (default: phi)\n  \
+           --max-nesting-depth <n>\n                     \
+                              Reject expressions and blocks nested deeper than this (default: {})\n  \
+           --no-schedule      Keep machine instructions in the order they were selected in\n  \
+           --fast-math        Allow float optimizations that assume no NaN or infinities and may change rounding\n  \
+           --division-by-zero <trap|undefined>\n                     \
+                              What integer division and remainder by zero do at runtime (default: trap)\n  \
+           --checks           Stop the program when a float operation produces NaN or a value is read before it is written\n  \
+           --coverage         Count how often each line runs and write the counts to {} when the program ends\n  \
+           --verify-opts      Run main and the @test functions before and after optimizing, and fail if they differ\n  \
+           --dump-escape      Print the registers of each function whose value may escape it, once optimized\n  \
+           --pass <name>      With ir-diff, the pass to show the changes of, as in ssa, cse or dead-code\n  \
+           --seed <n>         With testgen, the seed of the (first) program (default: 0)\n  \
+           --count <n>        With testgen, check the programs of this many seeds instead of printing one\n  \
+           --keep-ir-history  With --out-dir, also write the MIR as each pass left it\n  \
+           -g, --debug-info   Annotate printed MIR with the source location of every instruction\n  \
+           --script           Run statements outside of functions as the body of an implicit main\n  \
+           --comparison-chains <reject|desugar>\n                     \
+                              Reject comparisons chained as a < b < c, or read them as a < b && b < c (default: reject)\n  \
+           --fix              With check, apply the suggested fixes that are known to be correct\n  \
+           --dry-run          With --fix, print the fixes as a diff instead of writing them\n  \
+           --error-format <human|json>\n                     \
+                              Print errors and warnings as text or as one JSON object per line (default: human)\n  \
+           --error-limit <n>  Stop printing errors after this many, 0 for no limit (default: {})\n  \
+           --stop-on <error|warning>\n                     \
+                              Which problems stop compilation after the passes that check for them (default: error)\n  \
+           --watch            Run the command again whenever an input file changes\n  \
+           --color <never|auto|always>\n                     \
+                              Color the output, auto colors it on a terminal unless NO_COLOR is set (default: auto)",
+        program,
+        program,
+        COVERAGE_FILE,
+        DEFAULT_HIR_PASSES.iter().chain(DEFAULT_MIR_PASSES).copied().collect::<Vec<_>>().join(","),
+        DEFAULT_MAX_NESTING_DEPTH, COVERAGE_FILE, DEFAULT_ERROR_LIMIT
+    )
+}
+
+fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut options = CliOptions {
+        command: Command::Compile,
+        inputs: Vec::new(),
+        targets: Vec::new(),
+        opt_level: OptLevel::default(),
+        passes: None,
+        ssa_form: SsaForm::default(),
+        max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+        fast_math: false,
+        division_by_zero: DivisionByZero::default(),
+        runtime_checks: false,
+        coverage: false,
+        verify_opts: false,
+        dump_escape: false,
+        keep_ir_history: false,
+        pass: None,
+        seed: None,
+        count: None,
+        debug_info: false,
+        script_mode: false,
+        comparison_chains: ComparisonChains::default(),
+        fix: false,
+        dry_run: false,
+        error_format: ErrorFormat::Human,
+        error_limit: Some(DEFAULT_ERROR_LIMIT),
+        stop_policy: StopPolicy::default(),
+        watch: false,
+        color: ColorChoice::default(),
+        out_dir: None,
+        output: None,
+        target_triple: None,
+        scheduling: true,
+    };
+
+    let mut iter = args.iter().skip(1).peekable();
+    if iter.next_if(|arg| *arg == "build").is_some() {
+        options.command = Command::Compile;
+    } else if iter.next_if(|arg| *arg == "test").is_some() {
+        options.command = Command::Test;
+    } else if iter.next_if(|arg| *arg == "check").is_some() {
+        options.command = Command::Check;
+    } else if iter.next_if(|arg| *arg == "cov").is_some() {
+        iter.next_if(|arg| *arg == "report")
+            .ok_or("Expected 'report' after 'cov'")?;
+        options.command = Command::CoverageReport;
+    } else if iter.next_if(|arg| *arg == "ir-diff").is_some() {
+        options.command = Command::IrDiff;
+    } else if iter.next_if(|arg| *arg == "testgen").is_some() {
+        options.command = Command::Testgen;
+    }
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--emit" => {
+                let value = iter.next().ok_or("Expected a value after '--emit'")?;
+                for stage in value.split(',') {
+                    let target = match stage {
+                        "tokens" => Target::Tokens,
+                        "tokens-json" => Target::TokensJson,
+                        "ast" => Target::Ast,
+                        "spans" => Target::Spans,
+                        "mir" => Target::Mir,
+                        "asm" => Target::Asm,
+                        "obj" => Target::Object,
+                        "rust" => Target::Rust,
+                        other => return Err(format!("Unknown emit target '{}'", other)),
+                    };
+                    options.targets.push(target);
+                }
+            }
+            "--ssa" => {
+                let value = iter.next().ok_or("Expected a value after '--ssa'")?;
+                options.ssa_form = match value.as_str() {
+                    "phi" => SsaForm::Phi,
+                    "block-params" => SsaForm::BlockParams,
+                    other => return Err(format!("Unknown SSA form '{}'", other)),
+                };
+            }
+            "--max-nesting-depth" => {
+                let value = iter
+                    .next()
+                    .ok_or("Expected a value after '--max-nesting-depth'")?;
+                options.max_nesting_depth = value
+                    .parse()
+                    .map_err(|_| format!("Invalid nesting depth '{}'", value))?;
+            }
+            "--fast-math" => options.fast_math = true,
+            "--no-schedule" => options.scheduling = false,
+            "--division-by-zero" => {
+                let value = iter
+                    .next()
+                    .ok_or("Expected a value after '--division-by-zero'")?;
+                options.division_by_zero = match value.as_str() {
+                    "trap" => DivisionByZero::Trap,
+                    "undefined" => DivisionByZero::Undefined,
+                    other => return Err(format!("Unknown division by zero behavior '{}'", other)),
+                };
+            }
+            "--checks" => options.runtime_checks = true,
+            "--coverage" => options.coverage = true,
+            "--verify-opts" => options.verify_opts = true,
+            "--dump-escape" => options.dump_escape = true,
+            "--keep-ir-history" => options.keep_ir_history = true,
+            "--pass" => {
+                let value = iter.next().ok_or("Expected a value after '--pass'")?;
+                options.pass = Some(value.clone());
+            }
+            "--seed" => {
+                let value = iter.next().ok_or("Expected a value after '--seed'")?;
+                options.seed = Some(value.parse().map_err(|_| format!("Invalid seed '{}'", value))?);
+            }
+            "--count" => {
+                let value = iter.next().ok_or("Expected a value after '--count'")?;
+                options.count = Some(value.parse().map_err(|_| format!("Invalid count '{}'", value))?);
+            }
+            "-g" | "--debug-info" => options.debug_info = true,
+            "--script" => options.script_mode = true,
+            "--comparison-chains" => {
+                let value = iter
+                    .next()
+                    .ok_or("Expected a value after '--comparison-chains'")?;
+                options.comparison_chains = match value.as_str() {
+                    "reject" => ComparisonChains::Reject,
+                    "desugar" => ComparisonChains::Desugar,
+                    other => return Err(format!("Unknown comparison chain handling '{}'", other)),
+                };
+            }
+            "--fix" => options.fix = true,
+            "--dry-run" => options.dry_run = true,
+            "--watch" => options.watch = true,
+            "-o" => {
+                let value = iter.next().ok_or("Expected a value after '-o'")?;
+                options.output = Some(value.clone());
+            }
+            "--target" => {
+                let value = iter.next().ok_or("Expected a value after '--target'")?;
+                options.target_triple = Some(TargetTriple::parse(value)?);
+            }
+            "--out-dir" => {
+                let value = iter.next().ok_or("Expected a value after '--out-dir'")?;
+                options.out_dir = Some(value.clone());
+            }
+            "--color" => {
+                let value = iter.next().ok_or("Expected a value after '--color'")?;
+                options.color = parse_color(value)?;
+            }
+            flag if flag.starts_with("--color=") => {
+                options.color = parse_color(&flag["--color=".len()..])?;
+            }
+            "--stop-on" => {
+                let value = iter.next().ok_or("Expected a value after '--stop-on'")?;
+                options.stop_policy = match value.as_str() {
+                    "error" => StopPolicy::OnError,
+                    "warning" => StopPolicy::OnWarning,
+                    other => return Err(format!("Unknown stop policy '{}'", other)),
+                };
+            }
+            "--error-limit" => {
+                let value = iter.next().ok_or("Expected a value after '--error-limit'")?;
+                let limit: usize = value
+                    .parse()
+                    .map_err(|_| format!("Invalid error limit '{}'", value))?;
+                options.error_limit = (limit > 0).then_some(limit);
+            }
+            "--error-format" => {
+                let value = iter.next().ok_or("Expected a value after '--error-format'")?;
+                options.error_format = match value.as_str() {
+                    "human" => ErrorFormat::Human,
+                    "json" => ErrorFormat::Json,
+                    other => return Err(format!("Unknown error format '{}'", other)),
+                };
+            }
+            "--passes" => {
+                let value = iter.next().ok_or("Expected a value after '--passes'")?;
+                options.passes = Some(
+                    value
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                );
+            }
+            "-O0" => options.opt_level = OptLevel::O0,
+            "-O1" => options.opt_level = OptLevel::O1,
+            "-Osat" if cfg!(feature = "saturation") => options.opt_level = OptLevel::Osat,
+            "-Osat" => return Err("'-Osat' needs iris built with the 'saturation' feature".to_string()),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            input => options.inputs.push(input.to_string()),
+        }
     }
 
-    // Print info
-    for info in &diagnostics.info {
-        println!("Info: {}", info);
+    if options.command == Command::Testgen {
+        if !options.inputs.is_empty() {
+            return Err("'testgen' takes no input files".to_string());
+        }
+        if options.watch {
+            return Err("'--watch' cannot be used with 'testgen'".to_string());
+        }
+    } else if options.inputs.is_empty() {
+        return Err("No input files".to_string());
+    }
+    if (options.seed.is_some() || options.count.is_some()) && options.command != Command::Testgen {
+        return Err("'--seed' and '--count' can only be used with 'testgen'".to_string());
+    }
+    if options.fix && options.command != Command::Check {
+        return Err("'--fix' can only be used with 'check'".to_string());
+    }
+    if options.coverage && options.command == Command::Check {
+        return Err("'--coverage' cannot be used with 'check'".to_string());
+    }
+    if options.command == Command::IrDiff && options.pass.is_none() {
+        return Err("'ir-diff' needs the pass to show given with '--pass'".to_string());
+    }
+    if options.pass.is_some() && options.command != Command::IrDiff {
+        return Err("'--pass' can only be used with 'ir-diff'".to_string());
+    }
+    if options.keep_ir_history && options.out_dir.is_none() {
+        return Err("'--keep-ir-history' can only be used with '--out-dir'".to_string());
+    }
+    if options.dry_run && !options.fix {
+        return Err("'--dry-run' can only be used with '--fix'".to_string());
+    }
+    // Fixes write the inputs, which would start the next run
+    if options.watch && options.fix && !options.dry_run {
+        return Err("'--watch' cannot be used with '--fix' unless '--dry-run' is given".to_string());
+    }
+    if options.out_dir.is_some() && options.command != Command::Compile {
+        return Err("'--out-dir' can only be used when compiling".to_string());
+    }
+    if options.output.is_some() && options.command != Command::Compile {
+        return Err("'-o' can only be used when compiling".to_string());
+    }
+    if options.target_triple.is_some() && options.command != Command::Compile {
+        return Err("'--target' can only be used when compiling".to_string());
+    }
+    // Object files are not for terminals
+    if options.targets.contains(&Target::Object) && options.out_dir.is_none() {
+        return Err("'--emit obj' can only be used with '--out-dir'".to_string());
     }
+    if options.targets.is_empty() && options.output.is_none() {
+        options.targets.push(Target::Mir);
+    }
+    Ok(options)
 }
 
-/// Helper function to print diagnostics from a MIR visitor
-fn print_mir_diagnostics<V: MirVisitor>(visitor: &V) {
-    let diagnostics = visitor.diagnostics();
+fn parse_color(value: &str) -> Result<ColorChoice, String> {
+    match value {
+        "never" => Ok(ColorChoice::Never),
+        "auto" => Ok(ColorChoice::Auto),
+        "always" => Ok(ColorChoice::Always),
+        other => Err(format!("Unknown color choice '{}'", other)),
+    }
+}
 
-    // Print errors
-    for error in &diagnostics.errors {
-        eprintln!("Error: {}", error);
+/// Prints the errors and warnings of collected diagnostics
+fn print_problems(diagnostics: &DiagnosticCollector, format: ErrorFormat, painter: Painter) {
+    let problems = diagnostics
+        .errors
+        .iter()
+        .map(|error| (Severity::Error, error))
+        .chain(diagnostics.warnings.iter().map(|warning| (Severity::Warning, warning)));
+    for (severity, problem) in problems {
+        print_problem(problem, severity, format, painter);
+    }
+    if diagnostics.suppressed_errors > 0 {
+        let summary = Diagnostic::new(format!(
+            "{} more error(s) not shown, raise --error-limit to see them",
+            diagnostics.suppressed_errors
+        ));
+        print_problem(&summary, Severity::Error, format, painter);
     }
+}
 
-    // Print warnings
-    for warning in &diagnostics.warnings {
-        eprintln!("Warning: {}", warning);
+fn print_problem(problem: &Diagnostic, severity: Severity, format: ErrorFormat, painter: Painter) {
+    if format == ErrorFormat::Json {
+        eprintln!("{}", problem.to_json(severity));
+        return;
+    }
+    match severity {
+        Severity::Error => eprintln!("{} {}", painter.error("Error:"), problem),
+        Severity::Warning => eprintln!("{} {}", painter.warning("Warning:"), problem),
+    }
+    for note in &problem.notes {
+        eprintln!("  {} {}", painter.note("Note:"), note);
     }
+    for suggestion in &problem.suggestions {
+        eprintln!("  {} {}", painter.note("Help:"), suggestion.message);
+    }
+}
+
+/// Helper function to print collected diagnostics
+fn print_diagnostics(diagnostics: &DiagnosticCollector, format: ErrorFormat, painter: Painter) {
+    print_problems(diagnostics, format, painter);
 
     // Print info
     for info in &diagnostics.info {
@@ -53,88 +457,450 @@ fn print_mir_diagnostics<V: MirVisitor>(visitor: &V) {
 /// Runs the compiler CLI with the given command-line arguments.
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
+    let program_name = args.first().map(String::as_str).unwrap_or("iris");
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, usage(program_name));
+            std::process::exit(1);
+        }
+    };
+
+    if options.watch {
+        return watch(&options);
+    }
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <input-file>", args[0]);
+    // Tools reading JSON get the reason for failing as one more error
+    let result = execute(&options);
+    if let Err(e) = &result
+        && options.error_format == ErrorFormat::Json
+    {
+        print_problem(
+            &Diagnostic::new(e.to_string()),
+            Severity::Error,
+            options.error_format,
+            Painter::default(),
+        );
         std::process::exit(1);
     }
+    result
+}
 
-    let filename = &args[1];
+/// A compiler configured as the options ask, without any sources
+fn configure(options: &CliOptions) -> Compiler {
+    let mut compiler = Compiler::new();
+    compiler
+        .set_opt_level(options.opt_level)
+        .set_passes(options.passes.clone())
+        .set_ssa_form(options.ssa_form)
+        .set_max_nesting_depth(options.max_nesting_depth)
+        .set_fast_math(options.fast_math)
+        .set_scheduling(options.scheduling)
+        .set_division_by_zero(options.division_by_zero)
+        .set_runtime_checks(options.runtime_checks)
+        .set_coverage(options.coverage || options.command == Command::CoverageReport)
+        .set_verify_opts(options.verify_opts)
+        .set_debug_info(options.debug_info)
+        .set_script_mode(options.script_mode)
+        .set_comparison_chains(options.comparison_chains)
+        .set_error_limit(options.error_limit)
+        .set_stop_policy(options.stop_policy)
+        .set_color(options.out_dir.is_none() && options.color.enabled_for(Stream::Stdout));
+    if let Some(triple) = &options.target_triple {
+        compiler.set_target_triple(triple.clone());
+    }
+    compiler
+}
 
-    // Read the input file
-    let input = fs::read_to_string(filename)
-        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+/// Compiles, tests or checks the inputs as the options ask
+fn execute(options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut compiler = configure(options);
+    for filename in &options.inputs {
+        compiler
+            .add_file(filename)
+            .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    }
+    execute_with(&compiler, options)
+}
 
-    // Lex the input
-    let tokens = LexerContext::lex(&input).map_err(|e| {
-        format!(
-            "Lexing error at line {}, column {}: {}",
-            e.row, e.column, e.message
-        )
-    })?;
-
-    // Parse the tokens
-    let mut parser = ParserContext::new(tokens);
-    let mut program = parser
-        .parse()
-        .map_err(|e| format!("Parse error: {}", e.message))?;
-
-    // Run counting pass
-    let mut counting_pass = CountingPass::new();
-    counting_pass.visit_program(&mut program);
-    print_diagnostics(&counting_pass);
-    if counting_pass.diagnostics().has_errors() {
-        return Err("Compilation failed due to errors".into());
+/// Runs the command of the options on the sources of the compiler
+fn execute_with(compiler: &Compiler, options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.command == Command::Test {
+        return test(compiler, options);
+    }
+    if options.command == Command::Check {
+        return check(compiler, options);
+    }
+    if options.command == Command::CoverageReport {
+        return coverage_report(compiler, options);
+    }
+    if options.command == Command::IrDiff {
+        return ir_diff(compiler, options);
+    }
+    if options.command == Command::Testgen {
+        return testgen(options);
     }
 
-    // Run print pass
-    let mut print_pass = PrintPass::new();
-    print_pass.visit_program(&mut program);
-    print_diagnostics(&print_pass);
-    if print_pass.diagnostics().has_errors() {
-        return Err("Compilation failed due to errors".into());
+    // A manifest left from an earlier run must not describe this one's files
+    let mut manifest = None;
+    if let Some(dir) = &options.out_dir {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory '{}': {}", dir, e))?;
+        let path = Path::new(dir).join(MANIFEST_FILE);
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove '{}': {}", path.display(), e))?;
+        }
+        let mut inputs = Manifest::new();
+        for source in compiler.sources() {
+            inputs.add_input(&source.name, source.text.as_bytes());
+        }
+        manifest = Some(inputs);
     }
 
-    // Run AST simplification pass (constant folding, boolean folding, etc.)
-    let mut ast_simplification_pass = ASTSimplificationPass::new();
-    ast_simplification_pass.visit_program(&mut program);
-    print_diagnostics(&ast_simplification_pass);
-    if ast_simplification_pass.diagnostics().has_errors() {
-        return Err("Compilation failed due to errors".into());
+    for target in &options.targets {
+        let result = match (&options.out_dir, &mut manifest) {
+            (Some(dir), Some(manifest)) => {
+                let mut contents = Vec::new();
+                let result = compiler.emit_to(*target, &mut contents);
+                if result.is_ok() {
+                    let path = output_path(dir, options, *target);
+                    fs::write(&path, &contents)
+                        .map_err(|e| format!("Failed to write file '{}': {}", path, e))?;
+                    manifest.add_output(path, target.name(), &contents);
+                }
+                result
+            }
+            _ => compiler.emit(*target),
+        };
+        print_diagnostics(&result.diagnostics, options.error_format, stderr_painter(options));
+        if !result.is_ok() {
+            return Err("Compilation failed due to errors".into());
+        }
+
+        if let Some(Artifact::Mir(mir)) = &result.output {
+            println!("\nMIR: Generated {} functions", mir.functions.len());
+            for func in &mir.functions {
+                println!(
+                    "  Function: {} ({} blocks, {} registers)",
+                    func.name,
+                    func.arena.len(),
+                    func.reg_types.len()
+                );
+            }
+        }
+    }
+
+    if options.dump_escape {
+        let result = compiler.lower();
+        // Problems were already printed along with the emitted stages
+        if options.targets.is_empty() || !result.is_ok() {
+            print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+        }
+        let Some(mir) = &result.output else {
+            return Err("Compilation failed due to errors".into());
+        };
+        println!("\nEscape: values that may leave their function");
+        for func in &mir.functions {
+            let escaping: Vec<String> = EscapeAnalysis::new(func)
+                .escaping()
+                .map(|reg| format!("r{}", reg))
+                .collect();
+            let escaping = if escaping.is_empty() { "none".to_string() } else { escaping.join(", ") };
+            println!("  Function: {} ({})", func.name, escaping);
+        }
+    }
+
+    if let Some(output) = &options.output {
+        let result = compiler.build_executable(Path::new(output));
+        // Problems were already printed along with the emitted stages
+        if options.targets.is_empty() || !result.is_ok() {
+            print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+        }
+        if !result.is_ok() {
+            return Err("Compilation failed due to errors".into());
+        }
+        if let Some(manifest) = &mut manifest {
+            let contents = fs::read(output).map_err(|e| format!("Failed to read file '{}': {}", output, e))?;
+            manifest.add_output(output, "executable", &contents);
+        }
+    }
+
+    if let (Some(dir), Some(manifest)) = (&options.out_dir, &mut manifest)
+        && options.keep_ir_history
+    {
+        let result = compiler.ir_history();
+        if let Some(history) = result.output {
+            for (index, snapshot) in history.snapshots.iter().enumerate() {
+                let name = format!("{:02}-{}", index, snapshot.pass);
+                let path = history_path(dir, options, &name);
+                let contents = print_mir(&snapshot.program, options.debug_info);
+                fs::write(&path, &contents).map_err(|e| format!("Failed to write file '{}': {}", path, e))?;
+                manifest.add_output(path, "mir", contents.as_bytes());
+            }
+        }
+    }
+
+    if let (Some(dir), Some(manifest)) = (&options.out_dir, &manifest) {
+        let path = Path::new(dir).join(MANIFEST_FILE);
+        fs::write(&path, manifest.to_json())
+            .map_err(|e| format!("Failed to write file '{}': {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// The file a target is written to with `--out-dir`, named after the first
+/// input
+fn output_path(dir: &str, options: &CliOptions, target: Target) -> String {
+    Path::new(dir)
+        .join(format!("{}.{}", output_stem(options), target.extension()))
+        .display()
+        .to_string()
+}
+
+/// The file the MIR after a pass is written to with `--keep-ir-history`
+fn history_path(dir: &str, options: &CliOptions, name: &str) -> String {
+    Path::new(dir)
+        .join(format!("{}.{}.mir", output_stem(options), name))
+        .display()
+        .to_string()
+}
+
+fn output_stem(options: &CliOptions) -> String {
+    options
+        .inputs
+        .first()
+        .and_then(|input| Path::new(input).file_stem())
+        .map_or("out".into(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// How errors and warnings are styled
+fn stderr_painter(options: &CliOptions) -> Painter {
+    Painter::for_stream(options.color, Stream::Stderr)
+}
+
+/// Runs the command, then runs it again every time an input file is saved,
+/// clearing the screen first so only the problems of the latest run are
+/// shown. The same compiler is kept between runs and given the new text of
+/// the inputs, so only the lines that changed are lexed again. Failures are
+/// printed and do not stop watching; this only returns if the terminal
+/// cannot be written to.
+fn watch(options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut compiler = configure(options);
+    let mut modified = vec![None; options.inputs.len()];
+    loop {
+        print!("\x1b[2J\x1b[H");
+        let mut readable = true;
+        for (filename, modified) in options.inputs.iter().zip(&mut modified) {
+            *modified = modification_time(filename);
+            match fs::read_to_string(filename) {
+                Ok(text) => {
+                    compiler.update_source(filename, text);
+                }
+                Err(e) => {
+                    let message = format!("Failed to read file '{}': {}", filename, e);
+                    print_problem(
+                        &Diagnostic::new(message),
+                        Severity::Error,
+                        options.error_format,
+                        stderr_painter(options),
+                    );
+                    readable = false;
+                }
+            }
+        }
+        if readable && let Err(e) = execute_with(&compiler, options) {
+            print_problem(
+                &Diagnostic::new(e.to_string()),
+                Severity::Error,
+                options.error_format,
+                stderr_painter(options),
+            );
+        }
+        println!("\nWatching {} file(s) for changes...", options.inputs.len());
+        io::stdout().flush()?;
+
+        while options
+            .inputs
+            .iter()
+            .zip(&modified)
+            .all(|(filename, modified)| modification_time(filename) == *modified)
+        {
+            thread::sleep(WATCH_INTERVAL);
+        }
     }
-    // Run typechecking pass
-    let mut typechecking_pass = TypecheckingPass::new();
-    typechecking_pass.visit_program(&mut program);
-    print_diagnostics(&typechecking_pass);
-    if typechecking_pass.diagnostics().has_errors() {
+}
+
+/// When a file was last written, or `None` if that cannot be told
+fn modification_time(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Typechecks the program and prints its problems. With `--fix`, applies
+/// the machine-applicable suggestions to the input files, or with
+/// `--dry-run` prints them as a diff. The program is not simplified first,
+/// so the fixes match the source as written. Fails if the program had
+/// problems that stop compilation, fixed or not.
+fn check(compiler: &Compiler, options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let result = compiler.typecheck();
+    print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+
+    if options.fix {
+        for source in compiler.sources() {
+            let suggestions = applicable_suggestions(&result.diagnostics, &source.name);
+            if suggestions.is_empty() {
+                continue;
+            }
+            let fixed = apply_suggestions(&source.text, &suggestions);
+            if options.dry_run {
+                print!("{}", unified_diff(&source.name, &source.text, &fixed.text));
+            } else {
+                fs::write(&source.name, &fixed.text)
+                    .map_err(|e| format!("Failed to write file '{}': {}", source.name, e))?;
+                println!("Fixed {} problem(s) in '{}'", fixed.applied, source.name);
+            }
+        }
+    }
+
+    if !result.is_ok() {
         return Err("Compilation failed due to errors".into());
     }
+    Ok(())
+}
 
-    // Lower HIR to MIR
-    let mut lowering_pass = LoweringPass::new();
-    let mut mir = lowering_pass.lower(&mut program);
-    print_diagnostics(&lowering_pass);
-    if lowering_pass.diagnostics().has_errors() {
+/// Compiles the program and runs its tests, printing one line per test and
+/// a summary. Fails if the program does not compile or any test fails.
+fn test(compiler: &Compiler, options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let result = compiler.lower();
+    print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+    let Some(mir) = result.output.filter(|_| !result.diagnostics.has_errors()) else {
         return Err("Compilation failed due to errors".into());
+    };
+
+    let painter = Painter::for_stream(options.color, Stream::Stdout);
+    let (results, counts) = run_tests_with_coverage(&mir);
+    for result in &results {
+        match &result.failure {
+            None => println!("test {} ... {}", result.name, painter.success("ok")),
+            Some(reason) => println!("test {} ... {}: {}", result.name, painter.error("FAILED"), reason),
+        }
     }
 
-    // Convert MIR to SSA
-    let mut ssa_pass = MirSSAPass::new();
-    ssa_pass.convert(&mut mir);
-    print_mir_diagnostics(&ssa_pass);
-    if ssa_pass.diagnostics().has_errors() {
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failed == 0 { painter.success("ok") } else { painter.error("FAILED") },
+        results.len() - failed,
+        failed
+    );
+    if compiler.coverage() {
+        fs::write(COVERAGE_FILE, encode_counts(&counts))
+            .map_err(|e| format!("Failed to write file '{}': {}", COVERAGE_FILE, e))?;
+    }
+    if failed > 0 {
+        return Err(format!("{} test(s) failed", failed).into());
+    }
+    Ok(())
+}
+
+/// Prints the inputs with how often each line ran, from the counts a
+/// program built from them with `--coverage` wrote. Fails if the program
+/// does not compile or has another number of counters than were written.
+fn coverage_report(compiler: &Compiler, options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let result = compiler.lower();
+    print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+    let Some(mir) = result.output.filter(|_| !result.diagnostics.has_errors()) else {
         return Err("Compilation failed due to errors".into());
+    };
+
+    let bytes = fs::read(COVERAGE_FILE).map_err(|e| format!("Failed to read file '{}': {}", COVERAGE_FILE, e))?;
+    let counts = decode_counts(&bytes)?;
+    if counts.len() != mir.coverage.len() {
+        return Err(format!(
+            "'{}' has {} count(s) but the program has {} counter(s), was it written by a build of other sources?",
+            COVERAGE_FILE,
+            counts.len(),
+            mir.coverage.len()
+        )
+        .into());
     }
+    for source in compiler.sources() {
+        print!("{}", render_report(&mir.coverage, &counts, source));
+    }
+    Ok(())
+}
 
-   let mut mir_print_pass = MirPrintingPass::new();
-   mir_print_pass.visit_program(&mut mir);
-   print_mir_diagnostics(&mir_print_pass);
+/// Prints what the pass given with `--pass` changed in the MIR, as a
+/// unified diff for each time it ran. Fails if the program does not
+/// compile or the pass never ran on it.
+/// Prints the program generated from `--seed`, or with `--count`, compiles
+/// the programs of that many seeds from it with `--verify-opts` and reports
+/// each one the compiler rejects, optimizes into something else or panics on
+fn testgen(options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let first = options.seed.unwrap_or(0);
+    let Some(count) = options.count else {
+        print!("{}", ProgramGenerator::new(first).generate());
+        return Ok(());
+    };
 
-   println!("\nMIR: Generated {} functions", mir.functions.len());
-   for func in &mir.functions {
-       println!("  Function: {} ({} blocks)", func.name, func.arena.len());
-   }
+    // Panics are reported with the seed instead
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failed = 0;
+    for seed in first..first.saturating_add(count) {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut compiler = configure(options);
+            compiler
+                .set_verify_opts(true)
+                .add_source(format!("testgen-{}.iris", seed), ProgramGenerator::new(seed).generate());
+            compiler.lower().diagnostics.errors.first().map(|error| error.to_string())
+        }));
+        let failure = outcome.unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Some(format!("the compiler panicked: {}", message))
+        });
+        if let Some(failure) = failure {
+            eprintln!("seed {}: {}", seed, failure);
+            failed += 1;
+        }
+    }
+    panic::set_hook(hook);
+
+    println!("Checked {} generated program(s), {} failed", count, failed);
+    if failed > 0 {
+        return Err(format!("{} generated program(s) failed", failed).into());
+    }
+    Ok(())
+}
 
+fn ir_diff(compiler: &Compiler, options: &CliOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let result = compiler.ir_history();
+    print_problems(&result.diagnostics, options.error_format, stderr_painter(options));
+    let Some(history) = result.output.filter(|_| !result.diagnostics.has_errors()) else {
+        return Err("Compilation failed due to errors".into());
+    };
 
+    let pass = options.pass.as_deref().unwrap_or_default();
+    let runs = history.runs(pass);
+    if runs.is_empty() {
+        return Err(format!(
+            "No pass named '{}' ran, the passes that did are: {}",
+            pass,
+            history.passes().join(", ")
+        )
+        .into());
+    }
+    for (before, after) in runs {
+        let diff = diff_mir(pass, before, after, options.debug_info);
+        if diff.is_empty() {
+            println!("Pass '{}' did not change the MIR", pass);
+        } else {
+            print!("{}", diff);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,149 @@
+//! Calling conventions: where the arguments and the result of a call go.
+//!
+//! A [`CallingConvention`] describes the convention of one target, and
+//! [`CallingConvention::signature`] applies it to the parameter and return
+//! types of a MIR function. Registers are given by their number in the
+//! architecture's encoding, so the description does not depend on how a
+//! backend names them. Both sides of a call use the same [`Signature`],
+//! which is what keeps callers and callees of different backends in
+//! agreement.
+
+use super::triple::{Arch, Os, TargetTriple};
+use crate::mir::MirType;
+
+/// The kind of register a value is passed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegClass {
+    /// General purpose registers, for integers and booleans
+    Int,
+    /// Floating point and vector registers
+    Float,
+}
+
+impl RegClass {
+    pub fn of(typ: MirType) -> Self {
+        match typ {
+            MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64 => RegClass::Float,
+            _ => RegClass::Int,
+        }
+    }
+}
+
+/// Where one argument or result is passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// The register with this number in the architecture's encoding
+    Reg(RegClass, u8),
+    /// Bytes above the stack pointer at the call
+    Stack(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallingConvention {
+    pub name: &'static str,
+    /// Registers of integer arguments, in order
+    pub int_args: &'static [u8],
+    /// Registers of float arguments, in order
+    pub float_args: &'static [u8],
+    pub int_return: u8,
+    pub float_return: u8,
+    /// Bytes each argument passed on the stack takes
+    pub stack_slot_size: u32,
+    /// Alignment of the stack pointer at a call
+    pub stack_alignment: u32,
+}
+
+/// The System V convention for x86-64, used by every x86-64 system but
+/// Windows: `rdi`, `rsi`, `rdx`, `rcx`, `r8` and `r9` for integers, `xmm0`
+/// to `xmm7` for floats
+pub const SYSTEM_V_X86_64: CallingConvention = CallingConvention {
+    name: "sysv64",
+    int_args: &[7, 6, 2, 1, 8, 9],
+    float_args: &[0, 1, 2, 3, 4, 5, 6, 7],
+    int_return: 0,
+    float_return: 0,
+    stack_slot_size: 8,
+    stack_alignment: 16,
+};
+
+/// The Arm convention for AArch64: `x0` to `x7` for integers, `v0` to `v7`
+/// for floats
+pub const AAPCS64: CallingConvention = CallingConvention {
+    name: "aapcs64",
+    int_args: &[0, 1, 2, 3, 4, 5, 6, 7],
+    float_args: &[0, 1, 2, 3, 4, 5, 6, 7],
+    int_return: 0,
+    float_return: 0,
+    stack_slot_size: 8,
+    stack_alignment: 16,
+};
+
+/// Where the arguments and the result of calls to a function go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Location>,
+    /// `None` for functions that return nothing
+    pub ret: Option<Location>,
+    /// Bytes of stack the arguments passed on it take, rounded up to the
+    /// stack alignment
+    pub stack_size: u32,
+}
+
+impl Signature {
+    /// Whether any argument is passed on the stack
+    pub fn uses_stack(&self) -> bool {
+        self.stack_size > 0
+    }
+}
+
+impl CallingConvention {
+    /// The C calling convention of the target, if it is described. Windows
+    /// and Apple's variant for AArch64 pass arguments differently and are
+    /// not.
+    pub fn for_target(triple: &TargetTriple) -> Option<&'static CallingConvention> {
+        match (triple.arch(), triple.os()) {
+            (_, Os::Windows) => None,
+            (Arch::X86_64, _) => Some(&SYSTEM_V_X86_64),
+            (Arch::Aarch64, Os::MacOs) => None,
+            (Arch::Aarch64, _) => Some(&AAPCS64),
+            _ => None,
+        }
+    }
+
+    /// Assigns the parameters of a function to registers while there are
+    /// registers of their class left, and to the stack after that
+    pub fn signature(&self, params: &[MirType], ret: MirType) -> Signature {
+        let (mut ints, mut floats, mut stack) = (0, 0, 0);
+        let params = params
+            .iter()
+            .map(|&typ| {
+                let (next, registers) = match RegClass::of(typ) {
+                    RegClass::Int => (&mut ints, self.int_args),
+                    RegClass::Float => (&mut floats, self.float_args),
+                };
+                match registers.get(*next) {
+                    Some(&number) => {
+                        *next += 1;
+                        Location::Reg(RegClass::of(typ), number)
+                    }
+                    None => {
+                        stack += self.stack_slot_size;
+                        Location::Stack(stack - self.stack_slot_size)
+                    }
+                }
+            })
+            .collect();
+        let ret = match ret {
+            MirType::Void => None,
+            typ => Some(match RegClass::of(typ) {
+                RegClass::Int => Location::Reg(RegClass::Int, self.int_return),
+                RegClass::Float => Location::Reg(RegClass::Float, self.float_return),
+            }),
+        };
+        Signature {
+            params,
+            ret,
+            stack_size: stack.next_multiple_of(self.stack_alignment),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Stack frame layout: where the values a function keeps in memory live.
+//!
+//! Offsets are relative to the frame pointer, which points just below
+//! what the call and the prologue push: the return address and the saved
+//! frame pointer on x86-64, the frame record on AArch64. Slots are handed
+//! out downwards from there as they are asked for, so the size of the frame
+//! is known once the body of the function has been selected, and the
+//! prologue is emitted last.
+
+use std::collections::HashMap;
+
+use super::abi::CallingConvention;
+use crate::mir::Reg;
+
+/// Bytes of the slot of a MIR register, which holds any of its types
+pub const SPILL_SLOT_SIZE: u32 = 8;
+
+#[derive(Debug, Clone)]
+pub struct FrameLayout {
+    /// Bytes of slots handed out so far
+    allocated: u32,
+    /// Alignment of the stack pointer once the frame is set up
+    alignment: u32,
+    /// Bytes the call and the prologue push above the frame pointer
+    linkage_size: u32,
+    spill_slots: HashMap<Reg, i32>,
+}
+
+impl FrameLayout {
+    pub fn new(convention: &CallingConvention, linkage_size: u32) -> Self {
+        FrameLayout {
+            allocated: 0,
+            alignment: convention.stack_alignment,
+            linkage_size,
+            spill_slots: HashMap::new(),
+        }
+    }
+
+    /// Reserves `size` bytes aligned to `align`, returning their offset
+    pub fn allocate(&mut self, size: u32, align: u32) -> i32 {
+        self.allocated = (self.allocated + size).next_multiple_of(align);
+        -(self.allocated as i32)
+    }
+
+    /// Offset of the slot a MIR register is kept in, reserved the first
+    /// time it is asked for
+    pub fn spill_slot(&mut self, reg: Reg) -> i32 {
+        if let Some(&offset) = self.spill_slots.get(&reg) {
+            return offset;
+        }
+        let offset = self.allocate(SPILL_SLOT_SIZE, SPILL_SLOT_SIZE);
+        self.spill_slots.insert(reg, offset);
+        offset
+    }
+
+    /// Offset of an argument the caller passed on the stack, at the given
+    /// offset of [`super::abi::Location::Stack`]
+    pub fn incoming_argument(&self, offset: u32) -> i32 {
+        (self.linkage_size + offset) as i32
+    }
+
+    /// Bytes the prologue reserves below the frame pointer. With the
+    /// linkage, it keeps the stack pointer aligned for calls.
+    pub fn size(&self) -> u32 {
+        (self.allocated + self.linkage_size).next_multiple_of(self.alignment) - self.linkage_size
+    }
+}
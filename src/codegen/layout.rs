@@ -0,0 +1,69 @@
+//! Block layout: the order the blocks of a function are placed in.
+//!
+//! A jump to the block placed right after it can be left out, so the edges
+//! taken most often should fall through. [`chain_blocks`] joins blocks into
+//! chains the way Pettis and Hansen describe: every block starts as a chain
+//! of its own, and the edges, most frequent first, append the chain that
+//! starts at their target to the chain that ends at their source. The
+//! entry's chain goes first and the others follow, hottest first, which
+//! leaves the blocks that hardly ever run, such as the paths to traps, at
+//! the end.
+//!
+//! How often blocks and edges run comes from
+//! [`crate::mir::analysis::block_frequency`], so from the weights of the
+//! branches.
+
+/// A branch from one block to another, and how often it is expected to be
+/// taken per call
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub frequency: f64,
+}
+
+/// An order of the blocks that starts with block 0. Blocks that fall
+/// through, ending without a jump, stay right before the block after them.
+pub fn chain_blocks(frequencies: &[f64], edges: &[Edge], falls_through: &[bool]) -> Vec<usize> {
+    let count = frequencies.len();
+    let mut chains: Vec<Vec<usize>> = (0..count).map(|block| vec![block]).collect();
+    let mut chain_of: Vec<usize> = (0..count).collect();
+
+    let mut join = |from: usize, to: usize, chains: &mut Vec<Vec<usize>>| {
+        let (head, tail) = (chain_of[from], chain_of[to]);
+        if head == tail || chains[head].last() != Some(&from) || chains[tail].first() != Some(&to) {
+            return;
+        }
+        let moved = std::mem::take(&mut chains[tail]);
+        for &block in &moved {
+            chain_of[block] = head;
+        }
+        chains[head].extend(moved);
+    };
+
+    for block in (0..count.saturating_sub(1)).filter(|&block| falls_through[block]) {
+        join(block, block + 1, &mut chains);
+    }
+    let mut edges: Vec<&Edge> = edges
+        .iter()
+        .filter(|edge| edge.to != 0 && edge.frequency > 0.0)
+        .collect();
+    edges.sort_by(|a, b| b.frequency.total_cmp(&a.frequency));
+    for edge in edges {
+        join(edge.from, edge.to, &mut chains);
+    }
+
+    let heat = |chain: &[usize]| chain.iter().map(|&block| frequencies[block]).fold(0.0, f64::max);
+    let mut rest: Vec<&Vec<usize>> = chains
+        .iter()
+        .filter(|chain| !chain.is_empty() && chain[0] != 0)
+        .collect();
+    rest.sort_by(|a, b| heat(b).total_cmp(&heat(a)).then(a[0].cmp(&b[0])));
+    chains
+        .iter()
+        .filter(|chain| chain.first() == Some(&0))
+        .chain(rest)
+        .flatten()
+        .copied()
+        .collect()
+}
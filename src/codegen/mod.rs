@@ -0,0 +1,24 @@
+//! Code generation: from MIR out of SSA form to machine code, and from
+//! there to object files and executables.
+//!
+//! Instruction selection produces a [`x86_64::MachineProgram`], which is
+//! printed as assembly. Object files and executables are made from that
+//! assembly by the C compiler driver, see [`toolchain`].
+//!
+//! The machine and system to generate code for are named by a
+//! [`triple::TargetTriple`]. x86-64 is the only backend so far, and the
+//! system decides the object file format the assembly is written for.
+//! Where arguments and results go is decided by the target's calling
+//! convention, see [`abi`], rather than by each backend.
+//!
+//! The typed program can also be written as Rust source, see [`rust`],
+//! for use without any native toolchain.
+
+pub mod abi;
+pub mod frame;
+pub mod layout;
+pub mod rust;
+pub mod schedule;
+pub mod toolchain;
+pub mod triple;
+pub mod x86_64;
@@ -0,0 +1,558 @@
+//! Rust source generation: the typed program written as a Rust module.
+//!
+//! The module needs no toolchain but `rustc` and nothing from Iris, so
+//! kernels written in Iris can be built into Rust projects as source, with
+//! `mod kernels;` next to the generated `kernels.rs`. Every function is
+//! `pub`, and so are enums and newtypes, which become type aliases of their
+//! underlying type.
+//!
+//! Values keep the types the compiled program computes in: `f32` stays
+//! `f32`, and `f8`, `f16` and `f64` all become `f64`, since Iris computes
+//! the narrow types in double precision too. Negation is written `0.0 - x`,
+//! as Iris computes it, which is `+0.0` rather than `-0.0` for zero.
+//! Powers are computed in `f64` with `powf` and rounded, also as Iris does.
+//!
+//! Globals become thread-local [`Cell`](std::cell::Cell)s, so each one is
+//! initialized on first use rather than before `main` runs. Extern
+//! functions are declared `safe` in an `extern "C"` block, and calls to
+//! them link against whatever library defines them.
+
+use crate::ast::{Block, BranchHint, Expression, Pattern, Program, Statement};
+use crate::diagnostics::DiagnosticCollector;
+use crate::frontend::TokenType;
+use crate::types::{BaseType, Function, Type};
+use crate::value::{format_f32, format_float};
+use std::collections::HashSet;
+
+/// Lints the generated code would otherwise trip, since it keeps the shape
+/// of the Iris source rather than of idiomatic Rust
+const ALLOWED_LINTS: &[&str] = &[
+    "confusable_idents",
+    "dead_code",
+    "mixed_script_confusables",
+    "non_camel_case_types",
+    "non_snake_case",
+    "non_upper_case_globals",
+    "unreachable_code",
+    "unreachable_patterns",
+    "unused_assignments",
+    "unused_mut",
+    "unused_parens",
+    "uncommon_codepoints",
+    "unused_variables",
+    "while_true",
+    "clippy::all",
+];
+
+/// Words Rust reserves that Iris does not, which names are escaped from
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do",
+    "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "try", "type", "typeof", "union",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Writes a typechecked program as Rust source. The text is collected
+/// rather than written out, see [`RustEmitter::into_output`].
+pub struct RustEmitter {
+    diagnostics: DiagnosticCollector,
+    output: String,
+    indent: usize,
+    globals: HashSet<String>,
+    /// Locals declared in each enclosing block, innermost last
+    scopes: Vec<HashSet<String>>,
+    /// Names the current function assigns to after declaring them
+    reassigned: HashSet<String>,
+}
+
+impl RustEmitter {
+    pub fn new() -> Self {
+        RustEmitter {
+            diagnostics: DiagnosticCollector::new(),
+            output: String::new(),
+            indent: 0,
+            globals: HashSet::new(),
+            scopes: Vec::new(),
+            reassigned: HashSet::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn into_output(self) -> String {
+        self.output
+    }
+
+    pub fn run(&mut self, program: &Program) {
+        self.line("// Generated by `iris --emit rust`");
+        self.line(&format!("#![allow({})]", ALLOWED_LINTS.join(", ")));
+
+        if !program.newtypes.is_empty() {
+            self.line("");
+        }
+        for newtype in &program.newtypes {
+            let underlying = self.rust_type(&newtype.underlying);
+            self.line(&format!("pub type {} = {};", identifier(&newtype.name), underlying));
+        }
+
+        for declared in &program.enums {
+            let variants: Vec<String> = declared.variants.iter().map(|variant| identifier(variant)).collect();
+            self.line("");
+            self.line("#[derive(Clone, Copy, Debug, PartialEq, Eq)]");
+            self.line(&format!("pub enum {} {{ {} }}", identifier(&declared.name), variants.join(", ")));
+        }
+
+        if !program.globals.is_empty() {
+            self.line("");
+            self.line("thread_local! {");
+            self.indent += 1;
+            for global in &program.globals {
+                let typ = self.rust_type(&global.typ);
+                let value = match &global.initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.zero(&global.typ),
+                };
+                self.line(&format!(
+                    "static {}: std::cell::Cell<{}> = std::cell::Cell::new({});",
+                    global_name(&global.name),
+                    typ,
+                    value
+                ));
+                self.globals.insert(global.name.clone());
+            }
+            self.indent -= 1;
+            self.line("}");
+        }
+
+        if !program.externs.is_empty() {
+            self.line("");
+            self.line("unsafe extern \"C\" {");
+            self.indent += 1;
+            for function in &program.externs {
+                let signature = self.signature(function);
+                self.line(&format!("pub safe {};", signature));
+            }
+            self.indent -= 1;
+            self.line("}");
+        }
+
+        for function in &program.functions {
+            self.line("");
+            self.function(function);
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.reassigned.clear();
+        collect_reassigned(&function.body, &mut self.reassigned);
+        self.scopes.push(function.args.iter().map(|arg| arg.name.clone()).collect());
+
+        let signature = self.signature(function);
+        self.line(&format!("pub {} {{", signature));
+        self.indent += 1;
+        for statement in &function.body.statements {
+            self.statement(statement);
+        }
+        // Rust does not know that Iris has checked every path returns, as
+        // a loop that always does
+        let returns = matches!(function.body.statements.last(), Some(Statement::Return { .. }));
+        if !returns && !matches!(function.return_type.underlying(), Type::Base(BaseType::Void)) {
+            self.line("unreachable!()");
+        }
+        self.indent -= 1;
+        self.line("}");
+
+        self.scopes.pop();
+    }
+
+    /// `fn name(params) -> type`, with the parameters the body assigns to
+    /// declared `mut`
+    fn signature(&mut self, function: &Function) -> String {
+        let params: Vec<String> = function
+            .args
+            .iter()
+            .map(|arg| {
+                let binding = if self.reassigned.contains(&arg.name) { "mut " } else { "" };
+                format!("{}{}: {}", binding, identifier(&arg.name), self.rust_type(&arg.typ))
+            })
+            .collect();
+        let returns = match function.return_type.underlying() {
+            Type::Base(BaseType::Void) => String::new(),
+            _ => format!(" -> {}", self.rust_type(&function.return_type)),
+        };
+        format!("fn {}({}){}", function_name(&function.name), params.join(", "), returns)
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashSet::new());
+        self.indent += 1;
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.indent -= 1;
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment { left, typ: Some(declared), right, .. } => {
+                let typ = right.as_ref().and_then(|right| right.typ().clone()).unwrap_or(declared.clone());
+                let value = match right {
+                    Some(right) => self.expression(right),
+                    None => self.zero(&typ),
+                };
+                let binding = if self.reassigned.contains(left) { "mut " } else { "" };
+                let typ = self.rust_type(&typ);
+                self.line(&format!("let {}{}: {} = {};", binding, identifier(left), typ, value));
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(left.clone());
+                }
+            }
+            Statement::Assignment { left, typ: None, right: Some(right), .. } => {
+                let value = self.expression(right);
+                if self.is_global(left) {
+                    self.line(&format!("{}.set({});", global_name(left), value));
+                } else {
+                    self.line(&format!("{} = {};", identifier(left), value));
+                }
+            }
+            Statement::If { condition, then, els, .. } => {
+                let condition = self.expression(condition);
+                self.line(&format!("if {} {{", condition));
+                self.else_chain(then, els.as_ref());
+            }
+            Statement::While { condition, body, do_while: false, .. } => {
+                let condition = self.expression(condition);
+                self.line(&format!("while {} {{", condition));
+                self.block(body);
+                self.line("}");
+            }
+            // The condition sees the variables of the body's scope in Iris,
+            // but not in Rust, so it is tested at the end of the body
+            Statement::While { condition, body, do_while: true, .. } => {
+                self.line("loop {");
+                self.scopes.push(HashSet::new());
+                self.indent += 1;
+                for statement in &body.statements {
+                    self.statement(statement);
+                }
+                let condition = self.operand(condition);
+                self.line(&format!("if !{} {{ break; }}", condition));
+                self.indent -= 1;
+                self.scopes.pop();
+                self.line("}");
+            }
+            Statement::Block { block, .. } => {
+                self.line("{");
+                self.block(block);
+                self.line("}");
+            }
+            Statement::Return { expression: Some(expression), .. } => {
+                let value = self.expression(expression);
+                self.line(&format!("return {};", value));
+            }
+            Statement::Return { expression: None, .. } => self.line("return;"),
+            Statement::Match { scrutinee, arms, .. } => {
+                let typ = scrutinee.typ().as_ref().map(|typ| self.rust_type(typ));
+                let scrutinee = self.expression(scrutinee);
+                self.line(&format!("match {} {{", scrutinee));
+                self.indent += 1;
+                for arm in arms {
+                    let pattern = match &arm.pattern {
+                        Pattern::Number(value) => float_literal(*value, typ.as_deref().unwrap_or("f64")),
+                        Pattern::Boolean(value) => value.to_string(),
+                        Pattern::Variant { enum_name, variant } => {
+                            format!("{}::{}", identifier(enum_name), identifier(variant))
+                        }
+                        Pattern::Wildcard => "_".to_string(),
+                    };
+                    self.line(&format!("{} => {{", pattern));
+                    self.block(&arm.body);
+                    self.line("}");
+                }
+                // Rust wants a wildcard to cover the floats the arms do
+                // not, which Iris also does
+                let floats = typ.as_deref().is_some_and(is_float);
+                if floats && !arms.iter().any(|arm| arm.pattern == Pattern::Wildcard) {
+                    self.line("_ => unreachable!(),");
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            Statement::Expression { expression, .. } => {
+                let value = self.expression(expression);
+                if matches!(**expression, Expression::Call { .. }) {
+                    self.line(&format!("{};", value));
+                } else {
+                    self.line(&format!("let _ = {};", value));
+                }
+            }
+            Statement::Assert { condition, message, .. } => {
+                let condition = self.expression(condition);
+                match message {
+                    Some(message) => self.line(&format!("assert!({}, \"{{}}\", {:?});", condition, message)),
+                    None => self.line(&format!("assert!({});", condition)),
+                }
+            }
+            // Declarations are written at the top level, and nested
+            // functions are not compiled
+            Statement::FunctionDefinition { .. }
+            | Statement::Newtype { .. }
+            | Statement::Enum { .. }
+            | Statement::Assignment { right: None, .. }
+            | Statement::Error { .. } => {}
+        }
+    }
+
+    /// The branches of an `if` whose header is written, with an `else`
+    /// holding only another `if` written as `else if`
+    fn else_chain(&mut self, then: &Block, els: Option<&Block>) {
+        self.block(then);
+        match els.map(|els| els.statements.as_slice()) {
+            Some([Statement::If { condition, then, els, .. }]) => {
+                let condition = self.expression(condition);
+                self.line(&format!("}} else if {} {{", condition));
+                self.else_chain(then, els.as_ref());
+            }
+            Some(_) => {
+                self.line("} else {");
+                self.block(els.unwrap());
+                self.line("}");
+            }
+            None => self.line("}"),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> String {
+        match expression {
+            Expression::Number { value, typ, .. } => {
+                let typ = typ.as_ref().map_or("f64".to_string(), |typ| self.rust_type(typ));
+                float_literal(*value, &typ)
+            }
+            Expression::Boolean { value, .. } => value.to_string(),
+            Expression::Variable { name, .. } => {
+                if self.is_global(name) {
+                    format!("{}.get()", global_name(name))
+                } else {
+                    identifier(name)
+                }
+            }
+            Expression::Variant { enum_name, variant, .. } => {
+                format!("{}::{}", identifier(enum_name), identifier(variant))
+            }
+            Expression::BinaryOp { left, op, right, typ, .. } if op.tag == TokenType::StarStar => {
+                let (left, right) = (self.operand(left), self.operand(right));
+                let typ = typ.as_ref().map_or("f64".to_string(), |typ| self.rust_type(typ));
+                match typ.as_str() {
+                    "f64" => format!("{}.powf({})", left, right),
+                    _ => format!("(f64::from({}).powf(f64::from({})) as {})", left, right, typ),
+                }
+            }
+            Expression::BinaryOp { left, op, right, .. } => {
+                let left = self.operand(left);
+                let right = self.operand(right);
+                let op = match op.tag {
+                    TokenType::Plus => "+",
+                    TokenType::Minus => "-",
+                    TokenType::Star => "*",
+                    TokenType::Slash => "/",
+                    TokenType::Percent => "%",
+                    TokenType::Equal => "==",
+                    TokenType::NotEqual => "!=",
+                    TokenType::Less => "<",
+                    TokenType::LessEqual => "<=",
+                    TokenType::Greater => ">",
+                    TokenType::GreaterEqual => ">=",
+                    TokenType::And => "&&",
+                    TokenType::Or => "||",
+                    _ => {
+                        self.diagnostics
+                            .error(format!("Operator '{}' cannot be written as Rust", op.lexeme));
+                        "?"
+                    }
+                };
+                format!("{} {} {}", left, op, right)
+            }
+            Expression::UnaryOp { left, op, .. } => match op.tag {
+                TokenType::Minus => {
+                    let typ = left.typ().as_ref().map_or("f64".to_string(), |typ| self.rust_type(typ));
+                    format!("{} - {}", float_literal(0.0, &typ), self.operand(left))
+                }
+                TokenType::Bang => format!("!{}", self.operand(left)),
+                _ => self.expression(left),
+            },
+            // The hint only matters to the branch the condition is in
+            Expression::Call { identifier, args, .. } if BranchHint::from_name(identifier.as_str()).is_some() => {
+                self.expression(&args[0])
+            }
+            Expression::Call { identifier, args, .. } => {
+                let args: Vec<String> = args.iter().map(|arg| self.expression(arg)).collect();
+                format!("{}({})", function_name(identifier.as_str()), args.join(", "))
+            }
+            Expression::Cast { expression: inner, typ, .. } => {
+                let from = inner.typ().as_ref().map(|typ| self.rust_type(typ.underlying()));
+                let to = typ.as_ref().map(|typ| self.rust_type(typ.underlying()));
+                if from == to {
+                    return self.expression(inner);
+                }
+                format!("{} as {}", self.operand(inner), to.unwrap_or_default())
+            }
+            Expression::Error { .. } => {
+                self.diagnostics.error("Programs with syntax errors cannot be written as Rust".to_string());
+                String::new()
+            }
+        }
+    }
+
+    /// An expression, in parentheses unless it binds tighter than any
+    /// operator it is an operand of
+    fn operand(&mut self, expression: &Expression) -> String {
+        let text = self.expression(expression);
+        if needs_parens(expression) {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    /// The Rust type values of the type are represented by
+    fn rust_type(&mut self, typ: &Type) -> String {
+        match typ {
+            Type::Base(BaseType::F32) => "f32".to_string(),
+            Type::Base(BaseType::F8 | BaseType::F16 | BaseType::F64) => "f64".to_string(),
+            Type::Base(BaseType::Bool) => "bool".to_string(),
+            Type::Base(BaseType::Void) => "()".to_string(),
+            Type::Newtype { name, .. } | Type::Enum { name, .. } | Type::Named(name) => identifier(name),
+            Type::Base(BaseType::Auto) | Type::PointerType(_) => {
+                self.diagnostics
+                    .error(format!("Values of type {} cannot be written as Rust", typ));
+                "()".to_string()
+            }
+        }
+    }
+
+    /// The value a variable without an initializer starts with
+    fn zero(&mut self, typ: &Type) -> String {
+        match typ.underlying() {
+            Type::Base(BaseType::Bool) => "false".to_string(),
+            Type::Enum { name, variants } => format!("{}::{}", identifier(name), identifier(&variants[0])),
+            typ => float_literal(0.0, &self.rust_type(typ)),
+        }
+    }
+
+    /// Whether the name refers to a global rather than a local that
+    /// shadows it
+    fn is_global(&self, name: &str) -> bool {
+        self.globals.contains(name) && !self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.output.push_str(&"    ".repeat(self.indent));
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+}
+
+impl Default for RustEmitter {
+    fn default() -> Self {
+        RustEmitter::new()
+    }
+}
+
+/// Whether the expression is written with an operator that may bind less
+/// tightly than one it is an operand of
+fn needs_parens(expression: &Expression) -> bool {
+    match expression {
+        Expression::BinaryOp { .. } => true,
+        Expression::UnaryOp { left, op, .. } => match op.tag {
+            TokenType::Minus => true,
+            TokenType::Bang => false,
+            _ => needs_parens(left),
+        },
+        Expression::Call { identifier, args, .. } if BranchHint::from_name(identifier.as_str()).is_some() => {
+            needs_parens(&args[0])
+        }
+        Expression::Cast { expression: inner, typ, .. } => {
+            let representation = |typ: Option<&Type>| typ.map(|typ| typ.underlying().float_width().map(|width| width == 32));
+            representation(inner.typ().as_ref()) != representation(typ.as_ref()) || needs_parens(inner)
+        }
+        Expression::Number { value, .. } => value.is_sign_negative(),
+        _ => false,
+    }
+}
+
+/// Collects the names a block or any block in it assigns to without
+/// declaring them
+fn collect_reassigned(block: &Block, names: &mut HashSet<String>) {
+    for statement in &block.statements {
+        match statement {
+            Statement::Assignment { left, typ: None, .. } => {
+                names.insert(left.clone());
+            }
+            Statement::If { then, els, .. } => {
+                collect_reassigned(then, names);
+                if let Some(els) = els {
+                    collect_reassigned(els, names);
+                }
+            }
+            Statement::While { body: block, .. } | Statement::Block { block, .. } => collect_reassigned(block, names),
+            Statement::Match { arms, .. } => {
+                for arm in arms {
+                    collect_reassigned(&arm.body, names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_float(typ: &str) -> bool {
+    matches!(typ, "f32" | "f64")
+}
+
+/// A float literal of the Rust type, exact for every value the type holds
+fn float_literal(value: f64, typ: &str) -> String {
+    let typ = if is_float(typ) { typ } else { "f64" };
+    if value.is_nan() {
+        format!("{}::NAN", typ)
+    } else if value.is_infinite() {
+        let sign = if value < 0.0 { "NEG_" } else { "" };
+        format!("{}::{}INFINITY", typ, sign)
+    } else if typ == "f32" {
+        format!("{}f32", format_f32(value as f32))
+    } else {
+        format!("{}f64", format_float(value))
+    }
+}
+
+/// A name as a Rust identifier, escaped if Rust reserves it
+fn identifier(name: &str) -> String {
+    match name {
+        // Cannot be raw identifiers
+        "self" | "Self" | "super" | "crate" => format!("{}_", name),
+        _ if RUST_KEYWORDS.contains(&name) => format!("r#{}", name),
+        _ => name.to_string(),
+    }
+}
+
+/// A function name as a Rust identifier. Instantiations of generic
+/// functions are named after their type arguments, so `max<f32>` is
+/// `max_f32`.
+fn function_name(name: &str) -> String {
+    let parts: Vec<&str> = name
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|part| !part.is_empty())
+        .collect();
+    identifier(&parts.join("_"))
+}
+
+/// Statics cannot be shadowed by locals in Rust, so globals are written in
+/// upper case like Rust's own
+fn global_name(name: &str) -> String {
+    name.to_uppercase()
+}
@@ -0,0 +1,116 @@
+//! List scheduling of straight-line machine code.
+//!
+//! A backend describes each instruction of a region by the resources it
+//! reads and writes, registers, flags or memory, and how many cycles its
+//! result takes. [`dependences`] orders any two instructions that touch
+//! the same resource when at least one of them writes it, and
+//! [`list_schedule`] picks, among the instructions whose dependences are
+//! met, the one with the longest chain of latencies after it. That starts
+//! slow operations such as divisions as early as possible, so the
+//! instructions that do not need their result run in their shadow.
+//!
+//! [`estimate_cycles`] simulates an in-order machine that issues one
+//! instruction a cycle and waits for the operands it needs. It is a rough
+//! model, meant for comparing two orders of the same instructions.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// What one instruction does, as far as ordering is concerned
+#[derive(Debug, Clone)]
+pub struct Effects<R> {
+    pub reads: Vec<R>,
+    pub writes: Vec<R>,
+    /// Cycles until the results can be used
+    pub latency: u32,
+}
+
+/// The instructions each instruction has to come after
+pub fn dependences<R: Eq + Hash + Clone>(effects: &[Effects<R>]) -> Vec<Vec<usize>> {
+    let mut last_write: HashMap<R, usize> = HashMap::new();
+    let mut reads_since_write: HashMap<R, Vec<usize>> = HashMap::new();
+    let mut preds = vec![Vec::new(); effects.len()];
+    for (i, effect) in effects.iter().enumerate() {
+        for resource in &effect.reads {
+            // Read after write
+            if let Some(&writer) = last_write.get(resource) {
+                preds[i].push(writer);
+            }
+        }
+        for resource in &effect.writes {
+            // Write after write, and write after read
+            if let Some(&writer) = last_write.get(resource) {
+                preds[i].push(writer);
+            }
+            if let Some(readers) = reads_since_write.get(resource) {
+                preds[i].extend(readers.iter().copied().filter(|&reader| reader != i));
+            }
+        }
+        for resource in &effect.reads {
+            reads_since_write.entry(resource.clone()).or_default().push(i);
+        }
+        for resource in &effect.writes {
+            last_write.insert(resource.clone(), i);
+            reads_since_write.remove(resource);
+        }
+        preds[i].sort_unstable();
+        preds[i].dedup();
+    }
+    preds
+}
+
+/// An order of the instructions that respects their dependences, with
+/// those on the longest path of latencies first. Ties keep the original
+/// order.
+pub fn list_schedule(latencies: &[u32], preds: &[Vec<usize>]) -> Vec<usize> {
+    let count = latencies.len();
+    let mut succs = vec![Vec::new(); count];
+    for (i, preds) in preds.iter().enumerate() {
+        for &pred in preds {
+            succs[pred].push(i);
+        }
+    }
+    // Every dependence points backwards, so one pass from the end suffices
+    let mut height = vec![0; count];
+    for i in (0..count).rev() {
+        let after = succs[i].iter().map(|&succ| height[succ]).max().unwrap_or(0);
+        height[i] = latencies[i] + after;
+    }
+
+    let mut waiting: Vec<usize> = preds.iter().map(Vec::len).collect();
+    let mut ready: Vec<usize> = (0..count).filter(|&i| waiting[i] == 0).collect();
+    let mut order = Vec::with_capacity(count);
+    while !ready.is_empty() {
+        let best = (0..ready.len())
+            .max_by_key(|&at| (height[ready[at]], std::cmp::Reverse(ready[at])))
+            .expect("the ready list is not empty");
+        let next = ready.swap_remove(best);
+        order.push(next);
+        for &succ in &succs[next] {
+            waiting[succ] -= 1;
+            if waiting[succ] == 0 {
+                ready.push(succ);
+            }
+        }
+    }
+    order
+}
+
+/// Cycles an in-order machine that issues one instruction a cycle takes
+/// to run the instructions in the given order
+pub fn estimate_cycles(latencies: &[u32], preds: &[Vec<usize>], order: &[usize]) -> u32 {
+    let mut issued = vec![0; latencies.len()];
+    let mut cycle = 0;
+    let mut done = 0;
+    for (position, &i) in order.iter().enumerate() {
+        let operands_ready = preds[i]
+            .iter()
+            .map(|&pred| issued[pred] + latencies[pred])
+            .max()
+            .unwrap_or(0);
+        cycle = if position == 0 { operands_ready } else { operands_ready.max(cycle + 1) };
+        issued[i] = cycle;
+        done = done.max(cycle + latencies[i]);
+    }
+    done
+}
@@ -0,0 +1,134 @@
+//! Assembling and linking with the system's C compiler driver.
+//!
+//! The driver for a target is named by the `CC_<triple>` environment
+//! variable, with the dashes of the triple written as underscores, or else
+//! by `CC`. Without either it is `cc` when building for the host, and GCC's
+//! name for a cross compiler, `<triple>-gcc`, otherwise. A variable may
+//! give arguments after the driver, as in `clang --target=x86_64-apple-darwin`.
+//! The driver is only ever given assembly and object files, so any driver
+//! that understands GNU assembler syntax works.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::triple::TargetTriple;
+
+/// Libraries every program links against: `libm` for `fmod` and `pow`
+const LIBRARIES: &[&str] = &["-lm"];
+
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    cc: String,
+    /// Arguments given to the driver before any others
+    args: Vec<String>,
+}
+
+impl Toolchain {
+    pub fn new(cc: impl Into<String>) -> Self {
+        Toolchain {
+            cc: cc.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// The driver for building code for the target
+    pub fn for_target(triple: &TargetTriple) -> Self {
+        let variable = format!("CC_{}", triple.to_string().replace('-', "_"));
+        for name in [variable.as_str(), "CC"] {
+            let Ok(value) = std::env::var(name) else {
+                continue;
+            };
+            let mut words = value.split_whitespace().map(str::to_string);
+            if let Some(cc) = words.next() {
+                return Toolchain {
+                    cc,
+                    args: words.collect(),
+                };
+            }
+        }
+        if triple.is_host() {
+            Toolchain::new("cc")
+        } else {
+            Toolchain::new(format!("{}-gcc", triple))
+        }
+    }
+
+    /// Assembles the assembly into a relocatable object file
+    pub fn assemble(&self, assembly: &str, object: &Path) -> Result<(), String> {
+        let mut command = self.command();
+        command.args(["-x", "assembler", "-c", "-", "-o"]).arg(object);
+        self.run(command, Some(assembly))
+    }
+
+    /// Links object files into an executable
+    pub fn link(&self, objects: &[&Path], output: &Path) -> Result<(), String> {
+        let mut command = self.command();
+        command.args(objects).arg("-o").arg(output).args(LIBRARIES);
+        self.run(command, None)
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.cc);
+        command.args(&self.args);
+        command
+    }
+
+    fn run(&self, mut command: Command, input: Option<&str>) -> Result<(), String> {
+        command
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("could not run '{}': {}", self.cc, e))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin
+                .write_all(input.as_bytes())
+                .map_err(|e| format!("could not write to '{}': {}", self.cc, e))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("could not run '{}': {}", self.cc, e))?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(format!(
+            "'{}' failed ({}): {}",
+            self.cc,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// A path for an intermediate file that is removed when dropped
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(extension: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "iris-{}-{}.{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            extension
+        );
+        TempFile {
+            path: std::env::temp_dir().join(name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
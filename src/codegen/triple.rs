@@ -0,0 +1,199 @@
+//! Target triples, naming the machine and system code is generated for.
+//!
+//! A triple is written `<arch>-<vendor>-<os>[-<env>]`, as in
+//! `x86_64-unknown-linux-gnu`, or without the vendor as GCC names its
+//! cross compilers, as in `x86_64-linux-gnu`. Parts that are not known are
+//! kept, so any triple can be named; whether code can be generated for it
+//! is decided by the backends.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    X86,
+    Aarch64,
+    Arm,
+    Riscv64,
+    Wasm32,
+    Other(String),
+}
+
+impl Arch {
+    fn parse(name: &str) -> Self {
+        match name {
+            "x86_64" | "amd64" => Arch::X86_64,
+            "i386" | "i486" | "i586" | "i686" | "x86" => Arch::X86,
+            "aarch64" | "arm64" => Arch::Aarch64,
+            "riscv64" | "riscv64gc" => Arch::Riscv64,
+            "wasm32" => Arch::Wasm32,
+            name if name.starts_with("arm") || name.starts_with("thumb") => Arch::Arm,
+            other => Arch::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Os {
+    Linux,
+    MacOs,
+    FreeBsd,
+    Windows,
+    /// Bare metal, without an operating system
+    None,
+    Other(String),
+}
+
+impl Os {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linux" => Some(Os::Linux),
+            "darwin" | "macos" | "macosx" => Some(Os::MacOs),
+            "windows" | "win32" | "mingw32" => Some(Os::Windows),
+            "none" => Some(Os::None),
+            // FreeBSD triples may carry the release, as in `freebsd14.1`
+            name if name.starts_with("freebsd") => Some(Os::FreeBsd),
+            name if name.starts_with("darwin") || name.starts_with("macos") => Some(Os::MacOs),
+            _ => None,
+        }
+    }
+}
+
+/// The kind of object file the assembler produces, which decides the
+/// spelling of symbols, labels and sections in assembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Elf,
+    MachO,
+    Coff,
+}
+
+impl ObjectFormat {
+    /// The assembler's name for a symbol the C compiler names `name`
+    pub fn c_symbol(self, name: &str) -> String {
+        match self {
+            ObjectFormat::MachO => format!("_{}", name),
+            ObjectFormat::Elf | ObjectFormat::Coff => name.to_string(),
+        }
+    }
+
+    /// The target of a call to a C library function, which may live in a
+    /// shared library
+    pub fn library_call(self, name: &str) -> String {
+        match self {
+            ObjectFormat::Elf => format!("{}@PLT", name),
+            ObjectFormat::MachO | ObjectFormat::Coff => self.c_symbol(name),
+        }
+    }
+
+    /// A label that is not written to the object file's symbol table
+    pub fn local_label(self, name: &str) -> String {
+        match self {
+            ObjectFormat::MachO => format!("L{}", name),
+            ObjectFormat::Elf | ObjectFormat::Coff => format!(".L{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetTriple {
+    /// The triple as it was written
+    text: String,
+    arch: Arch,
+    os: Os,
+    env: Option<String>,
+}
+
+impl TargetTriple {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = text.split('-').collect();
+        if parts.len() < 2 || parts.iter().any(|part| part.is_empty()) {
+            return Err(format!(
+                "Invalid target triple '{}', expected <arch>-<vendor>-<os>[-<env>]",
+                text
+            ));
+        }
+        let arch = Arch::parse(parts[0]);
+
+        // The system is the first part after the architecture that names
+        // one, or else the part after the vendor
+        let (os, os_index) = parts
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find_map(|(index, part)| Os::parse(part).map(|os| (os, index)))
+            .unwrap_or_else(|| {
+                let index = parts.len().min(3) - 1;
+                (Os::Other(parts[index].to_string()), index)
+            });
+        let env = (os_index + 1 < parts.len()).then(|| parts[os_index + 1..].join("-"));
+        Ok(TargetTriple {
+            text: text.to_string(),
+            arch,
+            os,
+            env,
+        })
+    }
+
+    /// The machine the compiler itself runs on
+    pub fn host() -> Self {
+        let arch = std::env::consts::ARCH;
+        let text = match std::env::consts::OS {
+            "linux" if cfg!(target_env = "musl") => format!("{}-unknown-linux-musl", arch),
+            "linux" => format!("{}-unknown-linux-gnu", arch),
+            "macos" => format!("{}-apple-darwin", arch),
+            "windows" => format!("{}-pc-windows-msvc", arch),
+            os => format!("{}-unknown-{}", arch, os),
+        };
+        TargetTriple::parse(&text).expect("the host triple is well formed")
+    }
+
+    pub fn arch(&self) -> &Arch {
+        &self.arch
+    }
+
+    pub fn os(&self) -> &Os {
+        &self.os
+    }
+
+    /// The ABI or C library, as in `gnu` or `musl`
+    pub fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+
+    /// Whether code for the target runs on the host, so the host's tools
+    /// can build it
+    pub fn is_host(&self) -> bool {
+        let host = TargetTriple::host();
+        self.arch == host.arch && self.os == host.os
+    }
+
+    /// Size of a pointer in bits, if the architecture is known
+    pub fn pointer_width(&self) -> Option<u32> {
+        match self.arch {
+            Arch::X86_64 | Arch::Aarch64 | Arch::Riscv64 => Some(64),
+            Arch::X86 | Arch::Arm | Arch::Wasm32 => Some(32),
+            Arch::Other(_) => None,
+        }
+    }
+
+    pub fn object_format(&self) -> ObjectFormat {
+        match self.os {
+            Os::MacOs => ObjectFormat::MachO,
+            Os::Windows => ObjectFormat::Coff,
+            _ => ObjectFormat::Elf,
+        }
+    }
+}
+
+impl Default for TargetTriple {
+    fn default() -> Self {
+        TargetTriple::host()
+    }
+}
+
+impl fmt::Display for TargetTriple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,33 @@
+//! Prologues and epilogues of x86-64 functions.
+//!
+//! Every function keeps `rbp` as its frame pointer, so slots are addressed
+//! from it and the epilogue does not need to know the frame's size.
+
+use super::{Gpr, Inst, Mnemonic, Operand};
+use crate::codegen::frame::FrameLayout;
+
+/// Bytes above the frame pointer: the return address and the saved `rbp`
+pub const LINKAGE_SIZE: u32 = 16;
+
+/// Saves the caller's frame pointer and reserves the frame
+pub fn prologue(frame: &FrameLayout) -> Vec<Inst> {
+    let mut insts = vec![
+        Inst::new(Mnemonic::Pushq, vec![Operand::q(Gpr::Rbp)]),
+        Inst::new(Mnemonic::Movq, vec![Operand::q(Gpr::Rsp), Operand::q(Gpr::Rbp)]),
+    ];
+    if frame.size() > 0 {
+        insts.push(Inst::new(
+            Mnemonic::Subq,
+            vec![Operand::Imm(frame.size() as i64), Operand::q(Gpr::Rsp)],
+        ));
+    }
+    insts
+}
+
+/// Releases the frame and returns to the caller
+pub fn epilogue() -> Vec<Inst> {
+    vec![
+        Inst::new(Mnemonic::Leave, vec![]),
+        Inst::new(Mnemonic::Ret, vec![]),
+    ]
+}
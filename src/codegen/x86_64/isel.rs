@@ -0,0 +1,974 @@
+//! Instruction selection from MIR out of SSA form.
+//!
+//! Every MIR register gets its own stack slot, see
+//! [`crate::codegen::frame`]. Each instruction
+//! loads its operands into fixed scratch registers, computes its result and
+//! stores it back to the destination's slot, so no register allocation is
+//! needed. Floats of every width are held as doubles, rounded after every
+//! operation whose result is `F32`; `F8` and `F16` are computed at double
+//! precision, as the interpreter does. Integers are held sign-extended to 64
+//! bits and booleans as 0 or 1.
+//!
+//! Calls follow the calling convention the selector is given, see
+//! [`crate::codegen::abi`]. Arguments passed on the stack are stored below
+//! the stack pointer before the call rather than pushed.
+//!
+//! A trap prints the same message the interpreter would and exits with
+//! [`TRAP_EXIT_CODE`]. Float to integer conversions of values out of range
+//! give the smallest integer rather than saturating.
+//!
+//! With `--coverage`, the counters are written to [`COVERAGE_FILE`] when
+//! `main` returns and when the program traps.
+
+use super::frame::{self, LINKAGE_SIZE};
+use super::{
+    Cond, Gpr, Inst, MachineBlock, MachineConstant, MachineFunction, MachineGlobal, MachineProgram, MachineString,
+    Mnemonic, Operand, Width, Xmm,
+};
+use crate::codegen::abi::{CallingConvention, Location, RegClass};
+use crate::codegen::frame::FrameLayout;
+use crate::codegen::triple::ObjectFormat;
+use crate::coverage::COVERAGE_FILE;
+use crate::diagnostics::DiagnosticCollector;
+use crate::intern::Symbol;
+use crate::mir::analysis::block_frequency::BlockFrequency;
+use crate::mir::{
+    self, FuncId, GlobalId, INIT_FUNCTION, Instruction, Linkage, MirFunction, MirProgram, MirType,
+    Opcode, Terminator,
+};
+use crate::span::Span;
+use std::collections::HashSet;
+
+/// Exit status of a program stopped by a trap
+pub const TRAP_EXIT_CODE: i64 = 101;
+
+/// Runtime routine that prints the message in `rdi`, `rsi` bytes long, to
+/// standard error and exits
+const TRAP_SYMBOL: &str = "iris.trap";
+
+/// Runtime routine that writes the coverage counters out
+const COVERAGE_SYMBOL: &str = "iris.coverage";
+
+/// Registers an operand is loaded into: `xmm` if it is a float, `gpr`
+/// otherwise
+#[derive(Debug, Clone, Copy)]
+struct Acc {
+    xmm: Xmm,
+    gpr: Gpr,
+}
+
+/// The registers most values are computed in
+const A: Acc = Acc {
+    xmm: Xmm(0),
+    gpr: Gpr::Rax,
+};
+
+/// The registers of second operands
+const B: Acc = Acc {
+    xmm: Xmm(1),
+    gpr: Gpr::Rcx,
+};
+
+/// Scratch registers for values on their way to the stack
+const STACK: Acc = Acc {
+    xmm: Xmm(15),
+    gpr: Gpr::R11,
+};
+
+fn is_float(typ: MirType) -> bool {
+    matches!(typ, MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64)
+}
+
+/// Makes a MIR name usable as an assembler symbol. Letters, digits and
+/// underscores are kept, anything else is written as `.xx.` in hex, so
+/// distinct names give distinct symbols.
+fn mangle(prefix: &str, name: &str) -> String {
+    let mut symbol = String::from(prefix);
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            symbol.push(c);
+        } else {
+            symbol.push_str(&format!(".{:x}.", c as u32));
+        }
+    }
+    symbol
+}
+
+pub fn function_symbol(name: FuncId) -> String {
+    mangle("iris.", name.as_str())
+}
+
+pub fn global_symbol(name: GlobalId) -> String {
+    mangle("iris.global.", name.as_str())
+}
+
+/// Selects x86-64 instructions for a program out of SSA form
+pub struct X86_64Selector {
+    format: ObjectFormat,
+    convention: &'static CallingConvention,
+    diagnostics: DiagnosticCollector,
+    strings: Vec<MachineString>,
+}
+
+impl X86_64Selector {
+    /// A selector for code that calls functions with the convention and
+    /// goes into object files of the given format
+    pub fn new(format: ObjectFormat, convention: &'static CallingConvention) -> Self {
+        X86_64Selector {
+            format,
+            convention,
+            diagnostics: DiagnosticCollector::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn select(&mut self, program: &MirProgram) -> MachineProgram {
+        let mut functions = Vec::new();
+        for (index, function) in program.functions.iter().enumerate() {
+            let mut selector = FunctionSelector {
+                program,
+                function,
+                index,
+                format: self.format,
+                convention: self.convention,
+                frame: FrameLayout::new(self.convention, LINKAGE_SIZE),
+                frequencies: BlockFrequency::compute(function),
+                insts: Vec::new(),
+                blocks: Vec::new(),
+                traps: Vec::new(),
+                strings: &mut self.strings,
+                diagnostics: &mut self.diagnostics,
+            };
+            functions.push(selector.select());
+        }
+        let coverage = !program.coverage.is_empty();
+        functions.push(trap_function(self.format, self.convention, coverage));
+        if coverage {
+            functions.push(coverage_function(program, self.format, self.convention, &mut self.strings));
+        }
+        if let Some(main) = entry_function(program, self.format, self.convention) {
+            functions.push(main);
+        }
+
+        // The counters go last and in order, so they are written out at once
+        let counters: HashSet<GlobalId> = program.coverage.iter().map(|counter| counter.global).collect();
+        let exported: HashSet<GlobalId> = program
+            .globals
+            .iter()
+            .filter(|global| global.linkage == Linkage::Public)
+            .map(|global| global.name)
+            .collect();
+        let globals = program
+            .globals
+            .iter()
+            .map(|global| global.name)
+            .filter(|name| !counters.contains(name))
+            .chain(program.coverage.iter().map(|counter| counter.global))
+            .map(|name| MachineGlobal {
+                symbol: global_symbol(name),
+                exported_as: match exported.contains(&name) {
+                    true => vec![self.format.c_symbol(name.as_str())],
+                    false => Vec::new(),
+                },
+            })
+            .collect();
+        let constants = program
+            .constants
+            .values()
+            .enumerate()
+            .map(|(index, value)| MachineConstant {
+                symbol: constant_symbol(self.format, index),
+                bits: value.to_bits(),
+            })
+            .collect();
+        MachineProgram {
+            functions,
+            globals,
+            constants,
+            strings: std::mem::take(&mut self.strings),
+        }
+    }
+}
+
+/// Symbol of the entry of the constant pool at the index
+fn constant_symbol(format: ObjectFormat, index: usize) -> String {
+    format.local_label(&format!("fp{}", index))
+}
+
+/// Symbol of a string constant with the given bytes
+fn string_symbol(strings: &mut Vec<MachineString>, format: ObjectFormat, bytes: &[u8]) -> String {
+    if let Some(existing) = strings.iter().find(|string| string.bytes == bytes) {
+        return existing.symbol.clone();
+    }
+    let symbol = format.local_label(&format!("str{}", strings.len()));
+    strings.push(MachineString {
+        symbol: symbol.clone(),
+        bytes: bytes.to_vec(),
+    });
+    symbol
+}
+
+/// `iris.trap`: writes the message to standard error and exits, after
+/// writing the coverage counters out if there are any
+fn trap_function(format: ObjectFormat, convention: &CallingConvention, coverage: bool) -> MachineFunction {
+    let mut insts = frame::prologue(&FrameLayout::new(convention, LINKAGE_SIZE));
+    insts.extend([
+        Inst::new(Mnemonic::Movq, vec![Operand::q(Gpr::Rsi), Operand::q(Gpr::Rdx)]),
+        Inst::new(Mnemonic::Movq, vec![Operand::q(Gpr::Rdi), Operand::q(Gpr::Rsi)]),
+        Inst::new(Mnemonic::Movl, vec![Operand::Imm(2), Operand::Gpr(Gpr::Rdi, Width::B32)]),
+        Inst::new(Mnemonic::Call, vec![Operand::Label(format.library_call("write"))]),
+    ]);
+    if coverage {
+        insts.push(Inst::new(Mnemonic::Call, vec![Operand::Label(COVERAGE_SYMBOL.to_string())]));
+    }
+    insts.extend([
+        Inst::new(
+            Mnemonic::Movl,
+            vec![Operand::Imm(TRAP_EXIT_CODE), Operand::Gpr(Gpr::Rdi, Width::B32)],
+        ),
+        Inst::new(Mnemonic::Call, vec![Operand::Label(format.library_call("exit"))]),
+        Inst::new(Mnemonic::Ud2, vec![]),
+    ]);
+    MachineFunction {
+        symbol: TRAP_SYMBOL.to_string(),
+        global: false,
+        exported_as: Vec::new(),
+        blocks: vec![MachineBlock {
+            label: format.local_label("trap"),
+            insts,
+            frequency: 1.0,
+        }],
+    }
+}
+
+/// `iris.coverage`: writes the coverage counters, the last globals, to
+/// [`COVERAGE_FILE`] with a single `fwrite`, or nothing if the file cannot
+/// be opened
+fn coverage_function(
+    program: &MirProgram,
+    format: ObjectFormat,
+    convention: &CallingConvention,
+    strings: &mut Vec<MachineString>,
+) -> MachineFunction {
+    let arg = |index: usize| Operand::q(Gpr::from_number(convention.int_args[index]));
+    let result = Operand::q(Gpr::from_number(convention.int_return));
+    let path = string_symbol(strings, format, format!("{}\0", COVERAGE_FILE).as_bytes());
+    let mode = string_symbol(strings, format, b"wb\0");
+    let mut frame = FrameLayout::new(convention, LINKAGE_SIZE);
+    let file = Operand::frame(frame.allocate(8, 8));
+    let done = format.local_label("coverage_done");
+
+    let mut open = frame::prologue(&frame);
+    open.extend([
+        Inst::new(Mnemonic::Leaq, vec![Operand::Symbol(path), arg(0)]),
+        Inst::new(Mnemonic::Leaq, vec![Operand::Symbol(mode), arg(1)]),
+        Inst::new(Mnemonic::Call, vec![Operand::Label(format.library_call("fopen"))]),
+        Inst::new(Mnemonic::Movq, vec![result.clone(), file.clone()]),
+        Inst::new(Mnemonic::Testq, vec![result.clone(), result]),
+        Inst::new(Mnemonic::J(Cond::E), vec![Operand::Label(done.clone())]),
+    ]);
+    let write = vec![
+        Inst::new(
+            Mnemonic::Leaq,
+            vec![Operand::Symbol(global_symbol(program.coverage[0].global)), arg(0)],
+        ),
+        Inst::new(Mnemonic::Movq, vec![Operand::Imm(8), arg(1)]),
+        Inst::new(Mnemonic::Movq, vec![Operand::Imm(program.coverage.len() as i64), arg(2)]),
+        Inst::new(Mnemonic::Movq, vec![file.clone(), arg(3)]),
+        Inst::new(Mnemonic::Call, vec![Operand::Label(format.library_call("fwrite"))]),
+        Inst::new(Mnemonic::Movq, vec![file, arg(0)]),
+        Inst::new(Mnemonic::Call, vec![Operand::Label(format.library_call("fclose"))]),
+    ];
+    MachineFunction {
+        symbol: COVERAGE_SYMBOL.to_string(),
+        global: false,
+        exported_as: Vec::new(),
+        blocks: vec![
+            MachineBlock {
+                label: format.local_label("coverage"),
+                insts: open,
+                frequency: 1.0,
+            },
+            MachineBlock {
+                label: format.local_label("coverage_write"),
+                insts: write,
+                frequency: 1.0,
+            },
+            MachineBlock {
+                label: done,
+                insts: frame::epilogue(),
+                frequency: 1.0,
+            },
+        ],
+    }
+}
+
+/// The C `main` that initializes the globals, calls the program's `main`
+/// and exits with its result, if the program has a `main` without
+/// parameters. With coverage, the counters are written out before it exits.
+fn entry_function(
+    program: &MirProgram,
+    format: ObjectFormat,
+    convention: &CallingConvention,
+) -> Option<MachineFunction> {
+    let main = program
+        .functions
+        .iter()
+        .find(|function| function.name == Symbol::intern("main") && function.params.is_empty())?;
+
+    let mut frame = FrameLayout::new(convention, LINKAGE_SIZE);
+    let saved_status = (!program.coverage.is_empty()).then(|| Operand::frame(frame.allocate(8, 8)));
+    let mut insts = frame::prologue(&frame);
+    let init = Symbol::intern(INIT_FUNCTION);
+    if program.functions.iter().any(|function| function.name == init) {
+        insts.push(Inst::new(Mnemonic::Call, vec![Operand::Label(function_symbol(init))]));
+    }
+    insts.push(Inst::new(Mnemonic::Call, vec![Operand::Label(function_symbol(main.name))]));
+    let status = Gpr::from_number(convention.int_return);
+    match convention.signature(&[], main.return_type).ret {
+        Some(Location::Reg(RegClass::Float, number)) => insts.push(Inst::new(
+            Mnemonic::Cvttsd2siq,
+            vec![Operand::xmm(number), Operand::q(status)],
+        )),
+        Some(Location::Reg(RegClass::Int, number)) if number != convention.int_return => insts.push(
+            Inst::new(Mnemonic::Movq, vec![Operand::q(Gpr::from_number(number)), Operand::q(status)]),
+        ),
+        Some(_) => {}
+        None => {
+            let status = Operand::Gpr(status, Width::B32);
+            insts.push(Inst::new(Mnemonic::Xorl, vec![status.clone(), status]));
+        }
+    }
+    if let Some(saved) = saved_status {
+        insts.extend([
+            Inst::new(Mnemonic::Movq, vec![Operand::q(status), saved.clone()]),
+            Inst::new(Mnemonic::Call, vec![Operand::Label(COVERAGE_SYMBOL.to_string())]),
+            Inst::new(Mnemonic::Movq, vec![saved, Operand::q(status)]),
+        ]);
+    }
+    insts.extend(frame::epilogue());
+
+    Some(MachineFunction {
+        symbol: format.c_symbol("main"),
+        global: true,
+        exported_as: Vec::new(),
+        blocks: vec![MachineBlock {
+            label: format.local_label("main"),
+            insts,
+            frequency: 1.0,
+        }],
+    })
+}
+
+struct FunctionSelector<'a> {
+    program: &'a MirProgram,
+    function: &'a MirFunction,
+    /// Position of the function in the program, to keep labels unique
+    index: usize,
+    format: ObjectFormat,
+    convention: &'a CallingConvention,
+    frame: FrameLayout,
+    frequencies: BlockFrequency,
+    /// Instructions of the block being selected
+    insts: Vec<Inst>,
+    blocks: Vec<MachineBlock>,
+    /// Labels of the blocks that trap, and the message of each
+    traps: Vec<(String, String)>,
+    strings: &'a mut Vec<MachineString>,
+    diagnostics: &'a mut DiagnosticCollector,
+}
+
+impl FunctionSelector<'_> {
+    fn select(&mut self) -> MachineFunction {
+        let function = self.function;
+        self.arguments();
+        self.emit(Mnemonic::Jmp, vec![Operand::Label(self.block_label(function.entry))]);
+        self.finish_block(self.format.local_label(&format!("{}_prologue", self.index)), 1.0);
+
+        for (block_id, block) in function.arena.iter() {
+            for instruction in &block.instructions {
+                self.instruction(instruction);
+            }
+            self.terminator(&block.terminator, block.terminator_span);
+            self.finish_block(self.block_label(block_id), self.frequencies.frequency(block_id));
+        }
+
+        // Overflow and division checks are assumed to pass
+        for (label, message) in std::mem::take(&mut self.traps) {
+            self.trap(&message);
+            self.finish_block(label, 0.0);
+        }
+        // Only now are all the slots known
+        self.blocks[0].insts.splice(0..0, frame::prologue(&self.frame));
+
+        let exported_as = match function.linkage {
+            Linkage::Public if function.name.as_str().chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                vec![self.format.c_symbol(function.name.as_str())]
+            }
+            _ => Vec::new(),
+        };
+        MachineFunction {
+            symbol: function_symbol(function.name),
+            global: false,
+            exported_as,
+            blocks: std::mem::take(&mut self.blocks),
+        }
+    }
+
+    fn emit(&mut self, mnemonic: Mnemonic, operands: Vec<Operand>) {
+        self.insts.push(Inst::new(mnemonic, operands));
+    }
+
+    fn finish_block(&mut self, label: String, frequency: f64) {
+        self.blocks.push(MachineBlock {
+            label,
+            insts: std::mem::take(&mut self.insts),
+            frequency,
+        });
+    }
+
+    fn block_label(&self, block: mir::BlockId) -> String {
+        self.format.local_label(&format!("{}_{}", self.index, block.index()))
+    }
+
+    fn slot(&mut self, reg: mir::Reg) -> Operand {
+        Operand::frame(self.frame.spill_slot(reg))
+    }
+
+    fn error(&mut self, message: String) {
+        self.diagnostics.error(format!(
+            "Code generation failed in '{}': {}",
+            self.function.name, message
+        ));
+    }
+
+    /// Stores the arguments to the slots of the parameters
+    fn arguments(&mut self) {
+        let function = self.function;
+        let types: Vec<MirType> = function.params.iter().map(|(_, typ)| *typ).collect();
+        let signature = self.convention.signature(&types, function.return_type);
+        for (&(reg, _), location) in function.params.iter().zip(signature.params) {
+            let slot = self.slot(reg);
+            match location {
+                Location::Reg(RegClass::Float, number) => {
+                    self.emit(Mnemonic::Movsd, vec![Operand::xmm(number), slot])
+                }
+                Location::Reg(RegClass::Int, number) => {
+                    self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::from_number(number)), slot])
+                }
+                Location::Stack(offset) => {
+                    let incoming = Operand::frame(self.frame.incoming_argument(offset));
+                    self.emit(Mnemonic::Movq, vec![incoming, Operand::q(Gpr::R11)]);
+                    self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::R11), slot]);
+                }
+            }
+        }
+    }
+
+    fn operand_type(&self, operand: &mir::Operand) -> MirType {
+        match operand {
+            mir::Operand::Reg(reg) => self.function.reg_type(*reg).unwrap_or(MirType::F64),
+            mir::Operand::ImmI64(_) => MirType::I64,
+            mir::Operand::ImmBool(_) => MirType::I1,
+            mir::Operand::ImmF32(_) => MirType::F32,
+            _ => MirType::F64,
+        }
+    }
+
+    /// Loads an operand into the accumulator as it is, returning its type
+    fn load(&mut self, operand: &mir::Operand, acc: Acc) -> MirType {
+        let typ = self.operand_type(operand);
+        match operand {
+            mir::Operand::Reg(reg) => {
+                let slot = self.slot(*reg);
+                if is_float(typ) {
+                    self.emit(Mnemonic::Movsd, vec![slot, Operand::Xmm(acc.xmm)]);
+                } else {
+                    self.emit(Mnemonic::Movq, vec![slot, Operand::q(acc.gpr)]);
+                }
+            }
+            // Floats of every type are held as F64 values
+            mir::Operand::ImmF64(_) | mir::Operand::ImmF32(_) => {
+                let value = operand.as_float().unwrap_or_default();
+                if let Some(index) = self.program.constants.index_of(value) {
+                    let symbol = constant_symbol(self.format, index);
+                    self.emit(Mnemonic::Movsd, vec![Operand::Symbol(symbol), Operand::Xmm(acc.xmm)]);
+                    return typ;
+                }
+                // Not pooled, so it goes through a general purpose register
+                self.emit(
+                    Mnemonic::Movabsq,
+                    vec![Operand::Imm(value.to_bits() as i64), Operand::q(Gpr::R11)],
+                );
+                self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::R11), Operand::Xmm(acc.xmm)]);
+            }
+            mir::Operand::ImmI64(value) => self.load_int(*value, acc.gpr),
+            mir::Operand::ImmBool(value) => self.load_int(*value as i64, acc.gpr),
+            mir::Operand::Label(_) | mir::Operand::Global(_) => {
+                self.error(format!("{:?} is not a value", operand));
+            }
+        }
+        typ
+    }
+
+    fn load_int(&mut self, value: i64, gpr: Gpr) {
+        let mnemonic = if i32::try_from(value).is_ok() {
+            Mnemonic::Movq
+        } else {
+            Mnemonic::Movabsq
+        };
+        self.emit(mnemonic, vec![Operand::Imm(value), Operand::q(gpr)]);
+    }
+
+    /// Converts the value in the accumulator from one type to another, as
+    /// the interpreter's conversions do
+    fn convert(&mut self, from: MirType, to: MirType, acc: Acc) {
+        let (xmm, gpr) = (Operand::Xmm(acc.xmm), Operand::q(acc.gpr));
+        match to {
+            MirType::Void => {}
+            to if is_float(to) => {
+                if !is_float(from) {
+                    self.emit(Mnemonic::Cvtsi2sdq, vec![gpr, xmm.clone()]);
+                }
+                if to == MirType::F32 && from != MirType::F32 {
+                    self.emit(Mnemonic::Cvtsd2ss, vec![xmm.clone(), xmm.clone()]);
+                    self.emit(Mnemonic::Cvtss2sd, vec![xmm.clone(), xmm]);
+                }
+            }
+            MirType::I1 if from == MirType::I1 => {}
+            MirType::I1 if is_float(from) => {
+                // NaN is unordered, and unequal to zero
+                self.emit(Mnemonic::Xorpd, vec![Operand::xmm(14), Operand::xmm(14)]);
+                self.emit(Mnemonic::Ucomisd, vec![Operand::xmm(14), xmm]);
+                self.emit(Mnemonic::Set(Cond::Ne), vec![Operand::b(Gpr::R10)]);
+                self.emit(Mnemonic::Set(Cond::P), vec![Operand::b(Gpr::R11)]);
+                self.emit(Mnemonic::Orb, vec![Operand::b(Gpr::R11), Operand::b(Gpr::R10)]);
+                self.emit(Mnemonic::Movzbq, vec![Operand::b(Gpr::R10), gpr]);
+            }
+            MirType::I1 => {
+                self.emit(Mnemonic::Testq, vec![gpr.clone(), gpr.clone()]);
+                self.emit(Mnemonic::Set(Cond::Ne), vec![Operand::b(Gpr::R10)]);
+                self.emit(Mnemonic::Movzbq, vec![Operand::b(Gpr::R10), gpr]);
+            }
+            to => {
+                if is_float(from) {
+                    self.emit(Mnemonic::Cvttsd2siq, vec![xmm, gpr.clone()]);
+                }
+                let narrow = match to {
+                    MirType::I8 => Some((Mnemonic::Movsbq, Width::B8)),
+                    MirType::I16 => Some((Mnemonic::Movswq, Width::B16)),
+                    MirType::I32 => Some((Mnemonic::Movslq, Width::B32)),
+                    _ => None,
+                };
+                if let Some((mnemonic, width)) = narrow {
+                    self.emit(mnemonic, vec![Operand::Gpr(acc.gpr, width), gpr]);
+                }
+            }
+        }
+    }
+
+    /// Loads an operand into the accumulator as a value of the given type
+    fn value(&mut self, operand: &mir::Operand, typ: MirType, acc: Acc) {
+        let from = self.load(operand, acc);
+        self.convert(from, typ, acc);
+    }
+
+    /// Stores the value of the given type in the accumulator to a register's slot
+    fn store(&mut self, reg: mir::Reg, typ: MirType, acc: Acc) {
+        let slot = self.slot(reg);
+        match typ {
+            MirType::Void => {}
+            typ if is_float(typ) => self.emit(Mnemonic::Movsd, vec![Operand::Xmm(acc.xmm), slot]),
+            _ => self.emit(Mnemonic::Movq, vec![Operand::q(acc.gpr), slot]),
+        }
+    }
+
+    /// Label of a block that traps with the message, at the given span
+    fn trap_label(&mut self, message: &str, span: Option<Span>) -> String {
+        let message = self.trap_message(message, span);
+        if let Some((label, _)) = self.traps.iter().find(|(_, existing)| *existing == message) {
+            return label.clone();
+        }
+        let label = self.format.local_label(&format!("{}_trap{}", self.index, self.traps.len()));
+        self.traps.push((label.clone(), message));
+        label
+    }
+
+    /// The message the interpreter's trap would print
+    fn trap_message(&self, message: &str, span: Option<Span>) -> String {
+        let location = span.map(|span| match &self.function.source {
+            Some(source) => format!("{}:{}", source, span),
+            None => span.to_string(),
+        });
+        match location {
+            Some(location) => format!(
+                "trap in '{}' at {}: {}\n",
+                self.function.name, location, message
+            ),
+            None => format!("trap in '{}': {}\n", self.function.name, message),
+        }
+    }
+
+    /// Calls the trap routine with the message, which does not return
+    fn trap(&mut self, message: &str) {
+        let symbol = self.string(message.as_bytes());
+        self.emit(Mnemonic::Leaq, vec![Operand::Symbol(symbol), Operand::q(Gpr::Rdi)]);
+        self.emit(Mnemonic::Movq, vec![Operand::Imm(message.len() as i64), Operand::q(Gpr::Rsi)]);
+        self.emit(Mnemonic::Call, vec![Operand::Label(TRAP_SYMBOL.to_string())]);
+        self.emit(Mnemonic::Ud2, vec![]);
+    }
+
+    /// Symbol of a string constant with the given bytes
+    fn string(&mut self, bytes: &[u8]) -> String {
+        string_symbol(self.strings, self.format, bytes)
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let typ = instruction.typ;
+        let args = &instruction.args;
+        if args.len() < expected_args(instruction.op) {
+            self.error(format!("{:?} into r{} is missing operands", instruction.op, instruction.dest));
+            return;
+        }
+
+        match instruction.op {
+            Opcode::Copy | Opcode::Convert => {
+                self.value(&args[0], typ, A);
+                self.store(instruction.dest, typ, A);
+            }
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod | Opcode::Pow if is_float(typ) => {
+                self.value(&args[0], typ, A);
+                self.value(&args[1], typ, B);
+                let (lhs, rhs) = (Operand::xmm(0), Operand::xmm(1));
+                match instruction.op {
+                    Opcode::Add => self.emit(Mnemonic::Addsd, vec![rhs, lhs]),
+                    Opcode::Sub => self.emit(Mnemonic::Subsd, vec![rhs, lhs]),
+                    Opcode::Mul => self.emit(Mnemonic::Mulsd, vec![rhs, lhs]),
+                    Opcode::Div => self.emit(Mnemonic::Divsd, vec![rhs, lhs]),
+                    Opcode::Mod => self.emit(Mnemonic::Call, vec![Operand::Label(self.format.library_call("fmod"))]),
+                    _ => self.emit(Mnemonic::Call, vec![Operand::Label(self.format.library_call("pow"))]),
+                }
+                self.convert(MirType::F64, typ, A);
+                self.store(instruction.dest, typ, A);
+            }
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => {
+                self.integer_arithmetic(instruction);
+            }
+            Opcode::Pow => {
+                self.error(format!("power into r{} is not of a float type", instruction.dest));
+            }
+            Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                self.compare(instruction.op, &args[0], &args[1]);
+                self.convert(MirType::I1, typ, A);
+                self.store(instruction.dest, typ, A);
+            }
+            Opcode::Select => {
+                self.value(&args[0], MirType::I1, A);
+                self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::Rax), Operand::q(Gpr::Rdx)]);
+                self.value(&args[1], typ, A);
+                self.value(&args[2], typ, B);
+                // Floats are chosen between as bits
+                if is_float(typ) {
+                    self.emit(Mnemonic::Movq, vec![Operand::xmm(0), Operand::q(Gpr::Rax)]);
+                    self.emit(Mnemonic::Movq, vec![Operand::xmm(1), Operand::q(Gpr::Rcx)]);
+                }
+                self.emit(Mnemonic::Testq, vec![Operand::q(Gpr::Rdx), Operand::q(Gpr::Rdx)]);
+                self.emit(Mnemonic::Cmov(Cond::E), vec![Operand::q(Gpr::Rcx), Operand::q(Gpr::Rax)]);
+                let slot = self.slot(instruction.dest);
+                self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::Rax), slot]);
+            }
+            Opcode::Call => self.call(instruction),
+            Opcode::Load => {
+                let mir::Operand::Global(global) = args[0] else {
+                    self.error(format!("load into r{} has no global", instruction.dest));
+                    return;
+                };
+                let global_type = self.global_type(global);
+                let storage = Operand::Symbol(global_symbol(global));
+                if is_float(global_type) {
+                    self.emit(Mnemonic::Movsd, vec![storage, Operand::xmm(0)]);
+                } else {
+                    self.emit(Mnemonic::Movq, vec![storage, Operand::q(Gpr::Rax)]);
+                }
+                self.convert(global_type, typ, A);
+                self.store(instruction.dest, typ, A);
+            }
+            Opcode::Store => {
+                let mir::Operand::Global(global) = args[0] else {
+                    self.error(format!("store from r{} has no global", instruction.dest));
+                    return;
+                };
+                let global_type = self.global_type(global);
+                self.value(&args[1], global_type, A);
+                let storage = Operand::Symbol(global_symbol(global));
+                if is_float(global_type) {
+                    self.emit(Mnemonic::Movsd, vec![Operand::xmm(0), storage]);
+                } else {
+                    self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::Rax), storage]);
+                }
+            }
+        }
+    }
+
+    fn global_type(&mut self, global: GlobalId) -> MirType {
+        self.program.global_type(global).unwrap_or_else(|| {
+            self.diagnostics.error(format!(
+                "Code generation failed in '{}': unknown global ${}",
+                self.function.name, global
+            ));
+            MirType::F64
+        })
+    }
+
+    /// Checked 64-bit arithmetic, trapping on overflow and division by
+    /// zero, with the result narrowed to the instruction's type
+    fn integer_arithmetic(&mut self, instruction: &Instruction) {
+        let typ = instruction.typ;
+        self.value(&instruction.args[0], typ, A);
+        self.value(&instruction.args[1], typ, B);
+        let (lhs, rhs) = (Operand::q(Gpr::Rax), Operand::q(Gpr::Rcx));
+        let overflow = self.trap_label(&format!("integer overflow in {:?}", instruction.op), instruction.span);
+
+        match instruction.op {
+            Opcode::Add | Opcode::Sub | Opcode::Mul => {
+                let mnemonic = match instruction.op {
+                    Opcode::Add => Mnemonic::Addq,
+                    Opcode::Sub => Mnemonic::Subq,
+                    _ => Mnemonic::Imulq,
+                };
+                self.emit(mnemonic, vec![rhs, lhs]);
+                self.emit(Mnemonic::J(Cond::O), vec![Operand::Label(overflow)]);
+            }
+            _ => {
+                let by_zero = self.trap_label(&mir::TrapReason::DivisionByZero.to_string(), instruction.span);
+                self.emit(Mnemonic::Testq, vec![rhs.clone(), rhs.clone()]);
+                self.emit(Mnemonic::J(Cond::E), vec![Operand::Label(by_zero)]);
+                // The smallest integer divided by -1 does not fit
+                self.emit(Mnemonic::Movabsq, vec![Operand::Imm(i64::MIN), Operand::q(Gpr::R11)]);
+                self.emit(Mnemonic::Cmpq, vec![Operand::q(Gpr::R11), lhs.clone()]);
+                self.emit(Mnemonic::Set(Cond::E), vec![Operand::b(Gpr::R11)]);
+                self.emit(Mnemonic::Cmpq, vec![Operand::Imm(-1), rhs.clone()]);
+                self.emit(Mnemonic::Set(Cond::E), vec![Operand::b(Gpr::R10)]);
+                self.emit(Mnemonic::Andb, vec![Operand::b(Gpr::R10), Operand::b(Gpr::R11)]);
+                self.emit(Mnemonic::J(Cond::Ne), vec![Operand::Label(overflow)]);
+                self.emit(Mnemonic::Cqto, vec![]);
+                self.emit(Mnemonic::Idivq, vec![rhs]);
+                if instruction.op == Opcode::Mod {
+                    self.emit(Mnemonic::Movq, vec![Operand::q(Gpr::Rdx), lhs]);
+                }
+            }
+        }
+        self.convert(MirType::I64, typ, A);
+        self.store(instruction.dest, typ, A);
+    }
+
+    /// Leaves 1 in `rax` if the comparison holds and 0 otherwise. Booleans
+    /// and integers compare as integers, anything else as floats, where a
+    /// comparison with NaN only holds for `Ne`.
+    fn compare(&mut self, op: Opcode, lhs: &mir::Operand, rhs: &mir::Operand) {
+        let al = Operand::b(Gpr::Rax);
+        if !is_float(self.operand_type(lhs)) && !is_float(self.operand_type(rhs)) {
+            self.load(lhs, A);
+            self.load(rhs, B);
+            self.emit(Mnemonic::Cmpq, vec![Operand::q(Gpr::Rcx), Operand::q(Gpr::Rax)]);
+            let cond = match op {
+                Opcode::Eq => Cond::E,
+                Opcode::Ne => Cond::Ne,
+                Opcode::Lt => Cond::L,
+                Opcode::Le => Cond::Le,
+                Opcode::Gt => Cond::G,
+                _ => Cond::Ge,
+            };
+            self.emit(Mnemonic::Set(cond), vec![al.clone()]);
+        } else {
+            self.value(lhs, MirType::F64, A);
+            self.value(rhs, MirType::F64, B);
+            let (lhs, rhs) = (Operand::xmm(0), Operand::xmm(1));
+            match op {
+                // Above and above-or-equal are false when unordered
+                Opcode::Gt | Opcode::Ge | Opcode::Lt | Opcode::Le => {
+                    let (a, b) = match op {
+                        Opcode::Gt | Opcode::Ge => (lhs, rhs),
+                        _ => (rhs, lhs),
+                    };
+                    self.emit(Mnemonic::Ucomisd, vec![b, a]);
+                    let cond = match op {
+                        Opcode::Gt | Opcode::Lt => Cond::A,
+                        _ => Cond::Ae,
+                    };
+                    self.emit(Mnemonic::Set(cond), vec![al.clone()]);
+                }
+                Opcode::Eq => {
+                    self.emit(Mnemonic::Ucomisd, vec![rhs, lhs]);
+                    self.emit(Mnemonic::Set(Cond::E), vec![al.clone()]);
+                    self.emit(Mnemonic::Set(Cond::Np), vec![Operand::b(Gpr::Rcx)]);
+                    self.emit(Mnemonic::Andb, vec![Operand::b(Gpr::Rcx), al.clone()]);
+                }
+                _ => {
+                    self.emit(Mnemonic::Ucomisd, vec![rhs, lhs]);
+                    self.emit(Mnemonic::Set(Cond::Ne), vec![al.clone()]);
+                    self.emit(Mnemonic::Set(Cond::P), vec![Operand::b(Gpr::Rcx)]);
+                    self.emit(Mnemonic::Orb, vec![Operand::b(Gpr::Rcx), al.clone()]);
+                }
+            }
+        }
+        self.emit(Mnemonic::Movzbq, vec![al, Operand::q(Gpr::Rax)]);
+    }
+
+    fn call(&mut self, instruction: &Instruction) {
+        let mir::Operand::Label(name) = instruction.args[0] else {
+            self.error(format!("call into r{} has no callee", instruction.dest));
+            return;
+        };
+        let Some(callee) = self.program.symbols.get(name) else {
+            self.error(format!("call to unknown function '{}'", name));
+            return;
+        };
+        let args = &instruction.args[1..];
+        if args.len() != callee.params.len() {
+            self.error(format!(
+                "call to '{}' passes {} argument(s) for {} parameter(s)",
+                name,
+                args.len(),
+                callee.params.len()
+            ));
+            return;
+        }
+
+        let types = &callee.params;
+        let signature = self.convention.signature(types, callee.return_type);
+        // Code outside the program takes and returns F32 values in single
+        // precision, where Iris holds every float as F64
+        let external = callee.linkage == Linkage::External;
+        let single = |typ: MirType| external && typ == MirType::F32;
+        let stack_size = signature.stack_size as i64;
+        if stack_size > 0 {
+            self.emit(Mnemonic::Subq, vec![Operand::Imm(stack_size), Operand::q(Gpr::Rsp)]);
+        }
+        // Stack arguments first, as the scratch registers they go through
+        // may be argument registers
+        for (i, location) in signature.params.iter().enumerate() {
+            if let Location::Stack(offset) = *location {
+                self.value(&args[i], types[i], STACK);
+                if single(types[i]) {
+                    self.emit(Mnemonic::Cvtsd2ss, vec![Operand::Xmm(STACK.xmm), Operand::Xmm(STACK.xmm)]);
+                }
+                let outgoing = Operand::Mem {
+                    base: Gpr::Rsp,
+                    offset: offset as i32,
+                };
+                if is_float(types[i]) {
+                    self.emit(Mnemonic::Movsd, vec![Operand::Xmm(STACK.xmm), outgoing]);
+                } else {
+                    self.emit(Mnemonic::Movq, vec![Operand::q(STACK.gpr), outgoing]);
+                }
+            }
+        }
+        for (i, location) in signature.params.iter().enumerate() {
+            if let Location::Reg(..) = *location {
+                let acc = location_acc(*location);
+                self.value(&args[i], types[i], acc);
+                if single(types[i]) {
+                    self.emit(Mnemonic::Cvtsd2ss, vec![Operand::Xmm(acc.xmm), Operand::Xmm(acc.xmm)]);
+                }
+            }
+        }
+
+        let target = match external {
+            true => self.format.library_call(name.as_str()),
+            false => function_symbol(name),
+        };
+        self.emit(Mnemonic::Call, vec![Operand::Label(target)]);
+        if stack_size > 0 {
+            self.emit(Mnemonic::Addq, vec![Operand::Imm(stack_size), Operand::q(Gpr::Rsp)]);
+        }
+        if let Some(location) = signature.ret
+            && instruction.typ != MirType::Void
+        {
+            let acc = location_acc(location);
+            if single(callee.return_type) {
+                self.emit(Mnemonic::Cvtss2sd, vec![Operand::Xmm(acc.xmm), Operand::Xmm(acc.xmm)]);
+            }
+            self.convert(callee.return_type, instruction.typ, acc);
+            self.store(instruction.dest, instruction.typ, acc);
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, span: Option<Span>) {
+        match terminator {
+            Terminator::Br { target, .. } => {
+                let label = self.block_label(*target);
+                self.emit(Mnemonic::Jmp, vec![Operand::Label(label)]);
+            }
+            Terminator::BrIf {
+                cond,
+                then_bb,
+                else_bb,
+                ..
+            } => {
+                self.value(cond, MirType::I1, A);
+                self.emit(Mnemonic::Testq, vec![Operand::q(Gpr::Rax), Operand::q(Gpr::Rax)]);
+                let (then_label, else_label) = (self.block_label(*then_bb), self.block_label(*else_bb));
+                self.emit(Mnemonic::J(Cond::Ne), vec![Operand::Label(then_label)]);
+                self.emit(Mnemonic::Jmp, vec![Operand::Label(else_label)]);
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+                ..
+            } => {
+                for case in cases {
+                    self.compare(Opcode::Eq, value, &case.value);
+                    self.emit(Mnemonic::Testq, vec![Operand::q(Gpr::Rax), Operand::q(Gpr::Rax)]);
+                    let label = self.block_label(case.target);
+                    self.emit(Mnemonic::J(Cond::Ne), vec![Operand::Label(label)]);
+                }
+                let label = self.block_label(*default);
+                self.emit(Mnemonic::Jmp, vec![Operand::Label(label)]);
+            }
+            Terminator::Ret { value } => {
+                let return_type = self.function.return_type;
+                let ret = self.convention.signature(&[], return_type).ret;
+                if let (Some(value), Some(location)) = (value, ret) {
+                    self.value(value, return_type, location_acc(location));
+                }
+                self.insts.extend(frame::epilogue());
+            }
+            Terminator::Trap { reason } => {
+                let message = self.trap_message(&reason.to_string(), span);
+                self.trap(&message);
+            }
+            Terminator::Unreachable => self.emit(Mnemonic::Ud2, vec![]),
+        }
+    }
+}
+
+/// The accumulator that loads a value into the register of a location,
+/// with a scratch register for the other class
+fn location_acc(location: Location) -> Acc {
+    match location {
+        Location::Reg(RegClass::Float, number) => Acc {
+            xmm: Xmm(number),
+            gpr: STACK.gpr,
+        },
+        Location::Reg(RegClass::Int, number) => Acc {
+            xmm: STACK.xmm,
+            gpr: Gpr::from_number(number),
+        },
+        Location::Stack(_) => STACK,
+    }
+}
+
+/// How many operands an instruction reads at least
+fn expected_args(op: Opcode) -> usize {
+    match op {
+        Opcode::Copy | Opcode::Convert | Opcode::Call | Opcode::Load => 1,
+        Opcode::Select => 3,
+        _ => 2,
+    }
+}
@@ -0,0 +1,126 @@
+//! Block layout of selected x86-64 code.
+//!
+//! Instruction selection places the blocks in the order of the MIR and ends
+//! each with an explicit jump, so they can be placed in any order. This
+//! pass orders them with [`crate::codegen::layout`] and leaves removing the
+//! jumps that now go to the next block to the peephole pass, so it has to
+//! run before it.
+//!
+//! Machine blocks only know how often they run, so an edge is estimated to
+//! be taken as often as the less frequent of its two ends.
+
+use super::{MachineFunction, MachineProgram, Mnemonic, Operand};
+use crate::codegen::layout::{Edge, chain_blocks};
+use crate::diagnostics::DiagnosticCollector;
+use std::collections::HashMap;
+
+pub struct X86_64LayoutPass {
+    diagnostics: DiagnosticCollector,
+    moved: usize,
+    fallthroughs_before: usize,
+    fallthroughs_after: usize,
+}
+
+impl X86_64LayoutPass {
+    pub fn new() -> Self {
+        X86_64LayoutPass {
+            diagnostics: DiagnosticCollector::new(),
+            moved: 0,
+            fallthroughs_before: 0,
+            fallthroughs_after: 0,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MachineProgram) {
+        for function in &mut program.functions {
+            self.fallthroughs_before += jumps_to_next(function);
+            self.moved += lay_out(function);
+            self.fallthroughs_after += jumps_to_next(function);
+        }
+        self.diagnostics.info(format!(
+            "Block layout moved {} block(s), {} jump(s) now fall through, up from {}",
+            self.moved, self.fallthroughs_after, self.fallthroughs_before
+        ));
+    }
+}
+
+impl Default for X86_64LayoutPass {
+    fn default() -> Self {
+        X86_64LayoutPass::new()
+    }
+}
+
+/// The blocks each block jumps to, by position
+fn successors(function: &MachineFunction) -> Vec<Vec<usize>> {
+    let position: HashMap<&str, usize> = function
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.label.as_str(), i))
+        .collect();
+    function
+        .blocks
+        .iter()
+        .map(|block| {
+            block
+                .insts
+                .iter()
+                .filter(|inst| matches!(inst.mnemonic, Mnemonic::Jmp | Mnemonic::J(_)))
+                .filter_map(|inst| match inst.operands.first() {
+                    Some(Operand::Label(label)) => position.get(label.as_str()).copied(),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Reorders the blocks of a function, returning how many moved
+fn lay_out(function: &mut MachineFunction) -> usize {
+    if function.blocks.len() < 3 {
+        return 0;
+    }
+    let frequencies: Vec<f64> = function.blocks.iter().map(|block| block.frequency).collect();
+    let edges: Vec<Edge> = successors(function)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(from, targets)| targets.into_iter().map(move |to| (from, to)))
+        .map(|(from, to)| Edge {
+            from,
+            to,
+            frequency: frequencies[from].min(frequencies[to]),
+        })
+        .collect();
+    let falls_through: Vec<bool> = function
+        .blocks
+        .iter()
+        .map(|block| {
+            !block
+                .insts
+                .last()
+                .is_some_and(|inst| matches!(inst.mnemonic, Mnemonic::Jmp | Mnemonic::Ret | Mnemonic::Ud2))
+        })
+        .collect();
+
+    let order = chain_blocks(&frequencies, &edges, &falls_through);
+    let moved = order.iter().enumerate().filter(|&(at, &i)| at != i).count();
+    let mut blocks: Vec<_> = std::mem::take(&mut function.blocks).into_iter().map(Some).collect();
+    function.blocks = order
+        .into_iter()
+        .map(|i| blocks[i].take().expect("every block is placed once"))
+        .collect();
+    moved
+}
+
+/// How many jumps go to the block right after theirs
+fn jumps_to_next(function: &MachineFunction) -> usize {
+    successors(function)
+        .iter()
+        .enumerate()
+        .map(|(i, targets)| targets.iter().filter(|&&to| to == i + 1).count())
+        .sum()
+}
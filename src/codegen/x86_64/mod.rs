@@ -0,0 +1,488 @@
+//! x86-64 machine code, printed as AT&T assembly for the GNU assembler.
+//!
+//! Instructions are kept as data rather than text until the very end, so
+//! later passes can look at what an instruction reads and writes.
+
+pub mod frame;
+pub mod isel;
+pub mod layout;
+pub mod peephole;
+pub mod schedule;
+
+use super::triple::ObjectFormat;
+use crate::value::format_float;
+use std::fmt;
+
+/// A general purpose register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gpr {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+/// How many bits of a register an operand uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Width {
+    B8,
+    B16,
+    B32,
+    B64,
+}
+
+impl Gpr {
+    /// Every register, in the order of its number in instruction encodings
+    pub const ALL: [Gpr; 16] = [
+        Gpr::Rax,
+        Gpr::Rcx,
+        Gpr::Rdx,
+        Gpr::Rbx,
+        Gpr::Rsp,
+        Gpr::Rbp,
+        Gpr::Rsi,
+        Gpr::Rdi,
+        Gpr::R8,
+        Gpr::R9,
+        Gpr::R10,
+        Gpr::R11,
+        Gpr::R12,
+        Gpr::R13,
+        Gpr::R14,
+        Gpr::R15,
+    ];
+
+    /// The register with the given number in instruction encodings
+    pub fn from_number(number: u8) -> Gpr {
+        Gpr::ALL[number as usize]
+    }
+
+    /// The AT&T name of the register at the given width, without `%`
+    pub fn name(self, width: Width) -> &'static str {
+        const NAMES: [[&str; 4]; 16] = [
+            ["al", "ax", "eax", "rax"],
+            ["cl", "cx", "ecx", "rcx"],
+            ["dl", "dx", "edx", "rdx"],
+            ["bl", "bx", "ebx", "rbx"],
+            ["spl", "sp", "esp", "rsp"],
+            ["bpl", "bp", "ebp", "rbp"],
+            ["sil", "si", "esi", "rsi"],
+            ["dil", "di", "edi", "rdi"],
+            ["r8b", "r8w", "r8d", "r8"],
+            ["r9b", "r9w", "r9d", "r9"],
+            ["r10b", "r10w", "r10d", "r10"],
+            ["r11b", "r11w", "r11d", "r11"],
+            ["r12b", "r12w", "r12d", "r12"],
+            ["r13b", "r13w", "r13d", "r13"],
+            ["r14b", "r14w", "r14d", "r14"],
+            ["r15b", "r15w", "r15d", "r15"],
+        ];
+        let column = match width {
+            Width::B8 => 0,
+            Width::B16 => 1,
+            Width::B32 => 2,
+            Width::B64 => 3,
+        };
+        NAMES[self as usize][column]
+    }
+}
+
+/// An SSE register, `xmm0` through `xmm15`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Xmm(pub u8);
+
+/// A condition code, as used by `jcc` and `setcc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cond {
+    E,
+    Ne,
+    /// Signed less than
+    L,
+    Le,
+    G,
+    Ge,
+    /// Unsigned above, used for float comparisons
+    A,
+    Ae,
+    /// Unsigned below
+    B,
+    Be,
+    /// Parity, set by an unordered float comparison
+    P,
+    Np,
+    /// Signed overflow
+    O,
+    No,
+}
+
+impl Cond {
+    fn suffix(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::L => "l",
+            Cond::Le => "le",
+            Cond::G => "g",
+            Cond::Ge => "ge",
+            Cond::A => "a",
+            Cond::Ae => "ae",
+            Cond::B => "b",
+            Cond::Be => "be",
+            Cond::P => "p",
+            Cond::Np => "np",
+            Cond::O => "o",
+            Cond::No => "no",
+        }
+    }
+
+    /// The condition that holds exactly when this one does not
+    pub fn inverse(self) -> Cond {
+        match self {
+            Cond::E => Cond::Ne,
+            Cond::Ne => Cond::E,
+            Cond::L => Cond::Ge,
+            Cond::Ge => Cond::L,
+            Cond::Le => Cond::G,
+            Cond::G => Cond::Le,
+            Cond::A => Cond::Be,
+            Cond::Be => Cond::A,
+            Cond::Ae => Cond::B,
+            Cond::B => Cond::Ae,
+            Cond::P => Cond::Np,
+            Cond::Np => Cond::P,
+            Cond::O => Cond::No,
+            Cond::No => Cond::O,
+        }
+    }
+}
+
+/// An operand of a machine instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Gpr(Gpr, Width),
+    Xmm(Xmm),
+    Imm(i64),
+    /// `offset(base)`
+    Mem { base: Gpr, offset: i32 },
+    /// A symbol addressed relative to the instruction pointer
+    Symbol(String),
+    /// A jump or call target
+    Label(String),
+}
+
+impl Operand {
+    pub fn q(gpr: Gpr) -> Self {
+        Operand::Gpr(gpr, Width::B64)
+    }
+
+    pub fn b(gpr: Gpr) -> Self {
+        Operand::Gpr(gpr, Width::B8)
+    }
+
+    pub fn xmm(index: u8) -> Self {
+        Operand::Xmm(Xmm(index))
+    }
+
+    pub fn frame(offset: i32) -> Self {
+        Operand::Mem {
+            base: Gpr::Rbp,
+            offset,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Gpr(gpr, width) => write!(f, "%{}", gpr.name(*width)),
+            Operand::Xmm(xmm) => write!(f, "%xmm{}", xmm.0),
+            Operand::Imm(value) => write!(f, "${}", value),
+            Operand::Mem { base, offset } => write!(f, "{}(%{})", offset, base.name(Width::B64)),
+            Operand::Symbol(name) => write!(f, "{}(%rip)", name),
+            Operand::Label(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The operation of a machine instruction. Operands are given in AT&T
+/// order, sources before the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    Movq,
+    Movabsq,
+    Movl,
+    Movsd,
+    Movzbq,
+    Movsbq,
+    Movswq,
+    Movslq,
+    Leaq,
+    Pushq,
+    Popq,
+    Addq,
+    Subq,
+    Imulq,
+    Idivq,
+    Cqto,
+    Andb,
+    Orb,
+    Xorl,
+    Cmpq,
+    Testq,
+    Cmov(Cond),
+    Set(Cond),
+    Addsd,
+    Subsd,
+    Mulsd,
+    Divsd,
+    Xorpd,
+    Ucomisd,
+    Cvtsi2sdq,
+    Cvttsd2siq,
+    Cvtsd2ss,
+    Cvtss2sd,
+    Jmp,
+    J(Cond),
+    Call,
+    Leave,
+    Ret,
+    Ud2,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mnemonic::Movq => "movq",
+            Mnemonic::Movabsq => "movabsq",
+            Mnemonic::Movl => "movl",
+            Mnemonic::Movsd => "movsd",
+            Mnemonic::Movzbq => "movzbq",
+            Mnemonic::Movsbq => "movsbq",
+            Mnemonic::Movswq => "movswq",
+            Mnemonic::Movslq => "movslq",
+            Mnemonic::Leaq => "leaq",
+            Mnemonic::Pushq => "pushq",
+            Mnemonic::Popq => "popq",
+            Mnemonic::Addq => "addq",
+            Mnemonic::Subq => "subq",
+            Mnemonic::Imulq => "imulq",
+            Mnemonic::Idivq => "idivq",
+            Mnemonic::Cqto => "cqto",
+            Mnemonic::Andb => "andb",
+            Mnemonic::Orb => "orb",
+            Mnemonic::Xorl => "xorl",
+            Mnemonic::Cmpq => "cmpq",
+            Mnemonic::Testq => "testq",
+            Mnemonic::Cmov(cond) => return write!(f, "cmov{}q", cond.suffix()),
+            Mnemonic::Set(cond) => return write!(f, "set{}", cond.suffix()),
+            Mnemonic::Addsd => "addsd",
+            Mnemonic::Subsd => "subsd",
+            Mnemonic::Mulsd => "mulsd",
+            Mnemonic::Divsd => "divsd",
+            Mnemonic::Xorpd => "xorpd",
+            Mnemonic::Ucomisd => "ucomisd",
+            Mnemonic::Cvtsi2sdq => "cvtsi2sdq",
+            Mnemonic::Cvttsd2siq => "cvttsd2siq",
+            Mnemonic::Cvtsd2ss => "cvtsd2ss",
+            Mnemonic::Cvtss2sd => "cvtss2sd",
+            Mnemonic::Jmp => "jmp",
+            Mnemonic::J(cond) => return write!(f, "j{}", cond.suffix()),
+            Mnemonic::Call => "call",
+            Mnemonic::Leave => "leave",
+            Mnemonic::Ret => "ret",
+            Mnemonic::Ud2 => "ud2",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One machine instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+}
+
+impl Inst {
+    pub fn new(mnemonic: Mnemonic, operands: Vec<Operand>) -> Self {
+        Inst { mnemonic, operands }
+    }
+
+    /// Whether the instruction changes the status flags. Calls are
+    /// assumed to.
+    pub fn writes_flags(&self) -> bool {
+        matches!(
+            self.mnemonic,
+            Mnemonic::Addq
+                | Mnemonic::Subq
+                | Mnemonic::Imulq
+                | Mnemonic::Idivq
+                | Mnemonic::Andb
+                | Mnemonic::Orb
+                | Mnemonic::Xorl
+                | Mnemonic::Cmpq
+                | Mnemonic::Testq
+                | Mnemonic::Ucomisd
+                | Mnemonic::Call
+        )
+    }
+
+    /// Whether control may leave the block at this instruction
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self.mnemonic,
+            Mnemonic::Jmp | Mnemonic::J(_) | Mnemonic::Ret | Mnemonic::Ud2
+        )
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// A labelled run of instructions
+#[derive(Debug, Clone)]
+pub struct MachineBlock {
+    pub label: String,
+    pub insts: Vec<Inst>,
+    /// How many times the block is expected to run per call of its
+    /// function, see [`crate::mir::analysis::block_frequency`]
+    pub frequency: f64,
+}
+
+/// The code of one function
+#[derive(Debug, Clone)]
+pub struct MachineFunction {
+    pub symbol: String,
+    /// Whether `symbol` is visible outside the object file
+    pub global: bool,
+    /// Other names the function is visible under outside the object file
+    pub exported_as: Vec<String>,
+    pub blocks: Vec<MachineBlock>,
+}
+
+/// Storage of a global: eight zeroed bytes
+#[derive(Debug, Clone)]
+pub struct MachineGlobal {
+    pub symbol: String,
+    /// Other names the global is visible under outside the object file
+    pub exported_as: Vec<String>,
+}
+
+/// A float constant in read-only data, from the constant pool
+#[derive(Debug, Clone)]
+pub struct MachineConstant {
+    pub symbol: String,
+    pub bits: u64,
+}
+
+/// A string constant in read-only data
+#[derive(Debug, Clone)]
+pub struct MachineString {
+    pub symbol: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Everything that goes into one assembly file
+#[derive(Debug, Clone, Default)]
+pub struct MachineProgram {
+    pub functions: Vec<MachineFunction>,
+    pub globals: Vec<MachineGlobal>,
+    pub constants: Vec<MachineConstant>,
+    pub strings: Vec<MachineString>,
+}
+
+impl MachineProgram {
+    /// The program as an assembly file for the GNU assembler, or for the
+    /// LLVM assembler's Mach-O dialect of it
+    pub fn to_assembly(&self, format: ObjectFormat) -> String {
+        let elf = format == ObjectFormat::Elf;
+        let mut out = String::new();
+        out.push_str("\t.text\n");
+        for function in &self.functions {
+            out.push('\n');
+            if function.global {
+                out.push_str(&format!("\t.globl {}\n", function.symbol));
+            }
+            for name in &function.exported_as {
+                out.push_str(&format!("\t.globl {}\n", name));
+            }
+            if elf {
+                out.push_str(&format!("\t.type {}, @function\n", function.symbol));
+            }
+            for name in &function.exported_as {
+                out.push_str(&format!("{}:\n", name));
+            }
+            out.push_str(&format!("{}:\n", function.symbol));
+            for block in &function.blocks {
+                out.push_str(&format!("{}:\n", block.label));
+                for inst in &block.insts {
+                    out.push_str(&format!("\t{}\n", inst));
+                }
+            }
+            if elf {
+                out.push_str(&format!("\t.size {0}, .-{0}\n", function.symbol));
+            }
+        }
+
+        if !self.constants.is_empty() || !self.strings.is_empty() {
+            out.push_str(match format {
+                ObjectFormat::MachO => "\n\t.section __TEXT,__const\n",
+                ObjectFormat::Elf | ObjectFormat::Coff => "\n\t.section .rodata\n",
+            });
+            if !self.constants.is_empty() {
+                out.push_str("\t.p2align 3\n");
+            }
+            for constant in &self.constants {
+                out.push_str(&format!(
+                    "{}:\n\t.quad {:#x} # {}\n",
+                    constant.symbol,
+                    constant.bits,
+                    format_float(f64::from_bits(constant.bits))
+                ));
+            }
+            for string in &self.strings {
+                let bytes: Vec<String> = string.bytes.iter().map(|byte| byte.to_string()).collect();
+                out.push_str(&format!("{}:\n\t.byte {}\n", string.symbol, bytes.join(", ")));
+            }
+        }
+        if !self.globals.is_empty() {
+            // Mach-O only zero fills with `.zerofill`, so its globals are
+            // written out as data
+            out.push_str(match format {
+                ObjectFormat::MachO => "\n\t.data\n\t.p2align 3\n",
+                ObjectFormat::Elf | ObjectFormat::Coff => "\n\t.bss\n\t.p2align 3\n",
+            });
+            for global in &self.globals {
+                for name in &global.exported_as {
+                    out.push_str(&format!("\t.globl {0}\n{0}:\n", name));
+                }
+                out.push_str(&format!("{}:\n\t.zero 8\n", global.symbol));
+            }
+        }
+        if elf {
+            // The stack does not need to be executable
+            out.push_str("\n\t.section .note.GNU-stack,\"\",@progbits\n");
+        }
+        out
+    }
+}
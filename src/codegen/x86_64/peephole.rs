@@ -0,0 +1,174 @@
+//! Peephole optimization of selected x86-64 code.
+//!
+//! Instruction selection works one MIR instruction at a time, storing every
+//! result to its slot and loading every operand back, and ends every block
+//! with an explicit jump. This pass cleans up after it, looking at a few
+//! neighbouring instructions at a time:
+//!
+//! - moves of a register to itself are removed
+//! - a load from a slot right after a store of the same register to it is
+//!   removed, the register still holds the value
+//! - `set<c>`, `movzbq` and `testq` of the result followed by `jne` or
+//!   `je` branch on the condition directly, the flags still hold it
+//! - a jump to the block that follows is removed, and a conditional jump
+//!   over an unconditional one is inverted so the block falls through
+//!
+//! Values never stay in registers from one block to another, so the
+//! patterns only need to look within a block.
+
+use super::{Cond, Inst, MachineBlock, MachineFunction, MachineProgram, Mnemonic, Operand, Width};
+use crate::diagnostics::DiagnosticCollector;
+
+pub struct X86_64PeepholePass {
+    diagnostics: DiagnosticCollector,
+    removed: usize,
+}
+
+impl X86_64PeepholePass {
+    pub fn new() -> Self {
+        X86_64PeepholePass {
+            diagnostics: DiagnosticCollector::new(),
+            removed: 0,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MachineProgram) {
+        for function in &mut program.functions {
+            for block in &mut function.blocks {
+                self.removed += remove_redundant_moves(block);
+                self.removed += fold_branch_on_condition(block);
+            }
+            self.removed += remove_fallthrough_jumps(function);
+        }
+        self.diagnostics
+            .info(format!("Peephole removed {} instruction(s)", self.removed));
+    }
+}
+
+impl Default for X86_64PeepholePass {
+    fn default() -> Self {
+        X86_64PeepholePass::new()
+    }
+}
+
+fn is_move(mnemonic: Mnemonic) -> bool {
+    matches!(mnemonic, Mnemonic::Movq | Mnemonic::Movsd)
+}
+
+/// Removes `mov r, r`, and `mov m, r` right after `mov r, m`
+fn remove_redundant_moves(block: &mut MachineBlock) -> usize {
+    let before = block.insts.len();
+    let mut insts: Vec<Inst> = Vec::with_capacity(before);
+    for inst in std::mem::take(&mut block.insts) {
+        if is_move(inst.mnemonic) && inst.operands[0] == inst.operands[1] {
+            continue;
+        }
+        let reloads = insts.last().is_some_and(|store| {
+            store.mnemonic == inst.mnemonic
+                && is_move(inst.mnemonic)
+                && matches!(store.operands[1], Operand::Mem { .. })
+                && store.operands[1] == inst.operands[0]
+                && store.operands[0] == inst.operands[1]
+        });
+        if !reloads {
+            insts.push(inst);
+        }
+    }
+    block.insts = insts;
+    before - block.insts.len()
+}
+
+/// Replaces `set<c> %r8; movzbq %r8, %r; [movq %r, m;] testq %r, %r; jne l`
+/// with the same instructions up to the store and `j<c> l`. The moves leave
+/// the flags alone, so they still hold the condition.
+fn fold_branch_on_condition(block: &mut MachineBlock) -> usize {
+    let mut removed = 0;
+    let mut i = 0;
+    while i + 1 < block.insts.len() {
+        let jump = match block.insts[i + 1].mnemonic {
+            Mnemonic::J(Cond::Ne) => Some(false),
+            Mnemonic::J(Cond::E) => Some(true),
+            _ => None,
+        };
+        let test = &block.insts[i];
+        let Some(inverted) = jump.filter(|_| test.mnemonic == Mnemonic::Testq && test.operands[0] == test.operands[1])
+        else {
+            i += 1;
+            continue;
+        };
+        let Operand::Gpr(gpr, Width::B64) = test.operands[0] else {
+            i += 1;
+            continue;
+        };
+
+        // Step back over a store of the result to its slot
+        let mut at = i;
+        if at > 0 {
+            let store = &block.insts[at - 1];
+            if store.mnemonic == Mnemonic::Movq
+                && store.operands[0] == Operand::q(gpr)
+                && matches!(store.operands[1], Operand::Mem { .. })
+            {
+                at -= 1;
+            }
+        }
+        let cond = (at >= 2)
+            .then(|| (&block.insts[at - 2], &block.insts[at - 1]))
+            .and_then(|(set, extend)| match set.mnemonic {
+                Mnemonic::Set(cond)
+                    if set.operands[0] == Operand::b(gpr)
+                        && extend.mnemonic == Mnemonic::Movzbq
+                        && extend.operands == [Operand::b(gpr), Operand::q(gpr)] =>
+                {
+                    Some(cond)
+                }
+                _ => None,
+            });
+        let Some(cond) = cond else {
+            i += 1;
+            continue;
+        };
+
+        let cond = if inverted { cond.inverse() } else { cond };
+        let target = block.insts[i + 1].operands.clone();
+        block.insts.splice(i..i + 2, [Inst::new(Mnemonic::J(cond), target)]);
+        removed += 1;
+        i += 1;
+    }
+    removed
+}
+
+/// Removes jumps to the block that follows, inverting `j<c> a; jmp b` into
+/// `j<!c> b` when `a` follows
+fn remove_fallthrough_jumps(function: &mut MachineFunction) -> usize {
+    let mut removed = 0;
+    for index in 0..function.blocks.len().saturating_sub(1) {
+        let next = Operand::Label(function.blocks[index + 1].label.clone());
+        let insts = &mut function.blocks[index].insts;
+        let Some(last) = insts.last() else {
+            continue;
+        };
+        if last.mnemonic != Mnemonic::Jmp {
+            continue;
+        }
+        if last.operands[0] == next {
+            insts.pop();
+            removed += 1;
+            continue;
+        }
+        let len = insts.len();
+        if len >= 2
+            && let Mnemonic::J(cond) = insts[len - 2].mnemonic
+            && insts[len - 2].operands[0] == next
+        {
+            let jump = insts.pop().expect("the block ends in a jump");
+            insts[len - 2] = Inst::new(Mnemonic::J(cond.inverse()), jump.operands);
+            removed += 1;
+        }
+    }
+    removed
+}
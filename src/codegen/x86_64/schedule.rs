@@ -0,0 +1,258 @@
+//! Instruction scheduling of selected x86-64 code.
+//!
+//! Each block is split into regions at the instructions that cannot move:
+//! jumps, calls and whatever changes the stack pointer. The instructions
+//! of each region are reordered with [`crate::codegen::schedule`], from
+//! what they read and write and rough latencies of a modern core. A region
+//! keeps its order unless the new one is estimated to be faster.
+
+use super::{Gpr, Inst, MachineProgram, Mnemonic, Operand};
+use crate::codegen::schedule::{Effects, dependences, estimate_cycles, list_schedule};
+use crate::diagnostics::DiagnosticCollector;
+
+/// Something instructions are ordered by when they read or write it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Resource {
+    Gpr(Gpr),
+    Xmm(u8),
+    Flags,
+    /// A stack slot, at its offset from `rbp`
+    Slot(i32),
+    /// Any other memory
+    Memory,
+}
+
+pub struct X86_64SchedulingPass {
+    diagnostics: DiagnosticCollector,
+    rescheduled: usize,
+    cycles_before: u64,
+    cycles_after: u64,
+}
+
+impl X86_64SchedulingPass {
+    pub fn new() -> Self {
+        X86_64SchedulingPass {
+            diagnostics: DiagnosticCollector::new(),
+            rescheduled: 0,
+            cycles_before: 0,
+            cycles_after: 0,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MachineProgram) {
+        for function in &mut program.functions {
+            for block in &mut function.blocks {
+                let mut insts = Vec::with_capacity(block.insts.len());
+                let mut region = Vec::new();
+                for inst in std::mem::take(&mut block.insts) {
+                    if is_barrier(&inst) {
+                        insts.extend(self.schedule(std::mem::take(&mut region)));
+                        insts.push(inst);
+                    } else {
+                        region.push(inst);
+                    }
+                }
+                insts.extend(self.schedule(region));
+                block.insts = insts;
+            }
+        }
+        self.diagnostics.info(format!(
+            "Rescheduled {} region(s), estimated {} cycle(s) down from {}",
+            self.rescheduled, self.cycles_after, self.cycles_before
+        ));
+    }
+
+    fn schedule(&mut self, region: Vec<Inst>) -> Vec<Inst> {
+        let effects: Vec<Effects<Resource>> = region.iter().map(effects).collect();
+        let latencies: Vec<u32> = effects.iter().map(|effect| effect.latency).collect();
+        let preds = dependences(&effects);
+        let original: Vec<usize> = (0..region.len()).collect();
+        let before = estimate_cycles(&latencies, &preds, &original);
+        let order = list_schedule(&latencies, &preds);
+        let after = estimate_cycles(&latencies, &preds, &order);
+
+        self.cycles_before += before as u64;
+        if after >= before {
+            self.cycles_after += before as u64;
+            return region;
+        }
+        self.cycles_after += after as u64;
+        self.rescheduled += 1;
+        let mut slots: Vec<Option<Inst>> = region.into_iter().map(Some).collect();
+        order
+            .into_iter()
+            .map(|i| slots[i].take().expect("every instruction is scheduled once"))
+            .collect()
+    }
+}
+
+impl Default for X86_64SchedulingPass {
+    fn default() -> Self {
+        X86_64SchedulingPass::new()
+    }
+}
+
+/// Instructions that stay where they are, with everything before them
+/// before and everything after them after
+fn is_barrier(inst: &Inst) -> bool {
+    inst.is_branch()
+        || matches!(
+            inst.mnemonic,
+            Mnemonic::Call | Mnemonic::Leave | Mnemonic::Pushq | Mnemonic::Popq
+        )
+}
+
+/// The resources an operand reads when its value is used
+fn reads(operand: &Operand, out: &mut Vec<Resource>) {
+    match operand {
+        Operand::Gpr(gpr, _) => out.push(Resource::Gpr(*gpr)),
+        Operand::Xmm(xmm) => out.push(Resource::Xmm(xmm.0)),
+        Operand::Mem { base, offset } => {
+            out.push(Resource::Gpr(*base));
+            out.push(match base {
+                Gpr::Rbp => Resource::Slot(*offset),
+                _ => Resource::Memory,
+            });
+        }
+        Operand::Symbol(_) => out.push(Resource::Memory),
+        Operand::Imm(_) | Operand::Label(_) => {}
+    }
+}
+
+/// Adds the resources an operand writes when it is a destination, and the
+/// registers its address reads
+fn writes(operand: &Operand, reads: &mut Vec<Resource>, writes: &mut Vec<Resource>) {
+    match operand {
+        Operand::Gpr(gpr, _) => writes.push(Resource::Gpr(*gpr)),
+        Operand::Xmm(xmm) => writes.push(Resource::Xmm(xmm.0)),
+        Operand::Mem { base, offset } => {
+            reads.push(Resource::Gpr(*base));
+            writes.push(match base {
+                Gpr::Rbp => Resource::Slot(*offset),
+                _ => Resource::Memory,
+            });
+        }
+        Operand::Symbol(_) => writes.push(Resource::Memory),
+        Operand::Imm(_) | Operand::Label(_) => {}
+    }
+}
+
+fn effects(inst: &Inst) -> Effects<Resource> {
+    let (mut r, mut w) = (Vec::new(), Vec::new());
+    let ops = &inst.operands;
+    match inst.mnemonic {
+        // The address is computed, the memory at it is not read
+        Mnemonic::Leaq => {
+            if let Operand::Mem { base, .. } = &ops[0] {
+                r.push(Resource::Gpr(*base));
+            }
+            writes(&ops[1], &mut r, &mut w);
+        }
+        Mnemonic::Movq
+        | Mnemonic::Movabsq
+        | Mnemonic::Movl
+        | Mnemonic::Movsd
+        | Mnemonic::Movzbq
+        | Mnemonic::Movsbq
+        | Mnemonic::Movswq
+        | Mnemonic::Movslq
+        | Mnemonic::Cvtsi2sdq
+        | Mnemonic::Cvttsd2siq
+        | Mnemonic::Cvtsd2ss
+        | Mnemonic::Cvtss2sd => {
+            reads(&ops[0], &mut r);
+            writes(&ops[1], &mut r, &mut w);
+        }
+        // Only the low byte is written, the rest of the register is kept
+        Mnemonic::Set(_) => {
+            r.push(Resource::Flags);
+            reads(&ops[0], &mut r);
+            writes(&ops[0], &mut r, &mut w);
+        }
+        Mnemonic::Cmov(_) => {
+            r.push(Resource::Flags);
+            reads(&ops[0], &mut r);
+            reads(&ops[1], &mut r);
+            writes(&ops[1], &mut r, &mut w);
+        }
+        Mnemonic::Addq
+        | Mnemonic::Subq
+        | Mnemonic::Imulq
+        | Mnemonic::Andb
+        | Mnemonic::Orb
+        | Mnemonic::Xorl
+        | Mnemonic::Addsd
+        | Mnemonic::Subsd
+        | Mnemonic::Mulsd
+        | Mnemonic::Divsd
+        | Mnemonic::Xorpd => {
+            reads(&ops[0], &mut r);
+            reads(&ops[1], &mut r);
+            writes(&ops[1], &mut r, &mut w);
+        }
+        Mnemonic::Cmpq | Mnemonic::Testq | Mnemonic::Ucomisd => {
+            reads(&ops[0], &mut r);
+            reads(&ops[1], &mut r);
+        }
+        Mnemonic::Cqto => {
+            r.push(Resource::Gpr(Gpr::Rax));
+            w.push(Resource::Gpr(Gpr::Rdx));
+        }
+        Mnemonic::Idivq => {
+            reads(&ops[0], &mut r);
+            r.extend([Resource::Gpr(Gpr::Rax), Resource::Gpr(Gpr::Rdx)]);
+            w.extend([Resource::Gpr(Gpr::Rax), Resource::Gpr(Gpr::Rdx)]);
+        }
+        // Barriers are never scheduled; order them after everything
+        Mnemonic::Pushq
+        | Mnemonic::Popq
+        | Mnemonic::Jmp
+        | Mnemonic::J(_)
+        | Mnemonic::Call
+        | Mnemonic::Leave
+        | Mnemonic::Ret
+        | Mnemonic::Ud2 => {
+            r.extend([Resource::Flags, Resource::Memory]);
+            w.extend([Resource::Flags, Resource::Memory]);
+        }
+    }
+    if inst.writes_flags() && !w.contains(&Resource::Flags) {
+        w.push(Resource::Flags);
+    }
+    Effects {
+        reads: r,
+        writes: w,
+        latency: latency(inst),
+    }
+}
+
+/// Rough cycles until the result of an instruction can be used
+fn latency(inst: &Inst) -> u32 {
+    let loads = inst
+        .operands
+        .first()
+        .is_some_and(|operand| matches!(operand, Operand::Mem { .. } | Operand::Symbol(_)))
+        && inst.mnemonic != Mnemonic::Leaq;
+    let base = match inst.mnemonic {
+        Mnemonic::Idivq => 40,
+        Mnemonic::Divsd => 14,
+        Mnemonic::Addsd | Mnemonic::Subsd | Mnemonic::Mulsd => 4,
+        Mnemonic::Cvtsi2sdq | Mnemonic::Cvttsd2siq | Mnemonic::Cvtsd2ss | Mnemonic::Cvtss2sd => 4,
+        Mnemonic::Imulq | Mnemonic::Ucomisd => 3,
+        // Between the general purpose and the SSE registers
+        Mnemonic::Movq if ops_cross_files(inst) => 2,
+        _ => 1,
+    };
+    if loads { base + 4 } else { base }
+}
+
+fn ops_cross_files(inst: &Inst) -> bool {
+    matches!(
+        inst.operands.as_slice(),
+        [Operand::Gpr(..), Operand::Xmm(_)] | [Operand::Xmm(_), Operand::Gpr(..)]
+    )
+}
@@ -0,0 +1,1338 @@
+//! Library-level driver for the Iris compiler.
+//!
+//! The [`Compiler`] type owns a set of sources and a configuration and runs
+//! the pass pipeline on demand, returning the produced program together with
+//! every diagnostic emitted along the way. The CLI is a thin wrapper around it.
+
+use crate::ast::{Attribute, Expression, NodeId, Program};
+use crate::diagnostics::{
+    Applicability, Diagnostic, DiagnosticCollector, Lint, Suggestion, TextEdit, closest_match,
+};
+use crate::frontend::{ComparisonChains, DEFAULT_MAX_NESTING_DEPTH, Edit, LexerContext, ParserContext, Token};
+use crate::hir::analysis::confusables::ConfusableIdentifiers;
+use crate::hir::analysis::purity::PurityAnalysis;
+use crate::hir::analysis::unused::UnusedVariables;
+use crate::hir::passes::counting::CountingPass;
+use crate::hir::passes::lowering::LoweringPass;
+use crate::hir::passes::print::PrintPass;
+use crate::hir::passes::typechecking::TypecheckingPass;
+use crate::hir::visitor::{Visitor, VisitorRef};
+use crate::mir::analysis::call_graph::CallGraph;
+use crate::mir::block_params::phis_to_block_params;
+use crate::mir::history::IrHistory;
+use crate::mir::passes::checks::MirChecksPass;
+use crate::mir::passes::compact_registers::MirCompactRegistersPass;
+use crate::mir::passes::constant_pool::MirConstantPoolPass;
+use crate::mir::passes::coverage::MirCoveragePass;
+use crate::mir::passes::dead_functions::MirDeadFunctionPass;
+use crate::mir::passes::out_of_ssa::MirOutOfSSAPass;
+use crate::mir::passes::print::MirPrintingPass;
+use crate::mir::passes::ssa::MirSSAPass;
+use crate::mir::passes::verify::MirVerifierPass;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::MirProgram;
+use crate::codegen::abi::CallingConvention;
+use crate::codegen::rust::RustEmitter;
+use crate::codegen::toolchain::{TempFile, Toolchain};
+use crate::codegen::triple::{Arch, Os, TargetTriple};
+use crate::codegen::x86_64::MachineProgram;
+use crate::codegen::x86_64::isel::X86_64Selector;
+use crate::codegen::x86_64::layout::X86_64LayoutPass;
+use crate::codegen::x86_64::peephole::X86_64PeepholePass;
+use crate::codegen::x86_64::schedule::X86_64SchedulingPass;
+use crate::json;
+use crate::registry::{
+    DEFAULT_HIR_PASSES, DEFAULT_MIR_PASSES, HirPass, PassFactory, PassOptions, PassRegistry, SATURATION_MIR_PASSES,
+};
+use crate::term::Painter;
+use crate::testing::compare_runs;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Optimization level controlling which simplification passes run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    /// No simplification, the program is lowered as written
+    O0,
+    /// Constant folding and algebraic simplification on the AST, and
+    /// instruction combining, reassociation, strength reduction, common
+    /// subexpression elimination, flattening of small branches and dead
+    /// code removal on the MIR
+    #[default]
+    O1,
+    /// The passes of `O1`, and equality saturation of float arithmetic
+    /// before dead code removal. Needs the `saturation` feature.
+    Osat,
+}
+
+/// How merges of SSA values are represented in the lowered MIR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SsaForm {
+    /// Phi nodes at the top of merge blocks
+    #[default]
+    Phi,
+    /// Block parameters with arguments passed on each branch
+    BlockParams,
+}
+
+/// What integer division and remainder by zero do at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DivisionByZero {
+    /// The divisor is checked and the program traps if it is zero
+    #[default]
+    Trap,
+    /// No check is made, the result is undefined
+    Undefined,
+}
+
+/// Which problems stop the pipeline from transforming the program further
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopPolicy {
+    /// Stop once a pass reports an error
+    #[default]
+    OnError,
+    /// Stop once a pass reports an error or a warning
+    OnWarning,
+}
+
+/// The stage a call to [`Compiler::emit`] should produce and print
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The tokens of every source, one per line
+    Tokens,
+    /// The tokens of every source as JSON Lines, one object per token
+    TokensJson,
+    Ast,
+    /// The parsed program with the source text each span covers
+    Spans,
+    Mir,
+    /// x86-64 assembly for the GNU assembler
+    Asm,
+    /// A relocatable object file
+    Object,
+    /// A Rust module with the functions of the program
+    Rust,
+}
+
+impl Target {
+    /// The name the target is asked for with, as in `--emit`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Tokens => "tokens",
+            Target::TokensJson => "tokens-json",
+            Target::Ast => "ast",
+            Target::Spans => "spans",
+            Target::Mir => "mir",
+            Target::Asm => "asm",
+            Target::Object => "obj",
+            Target::Rust => "rust",
+        }
+    }
+
+    /// Extension of the file the target is written to
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::Tokens => "tokens",
+            Target::TokensJson => "tokens.jsonl",
+            Target::Ast => "ast",
+            Target::Spans => "spans",
+            Target::Mir => "mir",
+            Target::Asm => "s",
+            Target::Object => "o",
+            Target::Rust => "rs",
+        }
+    }
+}
+
+/// Output of [`Compiler::emit`]
+#[derive(Debug)]
+pub enum Artifact {
+    Tokens(Vec<SourceTokens>),
+    Ast(Program),
+    Mir(MirProgram),
+    Assembly(String),
+    Object(Vec<u8>),
+    Rust(String),
+}
+
+/// The tokens lexed from one source
+#[derive(Debug, Clone)]
+pub struct SourceTokens {
+    pub source: String,
+    pub tokens: Vec<Token>,
+}
+
+/// A named piece of Iris source code
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+/// The outcome of running (part of) the pipeline.
+///
+/// `output` is `None` when a stage reported errors, in which case the
+/// diagnostics explain why.
+#[derive(Debug)]
+pub struct CompileResult<T> {
+    pub output: Option<T>,
+    pub diagnostics: DiagnosticCollector,
+}
+
+impl<T> CompileResult<T> {
+    fn failed(diagnostics: DiagnosticCollector) -> Self {
+        CompileResult {
+            output: None,
+            diagnostics,
+        }
+    }
+
+    /// Returns true if the stage produced an output without errors
+    pub fn is_ok(&self) -> bool {
+        self.output.is_some() && !self.diagnostics.has_errors()
+    }
+
+    /// Transforms the output, keeping the diagnostics
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> CompileResult<U> {
+        CompileResult {
+            output: self.output.map(f),
+            diagnostics: self.diagnostics,
+        }
+    }
+}
+
+/// Builder-style entry point for compiling Iris programs from Rust.
+///
+/// # Example
+/// ```ignore
+/// let mut compiler = Compiler::new();
+/// compiler
+///     .add_source("main.iris", "fn main() -> f64 { return 1 }")
+///     .set_opt_level(OptLevel::O0);
+/// let result = compiler.lower();
+/// assert!(result.is_ok());
+/// ```
+#[derive(Debug)]
+pub struct Compiler {
+    sources: Vec<Source>,
+    /// Tokens kept from the last update of each source, by index into
+    /// `sources`. `None` until [`Compiler::update_source`] has lexed it.
+    tokens: Vec<Option<Vec<Token>>>,
+    opt_level: OptLevel,
+    ssa_form: SsaForm,
+    max_nesting_depth: usize,
+    fast_math: bool,
+    division_by_zero: DivisionByZero,
+    runtime_checks: bool,
+    coverage: bool,
+    verify_opts: bool,
+    /// Passes to optimize with in place of those of the optimization level
+    passes: Option<Vec<String>>,
+    registry: PassRegistry,
+    debug_info: bool,
+    script_mode: bool,
+    comparison_chains: ComparisonChains,
+    error_limit: Option<usize>,
+    stop_policy: StopPolicy,
+    color: bool,
+    target_triple: TargetTriple,
+    scheduling: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler {
+            sources: Vec::new(),
+            tokens: Vec::new(),
+            opt_level: OptLevel::default(),
+            ssa_form: SsaForm::default(),
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            fast_math: false,
+            division_by_zero: DivisionByZero::default(),
+            runtime_checks: false,
+            coverage: false,
+            verify_opts: false,
+            passes: None,
+            registry: PassRegistry::new(),
+            debug_info: false,
+            script_mode: false,
+            comparison_chains: ComparisonChains::default(),
+            error_limit: None,
+            stop_policy: StopPolicy::default(),
+            color: false,
+            target_triple: TargetTriple::host(),
+            scheduling: true,
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an in-memory source under the given name
+    pub fn add_source(&mut self, name: impl Into<String>, text: impl Into<String>) -> &mut Self {
+        self.sources.push(Source {
+            name: name.into(),
+            text: text.into(),
+        });
+        self.tokens.push(None);
+        self
+    }
+
+    /// Replaces the text of the source with the given name, or adds it if
+    /// there is none. Only the lines that changed are lexed again, the
+    /// tokens of the rest are kept from the previous text, so a small edit
+    /// to a large source is cheap to compile again. Returns whether the text
+    /// changed.
+    pub fn update_source(&mut self, name: &str, text: impl Into<String>) -> bool {
+        let text = text.into();
+        let Some(index) = self.sources.iter().position(|source| source.name == name) else {
+            self.add_source(name, text);
+            return true;
+        };
+        if self.sources[index].text == text {
+            return false;
+        }
+
+        let previous = self.tokens[index]
+            .take()
+            .or_else(|| LexerContext::lex(&self.sources[index].text).ok());
+        // A source that does not lex is lexed in full, which reports why
+        self.tokens[index] = previous.and_then(|previous| {
+            let edit = changed_lines(&self.sources[index].text, &text);
+            LexerContext::relex(&previous, &text, edit).ok().map(|relexed| relexed.tokens)
+        });
+        self.sources[index].text = text;
+        true
+    }
+
+    /// Reads a file from disk and adds it as a source
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> io::Result<&mut Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Ok(self.add_source(path.display().to_string(), text))
+    }
+
+    pub fn set_opt_level(&mut self, level: OptLevel) -> &mut Self {
+        self.opt_level = level;
+        self
+    }
+
+    pub fn opt_level(&self) -> OptLevel {
+        self.opt_level
+    }
+
+    pub fn set_ssa_form(&mut self, form: SsaForm) -> &mut Self {
+        self.ssa_form = form;
+        self
+    }
+
+    pub fn ssa_form(&self) -> SsaForm {
+        self.ssa_form
+    }
+
+    /// Limits how deeply expressions and blocks may nest in the source
+    pub fn set_max_nesting_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_nesting_depth = depth;
+        self
+    }
+
+    pub fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth
+    }
+
+    /// Allows optimizations that assume floats are never NaN or infinite,
+    /// ignore the sign of zero and treat arithmetic as associative. Results
+    /// can differ from strict IEEE 754 evaluation.
+    pub fn set_fast_math(&mut self, enabled: bool) -> &mut Self {
+        self.fast_math = enabled;
+        self
+    }
+
+    pub fn fast_math(&self) -> bool {
+        self.fast_math
+    }
+
+    /// Chooses whether integer division and remainder check for a zero divisor
+    pub fn set_division_by_zero(&mut self, behavior: DivisionByZero) -> &mut Self {
+        self.division_by_zero = behavior;
+        self
+    }
+
+    pub fn division_by_zero(&self) -> DivisionByZero {
+        self.division_by_zero
+    }
+
+    /// Traps at runtime when a float operation produces NaN or a value is
+    /// read before it was written, see [`MirChecksPass`]
+    pub fn set_runtime_checks(&mut self, enabled: bool) -> &mut Self {
+        self.runtime_checks = enabled;
+        self
+    }
+
+    pub fn runtime_checks(&self) -> bool {
+        self.runtime_checks
+    }
+
+    /// Counts how often every block of the program runs, see
+    /// [`crate::coverage`]
+    pub fn set_coverage(&mut self, enabled: bool) -> &mut Self {
+        self.coverage = enabled;
+        self
+    }
+
+    pub fn coverage(&self) -> bool {
+        self.coverage
+    }
+
+    /// Runs `main` and the `@test` functions in the interpreter as compiled
+    /// with no passes at all and as optimized, and reports an error if any
+    /// of them behaves differently, see [`compare_runs`]
+    pub fn set_verify_opts(&mut self, enabled: bool) -> &mut Self {
+        self.verify_opts = enabled;
+        self
+    }
+
+    pub fn verify_opts(&self) -> bool {
+        self.verify_opts
+    }
+
+    /// Optimizes with the named passes of the registry, in the given order,
+    /// instead of the passes of the optimization level. `None` goes back
+    /// to those.
+    pub fn set_passes(&mut self, passes: Option<Vec<String>>) -> &mut Self {
+        self.passes = passes;
+        self
+    }
+
+    pub fn passes(&self) -> Option<&[String]> {
+        self.passes.as_deref()
+    }
+
+    /// The passes [`Compiler::set_passes`] picks from, to register more
+    pub fn pass_registry_mut(&mut self) -> &mut PassRegistry {
+        &mut self.registry
+    }
+
+    pub fn pass_registry(&self) -> &PassRegistry {
+        &self.registry
+    }
+
+    /// The names of the passes to optimize with
+    fn pipeline(&self) -> Vec<&str> {
+        match &self.passes {
+            Some(passes) => passes.iter().map(String::as_str).collect(),
+            None => match self.opt_level {
+                OptLevel::O0 => Vec::new(),
+                OptLevel::O1 => DEFAULT_HIR_PASSES.iter().chain(DEFAULT_MIR_PASSES).copied().collect(),
+                OptLevel::Osat => DEFAULT_HIR_PASSES.iter().chain(SATURATION_MIR_PASSES).copied().collect(),
+            },
+        }
+    }
+
+    fn pass_options(&self) -> PassOptions {
+        PassOptions {
+            fast_math: self.fast_math,
+        }
+    }
+
+    /// Annotates emitted MIR with the source span of every instruction and
+    /// terminator
+    pub fn set_debug_info(&mut self, enabled: bool) -> &mut Self {
+        self.debug_info = enabled;
+        self
+    }
+
+    pub fn debug_info(&self) -> bool {
+        self.debug_info
+    }
+
+    /// Runs top-level statements as the body of an implicit `main`, for
+    /// small scripts that don't define any functions
+    pub fn set_script_mode(&mut self, enabled: bool) -> &mut Self {
+        self.script_mode = enabled;
+        self
+    }
+
+    pub fn script_mode(&self) -> bool {
+        self.script_mode
+    }
+
+    /// Whether comparisons chained as `a < b < c` are rejected or read as
+    /// `a < b && b < c`
+    pub fn set_comparison_chains(&mut self, chains: ComparisonChains) -> &mut Self {
+        self.comparison_chains = chains;
+        self
+    }
+
+    pub fn comparison_chains(&self) -> ComparisonChains {
+        self.comparison_chains
+    }
+
+    /// Keeps at most `limit` errors and only counts the rest, so one
+    /// mistake cannot bury the output in follow-on errors
+    pub fn set_error_limit(&mut self, limit: Option<usize>) -> &mut Self {
+        self.error_limit = limit;
+        self
+    }
+
+    pub fn error_limit(&self) -> Option<usize> {
+        self.error_limit
+    }
+
+    /// Sets which problems stop the passes that transform the program.
+    /// Passes that only check it run regardless.
+    pub fn set_stop_policy(&mut self, policy: StopPolicy) -> &mut Self {
+        self.stop_policy = policy;
+        self
+    }
+
+    pub fn stop_policy(&self) -> StopPolicy {
+        self.stop_policy
+    }
+
+    /// Color what [`Compiler::emit`] prints for terminals
+    pub fn set_color(&mut self, enabled: bool) -> &mut Self {
+        self.color = enabled;
+        self
+    }
+
+    pub fn color(&self) -> bool {
+        self.color
+    }
+
+    /// The machine and system to generate code for, the host by default
+    pub fn set_target_triple(&mut self, triple: TargetTriple) -> &mut Self {
+        self.target_triple = triple;
+        self
+    }
+
+    pub fn target_triple(&self) -> &TargetTriple {
+        &self.target_triple
+    }
+
+    /// Reorder machine instructions to hide the latency of slow ones, at
+    /// `-O1`. Turning it off keeps instructions in the order they were
+    /// selected in, which is easier to follow.
+    pub fn set_scheduling(&mut self, enabled: bool) -> &mut Self {
+        self.scheduling = enabled;
+        self
+    }
+
+    pub fn scheduling(&self) -> bool {
+        self.scheduling
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Lexes every source without parsing it
+    pub fn lex(&self) -> CompileResult<Vec<SourceTokens>> {
+        let mut diagnostics = DiagnosticCollector::with_error_limit(self.error_limit);
+        let mut lexed = Vec::new();
+        for (source, cached) in self.sources.iter().zip(&self.tokens) {
+            lexed.push(SourceTokens {
+                source: source.name.clone(),
+                tokens: lex_source(source, cached, &mut diagnostics),
+            });
+        }
+
+        if diagnostics.has_errors() {
+            return CompileResult::failed(diagnostics);
+        }
+        CompileResult {
+            output: Some(lexed),
+            diagnostics,
+        }
+    }
+
+    /// Lexes and parses every source, merging them into a single program
+    pub fn parse(&self) -> CompileResult<Program> {
+        let (program, diagnostics) = self.parse_recovering();
+        if diagnostics.has_errors() {
+            return CompileResult::failed(diagnostics);
+        }
+        CompileResult {
+            output: Some(program),
+            diagnostics,
+        }
+    }
+
+    /// Like [`Compiler::parse`], but returns the program even when there are
+    /// syntax errors, with the code that did not parse replaced by error
+    /// nodes. Text that does not lex is reported and skipped. The program
+    /// can still be typechecked, which is what editors need to work on
+    /// broken code.
+    pub fn parse_recovering(&self) -> (Program, DiagnosticCollector) {
+        let mut diagnostics = DiagnosticCollector::with_error_limit(self.error_limit);
+        let mut program = Program {
+            globals: Vec::new(),
+            functions: Vec::new(),
+            externs: Vec::new(),
+            newtypes: Vec::new(),
+            enums: Vec::new(),
+            generics: Vec::new(),
+            statement_attrs: Vec::new(),
+            // Node ids are unique across all sources of the program
+            next_node_id: NodeId(0),
+        };
+
+        for (source, cached) in self.sources.iter().zip(&self.tokens) {
+            let tokens = lex_source(source, cached, &mut diagnostics);
+
+            let mut parser = ParserContext::new(tokens)
+                .with_source_name(&source.name)
+                .with_first_node_id(program.next_node_id)
+                .with_max_depth(self.max_nesting_depth)
+                .with_script_mode(self.script_mode)
+                .with_comparison_chains(self.comparison_chains);
+            let (parsed, errors) = parser.parse_recovering();
+            program.next_node_id = parser.next_node_id();
+            for e in errors {
+                diagnostics.error(format!("{}: Parse error: {}", source.name, e.message));
+            }
+            program.globals.extend(parsed.globals);
+            program.functions.extend(parsed.functions);
+            program.externs.extend(parsed.externs);
+            program.newtypes.extend(parsed.newtypes);
+            program.enums.extend(parsed.enums);
+            program.statement_attrs.extend(parsed.statement_attrs);
+        }
+
+        (program, diagnostics)
+    }
+
+    /// Parses, simplifies and typechecks the program. Code that does not
+    /// parse is left out and the rest is still checked, so one run reports
+    /// as many problems as it can.
+    pub fn check(&self) -> CompileResult<Program> {
+        self.front_end(true)
+    }
+
+    /// Parses and typechecks the program, without gathering statistics or
+    /// simplifying it. This is the fast way to find problems when nothing
+    /// will be compiled, as in editors and CI. The spans of the returned
+    /// program and of its diagnostics are those of the source as written.
+    pub fn typecheck(&self) -> CompileResult<Program> {
+        self.front_end(false)
+    }
+
+    /// The passes up to typechecking. `full` adds the ones only needed to
+    /// compile the program.
+    fn front_end(&self, full: bool) -> CompileResult<Program> {
+        let (mut program, mut diagnostics) = self.parse_recovering();
+        allow_lints(&program, &mut diagnostics);
+        let mut passes = PassManager::new(self.stop_policy, diagnostics);
+
+        if full {
+            passes.analyze(&mut CountingPass::new(), &program);
+        }
+
+        // Before simplification, which may fold uses of variables away
+        let purity = PurityAnalysis::new(&program);
+        report_unused_variables(&UnusedVariables::new(&program, &purity), &mut passes.diagnostics);
+        report_confusable_identifiers(&ConfusableIdentifiers::new(&program), &mut passes.diagnostics);
+
+        // Before the passes, which may drop code that must typecheck all the same
+        passes.check(&mut TypecheckingPass::new(), &mut program);
+
+        if full {
+            let mut transformed = false;
+            for name in self.pipeline() {
+                match self.registry.get(name) {
+                    Some(PassFactory::Hir(factory)) => {
+                        transformed |= !passes.stopped();
+                        passes.transform(factory(self.pass_options()).as_mut(), &mut program)
+                    }
+                    Some(PassFactory::Mir(_)) => {}
+                    None => {
+                        let hint = match closest_match(name, self.registry.names()) {
+                            Some(closest) => format!(", did you mean '{}'?", closest),
+                            None => String::new(),
+                        };
+                        passes.diagnostics.error(format!("Unknown pass '{}'{}", name, hint));
+                    }
+                }
+            }
+            // Types what the passes wrote, such as the temporaries of `cse`
+            if transformed && !passes.stopped() {
+                passes.check(&mut TypecheckingPass::new(), &mut program);
+            }
+        }
+
+        report_purity_violations(&PurityAnalysis::new(&program), &mut passes.diagnostics);
+
+        passes.finish(program)
+    }
+
+    /// Runs the full pipeline down to SSA-form MIR
+    pub fn lower(&self) -> CompileResult<MirProgram> {
+        self.lower_recording(None)
+    }
+
+    /// Lowers the program like [`Compiler::lower`], keeping a copy of the
+    /// MIR after every pass that may change it
+    pub fn ir_history(&self) -> CompileResult<IrHistory> {
+        let mut history = IrHistory::new();
+        self.lower_recording(Some(&mut history)).map(|_| history)
+    }
+
+    fn lower_recording(&self, mut history: Option<&mut IrHistory>) -> CompileResult<MirProgram> {
+        let mut record = |pass: &str, mir: &MirProgram| {
+            if let Some(history) = history.as_deref_mut() {
+                history.record(pass, mir);
+            }
+        };
+        let CompileResult {
+            output,
+            diagnostics,
+        } = self.check();
+        let Some(mut program) = output else {
+            return CompileResult::failed(diagnostics);
+        };
+        let mut passes = PassManager::new(self.stop_policy, diagnostics);
+
+        let Some(mut mir) = self.lower_to_ssa(&mut program, &mut passes, &mut record) else {
+            return passes.fail();
+        };
+
+        // Lowered again with no passes at all, so the HIR passes are verified too
+        let mut unoptimized = None;
+        if self.verify_opts {
+            let Some(mir) = self.unoptimized() else {
+                passes.diagnostics.error("Could not compile the program without optimizations to verify them".to_string());
+                return passes.fail();
+            };
+            unoptimized = Some(mir);
+        }
+
+        // Unknown names were reported by the front end
+        for name in self.pipeline() {
+            if let Some(PassFactory::Mir(factory)) = self.registry.get(name) {
+                let mut pass = factory(self.pass_options());
+                pass.run(&mut mir);
+                passes.diagnostics.merge(pass.diagnostics());
+                record(name, &mir);
+            }
+        }
+
+        if self.ssa_form == SsaForm::BlockParams {
+            for function in &mut mir.functions {
+                phis_to_block_params(function);
+            }
+            record("block-params", &mir);
+        }
+
+        // Drop the registers the passes above left unused
+        let mut compact_registers_pass = MirCompactRegistersPass::new();
+        compact_registers_pass.run(&mut mir);
+        passes.diagnostics.merge(compact_registers_pass.diagnostics());
+        record("compact-registers", &mir);
+        passes.diagnostics.info(mir.memory_usage().to_string());
+
+        // SSA construction must preserve types and produce well-formed phis
+        let mut verifier = MirVerifierPass::new();
+        verifier.verify(&mut mir);
+        passes.diagnostics.merge(verifier.diagnostics());
+        if passes.stopped() {
+            return passes.fail();
+        }
+
+        if let Some(unoptimized) = &unoptimized {
+            let divergences = compare_runs(unoptimized, &mir, !self.fast_math);
+            for divergence in &divergences {
+                passes.diagnostics.error(format!(
+                    "Optimization changed what '{}' does: before it {}, after it {}",
+                    divergence.name, divergence.before, divergence.after
+                ));
+            }
+            if passes.stopped() {
+                return passes.fail();
+            }
+        }
+
+        // Nothing reachable from main calls these, so there is no point generating code for them
+        let mut dead_function_pass = MirDeadFunctionPass::new();
+        dead_function_pass.run(&mut mir);
+        passes.diagnostics.merge(dead_function_pass.diagnostics());
+        record("dead-functions", &mir);
+
+        report_recursion(&CallGraph::new(&mir), &mut passes.diagnostics);
+
+        passes.finish(mir)
+    }
+
+    /// Lowers a checked program to MIR in SSA form, with the coverage
+    /// counters and runtime checks asked for. `None` when the pipeline
+    /// stopped on the way.
+    fn lower_to_ssa(
+        &self,
+        program: &mut Program,
+        passes: &mut PassManager,
+        record: &mut dyn FnMut(&str, &MirProgram),
+    ) -> Option<MirProgram> {
+        // Lower HIR to MIR
+        let mut lowering_pass = LoweringPass::new()
+            .checked_division(self.division_by_zero == DivisionByZero::Trap);
+        let mut mir = lowering_pass.lower(program);
+        passes.diagnostics.merge(lowering_pass.diagnostics());
+        if passes.stopped() {
+            return None;
+        }
+        record("lowering", &mir);
+
+        // Check the lowered MIR before transforming it
+        let mut verifier = MirVerifierPass::new();
+        verifier.verify(&mut mir);
+        passes.diagnostics.merge(verifier.diagnostics());
+        if passes.stopped() {
+            return None;
+        }
+
+        // First, so only blocks lowered from source are counted
+        if self.coverage {
+            let mut coverage_pass = MirCoveragePass::new();
+            coverage_pass.run(&mut mir);
+            passes.diagnostics.merge(coverage_pass.diagnostics());
+            record("coverage", &mir);
+        }
+
+        // Before SSA construction, so the flags of the checks need no phis
+        if self.runtime_checks {
+            let mut checks_pass = MirChecksPass::new();
+            checks_pass.run(&mut mir);
+            passes.diagnostics.merge(checks_pass.diagnostics());
+            record("checks", &mir);
+        }
+
+        // Convert MIR to SSA
+        let mut ssa_pass = MirSSAPass::new();
+        ssa_pass.convert(&mut mir);
+        passes.diagnostics.merge(ssa_pass.diagnostics());
+        if passes.stopped() {
+            return None;
+        }
+        record("ssa", &mir);
+        Some(mir)
+    }
+
+    /// The program as it runs without any optimization, HIR passes
+    /// included, for `--verify-opts` to compare the optimized one against
+    fn unoptimized(&self) -> Option<MirProgram> {
+        let CompileResult {
+            output,
+            diagnostics,
+        } = self.typecheck();
+        let mut program = output?;
+        let mut passes = PassManager::new(self.stop_policy, diagnostics);
+        self.lower_to_ssa(&mut program, &mut passes, &mut |_, _| {})
+    }
+
+    /// Runs the full pipeline and takes the MIR back out of SSA form,
+    /// leaving plain copies in place of phi nodes for code generation
+    pub fn destruct_ssa(&self) -> CompileResult<MirProgram> {
+        let CompileResult {
+            output,
+            diagnostics,
+        } = self.lower();
+        let Some(mut mir) = output else {
+            return CompileResult::failed(diagnostics);
+        };
+        let mut passes = PassManager::new(self.stop_policy, diagnostics);
+
+        let mut out_of_ssa_pass = MirOutOfSSAPass::new();
+        out_of_ssa_pass.destruct(&mut mir);
+        passes.diagnostics.merge(out_of_ssa_pass.diagnostics());
+
+        let mut verifier = MirVerifierPass::new().forbid_phis();
+        verifier.verify(&mut mir);
+        passes.diagnostics.merge(verifier.diagnostics());
+        if passes.stopped() {
+            return passes.fail();
+        }
+
+        passes.finish(mir)
+    }
+
+    /// Writes the typechecked program as a Rust module, see
+    /// [`crate::codegen::rust`]
+    pub fn rust(&self) -> CompileResult<String> {
+        let CompileResult {
+            output,
+            mut diagnostics,
+        } = self.check();
+        let Some(program) = output else {
+            return CompileResult::failed(diagnostics);
+        };
+        let mut emitter = RustEmitter::new();
+        emitter.run(&program);
+        diagnostics.merge(emitter.diagnostics());
+        if diagnostics.has_errors() {
+            return CompileResult::failed(diagnostics);
+        }
+        CompileResult {
+            output: Some(emitter.into_output()),
+            diagnostics,
+        }
+    }
+
+    /// Compiles the program to assembly for the target
+    pub fn assembly(&self) -> CompileResult<String> {
+        let format = self.target_triple.object_format();
+        self.machine_code().map(|machine| machine.to_assembly(format))
+    }
+
+    /// Compiles the program to a relocatable object file, assembled by the
+    /// C compiler driver
+    pub fn object(&self) -> CompileResult<Vec<u8>> {
+        let object = TempFile::new("o");
+        let CompileResult {
+            output,
+            mut diagnostics,
+        } = self.machine_code();
+        let Some(machine) = output else {
+            return CompileResult::failed(diagnostics);
+        };
+        if !self.assemble(&machine, object.path(), &mut diagnostics) {
+            return CompileResult::failed(diagnostics);
+        }
+        match fs::read(object.path()) {
+            Ok(bytes) => CompileResult {
+                output: Some(bytes),
+                diagnostics,
+            },
+            Err(e) => {
+                diagnostics.error(format!("Failed to read the object file: {}", e));
+                CompileResult::failed(diagnostics)
+            }
+        }
+    }
+
+    /// Compiles the program to an executable that runs its `main`, linked
+    /// by the C compiler driver
+    pub fn build_executable(&self, output: &Path) -> CompileResult<()> {
+        let CompileResult {
+            output: machine,
+            mut diagnostics,
+        } = self.machine_code();
+        let Some(machine) = machine else {
+            return CompileResult::failed(diagnostics);
+        };
+        let entry = self.target_triple.object_format().c_symbol("main");
+        if !machine.functions.iter().any(|function| function.symbol == entry) {
+            diagnostics.error("No 'main' function without parameters to start the program from".to_string());
+            return CompileResult::failed(diagnostics);
+        }
+
+        let object = TempFile::new("o");
+        if !self.assemble(&machine, object.path(), &mut diagnostics) {
+            return CompileResult::failed(diagnostics);
+        }
+        if let Err(e) = Toolchain::for_target(&self.target_triple).link(&[object.path()], output) {
+            diagnostics.error(format!("Failed to link '{}': {}", output.display(), e));
+            return CompileResult::failed(diagnostics);
+        }
+        CompileResult {
+            output: Some(()),
+            diagnostics,
+        }
+    }
+
+    /// Runs the full pipeline and selects machine instructions for the
+    /// program out of SSA form, with the backend of the target
+    fn machine_code(&self) -> CompileResult<MachineProgram> {
+        let triple = &self.target_triple;
+        // The runtime calls into the C library, so the system needs one
+        let convention = CallingConvention::for_target(triple)
+            .filter(|_| *triple.arch() == Arch::X86_64)
+            .filter(|_| matches!(triple.os(), Os::Linux | Os::FreeBsd | Os::MacOs));
+        let Some(convention) = convention else {
+            let mut diagnostics = DiagnosticCollector::new();
+            diagnostics.error(format!(
+                "No code generator for target '{}', only x86-64 Linux, FreeBSD and macOS are supported",
+                triple
+            ));
+            return CompileResult::failed(diagnostics);
+        };
+
+        let CompileResult {
+            output,
+            diagnostics,
+        } = self.destruct_ssa();
+        let Some(mut mir) = output else {
+            return CompileResult::failed(diagnostics);
+        };
+        let mut passes = PassManager::new(self.stop_policy, diagnostics);
+
+        // x86-64 loads float constants from memory
+        let mut constant_pool_pass = MirConstantPoolPass::new();
+        constant_pool_pass.run(&mut mir);
+        passes.diagnostics.merge(constant_pool_pass.diagnostics());
+
+        let mut selector = X86_64Selector::new(triple.object_format(), convention);
+        let mut machine = selector.select(&mir);
+        passes.diagnostics.merge(selector.diagnostics());
+
+        if self.opt_level != OptLevel::O0 {
+            // Before the peephole pass, which removes the jumps to the next block
+            let mut layout_pass = X86_64LayoutPass::new();
+            layout_pass.run(&mut machine);
+            passes.diagnostics.merge(layout_pass.diagnostics());
+
+            let mut peephole_pass = X86_64PeepholePass::new();
+            peephole_pass.run(&mut machine);
+            passes.diagnostics.merge(peephole_pass.diagnostics());
+
+            if self.scheduling {
+                let mut scheduling_pass = X86_64SchedulingPass::new();
+                scheduling_pass.run(&mut machine);
+                passes.diagnostics.merge(scheduling_pass.diagnostics());
+            }
+        }
+        passes.finish(machine)
+    }
+
+    /// Assembles machine code into an object file for the target, reporting
+    /// why it failed
+    fn assemble(&self, machine: &MachineProgram, object: &Path, diagnostics: &mut DiagnosticCollector) -> bool {
+        let triple = &self.target_triple;
+        let assembly = machine.to_assembly(triple.object_format());
+        match Toolchain::for_target(triple).assemble(&assembly, object) {
+            Ok(()) => true,
+            Err(e) => {
+                diagnostics.error(format!("Failed to assemble the program: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Runs the pipeline up to the given target and prints the result
+    pub fn emit(&self, target: Target) -> CompileResult<Artifact> {
+        self.emit_to(target, &mut io::stdout())
+    }
+
+    /// Like [`Compiler::emit`], but writes the printed result to `out`.
+    /// Failing to write is reported as an error.
+    pub fn emit_to(&self, target: Target, out: &mut dyn Write) -> CompileResult<Artifact> {
+        let mut result = match target {
+            Target::Tokens | Target::TokensJson => self.lex().map(Artifact::Tokens),
+            Target::Ast => self.check().map(Artifact::Ast),
+            Target::Spans => self.parse().map(Artifact::Ast),
+            Target::Mir => self.lower().map(Artifact::Mir),
+            Target::Asm => self.assembly().map(Artifact::Assembly),
+            Target::Object => self.object().map(Artifact::Object),
+            Target::Rust => self.rust().map(Artifact::Rust),
+        };
+        if let Some(artifact) = &mut result.output {
+            let bytes = self.render(target, artifact);
+            if let Err(e) = out.write_all(&bytes).and_then(|()| out.flush()) {
+                result.diagnostics.error(format!("Failed to write the output: {}", e));
+            }
+        }
+        result
+    }
+
+    /// The bytes [`Compiler::emit`] prints for an artifact
+    fn render(&self, target: Target, artifact: &mut Artifact) -> Vec<u8> {
+        let painter = Painter::new(self.color);
+        match artifact {
+            Artifact::Tokens(lexed) => {
+                let mut text = String::new();
+                for source in lexed.iter() {
+                    if target == Target::TokensJson {
+                        for token in &source.tokens {
+                            text.push_str(&format!(
+                                "{{\"source\":{},\"token\":{}}}\n",
+                                json::string(&source.source),
+                                token.to_json()
+                            ));
+                        }
+                    } else {
+                        text.push_str(&format!(
+                            "{}\n",
+                            painter.heading(&format!("=== Tokens: {} ===", source.source))
+                        ));
+                        for token in &source.tokens {
+                            text.push_str(&format!("{}\n", token));
+                        }
+                    }
+                }
+                text.into_bytes()
+            }
+            Artifact::Ast(program) => {
+                let mut printer = PrintPass::new().with_painter(painter);
+                if target == Target::Spans {
+                    printer = printer.with_sources(
+                        self.sources.iter().map(|source| (source.name.as_str(), source.text.as_str())),
+                    );
+                }
+                printer.visit_program(program);
+                printer.into_output().into_bytes()
+            }
+            Artifact::Mir(mir) => {
+                let mut printer = MirPrintingPass::new()
+                    .with_spans(self.debug_info)
+                    .with_painter(painter);
+                printer.visit_program(mir);
+                printer.into_output().into_bytes()
+            }
+            Artifact::Assembly(assembly) => assembly.clone().into_bytes(),
+            Artifact::Object(bytes) => bytes.clone(),
+            Artifact::Rust(source) => source.clone().into_bytes(),
+        }
+    }
+}
+
+/// Lexes a single source, reporting every lexing error as a diagnostic.
+/// Text that is not a token is kept as an unknown token. The tokens are
+/// taken from `cached` instead when the last update of the source left
+/// them there.
+fn lex_source(source: &Source, cached: &Option<Vec<Token>>, diagnostics: &mut DiagnosticCollector) -> Vec<Token> {
+    if let Some(tokens) = cached {
+        return tokens.clone();
+    }
+    let (tokens, errors) = LexerContext::lex_recovering(&source.text);
+    for e in errors {
+        diagnostics.report_error(
+            Diagnostic::new(format!(
+                "{}: Lexing error at {}: {}",
+                source.name, e.span, e.message
+            ))
+            .with_location(Some(source.name.clone()), e.span),
+        );
+    }
+    tokens
+}
+
+/// The lines that differ between two versions of a text, as an [`Edit`]
+/// turning `old` into `new`. At least one line of each is included, so
+/// insertions and deletions are edits of the line next to them.
+fn changed_lines(old: &str, new: &str) -> Edit {
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    let shortest = old_lines.len().min(new_lines.len());
+
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take(shortest - 1)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines
+        .iter()
+        .rev()
+        .zip(new_lines.iter().rev())
+        .take(shortest - 1 - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    Edit {
+        start_row: prefix,
+        old_end_row: old_lines.len() - 1 - suffix,
+        new_end_row: new_lines.len() - 1 - suffix,
+    }
+}
+
+/// Runs passes and collects their diagnostics. Once problems the policy
+/// stops at are reported, passes that transform the program are skipped,
+/// while analyses and checks still run to report what else is wrong.
+struct PassManager {
+    policy: StopPolicy,
+    diagnostics: DiagnosticCollector,
+}
+
+impl PassManager {
+    fn new(policy: StopPolicy, diagnostics: DiagnosticCollector) -> Self {
+        PassManager { policy, diagnostics }
+    }
+
+    /// Returns true if problems were reported that the policy stops at
+    fn stopped(&self) -> bool {
+        self.diagnostics.has_errors()
+            || (self.policy == StopPolicy::OnWarning && self.diagnostics.has_warnings())
+    }
+
+    /// Runs a read-only pass
+    fn analyze<V: VisitorRef>(&mut self, pass: &mut V, program: &Program) {
+        pass.visit_program(program);
+        self.diagnostics.merge(pass.diagnostics());
+    }
+
+    /// Runs a pass that finds problems and annotates the program with what
+    /// it learns, even if earlier passes found problems already
+    fn check<V: Visitor>(&mut self, pass: &mut V, program: &mut Program) {
+        pass.visit_program(program);
+        self.diagnostics.merge(pass.diagnostics());
+    }
+
+    /// Runs a pass that transforms the program, unless the pipeline stopped
+    fn transform(&mut self, pass: &mut dyn HirPass, program: &mut Program) {
+        if self.stopped() {
+            return;
+        }
+        pass.run(program);
+        self.diagnostics.merge(pass.diagnostics());
+    }
+
+    fn fail<T>(self) -> CompileResult<T> {
+        CompileResult::failed(self.diagnostics)
+    }
+
+    /// The output, unless the pipeline stopped on the way to it
+    fn finish<T>(self, output: T) -> CompileResult<T> {
+        if self.stopped() {
+            return self.fail();
+        }
+        CompileResult {
+            output: Some(output),
+            diagnostics: self.diagnostics,
+        }
+    }
+}
+
+/// Rejects functions declared `@pure` whose bodies have effects
+fn report_purity_violations(purity: &PurityAnalysis, diagnostics: &mut DiagnosticCollector) {
+    for violation in purity.violations() {
+        diagnostics.error(format!(
+            "Function '{}' at {} is marked @pure but {}",
+            violation.function, violation.span, violation.impurity
+        ));
+    }
+}
+
+/// Makes the collector drop the warnings of every lint named by an
+/// `@allow(...)` attribute inside the function or statement it is on
+fn allow_lints(program: &Program, diagnostics: &mut DiagnosticCollector) {
+    let function_attrs = program.functions.iter().flat_map(|function| {
+        function
+            .attrs
+            .iter()
+            .map(move |attr| (attr, function.source.as_str(), function.span))
+    });
+    let statement_attrs = program
+        .statement_attrs
+        .iter()
+        .map(|attr| (&attr.attribute, attr.source.as_str(), attr.span));
+
+    for (attr, source, span) in function_attrs.chain(statement_attrs) {
+        if attr.name != "allow" {
+            continue;
+        }
+        for lint in allowed_lints(attr, diagnostics) {
+            diagnostics.allow(lint, source, span);
+        }
+    }
+}
+
+/// The lints named by an `@allow(...)` attribute. Names are words joined
+/// by '-', so they parse as subtractions of variables.
+fn allowed_lints(attr: &Attribute, diagnostics: &mut DiagnosticCollector) -> Vec<Lint> {
+    fn lint_name(expression: &Expression) -> Option<String> {
+        match expression {
+            Expression::Variable { name, .. } => Some(name.clone()),
+            Expression::BinaryOp { left, op, right, .. } if op.lexeme == "-" => {
+                Some(format!("{}-{}", lint_name(left)?, lint_name(right)?))
+            }
+            _ => None,
+        }
+    }
+
+    if attr.args.is_empty() {
+        diagnostics.error(format!(
+            "Attribute '@allow' at {} needs at least one lint name",
+            attr.span
+        ));
+    }
+    let mut lints = Vec::new();
+    for arg in &attr.args {
+        let Some(name) = lint_name(arg) else {
+            diagnostics.error(format!(
+                "Expected a lint name in '@allow' at {}",
+                attr.span
+            ));
+            continue;
+        };
+        match Lint::from_name(&name) {
+            Some(lint) => lints.push(lint),
+            None => {
+                let names = Lint::ALL.iter().map(|lint| lint.name());
+                let hint = match closest_match(&name, names) {
+                    Some(closest) => format!(", did you mean '{}'?", closest),
+                    None => String::new(),
+                };
+                diagnostics.error(format!(
+                    "Unknown lint '{}' in '@allow' at {}{}",
+                    name, attr.span, hint
+                ));
+            }
+        }
+    }
+    lints
+}
+
+/// Warns about unused declarations, suggesting their removal. Removing one
+/// is only safe when its initializer has no effect.
+fn report_unused_variables(unused: &UnusedVariables, diagnostics: &mut DiagnosticCollector) {
+    for variable in unused.unused() {
+        let mut warning = Diagnostic::new(format!(
+            "Variable '{}' at {} is never used",
+            variable.name, variable.span
+        ))
+        .with_location(Some(variable.source.clone()), variable.span)
+        .with_lint(Lint::UnusedVariable);
+        let applicability = if variable.removable {
+            Applicability::MachineApplicable
+        } else {
+            warning = warning.with_note(
+                "its initializer has effects, which removing the declaration also removes".to_string(),
+            );
+            Applicability::MaybeIncorrect
+        };
+        diagnostics.report_warning(warning.with_suggestion(Suggestion {
+            message: format!("remove the declaration of '{}'", variable.name),
+            edits: vec![TextEdit {
+                span: variable.span,
+                replacement: String::new(),
+            }],
+            applicability,
+        }));
+    }
+}
+
+/// Warns about names that look like other names but are not the same
+fn report_confusable_identifiers(confusables: &ConfusableIdentifiers, diagnostics: &mut DiagnosticCollector) {
+    for confusable in confusables.confusables() {
+        diagnostics.report_warning(
+            Diagnostic::new(format!(
+                "Identifier '{}' at {} looks like '{}' at {}",
+                confusable.name, confusable.span, confusable.original, confusable.original_span
+            ))
+            .with_location(Some(confusable.source.clone()), confusable.span)
+            .with_note(format!(
+                "they are different names, because '{}' is written with other characters",
+                confusable.name
+            ))
+            .with_lint(Lint::ConfusableIdentifier),
+        );
+    }
+}
+
+/// Notes every recursive cycle in the call graph, since interprocedural
+/// passes such as inlining have to stop at them
+fn report_recursion(call_graph: &CallGraph, diagnostics: &mut DiagnosticCollector) {
+    for component in call_graph.recursive_sccs() {
+        let names: Vec<String> = component.iter().map(|name| format!("'{}'", name)).collect();
+        if names.len() == 1 {
+            diagnostics.info(format!("Function {} is recursive", names[0]));
+        } else {
+            diagnostics.info(format!(
+                "Functions {} are mutually recursive",
+                names.join(", ")
+            ));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Line coverage: how often each line of the source ran.
+//!
+//! With `--coverage`, [`crate::mir::passes::coverage::MirCoveragePass`]
+//! gives every block lowered from source a counter, a global the block adds
+//! one to whenever it runs. When the program is done, `iris test` after the
+//! last test and an executable when `main` returns or traps, the counters
+//! are written to [`COVERAGE_FILE`] as little-endian 64-bit integers, in the
+//! order of [`MirProgram::coverage`].
+//!
+//! The file holds nothing but the counts. `iris cov report` compiles the
+//! sources again to learn which lines each counter stands for, and prints
+//! every line with how often it ran, the most any block on it ran.
+//!
+//! [`MirProgram::coverage`]: crate::mir::MirProgram::coverage
+
+use crate::compiler::Source;
+use crate::mir::GlobalId;
+
+/// The file coverage counts are written to, in the working directory
+pub const COVERAGE_FILE: &str = "iris.cov";
+
+/// The counter of one block
+#[derive(Debug, Clone)]
+pub struct CoverageCounter {
+    pub global: GlobalId,
+    /// Name of the source the block was lowered from
+    pub source: String,
+    /// Rows the instructions of the block start on, zero-based
+    pub rows: Vec<usize>,
+}
+
+/// The contents of a coverage file with the given counts
+pub fn encode_counts(counts: &[u64]) -> Vec<u8> {
+    counts.iter().flat_map(|count| count.to_le_bytes()).collect()
+}
+
+/// The counts in the contents of a coverage file
+pub fn decode_counts(bytes: &[u8]) -> Result<Vec<u64>, String> {
+    if !bytes.len().is_multiple_of(8) {
+        return Err(format!(
+            "Coverage file has {} byte(s), which is not a whole number of counts",
+            bytes.len()
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes")))
+        .collect())
+}
+
+/// How often each line of a source ran, or `None` for lines without code
+pub fn line_counts(counters: &[CoverageCounter], counts: &[u64], source: &Source) -> Vec<Option<u64>> {
+    let mut lines = vec![None; source.text.lines().count()];
+    for (counter, &count) in counters.iter().zip(counts) {
+        if counter.source != source.name {
+            continue;
+        }
+        for &row in &counter.rows {
+            if let Some(line) = lines.get_mut(row) {
+                *line = Some(line.unwrap_or(0).max(count));
+            }
+        }
+    }
+    lines
+}
+
+/// A source annotated with how often each line ran, in the format of
+/// `gcov`: the count, `-` for lines without code or `#####` for lines that
+/// never ran, then the line number and the line. Ends with how many of the
+/// lines with code ran.
+pub fn render_report(counters: &[CoverageCounter], counts: &[u64], source: &Source) -> String {
+    let lines = line_counts(counters, counts, source);
+    let mut report = format!("        -:    0:Source:{}\n", source.name);
+    for (row, (text, count)) in source.text.lines().zip(&lines).enumerate() {
+        let count = match count {
+            None => "-".to_string(),
+            Some(0) => "#####".to_string(),
+            Some(count) => count.to_string(),
+        };
+        report.push_str(&format!("{:>9}:{:>5}:{}\n", count, row + 1, text));
+    }
+    let executable = lines.iter().flatten().count();
+    let executed = lines.iter().flatten().filter(|&&count| count > 0).count();
+    match executable {
+        0 => report.push_str("No executable lines\n"),
+        _ => report.push_str(&format!(
+            "Lines executed: {:.2}% of {}\n",
+            100.0 * executed as f64 / executable as f64,
+            executable
+        )),
+    }
+    report
+}
@@ -1,10 +1,287 @@
+use crate::json;
+use crate::span::Span;
+use std::fmt;
+
+/// An error or warning, with what tools need to point at and fix it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Name of the source the problem is in, if known
+    pub source: Option<String>,
+    /// Where the problem is, if known
+    pub span: Option<Span>,
+    /// Further explanation of the problem
+    pub notes: Vec<String>,
+    /// Changes to the source that would resolve the problem
+    pub suggestions: Vec<Suggestion>,
+    /// The lint that reported the problem, for warnings that can be allowed
+    pub lint: Option<Lint>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String) -> Self {
+        Diagnostic {
+            message,
+            source: None,
+            span: None,
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+            lint: None,
+        }
+    }
+
+    pub fn with_location(mut self, source: Option<String>, span: Span) -> Self {
+        self.source = source;
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestions.push(suggestion);
+        self
+    }
+
+    pub fn with_lint(mut self, lint: Lint) -> Self {
+        self.lint = Some(lint);
+        self
+    }
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic as a single-line JSON object. The code is the
+    /// name of its lint, and rows and columns start at 0 as in spans.
+    pub fn to_json(&self, severity: Severity) -> String {
+        let notes: Vec<String> = self.notes.iter().map(|note| json::string(note)).collect();
+        let suggestions: Vec<String> = self.suggestions.iter().map(Suggestion::to_json).collect();
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"span\":{},\"notes\":[{}],\"suggestions\":[{}]}}",
+            json::string(severity.name()),
+            self.lint.map_or("null".to_string(), |lint| json::string(lint.name())),
+            json::string(&self.message),
+            self.source.as_deref().map_or("null".to_string(), json::string),
+            self.span.as_ref().map_or("null".to_string(), span_json),
+            notes.join(","),
+            suggestions.join(",")
+        )
+    }
+}
+
+fn span_json(span: &Span) -> String {
+    format!(
+        "{{\"start_row\":{},\"start_column\":{},\"end_row\":{},\"end_column\":{}}}",
+        span.start_row, span.start_column, span.end_row, span.end_column
+    )
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A warning that code can opt out of with `@allow(name)` on a function
+/// or statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    UnusedVariable,
+    UnreachableArm,
+    DivisionByZero,
+    DeadFunction,
+    UnreachableCode,
+    ConfusableIdentifier,
+    IntegerOverflow,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedVariable,
+        Lint::UnreachableArm,
+        Lint::DivisionByZero,
+        Lint::DeadFunction,
+        Lint::UnreachableCode,
+        Lint::ConfusableIdentifier,
+        Lint::IntegerOverflow,
+    ];
+
+    /// The name the lint is allowed by
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnreachableArm => "unreachable-arm",
+            Lint::DivisionByZero => "division-by-zero",
+            Lint::DeadFunction => "dead-function",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ConfusableIdentifier => "confusable-identifier",
+            Lint::IntegerOverflow => "integer-overflow",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A part of a source in which a lint is allowed
+#[derive(Debug, Clone)]
+pub struct Allowed {
+    pub lint: Lint,
+    pub source: String,
+    pub span: Span,
+}
+
+impl Allowed {
+    /// Returns true if the diagnostic is of this lint and inside the span
+    pub fn covers(&self, diagnostic: &Diagnostic) -> bool {
+        let (Some(source), Some(span)) = (&diagnostic.source, &diagnostic.span) else {
+            return false;
+        };
+        diagnostic.lint == Some(self.lint)
+            && *source == self.source
+            && (self.span.start_row, self.span.start_column) <= (span.start_row, span.start_column)
+            && (span.end_row, span.end_column) <= (self.span.end_row, self.span.end_column)
+    }
+}
+
+/// How safely a suggestion can be applied without a person looking at it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    /// The suggestion is what the code means, and can be applied by tools
+    MachineApplicable,
+    /// The suggestion compiles, but may not be what the author intended
+    MaybeIncorrect,
+}
+
+impl Applicability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Applicability::MachineApplicable => "machine-applicable",
+            Applicability::MaybeIncorrect => "maybe-incorrect",
+        }
+    }
+}
+
+/// A fix for a diagnostic, made of edits to the source it was reported in
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub edits: Vec<TextEdit>,
+    pub applicability: Applicability,
+}
+
+impl Suggestion {
+    pub fn to_json(&self) -> String {
+        let edits: Vec<String> = self
+            .edits
+            .iter()
+            .map(|edit| {
+                format!(
+                    "{{\"span\":{},\"replacement\":{}}}",
+                    span_json(&edit.span),
+                    json::string(&edit.replacement)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"message\":{},\"applicability\":{},\"edits\":[{}]}}",
+            json::string(&self.message),
+            json::string(self.applicability.name()),
+            edits.join(",")
+        )
+    }
+}
+
+/// Replaces the text in `span` with `replacement`; an empty span inserts
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    /// Inserts `text` at the given row and column
+    pub fn insert(row: usize, column: usize, text: &str) -> Self {
+        TextEdit {
+            span: Span::new(row, column, row, column),
+            replacement: text.to_string(),
+        }
+    }
+}
+
+/// The candidate closest to `name`, if one is close enough to likely be
+/// what was meant. Ties go to the alphabetically first candidate.
+pub fn closest_match<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    // Allow a typo for every three characters, where swapping two
+    // neighbouring characters counts as two. A name must keep at least one
+    // character, or any short name would match any other.
+    let length = name.chars().count();
+    let limit = length.div_ceil(3).min(length.saturating_sub(1));
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings: how many characters have
+/// to be inserted, removed or replaced to turn one into the other
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // previous[j] is the distance between the prefix of `a` seen so far,
+    // minus its last character, and the first j characters of `b`
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 /// Collects diagnostic messages during compilation
 #[derive(Default, Debug)]
 pub struct DiagnosticCollector {
-    pub errors: Vec<String>,
-    pub warnings: Vec<String>,
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
     pub info: Vec<String>,
     pub debug: Vec<String>,
+    /// Where lints are allowed; warnings reported there are dropped
+    pub allowed: Vec<Allowed>,
+    /// How many errors are kept, if limited
+    pub error_limit: Option<usize>,
+    /// Errors reported past the limit, which were counted but not kept
+    pub suppressed_errors: usize,
 }
 
 impl DiagnosticCollector {
@@ -12,8 +289,16 @@ impl DiagnosticCollector {
         Self::default()
     }
 
+    /// A collector that keeps at most `limit` errors
+    pub fn with_error_limit(limit: Option<usize>) -> Self {
+        DiagnosticCollector {
+            error_limit: limit,
+            ..Self::default()
+        }
+    }
+
     pub fn has_errors(&self) -> bool {
-        !self.errors.is_empty()
+        !self.errors.is_empty() || self.suppressed_errors > 0
     }
 
     pub fn has_warnings(&self) -> bool {
@@ -21,11 +306,45 @@ impl DiagnosticCollector {
     }
 
     pub fn error(&mut self, msg: String) {
-        self.errors.push(msg);
+        self.report_error(Diagnostic::new(msg));
     }
 
     pub fn warn(&mut self, msg: String) {
-        self.warnings.push(msg);
+        self.warnings.push(Diagnostic::new(msg));
+    }
+
+    /// Records an error that carries a location or suggestions. An error
+    /// already reported at the same location is dropped, and one past the
+    /// limit is only counted.
+    pub fn report_error(&mut self, diagnostic: Diagnostic) {
+        if self.errors.iter().any(|error| is_duplicate(error, &diagnostic)) {
+            return;
+        }
+        if self.error_limit.is_some_and(|limit| self.errors.len() >= limit) {
+            self.suppressed_errors += 1;
+            return;
+        }
+        self.errors.push(diagnostic);
+    }
+
+    /// Records a warning that carries a location or suggestions, unless
+    /// its lint is allowed where it is or it was reported already
+    pub fn report_warning(&mut self, diagnostic: Diagnostic) {
+        if self.allowed.iter().any(|allowed| allowed.covers(&diagnostic))
+            || self.warnings.iter().any(|warning| is_duplicate(warning, &diagnostic))
+        {
+            return;
+        }
+        self.warnings.push(diagnostic);
+    }
+
+    /// Drops the warnings of `lint` reported inside `span` from now on
+    pub fn allow(&mut self, lint: Lint, source: &str, span: Span) {
+        self.allowed.push(Allowed {
+            lint,
+            source: source.to_string(),
+            span,
+        });
     }
 
     pub fn info(&mut self, msg: String) {
@@ -36,10 +355,32 @@ impl DiagnosticCollector {
         self.debug.push(msg);
     }
 
+    /// Appends all messages from another collector to this one
+    pub fn merge(&mut self, other: &DiagnosticCollector) {
+        for error in &other.errors {
+            self.report_error(error.clone());
+        }
+        self.suppressed_errors += other.suppressed_errors;
+        self.allowed.extend(other.allowed.iter().cloned());
+        for warning in &other.warnings {
+            self.report_warning(warning.clone());
+        }
+        self.info.extend(other.info.iter().cloned());
+        self.debug.extend(other.debug.iter().cloned());
+    }
+
     pub fn clear(&mut self) {
         self.errors.clear();
         self.warnings.clear();
         self.info.clear();
         self.debug.clear();
+        self.allowed.clear();
+        self.suppressed_errors = 0;
     }
 }
+
+/// Returns true if two diagnostics say the same thing about the same place.
+/// Without a location the same message may be about different code.
+fn is_duplicate(a: &Diagnostic, b: &Diagnostic) -> bool {
+    a.span.is_some() && a.message == b.message && a.source == b.source && a.span == b.span
+}
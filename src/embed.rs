@@ -0,0 +1,429 @@
+//! Compiling Iris source and calling its functions from Rust.
+//!
+//! ```ignore
+//! let module = iris::embed::compile("fn square(x: f64) -> f64 { return x * x }")?;
+//! let square = module.get_fn::<fn(f64) -> f64>("square")?;
+//! assert_eq!(square.call(3.0)?, 9.0);
+//! ```
+//!
+//! Functions run in the MIR interpreter, so nothing but this crate is
+//! needed. The Rust signature asked for is checked against the Iris one
+//! when the function is retrieved, after which a call can only fail by
+//! trapping. `f64` stands for the Iris types `f8`, `f16` and `f64`, which
+//! are all computed in double precision, `f32` for `f32` and `bool` for
+//! `bool`. Globals keep their values from one call to the next.
+//!
+//! Extern functions are defined by Rust closures registered with an
+//! [`Engine`] before compiling, which are checked against the extern
+//! declarations and run whenever the program calls them. Every extern
+//! function the program declares must have one:
+//!
+//! ```ignore
+//! let mut engine = Engine::new();
+//! engine.register("sqrt", |x: f64| x.sqrt());
+//! let module = engine.compile("extern fn sqrt(x: f64) -> f64\nfn norm(x: f64, y: f64) -> f64 { return sqrt(x * x + y * y) }")?;
+//! ```
+//!
+//! A program with a `main` only keeps the functions `main` calls and those
+//! marked `@export`, see [`crate::mir::passes::dead_functions`].
+
+use crate::compiler::Compiler;
+use crate::diagnostics::{closest_match, Diagnostic};
+use crate::intern::Symbol;
+use crate::mir::interpreter::{HostFunction, HostFunctions, Interpreter, Trap};
+use crate::mir::{FuncId, GlobalId, Linkage, MirProgram, MirType};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Compiles a single source at the default optimization level, without
+/// host functions
+pub fn compile(source: &str) -> Result<Module, EmbedError> {
+    Engine::new().compile(source)
+}
+
+/// Compiles programs whose extern functions are defined by the host
+/// functions registered with it
+pub struct Engine {
+    host: HostFunctions,
+    /// The signature each host function was registered with, to check
+    /// the extern declarations against
+    signatures: HashMap<FuncId, RegisteredSignature>,
+}
+
+struct RegisteredSignature {
+    matches: fn(&[MirType], MirType) -> bool,
+    name: &'static str,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            host: HashMap::new(),
+            signatures: HashMap::new(),
+        }
+    }
+
+    /// Defines the extern function `name` by a closure, replacing what was
+    /// registered for it before. Closures can take up to six arguments of
+    /// the types [`IrisType`] is implemented for.
+    pub fn register<S: Signature, F: HostClosure<S>>(&mut self, name: &str, function: F) -> &mut Self {
+        let name = Symbol::intern(name);
+        self.host.insert(name, function.into_host());
+        self.signatures.insert(
+            name,
+            RegisteredSignature {
+                matches: S::matches,
+                name: std::any::type_name::<S>(),
+            },
+        );
+        self
+    }
+
+    /// Compiles a single source at the default optimization level
+    pub fn compile(&self, source: &str) -> Result<Module, EmbedError> {
+        let mut compiler = Compiler::new();
+        compiler.add_source("embedded.iris", source);
+        self.load(&compiler)
+    }
+
+    /// Compiles the sources of the compiler, with its settings, and checks
+    /// that each extern function the program declares was registered with
+    /// its signature
+    ///
+    /// ```
+    /// use iris::embed::{EmbedError, Engine};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register("sqr", |x: f64| x * x);
+    /// let result = engine.compile("extern fn sqrt(x: f64) -> f64\nfn root(x: f64) -> f64 { return sqrt(x) }");
+    /// let Err(EmbedError::UnregisteredExtern { name, suggestion }) = result else {
+    ///     panic!("an extern without a host function was loaded");
+    /// };
+    /// assert_eq!((name.as_str(), suggestion.as_deref()), ("sqrt", Some("sqr")));
+    /// ```
+    pub fn load(&self, compiler: &Compiler) -> Result<Module, EmbedError> {
+        let mut module = Module::from_compiler(compiler)?;
+        for declared in module.program.symbols.iter().filter(|f| f.linkage == Linkage::External) {
+            let Some(registered) = self.signatures.get(&declared.name) else {
+                let names = self.signatures.keys().map(|name| name.as_str());
+                return Err(EmbedError::UnregisteredExtern {
+                    name: declared.name.to_string(),
+                    suggestion: closest_match(declared.name.as_str(), names).map(str::to_string),
+                });
+            };
+            if !(registered.matches)(&declared.params, declared.return_type) {
+                return Err(EmbedError::Signature {
+                    name: declared.name.to_string(),
+                    expected: registered.name.to_string(),
+                    found: describe(&declared.params, declared.return_type),
+                });
+            }
+            module.host.insert(declared.name, self.host[&declared.name].clone());
+        }
+        Ok(module)
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+/// Why a program could not be compiled or a function not be called
+#[derive(Debug, Clone)]
+pub enum EmbedError {
+    /// The program has errors
+    Compile(Vec<Diagnostic>),
+    UnknownFunction {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// The program declares an extern function no host function was
+    /// registered for
+    UnregisteredExtern {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// The function was asked for with a Rust signature its Iris one does
+    /// not match
+    Signature {
+        name: String,
+        /// The Rust signature asked for
+        expected: String,
+        /// The Iris signature, in MIR types
+        found: String,
+    },
+    Trap(Trap),
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedError::Compile(errors) => {
+                write!(f, "Failed to compile the program")?;
+                for error in errors {
+                    write!(f, "\n{}", error)?;
+                }
+                Ok(())
+            }
+            EmbedError::UnknownFunction { name, suggestion } => {
+                write!(f, "The program has no function '{}'", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean '{}'?", suggestion)?;
+                }
+                Ok(())
+            }
+            EmbedError::UnregisteredExtern { name, suggestion } => {
+                write!(f, "No host function was registered for extern function '{}'", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean to register it as '{}'?", suggestion)?;
+                }
+                Ok(())
+            }
+            EmbedError::Signature { name, expected, found } => write!(
+                f,
+                "Function '{}' is {}, which does not match {}",
+                name, found, expected
+            ),
+            EmbedError::Trap(trap) => write!(f, "{}", trap),
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {}
+
+impl From<Trap> for EmbedError {
+    fn from(trap: Trap) -> Self {
+        EmbedError::Trap(trap)
+    }
+}
+
+/// A compiled program whose functions can be called
+pub struct Module {
+    program: MirProgram,
+    /// Host functions for the extern functions of the program
+    host: HostFunctions,
+    /// Left by the last call, `None` until the first one initializes them
+    globals: RefCell<Option<HashMap<GlobalId, Value>>>,
+}
+
+impl Module {
+    pub fn new(program: MirProgram) -> Self {
+        Module {
+            program,
+            host: HashMap::new(),
+            globals: RefCell::new(None),
+        }
+    }
+
+    /// Compiles the sources of the compiler, with its settings, without
+    /// host functions
+    pub fn from_compiler(compiler: &Compiler) -> Result<Self, EmbedError> {
+        let result = compiler.lower();
+        match result.output {
+            Some(program) if !result.diagnostics.has_errors() => Ok(Module::new(program)),
+            _ => Err(EmbedError::Compile(result.diagnostics.errors)),
+        }
+    }
+
+    pub fn program(&self) -> &MirProgram {
+        &self.program
+    }
+
+    /// The function with the given name, if its signature is `F`, a
+    /// function pointer type such as `fn(f64, bool) -> f32`
+    pub fn get_fn<F: Signature>(&self, name: &str) -> Result<Function<'_, F>, EmbedError> {
+        let Some(function) = self.program.functions.iter().find(|function| function.name.as_str() == name) else {
+            let names = self.program.functions.iter().map(|function| function.name.as_str());
+            return Err(EmbedError::UnknownFunction {
+                name: name.to_string(),
+                suggestion: closest_match(name, names).map(str::to_string),
+            });
+        };
+        let params: Vec<MirType> = function.params.iter().map(|(_, typ)| *typ).collect();
+        if !F::matches(&params, function.return_type) {
+            return Err(EmbedError::Signature {
+                name: name.to_string(),
+                expected: std::any::type_name::<F>().to_string(),
+                found: describe(&params, function.return_type),
+            });
+        }
+        Ok(Function {
+            module: self,
+            name: function.name,
+            signature: PhantomData,
+        })
+    }
+
+    /// Calls a function with arguments that are not checked against its
+    /// signature beyond their number
+    pub fn call(&self, name: FuncId, args: &[Value]) -> Result<Option<Value>, Trap> {
+        let mut interpreter = Interpreter::new(&self.program).with_host_functions(&self.host);
+        if let Some(globals) = self.globals.take() {
+            interpreter = interpreter.with_globals(globals);
+        }
+        let result = interpreter.call(name, args);
+        *self.globals.borrow_mut() = interpreter.into_globals();
+        result
+    }
+
+    /// Gives every global its initial value again
+    pub fn reset(&self) {
+        *self.globals.borrow_mut() = None;
+    }
+
+    /// The current value of a global, once a call has initialized them
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().as_ref()?.get(&Symbol::intern(name)).copied()
+    }
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut host: Vec<&str> = self.host.keys().map(|name| name.as_str()).collect();
+        host.sort();
+        f.debug_struct("Module")
+            .field("program", &self.program)
+            .field("host", &host)
+            .field("globals", &self.globals)
+            .finish()
+    }
+}
+
+/// A function of a [`Module`] whose signature is known to be `F`
+#[derive(Debug, Clone, Copy)]
+pub struct Function<'m, F> {
+    module: &'m Module,
+    name: FuncId,
+    signature: PhantomData<F>,
+}
+
+impl<F> Function<'_, F> {
+    pub fn name(&self) -> FuncId {
+        self.name
+    }
+}
+
+/// An Iris signature the way [`EmbedError::Signature`] shows it
+fn describe(params: &[MirType], return_type: MirType) -> String {
+    let params: Vec<String> = params.iter().map(|typ| format!("{:?}", typ)).collect();
+    format!("fn({}) -> {:?}", params.join(", "), return_type)
+}
+
+/// A Rust type that Iris values of some types are passed as
+pub trait IrisType: Sized {
+    /// Whether values of the MIR type are passed as this type
+    fn accepts(typ: MirType) -> bool;
+
+    fn into_value(self) -> Value;
+
+    /// The value as a function returns it
+    fn into_result(self) -> Option<Value> {
+        Some(self.into_value())
+    }
+
+    /// Takes the result of a function returning one of the accepted types
+    fn from_value(value: Option<Value>) -> Self;
+}
+
+/// Iris types whose values are held by the variant of a Rust type
+macro_rules! iris_type {
+    ($rust:ty, $($typ:ident)|+) => {
+        impl IrisType for $rust {
+            fn accepts(typ: MirType) -> bool {
+                matches!(typ, $(MirType::$typ)|+)
+            }
+
+            fn into_value(self) -> Value {
+                Value::from(self)
+            }
+
+            fn from_value(value: Option<Value>) -> Self {
+                match value.map(<$rust>::try_from) {
+                    Some(Ok(value)) => value,
+                    other => unreachable!("expected a {} result, got {:?}", stringify!($rust), other),
+                }
+            }
+        }
+    };
+}
+
+iris_type!(f64, F8 | F16 | F64);
+iris_type!(f32, F32);
+iris_type!(bool, I1);
+
+/// Functions that return nothing
+impl IrisType for () {
+    fn accepts(typ: MirType) -> bool {
+        typ == MirType::Void
+    }
+
+    fn into_value(self) -> Value {
+        unreachable!("Iris has no values of type void")
+    }
+
+    fn into_result(self) -> Option<Value> {
+        None
+    }
+
+    fn from_value(_: Option<Value>) -> Self {}
+}
+
+/// A function pointer type that Iris functions can be retrieved as
+pub trait Signature {
+    fn matches(params: &[MirType], return_type: MirType) -> bool;
+}
+
+/// A closure that can define an extern function with the signature `S`
+pub trait HostClosure<S> {
+    fn into_host(self) -> HostFunction;
+}
+
+macro_rules! signature {
+    ($($arg:ident: $typ:ident),*) => {
+        impl<$($typ: IrisType,)* R: IrisType> Signature for fn($($typ),*) -> R {
+            fn matches(params: &[MirType], return_type: MirType) -> bool {
+                let accepts: &[fn(MirType) -> bool] = &[$($typ::accepts),*];
+                params.len() == accepts.len()
+                    && params.iter().zip(accepts).all(|(typ, accepts)| accepts(*typ))
+                    && R::accepts(return_type)
+            }
+        }
+
+        impl<$($typ: IrisType,)* R: IrisType> Function<'_, fn($($typ),*) -> R> {
+            pub fn call(&self, $($arg: $typ),*) -> Result<R, Trap> {
+                let result = self.module.call(self.name, &[$($arg.into_value()),*])?;
+                Ok(R::from_value(result))
+            }
+        }
+
+        impl<Closure, $($typ: IrisType,)* R: IrisType> HostClosure<fn($($typ),*) -> R> for Closure
+        where
+            Closure: Fn($($typ),*) -> R + 'static,
+        {
+            // The interpreter only calls it with arguments of the declared
+            // types, which the signature was checked against
+            #[allow(unused_variables, unused_mut)]
+            fn into_host(self) -> HostFunction {
+                Rc::new(move |args: &[Value]| {
+                    let mut args = args.iter();
+                    $(let $arg = $typ::from_value(args.next().copied());)*
+                    self($($arg),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+signature!();
+signature!(a: A);
+signature!(a: A, b: B);
+signature!(a: A, b: B, c: C);
+signature!(a: A, b: B, c: C, d: D);
+signature!(a: A, b: B, c: C, d: D, e: E);
+signature!(a: A, b: B, c: C, d: D, e: E, f: F);
@@ -0,0 +1,224 @@
+//! Applies the fix-its carried by diagnostics to source text.
+//!
+//! Only [`Applicability::MachineApplicable`] suggestions are applied, and a
+//! suggestion whose edits touch those of an earlier one is left for a later
+//! run, so every applied fix sees the text it was made for.
+
+use crate::diagnostics::{Applicability, DiagnosticCollector, Suggestion, TextEdit};
+
+/// The result of fixing one source
+#[derive(Debug, Clone)]
+pub struct FixedSource {
+    pub text: String,
+    /// How many suggestions were applied
+    pub applied: usize,
+}
+
+/// The machine-applicable suggestions of every error and warning reported
+/// in the given source, in the order they were reported
+pub fn applicable_suggestions<'a>(
+    diagnostics: &'a DiagnosticCollector,
+    source: &str,
+) -> Vec<&'a Suggestion> {
+    diagnostics
+        .errors
+        .iter()
+        .chain(&diagnostics.warnings)
+        .filter(|diagnostic| diagnostic.source.as_deref() == Some(source))
+        .flat_map(|diagnostic| &diagnostic.suggestions)
+        .filter(|suggestion| suggestion.applicability == Applicability::MachineApplicable)
+        .collect()
+}
+
+/// Applies the suggestions to `text`, skipping any that overlap an earlier
+/// one. A line left blank by a removal is removed as well.
+pub fn apply_suggestions(text: &str, suggestions: &[&Suggestion]) -> FixedSource {
+    let line_starts = line_starts(text);
+    let mut accepted: Vec<(usize, usize, &str)> = Vec::new();
+    let mut applied = 0;
+
+    for suggestion in suggestions {
+        let Some(ranges) = suggestion
+            .edits
+            .iter()
+            .map(|edit| byte_range(text, &line_starts, edit))
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+        let overlaps = ranges.iter().any(|&(start, end)| {
+            accepted
+                .iter()
+                .any(|&(other_start, other_end, _)| start <= other_end && other_start <= end)
+        });
+        if overlaps {
+            continue;
+        }
+        for ((start, end), edit) in ranges.into_iter().zip(&suggestion.edits) {
+            accepted.push((start, end, &edit.replacement));
+        }
+        applied += 1;
+    }
+
+    // Later edits first, so the offsets of earlier ones stay valid
+    accepted.sort_by_key(|&(start, _, _)| std::cmp::Reverse(start));
+    let mut fixed = text.to_string();
+    for (start, end, replacement) in accepted {
+        let (start, end) = if replacement.is_empty() {
+            blank_line_range(&fixed, start, end)
+        } else {
+            (start, end)
+        };
+        fixed.replace_range(start..end, replacement);
+    }
+
+    FixedSource { text: fixed, applied }
+}
+
+/// Byte offsets of the first character of every line
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// Byte offset of a row and a column counted in characters, if it is
+/// inside the text
+fn offset(text: &str, line_starts: &[usize], row: usize, column: usize) -> Option<usize> {
+    let start = *line_starts.get(row)?;
+    let line = text[start..].split('\n').next().unwrap_or("");
+    if column == line.chars().count() {
+        return Some(start + line.len());
+    }
+    line.char_indices().nth(column).map(|(i, _)| start + i)
+}
+
+fn byte_range(text: &str, line_starts: &[usize], edit: &TextEdit) -> Option<(usize, usize)> {
+    let span = &edit.span;
+    let start = offset(text, line_starts, span.start_row, span.start_column)?;
+    let end = offset(text, line_starts, span.end_row, span.end_column)?;
+    (start <= end).then_some((start, end))
+}
+
+/// Widens a removal to its whole line, newline included, if nothing else
+/// is on that line
+fn blank_line_range(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    let is_blank = |s: &str| s.trim().is_empty();
+    if is_blank(&text[line_start..start]) && is_blank(&text[end..line_end]) {
+        (line_start, line_end)
+    } else {
+        (start, end)
+    }
+}
+
+/// A unified diff between two versions of a source, with three lines of
+/// context around each change. Empty if they are the same.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    unified_diff_between(name, &format!("{} (fixed)", name), old, new)
+}
+
+/// Like [`unified_diff`], with the two versions named in the header
+pub fn unified_diff_between(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old, &new);
+    if ops.iter().all(|op| matches!(op, DiffOp::Same(..))) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    const CONTEXT: usize = 3;
+    let mut i = 0;
+    while i < ops.len() {
+        if matches!(ops[i], DiffOp::Same(..)) {
+            i += 1;
+            continue;
+        }
+
+        // Extend the hunk until a run of unchanged lines is long enough to
+        // separate it from the next change
+        let start = i.saturating_sub(CONTEXT);
+        let mut end = i;
+        let mut same_run = 0;
+        while end < ops.len() && same_run <= 2 * CONTEXT {
+            if matches!(ops[end], DiffOp::Same(..)) {
+                same_run += 1;
+            } else {
+                same_run = 0;
+            }
+            end += 1;
+        }
+        let end = (end - same_run + CONTEXT.min(same_run)).min(ops.len());
+
+        let (old_start, new_start) = ops[start].positions();
+        let old_count = ops[start..end].iter().filter(|op| !matches!(op, DiffOp::Insert(..))).count();
+        let new_count = ops[start..end].iter().filter(|op| !matches!(op, DiffOp::Delete(..))).count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_count,
+            new_start + 1,
+            new_count
+        ));
+        for op in &ops[start..end] {
+            match *op {
+                DiffOp::Same(o, _) => out.push_str(&format!(" {}\n", old[o])),
+                DiffOp::Delete(o, _) => out.push_str(&format!("-{}\n", old[o])),
+                DiffOp::Insert(_, n) => out.push_str(&format!("+{}\n", new[n])),
+            }
+        }
+        i = end;
+    }
+    out
+}
+
+/// One line of a diff, with the positions in the old and new text it is at
+#[derive(Debug, Clone, Copy)]
+enum DiffOp {
+    Same(usize, usize),
+    Delete(usize, usize),
+    Insert(usize, usize),
+}
+
+impl DiffOp {
+    fn positions(&self) -> (usize, usize) {
+        match *self {
+            DiffOp::Same(o, n) | DiffOp::Delete(o, n) | DiffOp::Insert(o, n) => (o, n),
+        }
+    }
+}
+
+/// The shortest edit script between two lists of lines, from their
+/// longest common subsequence
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<DiffOp> {
+    // lcs[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(DiffOp::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffOp::Delete(i, j));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(i, j));
+            j += 1;
+        }
+    }
+    ops
+}
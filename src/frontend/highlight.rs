@@ -0,0 +1,185 @@
+//! Token classification for syntax highlighting.
+//!
+//! Unlike the lexer, the highlighter never fails: comments are kept,
+//! unterminated strings run to the end of their line and characters the
+//! language does not know are classified as [`TokenClass::Unknown`], so
+//! partially typed source can still be colored.
+
+use crate::frontend::lexer::{keyword, multi_char_token, single_char_token};
+use crate::frontend::unicode::{is_identifier_continue, is_identifier_start};
+use crate::frontend::TokenType;
+use crate::span::Span;
+
+/// The highlighting category of a piece of source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    Keyword,
+    /// A built-in type name such as `f64` or `bool`
+    Type,
+    Identifier,
+    Number,
+    String,
+    /// `true` or `false`
+    Boolean,
+    Comment,
+    Operator,
+    /// Parentheses, braces, commas, semicolons, colons and `::`
+    Punctuation,
+    /// An `@` and the attribute name following it
+    Attribute,
+    /// A character that does not start any token
+    Unknown,
+}
+
+impl TokenClass {
+    /// The class of a token the lexer produces
+    pub fn of(tag: &TokenType) -> Self {
+        match tag {
+            TokenType::Fn
+            | TokenType::Extern
+            | TokenType::If
+            | TokenType::Else
+            | TokenType::Then
+            | TokenType::For
+            | TokenType::In
+            | TokenType::While
+            | TokenType::Do
+            | TokenType::Return
+            | TokenType::Match
+            | TokenType::Assert
+            | TokenType::Var
+            | TokenType::Newtype
+            | TokenType::Enum
+            | TokenType::As => TokenClass::Keyword,
+            TokenType::True | TokenType::False => TokenClass::Boolean,
+            TokenType::F8Type
+            | TokenType::F16Type
+            | TokenType::F32Type
+            | TokenType::F64Type
+            | TokenType::BoolType => TokenClass::Type,
+            TokenType::Identifier => TokenClass::Identifier,
+            TokenType::Number => TokenClass::Number,
+            TokenType::String => TokenClass::String,
+            TokenType::LParen
+            | TokenType::RParen
+            | TokenType::LBrace
+            | TokenType::RBrace
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Colon
+            | TokenType::ColonColon => TokenClass::Punctuation,
+            TokenType::At => TokenClass::Attribute,
+            TokenType::Eof | TokenType::Unknown => TokenClass::Unknown,
+            _ => TokenClass::Operator,
+        }
+    }
+}
+
+/// Classifies every token and comment of the source, in order.
+///
+/// Spans use the same 0-based rows and columns as the rest of the compiler,
+/// with columns counted in characters and the end column exclusive.
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    let mut scanner = Scanner {
+        chars: source.chars().collect(),
+        index: 0,
+        row: 0,
+        column: 0,
+    };
+    let mut classes = Vec::new();
+
+    while let Some(c) = scanner.peek(0) {
+        if c.is_whitespace() {
+            scanner.advance();
+            continue;
+        }
+
+        let (row, column) = (scanner.row, scanner.column);
+        let class = if c == '#' {
+            scanner.advance_while(|c| c != '\n');
+            TokenClass::Comment
+        } else if c == '"' {
+            scanner.advance();
+            while let Some(c) = scanner.peek(0) {
+                if c == '\n' {
+                    break;
+                }
+                scanner.advance();
+                if c == '"' {
+                    break;
+                }
+                if c == '\\' && scanner.peek(0).is_some_and(|c| c != '\n') {
+                    scanner.advance();
+                }
+            }
+            TokenClass::String
+        } else if c.is_ascii_digit() {
+            let mut has_dot = false;
+            scanner.advance_while(|c| {
+                let take = c.is_ascii_digit() || (c == '.' && !has_dot);
+                has_dot |= c == '.';
+                take
+            });
+            TokenClass::Number
+        } else if is_identifier_start(c) {
+            let start = scanner.index;
+            scanner.advance_while(is_identifier_continue);
+            let lexeme: String = scanner.chars[start..scanner.index].iter().collect();
+            keyword(&lexeme).map_or(TokenClass::Identifier, |tag| TokenClass::of(&tag))
+        } else if c == '@' {
+            scanner.advance();
+            scanner.advance_while(is_identifier_continue);
+            TokenClass::Attribute
+        } else if let Some((tag, lexeme)) = multi_char_token(c, scanner.peek(1)) {
+            scanner.advance_by(lexeme.chars().count());
+            TokenClass::of(&tag)
+        } else if let Some(tag) = single_char_token(c) {
+            scanner.advance();
+            TokenClass::of(&tag)
+        } else {
+            scanner.advance();
+            TokenClass::Unknown
+        };
+        classes.push((Span::new(row, column, scanner.row, scanner.column), class));
+    }
+
+    classes
+}
+
+/// Walks the source a character at a time, tracking rows and columns
+struct Scanner {
+    chars: Vec<char>,
+    index: usize,
+    row: usize,
+    column: usize,
+}
+
+impl Scanner {
+    fn peek(&self, lookahead: usize) -> Option<char> {
+        self.chars.get(self.index + lookahead).copied()
+    }
+
+    fn advance(&mut self) {
+        if let Some(c) = self.peek(0) {
+            if c == '\n' {
+                self.row += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+            self.index += 1;
+        }
+    }
+
+    fn advance_by(&mut self, n: usize) {
+        for _ in 0..n {
+            self.advance();
+        }
+    }
+
+    fn advance_while(&mut self, mut predicate: impl FnMut(char) -> bool) {
+        while self.peek(0).is_some_and(&mut predicate) {
+            self.advance();
+        }
+    }
+}
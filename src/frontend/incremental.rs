@@ -0,0 +1,126 @@
+//! Relexing of edited source without lexing the whole file again.
+//!
+//! No token spans more than one line: comments end at the newline and
+//! string literals may not contain one. Lexing can therefore restart at the
+//! beginning of any line, so an edit only requires relexing the lines it
+//! touched. Tokens after them are kept and moved by the number of lines and
+//! bytes the edit added or removed.
+
+use crate::frontend::{LexError, LexerContext, Token, TokenType};
+use std::ops::Range;
+
+/// The lines an edit replaced, in the style of an editor's change event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    /// First line the edit touched
+    pub start_row: usize,
+    /// Last line of the replaced text, in the source before the edit
+    pub old_end_row: usize,
+    /// Last line of the inserted text, in the source after the edit
+    pub new_end_row: usize,
+}
+
+/// The tokens of the edited source
+#[derive(Debug, Clone)]
+pub struct Relexed {
+    /// Every token of the new source, ending with [`TokenType::Eof`] like
+    /// the output of [`LexerContext::lex`]
+    pub tokens: Vec<Token>,
+    /// Indices into `tokens` of the tokens that were lexed again. Tokens
+    /// outside of it are the tokens of the previous list, moved to their
+    /// new rows and byte offsets.
+    pub invalidated: Range<usize>,
+}
+
+impl LexerContext {
+    /// Updates the tokens of `previous` after `edit` turned its source into
+    /// `source`, relexing only the lines from `edit.start_row` through
+    /// `edit.new_end_row`.
+    ///
+    /// # Errors
+    /// Returns a `LexError` if the relexed lines do not lex; rows in the
+    /// error are those of the new source.
+    pub fn relex(previous: &[Token], source: &str, edit: Edit) -> Result<Relexed, LexError> {
+        let lines = line_range(source, edit.start_row, edit.new_end_row);
+        let mut relexed = LexerContext::lex(&source[lines.clone()]).map_err(|mut e| {
+            e.span.start_row += edit.start_row;
+            e.span.end_row += edit.start_row;
+            e
+        })?;
+        relexed.pop(); // Eof of the relexed lines
+        for token in &mut relexed {
+            token.row += edit.start_row;
+            token.end_row += edit.start_row;
+            token.start += lines.start;
+            token.end += lines.start;
+        }
+
+        let previous = match previous.split_last() {
+            Some((last, rest)) if last.tag == TokenType::Eof => rest,
+            _ => previous,
+        };
+        let before = previous.partition_point(|token| token.row < edit.start_row);
+        let after = previous.partition_point(|token| token.row <= edit.old_end_row);
+
+        let mut tokens = Vec::with_capacity(before + relexed.len() + previous.len() - after + 1);
+        tokens.extend_from_slice(&previous[..before]);
+        let invalidated = tokens.len()..tokens.len() + relexed.len();
+        tokens.extend(relexed);
+
+        // The text after the edit is unchanged, so every kept token moves
+        // by as many bytes as the first of them
+        let (old_start, new_start) = match previous.get(after) {
+            Some(first) => {
+                let row = first.row + edit.new_end_row - edit.old_end_row;
+                (first.start, offset(source, row, first.column))
+            }
+            None => (0, 0),
+        };
+        tokens.extend(previous[after..].iter().map(|token| Token {
+            row: token.row + edit.new_end_row - edit.old_end_row,
+            end_row: token.end_row + edit.new_end_row - edit.old_end_row,
+            start: token.start + new_start - old_start,
+            end: token.end + new_start - old_start,
+            ..token.clone()
+        }));
+
+        // Eof sits where the lexer stops, after the last character
+        let row = source.matches('\n').count();
+        let column = source[source.rfind('\n').map_or(0, |i| i + 1)..].chars().count();
+        tokens.push(Token {
+            tag: TokenType::Eof,
+            lexeme: String::new(),
+            row,
+            column,
+            end_row: row,
+            end_column: column,
+            start: source.len(),
+            end: source.len(),
+        });
+
+        Ok(Relexed {
+            tokens,
+            invalidated,
+        })
+    }
+}
+
+/// Byte offset of a row and a column counted in characters
+fn offset(source: &str, row: usize, column: usize) -> usize {
+    let start = line_range(source, row, row).start;
+    source[start..].char_indices().nth(column).map_or(source.len(), |(i, _)| start + i)
+}
+
+/// Byte range of the lines `first` through `last` of the source, including
+/// the newline ending the last of them
+fn line_range(source: &str, first: usize, last: usize) -> Range<usize> {
+    let line_start = |row: usize| {
+        if row == 0 {
+            return Some(0);
+        }
+        source.match_indices('\n').nth(row - 1).map(|(i, _)| i + 1)
+    };
+    let start = line_start(first).unwrap_or(source.len());
+    let end = line_start(last + 1).unwrap_or(source.len());
+    start..end.max(start)
+}
@@ -1,8 +1,17 @@
+use crate::frontend::unicode::{is_identifier_continue, is_identifier_start, nfc};
+use crate::json;
+use crate::span::Span;
+use std::fmt;
+
 /// Represents the type of a token in the language.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum TokenType {
     // End of file
     Eof,
+    /// Text that is not a token, such as a stray character or an
+    /// unterminated string. The lexer reports it as a [`LexError`] and goes
+    /// on after it.
+    Unknown,
 
     // Keywords
     Fn,
@@ -13,8 +22,14 @@ pub enum TokenType {
     For,
     In,
     While,
+    Do,
     Return,
+    Match,
+    Assert,
     Var,
+    Newtype,
+    Enum,
+    As,
     True,
     False,
 
@@ -28,6 +43,9 @@ pub enum TokenType {
     // Identifiers and literals
     Identifier,
     Number,
+    /// A double-quoted string literal; the lexeme holds its contents with
+    /// escapes already resolved
+    String,
 
     // Delimiters
     LParen,
@@ -60,26 +78,74 @@ pub enum TokenType {
     NotEqual,     // !=
     LessEqual,    // <=
     GreaterEqual, // >=
+    LessLess,     // <<
+    GreaterGreater, // >>
+    StarStar,     // **
     And,          // &&
     Or,           // ||
     Arrow,        // ->
+    FatArrow,     // =>
+    ColonColon,   // ::
 }
 
 /// Error type returned when lexing fails.
 #[derive(Debug, Clone)]
 pub struct LexError {
     pub message: String,
-    pub row: usize,
-    pub column: usize,
+    /// The text that could not be lexed
+    pub span: Span,
 }
 
 /// A single token with its type, lexeme, and source location.
+///
+/// Rows and columns start at 0 and columns count characters, as in
+/// [`Span`]. The token covers the text from (`row`, `column`) up to
+/// (`end_row`, `end_column`), which is bytes `start..end` of the input; a
+/// string literal covers its quotes even though its lexeme holds only the
+/// contents, and an identifier's lexeme is the NFC form of its text.
 #[derive(Debug, Clone)]
 pub struct Token {
     pub tag: TokenType,
     pub lexeme: String,
     pub row: usize,
     pub column: usize,
+    pub end_row: usize,
+    pub end_column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    /// Formats the token as a single-line JSON object with the same fields
+    /// as its [`Display`](fmt::Display) form
+    pub fn to_json(&self) -> String {
+        let span = Span::from_token(self);
+        format!(
+            "{{\"type\":\"{:?}\",\"lexeme\":{},\"start_row\":{},\"start_column\":{},\"end_row\":{},\"end_column\":{}}}",
+            self.tag,
+            json::string(&self.lexeme),
+            span.start_row,
+            span.start_column,
+            span.end_row,
+            span.end_column
+        )
+    }
+}
+
+/// Formats as `span type "lexeme"`, e.g. `0:3-6 Identifier "foo"`. The
+/// lexeme is quoted and escaped so every token fits on one line.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?} {:?}", Span::from_token(self), self.tag, self.lexeme)
+    }
+}
+
+/// A place in the input, between two characters
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    cursor: usize,
+    row: usize,
+    column: usize,
 }
 
 /// The lexer context that maintains state during lexical analysis.
@@ -89,6 +155,7 @@ pub struct LexerContext {
     column: usize,
     cursor: usize,
     input: String,
+    errors: Vec<LexError>,
 }
 
 impl LexerContext {
@@ -111,7 +178,7 @@ impl LexerContext {
             } else {
                 self.column += 1;
             }
-            self.cursor += 1;
+            self.cursor += c.len_utf8();
         }
     }
 
@@ -122,124 +189,119 @@ impl LexerContext {
         }
     }
 
-    /// Adds a token to the token list at the current position without advancing the cursor.
-    /// The token is tagged with the current row and column.
-    fn add_token(&mut self, tag: TokenType, lexeme: String) {
+    /// The position of the cursor, where the next token starts
+    fn position(&self) -> Position {
+        Position {
+            cursor: self.cursor,
+            row: self.row,
+            column: self.column,
+        }
+    }
+
+    /// The span of the text from `start` up to the cursor
+    fn span_from(&self, start: Position) -> Span {
+        Span::new(start.row, start.column, self.row, self.column)
+    }
+
+    /// Records that the text at `span` is not a valid token
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(LexError { message, span });
+    }
+
+    /// Adds a token for the text from `start` up to the cursor. Every token
+    /// is added here once its text has been consumed, so all of them take
+    /// their positions the same way.
+    fn add_token(&mut self, tag: TokenType, lexeme: String, start: Position) {
         let token = Token {
             tag,
             lexeme,
-            row: self.row,
-            column: self.column,
+            row: start.row,
+            column: start.column,
+            end_row: self.row,
+            end_column: self.column,
+            start: start.cursor,
+            end: self.cursor,
         };
         self.tokens.push(token);
     }
 
-    /// Adds a token and advances the cursor by the length of the lexeme.
-    /// This is a convenience method for single-use tokens where the lexeme length
-    /// matches the number of characters to consume.
-    fn push_token(&mut self, tag: TokenType, lexeme: String) {
-        self.add_token(tag, lexeme.clone());
-        self.advance_by(lexeme.len());
+    /// Consumes `lexeme`, which must be the text at the cursor, and adds it
+    /// as a token
+    fn push_token(&mut self, tag: TokenType, lexeme: &str) {
+        let start = self.position();
+        self.advance_by(lexeme.chars().count());
+        self.add_token(tag, lexeme.to_string(), start);
     }
 
     /// Attempts to match and consume a multi-character operator token.
     /// Checks the current character and the next character using peek(1).
     /// Returns `true` if a multi-char token was matched and added, `false` otherwise.
     fn try_push_multi_char_token(&mut self, c: char) -> bool {
-        let next = self.peek(1);
-
-        match (c, next) {
-            ('=', Some('=')) => {
-                self.push_token(TokenType::Equal, "==".to_string());
-                true
-            }
-            ('!', Some('=')) => {
-                self.push_token(TokenType::NotEqual, "!=".to_string());
-                true
-            }
-            ('<', Some('=')) => {
-                self.push_token(TokenType::LessEqual, "<=".to_string());
+        match multi_char_token(c, self.peek(1)) {
+            Some((token_type, lexeme)) => {
+                self.push_token(token_type, lexeme);
                 true
             }
-            ('>', Some('=')) => {
-                self.push_token(TokenType::GreaterEqual, ">=".to_string());
-                true
-            }
-            ('&', Some('&')) => {
-                self.push_token(TokenType::And, "&&".to_string());
-                true
-            }
-            ('|', Some('|')) => {
-                self.push_token(TokenType::Or, "||".to_string());
-                true
-            }
-            ('-', Some('>')) => {
-                self.push_token(TokenType::Arrow, "->".to_string());
-                true
-            }
-            _ => false,
+            None => false,
         }
     }
 
     /// Attempts to match and consume a single-character token.
     /// Returns `true` if the character was recognized as a token, `false` otherwise.
     fn try_push_single_char_token(&mut self, c: char) -> bool {
-        let token_type = match c {
-            '(' => TokenType::LParen,
-            ')' => TokenType::RParen,
-            '{' => TokenType::LBrace,
-            '}' => TokenType::RBrace,
-            ';' => TokenType::Semicolon,
-            ':' => TokenType::Colon,
-            '+' => TokenType::Plus,
-            ',' => TokenType::Comma,
-            '-' => TokenType::Minus,
-            '/' => TokenType::Slash,
-            '*' => TokenType::Star,
-            '>' => TokenType::Greater,
-            '<' => TokenType::Less,
-            '=' => TokenType::Assign,
-            '!' => TokenType::Bang,
-            '|' => TokenType::Pipe,
-            '&' => TokenType::Ampersand,
-            '^' => TokenType::Caret,
-            '%' => TokenType::Percent,
-            '$' => TokenType::Dollar,
-            '@' => TokenType::At,
-            '~' => TokenType::Tilde,
-            _ => return false,
-        };
-        self.push_token(token_type, c.to_string());
-        true
+        match single_char_token(c) {
+            Some(token_type) => {
+                self.push_token(token_type, &c.to_string());
+                true
+            }
+            None => false,
+        }
     }
 
     /// Lexes the input string and returns a vector of tokens.
     ///
     /// This method consumes the lexer context and returns the complete list of tokens,
     /// including an EOF token at the end. It recognizes:
-    /// - Keywords: fn, extern, if, else, then, for, in, while, return, var
+    /// - Keywords: fn, extern, if, else, then, for, in, while, return, match, assert, var,
+    ///   newtype, enum, as
     /// - Types: f64
-    /// - Identifiers: alphanumeric with underscores (e.g., `my_var`, `_private`)
+    /// - Identifiers: an XID_Start character or underscore followed by XID_Continue
+    ///   characters (e.g., `my_var`, `_private`, `größe`), normalized to NFC
     /// - Number literals: integers and floats (e.g., `123`, `3.14`)
+    /// - String literals: `"..."`, with the escapes `\"`, `\\` and `\n`
     /// - Single-char operators: +, -, *, /, <, >, =, !, |, &, ^, %, $, @, ~
-    /// - Multi-char operators: ==, !=, <=, >=, &&, ||, ->
+    /// - Multi-char operators: ==, !=, <=, >=, &&, ||, ->, =>
     /// - Delimiters: (, ), {, }, ,, ;, :
     /// - Comments: lines starting with #
     ///
     /// # Errors
-    /// Returns a `LexError` if an unexpected character is encountered.
+    /// Returns the first `LexError` if any text is not a token, see
+    /// [`LexerContext::lex_recovering`] for all of them.
     ///
     /// # Example
     /// ```ignore
     /// let tokens = LexerContext::lex("fn foo(x: f64) -> f64 { return x + 1; }")?;
     /// ```
     pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
+        let (tokens, errors) = Self::lex_recovering(input);
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(tokens),
+        }
+    }
+
+    /// Like [`LexerContext::lex`], but lexes the whole input even when some
+    /// of it is not a token. That text becomes a [`TokenType::Unknown`]
+    /// token, and an unknown escape is kept as written in its string, so
+    /// every problem is returned as an error in one run.
+    pub fn lex_recovering(input: &str) -> (Vec<Token>, Vec<LexError>) {
         let mut lexer = LexerContext {
             tokens: Vec::new(),
             row: 0,
             column: 0,
             cursor: 0,
             input: input.to_string(),
+            errors: Vec::new(),
         };
 
         while let Some(c) = lexer.peek(0) {
@@ -269,7 +331,7 @@ impl LexerContext {
 
             // Numbers
             if c.is_ascii_digit() {
-                let start = lexer.cursor;
+                let start = lexer.position();
                 lexer.advance();
                 let mut has_dot = false;
 
@@ -284,58 +346,169 @@ impl LexerContext {
                     }
                 }
 
-                let lexeme = lexer.input[start..lexer.cursor].to_string();
-                lexer.add_token(TokenType::Number, lexeme);
+                let lexeme = lexer.input[start.cursor..lexer.cursor].to_string();
+                lexer.add_token(TokenType::Number, lexeme, start);
                 continue;
             }
 
-            // Identifiers and keywords
-            if c.is_alphabetic() || c == '_' {
-                let start = lexer.cursor;
+            // String literals
+            if c == '"' {
+                let start = lexer.position();
+                lexer.advance();
+                let mut contents = String::new();
+                let terminated = loop {
+                    match lexer.peek(0) {
+                        Some('"') => break true,
+                        Some('\\') => {
+                            let escape = lexer.position();
+                            lexer.advance();
+                            let escaped = match lexer.peek(0) {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('n') => '\n',
+                                Some(other) if other != '\n' => {
+                                    lexer.advance();
+                                    let span = lexer.span_from(escape);
+                                    lexer.error(format!("Unknown escape '\\{}'", other), span);
+                                    contents.push('\\');
+                                    contents.push(other);
+                                    continue;
+                                }
+                                _ => break false,
+                            };
+                            contents.push(escaped);
+                            lexer.advance();
+                        }
+                        Some(c) if c != '\n' => {
+                            contents.push(c);
+                            lexer.advance();
+                        }
+                        _ => break false,
+                    }
+                };
+                if terminated {
+                    lexer.advance(); // closing quote
+                    lexer.add_token(TokenType::String, contents, start);
+                } else {
+                    let span = lexer.span_from(start);
+                    lexer.error("Unterminated string literal".to_string(), span);
+                    let text = lexer.input[start.cursor..lexer.cursor].to_string();
+                    lexer.add_token(TokenType::Unknown, text, start);
+                }
+                continue;
+            }
+
+            // Identifiers and keywords, named in NFC so that names that
+            // differ only in how accents are encoded are the same
+            if is_identifier_start(c) {
+                let start = lexer.position();
                 lexer.advance();
 
                 while let Some(next_c) = lexer.peek(0) {
-                    if next_c.is_alphanumeric() || next_c == '_' {
+                    if is_identifier_continue(next_c) {
                         lexer.advance();
                     } else {
                         break;
                     }
                 }
 
-                let lexeme = lexer.input[start..lexer.cursor].to_string();
-                let token_type = match lexeme.as_str() {
-                    "fn" => TokenType::Fn,
-                    "extern" => TokenType::Extern,
-                    "var" => TokenType::Var,
-                    "if" => TokenType::If,
-                    "else" => TokenType::Else,
-                    "then" => TokenType::Then,
-                    "for" => TokenType::For,
-                    "in" => TokenType::In,
-                    "while" => TokenType::While,
-                    "return" => TokenType::Return,
-                    "true" => TokenType::True,
-                    "false" => TokenType::False,
-                    "f8" => TokenType::F8Type,
-                    "f16" => TokenType::F16Type,
-                    "f32" => TokenType::F32Type,
-                    "f64" => TokenType::F64Type,
-                    "bool" => TokenType::BoolType,
-                    _ => TokenType::Identifier,
-                };
-                lexer.add_token(token_type, lexeme);
+                let lexeme = nfc(&lexer.input[start.cursor..lexer.cursor]);
+                let tag = keyword(&lexeme).unwrap_or(TokenType::Identifier);
+                lexer.add_token(tag, lexeme, start);
                 continue;
             }
 
             // Unknown character - error
-            return Err(LexError {
-                message: format!("Unexpected character '{}'", c),
-                row: lexer.row + 1,
-                column: lexer.column + 1,
-            });
+            let start = lexer.position();
+            lexer.advance();
+            let span = lexer.span_from(start);
+            lexer.error(format!("Unexpected character '{}'", c), span);
+            lexer.add_token(TokenType::Unknown, c.to_string(), start);
         }
 
-        lexer.add_token(TokenType::Eof, String::new());
-        Ok(lexer.tokens)
+        let end = lexer.position();
+        lexer.add_token(TokenType::Eof, String::new(), end);
+        (lexer.tokens, lexer.errors)
     }
 }
+
+/// The keyword or type name token spelled by `lexeme`, if any
+pub(crate) fn keyword(lexeme: &str) -> Option<TokenType> {
+    let token_type = match lexeme {
+        "fn" => TokenType::Fn,
+        "extern" => TokenType::Extern,
+        "var" => TokenType::Var,
+        "newtype" => TokenType::Newtype,
+        "enum" => TokenType::Enum,
+        "as" => TokenType::As,
+        "if" => TokenType::If,
+        "else" => TokenType::Else,
+        "then" => TokenType::Then,
+        "for" => TokenType::For,
+        "in" => TokenType::In,
+        "while" => TokenType::While,
+        "do" => TokenType::Do,
+        "return" => TokenType::Return,
+        "match" => TokenType::Match,
+        "assert" => TokenType::Assert,
+        "true" => TokenType::True,
+        "false" => TokenType::False,
+        "f8" => TokenType::F8Type,
+        "f16" => TokenType::F16Type,
+        "f32" => TokenType::F32Type,
+        "f64" => TokenType::F64Type,
+        "bool" => TokenType::BoolType,
+        _ => return None,
+    };
+    Some(token_type)
+}
+
+/// The operator starting with `c` followed by `next`, with its spelling
+pub(crate) fn multi_char_token(c: char, next: Option<char>) -> Option<(TokenType, &'static str)> {
+    let token = match (c, next) {
+        ('=', Some('=')) => (TokenType::Equal, "=="),
+        ('!', Some('=')) => (TokenType::NotEqual, "!="),
+        ('<', Some('=')) => (TokenType::LessEqual, "<="),
+        ('>', Some('=')) => (TokenType::GreaterEqual, ">="),
+        ('<', Some('<')) => (TokenType::LessLess, "<<"),
+        ('>', Some('>')) => (TokenType::GreaterGreater, ">>"),
+        ('*', Some('*')) => (TokenType::StarStar, "**"),
+        ('&', Some('&')) => (TokenType::And, "&&"),
+        ('|', Some('|')) => (TokenType::Or, "||"),
+        ('-', Some('>')) => (TokenType::Arrow, "->"),
+        ('=', Some('>')) => (TokenType::FatArrow, "=>"),
+        (':', Some(':')) => (TokenType::ColonColon, "::"),
+        _ => return None,
+    };
+    Some(token)
+}
+
+/// The delimiter or operator spelled by the single character `c`, if any
+pub(crate) fn single_char_token(c: char) -> Option<TokenType> {
+    let token_type = match c {
+        '(' => TokenType::LParen,
+        ')' => TokenType::RParen,
+        '{' => TokenType::LBrace,
+        '}' => TokenType::RBrace,
+        ';' => TokenType::Semicolon,
+        ':' => TokenType::Colon,
+        '+' => TokenType::Plus,
+        ',' => TokenType::Comma,
+        '-' => TokenType::Minus,
+        '/' => TokenType::Slash,
+        '*' => TokenType::Star,
+        '>' => TokenType::Greater,
+        '<' => TokenType::Less,
+        '=' => TokenType::Assign,
+        '!' => TokenType::Bang,
+        '|' => TokenType::Pipe,
+        '&' => TokenType::Ampersand,
+        '^' => TokenType::Caret,
+        '%' => TokenType::Percent,
+        '$' => TokenType::Dollar,
+        '@' => TokenType::At,
+        '~' => TokenType::Tilde,
+        _ => return None,
+    };
+    Some(token_type)
+}
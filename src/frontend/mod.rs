@@ -1,6 +1,11 @@
+pub mod highlight;
+pub mod incremental;
 pub mod lexer;
 pub mod parser;
+pub mod unicode;
 
 // Re-export commonly used types
+pub use highlight::{TokenClass, highlight};
+pub use incremental::{Edit, Relexed};
 pub use lexer::{LexError, LexerContext, Token, TokenType};
-pub use parser::{ParseError, ParserContext};
+pub use parser::{ComparisonChains, DEFAULT_MAX_NESTING_DEPTH, ParseError, ParserContext};
@@ -1,7 +1,10 @@
-use crate::ast::{Block, Expression, Program, Statement};
-use crate::frontend::{Token, TokenType};
+use crate::ast::{
+    Attribute, Block, Expression, MatchArm, NodeId, Pattern, Program, Statement, StatementAttribute,
+};
+use crate::frontend::{Token, TokenClass, TokenType};
+use crate::intern::Symbol;
 use crate::span::Span;
-use crate::types::{BaseType, Function, Type, Variable};
+use crate::types::{BaseType, Enum, Function, Newtype, Type, TypeParam, Variable};
 
 impl Expression {
     /// Get the span of this expression
@@ -13,6 +16,42 @@ impl Expression {
             Expression::UnaryOp { span, .. } => *span,
             Expression::Call { span, .. } => *span,
             Expression::Variable { span, .. } => *span,
+            Expression::Variant { span, .. } => *span,
+            Expression::Cast { span, .. } => *span,
+            Expression::Error { span, .. } => *span,
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Expression::Number { span, .. }
+            | Expression::Boolean { span, .. }
+            | Expression::BinaryOp { span, .. }
+            | Expression::UnaryOp { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::Variable { span, .. }
+            | Expression::Variant { span, .. }
+            | Expression::Cast { span, .. }
+            | Expression::Error { span, .. } => span,
+        }
+    }
+}
+
+impl Statement {
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Statement::Assignment { span, .. }
+            | Statement::FunctionDefinition { span, .. }
+            | Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::Block { span, .. }
+            | Statement::Return { span, .. }
+            | Statement::Match { span, .. }
+            | Statement::Expression { span, .. }
+            | Statement::Assert { span, .. }
+            | Statement::Newtype { span, .. }
+            | Statement::Enum { span, .. }
+            | Statement::Error { span, .. } => span,
         }
     }
 }
@@ -23,20 +62,165 @@ pub struct ParseError {
     pub message: String,
 }
 
+/// A function's name, type parameters, arguments and return type
+struct Signature {
+    name: Token,
+    type_params: Vec<TypeParam>,
+    args: Vec<Variable>,
+    return_type: Type,
+}
+
+/// How deeply expressions and blocks may nest unless configured otherwise
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 256;
+
+/// What the parser makes of comparisons in a row, as in `a < b < c`,
+/// which would otherwise compare the bool `a < b` with `c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ComparisonChains {
+    /// Report the chain as an error
+    #[default]
+    Reject,
+    /// Read `a < b < c` as `a < b && b < c`, as long as `b` is a variable
+    /// or a literal, possibly negated, which can be evaluated twice
+    Desugar,
+}
+
 /// The parser context that maintains state during parsing.
 pub struct ParserContext {
     tokens: Vec<Token>,
     position: usize,
+    next_node_id: u32,
+    depth: usize,
+    max_depth: usize,
+    source_name: String,
+    script_mode: bool,
+    comparison_chains: ComparisonChains,
+    /// Parentheses open around the expression being parsed. Inside them a
+    /// line break does not end the expression.
+    grouping: usize,
+    /// Errors recovered from so far
+    errors: Vec<ParseError>,
+    /// Attributes of the statements parsed so far that are not functions
+    statement_attrs: Vec<StatementAttribute>,
 }
 
 impl ParserContext {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    /// A parser of the tokens. Unknown tokens are left out, since the
+    /// lexer has already reported them.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|token| token.tag != TokenType::Unknown);
         ParserContext {
             tokens,
             position: 0,
+            next_node_id: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
+            source_name: String::new(),
+            script_mode: false,
+            comparison_chains: ComparisonChains::default(),
+            grouping: 0,
+            errors: Vec::new(),
+            statement_attrs: Vec::new(),
+        }
+    }
+
+    /// Sets the name of the source being parsed, which is recorded on every
+    /// function so runtime errors can say where they happened
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.source_name = name.into();
+        self
+    }
+
+    /// Allows statements other than function definitions at the top level.
+    /// They are collected, in order, into the body of an implicit `main`
+    /// function returning nothing; top-level `var` declarations become its
+    /// locals rather than globals.
+    pub fn with_script_mode(mut self, enabled: bool) -> Self {
+        self.script_mode = enabled;
+        self
+    }
+
+    /// Sets whether `a < b < c` is an error or means `a < b && b < c`
+    pub fn with_comparison_chains(mut self, chains: ComparisonChains) -> Self {
+        self.comparison_chains = chains;
+        self
+    }
+
+    /// Sets how deeply expressions and blocks may nest before parsing fails.
+    /// The parser is recursive, so this keeps pathological input such as
+    /// `((((...))))` from overflowing the stack.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Starts node numbering at the given id, so several parsed sources
+    /// can share a single id space
+    pub fn with_first_node_id(mut self, id: NodeId) -> Self {
+        self.next_node_id = id.0;
+        self
+    }
+
+    /// The id that will be assigned to the next parsed node
+    pub fn next_node_id(&self) -> NodeId {
+        NodeId(self.next_node_id)
+    }
+
+    fn fresh_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_node_id);
+        self.next_node_id += 1;
+        id
+    }
+
+    /// Runs `parse` inside parentheses, where expressions may span lines
+    fn grouped<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        self.grouping += 1;
+        let result = parse(self);
+        self.grouping -= 1;
+        result
+    }
+
+    /// Returns true if the next token is on a later line than the last one
+    /// consumed
+    fn starts_line(&self) -> bool {
+        match (self.position.checked_sub(1).and_then(|index| self.tokens.get(index)), self.peek()) {
+            (Some(previous), Some(next)) => next.row > previous.end_row,
+            _ => false,
         }
     }
 
+    /// Returns true if a line break before the next token ends the
+    /// expression being parsed, which it does outside of parentheses
+    fn at_expression_end(&self) -> bool {
+        self.grouping == 0 && self.starts_line()
+    }
+
+    /// Runs `parse` one nesting level deeper, failing once the limit is exceeded
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            let location = match self.peek() {
+                Some(token) => format!(" at {}", Span::from_token(token)),
+                None => String::new(),
+            };
+            return Err(ParseError {
+                message: format!(
+                    "Nesting too deep{} (limit is {})",
+                    location, self.max_depth
+                ),
+            });
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// How tightly a binary operator binds, from `||` up to `**`. Unary
+    /// operators and casts bind tighter than any of them, so `-2 ** 2` is
+    /// `(-2) ** 2`, which is 4.
     fn get_precedence(&self, token_type: &TokenType) -> i8 {
         match token_type {
             TokenType::Or => 5,
@@ -48,10 +232,25 @@ impl ParserContext {
             | TokenType::GreaterEqual => 10,
             TokenType::Plus | TokenType::Minus => 20,
             TokenType::Star | TokenType::Slash | TokenType::Percent => 40,
+            TokenType::StarStar => 50,
             _ => -1, // Not a binary operator
         }
     }
 
+    /// Returns true if a chain of the operator groups from the right, as
+    /// `a ** b ** c` does
+    fn is_right_associative(&self, token_type: &TokenType) -> bool {
+        matches!(token_type, TokenType::StarStar)
+    }
+
+    /// Returns true for the operators that order their operands
+    fn is_ordering(&self, token_type: &TokenType) -> bool {
+        matches!(
+            token_type,
+            TokenType::Less | TokenType::Greater | TokenType::LessEqual | TokenType::GreaterEqual
+        )
+    }
+
     fn peek(&self) -> Option<&Token> {
         self.peek_offset(0)
     }
@@ -60,12 +259,22 @@ impl ParserContext {
         self.tokens.get(self.position + offset)
     }
 
+    /// Returns true if the next token has the given type
+    fn check(&self, expected_type: TokenType) -> bool {
+        matches!(self.peek(), Some(token) if token.tag == expected_type)
+    }
+
     fn consume(&mut self) -> Option<Token> {
         let token = self.tokens.get(self.position)?.clone();
         self.position += 1;
         Some(token)
     }
 
+    /// The span of the last token consumed
+    fn previous_span(&self) -> Span {
+        Span::from_token(&self.tokens[self.position - 1])
+    }
+
     fn consume_optional(&mut self, expected_type: TokenType) -> Option<Token> {
         match self.peek() {
             Some(token) if token.tag == expected_type => self.consume(),
@@ -81,10 +290,9 @@ impl ParserContext {
         match self.consume() {
             Some(tok) if tok.tag == expected_type => Ok(tok),
             Some(tok) => Err(ParseError {
-                message: format!(
-                    "{} at {}:{} (got {:?})",
-                    message, tok.row, tok.column, tok.tag
-                ),
+                message: unsupported_keyword(&tok).unwrap_or_else(|| {
+                    format!("{} at {} (got {})", message, Span::from_token(&tok), describe(&tok))
+                }),
             }),
             None => Err(ParseError {
                 message: format!("{} (unexpected end of input)", message),
@@ -92,13 +300,45 @@ impl ParserContext {
         }
     }
 
+    /// Parses the whole input, failing with the first syntax error
     pub fn parse(&mut self) -> Result<Program, ParseError> {
+        let (program, mut errors) = self.parse_recovering();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors.swap_remove(0))
+        }
+    }
+
+    /// Parses the whole input, continuing past syntax errors.
+    ///
+    /// A statement that does not parse becomes a [`Statement::Error`] and
+    /// parsing resumes at the next statement; an operand that does not parse
+    /// becomes an [`Expression::Error`]. Returns the program together with
+    /// every error, in source order.
+    pub fn parse_recovering(&mut self) -> (Program, Vec<ParseError>) {
         let mut globals: Vec<Variable> = Vec::new();
         let mut functions: Vec<Function> = Vec::new();
+        let mut externs: Vec<Function> = Vec::new();
+        let mut newtypes: Vec<Newtype> = Vec::new();
+        let mut enums: Vec<Enum> = Vec::new();
+        let mut script: Vec<Statement> = Vec::new();
 
         while self.peek().is_some() && self.peek().unwrap().tag != TokenType::Eof {
-            let statement = self.parse_statement()?;
+            let statement = self.parse_statement_recovering();
             match statement {
+                statement
+                    if self.script_mode
+                        && !matches!(
+                            statement,
+                            Statement::FunctionDefinition { .. }
+                                | Statement::Newtype { .. }
+                                | Statement::Enum { .. }
+                                | Statement::Error { .. }
+                        ) =>
+                {
+                    script.push(statement);
+                }
                 Statement::Assignment { left, typ, right, .. } => {
                     // If no type specified, default to Auto for type inference
                     let typ = typ.unwrap_or(Type::Base(BaseType::Auto));
@@ -111,40 +351,229 @@ impl ParserContext {
                 }
                 Statement::FunctionDefinition {
                     name,
+                    type_params,
                     args,
                     return_type,
+                    attrs,
                     body,
+                    external,
+                    span,
                     ..
                 } => {
-                    functions.push(Function {
+                    let function = Function {
                         name,
+                        type_params,
                         args,
                         return_type,
+                        attrs,
                         body,
+                        span,
+                        source: self.source_name.clone(),
+                    };
+                    if external {
+                        externs.push(function);
+                    } else {
+                        functions.push(function);
+                    }
+                }
+                Statement::Newtype { name, underlying, span, .. } => {
+                    newtypes.push(Newtype {
+                        name,
+                        underlying,
+                        span,
+                    });
+                }
+                Statement::Enum { name, variants, span, .. } => {
+                    enums.push(Enum {
+                        name,
+                        variants,
+                        span,
                     });
                 }
+                Statement::Error { .. } => {}
                 _ => {
-                    return Err(ParseError {
+                    self.errors.push(ParseError {
                         message: format!(
-                            "Unexpected statement at top level: {:?}. Only function definitions and variable declarations are allowed at the top level.",
-                            statement
+                            "Unexpected statement at top level at {}. Only function definitions, variable declarations and type declarations are allowed at the top level, unless script mode is enabled.",
+                            statement.span()
                         ),
-                    })
+                    });
+                }
+            }
+        }
+
+        if let (Some(first), Some(last)) = (script.first(), script.last()) {
+            let span = Span::merge(&first.span(), &last.span());
+            if functions.iter().any(|function| function.name == "main") {
+                self.errors.push(ParseError {
+                    message: format!(
+                        "Top-level statements at {} cannot be combined with a 'main' function",
+                        span
+                    ),
+                });
+            } else {
+                functions.push(Function {
+                    name: "main".to_string(),
+                    type_params: Vec::new(),
+                    args: Vec::new(),
+                    return_type: Type::Base(BaseType::Void),
+                    attrs: Vec::new(),
+                    body: Block::new(script, span),
+                    span,
+                    source: self.source_name.clone(),
+                });
+            }
+        }
+
+        let errors = std::mem::take(&mut self.errors);
+        (
+            Program {
+                globals,
+                functions,
+                externs,
+                newtypes,
+                enums,
+                generics: Vec::new(),
+                statement_attrs: std::mem::take(&mut self.statement_attrs),
+                next_node_id: self.next_node_id(),
+            },
+            errors,
+        )
+    }
+
+    /// Parses a statement. If it does not parse, the error is recorded and
+    /// the rest of the statement is skipped.
+    fn parse_statement_recovering(&mut self) -> Statement {
+        let start = self.position;
+        match self.parse_statement() {
+            Ok(statement) => {
+                self.end_line(&statement);
+                statement
+            }
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize(start);
+                let span = match (self.tokens.get(start), self.position.checked_sub(1)) {
+                    (Some(first), Some(last)) if last >= start => Span::merge(
+                        &Span::from_token(first),
+                        &Span::from_token(&self.tokens[last]),
+                    ),
+                    (Some(first), _) => Span::from_token(first),
+                    (None, _) => Span::new(0, 0, 0, 0),
+                };
+                Statement::Error {
+                    span,
+                    id: self.fresh_id(),
                 }
             }
         }
+    }
 
-        Ok(Program { globals, functions })
+    /// Reports a statement followed by more on the same line. Statements
+    /// end at line breaks, so `x = 1 y = 2` is two statements run together
+    /// rather than one. When what follows cannot start a statement, it is
+    /// skipped.
+    fn end_line(&mut self, statement: &Statement) {
+        let Some(next) = self.peek() else {
+            return;
+        };
+        // A stray semicolon is reported as such when it is parsed
+        if matches!(next.tag, TokenType::RBrace | TokenType::Eof | TokenType::Semicolon) || self.starts_line() {
+            return;
+        }
+        let starts_statement = matches!(
+            next.tag,
+            TokenType::Fn
+                | TokenType::Extern
+                | TokenType::At
+                | TokenType::Var
+                | TokenType::Newtype
+                | TokenType::Enum
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::Match
+                | TokenType::Return
+                | TokenType::Assert
+                | TokenType::Identifier
+                | TokenType::LBrace
+        );
+        self.errors.push(ParseError {
+            message: unsupported_keyword(next).unwrap_or_else(|| {
+                format!(
+                    "Expected a line break after the statement at {}, found {} at {}",
+                    statement.span(), describe(next), Span::from_token(next)
+                )
+            }),
+        });
+        if !starts_statement {
+            self.synchronize(self.position);
+        }
+    }
+
+    /// Skips ahead to the next line or the next token that can start a
+    /// statement, or to the `}` closing the block the failed statement
+    /// started in
+    fn synchronize(&mut self, start: usize) {
+        // Always make progress, so a statement can't fail at the same token forever
+        if self.position == start {
+            self.consume();
+        }
+
+        // Braces the failed statement opened before it stopped parsing
+        let mut depth = self.tokens[start..self.position.min(self.tokens.len())]
+            .iter()
+            .fold(0isize, |depth, token| match token.tag {
+                TokenType::LBrace => depth + 1,
+                TokenType::RBrace => depth - 1,
+                _ => depth,
+            })
+            .max(0);
+
+        while let Some(token) = self.peek() {
+            // Statements end at line breaks
+            if depth == 0 && self.starts_line() {
+                break;
+            }
+            match token.tag {
+                TokenType::Eof => break,
+                TokenType::LBrace => depth += 1,
+                TokenType::RBrace if depth == 0 => break,
+                TokenType::RBrace => depth -= 1,
+                TokenType::Fn
+                | TokenType::Extern
+                | TokenType::At
+                | TokenType::Var
+                | TokenType::Newtype
+                | TokenType::Enum
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::Match
+                | TokenType::Return
+                | TokenType::Assert
+                    if depth == 0 =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            self.consume();
+        }
     }
 
     fn parse_type(&mut self) -> Result<Type, ParseError> {
         // Check for pointer prefix (*)
-        if let Some(token) = self.peek() {
-            if token.tag == TokenType::Star {
-                self.consume(); // consume '*'
-                let inner_type = self.parse_type()?;
-                return Ok(Type::PointerType(Box::new(inner_type)));
-            }
+        if self.check(TokenType::Star) {
+            self.consume(); // consume '*'
+            let inner_type = self.parse_type()?;
+            return Ok(Type::PointerType(Box::new(inner_type)));
+        }
+        // `**T` is lexed as the power operator
+        if self.check(TokenType::StarStar) {
+            self.consume(); // consume '**'
+            let inner_type = self.parse_type()?;
+            return Ok(Type::PointerType(Box::new(Type::PointerType(Box::new(inner_type)))));
         }
 
         // Parse base type
@@ -156,9 +585,16 @@ impl ParserContext {
                     TokenType::F32Type => BaseType::F32,
                     TokenType::F64Type => BaseType::F64,
                     TokenType::BoolType => BaseType::Bool,
+                    // A newtype, resolved by the typechecker
+                    TokenType::Identifier => {
+                        let name = self.consume().unwrap().lexeme;
+                        return Ok(Type::Named(name));
+                    }
                     _ => {
                         return Err(ParseError {
-                            message: format!("Expected type, got {:?}", token.tag),
+                            message: unsupported_keyword(token).unwrap_or_else(|| {
+                                format!("Expected type at {}, got {}", Span::from_token(token), describe(token))
+                            }),
                         })
                     }
                 };
@@ -171,38 +607,282 @@ impl ParserContext {
         }
     }
 
-    fn parse_block(&mut self, start_token: &Token) -> Result<Block, ParseError> {
-        let mut statements = Vec::new();
+    /// Parses the statements of a block opened by `start_token` and the `}`
+    /// closing it, failing with `missing_rbrace` if it is not there. The
+    /// block spans both braces.
+    fn parse_block(&mut self, start_token: &Token, missing_rbrace: &str) -> Result<(Block, Token), ParseError> {
         let start_span = Span::from_token(start_token);
 
-        while self.peek().is_some() {
-            // Stop when we hit a closing brace
-            if let Some(token) = self.peek() {
-                if token.tag == TokenType::RBrace {
+        let statements = self.nested(|parser| {
+            let mut statements = Vec::new();
+            while parser.peek().is_some() {
+                // Stop when we hit a closing brace
+                if parser.check(TokenType::RBrace) || parser.check(TokenType::Eof) {
                     break;
                 }
+                statements.push(parser.parse_statement_recovering());
             }
-            let statement = self.parse_statement()?;
-            statements.push(statement);
-        }
-
-        // Compute the span: from start_token to the last statement (or just start_token if empty)
-        let span = if let Some(last_stmt) = statements.last() {
-            let end_span = match last_stmt {
-                Statement::Assignment { span, .. } => *span,
-                Statement::FunctionDefinition { span, .. } => *span,
-                Statement::If { span, .. } => *span,
-                Statement::While { span, .. } => *span,
-                Statement::Block { span, .. } => *span,
-                Statement::Return { span, .. } => *span,
-                Statement::Expression { span, .. } => *span,
-            };
-            Span::merge(&start_span, &end_span)
+            Ok(statements)
+        })?;
+
+        let rbrace = self.consume_assert(TokenType::RBrace, missing_rbrace.to_string())?;
+        let span = Span::merge(&start_span, &Span::from_token(&rbrace));
+        Ok((Block::new(statements, span), rbrace))
+    }
+
+    /// Parses a single attribute: `@name` or `@name(arg, ...)`
+    fn parse_attribute(&mut self) -> Result<Attribute, ParseError> {
+        let at = self.consume_assert(TokenType::At, "Expected '@'".to_string())?;
+        let name = self.consume_assert(
+            TokenType::Identifier,
+            "Expected attribute name after '@'".to_string(),
+        )?;
+
+        let mut args = Vec::new();
+        let mut end = Span::from_token(&name);
+        if self.consume_optional(TokenType::LParen).is_some() {
+            let rparen;
+            (args, rparen, _) = self.parse_list("attribute arguments", starts_expression, Self::parse_expression)?;
+            end = Span::from_token(&rparen);
+        }
+
+        Ok(Attribute {
+            name: name.lexeme,
+            args,
+            span: Span::merge(&Span::from_token(&at), &end),
+        })
+    }
+
+    /// Parses the `::Variant` following the enum name `enum_token`
+    fn parse_variant_path(&mut self, enum_token: Token) -> Result<(String, String, Span), ParseError> {
+        self.consume_assert(
+            TokenType::ColonColon,
+            format!(
+                "Expected '::' after '{}' at {}",
+                enum_token.lexeme, Span::from_token(&enum_token)
+            ),
+        )?;
+        let variant = self.consume_assert(
+            TokenType::Identifier,
+            format!("Expected a variant name after '{}::'", enum_token.lexeme),
+        )?;
+        let span = Span::merge(&Span::from_token(&enum_token), &Span::from_token(&variant));
+        Ok((enum_token.lexeme, variant.lexeme, span))
+    }
+
+    /// Parses the pattern of a match arm: a number, a bool, an enum variant or `_`
+    fn parse_pattern(&mut self) -> Result<(Pattern, Span), ParseError> {
+        let token = self.consume().ok_or_else(|| ParseError {
+            message: "Expected match pattern (unexpected end of input)".to_string(),
+        })?;
+        match token.tag {
+            TokenType::Number => {
+                let value = token.lexeme.parse::<f64>().map_err(|_| ParseError {
+                    message: format!("Failed to parse number: {}", token.lexeme),
+                })?;
+                Ok((Pattern::Number(value), Span::from_token(&token)))
+            }
+            TokenType::Minus => {
+                let number = self.consume_assert(
+                    TokenType::Number,
+                    "Expected number after '-' in match pattern".to_string(),
+                )?;
+                let value = number.lexeme.parse::<f64>().map_err(|_| ParseError {
+                    message: format!("Failed to parse number: {}", number.lexeme),
+                })?;
+                let span = Span::merge(&Span::from_token(&token), &Span::from_token(&number));
+                Ok((Pattern::Number(-value), span))
+            }
+            TokenType::True => Ok((Pattern::Boolean(true), Span::from_token(&token))),
+            TokenType::False => Ok((Pattern::Boolean(false), Span::from_token(&token))),
+            TokenType::Identifier if token.lexeme == "_" => {
+                Ok((Pattern::Wildcard, Span::from_token(&token)))
+            }
+            TokenType::Identifier => {
+                let (enum_name, variant, span) = self.parse_variant_path(token)?;
+                Ok((Pattern::Variant { enum_name, variant }, span))
+            }
+            _ => Err(ParseError {
+                message: unsupported_keyword(&token).unwrap_or_else(|| {
+                    format!("Expected match pattern at {} (got {})", Span::from_token(&token), describe(&token))
+                }),
+            }),
+        }
+    }
+
+    /// Parses what follows `fn`: the name, type parameters, arguments and
+    /// return type of a function
+    fn parse_signature(&mut self) -> Result<Signature, ParseError> {
+        let name = self.consume_assert(
+            TokenType::Identifier,
+            "Expected function name after 'fn'".to_string(),
+        )?;
+
+        // Type parameters: fn max<T: Comparable>(a: T, b: T) -> T
+        let mut type_params = Vec::new();
+        if self.consume_optional(TokenType::Less).is_some() {
+            loop {
+                let param = self.consume_assert(
+                    TokenType::Identifier,
+                    format!("Expected a type parameter name in '{}<...>'", name.lexeme),
+                )?;
+                let mut bounds = Vec::new();
+                if self.consume_optional(TokenType::Colon).is_some() {
+                    loop {
+                        let bound = self.consume_assert(
+                            TokenType::Identifier,
+                            format!("Expected a bound after '{}:'", param.lexeme),
+                        )?;
+                        bounds.push(bound.lexeme);
+                        if self.consume_optional(TokenType::Plus).is_none() {
+                            break;
+                        }
+                    }
+                }
+                type_params.push(TypeParam {
+                    name: param.lexeme,
+                    bounds,
+                });
+                if self.consume_optional(TokenType::Comma).is_none() {
+                    break;
+                }
+            }
+            self.consume_assert(
+                TokenType::Greater,
+                "Expected '>' after type parameters".to_string(),
+            )?;
+        }
+
+        self.consume_assert(
+            TokenType::LParen,
+            "Expected '(' after function name".to_string(),
+        )?;
+
+        let (args, _, _) = self.parse_list(
+            "parameters",
+            |tag| *tag == TokenType::Identifier,
+            Self::parse_parameter,
+        )?;
+
+        // Parse return type (optional, defaults to void)
+        let return_type = if self.consume_optional(TokenType::Arrow).is_some() {
+            self.parse_type()?
         } else {
-            start_span
+            Type::Base(BaseType::Void)
+        };
+
+        Ok(Signature {
+            name,
+            type_params,
+            args,
+            return_type,
+        })
+    }
+
+    /// Parses a parameter: `name: type`, with an optional `= default`
+    fn parse_parameter(&mut self) -> Result<Variable, ParseError> {
+        let name = self.consume_assert(
+            TokenType::Identifier,
+            "Expected argument name".to_string(),
+        )?;
+
+        self.consume_assert(
+            TokenType::Colon,
+            "Expected ':' after argument name".to_string(),
+        )?;
+
+        let typ = self.parse_type()?;
+
+        let initializer = match self.consume_optional(TokenType::Assign) {
+            Some(_) => Some(Box::new(self.parse_expression()?)),
+            None => None,
         };
 
-        Ok(Block::new(statements, span))
+        Ok(Variable {
+            name: name.lexeme,
+            typ,
+            initializer,
+        })
+    }
+
+    /// Parses the items of a list in parentheses, after its `(`, up to and
+    /// including its `)`. Items are separated by commas, and a comma may
+    /// follow the last one. An item that starts where a comma is missing
+    /// is reported and parsed as if the comma were there; after any other
+    /// error the rest of the list is skipped, so the construct around it
+    /// can still be parsed. `what` names the items in errors, and
+    /// `starts_item` tells the tokens an item can start with.
+    ///
+    /// Returns the items, the `)` and the span of the items skipped, if any.
+    fn parse_list<T>(
+        &mut self,
+        what: &str,
+        starts_item: impl Fn(&TokenType) -> bool,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<(Vec<T>, Token, Option<Span>), ParseError> {
+        self.grouped(|parser| {
+            let mut items = Vec::new();
+            loop {
+                if let Some(rparen) = parser.consume_optional(TokenType::RParen) {
+                    return Ok((items, rparen, None));
+                }
+                let start = parser.position;
+                if let Err(error) = item(parser).map(|parsed| items.push(parsed)) {
+                    return parser.skip_list(start, error).map(|(rparen, skipped)| (items, rparen, Some(skipped)));
+                }
+                if parser.consume_optional(TokenType::Comma).is_some() {
+                    continue;
+                }
+                match parser.peek() {
+                    Some(token) if token.tag == TokenType::RParen => {}
+                    Some(token) if starts_item(&token.tag) => {
+                        parser.errors.push(ParseError {
+                            message: format!("Missing ',' between {} at {}", what, Span::from_token(token)),
+                        });
+                    }
+                    Some(token) => {
+                        let error = ParseError {
+                            message: format!(
+                                "Expected ',' or ')' after {} at {}, found {}",
+                                what, Span::from_token(token), describe(token)
+                            ),
+                        };
+                        let start = parser.position;
+                        return parser.skip_list(start, error).map(|(rparen, skipped)| (items, rparen, Some(skipped)));
+                    }
+                    None => {
+                        return Err(ParseError {
+                            message: format!("Expected ')' after {} (unexpected end of input)", what),
+                        });
+                    }
+                }
+            }
+        })
+    }
+
+    /// Skips the rest of a list that failed to parse with `error`, from
+    /// the token at `start` up to and including its `)`. The error is
+    /// recorded if the `)` is found, and returned otherwise, when a brace
+    /// or the end of the input comes first. Returns the `)` and the span of
+    /// what was skipped before it.
+    fn skip_list(&mut self, start: usize, error: ParseError) -> Result<(Token, Span), ParseError> {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            match token.tag {
+                TokenType::RParen if depth == 0 => {
+                    let rparen = self.consume().unwrap();
+                    self.errors.push(error);
+                    let skipped = Span::merge(&Span::from_token(&self.tokens[start]), &Span::from_token(&rparen));
+                    return Ok((rparen, skipped));
+                }
+                TokenType::RParen => depth -= 1,
+                TokenType::LParen => depth += 1,
+                TokenType::LBrace | TokenType::RBrace | TokenType::Eof => break,
+                _ => {}
+            }
+            self.consume();
+        }
+        Err(error)
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
@@ -212,117 +892,113 @@ impl ParserContext {
                 TokenType::Semicolon => {
                     Err(ParseError {
                         message: format!(
-                            "Unexpected semicolon at line {}:{}. This language does not use semicolons.",
-                            token.row, token.column
+                            "Unexpected semicolon at {}. This language does not use semicolons.",
+                            Span::from_token(token)
                         ),
                     })
                 }
 
-                // Function definition
-                TokenType::Fn => {
-                    let fn_token = self.consume().unwrap(); // consume 'fn'
-
-                    let name = self.consume_assert(
-                        TokenType::Identifier,
-                        "Expected function name after 'fn'".to_string(),
-                    )?;
-
-                    self.consume_assert(
-                        TokenType::LParen,
-                        "Expected '(' after function name".to_string(),
-                    )?;
-
-                    // Parse argument list
-                    let mut args: Vec<Variable> = Vec::new();
-                    while let Some(t) = self.peek() {
-                        if t.tag == TokenType::RParen {
-                            break;
-                        }
-
-                        // Parse argument: name: type [= default_value]
-                        let arg_name = self.consume_assert(
-                            TokenType::Identifier,
-                            "Expected argument name".to_string(),
-                        )?;
-
-                        self.consume_assert(
-                            TokenType::Colon,
-                            "Expected ':' after argument name".to_string(),
-                        )?;
-
-                        let arg_type = self.parse_type()?;
-
-                        // Check for default value
-                        let initializer = if let Some(t) = self.peek() {
-                            if t.tag == TokenType::Assign {
-                                self.consume(); // consume '='
-                                Some(Box::new(self.parse_expression()?))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        };
-
-                        args.push(Variable {
-                            name: arg_name.lexeme,
-                            typ: arg_type,
-                            initializer,
-                        });
+                // Function attributes, e.g. `@pure @export fn f() { ... }`.
+                // Other statements may only be given `@allow(...)`.
+                TokenType::At => {
+                    let mut attrs = Vec::new();
+                    while self.peek().is_some_and(|t| t.tag == TokenType::At) {
+                        attrs.push(self.parse_attribute()?);
+                    }
 
-                        // Check for comma or end of args
-                        if let Some(t) = self.peek() {
-                            if t.tag == TokenType::Comma {
-                                self.consume();
+                    let mut statement = self.parse_statement()?;
+                    let span = Span::merge(&attrs[0].span, &statement.span());
+                    *statement.span_mut() = span;
+                    match &mut statement {
+                        Statement::FunctionDefinition { attrs: existing, .. } => *existing = attrs,
+                        _ => {
+                            if let Some(attr) = attrs.iter().find(|attr| attr.name != "allow") {
+                                return Err(ParseError {
+                                    message: format!(
+                                        "Attribute '@{}' at {} must be followed by a function definition",
+                                        attr.name, attr.span
+                                    ),
+                                });
                             }
+                            self.statement_attrs.extend(attrs.into_iter().map(|attribute| StatementAttribute {
+                                attribute,
+                                span,
+                                source: self.source_name.clone(),
+                            }));
                         }
                     }
+                    Ok(statement)
+                }
 
-                    self.consume_assert(
-                        TokenType::RParen,
-                        "Expected ')' after arguments".to_string(),
-                    )?;
-
-                    // Parse return type (optional, defaults to void)
-                    let return_type = if self.consume_optional(TokenType::Arrow).is_some() {
-                        self.parse_type()?
-                    } else {
-                        Type::Base(BaseType::Void)
-                    };
+                // Function definition
+                TokenType::Fn => {
+                    let fn_token = self.consume().unwrap(); // consume 'fn'
+                    let Signature {
+                        name,
+                        type_params,
+                        args,
+                        return_type,
+                    } = self.parse_signature()?;
 
                     // Parse body
                     let lbrace = self.consume_assert(
                         TokenType::LBrace,
                         "Expected '{' before function body".to_string(),
                     )?;
-                    let body = self.parse_block(&lbrace)?;
-                    let rbrace = self.consume_assert(
-                        TokenType::RBrace,
-                        "Expected '}' after function body".to_string(),
-                    )?;
+                    let (body, rbrace) = self.parse_block(&lbrace, "Expected '}' after function body")?;
 
                     let span = Span::merge(&Span::from_token(&fn_token), &Span::from_token(&rbrace));
 
                     Ok(Statement::FunctionDefinition {
                         name: name.lexeme,
+                        type_params,
                         args,
                         return_type,
+                        attrs: Vec::new(),
                         body,
+                        external: false,
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
+
+                // `extern fn name(args) -> type`, declaring a function defined
+                // outside the program
+                TokenType::Extern => {
+                    let extern_token = self.consume().unwrap(); // consume 'extern'
+                    self.consume_assert(TokenType::Fn, "Expected 'fn' after 'extern'".to_string())?;
+                    let Signature {
+                        name,
+                        type_params,
+                        args,
+                        return_type,
+                    } = self.parse_signature()?;
+                    let span = Span::merge(&Span::from_token(&extern_token), &self.previous_span());
+
+                    Ok(Statement::FunctionDefinition {
+                        name: name.lexeme,
+                        type_params,
+                        args,
+                        return_type,
+                        attrs: Vec::new(),
+                        body: Block::new(Vec::new(), span),
+                        external: true,
                         span,
+                        id: self.fresh_id(),
                     })
                 }
 
                 TokenType::LBrace => {
                     let lbrace = self.consume().unwrap();
 
-                    let body = self.parse_block(&lbrace)?;
-                    let rbrace = self.consume_assert(TokenType::RBrace, "Missing } after body".to_string())?;
+                    let (body, rbrace) = self.parse_block(&lbrace, "Missing } after body")?;
 
                     let span = Span::merge(&Span::from_token(&lbrace), &Span::from_token(&rbrace));
 
                     Ok(Statement::Block {
                         block: body,
                         span,
+                        id: self.fresh_id(),
                     })
                 }
                 TokenType::Return => {
@@ -331,6 +1007,8 @@ impl ParserContext {
                     let expr = match self.peek() {
                         // If we see a closing brace or EOF, it's a bare return
                         Some(t) if t.tag == TokenType::RBrace || t.tag == TokenType::Eof => None,
+                        // A return at the end of a line returns nothing
+                        Some(_) if self.starts_line() => None,
                         // Otherwise parse the expression
                         Some(_) => Some(Box::new(self.parse_expression()?)),
                         None => None,
@@ -345,6 +1023,30 @@ impl ParserContext {
                     Ok(Statement::Return {
                         expression: expr,
                         span,
+                        id: self.fresh_id(),
+                    })
+                }
+                TokenType::Assert => {
+                    let assert_token = self.consume().unwrap();
+                    let condition = self.parse_expression()?;
+                    let mut span = Span::merge(&Span::from_token(&assert_token), &condition.span());
+
+                    let message = if self.consume_optional(TokenType::Comma).is_some() {
+                        let message = self.consume_assert(
+                            TokenType::String,
+                            "Expected a string message after ',' in assert".to_string(),
+                        )?;
+                        span = Span::merge(&span, &Span::from_token(&message));
+                        Some(message.lexeme)
+                    } else {
+                        None
+                    };
+
+                    Ok(Statement::Assert {
+                        condition: Box::new(condition),
+                        message,
+                        span,
+                        id: self.fresh_id(),
                     })
                 }
                 TokenType::While => {
@@ -358,16 +1060,86 @@ impl ParserContext {
                         "Missing { after while conditional".to_string(),
                     )?;
 
-                    let body = self.parse_block(&lbrace)?;
+                    let (body, rbrace) = self.parse_block(&lbrace, "Missing } after while body")?;
+
+                    let span = Span::merge(&Span::from_token(&while_token), &Span::from_token(&rbrace));
+
+                    Ok(Statement::While {
+                        condition,
+                        body,
+                        do_while: false,
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
+                TokenType::Do => {
+                    let do_token = self.consume().unwrap();
+
+                    let lbrace = self.consume_assert(
+                        TokenType::LBrace,
+                        "Missing { after do".to_string(),
+                    )?;
+
+                    let (body, _) = self.parse_block(&lbrace, "Missing } after do body")?;
+                    self.consume_assert(
+                        TokenType::While,
+                        "Missing while after do body".to_string(),
+                    )?;
+
+                    let condition = Box::new(self.parse_expression()?);
+                    let span = Span::merge(&Span::from_token(&do_token), &condition.span());
+
+                    Ok(Statement::While {
+                        condition,
+                        body,
+                        do_while: true,
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
+                TokenType::Match => {
+                    let match_token = self.consume().unwrap();
+                    let scrutinee = Box::new(self.parse_expression()?);
+
+                    self.consume_assert(
+                        TokenType::LBrace,
+                        "Missing { after match scrutinee".to_string(),
+                    )?;
+
+                    let mut arms = Vec::new();
+                    while !self.check(TokenType::RBrace) && self.peek().is_some() {
+                        let (pattern, pattern_span) = self.parse_pattern()?;
+                        self.consume_assert(
+                            TokenType::FatArrow,
+                            "Expected '=>' after match pattern".to_string(),
+                        )?;
+                        let lbrace = self.consume_assert(
+                            TokenType::LBrace,
+                            "Missing { after '=>'".to_string(),
+                        )?;
+                        let (body, arm_rbrace) = self.parse_block(&lbrace, "Missing } after match arm")?;
+                        self.consume_optional(TokenType::Comma);
+
+                        arms.push(MatchArm {
+                            pattern,
+                            body,
+                            span: Span::merge(&pattern_span, &Span::from_token(&arm_rbrace)),
+                        });
+                    }
 
                     let rbrace = self.consume_assert(
                         TokenType::RBrace,
-                        "Missing } after while body".to_string(),
+                        "Missing } after match arms".to_string(),
                     )?;
 
-                    let span = Span::merge(&Span::from_token(&while_token), &Span::from_token(&rbrace));
+                    let span = Span::merge(&Span::from_token(&match_token), &Span::from_token(&rbrace));
 
-                    Ok(Statement::While { condition, body, span })
+                    Ok(Statement::Match {
+                        scrutinee,
+                        arms,
+                        span,
+                        id: self.fresh_id(),
+                    })
                 }
                 TokenType::If => {
                     let if_token = self.consume().unwrap();
@@ -380,9 +1152,7 @@ impl ParserContext {
                         "Missing { after if conditional".to_string(),
                     )?;
 
-                    let then = self.parse_block(&lbrace)?;
-
-                    let mut rbrace = self.consume_assert(TokenType::RBrace, "Missing } after if body".to_string())?;
+                    let (then, mut rbrace) = self.parse_block(&lbrace, "Missing } after if body")?;
 
                     let els = match self.peek() {
                         Some(token) if token.tag == TokenType::Else => {
@@ -391,11 +1161,8 @@ impl ParserContext {
                                 TokenType::LBrace,
                                 "Expected '{' after 'else'".to_string(),
                             )?;
-                            let block = self.parse_block(&else_lbrace)?;
-                            rbrace = self.consume_assert(
-                                TokenType::RBrace,
-                                "Expected '}' after else body".to_string(),
-                            )?;
+                            let (block, else_rbrace) = self.parse_block(&else_lbrace, "Expected '}' after else body")?;
+                            rbrace = else_rbrace;
                             Some(block)
                         }
                         _ => None,
@@ -408,6 +1175,7 @@ impl ParserContext {
                         then,
                         els,
                         span,
+                        id: self.fresh_id(),
                     })
                 }
 
@@ -430,6 +1198,7 @@ impl ParserContext {
                                 typ: None,
                                 right,
                                 span,
+                                id: self.fresh_id(),
                             })
                         }
                         // Expression Statement
@@ -439,6 +1208,7 @@ impl ParserContext {
                             Ok(Statement::Expression {
                                 expression: Box::new(expr),
                                 span,
+                                id: self.fresh_id(),
                             })
                         }
                         None => Err(ParseError {
@@ -470,15 +1240,14 @@ impl ParserContext {
                     let right = match self.peek() {
                         Some(t) if t.tag == TokenType::Assign => {
                             self.consume();
-                            self.parse_expression().ok().map(Box::new)
+                            Some(Box::new(self.parse_expression()?))
                         }
                         _ => None,
                     };
 
-                    let span = if let Some(r) = &right {
-                        Span::merge(&Span::from_token(&var_token), &r.span())
-                    } else {
-                        Span::merge(&Span::from_token(&var_token), &Span::from_token(&identifier))
+                    let span = match &right {
+                        Some(r) => Span::merge(&Span::from_token(&var_token), &r.span()),
+                        None => Span::merge(&Span::from_token(&var_token), &self.previous_span()),
                     };
 
                     Ok(Statement::Assignment {
@@ -486,11 +1255,84 @@ impl ParserContext {
                         typ,
                         right,
                         span,
+                        id: self.fresh_id(),
                     })
                 }
 
+                // Distinct types: newtype Meters = f64
+                TokenType::Newtype => {
+                    let newtype_token = self.consume().unwrap();
+                    let name = self.consume_assert(
+                        TokenType::Identifier,
+                        "Expected a type name after 'newtype'".to_string(),
+                    )?;
+                    self.consume_assert(
+                        TokenType::Assign,
+                        format!("Expected '=' after newtype '{}'", name.lexeme),
+                    )?;
+                    let underlying = self.parse_type()?;
+
+                    let span = Span::merge(&Span::from_token(&newtype_token), &self.previous_span());
+
+                    Ok(Statement::Newtype {
+                        name: name.lexeme,
+                        underlying,
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
+
+                // Enumerations: enum Color { Red, Green, Blue }
+                TokenType::Enum => {
+                    let enum_token = self.consume().unwrap();
+                    let name = self.consume_assert(
+                        TokenType::Identifier,
+                        "Expected a type name after 'enum'".to_string(),
+                    )?;
+                    self.consume_assert(
+                        TokenType::LBrace,
+                        format!("Expected '{{' after enum '{}'", name.lexeme),
+                    )?;
+
+                    let mut variants = Vec::new();
+                    while !self.check(TokenType::RBrace) {
+                        let variant = self.consume_assert(
+                            TokenType::Identifier,
+                            format!("Expected a variant name in enum '{}'", name.lexeme),
+                        )?;
+                        variants.push(variant.lexeme);
+                        if self.consume_optional(TokenType::Comma).is_none() {
+                            break;
+                        }
+                    }
+
+                    let rbrace = self.consume_assert(
+                        TokenType::RBrace,
+                        format!("Missing }} after the variants of enum '{}'", name.lexeme),
+                    )?;
+
+                    let span = Span::merge(&Span::from_token(&enum_token), &Span::from_token(&rbrace));
+
+                    Ok(Statement::Enum {
+                        name: name.lexeme,
+                        variants,
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
+
+                // An operator at the start of a line is not taken as
+                // continuing the line before
+                _ if self.get_precedence(&token.tag) >= 0 || token.tag == TokenType::As => Err(ParseError {
+                    message: format!(
+                        "'{}' at {} starts a line, so it does not continue the expression on the line before. Move it to the end of that line",
+                        token.lexeme, Span::from_token(token)
+                    ),
+                }),
                 _ => Err(ParseError {
-                    message: format!("Unexpected token: {:?}", token.tag),
+                    message: unsupported_keyword(token).unwrap_or_else(|| {
+                        format!("Unexpected {} at {}", describe(token), Span::from_token(token))
+                    }),
                 }),
             },
             None => Err(ParseError {
@@ -505,12 +1347,15 @@ impl ParserContext {
             Some(token) => match token.tag {
                 // Parenthesized expression
                 TokenType::LParen => {
-                    self.consume(); // consume '('
-                    let expr = self.parse_expression()?;
-                    self.consume_assert(
+                    let lparen = self.consume().unwrap();
+                    let mut expr = self.grouped(|parser| parser.parse_expression())?;
+                    let rparen = self.consume_assert(
                         TokenType::RParen,
                         "Expected ')' after expression".to_string(),
                     )?;
+                    // The parentheses belong to the expression, so that an
+                    // operation on it starts or ends at them
+                    *expr.span_mut() = Span::merge(&Span::from_token(&lparen), &Span::from_token(&rparen));
                     Ok(expr)
                 }
 
@@ -524,6 +1369,7 @@ impl ParserContext {
                         value,
                         span: Span::from_token(&token),
                         typ: None,
+                        id: self.fresh_id(),
                     })
                 }
 
@@ -534,6 +1380,7 @@ impl ParserContext {
                         value: true,
                         span: Span::from_token(&token),
                         typ: None,
+                        id: self.fresh_id(),
                     })
                 }
                 TokenType::False => {
@@ -542,6 +1389,7 @@ impl ParserContext {
                         value: false,
                         span: Span::from_token(&token),
                         typ: None,
+                        id: self.fresh_id(),
                     })
                 }
 
@@ -550,42 +1398,37 @@ impl ParserContext {
                     let identifier = self.consume().unwrap();
 
                     // Check if it's a function call
-                    if let Some(t) = self.peek() {
-                        if t.tag == TokenType::LParen {
-                            self.consume(); // consume '('
-
-                            let mut args = Vec::new();
-
-                            // Parse arguments if present
-                            if let Some(t) = self.peek() {
-                                if t.tag != TokenType::RParen {
-                                    args.push(self.parse_expression()?);
-
-                                    while let Some(t) = self.peek() {
-                                        if t.tag == TokenType::Comma {
-                                            self.consume(); // consume ','
-                                            args.push(self.parse_expression()?);
-                                        } else {
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
+                    if self.check(TokenType::LParen) {
+                        self.consume(); // consume '('
+
+                        let (mut args, rparen, skipped) =
+                            self.parse_list("arguments", starts_expression, Self::parse_expression)?;
+                        // The arguments skipped are not known to be one
+                        if let Some(span) = skipped {
+                            args.push(Expression::Error { span, id: self.fresh_id() });
+                        }
 
-                            let rparen = self.consume_assert(
-                                TokenType::RParen,
-                                "Expected ')' after arguments".to_string(),
-                            )?;
+                        let span = Span::merge(&Span::from_token(&identifier), &Span::from_token(&rparen));
 
-                            let span = Span::merge(&Span::from_token(&identifier), &Span::from_token(&rparen));
+                        return Ok(Expression::Call {
+                            identifier: Symbol::intern(&identifier.lexeme),
+                            args,
+                            span,
+                            typ: None,
+                            id: self.fresh_id(),
+                        });
+                    }
 
-                            return Ok(Expression::Call {
-                                identifier: identifier.lexeme,
-                                args,
-                                span,
-                                typ: None,
-                            });
-                        }
+                    // An enum variant: Color::Red
+                    if self.check(TokenType::ColonColon) {
+                        let (enum_name, variant, span) = self.parse_variant_path(identifier)?;
+                        return Ok(Expression::Variant {
+                            enum_name,
+                            variant,
+                            span,
+                            typ: None,
+                            id: self.fresh_id(),
+                        });
                     }
 
                     // Just a variable reference
@@ -594,12 +1437,49 @@ impl ParserContext {
                         name: identifier.lexeme,
                         span,
                         typ: None,
+                        id: self.fresh_id(),
                     })
                 }
 
-                _ => Err(ParseError {
-                    message: format!("Unexpected token in expression: {:?}", token.tag),
-                }),
+                _ => {
+                    let token = token.clone();
+                    self.errors.push(ParseError {
+                        message: unsupported_keyword(&token).unwrap_or_else(|| {
+                            format!("Unexpected {} in expression at {}", describe(&token), Span::from_token(&token))
+                        }),
+                    });
+                    let mut span = Span::from_token(&token);
+                    // Delimiters are left for the construct around the expression
+                    if !matches!(
+                        token.tag,
+                        TokenType::RParen
+                            | TokenType::LBrace
+                            | TokenType::RBrace
+                            | TokenType::Comma
+                            | TokenType::Eof
+                    ) {
+                        self.consume();
+                        // A stray token before an operand, as in `x + * 2`: the
+                        // operand becomes part of the error
+                        if self.peek().is_some_and(|next| {
+                            matches!(
+                                next.tag,
+                                TokenType::Number
+                                    | TokenType::Identifier
+                                    | TokenType::True
+                                    | TokenType::False
+                                    | TokenType::LParen
+                            )
+                        }) {
+                            let operand = self.nested(Self::parse_unary)?;
+                            span = Span::merge(&span, &operand.span());
+                        }
+                    }
+                    Ok(Expression::Error {
+                        span,
+                        id: self.fresh_id(),
+                    })
+                }
             },
             None => Err(ParseError {
                 message: "Unexpected end of input in expression".to_string(),
@@ -613,13 +1493,14 @@ impl ParserContext {
             Some(token) => match token.tag {
                 TokenType::Plus | TokenType::Minus | TokenType::Bang => {
                     let op = self.consume().unwrap();
-                    let expr = self.parse_unary()?;
+                    let expr = self.nested(Self::parse_unary)?;
                     let span = Span::merge(&Span::from_token(&op), &expr.span());
                     Ok(Expression::UnaryOp {
                         op,
                         left: Box::new(expr),
                         span,
                         typ: None,
+                        id: self.fresh_id(),
                     })
                 }
                 _ => self.parse_primary(),
@@ -636,7 +1517,27 @@ impl ParserContext {
         expr_prec: i8,
         mut lhs: Box<Expression>,
     ) -> Result<Box<Expression>, ParseError> {
+        // The last ordering of `lhs` and its right operand, while `lhs` ends
+        // with one
+        let mut ordering: Option<(Token, Expression)> = None;
         loop {
+            // An operator starting a line belongs to the next statement
+            if self.at_expression_end() {
+                return Ok(lhs);
+            }
+
+            // Every number is a float, so there is nothing to shift
+            if let Some(token) = self.peek().filter(|token| matches!(token.tag, TokenType::LessLess | TokenType::GreaterGreater)) {
+                return Err(ParseError {
+                    message: format!(
+                        "Operator '{}' at {} shifts integers, but every number is a float. Scale by a power of two instead, as in 'x {} 2 ** n'",
+                        token.lexeme,
+                        Span::from_token(token),
+                        if token.tag == TokenType::LessLess { "*" } else { "/" }
+                    ),
+                });
+            }
+
             // Get the precedence of the next operator
             let tok_prec = match self.peek() {
                 Some(token) => self.get_precedence(&token.tag),
@@ -652,7 +1553,7 @@ impl ParserContext {
             let op = self.consume().unwrap();
 
             // Parse the primary expression after the binary operator
-            let mut rhs = Box::new(self.parse_unary()?);
+            let mut rhs = Box::new(self.parse_cast()?);
 
             // Check the next operator's precedence for right-associativity
             let next_prec = match self.peek() {
@@ -662,6 +1563,21 @@ impl ParserContext {
 
             if tok_prec < next_prec {
                 rhs = self.parse_binop_rhs(tok_prec + 1, rhs)?;
+            } else if tok_prec == next_prec && self.is_right_associative(&op.tag) {
+                rhs = self.parse_binop_rhs(tok_prec, rhs)?;
+            }
+
+            let chained = ordering.take().filter(|_| self.is_ordering(&op.tag));
+            if self.is_ordering(&op.tag) {
+                ordering = Some((op.clone(), (*rhs).clone()));
+            }
+            if let Some((first, middle)) = chained {
+                lhs = Box::new(self.chain_comparison(lhs, &first, middle, op, rhs));
+                // A chain is only reported once
+                if matches!(*lhs, Expression::Error { .. }) {
+                    ordering = None;
+                }
+                continue;
             }
 
             // Merge LHS and RHS
@@ -672,12 +1588,154 @@ impl ParserContext {
                 right: rhs,
                 span,
                 typ: None,
+                id: self.fresh_id(),
             });
         }
     }
 
+    /// Joins `left`, which ends with the ordering `first` of `middle`, to
+    /// `right` with the ordering `op`, as `a < b` and `c` in `a < b < c`
+    fn chain_comparison(
+        &mut self,
+        left: Box<Expression>,
+        first: &Token,
+        middle: Expression,
+        op: Token,
+        right: Box<Expression>,
+    ) -> Expression {
+        let span = Span::merge(&left.span(), &right.span());
+        if self.comparison_chains == ComparisonChains::Reject {
+            self.errors.push(ParseError {
+                message: format!(
+                    "Comparisons cannot be chained: '{}' at {} would compare the bool result of '{}' at {}. Join the comparisons with '&&', as in 'a {} b && b {} c'",
+                    op.lexeme, Span::from_token(&op), first.lexeme, Span::from_token(first), first.lexeme, op.lexeme
+                ),
+            });
+            return Expression::Error { span, id: self.fresh_id() };
+        }
+
+        // The middle operand is compared twice, so it is copied
+        let Some(copy) = self.copy_operand(&middle) else {
+            self.errors.push(ParseError {
+                message: format!(
+                    "The middle operand of the comparisons chained at {} would be evaluated twice. Store it in a variable first",
+                    span
+                ),
+            });
+            return Expression::Error { span, id: self.fresh_id() };
+        };
+        let and = Token {
+            tag: TokenType::And,
+            lexeme: "&&".to_string(),
+            ..op.clone()
+        };
+        let second = Expression::BinaryOp {
+            span: Span::merge(&copy.span(), &right.span()),
+            left: Box::new(copy),
+            op,
+            right,
+            typ: None,
+            id: self.fresh_id(),
+        };
+        Expression::BinaryOp {
+            left,
+            op: and,
+            right: Box::new(second),
+            span,
+            typ: None,
+            id: self.fresh_id(),
+        }
+    }
+
+    /// A copy of a variable or a literal, possibly negated, with ids of its
+    /// own. Anything else may have effects, so it is not copied.
+    fn copy_operand(&mut self, operand: &Expression) -> Option<Expression> {
+        let mut copy = operand.clone();
+        match &mut copy {
+            Expression::Variable { id, .. } | Expression::Number { id, .. } | Expression::Boolean { id, .. } => {
+                *id = self.fresh_id();
+            }
+            Expression::UnaryOp { left, id, .. } => {
+                **left = self.copy_operand(left)?;
+                *id = self.fresh_id();
+            }
+            _ => return None,
+        }
+        Some(copy)
+    }
+
+    // Parse casts, which bind tighter than any binary operator: `-x as Meters`
+    fn parse_cast(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_unary()?;
+        while !self.at_expression_end() && self.consume_optional(TokenType::As).is_some() {
+            let target = self.parse_type()?;
+            let span = Span::merge(&expr.span(), &self.previous_span());
+            expr = Expression::Cast {
+                expression: Box::new(expr),
+                target,
+                span,
+                typ: None,
+                id: self.fresh_id(),
+            };
+        }
+        Ok(expr)
+    }
+
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
-        let lhs = self.parse_unary()?;
-        self.parse_binop_rhs(0, Box::new(lhs)).map(|b| *b)
+        self.nested(|parser| {
+            let lhs = parser.parse_cast()?;
+            parser.parse_binop_rhs(0, Box::new(lhs)).map(|b| *b)
+        })
     }
 }
+
+/// Returns true for the tokens an expression can start with
+fn starts_expression(tag: &TokenType) -> bool {
+    matches!(
+        tag,
+        TokenType::Identifier
+            | TokenType::Number
+            | TokenType::True
+            | TokenType::False
+            | TokenType::LParen
+            | TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Bang
+    )
+}
+
+/// Describes a token for an error message by its class, as in
+/// "keyword 'while'" or "number '2'"
+fn describe(token: &Token) -> String {
+    match TokenClass::of(&token.tag) {
+        _ if token.tag == TokenType::Eof => "end of input".to_string(),
+        TokenClass::Keyword => format!("keyword '{}'", token.lexeme),
+        TokenClass::Type => format!("type '{}'", token.lexeme),
+        TokenClass::Identifier => format!("identifier '{}'", token.lexeme),
+        TokenClass::Number => format!("number '{}'", token.lexeme),
+        TokenClass::String => format!("string \"{}\"", token.lexeme),
+        TokenClass::Operator => format!("operator '{}'", token.lexeme),
+        _ => format!("'{}'", token.lexeme),
+    }
+}
+
+/// The error for a keyword the lexer reserves but the language does not
+/// use yet, pointing to the syntax to write instead
+fn unsupported_keyword(token: &Token) -> Option<String> {
+    let help = match token.tag {
+        TokenType::Then => "'if' takes no 'then': its body is a block, as in 'if x > 0 { ... }'",
+        TokenType::For => {
+            "'for' loops are not implemented. Count with a variable and 'while' instead, as in 'while i < n { ... }'"
+        }
+        TokenType::In => {
+            "'in' belongs to 'for' loops, which are not implemented. Count with a variable and 'while' instead, as in 'while i < n { ... }'"
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "Keyword '{}' at {} is reserved but not supported: {}",
+        token.lexeme,
+        Span::from_token(token),
+        help
+    ))
+}
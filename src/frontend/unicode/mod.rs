@@ -0,0 +1,206 @@
+//! The Unicode rules for identifiers.
+//!
+//! Identifiers follow UAX #31: they start with a character that has the
+//! XID_Start property or an underscore, and continue with characters that
+//! have XID_Continue. The lexer normalizes them to NFC, so that the same
+//! name written with precomposed or combining characters is one name.
+//! Names that still only look alike, such as `path` and `раth` with a
+//! Cyrillic `а`, are found by comparing their [`skeleton`]s.
+
+mod tables;
+
+use tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS, XID_CONTINUE, XID_START};
+
+/// Whether an identifier may start with `c`
+pub fn is_identifier_start(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphabetic() || c == '_';
+    }
+    in_ranges(XID_START, c)
+}
+
+/// Whether an identifier may continue with `c`
+pub fn is_identifier_continue(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphanumeric() || c == '_';
+    }
+    in_ranges(XID_CONTINUE, c)
+}
+
+fn in_ranges(ranges: &[(u32, u32)], c: char) -> bool {
+    let c = c as u32;
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+// Hangul syllables decompose and compose by arithmetic rather than tables
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_L_COUNT: u32 = 19;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = HANGUL_L_COUNT * HANGUL_N_COUNT;
+
+/// The text in Unicode Normalization Form C: canonically decomposed,
+/// with combining marks in canonical order, then composed again
+pub fn nfc(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+
+    let mut chars = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        decompose(c as u32, &mut chars);
+    }
+
+    // Canonical ordering: a mark moves before the marks of higher class
+    // that precede it, up to the previous starter
+    for i in 1..chars.len() {
+        let class = combining_class(chars[i]);
+        if class == 0 {
+            continue;
+        }
+        let mut j = i;
+        while j > 0 && combining_class(chars[j - 1]) > class {
+            chars.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+
+    let mut composed: Vec<u32> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(index) = starter {
+            // A mark is blocked from the starter by a mark of the same or
+            // a higher class between them
+            let blocked = composed.len() > index + 1 && last_class >= class;
+            if !blocked && let Some(primary) = compose(composed[index], c) {
+                composed[index] = primary;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(composed.len());
+        }
+        last_class = class;
+        composed.push(c);
+    }
+    composed.into_iter().filter_map(char::from_u32).collect()
+}
+
+/// Appends the full canonical decomposition of `c`
+fn decompose(c: u32, out: &mut Vec<u32>) {
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&c) {
+        let index = c - HANGUL_S_BASE;
+        out.push(HANGUL_L_BASE + index / HANGUL_N_COUNT);
+        out.push(HANGUL_V_BASE + index % HANGUL_N_COUNT / HANGUL_T_COUNT);
+        if !index.is_multiple_of(HANGUL_T_COUNT) {
+            out.push(HANGUL_T_BASE + index % HANGUL_T_COUNT);
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(from, _, _)| from) {
+        Ok(i) => {
+            let (_, first, second) = DECOMPOSITIONS[i];
+            decompose(first, out);
+            if second != 0 {
+                decompose(second, out);
+            }
+        }
+        Err(_) => out.push(c),
+    }
+}
+
+/// The character `first` and `second` compose into, if any
+fn compose(first: u32, second: u32) -> Option<u32> {
+    let l_range = HANGUL_L_BASE..HANGUL_L_BASE + HANGUL_L_COUNT;
+    let v_range = HANGUL_V_BASE..HANGUL_V_BASE + HANGUL_V_COUNT;
+    if l_range.contains(&first) && v_range.contains(&second) {
+        let index = (first - HANGUL_L_BASE) * HANGUL_N_COUNT + (second - HANGUL_V_BASE) * HANGUL_T_COUNT;
+        return Some(HANGUL_S_BASE + index);
+    }
+    let lv = (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&first)
+        && (first - HANGUL_S_BASE).is_multiple_of(HANGUL_T_COUNT);
+    if lv && (HANGUL_T_BASE + 1..HANGUL_T_BASE + HANGUL_T_COUNT).contains(&second) {
+        return Some(first + second - HANGUL_T_BASE);
+    }
+    COMPOSITIONS
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()
+        .map(|i| COMPOSITIONS[i].2)
+}
+
+fn combining_class(c: u32) -> u8 {
+    match COMBINING_CLASSES.binary_search_by(|&(start, end, _)| {
+        if end < c {
+            std::cmp::Ordering::Less
+        } else if start > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }) {
+        Ok(i) => COMBINING_CLASSES[i].2,
+        Err(_) => 0,
+    }
+}
+
+/// What the identifier looks like: its characters with each replaced by
+/// the Latin letter or digit it is most easily mistaken for. Two different
+/// names with the same skeleton are confusable. The table covers the Greek
+/// and Cyrillic letters that look like Latin ones, and the ASCII characters
+/// that look like each other.
+pub fn skeleton(identifier: &str) -> String {
+    nfc(identifier).chars().map(prototype).collect()
+}
+
+fn prototype(c: char) -> char {
+    match c {
+        '0' | 'О' | 'Ο' | 'о' | 'ο' => 'o',
+        '1' | 'I' | 'Ι' | 'І' | 'ӏ' | 'l' => 'l',
+        'а' | 'α' => 'a',
+        'А' | 'Α' => 'A',
+        'В' | 'Β' => 'B',
+        'с' => 'c',
+        'С' => 'C',
+        'ԁ' => 'd',
+        'е' => 'e',
+        'Е' | 'Ε' => 'E',
+        'һ' => 'h',
+        'Н' | 'Η' => 'H',
+        'і' | 'ι' => 'i',
+        'ј' => 'j',
+        'Ј' => 'J',
+        'К' | 'Κ' => 'K',
+        'М' | 'Μ' => 'M',
+        'Ν' => 'N',
+        'р' | 'ρ' => 'p',
+        'Р' | 'Ρ' => 'P',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'Ѕ' => 'S',
+        'Т' | 'Τ' => 'T',
+        'ν' => 'v',
+        'ԝ' => 'w',
+        'х' => 'x',
+        'Х' | 'Χ' => 'X',
+        'у' => 'y',
+        'Υ' => 'Y',
+        'Ζ' => 'Z',
+        other => other,
+    }
+}
@@ -0,0 +1,168 @@
+use crate::ast::{Block, Expression, Program, Statement};
+use crate::frontend::unicode::skeleton;
+use crate::span::Span;
+use crate::types::Function;
+use std::collections::HashMap;
+
+/// Names that look alike but are different, like `path` and `раth` with a
+/// Cyrillic `а`.
+///
+/// Every name the program declares or refers to is compared with the first
+/// one seen that has the same [`skeleton`]. Two names that are both ASCII
+/// are not reported, since which of `l1` and `ll` was meant is a question
+/// of the font rather than of the characters.
+#[derive(Debug, Default)]
+pub struct ConfusableIdentifiers {
+    confusables: Vec<Confusable>,
+}
+
+/// A name that looks like a different name seen before it
+#[derive(Debug, Clone)]
+pub struct Confusable {
+    pub name: String,
+    /// Name of the source it is in
+    pub source: String,
+    /// Where it is first written
+    pub span: Span,
+    /// The name it looks like
+    pub original: String,
+    /// Where that name is first written
+    pub original_span: Span,
+}
+
+impl ConfusableIdentifiers {
+    pub fn new(program: &Program) -> Self {
+        let mut names = Vec::new();
+        for function in program.functions.iter().chain(&program.externs) {
+            let mut collector = Collector {
+                function,
+                names: &mut names,
+            };
+            collector.add(&function.name, function.span);
+            collector.block(&function.body);
+        }
+
+        // The first spelling of each skeleton, and the spellings reported
+        let mut first: HashMap<String, (String, Span)> = HashMap::new();
+        let mut confusables: Vec<Confusable> = Vec::new();
+        for (name, source, span) in names {
+            let (original, original_span) = first.entry(skeleton(&name)).or_insert((name.clone(), span));
+            let ascii = name.is_ascii() && original.is_ascii();
+            if name == *original || ascii || confusables.iter().any(|c| c.name == name) {
+                continue;
+            }
+            confusables.push(Confusable {
+                name,
+                source,
+                span,
+                original: original.clone(),
+                original_span: *original_span,
+            });
+        }
+        ConfusableIdentifiers { confusables }
+    }
+
+    /// The names that look like an earlier one, in the order they are seen
+    pub fn confusables(&self) -> &[Confusable] {
+        &self.confusables
+    }
+}
+
+/// Walks a function body, gathering every name written in it
+struct Collector<'a> {
+    function: &'a Function,
+    names: &'a mut Vec<(String, String, Span)>,
+}
+
+impl Collector<'_> {
+    fn add(&mut self, name: &str, span: Span) {
+        self.names.push((name.to_string(), self.function.source.clone(), span));
+    }
+
+    fn block(&mut self, block: &Block) {
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Variable { name, span, .. } => self.add(name, *span),
+            Expression::Variant {
+                enum_name,
+                variant,
+                span,
+                ..
+            } => {
+                self.add(enum_name, *span);
+                self.add(variant, *span);
+            }
+            Expression::Number { .. } | Expression::Boolean { .. } | Expression::Error { .. } => {}
+            Expression::UnaryOp { left, .. } => self.expression(left),
+            Expression::Cast { expression, .. } => self.expression(expression),
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Call {
+                identifier,
+                args,
+                span,
+                ..
+            } => {
+                self.add(identifier.as_str(), *span);
+                args.iter().for_each(|arg| self.expression(arg));
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment {
+                left, right, span, ..
+            } => {
+                self.add(left, *span);
+                if let Some(right) = right {
+                    self.expression(right);
+                }
+            }
+            Statement::If {
+                condition,
+                then,
+                els,
+                ..
+            } => {
+                self.expression(condition);
+                self.block(then);
+                if let Some(els) = els {
+                    self.block(els);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.block(body);
+            }
+            Statement::Block { block, .. } => self.block(block),
+            Statement::Match {
+                scrutinee, arms, ..
+            } => {
+                self.expression(scrutinee);
+                arms.iter().for_each(|arm| self.block(&arm.body));
+            }
+            Statement::Return { expression, .. } => {
+                if let Some(expression) = expression {
+                    self.expression(expression);
+                }
+            }
+            Statement::Expression { expression, .. } => self.expression(expression),
+            Statement::Assert { condition, .. } => self.expression(condition),
+            // Nested definitions are collected on their own
+            Statement::FunctionDefinition { .. }
+            | Statement::Newtype { .. }
+            | Statement::Enum { .. }
+            | Statement::Error { .. } => {}
+        }
+    }
+}
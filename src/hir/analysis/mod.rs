@@ -0,0 +1,6 @@
+//! Analyses over the AST that passes can query while transforming it.
+
+pub mod confusables;
+pub mod purity;
+pub mod unused;
+pub mod value_range;
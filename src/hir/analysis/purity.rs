@@ -0,0 +1,275 @@
+use crate::ast::{Block, BranchHint, Expression, Program, Statement};
+use crate::intern::Symbol;
+use crate::span::Span;
+use crate::types::Function;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Which functions can be called without any observable effect, so that
+/// calls to them may be removed or merged when their result is not needed.
+///
+/// A function is inferred pure if it does not assign to globals, contains
+/// no loops (which might never terminate) or asserts (which might stop the
+/// program) and only calls functions that are pure themselves. Functions are only inferred pure once every callee has
+/// been proven pure, so recursive functions never are: they might not return.
+///
+/// Functions declared `@pure` promise to return, so they may loop, assert
+/// and recurse, but are still checked for effects. A declaration that does not
+/// hold up is reported as a [`PurityViolation`] and not trusted.
+#[derive(Debug, Default)]
+pub struct PurityAnalysis {
+    pure: HashSet<Symbol>,
+    violations: Vec<PurityViolation>,
+}
+
+/// The first effect found in a function body
+#[derive(Debug, Clone, PartialEq)]
+pub enum Impurity {
+    WritesGlobal(String),
+    Loop,
+    Assert,
+    CallsImpure(Symbol),
+}
+
+impl fmt::Display for Impurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Impurity::WritesGlobal(name) => write!(f, "writes to global '{}'", name),
+            Impurity::Loop => write!(f, "contains a loop, which might not terminate"),
+            Impurity::Assert => write!(f, "contains an assert, which might stop the program"),
+            Impurity::CallsImpure(callee) => write!(f, "calls '{}', which is not pure", callee),
+        }
+    }
+}
+
+/// A function declared `@pure` whose body has an effect
+#[derive(Debug, Clone)]
+pub struct PurityViolation {
+    pub function: Symbol,
+    pub span: Span,
+    pub impurity: Impurity,
+}
+
+impl PurityAnalysis {
+    pub fn new(program: &Program) -> Self {
+        let globals: HashSet<&str> = program
+            .globals
+            .iter()
+            .map(|global| global.name.as_str())
+            .collect();
+        let mut declared: HashSet<Symbol> = program
+            .functions
+            .iter()
+            .filter(|function| function.has_attr("pure"))
+            .map(|function| Symbol::intern(&function.name))
+            .collect();
+        let mut violations = Vec::new();
+
+        loop {
+            let pure = Self::infer(program, &globals, &declared);
+
+            // Declarations are trusted optimistically, so mutually recursive
+            // @pure functions can vouch for each other. Any that turn out to
+            // have effects are dropped and everything is inferred again.
+            let mut broken = Vec::new();
+            for function in program.functions.iter().filter(|function| function.has_attr("pure")) {
+                let name = Symbol::intern(&function.name);
+                if !declared.contains(&name) {
+                    continue;
+                }
+                if let Err(impurity) = Checker::new(&globals, &pure, true).function(function) {
+                    broken.push(PurityViolation {
+                        function: name,
+                        span: function.span,
+                        impurity,
+                    });
+                }
+            }
+
+            if broken.is_empty() {
+                return PurityAnalysis { pure, violations };
+            }
+            for violation in broken {
+                declared.remove(&violation.function);
+                violations.push(violation);
+            }
+        }
+    }
+
+    /// The trusted declarations plus every function proven pure around them
+    fn infer(
+        program: &Program,
+        globals: &HashSet<&str>,
+        declared: &HashSet<Symbol>,
+    ) -> HashSet<Symbol> {
+        let mut pure = declared.clone();
+        loop {
+            let mut changed = false;
+            for function in program.functions.iter().filter(|function| !function.has_attr("pure")) {
+                let name = Symbol::intern(&function.name);
+                if !pure.contains(&name)
+                    && Checker::new(globals, &pure, false)
+                        .function(function)
+                        .is_ok()
+                {
+                    pure.insert(name);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return pure;
+            }
+        }
+    }
+
+    /// Returns true if the function has been proven free of effects
+    pub fn is_pure_function(&self, name: Symbol) -> bool {
+        self.pure.contains(&name)
+    }
+
+    /// Returns true if evaluating the expression has no effect besides
+    /// producing its value
+    pub fn is_pure(&self, expression: &Expression) -> bool {
+        expression_effect(expression, &self.pure).is_ok()
+    }
+
+    /// Functions declared `@pure` that have effects
+    pub fn violations(&self) -> &[PurityViolation] {
+        &self.violations
+    }
+}
+
+/// The first call to a function outside `pure` made by the expression
+fn expression_effect(expression: &Expression, pure: &HashSet<Symbol>) -> Result<(), Impurity> {
+    match expression {
+        Expression::Number { .. }
+        | Expression::Boolean { .. }
+        | Expression::Variable { .. }
+        | Expression::Variant { .. }
+        | Expression::Error { .. } => Ok(()),
+        Expression::UnaryOp { left, .. } => expression_effect(left, pure),
+        Expression::Cast { expression, .. } => expression_effect(expression, pure),
+        Expression::BinaryOp { left, right, .. } => {
+            expression_effect(left, pure)?;
+            expression_effect(right, pure)
+        }
+        Expression::Call {
+            identifier, args, ..
+        } => {
+            if !pure.contains(identifier) && BranchHint::from_name(identifier.as_str()).is_none() {
+                return Err(Impurity::CallsImpure(*identifier));
+            }
+            args.iter().try_for_each(|arg| expression_effect(arg, pure))
+        }
+    }
+}
+
+/// Looks for the first effect in a function body, given the set of
+/// functions that may be called freely
+struct Checker<'a> {
+    globals: &'a HashSet<&'a str>,
+    pure: &'a HashSet<Symbol>,
+    allow_divergence: bool,
+    scopes: Vec<HashSet<&'a str>>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(globals: &'a HashSet<&'a str>, pure: &'a HashSet<Symbol>, allow_divergence: bool) -> Self {
+        Checker {
+            globals,
+            pure,
+            allow_divergence,
+            scopes: Vec::new(),
+        }
+    }
+
+    fn function(mut self, function: &'a Function) -> Result<(), Impurity> {
+        let params = function.args.iter().map(|arg| arg.name.as_str()).collect();
+        self.scopes.push(params);
+        self.block(&function.body)
+    }
+
+    fn block(&mut self, block: &'a Block) -> Result<(), Impurity> {
+        self.scopes.push(HashSet::new());
+        let result = block
+            .statements
+            .iter()
+            .try_for_each(|statement| self.statement(statement));
+        self.scopes.pop();
+        result
+    }
+
+    fn expression(&self, expression: &Expression) -> Result<(), Impurity> {
+        expression_effect(expression, self.pure)
+    }
+
+    fn statement(&mut self, statement: &'a Statement) -> Result<(), Impurity> {
+        match statement {
+            Statement::Assignment {
+                left, typ, right, ..
+            } => {
+                if let Some(right) = right {
+                    self.expression(right)?;
+                }
+                if typ.is_some() {
+                    // A declaration, which shadows any global of the same name
+                    self.scopes.last_mut().unwrap().insert(left);
+                    return Ok(());
+                }
+                let is_local = self
+                    .scopes
+                    .iter()
+                    .any(|scope| scope.contains(left.as_str()));
+                if !is_local && self.globals.contains(left.as_str()) {
+                    return Err(Impurity::WritesGlobal(left.clone()));
+                }
+                Ok(())
+            }
+            Statement::If {
+                condition,
+                then,
+                els,
+                ..
+            } => {
+                self.expression(condition)?;
+                self.block(then)?;
+                match els {
+                    Some(els) => self.block(els),
+                    None => Ok(()),
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                if !self.allow_divergence {
+                    return Err(Impurity::Loop);
+                }
+                self.expression(condition)?;
+                self.block(body)
+            }
+            Statement::Block { block, .. } => self.block(block),
+            Statement::Match {
+                scrutinee, arms, ..
+            } => {
+                self.expression(scrutinee)?;
+                arms.iter().try_for_each(|arm| self.block(&arm.body))
+            }
+            Statement::Return { expression, .. } => match expression {
+                Some(expression) => self.expression(expression),
+                None => Ok(()),
+            },
+            Statement::Expression { expression, .. } => self.expression(expression),
+            Statement::Assert { condition, .. } => {
+                if !self.allow_divergence {
+                    return Err(Impurity::Assert);
+                }
+                self.expression(condition)
+            }
+            // Nested definitions are not called from here
+            Statement::FunctionDefinition { .. }
+            | Statement::Newtype { .. }
+            | Statement::Enum { .. }
+            | Statement::Error { .. } => Ok(()),
+        }
+    }
+}
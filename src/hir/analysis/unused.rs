@@ -0,0 +1,175 @@
+use crate::ast::{Block, Expression, Program, Statement};
+use crate::hir::analysis::purity::PurityAnalysis;
+use crate::span::Span;
+use crate::types::Function;
+
+/// Local variables that are declared but never read or assigned again.
+///
+/// Parameters are not reported, since removing them changes the signature
+/// of the function, and neither are variables whose name starts with an
+/// underscore.
+#[derive(Debug, Default)]
+pub struct UnusedVariables {
+    unused: Vec<UnusedVariable>,
+}
+
+/// A declaration nothing refers to
+#[derive(Debug, Clone)]
+pub struct UnusedVariable {
+    pub name: String,
+    /// Name of the source the declaration is in
+    pub source: String,
+    /// The whole declaration, including its initializer
+    pub span: Span,
+    /// True if the initializer has no effect, so the declaration can be
+    /// removed without changing what the program does
+    pub removable: bool,
+}
+
+impl UnusedVariables {
+    pub fn new(program: &Program, purity: &PurityAnalysis) -> Self {
+        let mut unused = Vec::new();
+        for function in &program.functions {
+            let mut finder = Finder {
+                function,
+                purity,
+                scopes: Vec::new(),
+                unused: &mut unused,
+            };
+            finder.block(&function.body);
+        }
+        UnusedVariables { unused }
+    }
+
+    /// The unused declarations, in source order within each function
+    pub fn unused(&self) -> &[UnusedVariable] {
+        &self.unused
+    }
+}
+
+/// A declaration in scope and whether anything has referred to it yet
+struct Declared {
+    variable: UnusedVariable,
+    used: bool,
+}
+
+/// Walks a function body, resolving every name to the innermost declaration
+struct Finder<'a> {
+    function: &'a Function,
+    purity: &'a PurityAnalysis,
+    scopes: Vec<Vec<Declared>>,
+    unused: &'a mut Vec<UnusedVariable>,
+}
+
+impl Finder<'_> {
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(Vec::new());
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        let scope = self.scopes.pop().unwrap();
+        self.unused.extend(
+            scope
+                .into_iter()
+                .filter(|declared| !declared.used && !declared.variable.name.starts_with('_'))
+                .map(|declared| declared.variable),
+        );
+    }
+
+    /// Marks the innermost declaration of `name` as used. Names declared
+    /// outside the function are parameters or globals.
+    fn use_name(&mut self, name: &str) {
+        let declared = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|declared| declared.variable.name == name));
+        if let Some(declared) = declared {
+            declared.used = true;
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Variable { name, .. } => self.use_name(name),
+            Expression::Number { .. }
+            | Expression::Boolean { .. }
+            | Expression::Variant { .. }
+            | Expression::Error { .. } => {}
+            Expression::UnaryOp { left, .. } => self.expression(left),
+            Expression::Cast { expression, .. } => self.expression(expression),
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Call { args, .. } => args.iter().for_each(|arg| self.expression(arg)),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment {
+                left,
+                typ,
+                right,
+                span,
+                ..
+            } => {
+                if let Some(right) = right {
+                    self.expression(right);
+                }
+                if typ.is_none() {
+                    self.use_name(left);
+                    return;
+                }
+                let removable = right.as_ref().is_none_or(|right| self.purity.is_pure(right));
+                self.scopes.last_mut().unwrap().push(Declared {
+                    variable: UnusedVariable {
+                        name: left.clone(),
+                        source: self.function.source.clone(),
+                        span: *span,
+                        removable,
+                    },
+                    used: false,
+                });
+            }
+            Statement::If {
+                condition,
+                then,
+                els,
+                ..
+            } => {
+                self.expression(condition);
+                self.block(then);
+                if let Some(els) = els {
+                    self.block(els);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.block(body);
+            }
+            Statement::Block { block, .. } => self.block(block),
+            Statement::Match {
+                scrutinee, arms, ..
+            } => {
+                self.expression(scrutinee);
+                arms.iter().for_each(|arm| self.block(&arm.body));
+            }
+            Statement::Return { expression, .. } => {
+                if let Some(expression) = expression {
+                    self.expression(expression);
+                }
+            }
+            Statement::Expression { expression, .. } => self.expression(expression),
+            Statement::Assert { condition, .. } => self.expression(condition),
+            // Nested definitions are checked on their own
+            Statement::FunctionDefinition { .. }
+            | Statement::Newtype { .. }
+            | Statement::Enum { .. }
+            | Statement::Error { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::ast::{Block, BranchHint, Expression, Program, Statement};
+use crate::frontend::TokenType;
+use crate::intern::Symbol;
+pub use crate::range::ValueRange;
+use crate::types::{BaseType, Function, Type};
+use std::collections::HashMap;
+
+/// Rounds of plain joins before variable ranges are widened to guarantee
+/// the fixpoint is reached
+const ROUNDS_BEFORE_WIDENING: usize = 2;
+
+impl ValueRange {
+    /// The range of a value of the given type that nothing else is known about
+    pub fn of_type(typ: &Type) -> Self {
+        match typ.underlying() {
+            Type::Base(BaseType::Bool) => Self::BOOLEAN,
+            _ => Self::UNKNOWN,
+        }
+    }
+}
+
+/// Flow-insensitive value ranges of the variables of a program.
+///
+/// Every local variable gets the join of all values ever assigned to it in
+/// its function, so the result holds at every point where the variable is
+/// read. Parameters and globals can hold anything their type allows, since
+/// callers and other functions may write them.
+///
+/// Arithmetic is evaluated in `f64`. Literals and results too large for
+/// the narrowest float type declared anywhere in the program are assumed
+/// to overflow, so the ranges stay valid whichever type an expression ends
+/// up with, and so are casts of values too large for their target.
+pub struct ValueRangeAnalysis {
+    limit: f64,
+    globals: HashMap<String, ValueRange>,
+    functions: HashMap<Symbol, ValueRange>,
+    locals: HashMap<String, ValueRange>,
+}
+
+impl ValueRangeAnalysis {
+    pub fn new(program: &Program) -> Self {
+        let globals = program
+            .globals
+            .iter()
+            .map(|global| (global.name.clone(), ValueRange::of_type(&global.typ)))
+            .collect();
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| {
+                (
+                    Symbol::intern(&function.name),
+                    ValueRange::of_type(&function.return_type),
+                )
+            })
+            .collect();
+        ValueRangeAnalysis {
+            limit: narrowest_float_max(program),
+            globals,
+            functions,
+            locals: HashMap::new(),
+        }
+    }
+
+    /// Computes the ranges of the locals of `function`, replacing those of
+    /// the previously analyzed function
+    pub fn analyze_function(&mut self, function: &Function) {
+        let mut definitions = Vec::new();
+        collect_definitions(&function.body, &mut definitions);
+
+        self.locals = function
+            .args
+            .iter()
+            .map(|arg| (arg.name.clone(), ValueRange::of_type(&arg.typ)))
+            .collect();
+        for (name, _) in &definitions {
+            // A local shadowing a global shares its name, so it may hold
+            // the global's values from this analysis' point of view
+            let start = self
+                .globals
+                .get(*name)
+                .copied()
+                .unwrap_or(ValueRange::EMPTY);
+            self.locals.entry(name.to_string()).or_insert(start);
+        }
+
+        let mut round = 0;
+        loop {
+            let mut changed = false;
+            for (name, value) in &definitions {
+                let value = value.map_or(ValueRange::UNKNOWN, |value| self.range(value));
+                let old = self.locals[*name];
+                let new = if round < ROUNDS_BEFORE_WIDENING {
+                    old.join(value)
+                } else {
+                    old.widen(value)
+                };
+                if new != old {
+                    self.locals.insert(name.to_string(), new);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            round += 1;
+        }
+    }
+
+    /// The values `expression` may evaluate to, given the variable ranges of
+    /// the last analyzed function
+    pub fn range(&self, expression: &Expression) -> ValueRange {
+        match expression {
+            Expression::Number { value, .. } => ValueRange::constant(*value).saturate(self.limit),
+            Expression::Boolean { .. } => ValueRange::BOOLEAN,
+            Expression::Variable { name, .. } => self
+                .locals
+                .get(name)
+                .or_else(|| self.globals.get(name))
+                .copied()
+                .unwrap_or(ValueRange::UNKNOWN),
+            Expression::Call { identifier, .. } if BranchHint::from_name(identifier.as_str()).is_some() => {
+                ValueRange::BOOLEAN
+            }
+            Expression::Call { identifier, .. } => self
+                .functions
+                .get(identifier)
+                .copied()
+                .unwrap_or(ValueRange::UNKNOWN),
+            Expression::Variant { .. } | Expression::Error { .. } => ValueRange::UNKNOWN,
+            // A value too large for the type it is cast to overflows
+            Expression::Cast { expression, target, .. } => {
+                self.range(expression).saturate(float_max(target).min(self.limit))
+            }
+            Expression::UnaryOp { left, op, .. } => match op.tag {
+                // Lowered to `0 - x`, which is never negative zero
+                TokenType::Minus => ValueRange::constant(0.0) - self.range(left),
+                TokenType::Plus => self.range(left),
+                _ => ValueRange::BOOLEAN,
+            },
+            Expression::BinaryOp {
+                left, op, right, ..
+            } => {
+                let (left, right) = (self.range(left), self.range(right));
+                let range = match op.tag {
+                    TokenType::Plus => left + right,
+                    TokenType::Minus => left - right,
+                    TokenType::Star => left * right,
+                    TokenType::Slash => left / right,
+                    TokenType::Percent => left % right,
+                    // Powers are not followed
+                    TokenType::StarStar => ValueRange::UNKNOWN,
+                    // Comparisons and logical operators
+                    _ => return ValueRange::BOOLEAN,
+                };
+                range.saturate(self.limit)
+            }
+        }
+    }
+}
+
+/// Largest finite value of the narrowest float type the program declares
+fn narrowest_float_max(program: &Program) -> f64 {
+    let mut types: Vec<&Type> = program.globals.iter().map(|global| &global.typ).collect();
+    for function in &program.functions {
+        types.push(&function.return_type);
+        types.extend(function.args.iter().map(|arg| &arg.typ));
+        collect_declared_types(&function.body, &mut types);
+    }
+    types.into_iter().map(float_max).fold(f64::MAX, f64::min)
+}
+
+/// Largest finite value of a float type, `f64::MAX` for other types
+fn float_max(typ: &Type) -> f64 {
+    match typ.underlying() {
+        // The 8-bit float is taken to be E4M3, the narrower common format
+        Type::Base(BaseType::F8) => 448.0,
+        Type::Base(BaseType::F16) => 65504.0,
+        Type::Base(BaseType::F32) => f32::MAX as f64,
+        _ => f64::MAX,
+    }
+}
+
+fn collect_declared_types<'a>(block: &'a Block, types: &mut Vec<&'a Type>) {
+    for statement in &block.statements {
+        match statement {
+            Statement::Assignment { typ: Some(typ), .. } => types.push(typ),
+            Statement::If { then, els, .. } => {
+                collect_declared_types(then, types);
+                if let Some(els) = els {
+                    collect_declared_types(els, types);
+                }
+            }
+            Statement::While { body, .. } => collect_declared_types(body, types),
+            Statement::Block { block, .. } => collect_declared_types(block, types),
+            Statement::Match { arms, .. } => {
+                for arm in arms {
+                    collect_declared_types(&arm.body, types);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Every assignment and declaration in a block, with the assigned value.
+/// Declarations without an initializer have no value.
+fn collect_definitions<'a>(
+    block: &'a Block,
+    definitions: &mut Vec<(&'a str, Option<&'a Expression>)>,
+) {
+    for statement in &block.statements {
+        match statement {
+            Statement::Assignment { left, right, .. } => {
+                definitions.push((left, right.as_deref()));
+            }
+            Statement::If { then, els, .. } => {
+                collect_definitions(then, definitions);
+                if let Some(els) = els {
+                    collect_definitions(els, definitions);
+                }
+            }
+            Statement::While { body, .. } => collect_definitions(body, definitions),
+            Statement::Block { block, .. } => collect_definitions(block, definitions),
+            Statement::Match { arms, .. } => {
+                for arm in arms {
+                    collect_definitions(&arm.body, definitions);
+                }
+            }
+            Statement::FunctionDefinition { .. }
+            | Statement::Return { .. }
+            | Statement::Expression { .. }
+            | Statement::Assert { .. }
+            | Statement::Newtype { .. }
+            | Statement::Enum { .. }
+            | Statement::Error { .. } => {}
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod analysis;
 pub mod passes;
+pub mod rewrite;
 pub mod visitor;
@@ -1,22 +1,236 @@
-use crate::ast::{Expression, Program, Statement};
+use crate::ast::{Block, Expression, NodeId, Program, Statement};
+use crate::diagnostics::{Diagnostic, Lint};
 use crate::frontend::{Token, TokenType};
+use crate::span::Span;
 use crate::types::Function;
+use crate::value::format_float;
+use crate::hir::analysis::purity::PurityAnalysis;
+use crate::hir::analysis::value_range::{ValueRange, ValueRangeAnalysis};
+use crate::hir::rewrite::{Guard, Pattern, Rule, Template};
 use crate::hir::visitor::{DiagnosticCollector, Visitor};
+use std::collections::HashSet;
+
+/// How many times the program is simplified at most before giving up on
+/// reaching a fixpoint
+pub const MAX_SIMPLIFICATION_ITERATIONS: usize = 8;
+
+const X: Pattern = Pattern::Any("x");
+const VAR_X: Pattern = Pattern::Variable("x");
+
+/// The algebraic identities, tried in order on every expression after its
+/// operands were simplified. The first rule that applies wins.
+///
+/// An identity that fails for some IEEE 754 values is guarded on the range
+/// of the operand: `x - x` is NaN for infinities, NaN compares unequal to
+/// itself, `-0 + 0` is `+0`, and `x * 0` is NaN for NaN and infinities and
+/// `-0` for negative `x`. A rule that drops an operand requires it to be
+/// pure. Commutative operations have their constant on the right already.
+const ALGEBRAIC_RULES: &[Rule] = &[
+    Rule {
+        description: "{x} - {x} -> 0",
+        pattern: Pattern::Binary(TokenType::Minus, &VAR_X, &VAR_X),
+        guards: &[Guard::Finite("x")],
+        result: Template::Number(0.0),
+    },
+    Rule {
+        description: "{x} == {x} -> true",
+        pattern: Pattern::Binary(TokenType::Equal, &VAR_X, &VAR_X),
+        guards: &[Guard::NotNan("x")],
+        result: Template::Boolean(true),
+    },
+    Rule {
+        description: "{x} != {x} -> false",
+        pattern: Pattern::Binary(TokenType::NotEqual, &VAR_X, &VAR_X),
+        guards: &[Guard::NotNan("x")],
+        result: Template::Boolean(false),
+    },
+    Rule {
+        description: "{x} < {x} -> false",
+        pattern: Pattern::Binary(TokenType::Less, &VAR_X, &VAR_X),
+        guards: &[],
+        result: Template::Boolean(false),
+    },
+    Rule {
+        description: "{x} > {x} -> false",
+        pattern: Pattern::Binary(TokenType::Greater, &VAR_X, &VAR_X),
+        guards: &[],
+        result: Template::Boolean(false),
+    },
+    Rule {
+        description: "{x} <= {x} -> true",
+        pattern: Pattern::Binary(TokenType::LessEqual, &VAR_X, &VAR_X),
+        guards: &[Guard::NotNan("x")],
+        result: Template::Boolean(true),
+    },
+    Rule {
+        description: "{x} >= {x} -> true",
+        pattern: Pattern::Binary(TokenType::GreaterEqual, &VAR_X, &VAR_X),
+        guards: &[Guard::NotNan("x")],
+        result: Template::Boolean(true),
+    },
+    Rule {
+        description: "expr + 0 -> expr",
+        pattern: Pattern::Binary(TokenType::Plus, &X, &Pattern::Number(0.0)),
+        guards: &[Guard::NoNegativeZero("x")],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr + 0 -> expr",
+        pattern: Pattern::Binary(TokenType::Plus, &X, &Pattern::Number(-0.0)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr - 0 -> expr",
+        pattern: Pattern::Binary(TokenType::Minus, &X, &Pattern::Number(0.0)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr - 0 -> expr",
+        pattern: Pattern::Binary(TokenType::Minus, &X, &Pattern::Number(-0.0)),
+        guards: &[Guard::NoNegativeZero("x")],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr * 1 -> expr",
+        pattern: Pattern::Binary(TokenType::Star, &X, &Pattern::Number(1.0)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    // A non-negative x times a signed zero is that same zero
+    Rule {
+        description: "expr * 0 -> 0",
+        pattern: Pattern::Binary(TokenType::Star, &X, &Pattern::Number(0.0)),
+        guards: &[Guard::Finite("x"), Guard::NonNegative("x"), Guard::Pure("x")],
+        result: Template::Number(0.0),
+    },
+    Rule {
+        description: "expr * 0 -> 0",
+        pattern: Pattern::Binary(TokenType::Star, &X, &Pattern::Number(-0.0)),
+        guards: &[Guard::Finite("x"), Guard::NonNegative("x"), Guard::Pure("x")],
+        result: Template::Number(-0.0),
+    },
+    Rule {
+        description: "expr / 1 -> expr",
+        pattern: Pattern::Binary(TokenType::Slash, &X, &Pattern::Number(1.0)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr && true -> expr",
+        pattern: Pattern::Binary(TokenType::And, &X, &Pattern::Boolean(true)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "expr && false -> false",
+        pattern: Pattern::Binary(TokenType::And, &X, &Pattern::Boolean(false)),
+        guards: &[Guard::Pure("x")],
+        result: Template::Boolean(false),
+    },
+    Rule {
+        description: "expr || true -> true",
+        pattern: Pattern::Binary(TokenType::Or, &X, &Pattern::Boolean(true)),
+        guards: &[Guard::Pure("x")],
+        result: Template::Boolean(true),
+    },
+    Rule {
+        description: "expr || false -> expr",
+        pattern: Pattern::Binary(TokenType::Or, &X, &Pattern::Boolean(false)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    // Constants stay on the left of `&&` and `||` when the right operand has side effects
+    Rule {
+        description: "false && expr -> false",
+        pattern: Pattern::Binary(TokenType::And, &Pattern::Boolean(false), &X),
+        guards: &[],
+        result: Template::Boolean(false),
+    },
+    Rule {
+        description: "true && expr -> expr",
+        pattern: Pattern::Binary(TokenType::And, &Pattern::Boolean(true), &X),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "true || expr -> true",
+        pattern: Pattern::Binary(TokenType::Or, &Pattern::Boolean(true), &X),
+        guards: &[],
+        result: Template::Boolean(true),
+    },
+    Rule {
+        description: "false || expr -> expr",
+        pattern: Pattern::Binary(TokenType::Or, &Pattern::Boolean(false), &X),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+    Rule {
+        description: "!!expr -> expr",
+        pattern: Pattern::Unary(TokenType::Bang, &Pattern::Unary(TokenType::Bang, &X)),
+        guards: &[],
+        result: Template::Bound("x"),
+    },
+];
 
 /// Visitor that performs AST simplification (constant folding, boolean folding, algebraic simplification)
+///
+/// The algebraic simplifications are the rules of [`ALGEBRAIC_RULES`].
+/// Identities that do not hold for every IEEE 754 value, such as `x - x -> 0`
+/// (NaN, infinities) or `x * 0 -> 0` (also negative numbers, whose product
+/// is negative zero), are only applied when value range analysis proves the
+/// operand is safe, or unconditionally with fast math enabled.
+///
+/// Rewrites that drop an operand, like `x * 0 -> 0`, only apply when the
+/// operand is free of side effects, so calls to impure functions are kept.
+///
+/// An `if` whose condition folds to a constant is replaced by the block
+/// that runs, or removed if none does, and so is a `while` whose condition
+/// is false. The program is typechecked before it is simplified, so errors
+/// in the code removed are reported all the same.
+///
+/// The program is simplified again as long as the last run changed it,
+/// with value ranges computed afresh, since a folded variable may make
+/// a rule's guard hold where it is read. The runs stop at a fixpoint or
+/// after [`MAX_SIMPLIFICATION_ITERATIONS`].
 pub struct ASTSimplificationPass {
     diagnostics: DiagnosticCollector,
     folded_nodes_count: u64,
+    iterations: usize,
+    /// Divisions by zero already warned about, which later runs find again
+    warned: HashSet<Span>,
+    fast_math: bool,
+    ranges: Option<ValueRangeAnalysis>,
+    purity: PurityAnalysis,
+    /// Name of the source the function being simplified was defined in
+    current_source: Option<String>,
+    /// The id of the next node a rewrite adds
+    next_node_id: NodeId,
 }
 
 impl ASTSimplificationPass {
-    pub fn new() -> Self {
+    pub fn new(fast_math: bool) -> Self {
         ASTSimplificationPass {
             diagnostics: DiagnosticCollector::new(),
             folded_nodes_count: 0,
+            iterations: 0,
+            warned: HashSet::new(),
+            fast_math,
+            ranges: None,
+            purity: PurityAnalysis::default(),
+            current_source: None,
+            next_node_id: NodeId(0),
         }
     }
 
+    /// Values an expression may take, as far as the analysis can tell
+    fn range(&self, expression: &Expression) -> ValueRange {
+        self.ranges
+            .as_ref()
+            .map_or(ValueRange::UNKNOWN, |ranges| ranges.range(expression))
+    }
+
     fn eval_binop(&mut self, left: f64, right: f64, op: &Token) -> Option<f64> {
         use TokenType;
 
@@ -26,10 +240,17 @@ impl ASTSimplificationPass {
             TokenType::Star => Some(left * right),
             TokenType::Slash => {
                 if right == 0.0 {
-                    self.diagnostics.warn(format!(
-                        "Division by zero: {} / {} at line {}, column {}",
-                        left, right, op.row, op.column
-                    ));
+                    if !self.warned.insert(Span::from_token(op)) {
+                        return None;
+                    }
+                    self.diagnostics.report_warning(
+                        Diagnostic::new(format!(
+                            "Division by zero: {} / {} at {}",
+                            format_float(left), format_float(right), Span::from_token(op)
+                        ))
+                        .with_location(self.current_source.clone(), Span::from_token(op))
+                        .with_lint(Lint::DivisionByZero),
+                    );
                     None // Can't fold division by zero
                 } else {
                     Some(left / right)
@@ -37,15 +258,23 @@ impl ASTSimplificationPass {
             }
             TokenType::Percent => {
                 if right == 0.0 {
-                    self.diagnostics.warn(format!(
-                        "Modulo by zero: {} % {} at line {}, column {}",
-                        left, right, op.row, op.column
-                    ));
+                    if !self.warned.insert(Span::from_token(op)) {
+                        return None;
+                    }
+                    self.diagnostics.report_warning(
+                        Diagnostic::new(format!(
+                            "Modulo by zero: {} % {} at {}",
+                            format_float(left), format_float(right), Span::from_token(op)
+                        ))
+                        .with_location(self.current_source.clone(), Span::from_token(op))
+                        .with_lint(Lint::DivisionByZero),
+                    );
                     None
                 } else {
                     Some(left % right)
                 }
             }
+            TokenType::StarStar => Some(left.powf(right)),
             _ => None, // Not a constant-foldable operation (comparisons, logic, etc)
         }
     }
@@ -54,7 +283,8 @@ impl ASTSimplificationPass {
         use TokenType;
 
         match op.tag {
-            TokenType::Minus => Some(-operand),
+            // Negation is lowered to `0 - x`, so `-0` is positive zero
+            TokenType::Minus => Some(0.0 - operand),
             TokenType::Plus => Some(operand),
             _ => None, // Not a constant-foldable operation (!, etc)
         }
@@ -106,14 +336,9 @@ impl ASTSimplificationPass {
     }
 
     fn try_algebraic_simplify(&mut self, expression: &mut Expression) {
-        // Save type before pattern matching (to avoid borrow issues)
-        let saved_typ = expression.typ().clone();
-
-        if let Expression::BinaryOp { left, op, right, span, .. } = expression {
-            use TokenType;
-
-            // Normalize commutative operations: put constants on the right
-            // This reduces pattern matching cases by half
+        // Normalize commutative operations: put constants on the right
+        // This reduces the rules needed by half
+        if let Expression::BinaryOp { left, op, right, .. } = expression {
             let is_commutative = matches!(
                 op.tag,
                 TokenType::Plus | TokenType::Star | TokenType::And | TokenType::Or | TokenType::Equal | TokenType::NotEqual
@@ -123,189 +348,94 @@ impl ASTSimplificationPass {
                 let left_is_const = matches!(left.as_ref(), Expression::Number { .. } | Expression::Boolean { .. });
                 let right_is_const = matches!(right.as_ref(), Expression::Number { .. } | Expression::Boolean { .. });
 
+                // `&&` and `||` skip their right operand, so swapping one
+                // with side effects to the left would make it run
+                let short_circuits = matches!(op.tag, TokenType::And | TokenType::Or);
+
                 // If left is constant but right isn't, swap them
-                if left_is_const && !right_is_const {
+                if left_is_const && !right_is_const && (!short_circuits || self.purity.is_pure(right)) {
                     std::mem::swap(left, right);
                 }
             }
+        }
 
-            // Check for variable identities (x op x)
-            if let (Expression::Variable { name: a, .. }, Expression::Variable { name: b, .. }) =
-                (left.as_ref(), right.as_ref())
-            {
-                if a == b {
-                    let expr_span = *span;
-                    let expr_typ = saved_typ.clone();
-                    match op.tag {
-                        TokenType::Minus => {
-                            self.diagnostics.info(format!(
-                                "Algebraic simplification: {} - {} -> 0 at line {}, column {}",
-                                a, a, op.row, op.column
-                            ));
-                            *expression = Expression::Number { value: 0.0, span: expr_span, typ: expr_typ };
-                            self.folded_nodes_count += 1;
-                            return;
-                        }
-                        TokenType::Equal => {
-                            self.diagnostics.info(format!(
-                                "Algebraic simplification: {} == {} -> true at line {}, column {}",
-                                a, a, op.row, op.column
-                            ));
-                            *expression = Expression::Boolean { value: true, span: expr_span, typ: expr_typ };
-                            self.folded_nodes_count += 1;
-                            return;
-                        }
-                        TokenType::NotEqual => {
-                            self.diagnostics.info(format!(
-                                "Algebraic simplification: {} != {} -> false at line {}, column {}",
-                                a, a, op.row, op.column
-                            ));
-                            *expression = Expression::Boolean { value: false, span: expr_span, typ: expr_typ };
-                            self.folded_nodes_count += 1;
-                            return;
-                        }
-                        TokenType::Less | TokenType::Greater => {
-                            self.diagnostics.info(format!(
-                                "Algebraic simplification: {} {} {} -> false at line {}, column {}",
-                                a, op.lexeme, a, op.row, op.column
-                            ));
-                            *expression = Expression::Boolean { value: false, span: expr_span, typ: expr_typ };
-                            self.folded_nodes_count += 1;
-                            return;
-                        }
-                        TokenType::LessEqual | TokenType::GreaterEqual => {
-                            self.diagnostics.info(format!(
-                                "Algebraic simplification: {} {} {} -> true at line {}, column {}",
-                                a, op.lexeme, a, op.row, op.column
-                            ));
-                            *expression = Expression::Boolean { value: true, span: expr_span, typ: expr_typ };
-                            self.folded_nodes_count += 1;
-                            return;
-                        }
-                        _ => {}
-                    }
-                }
+        let span = match &*expression {
+            Expression::BinaryOp { op, .. } | Expression::UnaryOp { op, .. } => Span::from_token(op),
+            _ => return,
+        };
+        let mut next_node_id = self.next_node_id;
+        for rule in ALGEBRAIC_RULES {
+            let rewrite = rule.apply(expression, &mut next_node_id, |guard, operand| self.holds(guard, operand));
+            self.next_node_id = next_node_id;
+            if let Some(rewrite) = rewrite {
+                self.diagnostics.info(format!(
+                    "Algebraic simplification: {} at {}",
+                    rewrite.description, span
+                ));
+                *expression = rewrite.expression;
+                self.folded_nodes_count += 1;
+                return;
             }
+        }
+    }
 
-            // Number identity simplifications
-            // (After normalization, constants are always on the right for commutative ops)
-            let expr_span = *span;
-            let expr_typ = saved_typ.clone();
-            match (left.as_ref(), &op.tag, right.as_ref()) {
-                // x + 0 -> x
-                (_, TokenType::Plus, Expression::Number { value: n, .. }) if *n == 0.0 => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr + 0 -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-                // x - 0 -> x
-                (_, TokenType::Minus, Expression::Number { value: n, .. }) if *n == 0.0 => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr - 0 -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-                // x * 1 -> x
-                (_, TokenType::Star, Expression::Number { value: n, .. }) if *n == 1.0 => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr * 1 -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-                // x * 0 -> 0
-                (_, TokenType::Star, Expression::Number { value: n, .. }) if *n == 0.0 => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr * 0 -> 0 at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = Expression::Number { value: 0.0, span: expr_span, typ: expr_typ };
-                    self.folded_nodes_count += 1;
-                }
-                // x / 1 -> x
-                (_, TokenType::Slash, Expression::Number { value: n, .. }) if *n == 1.0 => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr / 1 -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-
-                // Boolean identity simplifications
-                // x && true -> x
-                (_, TokenType::And, Expression::Boolean { value: b, .. }) if *b => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr && true -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-                // x && false -> false
-                (_, TokenType::And, Expression::Boolean { value: b, .. }) if !*b => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr && false -> false at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = Expression::Boolean { value: false, span: expr_span, typ: expr_typ };
-                    self.folded_nodes_count += 1;
-                }
-                // x || true -> true
-                (_, TokenType::Or, Expression::Boolean { value: b, .. }) if *b => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr || true -> true at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = Expression::Boolean { value: true, span: expr_span, typ: expr_typ };
-                    self.folded_nodes_count += 1;
-                }
-                // x || false -> x
-                (_, TokenType::Or, Expression::Boolean { value: b, .. }) if !*b => {
-                    self.diagnostics.info(format!(
-                        "Algebraic simplification: expr || false -> expr at line {}, column {}",
-                        op.row, op.column
-                    ));
-                    *expression = (**left).clone();
-                    self.folded_nodes_count += 1;
-                }
-
-                _ => {}
-            }
+    /// Whether a guard of a rule holds for the expression it is on
+    fn holds(&self, guard: Guard, operand: &Expression) -> bool {
+        let range = self.range(operand);
+        match guard {
+            Guard::Finite(_) => self.fast_math || range.is_finite(),
+            Guard::NotNan(_) => self.fast_math || !range.nan,
+            Guard::NoNegativeZero(_) => self.fast_math || !range.negative_zero,
+            Guard::NonNegative(_) => self.fast_math || range.is_non_negative(),
+            Guard::Pure(_) => self.purity.is_pure(operand),
         }
+    }
 
-        // Handle double negation: !!x -> x
-        if let Expression::UnaryOp { left, op, .. } = expression {
-            use TokenType;
-            if op.tag == TokenType::Bang {
-                if let Expression::UnaryOp {
-                    left: inner_left,
-                    op: inner_op,
-                    ..
-                } = left.as_ref()
-                {
-                    if inner_op.tag == TokenType::Bang {
-                        self.diagnostics.info(format!(
-                            "Algebraic simplification: !!expr -> expr at line {}, column {}",
-                            op.row, op.column
-                        ));
-                        *expression = (**inner_left).clone();
-                        self.folded_nodes_count += 1;
-                    }
-                }
+    /// Replaces an `if` or `while` with a constant condition by the block
+    /// that runs, or by nothing
+    fn fold_statement(&mut self, statement: Statement) -> Option<Statement> {
+        let constant = match &statement {
+            Statement::If { condition, .. } | Statement::While { condition, .. } => match **condition {
+                Expression::Boolean { value, .. } => Some(value),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(value) = constant else {
+            return Some(statement);
+        };
+        let span = statement.span();
+        let (kept, description) = match statement {
+            Statement::If { then, els, id, .. } => {
+                let description = match (value, &els) {
+                    (true, _) => "kept the then block",
+                    (false, Some(_)) => "kept the else block",
+                    (false, None) => "removed it",
+                };
+                let kept = if value { Some(then) } else { els };
+                (kept.map(|block| (block, id)), format!("if {} at {}: {}", value, span, description))
             }
-        }
+            // A do-while runs its body once before testing the condition
+            Statement::While { body, do_while, id, .. } if !value => {
+                let kept = do_while.then_some((body, id));
+                let description = if do_while { "kept the body" } else { "removed it" };
+                (kept, format!("while false at {}: {}", span, description))
+            }
+            statement => return Some(statement),
+        };
+        self.diagnostics.info(format!("Folded {}", description));
+        self.folded_nodes_count += 1;
+        kept.map(|(block, id)| Statement::Block {
+            span: block.span,
+            block,
+            id,
+        })
     }
 
     fn try_constant_fold(&mut self, expression: &mut Expression) {
-        // Save type before pattern matching (to avoid borrow issues)
+        // Save type and id before pattern matching (to avoid borrow issues)
         let saved_typ = expression.typ().clone();
+        let saved_id = expression.id();
 
         match expression {
             Expression::BinaryOp { left, op, right, span, .. } => {
@@ -315,13 +445,14 @@ impl ASTSimplificationPass {
                 match (left.as_ref(), right.as_ref()) {
                     // Both are numbers
                     (Expression::Number { value: a, .. }, Expression::Number { value: b, .. }) => {
-                        // Try arithmetic operations first
-                        if let Some(result) = self.eval_binop(*a, *b, op) {
+                        // Try arithmetic operations first. A NaN result is not
+                        // folded, so `--checks` still traps where it is made
+                        if let Some(result) = self.eval_binop(*a, *b, op).filter(|result| !result.is_nan()) {
                             self.diagnostics.info(format!(
                                 "Const folded {} {} {} to {}",
                                 a, op.lexeme, b, result
                             ));
-                            *expression = Expression::Number { value: result, span: expr_span, typ: expr_typ };
+                            *expression = Expression::Number { value: result, span: expr_span, typ: expr_typ, id: saved_id };
                             self.folded_nodes_count += 1;
                         }
                         // Try comparison operations (returns bool)
@@ -330,7 +461,7 @@ impl ASTSimplificationPass {
                                 "Const folded {} {} {} to {}",
                                 a, op.lexeme, b, result
                             ));
-                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ };
+                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ, id: saved_id };
                             self.folded_nodes_count += 1;
                         }
                     }
@@ -342,7 +473,7 @@ impl ASTSimplificationPass {
                                 "Const folded {} {} {} to {}",
                                 a, op.lexeme, b, result
                             ));
-                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ };
+                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ, id: saved_id };
                             self.folded_nodes_count += 1;
                         }
                     }
@@ -360,7 +491,7 @@ impl ASTSimplificationPass {
                                 "Const folded unary {}{} to {}",
                                 op.lexeme, n, result
                             ));
-                            *expression = Expression::Number { value: result, span: expr_span, typ: expr_typ };
+                            *expression = Expression::Number { value: result, span: expr_span, typ: expr_typ, id: saved_id };
                             self.folded_nodes_count += 1;
                         }
                     }
@@ -370,7 +501,7 @@ impl ASTSimplificationPass {
                                 "Const folded unary {}{} to {}",
                                 op.lexeme, b, result
                             ));
-                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ };
+                            *expression = Expression::Boolean { value: result, span: expr_span, typ: expr_typ, id: saved_id };
                             self.folded_nodes_count += 1;
                         }
                     }
@@ -394,15 +525,51 @@ impl Visitor for ASTSimplificationPass {
     }
 
     fn visit_program(&mut self, program: &mut Program) {
-        self.walk_program(program);
+        self.purity = PurityAnalysis::new(program);
+        self.next_node_id = program.next_node_id;
+        let fixpoint = loop {
+            let folded = self.folded_nodes_count;
+            self.ranges = Some(ValueRangeAnalysis::new(program));
+            self.walk_program(program);
+            self.iterations += 1;
+            if self.folded_nodes_count == folded {
+                break true;
+            }
+            if self.iterations == MAX_SIMPLIFICATION_ITERATIONS {
+                break false;
+            }
+        };
+        program.next_node_id = self.next_node_id;
         self.diagnostics
             .info(format!("Constant folded {} nodes", self.folded_nodes_count));
+        if fixpoint {
+            self.diagnostics
+                .info(format!("Simplification reached a fixpoint after {} iterations", self.iterations));
+        } else {
+            self.diagnostics.info(format!(
+                "Simplification stopped after {} iterations without reaching a fixpoint",
+                self.iterations
+            ));
+        }
     }
 
     fn visit_function(&mut self, function: &mut Function) {
+        if let Some(ranges) = &mut self.ranges {
+            ranges.analyze_function(function);
+        }
+        self.current_source = Some(function.source.clone());
         self.walk_function(function);
     }
 
+    fn visit_block(&mut self, block: &mut Block) {
+        self.walk_block(block);
+        for statement in std::mem::take(&mut block.statements) {
+            if let Some(statement) = self.fold_statement(statement) {
+                block.statements.push(statement);
+            }
+        }
+    }
+
     fn visit_statement(&mut self, statement: &mut Statement) {
         self.walk_statement(statement);
     }
@@ -1,6 +1,6 @@
 use crate::ast::{Expression, Program, Statement};
 use crate::types::{Function, Variable};
-use crate::hir::visitor::{DiagnosticCollector, Visitor};
+use crate::hir::visitor::{DiagnosticCollector, VisitorRef};
 
 /// Example visitor that counts different types of nodes in the AST
 pub struct CountingPass {
@@ -23,7 +23,13 @@ impl CountingPass {
     }
 }
 
-impl Visitor for CountingPass {
+impl Default for CountingPass {
+    fn default() -> Self {
+        CountingPass::new()
+    }
+}
+
+impl VisitorRef for CountingPass {
     type Output = ();
 
     fn diagnostics(&self) -> &DiagnosticCollector {
@@ -34,7 +40,7 @@ impl Visitor for CountingPass {
         &mut self.diagnostics
     }
 
-    fn visit_program(&mut self, program: &mut Program) -> () {
+    fn visit_program(&mut self, program: &Program) {
         // Walk the AST to count everything
         self.walk_program(program);
 
@@ -49,22 +55,22 @@ impl Visitor for CountingPass {
             .info(format!("Variables: {}", self.num_variables));
     }
 
-    fn visit_function(&mut self, function: &mut Function) -> () {
+    fn visit_function(&mut self, function: &Function) {
         self.num_functions += 1;
         self.walk_function(function);
     }
 
-    fn visit_statement(&mut self, statement: &mut Statement) -> () {
+    fn visit_statement(&mut self, statement: &Statement) {
         self.num_statements += 1;
         self.walk_statement(statement);
     }
 
-    fn visit_expression(&mut self, expression: &mut Expression) -> () {
+    fn visit_expression(&mut self, expression: &Expression) {
         self.num_expressions += 1;
         self.walk_expression(expression);
     }
 
-    fn visit_variable(&mut self, variable: &mut Variable) -> () {
+    fn visit_variable(&mut self, variable: &Variable) {
         self.num_variables += 1;
         self.walk_variable(variable);
     }
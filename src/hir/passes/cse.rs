@@ -0,0 +1,358 @@
+use crate::ast::{Block, BranchHint, Expression, NodeId, Program, Statement};
+use crate::frontend::TokenType;
+use crate::hir::analysis::purity::PurityAnalysis;
+use crate::hir::visitor::{DiagnosticCollector, Visitor};
+use crate::types::{BaseType, Function, Type};
+use std::collections::{HashMap, HashSet};
+
+/// Hoists a pure subexpression that a statement computes more than once
+/// into a temporary declared just before the statement
+///
+/// `return a * b + a * b` becomes `var cse_0 = a * b` and `return cse_0 +
+/// cse_0`. Copies are recognized by [`structural_eq`](crate::ast::structural_eq),
+/// wherever they were written, and the largest repeated expression is
+/// hoisted first, so `(a * b) * (a * b)` repeated twice needs one
+/// temporary for the product of products and one for `a * b` inside it.
+///
+/// Only the expression a statement evaluates before anything else is
+/// searched: the value of an assignment or return, the condition of an `if`
+/// or `assert` and the scrutinee of a `match`, but not the condition of a
+/// `while`, which runs again on every iteration. A repeated expression must
+/// be evaluated at least once outside the right operand of `&&` and `||`,
+/// so the temporary never computes something the statement might have
+/// skipped. Statements that call impure functions are left alone, as the
+/// temporary would move a trap in the expression ahead of their effects.
+///
+/// The type of a temporary is inferred from its initializer, so an
+/// expression is only hoisted if it reads a variable or calls a function. A
+/// literal alone takes its type from where it is used.
+pub struct HirCsePass {
+    diagnostics: DiagnosticCollector,
+    purity: PurityAnalysis,
+    /// Names of globals, which temporaries must not shadow
+    globals: HashSet<String>,
+    /// Names the function being visited uses, including its temporaries
+    taken: HashSet<String>,
+    next_temporary: usize,
+    hoisted: usize,
+    /// The id of the next node a hoist adds
+    next_node_id: NodeId,
+}
+
+/// How often an expression occurs in a statement
+#[derive(Default)]
+struct Occurrences {
+    count: usize,
+    /// Evaluated whenever the statement is, not only past `&&` or `||`
+    unconditional: bool,
+}
+
+impl HirCsePass {
+    pub fn new() -> Self {
+        HirCsePass {
+            diagnostics: DiagnosticCollector::new(),
+            purity: PurityAnalysis::default(),
+            globals: HashSet::new(),
+            taken: HashSet::new(),
+            next_temporary: 0,
+            hoisted: 0,
+            next_node_id: NodeId(0),
+        }
+    }
+
+    /// Moves the statement to `out`, preceded by the temporaries it needs
+    fn hoist(&mut self, mut statement: Statement, out: &mut Vec<Statement>) {
+        while let Some(temporary) = self.hoist_one(&mut statement) {
+            // The initializer may repeat a smaller expression itself
+            self.hoist(temporary, out);
+        }
+        out.push(statement);
+    }
+
+    /// Replaces the largest repeated expression of the statement with a
+    /// temporary, returning its declaration
+    fn hoist_one(&mut self, statement: &mut Statement) -> Option<Statement> {
+        let expression = evaluated(statement)?;
+        if !self.purity.is_pure(expression) || contains_error(expression) {
+            return None;
+        }
+        let repeated = repeated(expression)?;
+        let name = self.fresh_name();
+        let mut first = None;
+        let uses = replace(expression, &repeated, &name, &mut first, &mut self.next_node_id);
+        let first = first?;
+
+        self.diagnostics.info(format!(
+            "Hoisted repeated expression at {} into '{}' ({} uses)",
+            first.span(), name, uses
+        ));
+        self.hoisted += 1;
+        Some(Statement::Assignment {
+            left: name,
+            typ: Some(Type::Base(BaseType::Auto)),
+            span: first.span(),
+            id: self.next_node_id.take(),
+            right: Some(Box::new(first)),
+        })
+    }
+
+    fn fresh_name(&mut self) -> String {
+        loop {
+            let name = format!("cse_{}", self.next_temporary);
+            self.next_temporary += 1;
+            if !self.globals.contains(&name) && self.taken.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+impl Default for HirCsePass {
+    fn default() -> Self {
+        HirCsePass::new()
+    }
+}
+
+impl Visitor for HirCsePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut Program) {
+        self.purity = PurityAnalysis::new(program);
+        self.globals = program.globals.iter().map(|global| global.name.clone()).collect();
+        self.next_node_id = program.next_node_id;
+        self.walk_program(program);
+        program.next_node_id = self.next_node_id;
+        self.diagnostics
+            .info(format!("Hoisted {} repeated expressions", self.hoisted));
+    }
+
+    fn visit_function(&mut self, function: &mut Function) {
+        self.taken = function.args.iter().map(|arg| arg.name.clone()).collect();
+        block_names(&function.body, &mut self.taken);
+        self.next_temporary = 0;
+        self.walk_function(function);
+    }
+
+    fn visit_block(&mut self, block: &mut Block) {
+        self.walk_block(block);
+        for statement in std::mem::take(&mut block.statements) {
+            self.hoist(statement, &mut block.statements);
+        }
+    }
+}
+
+/// The expression a statement evaluates before anything else, if the
+/// statement evaluates it only once
+fn evaluated(statement: &mut Statement) -> Option<&mut Expression> {
+    match statement {
+        Statement::Assignment { right, .. } => right.as_deref_mut(),
+        Statement::Return { expression, .. } => expression.as_deref_mut(),
+        Statement::Expression { expression, .. } => Some(expression),
+        Statement::Assert { condition, .. } | Statement::If { condition, .. } => Some(condition),
+        Statement::Match { scrutinee, .. } => Some(scrutinee),
+        _ => None,
+    }
+}
+
+/// The largest expression worth a temporary that occurs more than once,
+/// the first one on ties
+fn repeated(expression: &Expression) -> Option<Expression> {
+    let mut occurrences = HashMap::new();
+    let mut order = Vec::new();
+    count(expression, false, &mut occurrences, &mut order);
+
+    let mut best: Option<(&Expression, usize)> = None;
+    for candidate in order {
+        let seen = &occurrences[candidate];
+        if seen.count < 2 || !seen.unconditional || !worth_hoisting(candidate) {
+            continue;
+        }
+        let size = size(candidate);
+        if best.is_none_or(|(_, best_size)| size > best_size) {
+            best = Some((candidate, size));
+        }
+    }
+    best.map(|(candidate, _)| candidate.clone())
+}
+
+fn count<'a>(
+    expression: &'a Expression,
+    conditional: bool,
+    occurrences: &mut HashMap<&'a Expression, Occurrences>,
+    order: &mut Vec<&'a Expression>,
+) {
+    let seen = occurrences.entry(expression).or_insert_with(|| {
+        order.push(expression);
+        Occurrences::default()
+    });
+    seen.count += 1;
+    seen.unconditional |= !conditional;
+
+    match expression {
+        Expression::BinaryOp { left, op, right, .. } => {
+            count(left, conditional, occurrences, order);
+            let short_circuits = matches!(op.tag, TokenType::And | TokenType::Or);
+            count(right, conditional || short_circuits, occurrences, order);
+        }
+        Expression::UnaryOp { left, .. } => count(left, conditional, occurrences, order),
+        Expression::Cast { expression, .. } => count(expression, conditional, occurrences, order),
+        Expression::Call { args, .. } => {
+            for arg in args {
+                count(arg, conditional, occurrences, order);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Computes something, reads a variable or calls a function, and does not
+/// contain a branch hint, which only means something where it was written
+fn worth_hoisting(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::BinaryOp { .. } | Expression::UnaryOp { .. } | Expression::Call { .. } | Expression::Cast { .. }
+    ) && has_fixed_type(expression)
+        && !has_branch_hint(expression)
+}
+
+fn has_fixed_type(expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable { .. } | Expression::Call { .. } | Expression::Variant { .. } => true,
+        Expression::BinaryOp { left, right, .. } => has_fixed_type(left) || has_fixed_type(right),
+        Expression::UnaryOp { left, .. } => has_fixed_type(left),
+        // The target is written out
+        Expression::Cast { .. } => true,
+        _ => false,
+    }
+}
+
+fn has_branch_hint(expression: &Expression) -> bool {
+    match expression {
+        Expression::Call { identifier, args, .. } => {
+            BranchHint::from_name(identifier.as_str()).is_some() || args.iter().any(has_branch_hint)
+        }
+        Expression::BinaryOp { left, right, .. } => has_branch_hint(left) || has_branch_hint(right),
+        Expression::UnaryOp { left, .. } => has_branch_hint(left),
+        Expression::Cast { expression, .. } => has_branch_hint(expression),
+        _ => false,
+    }
+}
+
+fn contains_error(expression: &Expression) -> bool {
+    match expression {
+        Expression::Error { .. } => true,
+        Expression::Call { args, .. } => args.iter().any(contains_error),
+        Expression::BinaryOp { left, right, .. } => contains_error(left) || contains_error(right),
+        Expression::UnaryOp { left, .. } => contains_error(left),
+        Expression::Cast { expression, .. } => contains_error(expression),
+        _ => false,
+    }
+}
+
+/// The number of nodes in the expression
+fn size(expression: &Expression) -> usize {
+    1 + match expression {
+        Expression::Call { args, .. } => args.iter().map(size).sum(),
+        Expression::BinaryOp { left, right, .. } => size(left) + size(right),
+        Expression::UnaryOp { left, .. } => size(left),
+        Expression::Cast { expression, .. } => size(expression),
+        _ => 0,
+    }
+}
+
+/// Replaces every copy of `target` with a read of `name`, keeping the first
+/// copy in `first`. Returns the number of copies replaced.
+fn replace(
+    expression: &mut Expression,
+    target: &Expression,
+    name: &str,
+    first: &mut Option<Expression>,
+    next_node_id: &mut NodeId,
+) -> usize {
+    if *expression == *target {
+        let variable = Expression::Variable {
+            name: name.to_string(),
+            span: expression.span(),
+            typ: None,
+            id: next_node_id.take(),
+        };
+        let copy = std::mem::replace(expression, variable);
+        first.get_or_insert(copy);
+        return 1;
+    }
+    match expression {
+        Expression::Call { args, .. } => {
+            args.iter_mut().map(|arg| replace(arg, target, name, first, next_node_id)).sum()
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            replace(left, target, name, first, next_node_id) + replace(right, target, name, first, next_node_id)
+        }
+        Expression::UnaryOp { left, .. } => replace(left, target, name, first, next_node_id),
+        Expression::Cast { expression, .. } => replace(expression, target, name, first, next_node_id),
+        _ => 0,
+    }
+}
+
+/// Collects the names declared or read anywhere in the block
+fn block_names(block: &Block, names: &mut HashSet<String>) {
+    for statement in &block.statements {
+        match statement {
+            Statement::Assignment { left, right, .. } => {
+                names.insert(left.clone());
+                if let Some(right) = right {
+                    expression_names(right, names);
+                }
+            }
+            Statement::FunctionDefinition { args, body, .. } => {
+                names.extend(args.iter().map(|arg| arg.name.clone()));
+                block_names(body, names);
+            }
+            Statement::If { condition, then, els, .. } => {
+                expression_names(condition, names);
+                block_names(then, names);
+                if let Some(els) = els {
+                    block_names(els, names);
+                }
+            }
+            Statement::While { condition, body, .. } => {
+                expression_names(condition, names);
+                block_names(body, names);
+            }
+            Statement::Block { block, .. } => block_names(block, names),
+            Statement::Match { scrutinee, arms, .. } => {
+                expression_names(scrutinee, names);
+                for arm in arms {
+                    block_names(&arm.body, names);
+                }
+            }
+            Statement::Return { expression: Some(expression), .. }
+            | Statement::Expression { expression, .. } => expression_names(expression, names),
+            Statement::Assert { condition, .. } => expression_names(condition, names),
+            _ => {}
+        }
+    }
+}
+
+fn expression_names(expression: &Expression, names: &mut HashSet<String>) {
+    match expression {
+        Expression::Variable { name, .. } => {
+            names.insert(name.clone());
+        }
+        Expression::Call { args, .. } => args.iter().for_each(|arg| expression_names(arg, names)),
+        Expression::BinaryOp { left, right, .. } => {
+            expression_names(left, names);
+            expression_names(right, names);
+        }
+        Expression::UnaryOp { left, .. } => expression_names(left, names),
+        Expression::Cast { expression, .. } => expression_names(expression, names),
+        _ => {}
+    }
+}
@@ -1,10 +1,15 @@
-use crate::ast::{Expression, Program, Statement};
+use crate::ast::{BranchHint, Expression, Pattern, Program, Statement};
+use crate::diagnostics::{Diagnostic, Lint};
 use crate::frontend::TokenType;
+use crate::intern::Symbol;
+use crate::hir::analysis::purity::PurityAnalysis;
 use crate::hir::visitor::{DiagnosticCollector, Visitor};
+use crate::mir::builder::Builder;
 use crate::mir::{
-    BasicBlock, BlockId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg,
-    Terminator,
+    BlockId, BranchWeights, ConstantPool, FunctionSignature, FunctionTable, InlineHint, Linkage, MirFunction, MirGlobal,
+    MirProgram, MirType, Opcode, Operand, Reg, TrapReason, INIT_FUNCTION,
 };
+use crate::span::Span;
 use crate::types::{BaseType, Function, Type};
 use std::collections::HashMap;
 
@@ -13,9 +18,19 @@ pub struct LoweringPass {
     diagnostics: DiagnosticCollector,
     functions: Vec<MirFunction>,
     scope_stack: Vec<HashMap<String, Reg>>,
-    register_cursor: usize,
+    globals: Vec<MirGlobal>,
+    /// Every function of the program, declared before any body is lowered
+    symbols: FunctionTable,
     current_function: Option<MirFunction>,
     current_block: Option<BlockId>,
+    purity: PurityAnalysis,
+    checked_division: bool,
+    /// Span of the construct being lowered, attached to everything emitted for it
+    current_span: Option<Span>,
+    /// Whether control can reach the statement being lowered. Cleared by a
+    /// return, or once every branch of an if or match has returned, after
+    /// which the remaining statements of the block are not lowered.
+    reachable: bool,
 }
 
 impl LoweringPass {
@@ -24,17 +39,34 @@ impl LoweringPass {
             diagnostics: DiagnosticCollector::new(),
             functions: Vec::new(),
             scope_stack: Vec::new(),
-            register_cursor: 0,
+            globals: Vec::new(),
+            symbols: FunctionTable::new(),
             current_function: None,
             current_block: None,
+            purity: PurityAnalysis::default(),
+            checked_division: true,
+            current_span: None,
+            reachable: true,
         }
     }
 
+    /// Whether integer division and remainder trap on a zero divisor. If
+    /// not, dividing by zero is undefined.
+    pub fn checked_division(mut self, enabled: bool) -> Self {
+        self.checked_division = enabled;
+        self
+    }
+
     /// Lower the HIR program to MIR and return the MIR functions
     pub fn lower(&mut self, program: &mut Program) -> MirProgram {
+        self.purity = PurityAnalysis::new(program);
         self.visit_program(program);
         MirProgram {
             functions: std::mem::take(&mut self.functions),
+            globals: std::mem::take(&mut self.globals),
+            coverage: Vec::new(),
+            constants: ConstantPool::new(),
+            symbols: std::mem::take(&mut self.symbols),
         }
     }
 
@@ -46,15 +78,22 @@ impl LoweringPass {
         self.scope_stack.pop();
     }
 
-    fn get_free_register(&mut self) -> Reg {
-        let reg = self.register_cursor;
-        self.register_cursor += 1;
-        reg
+    /// Builder appending to the current block of the current function
+    fn builder(&mut self) -> Builder<'_> {
+        let block_id = self.current_block.expect("No current block");
+        self.builder_at(block_id)
     }
 
-    /// Allocate a variable in the current scope
-    fn alloc_variable(&mut self, name: String) -> Reg {
-        let reg = self.get_free_register();
+    /// Builder appending to a specific block of the current function
+    fn builder_at(&mut self, block_id: BlockId) -> Builder<'_> {
+        let func = self.current_function.as_mut().expect("No current function");
+        Builder::new(func, block_id).with_span(self.current_span)
+    }
+
+    /// Allocate a variable in the current scope. Variables live in
+    /// registers, which SSA construction renames, never in memory.
+    fn alloc_variable(&mut self, name: String, typ: MirType) -> Reg {
+        let reg = self.builder().new_reg(typ);
         if let Some(scope) = self.scope_stack.last_mut() {
             scope.insert(name, reg);
         }
@@ -72,38 +111,51 @@ impl LoweringPass {
         None
     }
 
-    /// Allocate a new basic block in the current function
-    fn allocate_block(&mut self) -> BlockId {
-        let func = self.current_function.as_mut().expect("No current function");
-        func.arena.alloc(BasicBlock {
-            instructions: Vec::new(),
-            terminator: Terminator::Unreachable,
-            phi_nodes: Vec::new(),
-        })
+    /// Reports an error at the construct being lowered
+    fn report_error(&mut self, message: String) {
+        let error = match self.current_span {
+            Some(span) => {
+                let source = self.current_function.as_ref().and_then(|function| function.source.clone());
+                Diagnostic::new(format!("{} at {}", message, span)).with_location(source, span)
+            }
+            None => Diagnostic::new(message),
+        };
+        self.diagnostics.report_error(error);
     }
 
-    /// Add an instruction to the current basic block
-    fn add_instruction(&mut self, inst: Instruction) {
-        let block_id = self.current_block.expect("No current block");
-        self.add_instruction_to_block(block_id, inst);
+    /// Lookup a global by name, for names no local variable shadows
+    fn lookup_global(&self, name: &str) -> Option<MirGlobal> {
+        let name = Symbol::intern(name);
+        self.globals.iter().find(|global| global.name == name).cloned()
     }
 
-    /// Add an instruction to a specific basic block
-    fn add_instruction_to_block(&mut self, block_id: BlockId, inst: Instruction) {
-        let func = self.current_function.as_mut().expect("No current function");
-        func.block_mut(block_id).instructions.push(inst);
+    /// Allocate a new basic block in the current function
+    fn allocate_block(&mut self) -> BlockId {
+        self.builder().create_block()
     }
 
-    /// Set the terminator for the current basic block
-    fn set_terminator(&mut self, term: Terminator) {
-        let block_id = self.current_block.expect("No current block");
-        self.set_terminator_for_block(block_id, term);
+    /// Lowers the condition of a branch, with the weights `likely` or
+    /// `unlikely` around it ask for
+    fn lower_condition(&mut self, condition: &mut Expression) -> (Operand, Option<BranchWeights>) {
+        let weights = match condition {
+            Expression::Call { identifier, .. } => {
+                BranchHint::from_name(identifier.as_str()).map(|hint| match hint {
+                    BranchHint::Likely => BranchWeights::LIKELY,
+                    BranchHint::Unlikely => BranchWeights::UNLIKELY,
+                })
+            }
+            _ => None,
+        };
+        (self.visit_expression(condition).unwrap(), weights)
     }
 
-    /// Set the terminator for a specific basic block
-    fn set_terminator_for_block(&mut self, block_id: BlockId, term: Terminator) {
-        let func = self.current_function.as_mut().expect("No current function");
-        func.block_mut(block_id).terminator = term;
+    /// Linkage of a function the program defines
+    fn linkage(function: &Function) -> Linkage {
+        if function.has_attr("export") {
+            Linkage::Public
+        } else {
+            Linkage::Private
+        }
     }
 
     /// Convert HIR Type to MIR Type
@@ -128,10 +180,21 @@ impl LoweringPass {
             Type::PointerType(_) => {
                 panic!("Not Yet Implemented")
             }
+            // Newtypes share the representation of their underlying type
+            Type::Newtype { underlying, .. } => self.convert_type(underlying),
+            // Enums are represented by the index of their variant
+            Type::Enum { .. } => MirType::I32,
+            Type::Named(_) => unreachable!("Type names are resolved by the typechecker"),
         }
     }
 }
 
+impl Default for LoweringPass {
+    fn default() -> Self {
+        LoweringPass::new()
+    }
+}
+
 impl Visitor for LoweringPass {
     type Output = Option<Operand>;
 
@@ -144,15 +207,29 @@ impl Visitor for LoweringPass {
     }
 
     fn visit_program(&mut self, program: &mut Program) -> Self::Output {
-        self.push_scope();
-        for glob in &mut program.globals {
-            // Allocate variable in current scope which is the global one
-            self.alloc_variable(glob.name.clone());
+        for global in &program.globals {
+            let typ = self.convert_type(&global.typ);
+            self.globals.push(MirGlobal {
+                name: Symbol::intern(&global.name),
+                typ,
+                linkage: Linkage::Private,
+            });
         }
+        let defined = program.functions.iter().map(|function| (function, Self::linkage(function)));
+        let external = program.externs.iter().map(|function| (function, Linkage::External));
+        for (function, linkage) in defined.chain(external) {
+            let params = function.args.iter().map(|arg| self.convert_type(&arg.typ)).collect();
+            self.symbols.declare(FunctionSignature {
+                name: Symbol::intern(&function.name),
+                params,
+                return_type: self.convert_type(&function.return_type),
+                linkage,
+            });
+        }
+        self.lower_initializers(program);
         for function in &mut program.functions {
             self.visit_function(function);
         }
-        self.pop_scope();
 
         None
     }
@@ -161,29 +238,46 @@ impl Visitor for LoweringPass {
         // Push function scope for parameters
         self.push_scope();
 
-        // Convert parameters and allocate them in the function's scope
-        let mut params = Vec::new();
-        for arg in &function.args {
-            // Allocate parameter in current scope (gets register + adds to scope)
-            let reg = self.alloc_variable(arg.name.clone());
-            let mir_type = self.convert_type(&arg.typ);
-            params.push((reg, mir_type));
-        }
-
         // Convert return type
         let return_type = self.convert_type(&function.return_type);
 
         // Create MIR function and set as current
-        let mir_func = MirFunction::new(function.name.clone(), params, return_type);
+        let mut mir_func = MirFunction::new(Symbol::intern(&function.name), Vec::new(), return_type);
+        mir_func.span = Some(function.span);
+        mir_func.source = Some(function.source.clone());
+        mir_func.pure = self.purity.is_pure_function(mir_func.name);
+        mir_func.inline = if function.has_attr("inline") {
+            InlineHint::Always
+        } else if function.has_attr("noinline") {
+            InlineHint::Never
+        } else {
+            InlineHint::Auto
+        };
+        mir_func.linkage = Self::linkage(function);
+        mir_func.test = function.has_attr("test");
         let entry_block = mir_func.entry;
         self.current_function = Some(mir_func);
         self.current_block = Some(entry_block);
 
+        // Convert parameters and allocate them in the function's scope
+        for arg in &function.args {
+            // Allocate parameter in current scope (gets register + adds to scope)
+            let mir_type = self.convert_type(&arg.typ);
+            let reg = self.alloc_variable(arg.name.clone(), mir_type);
+            if let Some(func) = self.current_function.as_mut() {
+                func.params.push((reg, mir_type));
+            }
+        }
+
         // Lower function body statements to MIR instructions
         // Note: visit_block will handle its own scope push/pop
         // which is why we're doing it manually here (to keep vars)
-        for statement in &mut function.body.statements {
-            self.visit_statement(statement);
+        self.reachable = true;
+        self.lower_statements(&mut function.body.statements);
+
+        // Falling off the end of a void function returns from it
+        if return_type == MirType::Void && self.reachable {
+            self.builder().ret(None);
         }
 
         // Pop function scope
@@ -199,44 +293,145 @@ impl Visitor for LoweringPass {
     }
 
     fn visit_statement(&mut self, statement: &mut Statement) -> Self::Output {
+        let outer = self.current_span.replace(statement.span());
+        let result = self.lower_statement(statement);
+        self.current_span = outer;
+        result
+    }
+
+    fn visit_block(&mut self, block: &mut crate::ast::Block) -> Self::Output {
+        self.push_scope();
+
+        // Pre-allocate all variables from the HIR scope
+        if let Some(hir_scope) = &block.scope {
+            for (var_name, var) in &hir_scope.borrow().symbols {
+                let typ = self.convert_type(&var.typ);
+                self.alloc_variable(var_name.clone(), typ);
+            }
+        }
+
+        // Now traverse and generate instructions
+        self.lower_statements(&mut block.statements);
+
+        self.pop_scope();
+        None
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) -> Self::Output {
+        let outer = self.current_span.replace(expression.span());
+        let result = self.lower_expression(expression);
+        self.current_span = outer;
+        result
+    }
+}
+
+impl LoweringPass {
+    /// Emits the synthetic [`INIT_FUNCTION`], storing the initializer of
+    /// every global that has one, in declaration order
+    fn lower_initializers(&mut self, program: &mut Program) {
+        if !program.globals.iter().any(|global| global.initializer.is_some()) {
+            return;
+        }
+        let init = MirFunction::new(Symbol::intern(INIT_FUNCTION), Vec::new(), MirType::Void);
+        self.symbols.declare(FunctionSignature {
+            name: init.name,
+            params: Vec::new(),
+            return_type: MirType::Void,
+            linkage: Linkage::Private,
+        });
+        self.current_block = Some(init.entry);
+        self.current_function = Some(init);
+        for global in &mut program.globals {
+            let Some(initializer) = &mut global.initializer else {
+                continue;
+            };
+            if let Some(value) = self.visit_expression(initializer) {
+                self.builder().store(Symbol::intern(&global.name), value);
+            }
+        }
+        self.builder().ret(None);
+        self.functions.extend(self.current_function.take());
+        self.current_block = None;
+    }
+
+    /// Lowers the statements of a block until one of them leaves it for
+    /// good. Any code after that can never run, so it is reported and
+    /// dropped.
+    fn lower_statements(&mut self, statements: &mut [Statement]) {
+        let mut statements = statements.iter_mut();
+        for statement in statements.by_ref() {
+            self.visit_statement(statement);
+            if !self.reachable {
+                break;
+            }
+        }
+
+        // Nested definitions are not code, so they do not count
+        let unreachable: Vec<Span> = statements
+            .filter(|statement| {
+                !matches!(
+                    statement,
+                    Statement::FunctionDefinition { .. }
+                        | Statement::Newtype { .. }
+                        | Statement::Enum { .. }
+                )
+            })
+            .map(|statement| statement.span())
+            .collect();
+        if let (Some(first), Some(last)) = (unreachable.first(), unreachable.last()) {
+            let span = Span::merge(first, last);
+            let source = self.current_function.as_ref().and_then(|function| function.source.clone());
+            let warning = Diagnostic::new(format!(
+                "Unreachable code at {}: every path before it has already returned",
+                span
+            ))
+            .with_location(source, span)
+            .with_lint(Lint::UnreachableCode);
+            self.diagnostics.report_warning(warning);
+        }
+    }
+
+    /// Lowers a statement, with spans attributed by [`Visitor::visit_statement`]
+    fn lower_statement(&mut self, statement: &mut Statement) -> Option<Operand> {
         match statement {
             Statement::Expression { expression, .. } => {
                 self.visit_expression(expression);
             }
 
             Statement::While {
-                condition, body, ..
+                condition,
+                body,
+                do_while,
+                ..
             } => {
-                let cond_block = self.allocate_block();
-                let then_block = self.allocate_block();
-                let merge_block = self.allocate_block();
-
-                self.set_terminator(Terminator::Br { target: cond_block });
-                self.current_block = Some(cond_block);
-                let cond = self.visit_expression(condition).unwrap();
-                self.set_terminator_for_block(
-                    cond_block,
-                    Terminator::BrIf {
-                        cond,
-                        then_bb: then_block,
-                        else_bb: merge_block,
-                    },
-                );
-                self.current_block = Some(then_block);
-                self.set_terminator_for_block(then_block, Terminator::Br { target: cond_block });
+                // A while loop tests the condition in its header before every
+                // iteration; a do-while loop enters the body first and tests
+                // the condition after it
+                let header_block = self.allocate_block();
+                let body_block = self.allocate_block();
+                let exit_block = self.allocate_block();
+
+                self.builder().br(if *do_while { body_block } else { header_block });
+
+                self.current_block = Some(header_block);
+                let (cond, weights) = self.lower_condition(condition);
+                self.builder().br_if_weighted(cond, body_block, exit_block, weights);
+
+                // The body may end in another block than it started in, or
+                // already have returned
+                self.current_block = Some(body_block);
                 self.visit_block(body);
+                if self.reachable {
+                    self.builder().br(header_block);
+                }
 
-                // If current_block changed (nested loop), set its terminator too
-                if self.current_block != Some(then_block) {
-                    let block_id = self.current_block.unwrap();
-                    let block = self.current_function.as_ref().unwrap().block(block_id);
-
-                    // Only set terminator if it's still Unreachable (not a return)
-                    if matches!(block.terminator, Terminator::Unreachable) {
-                        self.set_terminator(Terminator::Br { target: cond_block });
-                    }
+                // Unless the body always returns, the condition decides when the loop ends
+                if *do_while && !self.reachable {
+                    self.current_block = Some(exit_block);
+                    return None;
                 }
-                self.current_block = Some(merge_block);
+                self.reachable = true;
+                self.current_block = Some(exit_block);
             }
             Statement::If {
                 condition,
@@ -244,141 +439,196 @@ impl Visitor for LoweringPass {
                 els,
                 ..
             } => {
+                let (cond, weights) = self.lower_condition(condition);
+
+                // Without an else branch the false edge goes straight to the merge block
                 let then_block = self.allocate_block();
-                let els_block = self.allocate_block();
                 let merge_block = self.allocate_block();
+                let else_block = match els {
+                    Some(_) => self.allocate_block(),
+                    None => merge_block,
+                };
+                self.builder().br_if_weighted(cond, then_block, else_block, weights);
 
-                let cond = self.visit_expression(condition).unwrap();
-
-                self.set_terminator(Terminator::BrIf {
-                    cond,
-                    then_bb: then_block,
-                    else_bb: els_block,
-                });
-
-                self.set_terminator_for_block(
-                    then_block,
-                    Terminator::Br {
-                        target: merge_block,
-                    },
-                );
-
+                // Each branch may end in another block than it started in, or
+                // already have returned, so it is only joined to the merge
+                // block once it has been lowered
                 self.current_block = Some(then_block);
                 self.visit_block(then);
+                let then_reaches_merge = self.reachable;
+                if then_reaches_merge {
+                    self.builder().br(merge_block);
+                }
 
-                // If current_block changed (nested control flow), set its terminator too
-                if self.current_block != Some(then_block) {
-                    let block_id = self.current_block.unwrap();
-                    let block = self.current_function.as_ref().unwrap().block(block_id);
-
-                    // Only set terminator if it's still Unreachable (not a return)
-                    if matches!(block.terminator, Terminator::Unreachable) {
-                        self.set_terminator(Terminator::Br {
-                            target: merge_block,
-                        });
+                self.reachable = true;
+                if let Some(els) = els {
+                    self.current_block = Some(else_block);
+                    self.visit_block(els);
+                    if self.reachable {
+                        self.builder().br(merge_block);
                     }
                 }
 
-                self.set_terminator_for_block(
-                    els_block,
-                    Terminator::Br {
-                        target: merge_block,
-                    },
-                );
+                self.reachable |= then_reaches_merge;
+                self.current_block = Some(merge_block);
+            }
+            Statement::Match {
+                scrutinee,
+                arms,
+                ..
+            } => {
+                let variants = match scrutinee.typ().as_ref().map(Type::underlying) {
+                    Some(Type::Enum { variants, .. }) => variants.clone(),
+                    _ => Vec::new(),
+                };
+                let scrutinee_type = scrutinee.typ().as_ref().map_or(MirType::F64, |t| self.convert_type(t));
+                let value = self.visit_expression(scrutinee).unwrap();
+                let arm_blocks: Vec<BlockId> = arms.iter().map(|_| self.allocate_block()).collect();
+                let merge_block = self.allocate_block();
 
-                self.current_block = Some(els_block);
-                if let Some(e) = els {
-                    self.visit_block(e);
+                // Arms after the first wildcard, and repeated patterns, can never be taken
+                let mut cases: Vec<(Operand, BlockId)> = Vec::new();
+                let mut default = None;
+                for (arm, &block) in arms.iter().zip(&arm_blocks) {
+                    let case_value = match &arm.pattern {
+                        Pattern::Number(n) => Operand::float(*n, scrutinee_type),
+                        Pattern::Boolean(b) => Operand::ImmBool(*b),
+                        Pattern::Variant { variant, .. } => variant_index(&variants, variant),
+                        Pattern::Wildcard => {
+                            default = Some(block);
+                            break;
+                        }
+                    };
+                    if !cases.iter().any(|(existing, _)| *existing == case_value) {
+                        cases.push((case_value, block));
+                    }
                 }
 
-                // If current_block changed (nested control flow), set its terminator too
-                if self.current_block != Some(els_block) {
-                    let block_id = self.current_block.unwrap();
-                    let block = self.current_function.as_ref().unwrap().block(block_id);
-
-                    // Only set terminator if it's still Unreachable (not a return)
-                    if matches!(block.terminator, Terminator::Unreachable) {
-                        self.set_terminator(Terminator::Br {
-                            target: merge_block,
-                        });
+                // The typechecker only accepts a match without a wildcard if
+                // the cases cover every value, so the default edge is never taken
+                let default = match default {
+                    Some(default) => default,
+                    None => {
+                        let trap_block = self.allocate_block();
+                        self.builder_at(trap_block).trap(TrapReason::UnreachableArm);
+                        trap_block
+                    }
+                };
+                self.builder().switch(value, cases, default);
+
+                let mut reaches_merge = false;
+                for (arm, block) in arms.iter_mut().zip(arm_blocks) {
+                    self.reachable = true;
+                    self.current_block = Some(block);
+                    self.visit_block(&mut arm.body);
+                    if self.reachable {
+                        self.builder().br(merge_block);
+                        reaches_merge = true;
                     }
                 }
 
+                self.reachable = reaches_merge;
                 self.current_block = Some(merge_block);
             }
             Statement::Block { block, .. } => {
                 self.visit_block(block);
             }
+            Statement::Assert {
+                condition,
+                message,
+                ..
+            } => {
+                let cond = self.visit_expression(condition).unwrap();
+                let fail_block = self.allocate_block();
+                let continue_block = self.allocate_block();
+                // Assertions are expected to hold
+                self.builder()
+                    .br_if_weighted(cond, continue_block, fail_block, Some(BranchWeights::LIKELY));
+
+                let reason = TrapReason::AssertionFailed(message.clone());
+                self.builder_at(fail_block).trap(reason);
+
+                self.current_block = Some(continue_block);
+            }
             Statement::Return { expression, .. } => {
                 let value = expression
                     .as_mut()
                     .and_then(|expr| self.visit_expression(expr));
-                self.set_terminator(Terminator::Ret { value });
+                self.builder().ret(value);
+                self.reachable = false;
             }
-            Statement::Assignment { left, right, .. } => {
-                // Get destination register
-                let dest_reg = self
-                    .lookup_variable(left)
-                    .unwrap_or_else(|| self.alloc_variable(left.clone()));
-
-                // Lower RHS if present
-                if let Some(expr) = right {
-                    if let Some(value) = self.visit_expression(expr) {
-                        // Get type from expression (set by typechecker)
-                        let mir_type = expr.typ().as_ref().map(|t| self.convert_type(t)).unwrap();
-
-                        self.add_instruction(Instruction {
-                            dest: dest_reg,
-                            op: Opcode::Copy,
-                            typ: mir_type,
-                            args: vec![value],
-                        });
+            Statement::Assignment { left, typ, right, .. } => {
+                // Assigning to a global that no local shadows stores to it
+                if typ.is_none()
+                    && self.lookup_variable(left).is_none()
+                    && let Some(global) = self.lookup_global(left)
+                {
+                    if let Some(expr) = right
+                        && let Some(value) = self.visit_expression(expr)
+                    {
+                        self.builder().store(global.name, value);
                     }
+                    return None;
                 }
-            }
-            _ => {}
-        };
 
-        None
-    }
+                // Get destination register, typed by the initializer or the declaration
+                let dest_reg = match self.lookup_variable(left) {
+                    Some(reg) => reg,
+                    None => {
+                        let var_type = right
+                            .as_ref()
+                            .and_then(|expr| expr.typ().clone())
+                            .or_else(|| typ.clone())
+                            .map(|t| self.convert_type(&t))
+                            .unwrap();
+                        self.alloc_variable(left.clone(), var_type)
+                    }
+                };
 
-    fn visit_block(&mut self, block: &mut crate::ast::Block) -> Self::Output {
-        self.push_scope();
+                // Lower RHS if present
+                if let Some(expr) = right
+                    && let Some(value) = self.visit_expression(expr)
+                {
+                    // Get type from expression (set by typechecker)
+                    let mir_type = expr.typ().as_ref().map(|t| self.convert_type(t)).unwrap();
 
-        // Pre-allocate all variables from the HIR scope
-        if let Some(hir_scope) = &block.scope {
-            for (var_name, _var) in &hir_scope.borrow().symbols {
-                self.alloc_variable(var_name.clone());
+                    self.builder().copy_into(dest_reg, mir_type, value);
+                }
             }
-        }
-
-        // Now traverse and generate instructions
-        for statement in &mut block.statements {
-            self.visit_statement(statement);
-        }
+            _ => {}
+        };
 
-        self.pop_scope();
         None
     }
 
-    fn visit_expression(&mut self, expression: &mut Expression) -> Self::Output {
+    /// Lowers an expression, with spans attributed by [`Visitor::visit_expression`]
+    fn lower_expression(&mut self, expression: &mut Expression) -> Option<Operand> {
         match expression {
-            Expression::Number { value, .. } => {
-                // Return immediate value
-                Some(Operand::ImmF64(*value))
+            Expression::Number { value, typ, .. } => {
+                // Return immediate value, of the type the literal was given
+                let mir_type = typ.as_ref().map_or(MirType::F64, |t| self.convert_type(t));
+                Some(Operand::float(*value, mir_type))
             }
             Expression::Boolean { value, .. } => {
                 // Return immediate boolean
                 Some(Operand::ImmBool(*value))
             }
-            Expression::Variable { name, .. } => {
-                // Look up variable's register
-                let Some(reg) = self.lookup_variable(name) else {
-                    self.diagnostics_mut()
-                        .error(format!("Variable '{}' not found", name));
-                    return None;
+            Expression::Variant { variant, typ, .. } => {
+                let Some(Type::Enum { variants, .. }) = typ else {
+                    unreachable!("Variants are typed by the typechecker")
                 };
-                Some(Operand::Reg(reg))
+                Some(variant_index(variants, variant))
+            }
+            Expression::Variable { name, .. } => {
+                // Look up variable's register, falling back to a global
+                if let Some(reg) = self.lookup_variable(name) {
+                    return Some(Operand::Reg(reg));
+                }
+                if let Some(global) = self.lookup_global(name) {
+                    return Some(Operand::Reg(self.builder().load(global.typ, global.name)));
+                }
+                self.report_error(format!("Variable '{}' not found", name));
+                None
             }
             Expression::BinaryOp {
                 left,
@@ -387,12 +637,35 @@ impl Visitor for LoweringPass {
                 typ,
                 ..
             } => {
+                // `&&` and `||` only evaluate their right operand when the left one
+                // does not already decide the result
+                if matches!(op.tag, TokenType::And | TokenType::Or) {
+                    let left_op = self.visit_expression(left)?;
+                    let result = self.builder().new_reg(MirType::I1);
+                    self.builder().copy_into(result, MirType::I1, left_op);
+
+                    let right_block = self.allocate_block();
+                    let merge_block = self.allocate_block();
+                    if op.tag == TokenType::And {
+                        self.builder().br_if(left_op, right_block, merge_block);
+                    } else {
+                        self.builder().br_if(left_op, merge_block, right_block);
+                    }
+
+                    self.current_block = Some(right_block);
+                    let right_op = self.visit_expression(right)?;
+                    self.builder().copy_into(result, MirType::I1, right_op);
+                    self.builder().br(merge_block);
+
+                    self.current_block = Some(merge_block);
+                    return Some(Operand::Reg(result));
+                }
+
                 // Lower both operands
                 let left_op = self.visit_expression(left)?;
                 let right_op = self.visit_expression(right)?;
 
-                // Allocate result register
-                let result_reg = self.get_free_register();
+                let mir_type = typ.as_ref().map(|t| self.convert_type(t)).unwrap();
 
                 // Determine opcode from token
                 use crate::frontend::TokenType;
@@ -402,6 +675,7 @@ impl Visitor for LoweringPass {
                     TokenType::Star => Opcode::Mul,
                     TokenType::Slash => Opcode::Div,
                     TokenType::Percent => Opcode::Mod,
+                    TokenType::StarStar => Opcode::Pow,
                     TokenType::Equal => Opcode::Eq,
                     TokenType::NotEqual => Opcode::Ne,
                     TokenType::Less => Opcode::Lt,
@@ -409,52 +683,52 @@ impl Visitor for LoweringPass {
                     TokenType::Greater => Opcode::Gt,
                     TokenType::GreaterEqual => Opcode::Ge,
                     _ => {
-                        self.diagnostics_mut()
-                            .error(format!("Unsupported binary operator: {:?}", op.tag));
+                        self.report_error(format!("Unsupported binary operator {:?}", op.tag));
                         return None;
                     }
                 };
 
-                // Add instruction
-                let mir_type = typ.as_ref().map(|t| self.convert_type(t)).unwrap();
-                self.add_instruction(Instruction {
-                    dest: result_reg,
-                    op: mir_op,
-                    typ: mir_type,
-                    args: vec![left_op, right_op],
-                });
+                let divisor_is_nonzero = match right_op {
+                    Operand::ImmI64(value) => value != 0,
+                    float => float.as_float().is_some_and(|value| value != 0.0),
+                };
+                if self.checked_division
+                    && matches!(mir_op, Opcode::Div | Opcode::Mod)
+                    && mir_type.is_integer()
+                    && !divisor_is_nonzero
+                {
+                    let is_zero = self.builder().eq(right_op, Operand::ImmI64(0));
+                    let trap_block = self.allocate_block();
+                    let continue_block = self.allocate_block();
+                    self.builder().br_if_weighted(
+                        Operand::Reg(is_zero),
+                        trap_block,
+                        continue_block,
+                        Some(BranchWeights::UNLIKELY),
+                    );
+                    self.builder_at(trap_block).trap(TrapReason::DivisionByZero);
+                    self.current_block = Some(continue_block);
+                }
 
+                let result_reg = self.builder().binary(mir_op, mir_type, left_op, right_op);
                 Some(Operand::Reg(result_reg))
             }
             Expression::UnaryOp { left, op, .. } => {
                 match op.tag {
                     TokenType::Minus => {
                         let val = self.visit_expression(left).unwrap();
-                        let dest = self.get_free_register();
                         let mir_type = left.typ().as_ref().map(|t| self.convert_type(t)).unwrap();
-                        self.add_instruction(Instruction {
-                            dest,
-                            op: Opcode::Sub,
-                            typ: mir_type,
-                            args: vec![Operand::ImmF64(0.0), val],
-                        });
+                        let dest = self.builder().sub(mir_type, Operand::zero(mir_type), val);
                         return Some(Operand::Reg(dest));
                     }
                     TokenType::Bang => {
                         let val = self.visit_expression(left).unwrap();
-                        let dest = self.get_free_register();
-                        self.add_instruction(Instruction {
-                            dest,
-                            op: Opcode::Eq,
-                            typ: MirType::I1,
-                            args: vec![Operand::ImmF64(0.0), val],
-                        });
+                        let dest = self.builder().eq(Operand::ImmBool(false), val);
                         return Some(Operand::Reg(dest));
                     }
                     _ => {}
                 }
-                self.diagnostics_mut()
-                    .error("Unary operations not yet implemented".to_string());
+                self.report_error(format!("Unary operator {:?} is not supported", op.tag));
                 None
             }
             Expression::Call {
@@ -463,20 +737,43 @@ impl Visitor for LoweringPass {
                 typ,
                 ..
             } => {
-                let dest = self.get_free_register();
+                // The hint was taken by the branch on the condition, if any
+                if BranchHint::from_name(identifier.as_str()).is_some() {
+                    return self.visit_expression(&mut args[0]);
+                }
+                // The typechecker rejects calls to unknown functions, so
+                // this only fails if lowering and the typechecker disagree
+                if self.symbols.id(*identifier).is_none() {
+                    self.report_error(format!("Call to unknown function '{}'", identifier));
+                }
+                let mir_type = typ.as_ref().map(|t| self.convert_type(t)).unwrap();
                 let mut operands: Vec<Operand> = Vec::new();
-                operands.push(Operand::Label(identifier.clone()));
                 for arg in args {
                     operands.push(self.visit_expression(arg).unwrap());
                 }
-                self.add_instruction(Instruction {
-                    dest,
-                    op: Opcode::Call,
-                    typ: typ.as_ref().map(|t| self.convert_type(t)).unwrap(),
-                    args: operands,
-                });
+                let dest = self.builder().call(mir_type, *identifier, operands);
                 Some(Operand::Reg(dest))
             }
+            // Casts between floats round the value; other casts only change the type
+            Expression::Cast { expression, typ, .. } => {
+                let from = self.convert_type(expression.typ().as_ref().unwrap());
+                let to = self.convert_type(typ.as_ref().unwrap());
+                let value = self.visit_expression(expression)?;
+                if from == to {
+                    return Some(value);
+                }
+                Some(Operand::Reg(self.builder().convert(to, value)))
+            }
+            Expression::Error { .. } => unreachable!("Programs with syntax errors are not lowered"),
         }
     }
 }
+
+/// The value representing `variant` of an enum with the given variants
+fn variant_index(variants: &[String], variant: &str) -> Operand {
+    let index = variants
+        .iter()
+        .position(|name| name == variant)
+        .expect("Variants are checked by the typechecker");
+    Operand::ImmI64(index as i64)
+}
@@ -3,3 +3,4 @@ pub mod print;
 pub mod typechecking;
 pub mod ast_simplification;
 pub mod lowering;
+pub mod cse;
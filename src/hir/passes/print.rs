@@ -1,11 +1,21 @@
-use crate::ast::{Expression, Program, Statement};
-use crate::hir::visitor::{DiagnosticCollector, Visitor};
+use crate::ast::{Expression, MatchArm, Pattern, Program, Statement};
+use crate::hir::visitor::{DiagnosticCollector, VisitorRef};
 use crate::span::Span;
+use crate::term::Painter;
 use crate::types::{Function, Variable};
+use crate::value::format_float;
+use std::collections::HashMap;
 
-/// Visitor that prints the AST structure with indentation
+/// Visitor that prints the AST structure with indentation. The text is
+/// collected rather than written out, see [`PrintPass::into_output`].
 pub struct PrintPass {
     indent: usize,
+    output: String,
+    painter: Painter,
+    /// Text of each source by name, to print what every span covers
+    sources: HashMap<String, String>,
+    /// Name of the source of the function being printed
+    source: Option<String>,
     diagnostics: DiagnosticCollector,
 }
 
@@ -13,26 +23,65 @@ impl PrintPass {
     pub fn new() -> Self {
         PrintPass {
             indent: 0,
+            output: String::new(),
+            painter: Painter::default(),
+            sources: HashMap::new(),
+            source: None,
             diagnostics: DiagnosticCollector::new(),
         }
     }
 
+    /// Style the output with the given painter
+    pub fn with_painter(mut self, painter: Painter) -> Self {
+        self.painter = painter;
+        self
+    }
+
+    /// Print the text each span covers after it, taking the text of the
+    /// sources from `(name, text)` pairs. The first line and the last are
+    /// shown of a span over several lines.
+    pub fn with_sources<'a>(mut self, sources: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        self.sources = sources
+            .into_iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect();
+        self
+    }
+
+    /// The text printed so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn into_output(self) -> String {
+        self.output
+    }
 
-    fn print(&self, msg: &str) {
-        println!("{}{}", "  ".repeat(self.indent), msg);
+    /// Prints a line, with the kind of node before its first colon or `@`
+    /// and the span after the `@` styled
+    fn print(&mut self, msg: &str) {
+        let (node, location) = match msg.split_once(" @ ") {
+            Some((node, span)) => (node, format!(" {}", self.painter.comment(&format!("@ {}", span)))),
+            None => (msg, String::new()),
+        };
+        let node = match node.split_once(':') {
+            Some((kind, rest)) => format!("{}:{}", self.painter.keyword(kind), rest),
+            None => self.painter.keyword(node),
+        };
+        self.output.push_str(&format!("{}{}{}\n", "  ".repeat(self.indent), node, location));
     }
 
-    fn format_span(span: &Span) -> String {
-        if span.start_row == span.end_row {
-            format!(
-                "{}:{}-{}",
-                span.start_row, span.start_column, span.end_column
-            )
-        } else {
-            format!(
-                "{}:{}-{}:{}",
-                span.start_row, span.start_column, span.end_row, span.end_column
-            )
+    fn format_span(&self, span: &Span) -> String {
+        let source = self.source.as_ref().and_then(|name| self.sources.get(name));
+        let Some(text) = source.and_then(|source| span.text(source)) else {
+            return span.to_string();
+        };
+        match text.split_once('\n') {
+            None => format!("{} {:?}", span, text),
+            Some((first, rest)) => {
+                let last = rest.rsplit('\n').next().unwrap_or(rest);
+                format!("{} {:?} ... {:?}", span, first, last)
+            }
         }
     }
 
@@ -47,7 +96,13 @@ impl PrintPass {
     }
 }
 
-impl Visitor for PrintPass {
+impl Default for PrintPass {
+    fn default() -> Self {
+        PrintPass::new()
+    }
+}
+
+impl VisitorRef for PrintPass {
     type Output = ();
 
     fn diagnostics(&self) -> &DiagnosticCollector {
@@ -58,78 +113,127 @@ impl Visitor for PrintPass {
         &mut self.diagnostics
     }
 
-    fn visit_program(&mut self, program: &mut Program) -> () {
+    fn visit_program(&mut self, program: &Program) {
         self.print(&format!(
             "Program ({} globals, {} functions)",
             program.globals.len(),
             program.functions.len()
         ));
         self.indent();
+        for function in &program.externs {
+            self.print(&format!("Extern function: {}", function.name));
+        }
         self.walk_program(program);
         self.dedent();
     }
 
-    fn visit_function(&mut self, function: &mut Function) -> () {
-        self.print(&format!("Function: {}", function.name));
+    fn visit_function(&mut self, function: &Function) {
+        let attributes: String = function
+            .attrs
+            .iter()
+            .map(|attr| format!(" @{}", attr.name))
+            .collect();
+        self.source = Some(function.source.clone());
+        let span = self.format_span(&function.span);
+        self.print(&format!("Function: {}{} @ {}", function.name, attributes, span));
         self.indent();
         self.walk_function(function);
         self.dedent();
     }
 
-    fn visit_variable(&mut self, variable: &mut Variable) -> () {
+    fn visit_variable(&mut self, variable: &Variable) {
         self.print(&format!("Variable: {}", variable.name));
         self.indent();
         self.walk_variable(variable);
         self.dedent();
     }
 
-    fn visit_statement(&mut self, statement: &mut Statement) -> () {
+    fn visit_statement(&mut self, statement: &Statement) {
         match statement {
             Statement::Assignment { left, span, .. } => self.print(&format!(
                 "Assignment to: {} @ {}",
                 left,
-                Self::format_span(span)
+                self.format_span(span)
             )),
             Statement::FunctionDefinition { name, span, .. } => self.print(&format!(
                 "FunctionDef: {} @ {}",
                 name,
-                Self::format_span(span)
+                self.format_span(span)
             )),
             Statement::If { span, .. } => {
-                self.print(&format!("If statement @ {}", Self::format_span(span)))
+                self.print(&format!("If statement @ {}", self.format_span(span)))
             }
-            Statement::While { span, .. } => {
-                self.print(&format!("While loop @ {}", Self::format_span(span)))
+            Statement::While { do_while: false, span, .. } => {
+                self.print(&format!("While loop @ {}", self.format_span(span)))
+            }
+            Statement::While { do_while: true, span, .. } => {
+                self.print(&format!("Do-while loop @ {}", self.format_span(span)))
             }
             Statement::Block { span, .. } => {
-                self.print(&format!("Block @ {}", Self::format_span(span)))
+                self.print(&format!("Block @ {}", self.format_span(span)))
             }
             Statement::Return { span, .. } => {
-                self.print(&format!("Return @ {}", Self::format_span(span)))
+                self.print(&format!("Return @ {}", self.format_span(span)))
+            }
+            Statement::Match { span, .. } => {
+                self.print(&format!("Match statement @ {}", self.format_span(span)))
             }
             Statement::Expression { span, .. } => self.print(&format!(
                 "Expression statement @ {}",
-                Self::format_span(span)
+                self.format_span(span)
             )),
+            Statement::Assert { span, .. } => {
+                self.print(&format!("Assert @ {}", self.format_span(span)))
+            }
+            Statement::Newtype { name, span, .. } => self.print(&format!(
+                "Newtype: {} @ {}",
+                name,
+                self.format_span(span)
+            )),
+            Statement::Enum { name, variants, span, .. } => self.print(&format!(
+                "Enum: {} {{ {} }} @ {}",
+                name,
+                variants.join(", "),
+                self.format_span(span)
+            )),
+            Statement::Error { span, .. } => {
+                self.print(&format!("Error @ {}", self.format_span(span)))
+            }
         }
         self.indent();
         self.walk_statement(statement);
         self.dedent();
     }
 
-    fn visit_expression(&mut self, expression: &mut Expression) -> () {
+    fn visit_match(&mut self, scrutinee: &Expression, arms: &[MatchArm]) {
+        self.visit_expression(scrutinee);
+        for arm in arms {
+            let pattern = match &arm.pattern {
+                Pattern::Number(value) => format_float(*value),
+                Pattern::Boolean(value) => value.to_string(),
+                Pattern::Variant { enum_name, variant } => format!("{}::{}", enum_name, variant),
+                Pattern::Wildcard => "_".to_string(),
+            };
+            self.print(&format!("Arm: {} @ {}", pattern, self.format_span(&arm.span)));
+            self.indent();
+            self.visit_block(&arm.body);
+            self.dedent();
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
         match expression {
             Expression::Number { value: n, span, .. } => {
-                self.print(&format!("Number: {} @ {}", n, Self::format_span(span)))
+                self.print(&format!("Number: {} @ {}", format_float(*n), self.format_span(span)))
             }
             Expression::Boolean { value: b, span, .. } => {
-                self.print(&format!("Boolean: {} @ {}", b, Self::format_span(span)))
+                self.print(&format!("Boolean: {} @ {}", b, self.format_span(span)))
             }
             Expression::BinaryOp { span, .. } => {
-                self.print(&format!("BinaryOp @ {}", Self::format_span(span)))
+                self.print(&format!("BinaryOp @ {}", self.format_span(span)))
             }
             Expression::UnaryOp { span, .. } => {
-                self.print(&format!("UnaryOp @ {}", Self::format_span(span)))
+                self.print(&format!("UnaryOp @ {}", self.format_span(span)))
             }
             Expression::Call {
                 identifier,
@@ -140,7 +244,7 @@ impl Visitor for PrintPass {
                 "Call: {}({} args) @ {}",
                 identifier,
                 args.len(),
-                Self::format_span(span)
+                self.format_span(span)
             )),
             Expression::Variable {
                 name: identifier,
@@ -149,8 +253,27 @@ impl Visitor for PrintPass {
             } => self.print(&format!(
                 "Variable ref: {} @ {}",
                 identifier,
-                Self::format_span(span)
+                self.format_span(span)
             )),
+            Expression::Variant {
+                enum_name,
+                variant,
+                span,
+                ..
+            } => self.print(&format!(
+                "Variant: {}::{} @ {}",
+                enum_name,
+                variant,
+                self.format_span(span)
+            )),
+            Expression::Cast { target, span, .. } => self.print(&format!(
+                "Cast to: {:?} @ {}",
+                target,
+                self.format_span(span)
+            )),
+            Expression::Error { span, .. } => {
+                self.print(&format!("Error @ {}", self.format_span(span)))
+            }
         }
         self.indent();
         self.walk_expression(expression);
@@ -1,15 +1,62 @@
-use crate::ast::{Expression, Program, Statement};
-use crate::types::{BaseType, Function, Scope, Type, Variable};
+use crate::ast::{Block, BranchHint, Expression, NodeId, Pattern, Program, Statement};
+use crate::diagnostics::{Applicability, Diagnostic, Lint, Suggestion, TextEdit, closest_match};
+use crate::frontend::Token;
+use crate::intern::Symbol;
+use crate::span::Span;
+use crate::types::{BaseType, Bound, Function, Scope, Type, Variable};
 use crate::hir::visitor::{DiagnosticCollector, Visitor};
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+/// Attributes a function may carry, with the number of arguments each takes
+const FUNCTION_ATTRIBUTES: &[(&str, usize)] = &[
+    ("pure", 0),
+    ("inline", 0),
+    ("noinline", 0),
+    ("export", 0),
+    ("test", 0),
+    // Takes any number of lint names, which the compiler checks
+    ("allow", 0),
+];
+
+/// The values a match scrutinee can take, which decide the patterns its arms may use
+enum ScrutineeKind {
+    Bool,
+    Number,
+    /// The name and variants of the enum
+    Enum(String, Vec<String>),
+}
+
 /// Visitor that Typechecks the AST structure
 pub struct TypecheckingPass {
     diagnostics: DiagnosticCollector,
     scope_stack: Vec<Rc<RefCell<Scope>>>,
     current_function_return_type: Option<Type>,
+    /// Name of the source the function being checked was defined in
+    current_source: Option<String>,
     next_scope_id: usize,
+    /// Declared newtypes and enums, by name
+    types: HashMap<String, Type>,
+    /// Type parameters of the generic signature being resolved, which are
+    /// left as names
+    type_params: Vec<String>,
+    /// Bounds of the type parameters of the generic body being checked
+    bounds: HashMap<String, Vec<Bound>>,
+    /// Generic functions whose bodies have errors, which are not instantiated
+    broken_generics: HashSet<String>,
+    /// Instantiations of generic functions whose bodies are not checked yet
+    pending: Vec<Function>,
+    /// Declarations without a type or an initializer, by scope id and name,
+    /// with the index of the error reporting them. The first assignment to
+    /// one tells which annotation it is missing.
+    unannotated: HashMap<(usize, String), usize>,
+    /// Unknown variables and functions already reported in the function
+    /// being checked, whose other uses are not reported again
+    unknown_variables: HashSet<String>,
+    unknown_functions: HashSet<Symbol>,
+    /// The id of the next node an instantiation copies
+    next_node_id: NodeId,
 }
 
 impl TypecheckingPass {
@@ -18,7 +65,224 @@ impl TypecheckingPass {
             diagnostics: DiagnosticCollector::new(),
             scope_stack: Vec::new(),
             current_function_return_type: None,
+            current_source: None,
             next_scope_id: 0,
+            types: HashMap::new(),
+            type_params: Vec::new(),
+            bounds: HashMap::new(),
+            broken_generics: HashSet::new(),
+            pending: Vec::new(),
+            unannotated: HashMap::new(),
+            unknown_variables: HashSet::new(),
+            unknown_functions: HashSet::new(),
+            next_node_id: NodeId(0),
+        }
+    }
+
+    /// Type parameters must be distinct, be inferable from the arguments
+    /// and have known bounds
+    fn check_type_params(&mut self, function: &Function) {
+        for (i, param) in function.type_params.iter().enumerate() {
+            if function.type_params[..i].iter().any(|other| other.name == param.name) {
+                self.diagnostics.error(format!(
+                    "Type parameter '{}' of function '{}' is declared more than once",
+                    param.name, function.name
+                ));
+            } else if !function.args.iter().any(|arg| mentions(&arg.typ, &param.name)) {
+                self.diagnostics.error(format!(
+                    "Type parameter '{}' of function '{}' at {} is not used by its parameters, so calls cannot infer it",
+                    param.name, function.name, function.span
+                ));
+            }
+            for bound in &param.bounds {
+                if Bound::from_name(bound).is_none() {
+                    self.diagnostics.error(format!(
+                        "Unknown bound '{}' on type parameter '{}' of function '{}', expected 'Numeric' or 'Comparable'",
+                        bound, param.name, function.name
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Binds the type parameters of `generic` to the types of the arguments
+    /// of a call, and returns the instantiation those bindings select,
+    /// creating it on first use
+    fn instantiate(&mut self, generic: &Function, arg_types: &[Type]) -> Option<Function> {
+        // Its errors were reported when its body was checked
+        if self.broken_generics.contains(&generic.name) {
+            return None;
+        }
+
+        let names: Vec<String> = generic.type_params.iter().map(|param| param.name.clone()).collect();
+        let mut bindings: HashMap<String, Type> = HashMap::new();
+        for (param, arg_type) in generic.args.iter().zip(arg_types) {
+            if let Err((name, bound)) = unify(&param.typ, arg_type, &names, &mut bindings) {
+                self.diagnostics.error(format!(
+                    "Type parameter '{}' of function '{}' is inferred as both {} and {}",
+                    name, generic.name, bound, arg_type
+                ));
+                return None;
+            }
+        }
+
+        // Bounds are checked here, so their violations point at the call
+        // rather than at the operations of the generic body
+        let mut type_args = Vec::new();
+        for param in &generic.type_params {
+            // A parameter that can't be inferred was reported at the definition
+            let typ = bindings.get(&param.name)?;
+            for bound in param.bounds.iter().filter_map(|bound| Bound::from_name(bound)) {
+                if !self.satisfies(typ, bound) {
+                    self.diagnostics.error(format!(
+                        "Type {} does not satisfy the bound '{}' of type parameter '{}' in the call to '{}'",
+                        typ, bound, param.name, generic.name
+                    ));
+                    return None;
+                }
+            }
+            type_args.push(typ.to_string());
+        }
+        let name = format!("{}<{}>", generic.name, type_args.join(", "));
+        if let Some(instance) = self.find_function(&name) {
+            return Some(instance);
+        }
+
+        let mut instance = generic.clone();
+        instance.name = name.clone();
+        instance.type_params.clear();
+        let mut substitution = Substitution {
+            diagnostics: DiagnosticCollector::new(),
+            bindings,
+            next_node_id: self.next_node_id,
+        };
+        for arg in &mut instance.args {
+            substitution.substitute(&mut arg.typ);
+            if let Some(initializer) = &mut arg.initializer {
+                substitution.visit_expression(initializer);
+            }
+        }
+        substitution.substitute(&mut instance.return_type);
+
+        // A call from another generic body only needs the signature; the
+        // instantiations of that body make the concrete calls
+        let opaque = |typ: &Type| matches!(typ, Type::Named(name) if self.type_params.contains(name));
+        if substitution.bindings.values().any(opaque) {
+            self.next_node_id = substitution.next_node_id;
+            return Some(instance);
+        }
+        substitution.visit_block(&mut instance.body);
+        self.next_node_id = substitution.next_node_id;
+
+        // Declared globally so recursive calls find it while it is pending
+        self.scope_stack[0]
+            .borrow_mut()
+            .functions
+            .insert(name, instance.clone());
+        self.pending.push(instance.clone());
+        Some(instance)
+    }
+
+    /// Returns true if `typ` meets `bound`. A type parameter of the generic
+    /// body being checked meets the bounds it was declared with.
+    fn satisfies(&self, typ: &Type, bound: Bound) -> bool {
+        match typ {
+            Type::Named(name) => self
+                .bounds
+                .get(name)
+                .is_some_and(|bounds| bounds.iter().any(|declared| declared.implies(bound))),
+            _ => bound.is_satisfied_by(typ),
+        }
+    }
+
+    /// Reports an operator applied to a type parameter whose bounds don't
+    /// provide it
+    fn check_operator_bound(&mut self, typ: &Type, op: &Token) -> bool {
+        let (Type::Named(name), Some(bound)) = (typ, Bound::required_by(&op.tag)) else {
+            return true;
+        };
+        if self.satisfies(typ, bound) {
+            return true;
+        }
+        self.diagnostics.error(format!(
+            "Operator '{}' at {} needs the bound '{}' on type parameter '{}' (declare it as '{}: {}')",
+            op.lexeme, Span::from_token(op), bound, name, name, bound
+        ));
+        false
+    }
+
+    /// Makes a newtype or enum usable by name, unless the name is taken
+    fn declare_type(&mut self, name: &str, span: Span, typ: Type) {
+        if self.types.contains_key(name) {
+            self.diagnostics
+                .error(format!("Type '{}' at {} is already declared", name, span));
+            return;
+        }
+        self.types.insert(name.to_string(), typ);
+    }
+
+    /// Replaces the type names in `typ` with the types they refer to.
+    /// An unknown name is reported and the type becomes 'auto', which
+    /// keeps the variables of that type from being reported again.
+    fn resolve_type(&mut self, typ: &mut Type) {
+        match typ {
+            Type::Named(name) if self.type_params.contains(name) => {}
+            Type::Named(name) => match self.types.get(name) {
+                Some(newtype) => *typ = newtype.clone(),
+                None => {
+                    self.diagnostics
+                        .error(format!("Unknown type '{}'", name));
+                    *typ = Type::Base(BaseType::Auto);
+                }
+            },
+            Type::PointerType(inner) => self.resolve_type(inner),
+            Type::Base(_) | Type::Newtype { .. } | Type::Enum { .. } => {}
+        }
+    }
+
+    /// Rejects unknown, duplicated and contradicting attributes
+    fn check_attributes(&mut self, function: &Function) {
+        let mut seen: Vec<&str> = Vec::new();
+        for attr in &function.attrs {
+            let Some(&(_, arity)) = FUNCTION_ATTRIBUTES
+                .iter()
+                .find(|(name, _)| *name == attr.name)
+            else {
+                self.diagnostics.error(format!(
+                    "Unknown attribute '@{}' at {} on function '{}'",
+                    attr.name, attr.span, function.name
+                ));
+                continue;
+            };
+            if attr.name != "allow" && attr.args.len() != arity {
+                self.diagnostics.error(format!(
+                    "Attribute '@{}' at {} takes {} argument(s), found {}",
+                    attr.name,
+                    attr.span,
+                    arity,
+                    attr.args.len()
+                ));
+            }
+            if seen.contains(&attr.name.as_str()) {
+                self.diagnostics.error(format!(
+                    "Attribute '@{}' at {} is given more than once on function '{}'",
+                    attr.name, attr.span, function.name
+                ));
+            }
+            seen.push(&attr.name);
+        }
+
+        if function.has_attr("inline") && function.has_attr("noinline") {
+            self.diagnostics.error(format!(
+                "Function '{}' at {} cannot be both @inline and @noinline",
+                function.name, function.span
+            ));
+        }
+        if function.has_attr("test") && !function.args.is_empty() {
+            self.diagnostics.error(format!(
+                "Test function '{}' at {} must not take parameters",
+                function.name, function.span
+            ));
         }
     }
 
@@ -28,6 +292,15 @@ impl TypecheckingPass {
         id
     }
 
+    /// Checks the body of a loop in a scope of its own
+    fn visit_loop_body(&mut self, body: &mut Block) {
+        let loop_scope = Rc::new(RefCell::new(Scope::new(self.allocate_scope_id())));
+        body.scope = Some(Rc::clone(&loop_scope));
+        self.scope_stack.push(loop_scope);
+        self.visit_block(body);
+        self.scope_stack.pop();
+    }
+
     pub fn find_variable(&self, name: &str) -> Option<Variable> {
         self.scope_stack
             .iter()
@@ -41,12 +314,102 @@ impl TypecheckingPass {
             .and_then(|scope| scope.borrow().symbols.get(name).cloned())
     }
 
+    /// Gives a declaration that was reported for having neither a type nor
+    /// an initializer the type of its first assignment, and suggests
+    /// writing it as an annotation
+    fn annotate_from_assignment(&mut self, name: &str, typ: Type) {
+        let Some(scope_id) = self.scope_of_variable(name) else {
+            return;
+        };
+        let Some(index) = self.unannotated.remove(&(scope_id, name.to_string())) else {
+            return;
+        };
+
+        let error = &mut self.diagnostics.errors[index];
+        if let Some(span) = error.span {
+            error.suggestions.push(Suggestion {
+                message: format!("annotate '{}' with the type of its first assignment, '{}'", name, typ),
+                edits: vec![TextEdit::insert(span.end_row, span.end_column, &format!(": {}", typ))],
+                applicability: Applicability::MachineApplicable,
+            });
+        }
+
+        let scope = self
+            .scope_stack
+            .iter()
+            .find(|scope| scope.borrow().id == scope_id)
+            .unwrap();
+        if let Some(variable) = scope.borrow_mut().symbols.get_mut(name) {
+            variable.typ = typ;
+        }
+    }
+
+    /// The id of the innermost scope declaring the variable
+    fn scope_of_variable(&self, name: &str) -> Option<usize> {
+        self.scope_stack
+            .iter()
+            .rev()
+            .map(|scope| scope.borrow())
+            .find(|scope| scope.symbols.contains_key(name))
+            .map(|scope| scope.id)
+    }
+
     pub fn add_variable_to_current_scope(&mut self, var: Variable) {
         if let Some(scope_rc) = self.scope_stack.last() {
             scope_rc.borrow_mut().symbols.insert(var.name.clone(), var);
         }
     }
 
+    /// Names of the variables visible from the current scope
+    pub fn visible_variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .scope_stack
+            .iter()
+            .flat_map(|scope| scope.borrow().symbols.keys().cloned().collect::<Vec<_>>())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Names of the functions visible from the current scope, without the
+    /// instantiations of generic functions
+    pub fn visible_functions(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .scope_stack
+            .iter()
+            .flat_map(|scope| scope.borrow().functions.keys().cloned().collect::<Vec<_>>())
+            .filter(|name| !name.contains('<'))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Reports a name that does not resolve, suggesting the closest of the
+    /// names that would
+    fn report_unknown_name(&mut self, message: String, name: &str, start: Span, candidates: &[String]) {
+        let span = Span::new(
+            start.start_row,
+            start.start_column,
+            start.start_row,
+            start.start_column + name.chars().count(),
+        );
+        let mut diagnostic = Diagnostic::new(message).with_location(self.current_source.clone(), span);
+        if let Some(candidate) = closest_match(name, candidates.iter().map(String::as_str)) {
+            diagnostic.message.push_str(&format!(", did you mean '{}'?", candidate));
+            diagnostic = diagnostic.with_suggestion(Suggestion {
+                message: format!("replace '{}' with '{}'", name, candidate),
+                edits: vec![TextEdit {
+                    span,
+                    replacement: candidate.to_string(),
+                }],
+                applicability: Applicability::MaybeIncorrect,
+            });
+        }
+        self.diagnostics.report_error(diagnostic);
+    }
+
     pub fn find_function(&self, name: &str) -> Option<Function> {
         self.scope_stack
             .iter()
@@ -55,6 +418,12 @@ impl TypecheckingPass {
     }
 }
 
+impl Default for TypecheckingPass {
+    fn default() -> Self {
+        TypecheckingPass::new()
+    }
+}
+
 impl Visitor for TypecheckingPass {
     type Output = Option<Type>;
 
@@ -67,17 +436,97 @@ impl Visitor for TypecheckingPass {
     }
 
     fn visit_program(&mut self, program: &mut Program) -> Self::Output {
+        self.next_node_id = program.next_node_id;
+
         // Create a global scope for globals and function declarations
         let mut global_scope = Scope::new(self.allocate_scope_id());
 
+        for declaration in &program.enums {
+            if declaration.variants.is_empty() {
+                self.diagnostics.error(format!(
+                    "Enum '{}' at {} must have at least one variant",
+                    declaration.name, declaration.span
+                ));
+            }
+            for (i, variant) in declaration.variants.iter().enumerate() {
+                if declaration.variants[..i].contains(variant) {
+                    self.diagnostics.error(format!(
+                        "Variant '{}' of enum '{}' is declared more than once",
+                        variant, declaration.name
+                    ));
+                }
+            }
+            let typ = Type::Enum {
+                name: declaration.name.clone(),
+                variants: declaration.variants.clone(),
+            };
+            self.declare_type(&declaration.name, declaration.span, typ);
+        }
+
+        // Newtypes may be used by every declaration, and by the newtypes
+        // declared after them
+        for newtype in &mut program.newtypes {
+            self.resolve_type(&mut newtype.underlying);
+            let typ = Type::Newtype {
+                name: newtype.name.clone(),
+                underlying: Box::new(newtype.underlying.clone()),
+            };
+            self.declare_type(&newtype.name, newtype.span, typ);
+        }
+
         // Add all global variables to the global scope
         for global in &mut program.globals {
+            self.resolve_type(&mut global.typ);
             self.visit_variable(global);
             global_scope.symbols.insert(global.name.clone(), global.clone());
         }
 
-        // Add all function declarations to the global scope
-        for function in &program.functions {
+        // Add all function declarations to the global scope, with the types
+        // of their signatures resolved so calls can be checked against them
+        for function in &mut program.functions {
+            if BranchHint::from_name(&function.name).is_some() {
+                self.diagnostics.error(format!(
+                    "Function '{}' at {} has the name of a built-in function",
+                    function.name, function.span
+                ));
+            }
+            self.check_type_params(function);
+            self.type_params = function.type_params.iter().map(|param| param.name.clone()).collect();
+            for arg in &mut function.args {
+                self.resolve_type(&mut arg.typ);
+            }
+            self.resolve_type(&mut function.return_type);
+            self.type_params.clear();
+            global_scope.functions.insert(function.name.clone(), function.clone());
+        }
+
+        // Functions defined outside the program can only be called, so
+        // their signatures are all there is to check
+        for function in &mut program.externs {
+            if !function.type_params.is_empty() {
+                self.diagnostics.error(format!(
+                    "Extern function '{}' at {} cannot have type parameters",
+                    function.name, function.span
+                ));
+                continue;
+            }
+            if let Some(attr) = function.attrs.first() {
+                self.diagnostics.error(format!(
+                    "Attribute '@{}' at {} cannot be given to extern function '{}'",
+                    attr.name, attr.span, function.name
+                ));
+            }
+            if global_scope.functions.contains_key(&function.name) {
+                self.diagnostics.error(format!(
+                    "Extern function '{}' at {} is also defined by the program",
+                    function.name, function.span
+                ));
+                continue;
+            }
+            for arg in &mut function.args {
+                self.resolve_type(&mut arg.typ);
+            }
+            self.resolve_type(&mut function.return_type);
             global_scope.functions.insert(function.name.clone(), function.clone());
         }
 
@@ -85,11 +534,58 @@ impl Visitor for TypecheckingPass {
         let global_scope_rc = Rc::new(RefCell::new(global_scope));
         self.scope_stack.push(global_scope_rc);
 
+        // Generic bodies are checked once with their type parameters left
+        // opaque, so they can only rely on what the bounds provide. Those
+        // that pass are checked again for every instantiation.
+        for function in &program.functions {
+            if function.type_params.is_empty() {
+                continue;
+            }
+            self.type_params = function.type_params.iter().map(|param| param.name.clone()).collect();
+            self.bounds = function
+                .type_params
+                .iter()
+                .map(|param| {
+                    let bounds = param.bounds.iter().filter_map(|bound| Bound::from_name(bound));
+                    (param.name.clone(), bounds.collect())
+                })
+                .collect();
+            let first_error = self.diagnostics.errors.len();
+            self.visit_function(&mut function.clone());
+            if self.diagnostics.errors.len() > first_error {
+                self.broken_generics.insert(function.name.clone());
+            }
+            self.type_params.clear();
+            self.bounds.clear();
+        }
+
         // Visit all functions
         for function in &mut program.functions {
-            self.visit_function(function);
+            self.check_attributes(function);
+            if function.type_params.is_empty() {
+                self.visit_function(function);
+            }
         }
 
+        // Checking an instantiation may instantiate more functions
+        let mut instances = Vec::new();
+        while let Some(mut instance) = self.pending.pop() {
+            let first_error = self.diagnostics.errors.len();
+            self.visit_function(&mut instance);
+            // Errors in a generic body depend on the types it was instantiated with
+            for error in &mut self.diagnostics.errors[first_error..] {
+                error.message.push_str(&format!(" (in instantiation '{}')", instance.name));
+            }
+            instances.push(instance);
+        }
+        let (generics, functions) = std::mem::take(&mut program.functions)
+            .into_iter()
+            .partition(|function| !function.type_params.is_empty());
+        program.functions = functions;
+        program.functions.extend(instances);
+        program.generics = generics;
+        program.next_node_id = self.next_node_id;
+
         // Pop global scope
         self.scope_stack.pop();
 
@@ -102,7 +598,10 @@ impl Visitor for TypecheckingPass {
 
         // Add the function parameters to the scope
         for arg in &mut function.args {
-            self.visit_variable(arg);
+            // A parameter of an unknown type was already reported
+            if !matches!(arg.typ, Type::Base(BaseType::Auto)) {
+                self.visit_variable(arg);
+            }
 
             scope.symbols.insert(arg.name.clone(), arg.clone());
         }
@@ -117,6 +616,9 @@ impl Visitor for TypecheckingPass {
 
         // Set the current function's return type so Return statements can check against it
         self.current_function_return_type = Some(function.return_type.clone());
+        self.current_source = Some(function.source.clone());
+        self.unknown_variables.clear();
+        self.unknown_functions.clear();
 
         for statement in &mut function.body.statements {
             self.visit_statement(statement);
@@ -155,13 +657,13 @@ impl Visitor for TypecheckingPass {
             }
             // Concrete type with initializer - check they match
             (_, Some(init)) => {
-                if let Some(init_type) = self.visit_expression(init) {
-                    if !variable.typ.is_equal(&init_type) {
-                        self.diagnostics_mut().error(format!(
-                            "Type mismatch for variable '{}': expected {:?}, found {:?}",
-                            variable.name, variable.typ, init_type
-                        ));
-                    }
+                if let Some(init_type) = self.visit_expression(init)
+                    && !variable.typ.is_equal(&init_type)
+                {
+                    self.diagnostics_mut().error(format!(
+                        "Type mismatch for variable '{}': expected {:?}, found {:?}",
+                        variable.name, variable.typ, init_type
+                    ));
                 }
             }
         }
@@ -174,17 +676,25 @@ impl Visitor for TypecheckingPass {
             Statement::Expression { expression, .. } => {
                 self.visit_expression(expression);
             }
+            Statement::Assert { condition, .. } => {
+                if let Some(cond_type) = self.visit_expression(condition)
+                    && !matches!(cond_type, Type::Base(BaseType::Bool))
+                {
+                    self.diagnostics_mut()
+                        .error(format!("assert condition must be bool, found {:?}", cond_type));
+                }
+            }
             Statement::Return { expression: maybe_expr, .. } => {
                 let expr_type = match maybe_expr {
                     Some(expr) => self.visit_expression(expr)?,
                     None => Type::Base(BaseType::Void),
                 };
 
-                let Some(expected_type) = self.current_function_return_type.clone() else {
-                    return None;
-                };
+                let expected_type = self.current_function_return_type.clone()?;
 
-                if !expr_type.is_equal(&expected_type) {
+                if !matches!(expected_type, Type::Base(BaseType::Auto))
+                    && !expr_type.is_equal(&expected_type)
+                {
                     self.diagnostics_mut().error(format!(
                         "Type mismatch in return statement: expected {:?}, found {:?}",
                         expected_type, expr_type
@@ -199,7 +709,27 @@ impl Visitor for TypecheckingPass {
                 self.visit_block(b);
                 self.scope_stack.pop();
             }
-            Statement::Assignment { left, typ, right, .. } => {
+            Statement::Assignment {
+                left, typ, right, span, ..
+            } => {
+                if let Some(t) = typ.as_mut()
+                    && !matches!(t, Type::Base(BaseType::Auto))
+                {
+                    self.resolve_type(t);
+                    // The type was unknown and already reported
+                    if matches!(t, Type::Base(BaseType::Auto)) {
+                        if let Some(r) = right.as_mut() {
+                            self.visit_expression(r);
+                        }
+                        self.add_variable_to_current_scope(Variable {
+                            name: left.clone(),
+                            typ: t.clone(),
+                            initializer: right.clone(),
+                        });
+                        return None;
+                    }
+                }
+
                 match typ.as_ref() {
                     // Declaration: check current scope only for redeclaration
                     Some(t) => {
@@ -213,9 +743,13 @@ impl Visitor for TypecheckingPass {
 
                         // Handle type checking based on type and initializer
                         match (t, right.as_mut()) {
-                            // Auto with initializer - infer type
+                            // Auto with initializer - infer type. If the initializer
+                            // has errors the variable stays 'auto', and uses of it
+                            // are not reported again.
                             (Type::Base(BaseType::Auto), Some(r)) => {
-                                let right_type = self.visit_expression(r)?;
+                                let right_type = self
+                                    .visit_expression(r)
+                                    .unwrap_or(Type::Base(BaseType::Auto));
                                 self.add_variable_to_current_scope(Variable {
                                     name: left.clone(),
                                     typ: right_type,
@@ -223,19 +757,32 @@ impl Visitor for TypecheckingPass {
                                 })
                             }
 
-                            // Auto without initializer - error
+                            // Auto without initializer - error. The variable stays
+                            // 'auto' until an assignment shows the type it needs.
                             (Type::Base(BaseType::Auto), None) => {
-                                self.diagnostics_mut().error(format!(
-                                    "Variable '{}' has type 'auto' but no initializer to infer type from",
-                                    left
-                                ));
+                                self.diagnostics.report_error(
+                                    Diagnostic::new(format!(
+                                        "Variable '{}' has type 'auto' but no initializer to infer type from",
+                                        left
+                                    ))
+                                    .with_location(self.current_source.clone(), *span),
+                                );
+                                let scope_id = self.scope_stack.last().unwrap().borrow().id;
+                                self.unannotated
+                                    .insert((scope_id, left.clone()), self.diagnostics.errors.len() - 1);
+                                self.add_variable_to_current_scope(Variable {
+                                    name: left.clone(),
+                                    typ: Type::Base(BaseType::Auto),
+                                    initializer: None,
+                                });
                                 return None;
                             }
 
                             // Concrete type with initializer - check match
                             (concrete_type, Some(r)) => {
-                                let right_type = self.visit_expression(r)?;
-                                if !concrete_type.is_equal(&right_type) {
+                                if let Some(right_type) = self.visit_expression(r)
+                                    && !concrete_type.is_equal(&right_type)
+                                {
                                     self.diagnostics_mut().error(format!(
                                         "Type mismatch for variable '{}': expected {:?}, found {:?}",
                                         left, concrete_type, right_type
@@ -261,13 +808,24 @@ impl Visitor for TypecheckingPass {
                     // Reassignment: check all scopes
                     None => {
                         let Some(var) = self.find_variable(left) else {
-                            self.diagnostics_mut()
-                                .error(format!("Cannot assign to undeclared variable '{}'", left));
+                            if self.unknown_variables.insert(left.clone()) {
+                                let candidates = self.visible_variables();
+                                self.report_unknown_name(
+                                    format!("Cannot assign to undeclared variable '{}'", left),
+                                    left,
+                                    *span,
+                                    &candidates,
+                                );
+                            }
                             return None;
                         };
 
                         if let Some(r) = right.as_mut() {
                             let right_type = self.visit_expression(r)?;
+                            if matches!(var.typ, Type::Base(BaseType::Auto)) {
+                                self.annotate_from_assignment(left, right_type);
+                                return None;
+                            }
                             if !var.typ.is_equal(&right_type) {
                                 self.diagnostics_mut().error(format!(
                                     "Type mismatch in assignment to '{}': expected {:?}, found {:?}",
@@ -287,11 +845,11 @@ impl Visitor for TypecheckingPass {
                 ..
             } => {
                 // Check that condition is bool
-                if let Some(cond_type) = self.visit_expression(condition) {
-                    if !matches!(cond_type, Type::Base(BaseType::Bool)) {
-                        self.diagnostics_mut()
-                            .error(format!("if condition must be bool, found {:?}", cond_type));
-                    }
+                if let Some(cond_type) = self.visit_expression(condition)
+                    && !matches!(cond_type, Type::Base(BaseType::Bool))
+                {
+                    self.diagnostics_mut()
+                        .error(format!("if condition must be bool, found {:?}", cond_type));
                 }
 
                 // Create and push scope for then block
@@ -310,24 +868,148 @@ impl Visitor for TypecheckingPass {
                     self.scope_stack.pop();
                 }
             }
-            Statement::While { condition, body, .. } => {
+            Statement::While {
+                condition,
+                body,
+                do_while,
+                ..
+            } => {
+                // Check the parts in source order, so errors come out in that order too
+                if *do_while {
+                    self.visit_loop_body(body);
+                }
+
                 // Check that condition is bool
-                if let Some(cond_type) = self.visit_expression(condition) {
-                    if !matches!(cond_type, Type::Base(BaseType::Bool)) {
+                if let Some(cond_type) = self.visit_expression(condition)
+                    && !matches!(cond_type, Type::Base(BaseType::Bool))
+                {
+                    self.diagnostics_mut().error(format!(
+                        "While condition must be bool, found {:?}",
+                        cond_type
+                    ));
+                }
+
+                if !*do_while {
+                    self.visit_loop_body(body);
+                }
+            }
+            Statement::Match { scrutinee, arms, .. } => {
+                let scrutinee_type = self.visit_expression(scrutinee);
+                // Newtypes are matched like their underlying type
+                let kind = match scrutinee_type.as_ref().map(Type::underlying) {
+                    Some(Type::Base(BaseType::Bool)) => Some(ScrutineeKind::Bool),
+                    Some(Type::Base(BaseType::F8 | BaseType::F16 | BaseType::F32 | BaseType::F64)) => {
+                        Some(ScrutineeKind::Number)
+                    }
+                    Some(Type::Enum { name, variants }) => {
+                        Some(ScrutineeKind::Enum(name.clone(), variants.clone()))
+                    }
+                    Some(other) => {
                         self.diagnostics_mut().error(format!(
-                            "While condition must be bool, found {:?}",
-                            cond_type
+                            "Match scrutinee must be a number, bool or enum, found {:?}",
+                            other
                         ));
+                        None
+                    }
+                    None => None,
+                };
+
+                let mut seen: Vec<Pattern> = Vec::new();
+                for arm in arms.iter_mut() {
+                    if seen.contains(&Pattern::Wildcard) || seen.contains(&arm.pattern) {
+                        self.diagnostics.report_warning(
+                            Diagnostic::new(format!("Unreachable match arm: {:?}", arm.pattern))
+                                .with_location(self.current_source.clone(), arm.span)
+                                .with_lint(Lint::UnreachableArm),
+                        );
+                    }
+                    match (&arm.pattern, &kind) {
+                        (Pattern::Wildcard, _)
+                        | (_, None)
+                        | (Pattern::Number(_), Some(ScrutineeKind::Number))
+                        | (Pattern::Boolean(_), Some(ScrutineeKind::Bool)) => {}
+                        (Pattern::Variant { enum_name, variant }, Some(ScrutineeKind::Enum(name, variants)))
+                            if enum_name == name =>
+                        {
+                            if !variants.contains(variant) {
+                                self.diagnostics_mut().error(format!(
+                                    "Enum '{}' has no variant '{}' (match arm at {})",
+                                    name, variant, arm.span
+                                ));
+                            }
+                        }
+                        _ => {
+                            self.diagnostics_mut().error(format!(
+                                "Match pattern {:?} does not match scrutinee type {:?}",
+                                arm.pattern,
+                                scrutinee_type.clone().unwrap()
+                            ));
+                        }
                     }
+                    seen.push(arm.pattern.clone());
+
+                    // Each arm body gets its own scope
+                    let arm_scope = Rc::new(RefCell::new(Scope::new(self.allocate_scope_id())));
+                    arm.body.scope = Some(Rc::clone(&arm_scope));
+                    self.scope_stack.push(arm_scope);
+                    self.visit_block(&mut arm.body);
+                    self.scope_stack.pop();
                 }
 
-                // Create and push scope for while body
-                let while_scope = Rc::new(RefCell::new(Scope::new(self.allocate_scope_id())));
-                body.scope = Some(Rc::clone(&while_scope));
-                self.scope_stack.push(while_scope);
-                self.visit_block(body);
-                self.scope_stack.pop();
+                // Only bools and enums can be covered without a wildcard
+                if seen.contains(&Pattern::Wildcard) {
+                    return None;
+                }
+                match kind {
+                    Some(ScrutineeKind::Bool) => {
+                        let missing: Vec<&str> = [(true, "'true'"), (false, "'false'")]
+                            .into_iter()
+                            .filter(|(value, _)| !seen.contains(&Pattern::Boolean(*value)))
+                            .map(|(_, name)| name)
+                            .collect();
+                        if !missing.is_empty() {
+                            self.diagnostics_mut().error(format!(
+                                "Non-exhaustive match on bool: missing {} arm",
+                                missing.join(" and ")
+                            ));
+                        }
+                    }
+                    Some(ScrutineeKind::Enum(name, variants)) => {
+                        let missing: Vec<String> = variants
+                            .into_iter()
+                            .filter(|variant| {
+                                !seen.contains(&Pattern::Variant {
+                                    enum_name: name.clone(),
+                                    variant: variant.clone(),
+                                })
+                            })
+                            .map(|variant| format!("'{}::{}'", name, variant))
+                            .collect();
+                        if !missing.is_empty() {
+                            self.diagnostics_mut().error(format!(
+                                "Non-exhaustive match on enum '{}': missing {} arm",
+                                name,
+                                missing.join(" and ")
+                            ));
+                        }
+                    }
+                    Some(ScrutineeKind::Number) => {
+                        self.diagnostics_mut().error(format!(
+                            "Non-exhaustive match on {:?}: add a '_' arm",
+                            scrutinee_type.unwrap()
+                        ));
+                    }
+                    None => {}
+                }
             }
+            Statement::Newtype { name, span, .. } | Statement::Enum { name, span, .. } => {
+                self.diagnostics_mut().error(format!(
+                    "Type '{}' at {} must be declared at the top level",
+                    name, span
+                ));
+            }
+            // Already reported by the parser
+            Statement::Error { .. } => {}
             _ => {
                 self.diagnostics_mut()
                     .error(format!("Unhandled statement type: {:?}", statement));
@@ -338,13 +1020,26 @@ impl Visitor for TypecheckingPass {
 
     fn visit_expression(&mut self, expression: &mut Expression) -> Self::Output {
         match expression {
-            Expression::Variable { name: identifier, typ, .. } => {
+            Expression::Variable {
+                name: identifier, typ, span, ..
+            } => {
                 if let Some(var) = self.find_variable(identifier) {
+                    // A variable whose type could not be inferred was already reported
+                    if matches!(var.typ, Type::Base(BaseType::Auto)) {
+                        return None;
+                    }
                     *typ = Some(var.typ.clone());
                     Some(var.typ)
                 } else {
-                    self.diagnostics_mut()
-                        .error(format!("Unknown variable: '{}'", identifier));
+                    if self.unknown_variables.insert(identifier.clone()) {
+                        let candidates = self.visible_variables();
+                        self.report_unknown_name(
+                            format!("Unknown variable: '{}'", identifier),
+                            identifier,
+                            *span,
+                            &candidates,
+                        );
+                    }
                     None
                 }
             }
@@ -360,6 +1055,9 @@ impl Visitor for TypecheckingPass {
             }
             Expression::UnaryOp { left, op, typ, .. } => {
                 let operand_type = self.visit_expression(left)?;
+                if !self.check_operator_bound(&operand_type, op) {
+                    return None;
+                }
                 match operand_type.unary_op_result(&op.tag) {
                     Some(result_type) => {
                         *typ = Some(result_type.clone());
@@ -377,23 +1075,58 @@ impl Visitor for TypecheckingPass {
             Expression::BinaryOp { left, op, right, typ, .. } => {
                 let left_type = self.visit_expression(left)?;
                 let right_type = self.visit_expression(right)?;
-
+                if !self.check_operator_bound(&left_type, op) {
+                    return None;
+                }
                 match left_type.binop_result(&op.tag, &right_type) {
                     Some(result_type) => {
                         *typ = Some(result_type.clone());
                         Some(result_type)
                     }
                     None => {
-                        self.diagnostics_mut().error(format!(
+                        let mut diagnostic = Diagnostic::new(format!(
                             "Type mismatch in binary operation: {:?} and {:?} are not compatible",
                             left_type, right_type
-                        ));
+                        ))
+                        .with_location(self.current_source.clone(), Span::merge(&left.span(), &right.span()));
+                        if let Some(suggestion) = operand_cast_suggestion(left, &left_type, right, &right_type) {
+                            diagnostic = diagnostic.with_suggestion(suggestion);
+                        }
+                        self.diagnostics.report_error(diagnostic);
                         None
                     }
                 }
             }
-            Expression::Call { identifier, args, typ, .. } => {
-                if let Some(func) = &mut self.find_function(identifier) {
+            Expression::Call {
+                identifier, args, typ, span, ..
+            } => {
+                if BranchHint::from_name(identifier.as_str()).is_some() {
+                    let [condition] = args.as_mut_slice() else {
+                        self.diagnostics_mut().error(format!(
+                            "Function '{}' at {} expects 1 argument, got {}",
+                            identifier,
+                            span,
+                            args.len()
+                        ));
+                        return None;
+                    };
+                    let condition_type = self.visit_expression(condition)?;
+                    if !matches!(condition_type, Type::Base(BaseType::Bool)) {
+                        self.diagnostics_mut().error(format!(
+                            "Function '{}' at {} expects a bool, found {:?}",
+                            identifier, span, condition_type
+                        ));
+                        return None;
+                    }
+                    *typ = Some(condition_type.clone());
+                    return Some(condition_type);
+                }
+                if let Some(func) = &mut self.find_function(identifier.as_str()) {
+                    // The number of arguments is unknown if one did not parse
+                    if args.iter().any(|arg| matches!(arg, Expression::Error { .. })) {
+                        return None;
+                    }
+
                     // Check argument count
                     if func.args.len() != args.len() {
                         self.diagnostics_mut().error(format!(
@@ -414,6 +1147,12 @@ impl Visitor for TypecheckingPass {
                         }
                     }
 
+                    // Generic calls go to the instantiation for their argument types
+                    if !func.type_params.is_empty() {
+                        *func = self.instantiate(func, &arg_types)?;
+                        *identifier = Symbol::intern(&func.name);
+                    }
+
                     // check types
                     for (param, arg_type) in func.args.iter().zip(arg_types.iter()) {
                         if !param.typ.is_equal(arg_type) {
@@ -424,15 +1163,214 @@ impl Visitor for TypecheckingPass {
                         }
                     }
 
+                    // A return type that is unknown was already reported
                     let return_type = func.return_type.clone();
+                    if matches!(return_type, Type::Base(BaseType::Auto)) {
+                        return None;
+                    }
                     *typ = Some(return_type.clone());
                     Some(return_type)
                 } else {
-                    self.diagnostics_mut()
-                        .error(format!("Unknown function: '{}'", identifier));
+                    if self.unknown_functions.insert(*identifier) {
+                        let candidates = self.visible_functions();
+                        self.report_unknown_name(
+                            format!("Unknown function: '{}'", identifier),
+                            identifier.as_str(),
+                            *span,
+                            &candidates,
+                        );
+                    }
                     None
                 }
             }
+            Expression::Variant {
+                enum_name,
+                variant,
+                typ,
+                span,
+                ..
+            } => {
+                let Some(enum_type @ Type::Enum { variants, .. }) = self.types.get(enum_name.as_str()) else {
+                    self.diagnostics
+                        .error(format!("Unknown enum '{}' at {}", enum_name, span));
+                    return None;
+                };
+                if !variants.contains(variant) {
+                    self.diagnostics.error(format!(
+                        "Enum '{}' has no variant '{}' at {}",
+                        enum_name, variant, span
+                    ));
+                    return None;
+                }
+                let enum_type = enum_type.clone();
+                *typ = Some(enum_type.clone());
+                Some(enum_type)
+            }
+            Expression::Cast {
+                expression: inner,
+                target,
+                typ,
+                ..
+            } => {
+                self.resolve_type(target);
+                let from = self.visit_expression(inner)?;
+                if matches!(target, Type::Base(BaseType::Auto)) {
+                    return None;
+                }
+
+                // Floats convert to each other. Otherwise one side must be the
+                // plain representation of the other, so two newtypes of the
+                // same type stay apart.
+                let is_newtype = |typ: &Type| matches!(typ, Type::Newtype { .. });
+                let is_float_conversion = from.float_width().is_some() && target.float_width().is_some();
+                if !from.is_equal(target)
+                    && !is_float_conversion
+                    && ((is_newtype(&from) && is_newtype(target))
+                        || !from.underlying().is_equal(target.underlying()))
+                {
+                    self.diagnostics_mut().error(format!(
+                        "Cannot cast {:?} to {:?}: casts only convert between float types, and between a newtype and its underlying type",
+                        from, target
+                    ));
+                    return None;
+                }
+                *typ = Some(target.clone());
+                Some(target.clone())
+            }
+            // Already reported by the parser
+            Expression::Error { .. } => None,
+        }
+    }
+}
+
+/// A cast that makes the operands of a binary operation agree: a number
+/// literal takes the float type of the other operand, otherwise the
+/// narrower of two floats is widened, and a plain value is wrapped in the
+/// newtype of the other operand
+fn operand_cast_suggestion(
+    left: &Expression,
+    left_type: &Type,
+    right: &Expression,
+    right_type: &Type,
+) -> Option<Suggestion> {
+    let is_literal = |operand: &Expression| matches!(operand, Expression::Number { .. });
+    let (operand, target, applicability) = match (left_type.float_width(), right_type.float_width()) {
+        (Some(_), Some(_)) if is_literal(right) => (right, left_type, Applicability::MachineApplicable),
+        (Some(_), Some(_)) if is_literal(left) => (left, right_type, Applicability::MachineApplicable),
+        (Some(l), Some(r)) if l < r => (left, right_type, Applicability::MachineApplicable),
+        (Some(l), Some(r)) if l > r => (right, left_type, Applicability::MachineApplicable),
+        _ => match (left_type, right_type) {
+            (Type::Newtype { .. }, plain) if left_type.underlying().is_equal(plain) => {
+                (right, left_type, Applicability::MaybeIncorrect)
+            }
+            (plain, Type::Newtype { .. }) if right_type.underlying().is_equal(plain) => {
+                (left, right_type, Applicability::MaybeIncorrect)
+            }
+            _ => return None,
+        },
+    };
+
+    // Casts bind tighter than binary operators
+    let span = operand.span();
+    let mut edits = Vec::new();
+    let cast = if matches!(operand, Expression::BinaryOp { .. }) {
+        edits.push(TextEdit::insert(span.start_row, span.start_column, "("));
+        format!(") as {}", target)
+    } else {
+        format!(" as {}", target)
+    };
+    edits.push(TextEdit::insert(span.end_row, span.end_column, &cast));
+    Some(Suggestion {
+        message: format!("cast the operand at {} to {} with 'as {}'", span, target, target),
+        edits,
+        applicability,
+    })
+}
+
+/// Returns true if `typ` refers to the type parameter `param`
+fn mentions(typ: &Type, param: &str) -> bool {
+    match typ {
+        Type::Named(name) => name == param,
+        Type::PointerType(inner) => mentions(inner, param),
+        Type::Base(_) | Type::Newtype { .. } | Type::Enum { .. } => false,
+    }
+}
+
+/// Binds the type parameters in `param` so it matches `arg`. Parts that
+/// are not type parameters are left for the argument check to compare.
+/// Fails with the parameter and its earlier binding on a conflict.
+fn unify(
+    param: &Type,
+    arg: &Type,
+    type_params: &[String],
+    bindings: &mut HashMap<String, Type>,
+) -> Result<(), (String, Type)> {
+    match (param, arg) {
+        (Type::Named(name), _) if type_params.contains(name) => match bindings.get(name) {
+            Some(bound) if !bound.is_equal(arg) => Err((name.clone(), bound.clone())),
+            Some(_) => Ok(()),
+            None => {
+                bindings.insert(name.clone(), arg.clone());
+                Ok(())
+            }
+        },
+        (Type::PointerType(param), Type::PointerType(arg)) => unify(param, arg, type_params, bindings),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the type parameters of a generic function with the types of
+/// one instantiation, and gives its nodes ids of their own
+struct Substitution {
+    diagnostics: DiagnosticCollector,
+    bindings: HashMap<String, Type>,
+    next_node_id: NodeId,
+}
+
+impl Substitution {
+    fn substitute(&self, typ: &mut Type) {
+        match typ {
+            Type::Named(name) => {
+                if let Some(bound) = self.bindings.get(name) {
+                    *typ = bound.clone();
+                }
+            }
+            Type::PointerType(inner) => self.substitute(inner),
+            Type::Base(_) | Type::Newtype { .. } | Type::Enum { .. } => {}
+        }
+    }
+}
+
+impl Visitor for Substitution {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        *statement.id_mut() = self.next_node_id.take();
+        self.walk_statement(statement);
+    }
+
+    fn visit_assignment(&mut self, typ: &mut Option<Type>, right: &mut Option<Box<Expression>>) {
+        if let Some(typ) = typ {
+            self.substitute(typ);
+        }
+        if let Some(right) = right {
+            self.visit_expression(right);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        *expression.id_mut() = self.next_node_id.take();
+        if let Expression::Cast { target, .. } = expression {
+            self.substitute(target);
         }
+        self.walk_expression(expression);
     }
 }
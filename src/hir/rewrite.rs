@@ -0,0 +1,173 @@
+//! Declarative rewrite rules over expressions.
+//!
+//! A [`Rule`] is data: a [`Pattern`] the expression must match, [`Guard`]s
+//! on what the pattern bound, and the [`Template`] to replace it with. The
+//! rules of a pass are kept in a table, so each can be read on its own and
+//! checked for the IEEE 754 cases it has to exclude, and the engine that
+//! applies them does not change when rules are added.
+//!
+//! Patterns bind names to the subexpressions they match. A name bound by
+//! [`Pattern::Variable`] twice matches only the same variable both times,
+//! which is how `x - x` is written.
+
+use crate::ast::{Expression, NodeId};
+use crate::frontend::TokenType;
+use crate::span::Span;
+use crate::types::{BaseType, Type};
+
+/// The shape an expression must have for a rule to apply
+#[derive(Debug)]
+pub enum Pattern {
+    /// Any expression, bound to the name
+    Any(&'static str),
+    /// A variable, bound to the name
+    Variable(&'static str),
+    /// A number literal of exactly this value, so `0` and `-0` differ
+    Number(f64),
+    Boolean(bool),
+    Binary(TokenType, &'static Pattern, &'static Pattern),
+    Unary(TokenType, &'static Pattern),
+}
+
+/// A condition on a bound expression for a rule to apply. The conditions
+/// on values hold for every expression under fast math.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Guard {
+    /// Never NaN or infinite
+    Finite(&'static str),
+    NotNan(&'static str),
+    NoNegativeZero(&'static str),
+    /// Never below zero, though it may be NaN
+    NonNegative(&'static str),
+    /// Free of side effects, so dropping it changes nothing
+    Pure(&'static str),
+}
+
+/// What a matched expression is replaced with
+#[derive(Debug)]
+pub enum Template {
+    /// The expression bound to the name
+    Bound(&'static str),
+    Number(f64),
+    Boolean(bool),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    /// What the rule does, as reported when it applies. `{x}` stands for
+    /// the name of the variable bound to `x`.
+    pub description: &'static str,
+    pub pattern: Pattern,
+    /// Conditions that must all hold
+    pub guards: &'static [Guard],
+    pub result: Template,
+}
+
+/// The outcome of applying a rule
+#[derive(Debug)]
+pub struct Rewrite {
+    pub expression: Expression,
+    /// The description of the rule with the names of the bound variables
+    pub description: String,
+}
+
+type Bindings<'e> = Vec<(&'static str, &'e Expression)>;
+
+impl Rule {
+    /// Rewrites the expression if it matches and every guard holds, as
+    /// `holds` tells for a guard and the expression it is on. A new literal
+    /// takes the span, type and id of the expression it replaces, which the
+    /// typechecker gave it already, and the casts it needs take their ids
+    /// from `next_node_id`.
+    pub fn apply(
+        &self,
+        expression: &Expression,
+        next_node_id: &mut NodeId,
+        holds: impl Fn(Guard, &Expression) -> bool,
+    ) -> Option<Rewrite> {
+        let mut bindings = Vec::new();
+        if !matches(&self.pattern, expression, &mut bindings) {
+            return None;
+        }
+        let bound = |name: &str| {
+            bindings
+                .iter()
+                .find(|(bound, _)| *bound == name)
+                .map(|(_, expression)| *expression)
+                .expect("rules only use names their pattern binds")
+        };
+        for &guard in self.guards {
+            let (Guard::Finite(name)
+            | Guard::NotNan(name)
+            | Guard::NoNegativeZero(name)
+            | Guard::NonNegative(name)
+            | Guard::Pure(name)) = guard;
+            if !holds(guard, bound(name)) {
+                return None;
+            }
+        }
+
+        let (span, typ, id) = (expression.span(), expression.typ().clone(), expression.id());
+        let expression = match self.result {
+            Template::Bound(name) => bound(name).clone(),
+            Template::Number(value) => number(value, span, typ, id, next_node_id),
+            Template::Boolean(value) => Expression::Boolean { value, span, typ, id },
+        };
+        let mut description = self.description.to_string();
+        for (name, expression) in &bindings {
+            if let Expression::Variable { name: variable, .. } = expression {
+                description = description.replace(&format!("{{{}}}", name), variable);
+            }
+        }
+        Some(Rewrite { expression, description })
+    }
+}
+
+/// A number literal of the given type. Literals are `f64`, so one of
+/// another float type or of a newtype is cast to it.
+fn number(value: f64, span: Span, typ: Option<Type>, id: NodeId, next_node_id: &mut NodeId) -> Expression {
+    let mut expression = Expression::Number { value, span, typ: Some(Type::Base(BaseType::F64)), id };
+    let Some(typ) = typ else {
+        return expression;
+    };
+    for target in [typ.underlying(), &typ] {
+        if !matches!(expression.typ(), Some(from) if from == target) {
+            expression = Expression::Cast {
+                expression: Box::new(expression),
+                target: target.clone(),
+                span,
+                typ: Some(target.clone()),
+                id: next_node_id.take(),
+            };
+        }
+    }
+    expression
+}
+
+fn matches<'e>(pattern: &Pattern, expression: &'e Expression, bindings: &mut Bindings<'e>) -> bool {
+    match (pattern, expression) {
+        (Pattern::Any(name), _) => {
+            bindings.push((name, expression));
+            true
+        }
+        (Pattern::Variable(name), Expression::Variable { name: variable, .. }) => {
+            match bindings.iter().find(|(bound, _)| bound == name) {
+                Some((_, Expression::Variable { name: earlier, .. })) => earlier == variable,
+                Some(_) => false,
+                None => {
+                    bindings.push((name, expression));
+                    true
+                }
+            }
+        }
+        (Pattern::Number(expected), Expression::Number { value, .. }) => expected.to_bits() == value.to_bits(),
+        (Pattern::Boolean(expected), Expression::Boolean { value, .. }) => expected == value,
+        (Pattern::Binary(tag, left, right), Expression::BinaryOp { left: l, op, right: r, .. }) => {
+            op.tag == *tag && matches(left, l, bindings) && matches(right, r, bindings)
+        }
+        (Pattern::Unary(tag, operand), Expression::UnaryOp { left, op, .. }) => {
+            op.tag == *tag && matches(operand, left, bindings)
+        }
+        _ => false,
+    }
+}
@@ -1,13 +1,93 @@
-use crate::ast::{Block, Expression, Program, Statement};
+use crate::ast::{Block, Expression, MatchArm, Program, Statement};
 use crate::types::{Function, Type, Variable};
+use std::ops::ControlFlow;
 
 // Re-export DiagnosticCollector for convenience
 pub use crate::diagnostics::DiagnosticCollector;
 
-/// Visitor trait for traversing the AST without mutation.
+/// Result type produced by visitor methods.
+///
+/// Default walks fold the results of visiting children together with
+/// [`VisitResult::combine`] and return early as soon as a result reports
+/// [`VisitResult::is_break`], so a visitor can both compute values bottom-up
+/// and stop the traversal once it has found what it is looking for.
+///
+/// # Example
+/// ```ignore
+/// // Stops at the first call expression instead of walking the whole body
+/// impl Visitor for FindCall {
+///     type Output = ControlFlow<String>;
+///
+///     fn visit_expression(&mut self, expression: &mut Expression) -> Self::Output {
+///         if let Expression::Call { identifier, .. } = expression {
+///             return ControlFlow::Break(identifier.to_string());
+///         }
+///         self.walk_expression(expression)
+///     }
+/// }
+/// ```
+pub trait VisitResult {
+    /// The result of a visit that produced nothing
+    fn output() -> Self;
+
+    /// Folds the result of visiting a child into the accumulated result
+    fn combine(self, child: Self) -> Self;
+
+    /// Returns true if the traversal should stop and return this result
+    fn is_break(&self) -> bool {
+        false
+    }
+}
+
+impl VisitResult for () {
+    fn output() -> Self {}
+
+    fn combine(self, _child: Self) -> Self {}
+}
+
+/// The last child that produced a value wins
+impl<T> VisitResult for Option<T> {
+    fn output() -> Self {
+        None
+    }
+
+    fn combine(self, child: Self) -> Self {
+        child.or(self)
+    }
+}
+
+/// `Break` stops the traversal and is handed back up to the caller
+impl<B> VisitResult for ControlFlow<B> {
+    fn output() -> Self {
+        ControlFlow::Continue(())
+    }
+
+    fn combine(self, child: Self) -> Self {
+        match self {
+            ControlFlow::Break(_) => self,
+            ControlFlow::Continue(()) => child,
+        }
+    }
+
+    fn is_break(&self) -> bool {
+        matches!(self, ControlFlow::Break(_))
+    }
+}
+
+/// Combines a child result into an accumulator, returning early on break
+macro_rules! visit_child {
+    ($acc:ident, $visit:expr) => {
+        $acc = $acc.combine($visit);
+        if $acc.is_break() {
+            return $acc;
+        }
+    };
+}
+
+/// Visitor trait for traversing the AST.
 pub trait Visitor {
     /// The type returned by visitor methods
-    type Output: Default;
+    type Output: VisitResult;
 
     /// Returns the diagnostic collector for this visitor
     fn diagnostics(&self) -> &DiagnosticCollector;
@@ -21,13 +101,14 @@ pub trait Visitor {
     }
 
     fn walk_program(&mut self, program: &mut Program) -> Self::Output {
+        let mut result = Self::Output::output();
         for global in &mut program.globals {
-            self.visit_variable(global);
+            visit_child!(result, self.visit_variable(global));
         }
         for function in &mut program.functions {
-            self.visit_function(function);
+            visit_child!(result, self.visit_function(function));
         }
-        Self::Output::default()
+        result
     }
 
     // Function
@@ -36,12 +117,13 @@ pub trait Visitor {
     }
 
     fn walk_function(&mut self, function: &mut Function) -> Self::Output {
+        let mut result = Self::Output::output();
         for arg in &mut function.args {
-            self.visit_variable(arg);
+            visit_child!(result, self.visit_variable(arg));
         }
-        self.visit_type(&mut function.return_type);
-        self.visit_block(&mut function.body);
-        Self::Output::default()
+        visit_child!(result, self.visit_type(&mut function.return_type));
+        visit_child!(result, self.visit_block(&mut function.body));
+        result
     }
 
     // Variable
@@ -50,17 +132,18 @@ pub trait Visitor {
     }
 
     fn walk_variable(&mut self, variable: &mut Variable) -> Self::Output {
-        self.visit_type(&mut variable.typ);
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_type(&mut variable.typ));
         if let Some(init) = &mut variable.initializer {
-            self.visit_expression(init);
+            visit_child!(result, self.visit_expression(init));
         }
-        Self::Output::default()
+        result
     }
 
     // Type
     fn visit_type(&mut self, _typ: &mut Type) -> Self::Output {
         // Default: do nothing, types are typically leaves
-        Self::Output::default()
+        Self::Output::output()
     }
 
     // Block
@@ -69,10 +152,11 @@ pub trait Visitor {
     }
 
     fn walk_block(&mut self, block: &mut Block) -> Self::Output {
+        let mut result = Self::Output::output();
         for statement in &mut block.statements {
-            self.visit_statement(statement);
+            visit_child!(result, self.visit_statement(statement));
         }
-        Self::Output::default()
+        result
     }
 
     // Statements
@@ -100,51 +184,73 @@ pub trait Visitor {
             Statement::Return { expression, .. } => {
                 self.visit_return(expression)
             }
+            Statement::Match { scrutinee, arms, .. } => {
+                self.visit_match(scrutinee, arms)
+            }
             Statement::Expression { expression, .. } => {
                 self.visit_expression_statement(expression)
             }
+            Statement::Assert { condition, .. } => {
+                self.visit_assert(condition)
+            }
+            // Type declarations have no expressions to visit
+            Statement::Newtype { .. } | Statement::Enum { .. } => Self::Output::output(),
+            Statement::Error { .. } => Self::Output::output(),
         }
     }
 
     fn visit_assignment(&mut self, typ: &mut Option<Type>, right: &mut Option<Box<Expression>>) -> Self::Output {
+        let mut result = Self::Output::output();
         if let Some(t) = typ {
-            self.visit_type(t);
+            visit_child!(result, self.visit_type(t));
         }
         if let Some(expr) = right {
-            self.visit_expression(expr);
+            visit_child!(result, self.visit_expression(expr));
         }
-        Self::Output::default()
+        result
     }
 
     fn visit_function_definition(&mut self, args: &mut [Variable], return_type: &mut Type, body: &mut Block) -> Self::Output {
+        let mut result = Self::Output::output();
         for arg in args.iter_mut() {
-            self.visit_variable(arg);
+            visit_child!(result, self.visit_variable(arg));
         }
-        self.visit_type(return_type);
-        self.visit_block(body);
-        Self::Output::default()
+        visit_child!(result, self.visit_type(return_type));
+        visit_child!(result, self.visit_block(body));
+        result
     }
 
     fn visit_if(&mut self, condition: &mut Expression, then: &mut Block, els: &mut Option<Block>) -> Self::Output {
-        self.visit_expression(condition);
-        self.visit_block(then);
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(condition));
+        visit_child!(result, self.visit_block(then));
         if let Some(else_block) = els {
-            self.visit_block(else_block);
+            visit_child!(result, self.visit_block(else_block));
         }
-        Self::Output::default()
+        result
     }
 
     fn visit_while(&mut self, condition: &mut Expression, body: &mut Block) -> Self::Output {
-        self.visit_expression(condition);
-        self.visit_block(body);
-        Self::Output::default()
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(condition));
+        visit_child!(result, self.visit_block(body));
+        result
+    }
+
+    fn visit_match(&mut self, scrutinee: &mut Expression, arms: &mut [MatchArm]) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(scrutinee));
+        for arm in arms.iter_mut() {
+            visit_child!(result, self.visit_block(&mut arm.body));
+        }
+        result
     }
 
     fn visit_return(&mut self, expr: &mut Option<Box<Expression>>) -> Self::Output {
         if let Some(e) = expr {
             self.visit_expression(e)
         } else {
-            Self::Output::default()
+            Self::Output::output()
         }
     }
 
@@ -152,6 +258,10 @@ pub trait Visitor {
         self.visit_expression(expr)
     }
 
+    fn visit_assert(&mut self, condition: &mut Expression) -> Self::Output {
+        self.visit_expression(condition)
+    }
+
     // Expressions
     fn visit_expression(&mut self, expression: &mut Expression) -> Self::Output {
         self.walk_expression(expression)
@@ -177,39 +287,302 @@ pub trait Visitor {
             Expression::Variable { .. } => {
                 self.visit_variable_expr()
             }
+            Expression::Variant { .. } => Self::Output::output(),
+            Expression::Cast { expression, .. } => {
+                self.visit_cast(expression)
+            }
+            Expression::Error { .. } => Self::Output::output(),
         }
     }
 
     fn visit_number(&mut self, _n: f64) -> Self::Output {
         // Default: do nothing, numbers are leaves
-        Self::Output::default()
+        Self::Output::output()
     }
 
     fn visit_boolean(&mut self, _b: bool) -> Self::Output {
         // Default: do nothing, booleans are leaves
-        Self::Output::default()
+        Self::Output::output()
     }
 
     fn visit_binary_op(&mut self, left: &mut Expression, right: &mut Expression) -> Self::Output {
-        self.visit_expression(left);
-        self.visit_expression(right);
-        Self::Output::default()
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(left));
+        visit_child!(result, self.visit_expression(right));
+        result
     }
 
     fn visit_unary_op(&mut self, operand: &mut Expression) -> Self::Output {
-        self.visit_expression(operand);
-        Self::Output::default()
+        self.visit_expression(operand)
+    }
+
+    fn visit_cast(&mut self, expression: &mut Expression) -> Self::Output {
+        self.visit_expression(expression)
     }
 
     fn visit_call(&mut self, args: &mut [Expression]) -> Self::Output {
+        let mut result = Self::Output::output();
         for arg in args.iter_mut() {
-            self.visit_expression(arg);
+            visit_child!(result, self.visit_expression(arg));
+        }
+        result
+    }
+
+    fn visit_variable_expr(&mut self) -> Self::Output {
+        // Default: do nothing, variable references are leaves
+        Self::Output::output()
+    }
+}
+
+/// Read-only counterpart of [`Visitor`] for analysis passes.
+///
+/// Mirrors the same visit/walk structure over shared references, so passes
+/// that only inspect the tree can run on a borrowed snapshot of the program.
+pub trait VisitorRef {
+    /// The type returned by visitor methods
+    type Output: VisitResult;
+
+    /// Returns the diagnostic collector for this visitor
+    fn diagnostics(&self) -> &DiagnosticCollector;
+
+    /// Returns a mutable reference to the diagnostic collector
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector;
+
+    // Program and top-level
+    fn visit_program(&mut self, program: &Program) -> Self::Output {
+        self.walk_program(program)
+    }
+
+    fn walk_program(&mut self, program: &Program) -> Self::Output {
+        let mut result = Self::Output::output();
+        for global in &program.globals {
+            visit_child!(result, self.visit_variable(global));
+        }
+        for function in &program.functions {
+            visit_child!(result, self.visit_function(function));
+        }
+        result
+    }
+
+    // Function
+    fn visit_function(&mut self, function: &Function) -> Self::Output {
+        self.walk_function(function)
+    }
+
+    fn walk_function(&mut self, function: &Function) -> Self::Output {
+        let mut result = Self::Output::output();
+        for arg in &function.args {
+            visit_child!(result, self.visit_variable(arg));
+        }
+        visit_child!(result, self.visit_type(&function.return_type));
+        visit_child!(result, self.visit_block(&function.body));
+        result
+    }
+
+    // Variable
+    fn visit_variable(&mut self, variable: &Variable) -> Self::Output {
+        self.walk_variable(variable)
+    }
+
+    fn walk_variable(&mut self, variable: &Variable) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_type(&variable.typ));
+        if let Some(init) = &variable.initializer {
+            visit_child!(result, self.visit_expression(init));
+        }
+        result
+    }
+
+    // Type
+    fn visit_type(&mut self, _typ: &Type) -> Self::Output {
+        // Default: do nothing, types are typically leaves
+        Self::Output::output()
+    }
+
+    // Block
+    fn visit_block(&mut self, block: &Block) -> Self::Output {
+        self.walk_block(block)
+    }
+
+    fn walk_block(&mut self, block: &Block) -> Self::Output {
+        let mut result = Self::Output::output();
+        for statement in &block.statements {
+            visit_child!(result, self.visit_statement(statement));
+        }
+        result
+    }
+
+    // Statements
+    fn visit_statement(&mut self, statement: &Statement) -> Self::Output {
+        self.walk_statement(statement)
+    }
+
+    fn walk_statement(&mut self, statement: &Statement) -> Self::Output {
+        match statement {
+            Statement::Assignment { typ, right, .. } => {
+                self.visit_assignment(typ, right)
+            }
+            Statement::FunctionDefinition { args, return_type, body, .. } => {
+                self.visit_function_definition(args, return_type, body)
+            }
+            Statement::If { condition, then, els, .. } => {
+                self.visit_if(condition, then, els)
+            }
+            Statement::While { condition, body, .. } => {
+                self.visit_while(condition, body)
+            }
+            Statement::Block { block, .. } => {
+                self.visit_block(block)
+            }
+            Statement::Return { expression, .. } => {
+                self.visit_return(expression)
+            }
+            Statement::Match { scrutinee, arms, .. } => {
+                self.visit_match(scrutinee, arms)
+            }
+            Statement::Expression { expression, .. } => {
+                self.visit_expression_statement(expression)
+            }
+            Statement::Assert { condition, .. } => {
+                self.visit_assert(condition)
+            }
+            // Type declarations have no expressions to visit
+            Statement::Newtype { .. } | Statement::Enum { .. } => Self::Output::output(),
+            Statement::Error { .. } => Self::Output::output(),
+        }
+    }
+
+    fn visit_assignment(&mut self, typ: &Option<Type>, right: &Option<Box<Expression>>) -> Self::Output {
+        let mut result = Self::Output::output();
+        if let Some(t) = typ {
+            visit_child!(result, self.visit_type(t));
+        }
+        if let Some(expr) = right {
+            visit_child!(result, self.visit_expression(expr));
+        }
+        result
+    }
+
+    fn visit_function_definition(&mut self, args: &[Variable], return_type: &Type, body: &Block) -> Self::Output {
+        let mut result = Self::Output::output();
+        for arg in args.iter() {
+            visit_child!(result, self.visit_variable(arg));
+        }
+        visit_child!(result, self.visit_type(return_type));
+        visit_child!(result, self.visit_block(body));
+        result
+    }
+
+    fn visit_if(&mut self, condition: &Expression, then: &Block, els: &Option<Block>) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(condition));
+        visit_child!(result, self.visit_block(then));
+        if let Some(else_block) = els {
+            visit_child!(result, self.visit_block(else_block));
+        }
+        result
+    }
+
+    fn visit_while(&mut self, condition: &Expression, body: &Block) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(condition));
+        visit_child!(result, self.visit_block(body));
+        result
+    }
+
+    fn visit_match(&mut self, scrutinee: &Expression, arms: &[MatchArm]) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(scrutinee));
+        for arm in arms.iter() {
+            visit_child!(result, self.visit_block(&arm.body));
+        }
+        result
+    }
+
+    fn visit_return(&mut self, expr: &Option<Box<Expression>>) -> Self::Output {
+        if let Some(e) = expr {
+            self.visit_expression(e)
+        } else {
+            Self::Output::output()
+        }
+    }
+
+    fn visit_expression_statement(&mut self, expr: &Expression) -> Self::Output {
+        self.visit_expression(expr)
+    }
+
+    fn visit_assert(&mut self, condition: &Expression) -> Self::Output {
+        self.visit_expression(condition)
+    }
+
+    // Expressions
+    fn visit_expression(&mut self, expression: &Expression) -> Self::Output {
+        self.walk_expression(expression)
+    }
+
+    fn walk_expression(&mut self, expression: &Expression) -> Self::Output {
+        match expression {
+            Expression::Number { value, .. } => {
+                self.visit_number(*value)
+            }
+            Expression::Boolean { value, .. } => {
+                self.visit_boolean(*value)
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.visit_binary_op(left, right)
+            }
+            Expression::UnaryOp { left, .. } => {
+                self.visit_unary_op(left)
+            }
+            Expression::Call { args, .. } => {
+                self.visit_call(args)
+            }
+            Expression::Variable { .. } => {
+                self.visit_variable_expr()
+            }
+            Expression::Variant { .. } => Self::Output::output(),
+            Expression::Cast { expression, .. } => {
+                self.visit_cast(expression)
+            }
+            Expression::Error { .. } => Self::Output::output(),
+        }
+    }
+
+    fn visit_number(&mut self, _n: f64) -> Self::Output {
+        // Default: do nothing, numbers are leaves
+        Self::Output::output()
+    }
+
+    fn visit_boolean(&mut self, _b: bool) -> Self::Output {
+        // Default: do nothing, booleans are leaves
+        Self::Output::output()
+    }
+
+    fn visit_binary_op(&mut self, left: &Expression, right: &Expression) -> Self::Output {
+        let mut result = Self::Output::output();
+        visit_child!(result, self.visit_expression(left));
+        visit_child!(result, self.visit_expression(right));
+        result
+    }
+
+    fn visit_unary_op(&mut self, operand: &Expression) -> Self::Output {
+        self.visit_expression(operand)
+    }
+
+    fn visit_cast(&mut self, expression: &Expression) -> Self::Output {
+        self.visit_expression(expression)
+    }
+
+    fn visit_call(&mut self, args: &[Expression]) -> Self::Output {
+        let mut result = Self::Output::output();
+        for arg in args.iter() {
+            visit_child!(result, self.visit_expression(arg));
         }
-        Self::Output::default()
+        result
     }
 
     fn visit_variable_expr(&mut self) -> Self::Output {
         // Default: do nothing, variable references are leaves
-        Self::Output::default()
+        Self::Output::output()
     }
 }
@@ -0,0 +1,103 @@
+//! String interning for names that are compared and copied often.
+//!
+//! Interning a string returns a [`Symbol`], a small `Copy` handle that
+//! compares and hashes as an integer. Symbols are backed by a single
+//! process-wide table, so the same name interned by the frontend and by MIR
+//! passes always yields the same symbol.
+//!
+//! # Example
+//! ```ignore
+//! let main = Symbol::intern("main");
+//! assert_eq!(main, Symbol::intern("main"));
+//! assert_eq!(main.as_str(), "main");
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+
+/// An interned string
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Interns `name`, returning the existing symbol if it was seen before
+    pub fn intern(name: &str) -> Symbol {
+        interner().intern(name)
+    }
+
+    /// Looks up the symbol for `name` without interning it
+    pub fn get(name: &str) -> Option<Symbol> {
+        interner().get(name)
+    }
+
+    /// The string this symbol was interned from
+    pub fn as_str(self) -> &'static str {
+        interner().resolve(self)
+    }
+
+    /// Position of the symbol in the interner, in order of first interning
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+/// Table mapping strings to symbols and back.
+///
+/// Interned strings are leaked so that [`Symbol::as_str`] can hand out
+/// `'static` references; each distinct string is stored once.
+#[derive(Debug, Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        self.strings.push(name);
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    /// Looks up a string without interning it
+    fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    fn resolve(&self, symbol: Symbol) -> &'static str {
+        self.strings[symbol.index()]
+    }
+}
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Default::default);
+
+fn interner() -> std::sync::MutexGuard<'static, Interner> {
+    // The table is only ever appended to, so it stays consistent even if a
+    // thread panicked while holding the lock
+    INTERNER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -4,10 +4,24 @@
 //! including lexical analysis, parsing, and code generation.
 
 pub mod span;
+pub mod intern;
 pub mod frontend;
 pub mod ast;
 pub mod types;
 pub mod diagnostics;
+pub mod fix;
+pub mod json;
+pub mod manifest;
+pub mod compiler;
+pub mod registry;
 pub mod cli;
+pub mod testing;
+pub mod testgen;
+pub mod embed;
+pub mod value;
+pub mod range;
+pub mod coverage;
+pub mod term;
 pub mod hir;
 pub mod mir;
+pub mod codegen;
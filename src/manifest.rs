@@ -0,0 +1,99 @@
+//! Manifest of the files a compilation wrote.
+//!
+//! With `--out-dir`, every emitted stage is written to a file and a
+//! `manifest.json` next to them lists the inputs they were compiled from and
+//! the files produced, with the stage and a hash of each. Build systems read
+//! it to learn what Iris produced without knowing how outputs are named,
+//! and compare hashes to tell whether a file changed.
+
+use crate::json;
+
+/// Name of the manifest written into the output directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Version of the manifest format, raised when fields change meaning
+const MANIFEST_VERSION: u32 = 1;
+
+/// A file read or written by the compilation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String,
+    /// The stage that produced the file, as named by `--emit`, or
+    /// `executable`. `None` for inputs.
+    pub stage: Option<&'static str>,
+    pub bytes: usize,
+    pub hash: u64,
+}
+
+impl ManifestEntry {
+    fn new(path: impl Into<String>, stage: Option<&'static str>, contents: &[u8]) -> Self {
+        ManifestEntry {
+            path: path.into(),
+            stage,
+            bytes: contents.len(),
+            hash: content_hash(contents),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let stage = match self.stage {
+            Some(stage) => format!("\"stage\":{},", json::string(stage)),
+            None => String::new(),
+        };
+        format!(
+            "{{\"path\":{},{}\"bytes\":{},\"hash\":\"fnv1a64:{:016x}\"}}",
+            json::string(&self.path),
+            stage,
+            self.bytes,
+            self.hash
+        )
+    }
+}
+
+/// The inputs and outputs of one compilation
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub inputs: Vec<ManifestEntry>,
+    pub outputs: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_input(&mut self, path: impl Into<String>, contents: &[u8]) {
+        self.inputs.push(ManifestEntry::new(path, None, contents));
+    }
+
+    pub fn add_output(&mut self, path: impl Into<String>, stage: &'static str, contents: &[u8]) {
+        self.outputs.push(ManifestEntry::new(path, Some(stage), contents));
+    }
+
+    pub fn to_json(&self) -> String {
+        let list = |entries: &[ManifestEntry]| {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|entry| format!("\n    {}", entry.to_json()))
+                .collect();
+            format!("[{}\n  ]", entries.join(","))
+        };
+        format!(
+            "{{\n  \"version\": {},\n  \"inputs\": {},\n  \"outputs\": {}\n}}\n",
+            MANIFEST_VERSION,
+            list(&self.inputs),
+            list(&self.outputs)
+        )
+    }
+}
+
+/// 64-bit FNV-1a hash of the contents of a file. It is stable across
+/// platforms and releases, which is all tracking changes needs; it is not
+/// meant to resist tampering.
+pub fn content_hash(contents: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    contents
+        .iter()
+        .fold(OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
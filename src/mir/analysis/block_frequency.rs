@@ -0,0 +1,193 @@
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::{BlockId, MirFunction, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// Most of a loop's iterations are assumed to go around again, but never
+/// all of them, or the loop would never be left
+const MAX_CYCLIC_PROBABILITY: f64 = 0.999;
+
+/// How often each block runs per call of its function, estimated from the
+/// branch weights and the shape of the CFG
+///
+/// Both edges of a conditional branch without weights are taken equally
+/// often, and so are all the edges of a switch. Loops are handled as
+/// described by Wu and Larus: every loop, innermost first, is entered once
+/// with its header running once, which gives the probability of coming back
+/// around its back edges, and so how many times it iterates when entered.
+#[derive(Debug)]
+pub struct BlockFrequency {
+    frequencies: HashMap<BlockId, f64>,
+}
+
+/// The probability of each outgoing edge of a terminator. Edges to the same
+/// block are added together.
+pub fn successor_probabilities(terminator: &Terminator) -> Vec<(BlockId, f64)> {
+    let edges: Vec<(BlockId, f64)> = match terminator {
+        Terminator::Br { target, .. } => vec![(*target, 1.0)],
+        Terminator::BrIf {
+            then_bb,
+            else_bb,
+            weights,
+            ..
+        } => {
+            let then = weights.map_or(0.5, |weights| weights.then_probability());
+            vec![(*then_bb, then), (*else_bb, 1.0 - then)]
+        }
+        Terminator::Switch { cases, default, .. } => {
+            let share = 1.0 / (cases.len() + 1) as f64;
+            cases
+                .iter()
+                .map(|case| (case.target, share))
+                .chain(std::iter::once((*default, share)))
+                .collect()
+        }
+        Terminator::Ret { .. } | Terminator::Trap { .. } | Terminator::Unreachable => Vec::new(),
+    };
+    let mut merged: Vec<(BlockId, f64)> = Vec::new();
+    for (target, probability) in edges {
+        match merged.iter_mut().find(|(existing, _)| *existing == target) {
+            Some((_, total)) => *total += probability,
+            None => merged.push((target, probability)),
+        }
+    }
+    merged
+}
+
+impl BlockFrequency {
+    pub fn compute(function: &MirFunction) -> Self {
+        let cfg = CFGAnalysis::new(function);
+        let (order, back_edges) = depth_first(function, &cfg);
+        let position: HashMap<BlockId, usize> = order.iter().enumerate().map(|(i, &block)| (block, i)).collect();
+
+        // The body of the loop each back edge closes: the blocks that reach
+        // its source without passing through the header
+        let mut loops: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+        for &(source, header) in &back_edges {
+            let body = loops.entry(header).or_insert_with(|| HashSet::from([header]));
+            let mut worklist = vec![source];
+            while let Some(block) = worklist.pop() {
+                if body.insert(block) {
+                    worklist.extend(cfg.predecessors[&block].iter().filter(|pred| position.contains_key(pred)));
+                }
+            }
+        }
+        // An inner loop's body is a strict subset of the loops around it
+        let mut headers: Vec<BlockId> = loops.keys().copied().collect();
+        headers.sort_by_key(|header| (loops[header].len(), position[header]));
+
+        let mut propagation = Propagation {
+            function,
+            cfg: &cfg,
+            order: &order,
+            back_edges: &back_edges,
+            frequencies: HashMap::new(),
+            back_edge_probabilities: HashMap::new(),
+        };
+        for header in &headers {
+            propagation.run(*header, &loops[header]);
+        }
+        let reachable: HashSet<BlockId> = order.iter().copied().collect();
+        propagation.run(function.entry, &reachable);
+
+        let mut frequencies = propagation.frequencies;
+        // The entry runs once per call even if a loop starts there
+        if let Some(cyclic) = propagation
+            .back_edge_probabilities
+            .iter()
+            .filter(|((_, header), _)| *header == function.entry)
+            .map(|(_, probability)| *probability)
+            .reduce(|a, b| a + b)
+        {
+            let scale = 1.0 / (1.0 - cyclic.min(MAX_CYCLIC_PROBABILITY));
+            for frequency in frequencies.values_mut() {
+                *frequency *= scale;
+            }
+        }
+        BlockFrequency { frequencies }
+    }
+
+    /// How many times the block is expected to run per call of the
+    /// function, 0 if it is unreachable
+    pub fn frequency(&self, block: BlockId) -> f64 {
+        self.frequencies.get(&block).copied().unwrap_or(0.0)
+    }
+}
+
+struct Propagation<'a> {
+    function: &'a MirFunction,
+    cfg: &'a CFGAnalysis,
+    /// Reachable blocks in reverse postorder
+    order: &'a [BlockId],
+    back_edges: &'a HashSet<(BlockId, BlockId)>,
+    frequencies: HashMap<BlockId, f64>,
+    /// For each back edge, how likely it is taken per run of its header
+    back_edge_probabilities: HashMap<(BlockId, BlockId), f64>,
+}
+
+impl Propagation<'_> {
+    /// Frequencies of the blocks of a region relative to a single run of
+    /// its head. Reverse postorder visits every block after the blocks that
+    /// reach it along forward edges.
+    fn run(&mut self, head: BlockId, region: &HashSet<BlockId>) {
+        let mut edge_frequencies: HashMap<(BlockId, BlockId), f64> = HashMap::new();
+        for &block in self.order.iter().filter(|block| region.contains(block)) {
+            let frequency = if block == head {
+                1.0
+            } else {
+                let mut incoming = 0.0;
+                let mut cyclic = 0.0;
+                // A block reached twice from the same predecessor already has
+                // both edges added together
+                let preds: HashSet<BlockId> = self.cfg.predecessors[&block].iter().copied().collect();
+                for pred in preds {
+                    if self.back_edges.contains(&(pred, block)) {
+                        cyclic += self.back_edge_probabilities.get(&(pred, block)).copied().unwrap_or(0.0);
+                    } else {
+                        incoming += edge_frequencies.get(&(pred, block)).copied().unwrap_or(0.0);
+                    }
+                }
+                incoming / (1.0 - f64::min(cyclic, MAX_CYCLIC_PROBABILITY))
+            };
+            self.frequencies.insert(block, frequency);
+
+            for (target, probability) in successor_probabilities(&self.function.block(block).terminator) {
+                let edge_frequency = frequency * probability;
+                if target == head {
+                    self.back_edge_probabilities.insert((block, head), edge_frequency);
+                } else {
+                    *edge_frequencies.entry((block, target)).or_insert(0.0) += edge_frequency;
+                }
+            }
+        }
+    }
+}
+
+/// The blocks reachable from the entry in reverse postorder, and the edges
+/// that lead back to a block still being visited
+fn depth_first(function: &MirFunction, cfg: &CFGAnalysis) -> (Vec<BlockId>, HashSet<(BlockId, BlockId)>) {
+    let mut postorder = Vec::new();
+    let mut back_edges = HashSet::new();
+    let mut visited = HashSet::from([function.entry]);
+    let mut on_stack = HashSet::from([function.entry]);
+    // Each entry is a block and how many of its successors were visited
+    let mut stack = vec![(function.entry, 0)];
+    while let Some((block, next)) = stack.last_mut() {
+        let block = *block;
+        let successors = &cfg.successors[&block];
+        if let Some(&successor) = successors.get(*next) {
+            *next += 1;
+            if on_stack.contains(&successor) {
+                back_edges.insert((block, successor));
+            } else if visited.insert(successor) {
+                on_stack.insert(successor);
+                stack.push((successor, 0));
+            }
+        } else {
+            stack.pop();
+            on_stack.remove(&block);
+            postorder.push(block);
+        }
+    }
+    postorder.reverse();
+    (postorder, back_edges)
+}
@@ -0,0 +1,174 @@
+use crate::mir::{FuncId, MirFunction, MirProgram, Opcode, Operand};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Which functions call which, built from the `Call` instructions of a program
+#[derive(Debug)]
+pub struct CallGraph {
+    /// Functions defined in the program, in program order
+    pub functions: Vec<FuncId>,
+    /// Distinct callees of every defined function, in order of first call
+    callees: HashMap<FuncId, Vec<FuncId>>,
+    /// Distinct callers of every function, in program order
+    callers: HashMap<FuncId, Vec<FuncId>>,
+}
+
+impl CallGraph {
+    pub fn new(program: &MirProgram) -> Self {
+        let functions: Vec<FuncId> = program.functions.iter().map(|f| f.name).collect();
+        let mut callees: HashMap<FuncId, Vec<FuncId>> = HashMap::new();
+        let mut callers: HashMap<FuncId, Vec<FuncId>> = HashMap::new();
+
+        for function in &program.functions {
+            let targets = callees.entry(function.name).or_default();
+            for callee in call_targets(function) {
+                if !targets.contains(&callee) {
+                    targets.push(callee);
+                    callers.entry(callee).or_default().push(function.name);
+                }
+            }
+        }
+
+        CallGraph {
+            functions,
+            callees,
+            callers,
+        }
+    }
+
+    /// Functions called directly by `function`
+    pub fn callees(&self, function: FuncId) -> &[FuncId] {
+        self.callees.get(&function).map_or(&[], Vec::as_slice)
+    }
+
+    /// Functions that call `function` directly
+    pub fn callers(&self, function: FuncId) -> &[FuncId] {
+        self.callers.get(&function).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns true if the function is defined in the program rather than
+    /// only referenced by a call
+    pub fn is_defined(&self, function: FuncId) -> bool {
+        self.callees.contains_key(&function)
+    }
+
+    /// Every function reachable through calls from the given roots, roots included
+    pub fn reachable_from(&self, roots: impl IntoIterator<Item = FuncId>) -> BTreeSet<FuncId> {
+        let mut reachable = BTreeSet::new();
+        let mut worklist: Vec<FuncId> = roots.into_iter().collect();
+        while let Some(function) = worklist.pop() {
+            if reachable.insert(function) {
+                worklist.extend(self.callees(function));
+            }
+        }
+        reachable
+    }
+
+    /// Strongly connected components of the graph, computed with Tarjan's
+    /// algorithm. Components come out bottom-up: every component is listed
+    /// after the components it calls into, which is the order an inliner or
+    /// an interprocedural analysis wants to visit functions in.
+    pub fn sccs(&self) -> Vec<Vec<FuncId>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            next_index: 0,
+            index: HashMap::new(),
+            lowlink: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            components: Vec::new(),
+        };
+        for &function in &self.functions {
+            if !tarjan.index.contains_key(&function) {
+                tarjan.visit(function);
+            }
+        }
+        tarjan.components
+    }
+
+    /// Defined functions in bottom-up order, callees before their callers
+    /// except within a recursive cycle
+    pub fn bottom_up_order(&self) -> Vec<FuncId> {
+        self.sccs().into_iter().flatten().collect()
+    }
+
+    /// Components whose functions can call themselves, directly or through
+    /// each other
+    pub fn recursive_sccs(&self) -> Vec<Vec<FuncId>> {
+        self.sccs()
+            .into_iter()
+            .filter(|component| match component.as_slice() {
+                [function] => self.callees(*function).contains(function),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Returns true if the function is part of a recursive cycle
+    pub fn is_recursive(&self, function: FuncId) -> bool {
+        self.recursive_sccs()
+            .iter()
+            .any(|component| component.contains(&function))
+    }
+}
+
+/// Names of the functions called by a function, in instruction order
+fn call_targets(function: &MirFunction) -> impl Iterator<Item = FuncId> + '_ {
+    function
+        .arena
+        .iter()
+        .flat_map(|(_, block)| &block.instructions)
+        .filter(|instruction| matches!(instruction.op, Opcode::Call))
+        .filter_map(|instruction| match instruction.args.first() {
+            Some(Operand::Label(callee)) => Some(*callee),
+            _ => None,
+        })
+}
+
+struct Tarjan<'g> {
+    graph: &'g CallGraph,
+    next_index: usize,
+    index: HashMap<FuncId, usize>,
+    lowlink: HashMap<FuncId, usize>,
+    stack: Vec<FuncId>,
+    on_stack: HashSet<FuncId>,
+    components: Vec<Vec<FuncId>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, function: FuncId) {
+        self.index.insert(function, self.next_index);
+        self.lowlink.insert(function, self.next_index);
+        self.next_index += 1;
+        self.stack.push(function);
+        self.on_stack.insert(function);
+
+        // Calls to functions that are not defined cannot be part of a cycle
+        for &callee in self.graph.callees(function) {
+            if !self.graph.is_defined(callee) {
+                continue;
+            }
+            if !self.index.contains_key(&callee) {
+                self.visit(callee);
+                let low = self.lowlink[&function].min(self.lowlink[&callee]);
+                self.lowlink.insert(function, low);
+            } else if self.on_stack.contains(&callee) {
+                let low = self.lowlink[&function].min(self.index[&callee]);
+                self.lowlink.insert(function, low);
+            }
+        }
+
+        // A root of a component pops everything above it off the stack
+        if self.lowlink[&function] == self.index[&function] {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+                component.push(member);
+                if member == function {
+                    break;
+                }
+            }
+            component.reverse();
+            self.components.push(component);
+        }
+    }
+}
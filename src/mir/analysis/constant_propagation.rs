@@ -0,0 +1,254 @@
+use crate::mir::analysis::dataflow::{DataflowAnalysis, DataflowResults, Direction, solve};
+use crate::mir::{BlockId, MirFunction, MirType, Opcode, Operand, Reg};
+use std::collections::{BTreeMap, HashSet};
+
+/// A known compile-time value
+#[derive(Debug, Clone, Copy)]
+pub enum ConstValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+// Floats compare by bit pattern so that a NaN constant is equal to itself
+// and the solver still reaches a fixpoint
+impl PartialEq for ConstValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => a == b,
+            (ConstValue::Float(a), ConstValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (ConstValue::Bool(a), ConstValue::Bool(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl ConstValue {
+    /// The immediate of the value, for a register of the type
+    pub fn to_operand(self, typ: MirType) -> Operand {
+        match self {
+            ConstValue::Int(value) => Operand::ImmI64(value),
+            ConstValue::Float(value) => Operand::float(value, typ),
+            ConstValue::Bool(value) => Operand::ImmBool(value),
+        }
+    }
+}
+
+/// What is known about a register at a program point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstLattice {
+    /// Not yet assigned on any path seen so far
+    Undefined,
+    Constant(ConstValue),
+    /// Assigned different or unknown values
+    Overdefined,
+}
+
+impl ConstLattice {
+    pub fn meet(self, other: ConstLattice) -> ConstLattice {
+        match (self, other) {
+            (ConstLattice::Undefined, x) | (x, ConstLattice::Undefined) => x,
+            (ConstLattice::Constant(a), ConstLattice::Constant(b)) if a == b => self,
+            _ => ConstLattice::Overdefined,
+        }
+    }
+}
+
+/// Tracks which registers hold a known constant; registers missing from
+/// the map are [`ConstLattice::Undefined`]
+pub struct ConstantPropagation;
+
+impl ConstantPropagation {
+    /// Constant facts at the start and end of every block
+    pub fn compute(function: &MirFunction) -> DataflowResults<BTreeMap<Reg, ConstLattice>> {
+        solve(&ConstantPropagation, function)
+    }
+
+    /// Registers that hold the same constant at the end of every block that
+    /// knows about them. Intended for SSA form, where each register has a
+    /// single definition.
+    pub fn constants(function: &MirFunction) -> BTreeMap<Reg, ConstValue> {
+        let results = Self::compute(function);
+        let mut merged: BTreeMap<Reg, ConstLattice> = BTreeMap::new();
+        for facts in results.block_exit.values() {
+            for (&reg, &value) in facts {
+                let entry = merged.entry(reg).or_insert(ConstLattice::Undefined);
+                *entry = entry.meet(value);
+            }
+        }
+        merged
+            .into_iter()
+            .filter_map(|(reg, value)| match value {
+                ConstLattice::Constant(constant) => Some((reg, constant)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn lookup(facts: &BTreeMap<Reg, ConstLattice>, operand: &Operand) -> ConstLattice {
+    match operand {
+        Operand::Reg(reg) => facts.get(reg).copied().unwrap_or(ConstLattice::Undefined),
+        Operand::ImmI64(value) => ConstLattice::Constant(ConstValue::Int(*value)),
+        Operand::ImmF64(value) => ConstLattice::Constant(ConstValue::Float(*value)),
+        Operand::ImmF32(value) => ConstLattice::Constant(ConstValue::Float(*value as f64)),
+        Operand::ImmBool(value) => ConstLattice::Constant(ConstValue::Bool(*value)),
+        Operand::Label(_) | Operand::Global(_) => ConstLattice::Overdefined,
+    }
+}
+
+/// Evaluates an operation on constant operands, if it can be folded
+pub fn evaluate(op: &Opcode, lhs: ConstValue, rhs: ConstValue) -> Option<ConstValue> {
+    use ConstValue::{Bool, Float, Int};
+    let value = match (op, lhs, rhs) {
+        (Opcode::Add, Float(a), Float(b)) => Float(a + b),
+        (Opcode::Sub, Float(a), Float(b)) => Float(a - b),
+        (Opcode::Mul, Float(a), Float(b)) => Float(a * b),
+        (Opcode::Div, Float(a), Float(b)) => Float(a / b),
+        (Opcode::Mod, Float(a), Float(b)) => Float(a % b),
+        (Opcode::Pow, Float(a), Float(b)) => Float(a.powf(b)),
+        (Opcode::Add, Int(a), Int(b)) => Int(a.checked_add(b)?),
+        (Opcode::Sub, Int(a), Int(b)) => Int(a.checked_sub(b)?),
+        (Opcode::Mul, Int(a), Int(b)) => Int(a.checked_mul(b)?),
+        (Opcode::Div, Int(a), Int(b)) => Int(a.checked_div(b)?),
+        (Opcode::Mod, Int(a), Int(b)) => Int(a.checked_rem(b)?),
+        (Opcode::Eq, a, b) => Bool(compare(a, b)? == std::cmp::Ordering::Equal),
+        (Opcode::Ne, a, b) => Bool(compare(a, b)? != std::cmp::Ordering::Equal),
+        (Opcode::Lt, a, b) => Bool(compare(a, b)? == std::cmp::Ordering::Less),
+        (Opcode::Le, a, b) => Bool(compare(a, b)? != std::cmp::Ordering::Greater),
+        (Opcode::Gt, a, b) => Bool(compare(a, b)? == std::cmp::Ordering::Greater),
+        (Opcode::Ge, a, b) => Bool(compare(a, b)? != std::cmp::Ordering::Less),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Orders two constants of the same kind; unordered floats (NaN) are not folded
+fn compare(lhs: ConstValue, rhs: ConstValue) -> Option<std::cmp::Ordering> {
+    match (lhs, rhs) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => Some(a.cmp(&b)),
+        (ConstValue::Float(a), ConstValue::Float(b)) => a.partial_cmp(&b),
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => Some(a.cmp(&b)),
+        _ => None,
+    }
+}
+
+impl DataflowAnalysis for ConstantPropagation {
+    type Domain = BTreeMap<Reg, ConstLattice>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    /// Parameters and registers never written in the function are unknown on entry
+    fn boundary(&self, function: &MirFunction) -> Self::Domain {
+        let mut defined: HashSet<Reg> = HashSet::new();
+        for (_, block) in function.arena.iter() {
+            defined.extend(block.phi_nodes.iter().map(|phi| phi.dest));
+            defined.extend(block.params.iter().map(|(reg, _)| *reg));
+            defined.extend(
+                block
+                    .instructions
+                    .iter()
+                    .map(|instruction| instruction.dest),
+            );
+        }
+        for (reg, _) in &function.params {
+            defined.remove(reg);
+        }
+        (0..function.reg_types.len())
+            .filter(|reg| !defined.contains(reg))
+            .map(|reg| (reg, ConstLattice::Overdefined))
+            .collect()
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        BTreeMap::new()
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        for (&reg, &value) in other {
+            let entry = into.entry(reg).or_insert(ConstLattice::Undefined);
+            *entry = entry.meet(value);
+        }
+    }
+
+    fn transfer(
+        &self,
+        function: &MirFunction,
+        block_id: BlockId,
+        input: &Self::Domain,
+    ) -> Self::Domain {
+        let block = function.block(block_id);
+        let mut facts = input.clone();
+
+        // Phis and block parameters were already given their values on the incoming edges
+        for instruction in &block.instructions {
+            let args: Vec<ConstLattice> = instruction
+                .args
+                .iter()
+                .map(|arg| lookup(&facts, arg))
+                .collect();
+
+            let value = match (&instruction.op, args.as_slice()) {
+                // Globals can be written by any call, so their values are never known
+                (Opcode::Call | Opcode::Load | Opcode::Store, _) => ConstLattice::Overdefined,
+                (Opcode::Copy, [value]) => *value,
+                // Rounding to the target type is left to run time
+                (Opcode::Convert, [ConstLattice::Undefined]) => ConstLattice::Undefined,
+                (Opcode::Convert, _) => ConstLattice::Overdefined,
+                (Opcode::Select, [cond, if_true, if_false]) => match cond {
+                    ConstLattice::Constant(ConstValue::Bool(true)) => *if_true,
+                    ConstLattice::Constant(ConstValue::Bool(false)) => *if_false,
+                    ConstLattice::Undefined => ConstLattice::Undefined,
+                    _ => if_true.meet(*if_false),
+                },
+                (_, [ConstLattice::Constant(lhs), ConstLattice::Constant(rhs)]) => {
+                    match evaluate(&instruction.op, *lhs, *rhs) {
+                        Some(constant) => ConstLattice::Constant(constant),
+                        None => ConstLattice::Overdefined,
+                    }
+                }
+                (_, operands) if operands.contains(&ConstLattice::Overdefined) => {
+                    ConstLattice::Overdefined
+                }
+                _ => ConstLattice::Undefined,
+            };
+            facts.insert(instruction.dest, value);
+        }
+        facts
+    }
+
+    /// Binds the phis and block parameters of `to` to the values `from` passes along
+    fn edge_transfer(
+        &self,
+        function: &MirFunction,
+        from: BlockId,
+        to: BlockId,
+        facts: &Self::Domain,
+    ) -> Self::Domain {
+        let target = function.block(to);
+        let mut edge_facts = facts.clone();
+
+        for phi in &target.phi_nodes {
+            let value = phi
+                .incoming(from)
+                .map(|operand| lookup(facts, operand))
+                .unwrap_or(ConstLattice::Undefined);
+            edge_facts.insert(phi.dest, value);
+        }
+
+        for (index, (reg, _)) in target.params.iter().enumerate() {
+            // Both edges of a BrIf may lead to the same block with different arguments
+            let value = function
+                .block(from)
+                .terminator
+                .edges()
+                .into_iter()
+                .filter(|(edge_target, _)| *edge_target == to)
+                .filter_map(|(_, args)| args.get(index))
+                .map(|arg| lookup(facts, arg))
+                .fold(ConstLattice::Undefined, ConstLattice::meet);
+            edge_facts.insert(*reg, value);
+        }
+        edge_facts
+    }
+}
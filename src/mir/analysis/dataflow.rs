@@ -0,0 +1,138 @@
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::{BlockId, MirFunction};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Which way facts flow through the CFG
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the entry block along branches (e.g. reaching definitions)
+    Forward,
+    /// From returning blocks against branches (e.g. liveness)
+    Backward,
+}
+
+/// A dataflow problem over the blocks of a MIR function.
+///
+/// The solver combines the facts flowing into a block with [`meet`], then
+/// runs [`transfer`] over the block, until no block's facts change.
+///
+/// [`meet`]: DataflowAnalysis::meet
+/// [`transfer`]: DataflowAnalysis::transfer
+pub trait DataflowAnalysis {
+    /// The facts tracked at each program point
+    type Domain: Clone + PartialEq;
+
+    const DIRECTION: Direction;
+
+    /// Facts at the function boundary: on entry for forward analyses,
+    /// at returning blocks for backward ones
+    fn boundary(&self, function: &MirFunction) -> Self::Domain;
+
+    /// Starting facts for every other block, the identity of `meet`
+    fn initial(&self, function: &MirFunction) -> Self::Domain;
+
+    /// Combine facts from another incoming edge into `into`
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain);
+
+    /// Compute the facts on the far side of a block from the facts on its near side
+    fn transfer(
+        &self,
+        function: &MirFunction,
+        block: BlockId,
+        input: &Self::Domain,
+    ) -> Self::Domain;
+
+    /// Adjust the facts carried along the CFG edge `from -> to` before they are
+    /// met with other edges, e.g. for values passed to phis or block parameters
+    fn edge_transfer(
+        &self,
+        _function: &MirFunction,
+        _from: BlockId,
+        _to: BlockId,
+        facts: &Self::Domain,
+    ) -> Self::Domain {
+        facts.clone()
+    }
+}
+
+/// Solved facts at the start and end of every block
+#[derive(Debug)]
+pub struct DataflowResults<D> {
+    pub block_entry: HashMap<BlockId, D>,
+    pub block_exit: HashMap<BlockId, D>,
+}
+
+/// Runs an analysis to a fixpoint with a worklist over the function's CFG
+pub fn solve<A: DataflowAnalysis>(
+    analysis: &A,
+    function: &MirFunction,
+) -> DataflowResults<A::Domain> {
+    let cfg = CFGAnalysis::new(function);
+    let mut blocks: Vec<BlockId> = function
+        .arena
+        .iter()
+        .map(|(block_id, _)| block_id)
+        .collect();
+    if A::DIRECTION == Direction::Backward {
+        blocks.reverse();
+    }
+
+    let initial = analysis.initial(function);
+    let mut block_entry: HashMap<BlockId, A::Domain> =
+        blocks.iter().map(|&b| (b, initial.clone())).collect();
+    let mut block_exit = block_entry.clone();
+
+    let mut worklist: VecDeque<BlockId> = blocks.iter().copied().collect();
+    let mut queued: HashSet<BlockId> = blocks.iter().copied().collect();
+
+    while let Some(block) = worklist.pop_front() {
+        queued.remove(&block);
+
+        // Facts flow in from the predecessors (forward) or successors (backward)
+        let (sources, dependents) = match A::DIRECTION {
+            Direction::Forward => (&cfg.predecessors[&block], &cfg.successors[&block]),
+            Direction::Backward => (&cfg.successors[&block], &cfg.predecessors[&block]),
+        };
+        let at_boundary = match A::DIRECTION {
+            Direction::Forward => block == function.entry,
+            Direction::Backward => sources.is_empty(),
+        };
+
+        let mut input = if at_boundary {
+            analysis.boundary(function)
+        } else {
+            initial.clone()
+        };
+        for &source in sources {
+            let facts = match A::DIRECTION {
+                Direction::Forward => {
+                    analysis.edge_transfer(function, source, block, &block_exit[&source])
+                }
+                Direction::Backward => {
+                    analysis.edge_transfer(function, block, source, &block_entry[&source])
+                }
+            };
+            analysis.meet(&mut input, &facts);
+        }
+
+        let output = analysis.transfer(function, block, &input);
+        let (near, far) = match A::DIRECTION {
+            Direction::Forward => (&mut block_entry, &mut block_exit),
+            Direction::Backward => (&mut block_exit, &mut block_entry),
+        };
+        near.insert(block, input);
+        if far[&block] != output {
+            far.insert(block, output);
+            for &dependent in dependents {
+                if queued.insert(dependent) {
+                    worklist.push_back(dependent);
+                }
+            }
+        }
+    }
+
+    DataflowResults {
+        block_entry,
+        block_exit,
+    }
+}
@@ -0,0 +1,77 @@
+use crate::mir::{MirFunction, Opcode, Operand, Reg, Terminator};
+use std::collections::{BTreeSet, HashMap};
+
+/// Registers whose value may leave the function that computes it
+///
+/// A value escapes when it is returned, stored to a global or passed to a
+/// call, or when a register that escapes may hold it: copies, selects, phis
+/// and block arguments pass a value on unchanged, while arithmetic makes a
+/// new one. The other values live and die with their function, so a
+/// backend may keep whatever it allocates for them on the stack.
+///
+/// MIR has no allocations yet, so the result only matters for the
+/// aggregates that may be heap-allocated once they are lowered to it.
+pub struct EscapeAnalysis {
+    escaping: BTreeSet<Reg>,
+}
+
+impl EscapeAnalysis {
+    pub fn new(function: &MirFunction) -> Self {
+        // The registers each register may take its value from unchanged
+        let mut sources: HashMap<Reg, Vec<Reg>> = HashMap::new();
+        let mut escaping = Vec::new();
+        let mut flow = |dest: Reg, operand: &Operand| {
+            if let Some(reg) = operand.as_reg() {
+                sources.entry(dest).or_default().push(reg);
+            }
+        };
+
+        for (_, block) in function.arena.iter() {
+            for phi in &block.phi_nodes {
+                for (_, operand) in &phi.incomings {
+                    flow(phi.dest, operand);
+                }
+            }
+            for instruction in &block.instructions {
+                match (instruction.op, instruction.args.as_slice()) {
+                    (Opcode::Copy, [value]) => flow(instruction.dest, value),
+                    (Opcode::Select, [_, if_true, if_false]) => {
+                        flow(instruction.dest, if_true);
+                        flow(instruction.dest, if_false);
+                    }
+                    (Opcode::Store, [_, value]) => escaping.extend(value.as_reg()),
+                    (Opcode::Call, [_, args @ ..]) => escaping.extend(args.iter().filter_map(Operand::as_reg)),
+                    _ => {}
+                }
+            }
+            for (target, args) in block.terminator.edges() {
+                let params = &function.block(target).params;
+                for ((param, _), arg) in params.iter().zip(args) {
+                    flow(*param, arg);
+                }
+            }
+            if let Terminator::Ret { value: Some(value) } = &block.terminator {
+                escaping.extend(value.as_reg());
+            }
+        }
+
+        let mut analysis = EscapeAnalysis {
+            escaping: BTreeSet::new(),
+        };
+        while let Some(reg) = escaping.pop() {
+            if analysis.escaping.insert(reg) {
+                escaping.extend(sources.get(&reg).into_iter().flatten());
+            }
+        }
+        analysis
+    }
+
+    pub fn escapes(&self, reg: Reg) -> bool {
+        self.escaping.contains(&reg)
+    }
+
+    /// The registers that escape, in order
+    pub fn escaping(&self) -> impl Iterator<Item = Reg> + '_ {
+        self.escaping.iter().copied()
+    }
+}
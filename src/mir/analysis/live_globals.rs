@@ -0,0 +1,119 @@
+use crate::mir::analysis::call_graph::CallGraph;
+use crate::mir::analysis::dataflow::{DataflowAnalysis, DataflowResults, Direction, solve};
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::{BlockId, FuncId, GlobalId, Instruction, MirFunction, MirProgram, Opcode, Operand, Terminator};
+use std::collections::{BTreeSet, HashSet};
+
+/// Globals whose current value may still be read later
+///
+/// Every global is live where a function returns or the program stops,
+/// since the caller, or whatever runs after the program, may read it: an
+/// embedder can read the globals a call left even after it trapped. So is
+/// every global before an instruction that may trap, and before a call
+/// unless the callee is defined in the program and neither it nor anything
+/// it calls loads a global or may trap.
+pub struct LiveGlobals {
+    globals: BTreeSet<GlobalId>,
+    /// Defined functions that never load a global or trap, directly or
+    /// through their callees
+    quiet: HashSet<FuncId>,
+}
+
+impl LiveGlobals {
+    pub fn new(program: &MirProgram) -> Self {
+        let call_graph = CallGraph::new(program);
+        let loud: HashSet<FuncId> = program
+            .functions
+            .iter()
+            .filter(|function| loads_or_traps(function))
+            .map(|function| function.name)
+            .collect();
+        let quiet = program
+            .functions
+            .iter()
+            .map(|function| function.name)
+            .filter(|&function| {
+                call_graph
+                    .reachable_from([function])
+                    .iter()
+                    .all(|callee| call_graph.is_defined(*callee) && !loud.contains(callee))
+            })
+            .collect();
+        LiveGlobals {
+            globals: program.globals.iter().map(|global| global.name).collect(),
+            quiet,
+        }
+    }
+
+    /// Live globals at the start and end of every block
+    pub fn compute(&self, function: &MirFunction) -> DataflowResults<BTreeSet<GlobalId>> {
+        solve(self, function)
+    }
+
+    /// Updates the globals live after the instruction to those live before it
+    pub fn step(&self, instruction: &Instruction, live: &mut BTreeSet<GlobalId>) {
+        match (instruction.op, instruction.args.first()) {
+            (Opcode::Load, Some(Operand::Global(global))) => {
+                live.insert(*global);
+            }
+            (Opcode::Store, Some(Operand::Global(global))) => {
+                live.remove(global);
+            }
+            (Opcode::Call, Some(Operand::Label(callee))) if self.quiet.contains(callee) => {}
+            (Opcode::Call, _) => live.extend(self.globals.iter().copied()),
+            _ if may_trap(instruction) => live.extend(self.globals.iter().copied()),
+            _ => {}
+        }
+    }
+}
+
+/// Whether executing the instruction can stop the program, as integer
+/// arithmetic does when it overflows or divides by zero
+fn may_trap(instruction: &Instruction) -> bool {
+    instruction.typ.is_integer()
+        && matches!(instruction.op, Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod)
+}
+
+/// Whether a block of the function that can run loads a global, traps or
+/// has an instruction that may trap
+fn loads_or_traps(function: &MirFunction) -> bool {
+    CFGAnalysis::new(function).reachable().into_iter().any(|block_id| {
+        let block = function.block(block_id);
+        matches!(block.terminator, Terminator::Trap { .. } | Terminator::Unreachable)
+            || block
+                .instructions
+                .iter()
+                .any(|instruction| instruction.op == Opcode::Load || may_trap(instruction))
+    })
+}
+
+impl DataflowAnalysis for LiveGlobals {
+    type Domain = BTreeSet<GlobalId>;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self, _function: &MirFunction) -> Self::Domain {
+        self.globals.clone()
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        into.extend(other.iter().copied());
+    }
+
+    fn transfer(
+        &self,
+        function: &MirFunction,
+        block_id: BlockId,
+        live_out: &Self::Domain,
+    ) -> Self::Domain {
+        let mut live = live_out.clone();
+        for instruction in function.block(block_id).instructions.iter().rev() {
+            self.step(instruction, &mut live);
+        }
+        live
+    }
+}
@@ -0,0 +1,85 @@
+use crate::mir::analysis::dataflow::{DataflowAnalysis, DataflowResults, Direction, solve};
+use crate::mir::{BlockId, MirFunction, Reg};
+use std::collections::BTreeSet;
+
+/// Registers whose current value may still be read later
+pub struct Liveness;
+
+impl Liveness {
+    /// Live registers at the start and end of every block
+    pub fn compute(function: &MirFunction) -> DataflowResults<BTreeSet<Reg>> {
+        solve(&Liveness, function)
+    }
+}
+
+impl DataflowAnalysis for Liveness {
+    type Domain = BTreeSet<Reg>;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self, _function: &MirFunction) -> Self::Domain {
+        BTreeSet::new()
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        into.extend(other.iter().copied());
+    }
+
+    fn transfer(
+        &self,
+        function: &MirFunction,
+        block_id: BlockId,
+        live_out: &Self::Domain,
+    ) -> Self::Domain {
+        let block = function.block(block_id);
+        let mut live = live_out.clone();
+
+        live.extend(
+            block
+                .terminator
+                .operands()
+                .into_iter()
+                .filter_map(|operand| operand.as_reg()),
+        );
+
+        for instruction in block.instructions.iter().rev() {
+            live.remove(&instruction.dest);
+            live.extend(instruction.args.iter().filter_map(|arg| arg.as_reg()));
+        }
+
+        for phi in &block.phi_nodes {
+            live.remove(&phi.dest);
+        }
+        for (reg, _) in &block.params {
+            live.remove(reg);
+        }
+        live
+    }
+
+    /// Values handed to a successor, either as block arguments or phi
+    /// incomings, are read on the edge leaving the predecessor
+    fn edge_transfer(
+        &self,
+        function: &MirFunction,
+        from: BlockId,
+        to: BlockId,
+        live_in: &Self::Domain,
+    ) -> Self::Domain {
+        let mut live = live_in.clone();
+        for (target, args) in function.block(from).terminator.edges() {
+            if target == to {
+                live.extend(args.iter().filter_map(|arg| arg.as_reg()));
+            }
+        }
+        for phi in &function.block(to).phi_nodes {
+            if let Some(reg) = phi.incoming(from).and_then(|value| value.as_reg()) {
+                live.insert(reg);
+            }
+        }
+        live
+    }
+}
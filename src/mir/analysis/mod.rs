@@ -0,0 +1,19 @@
+//! Analyses over MIR: per-function dataflow problems built on a shared
+//! solver, sparse conditional constant propagation, block frequencies
+//! estimated from branch weights, and the program-wide call graph.
+//!
+//! MIR allocates no memory: values live in registers, and globals, the only
+//! storage, are named by every load and store of them, so there is no alias
+//! analysis. [`escape`] tells which values may leave their function, ahead
+//! of the aggregates that may be heap-allocated once they are lowered to MIR.
+
+pub mod block_frequency;
+pub mod call_graph;
+pub mod constant_propagation;
+pub mod dataflow;
+pub mod escape;
+pub mod live_globals;
+pub mod liveness;
+pub mod reaching_definitions;
+pub mod sccp;
+pub mod value_range;
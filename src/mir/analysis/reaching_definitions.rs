@@ -0,0 +1,88 @@
+use crate::mir::analysis::dataflow::{DataflowAnalysis, DataflowResults, Direction, solve};
+use crate::mir::{BlockId, MirFunction, Reg};
+use std::collections::BTreeSet;
+
+/// Where a register is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DefSite {
+    /// Function parameter, defined on entry
+    Param,
+    /// The phi node at the given index of a block
+    Phi(BlockId, usize),
+    /// The block parameter at the given index of a block
+    BlockParam(BlockId, usize),
+    /// The instruction at the given index of a block
+    Instruction(BlockId, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Definition {
+    pub reg: Reg,
+    pub site: DefSite,
+}
+
+/// Definitions that may reach each program point without being overwritten
+pub struct ReachingDefinitions;
+
+impl ReachingDefinitions {
+    /// Reaching definitions at the start and end of every block
+    pub fn compute(function: &MirFunction) -> DataflowResults<BTreeSet<Definition>> {
+        solve(&ReachingDefinitions, function)
+    }
+}
+
+/// Replaces all reaching definitions of a register with a new one
+fn define(facts: &mut BTreeSet<Definition>, reg: Reg, site: DefSite) {
+    facts.retain(|definition| definition.reg != reg);
+    facts.insert(Definition { reg, site });
+}
+
+impl DataflowAnalysis for ReachingDefinitions {
+    type Domain = BTreeSet<Definition>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, function: &MirFunction) -> Self::Domain {
+        function
+            .params
+            .iter()
+            .map(|(reg, _)| Definition {
+                reg: *reg,
+                site: DefSite::Param,
+            })
+            .collect()
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        into.extend(other.iter().copied());
+    }
+
+    fn transfer(
+        &self,
+        function: &MirFunction,
+        block_id: BlockId,
+        input: &Self::Domain,
+    ) -> Self::Domain {
+        let block = function.block(block_id);
+        let mut facts = input.clone();
+
+        for (index, phi) in block.phi_nodes.iter().enumerate() {
+            define(&mut facts, phi.dest, DefSite::Phi(block_id, index));
+        }
+        for (index, (reg, _)) in block.params.iter().enumerate() {
+            define(&mut facts, *reg, DefSite::BlockParam(block_id, index));
+        }
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            define(
+                &mut facts,
+                instruction.dest,
+                DefSite::Instruction(block_id, index),
+            );
+        }
+        facts
+    }
+}
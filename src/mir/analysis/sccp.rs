@@ -0,0 +1,307 @@
+use crate::mir::analysis::constant_propagation::{ConstLattice, ConstValue, evaluate};
+use crate::mir::{BlockId, Instruction, MirFunction, MirType, Opcode, Operand, Reg, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// Sparse conditional constant propagation
+///
+/// Finds the registers that hold the same constant whenever they are
+/// computed, and the blocks that can run at all. Both are assumed of
+/// everything until shown otherwise: a block runs once an edge into it can
+/// be taken, and only the edges a branch can take given the values of its
+/// operands are. Values flow along the uses of each register rather than
+/// through every block, and a phi only meets the values of the edges found
+/// to run, so `x` stays constant past `if false { x = 2 }`. Intended for
+/// SSA form.
+pub struct ConditionalConstants {
+    /// The value of every register, indexed by register number
+    pub values: Vec<ConstLattice>,
+    /// Blocks an edge from the entry block can reach
+    pub executable: HashSet<BlockId>,
+}
+
+/// Where a register is read
+#[derive(Debug, Clone, Copy)]
+enum Use {
+    /// By the phis and block parameters of a block
+    Entry(BlockId),
+    Instruction(BlockId, usize),
+    Terminator(BlockId),
+}
+
+/// The state of the solver
+struct Solver<'f> {
+    function: &'f MirFunction,
+    values: Vec<ConstLattice>,
+    executable: HashSet<BlockId>,
+    edges: HashSet<(BlockId, BlockId)>,
+    uses: HashMap<Reg, Vec<Use>>,
+    block_worklist: Vec<BlockId>,
+    /// Blocks already running that a new edge was found into, whose phis
+    /// have to meet the values it passes
+    entry_worklist: Vec<BlockId>,
+    reg_worklist: Vec<Reg>,
+}
+
+impl ConditionalConstants {
+    pub fn compute(function: &MirFunction) -> Self {
+        let mut solver = Solver::new(function);
+        solver.run();
+        ConditionalConstants {
+            values: solver.values,
+            executable: solver.executable,
+        }
+    }
+
+    /// The constant a register holds, if any
+    pub fn constant(&self, reg: Reg) -> Option<ConstValue> {
+        match self.values.get(reg) {
+            Some(ConstLattice::Constant(value)) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl<'f> Solver<'f> {
+    fn new(function: &'f MirFunction) -> Self {
+        let mut uses: HashMap<Reg, Vec<Use>> = HashMap::new();
+        let mut defined: HashSet<Reg> = HashSet::new();
+        for (block_id, block) in function.arena.iter() {
+            for phi in &block.phi_nodes {
+                defined.insert(phi.dest);
+                for reg in phi.incomings.iter().filter_map(|(_, operand)| operand.as_reg()) {
+                    uses.entry(reg).or_default().push(Use::Entry(block_id));
+                }
+            }
+            defined.extend(block.params.iter().map(|(reg, _)| *reg));
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                defined.insert(instruction.dest);
+                for reg in instruction.args.iter().filter_map(Operand::as_reg) {
+                    uses.entry(reg).or_default().push(Use::Instruction(block_id, index));
+                }
+            }
+            for reg in block.terminator.operands().into_iter().filter_map(Operand::as_reg) {
+                uses.entry(reg).or_default().push(Use::Terminator(block_id));
+            }
+            for (target, args) in block.terminator.edges() {
+                for reg in args.iter().filter_map(Operand::as_reg) {
+                    uses.entry(reg).or_default().push(Use::Entry(target));
+                }
+            }
+        }
+
+        // Parameters, and registers never written in the function, can hold anything
+        for (reg, _) in &function.params {
+            defined.remove(reg);
+        }
+        let values = (0..function.reg_types.len())
+            .map(|reg| {
+                if defined.contains(&reg) {
+                    ConstLattice::Undefined
+                } else {
+                    ConstLattice::Overdefined
+                }
+            })
+            .collect();
+
+        Solver {
+            function,
+            values,
+            executable: HashSet::new(),
+            edges: HashSet::new(),
+            uses,
+            block_worklist: Vec::new(),
+            entry_worklist: Vec::new(),
+            reg_worklist: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) {
+        self.executable.insert(self.function.entry);
+        self.block_worklist.push(self.function.entry);
+        loop {
+            if let Some(block) = self.block_worklist.pop() {
+                self.visit_entry(block);
+                for index in 0..self.function.block(block).instructions.len() {
+                    self.visit_instruction(block, index);
+                }
+                self.visit_terminator(block);
+            } else if let Some(block) = self.entry_worklist.pop() {
+                self.visit_entry(block);
+            } else if let Some(reg) = self.reg_worklist.pop() {
+                for index in 0..self.uses.get(&reg).map_or(0, Vec::len) {
+                    let site = self.uses[&reg][index];
+                    match site {
+                        Use::Entry(block) if self.executable.contains(&block) => self.visit_entry(block),
+                        Use::Instruction(block, index) if self.executable.contains(&block) => {
+                            self.visit_instruction(block, index)
+                        }
+                        Use::Terminator(block) if self.executable.contains(&block) => self.visit_terminator(block),
+                        _ => {}
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn lookup(&self, operand: &Operand) -> ConstLattice {
+        match operand {
+            Operand::Reg(reg) => self.values.get(*reg).copied().unwrap_or(ConstLattice::Overdefined),
+            Operand::ImmI64(value) => ConstLattice::Constant(ConstValue::Int(*value)),
+            Operand::ImmF64(value) => ConstLattice::Constant(ConstValue::Float(*value)),
+            Operand::ImmF32(value) => ConstLattice::Constant(ConstValue::Float(*value as f64)),
+            Operand::ImmBool(value) => ConstLattice::Constant(ConstValue::Bool(*value)),
+            Operand::Label(_) | Operand::Global(_) => ConstLattice::Overdefined,
+        }
+    }
+
+    /// Lowers the register to `value` met with what it held, queueing its
+    /// uses if that changed anything
+    fn update(&mut self, reg: Reg, value: ConstLattice) {
+        let new = self.values[reg].meet(value);
+        if new != self.values[reg] {
+            self.values[reg] = new;
+            self.reg_worklist.push(reg);
+        }
+    }
+
+    /// Meets the values the edges found to run pass to the phis and block
+    /// parameters of the block
+    fn visit_entry(&mut self, block_id: BlockId) {
+        let function = self.function;
+        let block = function.block(block_id);
+        for phi in &block.phi_nodes {
+            let value = phi
+                .incomings
+                .iter()
+                .filter(|(pred, _)| self.edges.contains(&(*pred, block_id)))
+                .map(|(_, operand)| self.lookup(operand))
+                .fold(ConstLattice::Undefined, ConstLattice::meet);
+            self.update(phi.dest, value);
+        }
+        for (index, (reg, _)) in block.params.iter().enumerate() {
+            let mut value = ConstLattice::Undefined;
+            for &(pred, to) in &self.edges {
+                if to != block_id {
+                    continue;
+                }
+                for (target, args) in function.block(pred).terminator.edges() {
+                    if target == block_id && let Some(arg) = args.get(index) {
+                        value = value.meet(self.lookup(arg));
+                    }
+                }
+            }
+            self.update(*reg, value);
+        }
+    }
+
+    fn visit_instruction(&mut self, block_id: BlockId, index: usize) {
+        let function = self.function;
+        let instruction = &function.block(block_id).instructions[index];
+        if instruction.op == Opcode::Store {
+            return;
+        }
+        let value = self.evaluate(instruction);
+        self.update(instruction.dest, value);
+    }
+
+    fn evaluate(&self, instruction: &Instruction) -> ConstLattice {
+        let args: Vec<ConstLattice> = instruction.args.iter().map(|arg| self.lookup(arg)).collect();
+        let value = match (instruction.op, args.as_slice()) {
+            // Globals can be written by any call, so their values are never known
+            (Opcode::Call | Opcode::Load | Opcode::Store, _) => return ConstLattice::Overdefined,
+            (Opcode::Copy, [value]) => *value,
+            (Opcode::Select, [cond, if_true, if_false]) => match cond {
+                ConstLattice::Constant(ConstValue::Bool(true)) => *if_true,
+                ConstLattice::Constant(ConstValue::Bool(false)) => *if_false,
+                ConstLattice::Undefined => ConstLattice::Undefined,
+                _ => if_true.meet(*if_false),
+            },
+            // Only conversions between `f32` and `f64` round the same way everywhere
+            (Opcode::Convert, [ConstLattice::Constant(ConstValue::Float(value))])
+                if is_ieee(Some(instruction.typ)) && is_ieee(self.operand_type(&instruction.args[0])) =>
+            {
+                ConstLattice::Constant(ConstValue::Float(*value))
+            }
+            (Opcode::Convert, [ConstLattice::Undefined]) => ConstLattice::Undefined,
+            (Opcode::Convert, _) => ConstLattice::Overdefined,
+            (op, [ConstLattice::Constant(lhs), ConstLattice::Constant(rhs)]) => match evaluate(&op, *lhs, *rhs) {
+                Some(constant) => ConstLattice::Constant(constant),
+                None => ConstLattice::Overdefined,
+            },
+            (_, operands) if operands.contains(&ConstLattice::Overdefined) => ConstLattice::Overdefined,
+            _ => ConstLattice::Undefined,
+        };
+        round(value, instruction.typ)
+    }
+
+    fn operand_type(&self, operand: &Operand) -> Option<MirType> {
+        match operand {
+            Operand::Reg(reg) => self.function.reg_type(*reg),
+            Operand::ImmF64(_) => Some(MirType::F64),
+            Operand::ImmF32(_) => Some(MirType::F32),
+            _ => None,
+        }
+    }
+
+    fn visit_terminator(&mut self, block_id: BlockId) {
+        let function = self.function;
+        let terminator = &function.block(block_id).terminator;
+        let targets: Vec<BlockId> = match terminator {
+            Terminator::Br { target, .. } => vec![*target],
+            Terminator::BrIf { cond, then_bb, else_bb, .. } => match self.lookup(cond) {
+                ConstLattice::Constant(ConstValue::Bool(true)) => vec![*then_bb],
+                ConstLattice::Constant(ConstValue::Bool(false)) => vec![*else_bb],
+                ConstLattice::Undefined => Vec::new(),
+                _ => vec![*then_bb, *else_bb],
+            },
+            Terminator::Switch { value, cases, default, .. } => match self.lookup(value) {
+                ConstLattice::Constant(value) => {
+                    let matching = cases
+                        .iter()
+                        .find(|case| self.lookup(&case.value) == ConstLattice::Constant(value));
+                    vec![matching.map_or(*default, |case| case.target)]
+                }
+                ConstLattice::Undefined => Vec::new(),
+                ConstLattice::Overdefined => terminator.edges().into_iter().map(|(target, _)| target).collect(),
+            },
+            Terminator::Ret { .. } | Terminator::Trap { .. } | Terminator::Unreachable => Vec::new(),
+        };
+
+        for target in targets {
+            if !self.edges.insert((block_id, target)) {
+                continue;
+            }
+            if self.executable.insert(target) {
+                self.block_worklist.push(target);
+            } else {
+                self.entry_worklist.push(target);
+            }
+        }
+    }
+}
+
+fn is_ieee(typ: Option<MirType>) -> bool {
+    matches!(typ, Some(MirType::F32 | MirType::F64))
+}
+
+/// The constant as a register of the type holds it, rounded to `f32` or
+/// dropped if it does not fit a narrow integer
+fn round(value: ConstLattice, typ: MirType) -> ConstLattice {
+    match (value, typ) {
+        (ConstLattice::Constant(ConstValue::Float(value)), MirType::F32) => {
+            ConstLattice::Constant(ConstValue::Float(value as f32 as f64))
+        }
+        (ConstLattice::Constant(ConstValue::Int(value)), MirType::I8) if i8::try_from(value).is_err() => {
+            ConstLattice::Overdefined
+        }
+        (ConstLattice::Constant(ConstValue::Int(value)), MirType::I16) if i16::try_from(value).is_err() => {
+            ConstLattice::Overdefined
+        }
+        (ConstLattice::Constant(ConstValue::Int(value)), MirType::I32) if i32::try_from(value).is_err() => {
+            ConstLattice::Overdefined
+        }
+        _ => value,
+    }
+}
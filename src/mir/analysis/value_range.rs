@@ -0,0 +1,455 @@
+use crate::mir::analysis::dataflow::{DataflowAnalysis, DataflowResults, Direction, solve};
+use crate::mir::{BlockId, Instruction, MirFunction, MirType, Opcode, Operand, Reg, Terminator};
+use crate::range::ValueRange;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Times a block is visited with plain joins before the ranges it computes
+/// are widened to guarantee the fixpoint is reached
+const VISITS_BEFORE_WIDENING: usize = 3;
+
+/// The ranges of the registers at a program point, or `None` where the
+/// point cannot be reached. Registers missing from the map hold no value
+/// on any path seen so far.
+pub type RangeFacts = Option<BTreeMap<Reg, ValueRange>>;
+
+/// Intervals of the values of registers, over SSA form
+///
+/// Arithmetic is followed through [`ValueRange`], rounded to the type of
+/// its result, and a branch on a comparison narrows the compared registers
+/// on each of its edges: past `if x < 10`, `x` is below ten on the then
+/// edge and, unless either side may be NaN, at least ten on the else edge.
+/// An edge whose condition can never hold carries `None`, so the blocks
+/// only it leads to are unreachable. Loops are widened after a few
+/// visits, pushing bounds that keep moving out to infinity.
+///
+/// Parameters, loads and call results hold anything their type allows.
+pub struct RangeAnalysis {
+    /// The comparison defining each register that holds one
+    comparisons: HashMap<Reg, (Opcode, Operand, Operand)>,
+    /// Registers holding integers, whose strict comparisons exclude the bound
+    integers: HashSet<Reg>,
+    visits: RefCell<HashMap<BlockId, usize>>,
+    /// The last facts computed at the end of each block, to widen against
+    exits: RefCell<HashMap<BlockId, RangeFacts>>,
+}
+
+impl RangeAnalysis {
+    /// Ranges at the start and end of every block
+    pub fn compute(function: &MirFunction) -> DataflowResults<RangeFacts> {
+        let mut comparisons = HashMap::new();
+        for (_, block) in function.arena.iter() {
+            for instruction in &block.instructions {
+                if is_comparison(instruction.op) {
+                    let (lhs, rhs) = (instruction.args[0], instruction.args[1]);
+                    comparisons.insert(instruction.dest, (instruction.op, lhs, rhs));
+                }
+            }
+        }
+        let integers = (0..function.reg_types.len())
+            .filter(|&reg| function.reg_types[reg].is_integer())
+            .collect();
+        let analysis = RangeAnalysis {
+            comparisons,
+            integers,
+            visits: RefCell::new(HashMap::new()),
+            exits: RefCell::new(HashMap::new()),
+        };
+        solve(&analysis, function)
+    }
+
+    /// Updates the ranges before the instruction to those after it
+    pub fn step(instruction: &Instruction, facts: &mut BTreeMap<Reg, ValueRange>) {
+        let arg = |index: usize| range(facts, &instruction.args[index]);
+        let typ = instruction.typ;
+        let value = match instruction.op {
+            // Stores produce nothing
+            Opcode::Store => return,
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => {
+                let mut exact = arithmetic(instruction.op, arg(0), arg(1));
+                if instruction.op == Opcode::Div && typ.is_integer() && !exact.is_empty() {
+                    // Integer division rounds towards zero
+                    exact.min = exact.min.trunc();
+                    exact.max = exact.max.trunc();
+                }
+                fit(exact, typ)
+            }
+            Opcode::Copy | Opcode::Convert => fit(arg(0), typ),
+            Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                let (a, b) = (arg(0), arg(1));
+                let same = instruction.args[0].as_reg().is_some()
+                    && instruction.args[0] == instruction.args[1];
+                if is_unreached(a) || is_unreached(b) {
+                    ValueRange::EMPTY
+                } else {
+                    match compare(instruction.op, a, b, same) {
+                        Some(holds) => ValueRange::constant(if holds { 1.0 } else { 0.0 }),
+                        None => ValueRange::BOOLEAN,
+                    }
+                }
+            }
+            Opcode::Select => match truth(arg(0)) {
+                Some(true) => arg(1),
+                Some(false) => arg(2),
+                None => arg(1).join(arg(2)),
+            },
+            // Powers are not followed
+            Opcode::Pow | Opcode::Load | Opcode::Call => type_range(typ),
+        };
+        facts.insert(instruction.dest, value);
+    }
+
+    /// Narrows the ranges of the operands of the comparison defining `cond`
+    /// to those for which it evaluates to `holds`. Returns false if it
+    /// never does.
+    fn assume(&self, cond: &Operand, holds: bool, facts: &mut BTreeMap<Reg, ValueRange>) -> bool {
+        let Some(cond) = cond.as_reg() else {
+            return true;
+        };
+        if truth(range(facts, &Operand::Reg(cond))) == Some(!holds) {
+            return false;
+        }
+        facts.insert(cond, ValueRange::constant(if holds { 1.0 } else { 0.0 }));
+        let Some(&(op, lhs, rhs)) = self.comparisons.get(&cond) else {
+            return true;
+        };
+
+        let (a, b) = (range(facts, &lhs), range(facts, &rhs));
+        let is_integer = |operand: &Operand| match operand {
+            Operand::Reg(reg) => self.integers.contains(reg),
+            operand => matches!(operand, Operand::ImmI64(_)),
+        };
+        let step = if is_integer(&lhs) || is_integer(&rhs) { 1.0 } else { 0.0 };
+        let (a, b) = match (op, holds) {
+            // A NaN is unequal to everything, so `!=` says nothing about either side
+            (Opcode::Ne, true) | (Opcode::Eq, false) => return true,
+            (Opcode::Ne, false) => {
+                let (a, b) = narrow(Opcode::Eq, a, b, step);
+                (ordered(a), ordered(b))
+            }
+            (op, true) => {
+                let (a, b) = narrow(op, a, b, step);
+                (ordered(a), ordered(b))
+            }
+            // A comparison with a NaN is false, so the other side is only
+            // narrowed if this one is a number
+            (op, false) => {
+                let (narrowed_a, narrowed_b) = narrow(negate(op), a, b, step);
+                (
+                    if b.nan { a } else { narrowed_a },
+                    if a.nan { b } else { narrowed_b },
+                )
+            }
+        };
+
+        for (operand, value) in [(lhs, a), (rhs, b)] {
+            if is_unreached(value) {
+                return false;
+            }
+            if let Operand::Reg(reg) = operand {
+                facts.insert(reg, value);
+            }
+        }
+        true
+    }
+}
+
+/// The range of an operand
+pub fn range(facts: &BTreeMap<Reg, ValueRange>, operand: &Operand) -> ValueRange {
+    match operand {
+        Operand::Reg(reg) => facts.get(reg).copied().unwrap_or(ValueRange::EMPTY),
+        Operand::ImmI64(value) => ValueRange::constant(*value as f64),
+        Operand::ImmF64(value) => ValueRange::constant(*value),
+        Operand::ImmF32(value) => ValueRange::constant(*value as f64),
+        Operand::ImmBool(value) => ValueRange::constant(if *value { 1.0 } else { 0.0 }),
+        Operand::Label(_) | Operand::Global(_) => ValueRange::UNKNOWN,
+    }
+}
+
+/// The value of a condition, if its range decides it
+pub fn truth(range: ValueRange) -> Option<bool> {
+    if range.nan || range.is_empty() {
+        return None;
+    }
+    if range.min == 0.0 && range.max == 0.0 {
+        Some(false)
+    } else if range.min > 0.0 || range.max < 0.0 {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the range holds no value at all, as for a register no
+/// path computed yet
+fn is_unreached(range: ValueRange) -> bool {
+    range.is_empty() && !range.nan
+}
+
+/// Every value a register of the type can hold
+pub fn type_range(typ: MirType) -> ValueRange {
+    let (min, max) = match typ {
+        MirType::I1 => return ValueRange::BOOLEAN,
+        MirType::I8 => (i8::MIN as f64, i8::MAX as f64),
+        MirType::I16 => (i16::MIN as f64, i16::MAX as f64),
+        MirType::I32 => (i32::MIN as f64, i32::MAX as f64),
+        MirType::I64 => (i64::MIN as f64, i64::MAX as f64),
+        MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64 | MirType::Void => {
+            return ValueRange::UNKNOWN;
+        }
+    };
+    ValueRange {
+        min,
+        max,
+        nan: false,
+        negative_zero: false,
+    }
+}
+
+/// The exact result of an arithmetic opcode on real numbers, before it is
+/// rounded to the type of its result
+pub fn arithmetic(op: Opcode, lhs: ValueRange, rhs: ValueRange) -> ValueRange {
+    match op {
+        Opcode::Add => lhs + rhs,
+        Opcode::Sub => lhs - rhs,
+        Opcode::Mul => lhs * rhs,
+        Opcode::Div => lhs / rhs,
+        Opcode::Mod => lhs % rhs,
+        _ => ValueRange::UNKNOWN,
+    }
+}
+
+/// The values of the type that an exact result may round or wrap to
+fn fit(exact: ValueRange, typ: MirType) -> ValueRange {
+    // Integers up to 2^53 are exact in the bounds
+    const EXACT_INTEGERS: f64 = 9007199254740992.0;
+    match typ {
+        typ if typ.is_integer() || typ == MirType::I1 => {
+            let bounds = type_range(typ);
+            if exact.is_empty() {
+                // Only NaN, which integer arithmetic traps on instead
+                ValueRange::EMPTY
+            } else if exact.min >= bounds.min
+                && exact.max <= bounds.max
+                && exact.min >= -EXACT_INTEGERS
+                && exact.max <= EXACT_INTEGERS
+            {
+                ValueRange {
+                    nan: false,
+                    negative_zero: false,
+                    ..exact
+                }
+            } else {
+                // Results out of range wrap around or trap
+                bounds
+            }
+        }
+        MirType::F32 => {
+            // Round the bounds outwards to the nearest `f32`
+            let exact = exact.saturate(f32::MAX as f64);
+            if exact.is_empty() {
+                return exact;
+            }
+            let (mut min, mut max) = (exact.min as f32, exact.max as f32);
+            if (min as f64) > exact.min {
+                min = min.next_down();
+            }
+            if (max as f64) < exact.max {
+                max = max.next_up();
+            }
+            ValueRange {
+                min: min as f64,
+                max: max as f64,
+                ..exact
+            }
+        }
+        // F8 and F16 are computed at F64 precision
+        _ => exact,
+    }
+}
+
+fn is_comparison(op: Opcode) -> bool {
+    matches!(op, Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge)
+}
+
+/// Whether `a op b` holds for every value of the ranges, fails for every
+/// one, or depends on the values. `same` is set if both sides are the same
+/// register.
+fn compare(op: Opcode, a: ValueRange, b: ValueRange, same: bool) -> Option<bool> {
+    let decide = |always: bool, never: bool| {
+        if always {
+            Some(true)
+        } else if never {
+            Some(false)
+        } else {
+            None
+        }
+    };
+    // A side without numbers is NaN
+    let ordered = !a.nan && !b.nan;
+    let numbers = !a.is_empty() && !b.is_empty();
+    match op {
+        // Only NaN compares unequal to itself
+        Opcode::Eq | Opcode::Le | Opcode::Ge if same => decide(!a.nan, !numbers),
+        Opcode::Lt | Opcode::Gt if same => Some(false),
+        Opcode::Lt => decide(ordered && (!numbers || a.max < b.min), !numbers || a.min >= b.max),
+        Opcode::Le => decide(ordered && (!numbers || a.max <= b.min), !numbers || a.min > b.max),
+        Opcode::Gt => compare(Opcode::Lt, b, a, same),
+        Opcode::Ge => compare(Opcode::Le, b, a, same),
+        Opcode::Eq => decide(
+            ordered && numbers && a.min == a.max && b.min == b.max && a.min == b.min,
+            !numbers || a.max < b.min || b.max < a.min,
+        ),
+        Opcode::Ne => compare(Opcode::Eq, a, b, same).map(|equal| !equal),
+        _ => None,
+    }
+}
+
+/// The comparison that holds when `op` fails on numbers
+fn negate(op: Opcode) -> Opcode {
+    match op {
+        Opcode::Lt => Opcode::Ge,
+        Opcode::Le => Opcode::Gt,
+        Opcode::Gt => Opcode::Le,
+        Opcode::Ge => Opcode::Lt,
+        Opcode::Eq => Opcode::Ne,
+        _ => Opcode::Eq,
+    }
+}
+
+/// Narrows the numbers of `a` and `b` to those for which `a op b` holds.
+/// `step` is the distance between neighbouring values, or zero for floats.
+fn narrow(op: Opcode, a: ValueRange, b: ValueRange, step: f64) -> (ValueRange, ValueRange) {
+    match op {
+        Opcode::Lt => (within(a, f64::NEG_INFINITY, b.max - step), within(b, a.min + step, f64::INFINITY)),
+        Opcode::Le => (within(a, f64::NEG_INFINITY, b.max), within(b, a.min, f64::INFINITY)),
+        Opcode::Gt | Opcode::Ge => {
+            let flipped = if op == Opcode::Gt { Opcode::Lt } else { Opcode::Le };
+            let (b, a) = narrow(flipped, b, a, step);
+            (a, b)
+        }
+        Opcode::Eq => (within(a, b.min, b.max), within(b, a.min, a.max)),
+        _ => (a, b),
+    }
+}
+
+/// The values of the range between `min` and `max`
+fn within(range: ValueRange, min: f64, max: f64) -> ValueRange {
+    let (min, max) = (range.min.max(min), range.max.min(max));
+    ValueRange {
+        min,
+        max,
+        nan: range.nan,
+        // Negative zero compares equal to zero
+        negative_zero: range.negative_zero && min <= 0.0 && max >= 0.0,
+    }
+}
+
+/// The range without NaN, for a side of a comparison that held
+fn ordered(range: ValueRange) -> ValueRange {
+    ValueRange { nan: false, ..range }
+}
+
+impl DataflowAnalysis for RangeAnalysis {
+    type Domain = RangeFacts;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    /// Parameters, and registers never written in the function, hold
+    /// anything their type allows
+    fn boundary(&self, function: &MirFunction) -> Self::Domain {
+        let mut defined: HashSet<Reg> = HashSet::new();
+        for (_, block) in function.arena.iter() {
+            defined.extend(block.phi_nodes.iter().map(|phi| phi.dest));
+            defined.extend(block.params.iter().map(|(reg, _)| *reg));
+            defined.extend(block.instructions.iter().map(|instruction| instruction.dest));
+        }
+        for (reg, _) in &function.params {
+            defined.remove(reg);
+        }
+        Some(
+            (0..function.reg_types.len())
+                .filter(|reg| !defined.contains(reg))
+                .map(|reg| (reg, type_range(function.reg_types[reg])))
+                .collect(),
+        )
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        None
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        let Some(other) = other else {
+            return;
+        };
+        let Some(into) = into else {
+            *into = Some(other.clone());
+            return;
+        };
+        for (&reg, &value) in other {
+            let entry = into.entry(reg).or_insert(ValueRange::EMPTY);
+            *entry = entry.join(value);
+        }
+    }
+
+    fn transfer(&self, function: &MirFunction, block_id: BlockId, input: &Self::Domain) -> Self::Domain {
+        let mut output = input.clone();
+        if let Some(facts) = &mut output {
+            for instruction in &function.block(block_id).instructions {
+                Self::step(instruction, facts);
+            }
+        }
+
+        let mut visits = self.visits.borrow_mut();
+        let visits = visits.entry(block_id).or_default();
+        *visits += 1;
+        let mut exits = self.exits.borrow_mut();
+        if *visits > VISITS_BEFORE_WIDENING
+            && let (Some(Some(old)), Some(new)) = (exits.get(&block_id), &mut output)
+        {
+            for (reg, value) in new.iter_mut() {
+                if let Some(old) = old.get(reg) {
+                    *value = old.widen(*value);
+                }
+            }
+        }
+        exits.insert(block_id, output.clone());
+        output
+    }
+
+    /// Narrows the compared registers to the values that take the edge,
+    /// then binds the phis and block parameters of `to` to the values
+    /// `from` passes along
+    fn edge_transfer(&self, function: &MirFunction, from: BlockId, to: BlockId, facts: &Self::Domain) -> Self::Domain {
+        let mut edge_facts = facts.clone()?;
+        let source = function.block(from);
+        if let Terminator::BrIf { cond, then_bb, else_bb, .. } = &source.terminator
+            && then_bb != else_bb
+            && !self.assume(cond, to == *then_bb, &mut edge_facts)
+        {
+            return None;
+        }
+
+        let target = function.block(to);
+        for phi in &target.phi_nodes {
+            let value = phi
+                .incoming(from)
+                .map_or(ValueRange::EMPTY, |operand| range(&edge_facts, operand));
+            edge_facts.insert(phi.dest, value);
+        }
+        for (index, (reg, _)) in target.params.iter().enumerate() {
+            // Both edges of a BrIf may lead to the same block with different arguments
+            let value = source
+                .terminator
+                .edges()
+                .into_iter()
+                .filter(|(edge_target, _)| *edge_target == to)
+                .filter_map(|(_, args)| args.get(index))
+                .map(|arg| range(&edge_facts, arg))
+                .fold(ValueRange::EMPTY, ValueRange::join);
+            edge_facts.insert(*reg, value);
+        }
+        Some(edge_facts)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::mir::Operand;
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+/// Operands an instruction holds without a heap allocation
+pub const INLINE_ARGS: usize = 3;
+
+/// Fills the unused inline slots
+const EMPTY: Operand = Operand::ImmBool(false);
+
+/// The operands of an [`Instruction`](crate::mir::Instruction)
+///
+/// Up to [`INLINE_ARGS`] operands are kept in the instruction itself,
+/// which covers every opcode but calls with more than three arguments;
+/// those spill to the heap. Derefs to a slice of the operands.
+#[derive(Clone)]
+pub struct Args(Storage);
+
+#[derive(Clone)]
+enum Storage {
+    Inline { len: u8, operands: [Operand; INLINE_ARGS] },
+    Spilled(Vec<Operand>),
+}
+
+impl Args {
+    pub fn new() -> Self {
+        Args(Storage::Inline {
+            len: 0,
+            operands: [EMPTY; INLINE_ARGS],
+        })
+    }
+
+    pub fn as_slice(&self) -> &[Operand] {
+        match &self.0 {
+            Storage::Inline { len, operands } => &operands[..*len as usize],
+            Storage::Spilled(operands) => operands,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Operand] {
+        match &mut self.0 {
+            Storage::Inline { len, operands } => &mut operands[..*len as usize],
+            Storage::Spilled(operands) => operands,
+        }
+    }
+
+    pub fn push(&mut self, operand: Operand) {
+        match &mut self.0 {
+            Storage::Inline { len, operands } if (*len as usize) < INLINE_ARGS => {
+                operands[*len as usize] = operand;
+                *len += 1;
+            }
+            Storage::Inline { operands, .. } => {
+                let mut spilled = operands.to_vec();
+                spilled.push(operand);
+                self.0 = Storage::Spilled(spilled);
+            }
+            Storage::Spilled(operands) => operands.push(operand),
+        }
+    }
+
+    /// Whether the operands are on the heap
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.0, Storage::Spilled(_))
+    }
+
+    /// Bytes the operands take on the heap
+    pub fn heap_bytes(&self) -> usize {
+        match &self.0 {
+            Storage::Inline { .. } => 0,
+            Storage::Spilled(operands) => operands.capacity() * mem::size_of::<Operand>(),
+        }
+    }
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args::new()
+    }
+}
+
+impl Deref for Args {
+    type Target = [Operand];
+
+    fn deref(&self) -> &[Operand] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for Args {
+    fn deref_mut(&mut self) -> &mut [Operand] {
+        self.as_mut_slice()
+    }
+}
+
+impl PartialEq for Args {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl fmt::Debug for Args {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+impl FromIterator<Operand> for Args {
+    fn from_iter<I: IntoIterator<Item = Operand>>(iter: I) -> Self {
+        let mut args = Args::new();
+        for operand in iter {
+            args.push(operand);
+        }
+        args
+    }
+}
+
+impl From<Vec<Operand>> for Args {
+    fn from(operands: Vec<Operand>) -> Self {
+        if operands.len() > INLINE_ARGS {
+            return Args(Storage::Spilled(operands));
+        }
+        operands.into_iter().collect()
+    }
+}
+
+impl<const N: usize> From<[Operand; N]> for Args {
+    fn from(operands: [Operand; N]) -> Self {
+        operands.into_iter().collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a Args {
+    type Item = &'a Operand;
+    type IntoIter = std::slice::Iter<'a, Operand>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Args {
+    type Item = &'a mut Operand;
+    type IntoIter = std::slice::IterMut<'a, Operand>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_mut_slice().iter_mut()
+    }
+}
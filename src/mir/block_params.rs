@@ -0,0 +1,93 @@
+//! Conversion between the two representations of SSA merges.
+//!
+//! Phi nodes list one incoming value per predecessor at the top of a block.
+//! Block parameters move those values onto the branches instead: the block
+//! declares parameters and every edge into it passes arguments,
+//! so passes never have to look up which predecessor a value came from.
+
+use crate::mir::{BlockId, MirFunction, Operand, PhiNode};
+
+/// Replaces every phi node with a block parameter, moving the incoming
+/// values onto the branches of the predecessors
+pub fn phis_to_block_params(function: &mut MirFunction) {
+    for index in 0..function.arena.len() {
+        let pred = BlockId::new(index);
+        let targets: Vec<BlockId> = function
+            .block(pred)
+            .terminator
+            .edges()
+            .into_iter()
+            .map(|(target, _)| target)
+            .collect();
+
+        // Take one incoming value per edge, so two edges into the same block
+        // each receive their own arguments
+        let mut edge_args: Vec<Vec<Operand>> = Vec::new();
+        for target in targets {
+            let args = function
+                .block_mut(target)
+                .phi_nodes
+                .iter_mut()
+                .map(|phi| {
+                    let position = phi
+                        .incomings
+                        .iter()
+                        .position(|(block, _)| *block == pred)
+                        .expect("Phi has no incoming value for a predecessor");
+                    phi.incomings.remove(position).1
+                })
+                .collect();
+            edge_args.push(args);
+        }
+
+        for ((_, args), values) in function
+            .block_mut(pred)
+            .terminator
+            .edges_mut()
+            .into_iter()
+            .zip(edge_args)
+        {
+            args.extend(values);
+        }
+    }
+
+    for index in 0..function.arena.len() {
+        let block = function.block_mut(BlockId::new(index));
+        for phi in std::mem::take(&mut block.phi_nodes) {
+            block.params.push((phi.dest, phi.typ));
+        }
+    }
+}
+
+/// Replaces every block parameter with a phi node, collecting the
+/// arguments passed along each incoming edge
+pub fn block_params_to_phis(function: &mut MirFunction) {
+    for index in 0..function.arena.len() {
+        let block = function.block_mut(BlockId::new(index));
+        for (dest, typ) in std::mem::take(&mut block.params) {
+            block.phi_nodes.push(PhiNode {
+                dest,
+                typ,
+                incomings: Vec::new(),
+            });
+        }
+    }
+
+    for index in 0..function.arena.len() {
+        let pred = BlockId::new(index);
+        let edges: Vec<(BlockId, Vec<Operand>)> = function
+            .block_mut(pred)
+            .terminator
+            .edges_mut()
+            .into_iter()
+            .map(|(target, args)| (target, std::mem::take(args)))
+            .collect();
+
+        for (target, args) in edges {
+            let phis = &mut function.block_mut(target).phi_nodes;
+            for (phi, arg) in phis.iter_mut().zip(args) {
+                phi.incomings.push((pred, arg));
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Convenience API for emitting MIR.
+//!
+//! A [`Builder`] borrows a function and appends instructions to the end of
+//! one of its blocks, allocating destination registers as it goes.
+//!
+//! # Example
+//! ```ignore
+//! let mut function = MirFunction::new(Symbol::intern("double"), Vec::new(), MirType::F64);
+//! let x = function.new_reg(MirType::F64);
+//! function.params.push((x, MirType::F64));
+//!
+//! let mut b = Builder::at_entry(&mut function);
+//! let sum = b.add(MirType::F64, Operand::Reg(x), Operand::Reg(x));
+//! b.ret(Some(Operand::Reg(sum)));
+//! ```
+
+use crate::mir::{
+    Args, BasicBlock, BlockId, BranchWeights, FuncId, GlobalId, Instruction, MirFunction, MirType, Opcode, Operand, Reg,
+    Terminator, TrapReason,
+};
+use crate::span::Span;
+
+pub struct Builder<'f> {
+    function: &'f mut MirFunction,
+    block: BlockId,
+    span: Option<Span>,
+}
+
+impl<'f> Builder<'f> {
+    /// Create a builder that appends to the end of `block`
+    pub fn new(function: &'f mut MirFunction, block: BlockId) -> Self {
+        Builder {
+            function,
+            block,
+            span: None,
+        }
+    }
+
+    /// Attribute everything emitted from now on to the given source span
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Create a builder that appends to the function's entry block
+    pub fn at_entry(function: &'f mut MirFunction) -> Self {
+        let entry = function.entry;
+        Builder::new(function, entry)
+    }
+
+    pub fn function(&self) -> &MirFunction {
+        self.function
+    }
+
+    pub fn function_mut(&mut self) -> &mut MirFunction {
+        self.function
+    }
+
+    /// The block instructions are currently appended to
+    pub fn current_block(&self) -> BlockId {
+        self.block
+    }
+
+    /// Move the insertion point to the end of another block
+    pub fn position_at_end(&mut self, block: BlockId) {
+        self.block = block;
+    }
+
+    /// Allocate a new, empty block without moving the insertion point
+    pub fn create_block(&mut self) -> BlockId {
+        self.function.arena.alloc(BasicBlock {
+            instructions: Vec::new(),
+            terminator: Terminator::Unreachable,
+            phi_nodes: Vec::new(),
+            params: Vec::new(),
+            terminator_span: None,
+        })
+    }
+
+    /// Allocate a fresh register of the given type
+    pub fn new_reg(&mut self, typ: MirType) -> Reg {
+        self.function.new_reg(typ)
+    }
+
+    /// Returns true if the current block already has a terminator
+    pub fn is_terminated(&self) -> bool {
+        !matches!(
+            self.function.block(self.block).terminator,
+            Terminator::Unreachable
+        )
+    }
+
+    /// Append an already constructed instruction
+    pub fn push(&mut self, instruction: Instruction) {
+        self.function
+            .block_mut(self.block)
+            .instructions
+            .push(instruction);
+    }
+
+    /// Append an instruction writing to a fresh register and return that register
+    pub fn emit(&mut self, op: Opcode, typ: MirType, args: Args) -> Reg {
+        let dest = self.new_reg(typ);
+        self.push(Instruction {
+            dest,
+            op,
+            typ,
+            args,
+            span: self.span,
+        });
+        dest
+    }
+
+    pub fn binary(&mut self, op: Opcode, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.emit(op, typ, [lhs, rhs].into())
+    }
+
+    pub fn add(&mut self, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(Opcode::Add, typ, lhs, rhs)
+    }
+
+    pub fn sub(&mut self, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(Opcode::Sub, typ, lhs, rhs)
+    }
+
+    pub fn mul(&mut self, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(Opcode::Mul, typ, lhs, rhs)
+    }
+
+    pub fn div(&mut self, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(Opcode::Div, typ, lhs, rhs)
+    }
+
+    pub fn rem(&mut self, typ: MirType, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(Opcode::Mod, typ, lhs, rhs)
+    }
+
+    /// Compare two operands, producing an I1
+    pub fn compare(&mut self, op: Opcode, lhs: Operand, rhs: Operand) -> Reg {
+        self.binary(op, MirType::I1, lhs, rhs)
+    }
+
+    pub fn eq(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Eq, lhs, rhs)
+    }
+
+    pub fn ne(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Ne, lhs, rhs)
+    }
+
+    pub fn lt(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Lt, lhs, rhs)
+    }
+
+    pub fn le(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Le, lhs, rhs)
+    }
+
+    pub fn gt(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Gt, lhs, rhs)
+    }
+
+    pub fn ge(&mut self, lhs: Operand, rhs: Operand) -> Reg {
+        self.compare(Opcode::Ge, lhs, rhs)
+    }
+
+    /// Pick `if_true` or `if_false` depending on an I1 condition, without branching
+    pub fn select(&mut self, typ: MirType, cond: Operand, if_true: Operand, if_false: Operand) -> Reg {
+        self.emit(Opcode::Select, typ, [cond, if_true, if_false].into())
+    }
+
+    /// Convert a float to another float type
+    pub fn convert(&mut self, typ: MirType, src: Operand) -> Reg {
+        self.emit(Opcode::Convert, typ, [src].into())
+    }
+
+    /// Copy a value into a fresh register
+    pub fn copy(&mut self, typ: MirType, src: Operand) -> Reg {
+        self.emit(Opcode::Copy, typ, [src].into())
+    }
+
+    /// Copy a value into an existing register, e.g. a variable
+    pub fn copy_into(&mut self, dest: Reg, typ: MirType, src: Operand) {
+        self.push(Instruction {
+            dest,
+            op: Opcode::Copy,
+            typ,
+            args: [src].into(),
+            span: self.span,
+        });
+    }
+
+    /// Read the current value of a global
+    pub fn load(&mut self, typ: MirType, global: GlobalId) -> Reg {
+        self.emit(Opcode::Load, typ, [Operand::Global(global)].into())
+    }
+
+    /// Overwrite a global with a value
+    pub fn store(&mut self, global: GlobalId, value: Operand) {
+        self.emit(Opcode::Store, MirType::Void, [Operand::Global(global), value].into());
+    }
+
+    /// Call a function by name, returning the register holding its result
+    pub fn call(&mut self, typ: MirType, callee: FuncId, args: Vec<Operand>) -> Reg {
+        let operands = std::iter::once(Operand::Label(callee)).chain(args).collect();
+        self.emit(Opcode::Call, typ, operands)
+    }
+
+    /// Set the terminator of the current block, replacing any previous one
+    pub fn terminate(&mut self, terminator: Terminator) {
+        let block = self.function.block_mut(self.block);
+        block.terminator = terminator;
+        block.terminator_span = self.span;
+    }
+
+    pub fn br(&mut self, target: BlockId) {
+        self.terminate(Terminator::br(target));
+    }
+
+    pub fn br_if(&mut self, cond: Operand, then_bb: BlockId, else_bb: BlockId) {
+        self.terminate(Terminator::br_if(cond, then_bb, else_bb));
+    }
+
+    pub fn br_if_weighted(&mut self, cond: Operand, then_bb: BlockId, else_bb: BlockId, weights: Option<BranchWeights>) {
+        self.terminate(Terminator::br_if_weighted(cond, then_bb, else_bb, weights));
+    }
+
+    /// Branch on `value`, taking the block paired with the first matching case
+    pub fn switch(&mut self, value: Operand, cases: Vec<(Operand, BlockId)>, default: BlockId) {
+        self.terminate(Terminator::switch(value, cases, default));
+    }
+
+    pub fn ret(&mut self, value: Option<Operand>) {
+        self.terminate(Terminator::Ret { value });
+    }
+
+    /// Stop the program, reporting the reason
+    pub fn trap(&mut self, reason: TrapReason) {
+        self.terminate(Terminator::Trap { reason });
+    }
+}
@@ -1,6 +1,6 @@
-use crate::mir::{BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Operand, Terminator};
+use crate::mir::{BlockId, MirFunction, Operand, Terminator};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug)]
 pub struct CFGAnalysis {
@@ -22,22 +22,10 @@ impl CFGAnalysis {
         }
 
         for (block_id, block) in function.arena.iter() {
-            match &block.terminator {
-                Terminator::Br { target } => {
-                    // block_id -> target
-                    successors.get_mut(&block_id).unwrap().push(*target);
-                    predecessors.get_mut(target).unwrap().push(block_id);
-                }
-                Terminator::BrIf {
-                    then_bb, else_bb, ..
-                } => {
-                    // block_id -> then_bb, else_bb
-                    successors.get_mut(&block_id).unwrap().push(*then_bb);
-                    successors.get_mut(&block_id).unwrap().push(*else_bb);
-                    predecessors.get_mut(then_bb).unwrap().push(block_id);
-                    predecessors.get_mut(else_bb).unwrap().push(block_id);
-                }
-                _ => {}
+            // block_id -> target for every outgoing edge
+            for (target, _) in block.terminator.edges() {
+                successors.get_mut(&block_id).unwrap().push(target);
+                predecessors.get_mut(&target).unwrap().push(block_id);
             }
         }
 
@@ -47,4 +35,62 @@ impl CFGAnalysis {
             successors,
         }
     }
+
+    /// Blocks reachable from the entry block
+    pub fn reachable(&self) -> HashSet<BlockId> {
+        let mut reachable = HashSet::from([self.entry]);
+        let mut worklist = vec![self.entry];
+        while let Some(block) = worklist.pop() {
+            for &successor in &self.successors[&block] {
+                if reachable.insert(successor) {
+                    worklist.push(successor);
+                }
+            }
+        }
+        reachable
+    }
+}
+
+/// Replaces the terminator of `block` with a branch to `target`, dropping
+/// the values it passed to the phis of the successors it no longer
+/// branches to
+pub fn branch_to(function: &mut MirFunction, block: BlockId, target: BlockId, args: Vec<Operand>) {
+    let terminator = std::mem::replace(&mut function.block_mut(block).terminator, Terminator::Br { target, args });
+    let dropped: HashSet<BlockId> = terminator
+        .edges()
+        .into_iter()
+        .map(|(successor, _)| successor)
+        .filter(|&successor| successor != target)
+        .collect();
+    for successor in dropped {
+        for phi in &mut function.block_mut(successor).phi_nodes {
+            phi.incomings.retain(|(pred, _)| *pred != block);
+        }
+    }
+}
+
+/// Empties the blocks that can no longer be reached from the entry block,
+/// ending them in `unreachable`, and drops the values they passed to phis.
+/// Returns the number of blocks emptied.
+pub fn remove_unreachable_blocks(function: &mut MirFunction) -> usize {
+    let reachable = CFGAnalysis::new(function).reachable();
+    let mut removed = 0;
+    for index in 0..function.arena.len() {
+        let block_id = BlockId::new(index);
+        let block = function.block_mut(block_id);
+        if reachable.contains(&block_id) {
+            for phi in &mut block.phi_nodes {
+                phi.incomings.retain(|(pred, _)| reachable.contains(pred));
+            }
+        } else if !block.instructions.is_empty()
+            || !block.phi_nodes.is_empty()
+            || !matches!(block.terminator, Terminator::Unreachable)
+        {
+            block.instructions.clear();
+            block.phi_nodes.clear();
+            block.terminator = Terminator::Unreachable;
+            removed += 1;
+        }
+    }
+    removed
 }
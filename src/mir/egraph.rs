@@ -0,0 +1,164 @@
+//! E-graphs of MIR values, for equality saturation.
+//!
+//! An e-graph holds many equivalent ways to compute a value at once: every
+//! e-class is a set of e-nodes that compute the same value, and an e-node's
+//! operands are e-classes rather than single expressions. Rewrites only
+//! ever add e-nodes and merge e-classes, so no rewrite can hide a better one
+//! from the rules that run after it. [`EGraph::rebuild`] restores the
+//! invariant that no two e-nodes with the same operator and operands are
+//! in different classes.
+
+use crate::mir::{MirType, Opcode, Reg};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub type ClassId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ENode {
+    /// A register whose value is not known to the e-graph
+    Reg(Reg, MirType),
+    /// A float constant, by its bits
+    Float(u64),
+    Op {
+        op: Opcode,
+        typ: MirType,
+        args: Vec<ClassId>,
+    },
+}
+
+impl ENode {
+    fn canonical(&self, egraph: &EGraph) -> ENode {
+        match self {
+            ENode::Op { op, typ, args } => ENode::Op {
+                op: *op,
+                typ: *typ,
+                args: args.iter().map(|&arg| egraph.find(arg)).collect(),
+            },
+            leaf => leaf.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EGraph {
+    /// Union-find over class ids
+    parents: Vec<ClassId>,
+    /// The class of every canonical e-node
+    memo: HashMap<ENode, ClassId>,
+    /// The e-nodes of every canonical class
+    classes: BTreeMap<ClassId, Vec<ENode>>,
+}
+
+impl EGraph {
+    pub fn new() -> Self {
+        EGraph::default()
+    }
+
+    /// The canonical id of the class
+    pub fn find(&self, mut class: ClassId) -> ClassId {
+        while self.parents[class] != class {
+            class = self.parents[class];
+        }
+        class
+    }
+
+    /// The class of the e-node, added in a class of its own if it is new
+    pub fn add(&mut self, node: ENode) -> ClassId {
+        let node = node.canonical(self);
+        if let Some(&class) = self.memo.get(&node) {
+            return self.find(class);
+        }
+        let class = self.parents.len();
+        self.parents.push(class);
+        self.memo.insert(node.clone(), class);
+        self.classes.insert(class, vec![node]);
+        class
+    }
+
+    /// Records that two classes hold the same value. Returns whether they
+    /// were different classes before.
+    pub fn union(&mut self, a: ClassId, b: ClassId) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        let (keep, merge) = if a < b { (a, b) } else { (b, a) };
+        self.parents[merge] = keep;
+        let nodes = self.classes.remove(&merge).unwrap_or_default();
+        self.classes.entry(keep).or_default().extend(nodes);
+        true
+    }
+
+    /// Merges the classes of e-nodes that became the same after their
+    /// operands' classes were merged, until there are none
+    pub fn rebuild(&mut self) {
+        loop {
+            let mut memo: HashMap<ENode, ClassId> = HashMap::new();
+            let mut merges = Vec::new();
+            for (&class, nodes) in &self.classes {
+                for node in nodes {
+                    match memo.insert(node.canonical(self), class) {
+                        Some(other) if other != class => merges.push((class, other)),
+                        _ => {}
+                    }
+                }
+            }
+            let mut merged = false;
+            for (a, b) in merges {
+                merged |= self.union(a, b);
+            }
+            if merged {
+                continue;
+            }
+
+            let classes: Vec<ClassId> = self.classes.keys().copied().collect();
+            for class in classes {
+                let mut nodes: Vec<ENode> = self.classes[&class].iter().map(|node| node.canonical(self)).collect();
+                let mut seen = HashSet::new();
+                nodes.retain(|node| seen.insert(node.clone()));
+                self.classes.insert(class, nodes);
+            }
+            self.memo = memo;
+            return;
+        }
+    }
+
+    /// The canonical classes and their e-nodes, in the order the classes
+    /// were made
+    pub fn classes(&self) -> Vec<(ClassId, Vec<ENode>)> {
+        self.classes.iter().map(|(&class, nodes)| (class, nodes.clone())).collect()
+    }
+
+    pub fn nodes(&self, class: ClassId) -> &[ENode] {
+        self.classes.get(&self.find(class)).map_or(&[], Vec::as_slice)
+    }
+
+    /// How many e-nodes the graph holds
+    pub fn len(&self) -> usize {
+        self.classes.values().map(Vec::len).sum()
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    /// The constant the class is known to be, if any
+    pub fn constant(&self, class: ClassId) -> Option<f64> {
+        self.nodes(class).iter().find_map(|node| match node {
+            ENode::Float(bits) => Some(f64::from_bits(*bits)),
+            _ => None,
+        })
+    }
+
+    /// The type of the values of the class, unless it only holds constants
+    pub fn class_type(&self, class: ClassId) -> Option<MirType> {
+        self.nodes(class).iter().find_map(|node| match node {
+            ENode::Reg(_, typ) | ENode::Op { typ, .. } => Some(*typ),
+            ENode::Float(_) => None,
+        })
+    }
+}
@@ -0,0 +1,80 @@
+//! Copies of the MIR as each pass left it.
+//!
+//! [`crate::compiler::Compiler::ir_history`] lowers a program and records a
+//! snapshot after every pass that may change it, so pass authors can see
+//! what a pass did to a given input: `iris ir-diff --pass <name>` prints
+//! the difference between the snapshots before and after each run of the
+//! pass, and `--keep-ir-history` writes all of them out.
+
+use crate::fix::unified_diff_between;
+use crate::mir::MirProgram;
+use crate::mir::passes::print::MirPrintingPass;
+use crate::mir::visitor::MirVisitor;
+
+/// The MIR after one pass
+#[derive(Debug, Clone)]
+pub struct IrSnapshot {
+    /// Name of the pass, as `--pass` takes it
+    pub pass: String,
+    pub program: MirProgram,
+}
+
+/// Every snapshot taken while lowering a program, in the order the passes
+/// ran. The first is the MIR right after lowering.
+#[derive(Debug, Clone, Default)]
+pub struct IrHistory {
+    pub snapshots: Vec<IrSnapshot>,
+}
+
+impl IrHistory {
+    pub fn new() -> Self {
+        IrHistory::default()
+    }
+
+    /// Keeps a copy of the program as the pass left it
+    pub fn record(&mut self, pass: &str, program: &MirProgram) {
+        self.snapshots.push(IrSnapshot {
+            pass: pass.to_string(),
+            program: program.clone(),
+        });
+    }
+
+    /// The names of the passes that ran after lowering, each once, in the
+    /// order they first ran
+    pub fn passes(&self) -> Vec<&str> {
+        let mut passes = Vec::new();
+        for snapshot in self.snapshots.iter().skip(1) {
+            if !passes.contains(&snapshot.pass.as_str()) {
+                passes.push(snapshot.pass.as_str());
+            }
+        }
+        passes
+    }
+
+    /// For each run of the named pass, the program before and after it
+    pub fn runs(&self, pass: &str) -> Vec<(&MirProgram, &MirProgram)> {
+        self.snapshots
+            .windows(2)
+            .filter(|pair| pair[1].pass == pass)
+            .map(|pair| (&pair[0].program, &pair[1].program))
+            .collect()
+    }
+}
+
+/// The program as `--emit mir` prints it, without color
+pub fn print_mir(program: &MirProgram, spans: bool) -> String {
+    let mut printer = MirPrintingPass::new().with_spans(spans);
+    printer.visit_program(&mut program.clone());
+    printer.into_output()
+}
+
+/// A unified diff of the printed MIR before and after a pass, empty if the
+/// pass changed nothing
+pub fn diff_mir(pass: &str, before: &MirProgram, after: &MirProgram, spans: bool) -> String {
+    unified_diff_between(
+        &format!("mir before {}", pass),
+        &format!("mir after {}", pass),
+        &print_mir(before, spans),
+        &print_mir(after, spans),
+    )
+}
@@ -0,0 +1,512 @@
+use crate::intern::Symbol;
+use crate::mir::{
+    BasicBlock, BlockId, FuncId, FunctionTable, GlobalId, Instruction, Linkage, MirFunction, MirGlobal,
+    MirProgram, MirType, Opcode, Operand, Terminator, TrapReason, INIT_FUNCTION,
+};
+use crate::span::Span;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Calls nested deeper than this are reported as a stack overflow
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+/// Instructions and branches executed by a single top-level call before
+/// giving up on it, so a function that never returns does not hang the caller
+pub const MAX_STEPS: u64 = 10_000_000;
+
+/// Execution stopped because the program did something it cannot recover from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap {
+    /// The function that was executing when the trap happened
+    pub function: FuncId,
+    /// Where in the source the trap happened, as `file:span`, if known
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl Trap {
+    /// Whether execution ran into [`MAX_STEPS`] or [`MAX_CALL_DEPTH`]
+    /// rather than something the program did wrong
+    pub fn gave_up(&self) -> bool {
+        self.message.starts_with("gave up after") || self.message == "call stack overflow"
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "trap in '{}' at {}: {}",
+                self.function, location, self.message
+            ),
+            None => write!(f, "trap in '{}': {}", self.function, self.message),
+        }
+    }
+}
+
+/// A Rust function that calls to an extern function run, given arguments
+/// of the types the extern declares and returning nothing for `void`
+pub type HostFunction = Rc<dyn Fn(&[Value]) -> Option<Value>>;
+
+/// Host functions by the name of the extern function they define
+pub type HostFunctions = HashMap<FuncId, HostFunction>;
+
+/// Executes MIR directly, without generating code for it
+///
+/// Works on both SSA form, evaluating the phis of a block on entry, and
+/// block parameters. Registers that are read before they are written stop
+/// execution with a trap, unless a phi or block argument only passes them on. Globals keep their values from one call to the
+/// next, and are initialized before the first call. Extern functions can
+/// only be called if a [`HostFunction`] is given for them.
+pub struct Interpreter<'a> {
+    functions: HashMap<FuncId, &'a MirFunction>,
+    symbols: &'a FunctionTable,
+    host: Option<&'a HostFunctions>,
+    declared_globals: &'a [MirGlobal],
+    globals: HashMap<GlobalId, Value>,
+    initialized: bool,
+    depth: usize,
+    steps: u64,
+}
+
+/// The registers of one active call
+struct Frame<'a> {
+    function: &'a MirFunction,
+    registers: Vec<Option<Value>>,
+}
+
+impl<'a> Frame<'a> {
+    fn trap(&self, message: impl Into<String>) -> Trap {
+        Trap {
+            function: self.function.name,
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    /// Attaches a source location to a trap raised by this frame itself
+    fn locate(&self, mut trap: Trap, span: Option<Span>) -> Trap {
+        if trap.location.is_none() && trap.function == self.function.name {
+            trap.location = span.map(|span| match &self.function.source {
+                Some(source) => format!("{}:{}", source, span),
+                None => span.to_string(),
+            });
+        }
+        trap
+    }
+
+    fn read(&self, operand: &Operand) -> Result<Value, Trap> {
+        match operand {
+            Operand::Reg(reg) => self
+                .registers
+                .get(*reg)
+                .copied()
+                .flatten()
+                .ok_or_else(|| self.trap(format!("read of uninitialized register r{}", reg))),
+            Operand::ImmI64(value) => Ok(Value::Int(*value)),
+            Operand::ImmF64(value) => Ok(Value::F64(*value)),
+            Operand::ImmF32(value) => Ok(Value::F32(*value)),
+            Operand::ImmBool(value) => Ok(Value::Bool(*value)),
+            Operand::Label(label) => Err(self.trap(format!("label @{} used as a value", label))),
+            Operand::Global(global) => {
+                Err(self.trap(format!("global ${} used as a value without a load", global)))
+            }
+        }
+    }
+
+    /// Reads a value that is only passed along, as phis and block
+    /// arguments do. An uninitialized register stays uninitialized, since
+    /// SSA construction merges a variable on every path into a block even
+    /// where it is only ever read after being written.
+    fn forward(&self, operand: &Operand) -> Result<Option<Value>, Trap> {
+        match operand {
+            Operand::Reg(reg) => Ok(self.registers.get(*reg).copied().flatten()),
+            _ => self.read(operand).map(Some),
+        }
+    }
+
+    /// Writes a value passed along by [`Frame::forward`]
+    fn write_forwarded(&mut self, reg: usize, value: Option<Value>, typ: MirType) {
+        match value {
+            Some(value) => self.write(reg, value.convert(typ)),
+            None if reg < self.registers.len() => self.registers[reg] = None,
+            None => {}
+        }
+    }
+
+    fn write(&mut self, reg: usize, value: Value) {
+        if self.registers.len() <= reg {
+            self.registers.resize(reg + 1, None);
+        }
+        self.registers[reg] = Some(value);
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a MirProgram) -> Self {
+        Interpreter {
+            functions: program
+                .functions
+                .iter()
+                .map(|function| (function.name, function))
+                .collect(),
+            symbols: &program.symbols,
+            host: None,
+            declared_globals: &program.globals,
+            globals: HashMap::new(),
+            initialized: false,
+            depth: 0,
+            steps: 0,
+        }
+    }
+
+    /// Resets every global to zero and then runs [`INIT_FUNCTION`], if the
+    /// program has one, to give them their initial values
+    pub fn initialize(&mut self) -> Result<(), Trap> {
+        self.globals = self
+            .declared_globals
+            .iter()
+            .map(|global| (global.name, Value::F64(0.0).convert(global.typ)))
+            .collect();
+        self.initialized = true;
+
+        let init = Symbol::intern(INIT_FUNCTION);
+        if self.functions.contains_key(&init) {
+            self.steps = 0;
+            self.execute(init, &[], init)?;
+        }
+        Ok(())
+    }
+
+    /// Calls a function with the given arguments, initializing the globals
+    /// first if no call has yet. Returns its result, or `None` if it
+    /// returns nothing.
+    pub fn call(&mut self, name: FuncId, args: &[Value]) -> Result<Option<Value>, Trap> {
+        if !self.initialized {
+            self.initialize()?;
+        }
+        self.steps = 0;
+        self.execute(name, args, name)
+    }
+
+    /// The current value of a global, if the program has it and the
+    /// globals were initialized
+    pub fn global(&self, name: GlobalId) -> Option<Value> {
+        self.globals.get(&name).copied()
+    }
+
+    /// Starts from globals another interpreter of the same program left,
+    /// see [`Interpreter::into_globals`], instead of initializing them
+    pub fn with_globals(mut self, globals: HashMap<GlobalId, Value>) -> Self {
+        self.globals = globals;
+        self.initialized = true;
+        self
+    }
+
+    /// Runs calls to the extern functions the host functions are given for
+    pub fn with_host_functions(mut self, host: &'a HostFunctions) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// The values of the globals, if they were initialized
+    pub fn into_globals(self) -> Option<HashMap<GlobalId, Value>> {
+        self.initialized.then_some(self.globals)
+    }
+
+    fn execute(
+        &mut self,
+        name: FuncId,
+        args: &[Value],
+        caller: FuncId,
+    ) -> Result<Option<Value>, Trap> {
+        let Some(&function) = self.functions.get(&name) else {
+            if let Some(host) = self.host.and_then(|host| host.get(&name))
+                && let Some(signature) = self.symbols.get(name)
+                && signature.linkage == Linkage::External
+            {
+                let args: Vec<Value> = args.iter().zip(&signature.params).map(|(arg, typ)| arg.convert(*typ)).collect();
+                return Ok(host(&args));
+            }
+            let external = self.symbols.get(name).is_some_and(|f| f.linkage == Linkage::External);
+            let message = match external {
+                true => format!("call to extern function '{}', which has no host function, so only native code can call it", name),
+                false => format!("call to unknown function '{}'", name),
+            };
+            return Err(Trap {
+                function: caller,
+                location: None,
+                message,
+            });
+        };
+        let mut frame = Frame {
+            function,
+            registers: vec![None; function.reg_types.len()],
+        };
+        if args.len() != function.params.len() {
+            return Err(frame.trap(format!(
+                "expected {} argument(s), got {}",
+                function.params.len(),
+                args.len()
+            )));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(frame.trap("call stack overflow"));
+        }
+        for (&(reg, typ), arg) in function.params.iter().zip(args) {
+            frame.write(reg, arg.convert(typ));
+        }
+
+        self.depth += 1;
+        let result = self.run(&mut frame);
+        self.depth -= 1;
+        result
+    }
+
+    /// Runs the body of the function in the frame until it returns
+    fn run(&mut self, frame: &mut Frame<'a>) -> Result<Option<Value>, Trap> {
+        let function = frame.function;
+        let mut current = function.entry;
+        let mut previous: Option<BlockId> = None;
+        loop {
+            let block = function.block(current);
+            if let Some(previous) = previous {
+                Self::enter_phis(frame, block, previous)?;
+            }
+
+            self.step(frame)?;
+            for instruction in &block.instructions {
+                self.step(frame)?;
+                let value = self
+                    .instruction(frame, instruction)
+                    .map_err(|trap| frame.locate(trap, instruction.span))?;
+                if let Some(value) = value {
+                    frame.write(instruction.dest, value);
+                }
+            }
+
+            let (target, args) = match &block.terminator {
+                Terminator::Br { target, args } => (*target, args),
+                Terminator::BrIf {
+                    cond,
+                    then_bb,
+                    then_args,
+                    else_bb,
+                    else_args,
+                    ..
+                } => {
+                    if frame.read(cond)?.as_bool() {
+                        (*then_bb, then_args)
+                    } else {
+                        (*else_bb, else_args)
+                    }
+                }
+                Terminator::Switch {
+                    value,
+                    cases,
+                    default,
+                    default_args,
+                } => {
+                    let value = frame.read(value)?;
+                    let mut taken = (*default, default_args);
+                    for case in cases {
+                        if compare(Opcode::Eq, value, frame.read(&case.value)?) {
+                            taken = (case.target, &case.args);
+                            break;
+                        }
+                    }
+                    taken
+                }
+                Terminator::Ret { value } => {
+                    return match value {
+                        Some(value) => Ok(Some(frame.read(value)?.convert(function.return_type))),
+                        None => Ok(None),
+                    };
+                }
+                Terminator::Trap { reason } => {
+                    let trap = frame.trap(reason.to_string());
+                    return Err(frame.locate(trap, block.terminator_span));
+                }
+                Terminator::Unreachable => {
+                    let trap = frame.trap("reached unreachable code");
+                    return Err(frame.locate(trap, block.terminator_span));
+                }
+            };
+
+            // Block arguments are all read before any parameter is written
+            let values = args
+                .iter()
+                .map(|arg| frame.forward(arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (&(reg, typ), value) in function.block(target).params.iter().zip(values) {
+                frame.write_forwarded(reg, value, typ);
+            }
+            previous = Some(current);
+            current = target;
+        }
+    }
+
+    /// Counts one step of execution, trapping once the budget is spent
+    fn step(&mut self, frame: &Frame<'a>) -> Result<(), Trap> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(frame.trap(format!("gave up after {} steps", MAX_STEPS)));
+        }
+        Ok(())
+    }
+
+    /// Gives every phi of the block the value flowing in from `previous`.
+    /// All incoming values are read before any phi is written.
+    fn enter_phis(
+        frame: &mut Frame<'a>,
+        block: &BasicBlock,
+        previous: BlockId,
+    ) -> Result<(), Trap> {
+        let mut values = Vec::with_capacity(block.phi_nodes.len());
+        for phi in &block.phi_nodes {
+            let incoming = phi.incoming(previous).ok_or_else(|| {
+                frame.trap(format!(
+                    "phi into r{} has no value for block{}",
+                    phi.dest,
+                    previous.index()
+                ))
+            })?;
+            values.push((phi.dest, frame.forward(incoming)?, phi.typ));
+        }
+        for (reg, value, typ) in values {
+            frame.write_forwarded(reg, value, typ);
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        frame: &Frame<'a>,
+        instruction: &Instruction,
+    ) -> Result<Option<Value>, Trap> {
+        let typ = instruction.typ;
+        let arg = |index: usize| match instruction.args.get(index) {
+            Some(operand) => frame.read(operand),
+            None => Err(frame.trap(format!(
+                "{:?} into r{} is missing operand {}",
+                instruction.op, instruction.dest, index
+            ))),
+        };
+
+        let value = match instruction.op {
+            Opcode::Copy | Opcode::Convert => arg(0)?.convert(typ),
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod | Opcode::Pow => {
+                let (lhs, rhs) = (arg(0)?.convert(typ), arg(1)?.convert(typ));
+                arithmetic(instruction.op, lhs, rhs)
+                    .map_err(|message| frame.trap(message))?
+                    .convert(typ)
+            }
+            Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                Value::Bool(compare(instruction.op, arg(0)?, arg(1)?))
+            }
+            Opcode::Select => {
+                let chosen = if arg(0)?.as_bool() { arg(1)? } else { arg(2)? };
+                chosen.convert(typ)
+            }
+            Opcode::Call => {
+                let Some(Operand::Label(callee)) = instruction.args.first() else {
+                    return Err(
+                        frame.trap(format!("call into r{} has no callee", instruction.dest))
+                    );
+                };
+                let args = instruction.args[1..]
+                    .iter()
+                    .map(|arg| frame.read(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match self.execute(*callee, &args, frame.function.name)? {
+                    Some(value) if typ != MirType::Void => value.convert(typ),
+                    _ => return Ok(None),
+                }
+            }
+            Opcode::Load => {
+                let Some(Operand::Global(global)) = instruction.args.first() else {
+                    return Err(frame.trap(format!("load into r{} has no global", instruction.dest)));
+                };
+                self.globals
+                    .get(global)
+                    .copied()
+                    .ok_or_else(|| frame.trap(format!("load of unknown global ${}", global)))?
+                    .convert(typ)
+            }
+            Opcode::Store => {
+                let Some(Operand::Global(global)) = instruction.args.first() else {
+                    return Err(frame.trap(format!("store from r{} has no global", instruction.dest)));
+                };
+                let declared = self.declared_globals.iter().find(|declared| declared.name == *global);
+                let Some(declared) = declared else {
+                    return Err(frame.trap(format!("store to unknown global ${}", global)));
+                };
+                self.globals.insert(*global, arg(1)?.convert(declared.typ));
+                return Ok(None);
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+/// Applies an arithmetic opcode to two values already converted to the
+/// instruction's type
+fn arithmetic(op: Opcode, lhs: Value, rhs: Value) -> Result<Value, String> {
+    match (lhs, rhs) {
+        // Powers are computed in floats, whatever the type
+        (Value::Int(a), Value::Int(b)) if op != Opcode::Pow => {
+            let result = match op {
+                Opcode::Add => a.checked_add(b),
+                Opcode::Sub => a.checked_sub(b),
+                Opcode::Mul => a.checked_mul(b),
+                Opcode::Div | Opcode::Mod if b == 0 => {
+                    return Err(TrapReason::DivisionByZero.to_string());
+                }
+                Opcode::Div => a.checked_div(b),
+                Opcode::Mod => a.checked_rem(b),
+                _ => unreachable!("not an arithmetic opcode: {:?}", op),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| format!("integer overflow in {:?}", op))
+        }
+        _ => {
+            let (a, b) = (lhs.as_f64(), rhs.as_f64());
+            Ok(Value::F64(match op {
+                Opcode::Add => a + b,
+                Opcode::Sub => a - b,
+                Opcode::Mul => a * b,
+                Opcode::Div => a / b,
+                Opcode::Mod => a % b,
+                Opcode::Pow => a.powf(b),
+                _ => unreachable!("not an arithmetic opcode: {:?}", op),
+            }))
+        }
+    }
+}
+
+/// Evaluates a comparison. Booleans compare as booleans, everything else
+/// as integers if both sides are integers and as floats otherwise.
+fn compare(op: Opcode, lhs: Value, rhs: Value) -> bool {
+    let ordering = match (lhs, rhs) {
+        (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(&b),
+        (Value::Int(a), Value::Int(b)) => a.partial_cmp(&b),
+        _ => lhs.as_f64().partial_cmp(&rhs.as_f64()),
+    };
+    // An unordered comparison involves NaN, which is only unequal to anything
+    let Some(ordering) = ordering else {
+        return op == Opcode::Ne;
+    };
+    match op {
+        Opcode::Eq => ordering.is_eq(),
+        Opcode::Ne => ordering.is_ne(),
+        Opcode::Lt => ordering.is_lt(),
+        Opcode::Le => ordering.is_le(),
+        Opcode::Gt => ordering.is_gt(),
+        Opcode::Ge => ordering.is_ge(),
+        _ => unreachable!("not a comparison opcode: {:?}", op),
+    }
+}
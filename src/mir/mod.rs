@@ -1,14 +1,32 @@
+pub mod analysis;
+pub mod args;
+pub mod block_params;
+pub mod builder;
+#[cfg(feature = "saturation")]
+pub mod egraph;
+pub mod history;
+pub mod interpreter;
 pub mod passes;
 pub mod visitor;
 pub mod cfg;
 
-#[derive(Debug)]
+pub use args::Args;
+
+use crate::coverage::CoverageCounter;
+use crate::intern::Symbol;
+use crate::span::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcode {
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    /// `[base, exponent]`: the float `base` raised to `exponent`, as C's `pow`
+    Pow,
     Copy,
 
     Call,
@@ -20,10 +38,20 @@ pub enum Opcode {
     Gt,
     Ge,
 
-    Phi
+    /// `[cond, a, b]`: `a` if the I1 `cond` is true, otherwise `b`
+    Select,
+
+    /// `[value]`: the float `value` rounded to the float type of the result
+    Convert,
+
+    /// `[global]`: the current value of a global
+    Load,
+    /// `[global, value]`: overwrite a global with `value`. Produces nothing,
+    /// so the destination is a `Void` register.
+    Store,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MirType {
     F8,
     F16,
@@ -39,21 +67,151 @@ pub enum MirType {
     Void,
 }
 
+impl MirType {
+    pub fn is_integer(self) -> bool {
+        matches!(self, MirType::I8 | MirType::I16 | MirType::I32 | MirType::I64)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64)
+    }
+}
+
 pub type Reg = usize;
 
+/// Interned name of a function
+pub type FuncId = Symbol;
+
+/// Interned name referenced by a [`Operand::Label`]. Labels and function
+/// names share one namespace, so a call target compares directly against
+/// [`MirFunction::name`].
+pub type LabelId = Symbol;
+
+/// Interned name of a global, referenced by a [`Operand::Global`]
+pub type GlobalId = Symbol;
+
+/// Name of the synthetic function that gives every global its initial
+/// value. It cannot clash with a source function, since those names never
+/// contain `@`.
+pub const INIT_FUNCTION: &str = "@init";
+
 /// Operand can be either a register or an immediate value
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Immediates are typed: an instruction or terminator only takes the
+/// immediates of the types of its operands, which the verifier checks.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operand {
     Reg(Reg),
+    /// An integer of any integer type
     ImmI64(i64),
+    /// An `F64`, or an `F16` or `F8`, which are computed at `F64` precision
     ImmF64(f64),
+    ImmF32(f32),
     ImmBool(bool),
-    Label(String),
-    Pair(BlockId, Box<Operand>) // Used for Phi nodes
+    Label(LabelId),
+    /// Storage of a global; only read and written by [`Opcode::Load`] and
+    /// [`Opcode::Store`]
+    Global(GlobalId),
+}
+
+impl Operand {
+    /// The register this operand reads, if it is not an immediate
+    pub fn as_reg(&self) -> Option<Reg> {
+        match self {
+            Operand::Reg(reg) => Some(*reg),
+            _ => None,
+        }
+    }
+
+    /// The immediate of the float type holding the value, rounded to it
+    pub fn float(value: f64, typ: MirType) -> Operand {
+        match typ {
+            MirType::F32 => Operand::ImmF32(value as f32),
+            _ => Operand::ImmF64(value),
+        }
+    }
+
+    /// The immediate zero, or false, of the type
+    pub fn zero(typ: MirType) -> Operand {
+        match typ {
+            MirType::I1 => Operand::ImmBool(false),
+            typ if typ.is_integer() => Operand::ImmI64(0),
+            typ => Operand::float(0.0, typ),
+        }
+    }
+
+    /// The value of a float immediate
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Operand::ImmF64(value) => Some(*value),
+            Operand::ImmF32(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    /// Whether the operand is an immediate that can be used where a value
+    /// of the type is expected. `None` for operands that are not
+    /// immediates.
+    pub fn immediate_fits(&self, typ: MirType) -> Option<bool> {
+        Some(match self {
+            Operand::ImmI64(_) => typ.is_integer(),
+            Operand::ImmF64(_) => matches!(typ, MirType::F8 | MirType::F16 | MirType::F64),
+            Operand::ImmF32(_) => typ == MirType::F32,
+            Operand::ImmBool(_) => typ == MirType::I1,
+            Operand::Reg(_) | Operand::Label(_) | Operand::Global(_) => return None,
+        })
+    }
+
+    /// Whether both operands are the same register or the same immediate
+    /// bit for bit. Unlike `==`, tells `0.0` from `-0.0`, and a NaN
+    /// immediate is identical to itself.
+    pub fn is_identical(&self, other: &Operand) -> bool {
+        OperandKey::from(*self) == OperandKey::from(*other)
+    }
+}
+
+/// An operand, compared bit for bit so float immediates can be hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OperandKey {
+    Reg(Reg),
+    ImmI64(i64),
+    ImmF64(u64),
+    ImmF32(u32),
+    ImmBool(bool),
+    Label(LabelId),
+    Global(GlobalId),
+}
+
+impl From<Operand> for OperandKey {
+    fn from(operand: Operand) -> Self {
+        match operand {
+            Operand::Reg(reg) => OperandKey::Reg(reg),
+            Operand::ImmI64(value) => OperandKey::ImmI64(value),
+            Operand::ImmF64(value) => OperandKey::ImmF64(value.to_bits()),
+            Operand::ImmF32(value) => OperandKey::ImmF32(value.to_bits()),
+            Operand::ImmBool(value) => OperandKey::ImmBool(value),
+            Operand::Label(label) => OperandKey::Label(label),
+            Operand::Global(global) => OperandKey::Global(global),
+        }
+    }
+}
+
+impl From<OperandKey> for Operand {
+    fn from(key: OperandKey) -> Self {
+        match key {
+            OperandKey::Reg(reg) => Operand::Reg(reg),
+            OperandKey::ImmI64(value) => Operand::ImmI64(value),
+            OperandKey::ImmF64(bits) => Operand::ImmF64(f64::from_bits(bits)),
+            OperandKey::ImmF32(bits) => Operand::ImmF32(f32::from_bits(bits)),
+            OperandKey::ImmBool(value) => Operand::ImmBool(value),
+            OperandKey::Label(label) => Operand::Label(label),
+            OperandKey::Global(global) => Operand::Global(global),
+        }
+    }
 }
 
 /// Type-safe block identifier (index into BlockArena)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct BlockId(usize);
 
 impl BlockId {
@@ -66,39 +224,289 @@ impl BlockId {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Instruction {
     pub dest: Reg,
     pub op: Opcode,
     pub typ: MirType,
-    pub args: Vec<Operand>,
+    pub args: Args,
+    /// The source construct the instruction was lowered from, if any
+    pub span: Option<Span>,
+}
+
+/// SSA phi node: selects the incoming value of the predecessor control came from
+#[derive(Debug, Clone)]
+pub struct PhiNode {
+    pub dest: Reg,
+    pub typ: MirType,
+    pub incomings: Vec<(BlockId, Operand)>,
 }
 
-#[derive(Debug)]
+impl PhiNode {
+    /// Get the incoming value for a predecessor block
+    pub fn incoming(&self, pred: BlockId) -> Option<&Operand> {
+        self.incomings
+            .iter()
+            .find(|(block, _)| *block == pred)
+            .map(|(_, operand)| operand)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct BasicBlock {
     pub instructions: Vec<Instruction>,
     pub terminator: Terminator,
-    pub phi_nodes: Vec<Instruction>
+    /// Phi nodes evaluated on entry to the block, before any instruction
+    pub phi_nodes: Vec<PhiNode>,
+    /// Block parameters, the alternative to phi nodes: every branch to this
+    /// block passes one argument per parameter
+    pub params: Vec<(Reg, MirType)>,
+    /// The source construct the terminator was lowered from, if any
+    pub terminator_span: Option<Span>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Terminator {
     Br {
         target: BlockId,
+        args: Vec<Operand>,
     },
     BrIf {
         cond: Operand,
         then_bb: BlockId,
+        then_args: Vec<Operand>,
         else_bb: BlockId,
+        else_args: Vec<Operand>,
+        /// How often each edge is expected to be taken, if known
+        weights: Option<BranchWeights>,
+    },
+    /// Multi-way branch on the value of an operand. Case values are immediates
+    /// of the operand's type; `default` is taken when no case matches.
+    Switch {
+        value: Operand,
+        cases: Vec<SwitchCase>,
+        default: BlockId,
+        default_args: Vec<Operand>,
     },
     Ret {
         value: Option<Operand>,
     },
+    /// Stops the program, e.g. when an assertion fails
+    Trap {
+        reason: TrapReason,
+    },
     Unreachable,
 }
 
+/// Relative frequencies of the two edges of a [`Terminator::BrIf`]. Only
+/// their ratio matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchWeights {
+    pub then_weight: u32,
+    pub else_weight: u32,
+}
+
+impl BranchWeights {
+    /// The then edge is almost always taken
+    pub const LIKELY: BranchWeights = BranchWeights {
+        then_weight: 2000,
+        else_weight: 1,
+    };
+
+    /// The then edge is almost never taken
+    pub const UNLIKELY: BranchWeights = BranchWeights {
+        then_weight: 1,
+        else_weight: 2000,
+    };
+
+    /// Probability of taking the then edge
+    pub fn then_probability(self) -> f64 {
+        let total = self.then_weight as f64 + self.else_weight as f64;
+        if total == 0.0 {
+            0.5
+        } else {
+            self.then_weight as f64 / total
+        }
+    }
+}
+
+/// Why a [`Terminator::Trap`] stops the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrapReason {
+    /// An `assert` whose condition was false, with its message if it had one
+    AssertionFailed(Option<String>),
+    /// Integer division or remainder by zero
+    DivisionByZero,
+    /// A match arm that the typechecker proved can never be taken
+    UnreachableArm,
+    /// A float operation whose result is NaN, checked with `--checks`
+    NotANumber,
+    /// A read of a variable that was never written, checked with `--checks`
+    UninitializedRead,
+}
+
+impl fmt::Display for TrapReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapReason::AssertionFailed(Some(message)) => {
+                write!(f, "assertion failed: {}", message)
+            }
+            TrapReason::AssertionFailed(None) => write!(f, "assertion failed"),
+            TrapReason::DivisionByZero => write!(f, "division by zero"),
+            TrapReason::UnreachableArm => write!(f, "no match arm was taken"),
+            TrapReason::NotANumber => write!(f, "float operation produced NaN"),
+            TrapReason::UninitializedRead => write!(f, "variable read before it was written"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchCase {
+    pub value: Operand,
+    pub target: BlockId,
+    pub args: Vec<Operand>,
+}
+
+impl Terminator {
+    /// Unconditional branch without block arguments
+    pub fn br(target: BlockId) -> Self {
+        Terminator::Br {
+            target,
+            args: Vec::new(),
+        }
+    }
+
+    /// Conditional branch without block arguments
+    pub fn br_if(cond: Operand, then_bb: BlockId, else_bb: BlockId) -> Self {
+        Terminator::BrIf {
+            cond,
+            then_bb,
+            then_args: Vec::new(),
+            else_bb,
+            else_args: Vec::new(),
+            weights: None,
+        }
+    }
+
+    /// Conditional branch without block arguments, with weights if the
+    /// source gave any
+    pub fn br_if_weighted(cond: Operand, then_bb: BlockId, else_bb: BlockId, weights: Option<BranchWeights>) -> Self {
+        Terminator::BrIf {
+            cond,
+            then_bb,
+            then_args: Vec::new(),
+            else_bb,
+            else_args: Vec::new(),
+            weights,
+        }
+    }
+
+    /// Switch without block arguments
+    pub fn switch(value: Operand, cases: Vec<(Operand, BlockId)>, default: BlockId) -> Self {
+        Terminator::Switch {
+            value,
+            cases: cases
+                .into_iter()
+                .map(|(value, target)| SwitchCase {
+                    value,
+                    target,
+                    args: Vec::new(),
+                })
+                .collect(),
+            default,
+            default_args: Vec::new(),
+        }
+    }
+
+    /// Outgoing edges with the block arguments passed along each of them
+    pub fn edges(&self) -> Vec<(BlockId, &Vec<Operand>)> {
+        match self {
+            Terminator::Br { target, args } => vec![(*target, args)],
+            Terminator::BrIf {
+                then_bb,
+                then_args,
+                else_bb,
+                else_args,
+                ..
+            } => vec![(*then_bb, then_args), (*else_bb, else_args)],
+            Terminator::Switch {
+                cases,
+                default,
+                default_args,
+                ..
+            } => cases
+                .iter()
+                .map(|case| (case.target, &case.args))
+                .chain(std::iter::once((*default, default_args)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mutable access to the outgoing edges and their block arguments
+    pub fn edges_mut(&mut self) -> Vec<(BlockId, &mut Vec<Operand>)> {
+        match self {
+            Terminator::Br { target, args } => vec![(*target, args)],
+            Terminator::BrIf {
+                then_bb,
+                then_args,
+                else_bb,
+                else_args,
+                ..
+            } => vec![(*then_bb, then_args), (*else_bb, else_args)],
+            Terminator::Switch {
+                cases,
+                default,
+                default_args,
+                ..
+            } => cases
+                .iter_mut()
+                .map(|case| (case.target, &mut case.args))
+                .chain(std::iter::once((*default, default_args)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mutable access to the branch targets, in the same order as [`Terminator::edges`]
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Br { target, .. } => vec![target],
+            Terminator::BrIf {
+                then_bb, else_bb, ..
+            } => vec![then_bb, else_bb],
+            Terminator::Switch { cases, default, .. } => cases
+                .iter_mut()
+                .map(|case| &mut case.target)
+                .chain(std::iter::once(default))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Values the terminator itself reads, not counting block arguments
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::BrIf { cond, .. } => vec![cond],
+            Terminator::Switch { value, .. } => vec![value],
+            Terminator::Ret { value: Some(value) } => vec![value],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mutable access to the values the terminator itself reads
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::BrIf { cond, .. } => vec![cond],
+            Terminator::Switch { value, .. } => vec![value],
+            Terminator::Ret { value: Some(value) } => vec![value],
+            _ => Vec::new(),
+        }
+    }
+}
+
 /// Arena for allocating basic blocks
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BlockArena {
     blocks: Vec<BasicBlock>,
 }
@@ -144,18 +552,64 @@ impl BlockArena {
     }
 }
 
-#[derive(Debug)]
+impl Default for BlockArena {
+    fn default() -> Self {
+        BlockArena::new()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct MirFunction {
-    pub name: String,
+    pub name: FuncId,
     pub params: Vec<(Reg, MirType)>,
     pub return_type: MirType,
     pub arena: BlockArena,
     pub entry: BlockId,
+    /// Type of every register, indexed by register number.
+    /// Registers are numbered per function, starting at zero.
+    pub reg_types: Vec<MirType>,
+    /// Where the function was defined, if it came from source
+    pub span: Option<Span>,
+    /// Name of the source the function was defined in, if any
+    pub source: Option<String>,
+    /// Calls to the function have no effect besides their result, so they
+    /// may be merged or removed when the result is unused
+    pub pure: bool,
+    /// Whether calls to the function should be inlined, from `@inline` and
+    /// `@noinline`
+    pub inline: InlineHint,
+    /// Whether code outside the program may call the function
+    pub linkage: Linkage,
+    /// A test entry point, from `@test`
+    pub test: bool,
+}
+
+/// Whether a function or global is visible outside the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linkage {
+    /// Only used from within the program, so it may be removed once unused
+    #[default]
+    Private,
+    /// Defined by the program and visible to code outside it, from `@export`
+    Public,
+    /// Declared by the program but defined outside it, from `extern`. Only
+    /// found in [`MirProgram::symbols`], since there is no body to lower.
+    External,
+}
+
+/// What the source asked for when deciding whether to inline a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InlineHint {
+    /// Left to the optimizer
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 impl MirFunction {
     /// Create a new function with an entry block
-    pub fn new(name: String, params: Vec<(Reg, MirType)>, return_type: MirType) -> Self {
+    pub fn new(name: FuncId, params: Vec<(Reg, MirType)>, return_type: MirType) -> Self {
         let mut arena = BlockArena::new();
 
         // Create entry block
@@ -163,15 +617,51 @@ impl MirFunction {
             instructions: Vec::new(),
             terminator: Terminator::Unreachable,
             phi_nodes: Vec::new(),
+            params: Vec::new(),
+            terminator_span: None,
         });
 
-        MirFunction {
+        let mut function = MirFunction {
             name,
             params,
             return_type,
             arena,
             entry,
+            reg_types: Vec::new(),
+            span: None,
+            source: None,
+            pure: false,
+            inline: InlineHint::Auto,
+            linkage: Linkage::Private,
+            test: false,
+        };
+        for (reg, typ) in function.params.clone() {
+            function.set_reg_type(reg, typ);
         }
+        function
+    }
+
+    /// Allocate a fresh register of the given type in this function
+    pub fn new_reg(&mut self, typ: MirType) -> Reg {
+        let reg = self.reg_types.len();
+        self.reg_types.push(typ);
+        reg
+    }
+
+    /// Get the type of a register, if it holds a value
+    pub fn reg_type(&self, reg: Reg) -> Option<MirType> {
+        match self.reg_types.get(reg) {
+            Some(MirType::Void) | None => None,
+            Some(typ) => Some(*typ),
+        }
+    }
+
+    /// Record the type of a register, growing the table as needed
+    pub fn set_reg_type(&mut self, reg: Reg, typ: MirType) {
+        if self.reg_types.len() <= reg {
+            self.reg_types.resize(reg + 1, MirType::Void);
+        }
+        self.reg_types[reg] = typ;
     }
 
     /// Get a reference to a block
@@ -185,8 +675,185 @@ impl MirFunction {
     }
 }
 
+/// Storage shared by every function, outliving any call
+#[derive(Debug, Clone)]
+pub struct MirGlobal {
+    pub name: GlobalId,
+    pub typ: MirType,
+    pub linkage: Linkage,
+}
+
+#[derive(Debug, Clone)]
 pub struct MirProgram {
     pub functions: Vec<MirFunction>,
+    /// Globals start out as zero and are then given their initial values
+    /// by [`INIT_FUNCTION`], if the program has any
+    pub globals: Vec<MirGlobal>,
+    /// The counters of `--coverage`, in the order they are written out
+    pub coverage: Vec<CoverageCounter>,
+    /// Float constants the backend loads from memory, filled in by
+    /// [`passes::constant_pool::MirConstantPoolPass`] before code generation
+    pub constants: ConstantPool,
+    /// Signature of every function calls may refer to
+    pub symbols: FunctionTable,
+}
+
+/// Float constants kept in memory, each value once
+///
+/// Values are compared bit for bit, so `0` and `-0`, and NaNs with
+/// different payloads, are separate entries.
+#[derive(Debug, Clone, Default)]
+pub struct ConstantPool {
+    values: Vec<u64>,
+    indices: HashMap<u64, usize>,
+}
+
+impl ConstantPool {
+    pub fn new() -> Self {
+        ConstantPool::default()
+    }
+
+    /// Index of the value in the pool, adding it if it is new
+    pub fn intern(&mut self, value: f64) -> usize {
+        let bits = value.to_bits();
+        *self.indices.entry(bits).or_insert_with(|| {
+            self.values.push(bits);
+            self.values.len() - 1
+        })
+    }
+
+    pub fn index_of(&self, value: f64) -> Option<usize> {
+        self.indices.get(&value.to_bits()).copied()
+    }
+
+    /// The values in the order they were added
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.values.iter().map(|bits| f64::from_bits(*bits))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Type-safe function identifier (index into FunctionTable)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FunctionId(usize);
+
+impl FunctionId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// What a call needs to know about the function it calls
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSignature {
+    pub name: FuncId,
+    pub params: Vec<MirType>,
+    pub return_type: MirType,
+    pub linkage: Linkage,
+}
+
+/// The functions of a program by name
+///
+/// Lowering declares every function before lowering any body, so calls
+/// to functions defined further down resolve too. Functions removed from
+/// [`MirProgram::functions`] keep their entry.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionTable {
+    signatures: Vec<FunctionSignature>,
+    ids: HashMap<FuncId, FunctionId>,
+}
+
+impl FunctionTable {
+    pub fn new() -> Self {
+        FunctionTable::default()
+    }
+
+    /// Adds the function, or returns its id if one of that name was
+    /// already declared
+    pub fn declare(&mut self, signature: FunctionSignature) -> FunctionId {
+        *self.ids.entry(signature.name).or_insert_with(|| {
+            self.signatures.push(signature);
+            FunctionId(self.signatures.len() - 1)
+        })
+    }
+
+    pub fn id(&self, name: FuncId) -> Option<FunctionId> {
+        self.ids.get(&name).copied()
+    }
+
+    pub fn signature(&self, id: FunctionId) -> &FunctionSignature {
+        &self.signatures[id.0]
+    }
+
+    /// Signature of the named function, if it was declared
+    pub fn get(&self, name: FuncId) -> Option<&FunctionSignature> {
+        self.id(name).map(|id| self.signature(id))
+    }
+
+    /// The signatures in the order they were declared
+    pub fn iter(&self) -> impl Iterator<Item = &FunctionSignature> {
+        self.signatures.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+}
+
+impl MirProgram {
+    /// Type of the named global, if the program has it
+    pub fn global_type(&self, name: GlobalId) -> Option<MirType> {
+        self.globals
+            .iter()
+            .find(|global| global.name == name)
+            .map(|global| global.typ)
+    }
+
+    /// Memory the instructions of the program take up
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for function in &self.functions {
+            for (_, block) in function.arena.iter() {
+                for instruction in &block.instructions {
+                    usage.instructions += 1;
+                    usage.bytes += std::mem::size_of::<Instruction>() + instruction.args.heap_bytes();
+                    usage.spilled += instruction.args.is_spilled() as usize;
+                }
+            }
+        }
+        usage
+    }
+}
+
+/// Statistics of [`MirProgram::memory_usage`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub instructions: usize,
+    /// Instructions whose operands did not fit in [`Args`] and are on the heap
+    pub spilled: usize,
+    /// Bytes of the instructions and of their operands on the heap
+    pub bytes: usize,
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MIR holds {} instruction(s) in {} bytes, {} with operands on the heap",
+            self.instructions, self.bytes, self.spilled
+        )
+    }
 }
 
 // Example usage:
@@ -207,7 +874,7 @@ pub struct MirProgram {
 // });
 //
 // // Set entry to branch to our new block
-// func.block_mut(func.entry).terminator = Terminator::Br { target: block_id };
+// func.block_mut(func.entry).terminator = Terminator::br(block_id);
 //
 // // Access blocks
 // let block = func.block(block_id);
@@ -0,0 +1,144 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::liveness::Liveness;
+use crate::mir::builder::Builder;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{
+    BranchWeights, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg, Terminator, TrapReason,
+};
+use std::collections::{BTreeMap, HashSet};
+
+/// Instruments the program with the runtime checks of `--checks`
+///
+/// - every float `Add`, `Sub`, `Mul`, `Div`, `Mod` and `Pow` traps if its result
+///   is NaN, so a NaN is caught where it is made rather than where it
+///   ends up
+/// - a register that may be read before it is written, such as a variable
+///   declared without a value and only assigned on some paths, gets a flag
+///   that every write sets and every read checks
+///
+/// The checks trap like any other [`TrapReason`], so they work the same in
+/// the interpreter and in native code. Globals need no flags, since an
+/// initializer can only read the globals declared before it, and the
+/// language has no arrays yet, so there are no bounds to check.
+///
+/// Expects MIR before SSA construction, so flags may be written more than
+/// once and new blocks need no phis.
+pub struct MirChecksPass {
+    diagnostics: DiagnosticCollector,
+    inserted: usize,
+}
+
+impl MirChecksPass {
+    pub fn new() -> Self {
+        MirChecksPass {
+            diagnostics: DiagnosticCollector::new(),
+            inserted: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Inserted {} runtime check(s)", self.inserted));
+    }
+
+    /// Checks the flag of every register read that may not be written yet
+    fn check_reads<'o>(
+        &mut self,
+        b: &mut Builder,
+        operands: impl Iterator<Item = &'o Operand>,
+        flags: &BTreeMap<Reg, Reg>,
+    ) {
+        let mut checked = HashSet::new();
+        for operand in operands {
+            if let Some(reg) = operand.as_reg()
+                && let Some(&flag) = flags.get(&reg)
+                && checked.insert(reg)
+            {
+                self.guard(b, Operand::Reg(flag), TrapReason::UninitializedRead);
+            }
+        }
+    }
+
+    /// Ends the current block with a branch that continues in a new block
+    /// if `ok` holds and traps otherwise
+    fn guard(&mut self, b: &mut Builder, ok: Operand, reason: TrapReason) {
+        let pass = b.create_block();
+        let fail = b.create_block();
+        b.br_if_weighted(ok, pass, fail, Some(BranchWeights::LIKELY));
+        b.position_at_end(fail);
+        b.trap(reason);
+        b.position_at_end(pass);
+        self.inserted += 1;
+    }
+}
+
+impl Default for MirChecksPass {
+    fn default() -> Self {
+        MirChecksPass::new()
+    }
+}
+
+impl MirVisitor for MirChecksPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) {
+        // Registers live on entry are read before they are written on some path
+        let params: HashSet<Reg> = function.params.iter().map(|(reg, _)| *reg).collect();
+        let live = Liveness::compute(function);
+        let mut flags = BTreeMap::new();
+        for &reg in &live.block_entry[&function.entry] {
+            if !params.contains(&reg) {
+                flags.insert(reg, function.new_reg(MirType::I1));
+            }
+        }
+
+        let entry = function.entry;
+        let blocks: Vec<_> = function.arena.iter().map(|(block_id, _)| block_id).collect();
+        for block_id in blocks {
+            let block = function.block_mut(block_id);
+            let instructions = std::mem::take(&mut block.instructions);
+            let terminator = std::mem::replace(&mut block.terminator, Terminator::Unreachable);
+            let terminator_span = block.terminator_span;
+
+            let mut b = Builder::new(function, block_id);
+            if block_id == entry {
+                for &flag in flags.values() {
+                    b.copy_into(flag, MirType::I1, Operand::ImmBool(false));
+                }
+            }
+            for instruction in instructions {
+                b = b.with_span(instruction.span);
+                self.check_reads(&mut b, instruction.args.iter(), &flags);
+                let Instruction { dest, op, typ, .. } = instruction;
+                b.push(instruction);
+
+                if let Some(&flag) = flags.get(&dest) {
+                    b.copy_into(flag, MirType::I1, Operand::ImmBool(true));
+                }
+                if is_float_arithmetic(op, typ) {
+                    // Only NaN compares unequal to itself
+                    let number = b.eq(Operand::Reg(dest), Operand::Reg(dest));
+                    self.guard(&mut b, Operand::Reg(number), TrapReason::NotANumber);
+                }
+            }
+
+            b = b.with_span(terminator_span);
+            self.check_reads(&mut b, terminator.operands().into_iter(), &flags);
+            b.terminate(terminator);
+        }
+    }
+}
+
+fn is_float_arithmetic(op: Opcode, typ: MirType) -> bool {
+    matches!(op, Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod | Opcode::Pow)
+        && matches!(typ, MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64)
+}
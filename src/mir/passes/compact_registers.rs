@@ -0,0 +1,105 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{
+    BasicBlock, BlockId, Instruction, MirFunction, MirProgram, MirType, Operand, PhiNode, Reg,
+};
+use std::collections::HashMap;
+
+/// Renumbers the registers of every function so they are dense again
+///
+/// Lowering and SSA construction give every value a fresh register and
+/// never hand one back, and the registers of instructions removed by later
+/// passes are left behind in the type table. This pass numbers the
+/// registers still referenced from zero, parameters first and then in the
+/// order they appear, and shrinks the type table to match. Works on any
+/// form of MIR.
+pub struct MirCompactRegistersPass {
+    diagnostics: DiagnosticCollector,
+    /// New number of every register seen so far in the current function
+    renumbered: HashMap<Reg, Reg>,
+    before: usize,
+    after: usize,
+}
+
+impl MirCompactRegistersPass {
+    pub fn new() -> Self {
+        MirCompactRegistersPass {
+            diagnostics: DiagnosticCollector::new(),
+            renumbered: HashMap::new(),
+            before: 0,
+            after: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics.info(format!(
+            "Compacted {} register(s) into {}",
+            self.before, self.after
+        ));
+    }
+
+    /// The new number of a register, handing out the next free one the
+    /// first time it is seen
+    fn renumber(&mut self, reg: &mut Reg) {
+        let next = self.renumbered.len();
+        *reg = *self.renumbered.entry(*reg).or_insert(next);
+    }
+}
+
+impl Default for MirCompactRegistersPass {
+    fn default() -> Self {
+        MirCompactRegistersPass::new()
+    }
+}
+
+impl MirVisitor for MirCompactRegistersPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        self.renumbered.clear();
+        for (reg, _) in &mut function.params {
+            self.renumber(reg);
+        }
+        self.walk_function(function);
+
+        let mut reg_types = vec![MirType::Void; self.renumbered.len()];
+        for (&old, &new) in &self.renumbered {
+            reg_types[new] = function.reg_types.get(old).copied().unwrap_or(MirType::Void);
+        }
+        self.before += function.reg_types.len();
+        self.after += reg_types.len();
+        function.reg_types = reg_types;
+    }
+
+    fn visit_basicblock(&mut self, _block_id: BlockId, block: &mut BasicBlock) -> Self::Output {
+        for (reg, _) in &mut block.params {
+            self.renumber(reg);
+        }
+        self.walk_basicblock(block);
+    }
+
+    fn visit_phi(&mut self, phi: &mut PhiNode) -> Self::Output {
+        self.renumber(&mut phi.dest);
+        self.walk_phi(phi);
+    }
+
+    fn visit_instruction(&mut self, instruction: &mut Instruction) -> Self::Output {
+        self.walk_instruction(instruction);
+        self.renumber(&mut instruction.dest);
+    }
+
+    fn visit_operand(&mut self, operand: &mut Operand) -> Self::Output {
+        if let Operand::Reg(reg) = operand {
+            self.renumber(reg);
+        }
+    }
+}
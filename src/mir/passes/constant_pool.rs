@@ -0,0 +1,57 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::MirProgram;
+
+/// Collects the float immediates of the program into
+/// [`MirProgram::constants`]
+///
+/// x86-64 has no instructions that take a float immediate, so the backend
+/// loads each one from the pool, where a value used many times is stored
+/// once. Runs right before instruction selection, after every pass that
+/// may add or fold constants.
+pub struct MirConstantPoolPass {
+    diagnostics: DiagnosticCollector,
+}
+
+impl MirConstantPoolPass {
+    pub fn new() -> Self {
+        MirConstantPoolPass {
+            diagnostics: DiagnosticCollector::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        let mut uses = 0;
+        for function in &program.functions {
+            for (_, block) in function.arena.iter() {
+                let operands = block
+                    .phi_nodes
+                    .iter()
+                    .flat_map(|phi| phi.incomings.iter().map(|(_, operand)| operand))
+                    .chain(block.instructions.iter().flat_map(|instruction| &instruction.args))
+                    .chain(block.terminator.operands())
+                    .chain(block.terminator.edges().into_iter().flat_map(|(_, args)| args));
+                for operand in operands {
+                    if let Some(value) = operand.as_float() {
+                        program.constants.intern(value);
+                        uses += 1;
+                    }
+                }
+            }
+        }
+        self.diagnostics.info(format!(
+            "Pooled {} float constant(s) used {} time(s)",
+            program.constants.len(),
+            uses
+        ));
+    }
+}
+
+impl Default for MirConstantPoolPass {
+    fn default() -> Self {
+        MirConstantPoolPass::new()
+    }
+}
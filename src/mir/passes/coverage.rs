@@ -0,0 +1,88 @@
+use crate::coverage::CoverageCounter;
+use crate::diagnostics::DiagnosticCollector;
+use crate::intern::Symbol;
+use crate::mir::builder::Builder;
+use crate::mir::{Linkage, MirGlobal, MirProgram, MirType, Operand};
+
+/// Instruments the program with the counters of `--coverage`
+///
+/// Every block with an instruction or terminator lowered from source gets
+/// an `I64` global, named so it cannot clash with the globals of the
+/// source, that is loaded, incremented and stored back where the block
+/// starts. The counters are recorded in [`MirProgram::coverage`], with the
+/// rows the block covers. Blocks added later, such as the guards of
+/// `--checks`, are not counted.
+///
+/// Expects MIR before SSA construction, and before any pass that splits
+/// blocks, so a block is still one stretch of straight-line source.
+pub struct MirCoveragePass {
+    diagnostics: DiagnosticCollector,
+}
+
+impl MirCoveragePass {
+    pub fn new() -> Self {
+        MirCoveragePass {
+            diagnostics: DiagnosticCollector::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        let mut counters = Vec::new();
+        for function in &mut program.functions {
+            let Some(source) = function.source.clone() else {
+                continue;
+            };
+            let blocks: Vec<_> = function.arena.iter().map(|(block_id, _)| block_id).collect();
+            for block_id in blocks {
+                let block = function.block(block_id);
+                let mut rows: Vec<usize> = block
+                    .instructions
+                    .iter()
+                    .filter_map(|instruction| instruction.span)
+                    .chain(block.terminator_span)
+                    .map(|span| span.start_row)
+                    .collect();
+                if rows.is_empty() {
+                    continue;
+                }
+                rows.sort_unstable();
+                rows.dedup();
+
+                let global = Symbol::intern(&format!("@coverage.{}", counters.len()));
+                let instructions = std::mem::take(&mut function.block_mut(block_id).instructions);
+                let mut b = Builder::new(function, block_id);
+                let count = b.load(MirType::I64, global);
+                let count = b.add(MirType::I64, Operand::Reg(count), Operand::ImmI64(1));
+                b.store(global, Operand::Reg(count));
+                function.block_mut(block_id).instructions.extend(instructions);
+
+                counters.push(CoverageCounter {
+                    global,
+                    source: source.clone(),
+                    rows,
+                });
+            }
+        }
+
+        program.globals.extend(counters.iter().map(|counter| MirGlobal {
+            name: counter.global,
+            typ: MirType::I64,
+            linkage: Linkage::Private,
+        }));
+        self.diagnostics.info(format!(
+            "Instrumented {} block(s) with coverage counters",
+            counters.len()
+        ));
+        program.coverage = counters;
+    }
+}
+
+impl Default for MirCoveragePass {
+    fn default() -> Self {
+        MirCoveragePass::new()
+    }
+}
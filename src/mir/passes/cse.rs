@@ -0,0 +1,179 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::call_graph::CallGraph;
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::passes::dead_code::pure_functions;
+use crate::mir::passes::ssa::MirSSAPass;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{
+    BlockId, FuncId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, OperandKey, Reg,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Replaces recomputations of a value with copies of the first result
+///
+/// Blocks are visited along the dominator tree, and an instruction that
+/// computes the same operation on the same operands as one in a dominating
+/// block, or earlier in its own block, becomes a copy of that instruction's
+/// result. Calls take part only when the callee is pure and reads no
+/// globals, which a store in between could have changed. Operands of
+/// commutative operations are put in a canonical order first, so `a + b`
+/// and `b + a` are recognized as the same value. Expects SSA form.
+pub struct MirCsePass {
+    diagnostics: DiagnosticCollector,
+    pure_functions: HashSet<FuncId>,
+    eliminated: usize,
+}
+
+/// Everything that determines the value an instruction computes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Expression {
+    op: Opcode,
+    typ: MirType,
+    args: Vec<OperandKey>,
+}
+
+impl MirCsePass {
+    pub fn new() -> Self {
+        MirCsePass {
+            diagnostics: DiagnosticCollector::new(),
+            pure_functions: HashSet::new(),
+            eliminated: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.pure_functions = pure_functions(program);
+        let call_graph = CallGraph::new(program);
+        let reads_globals: HashSet<FuncId> = program
+            .functions
+            .iter()
+            .filter(|function| {
+                function.arena.iter().any(|(_, block)| {
+                    block
+                        .instructions
+                        .iter()
+                        .any(|instruction| instruction.op == Opcode::Load)
+                })
+            })
+            .map(|function| function.name)
+            .collect();
+        self.pure_functions.retain(|&function| {
+            !call_graph
+                .reachable_from([function])
+                .iter()
+                .any(|callee| reads_globals.contains(callee))
+        });
+        self.visit_program(program);
+        self.diagnostics.info(format!(
+            "Eliminated {} common subexpression(s)",
+            self.eliminated
+        ));
+    }
+
+    /// The value an instruction computes, or `None` if two executions of it
+    /// cannot be assumed to give the same result
+    fn expression(&self, instruction: &Instruction) -> Option<Expression> {
+        match instruction.op {
+            // Copies are already as cheap as what they would be replaced by
+            Opcode::Copy => return None,
+            // A global may be written between two loads of it
+            Opcode::Load | Opcode::Store => return None,
+            Opcode::Call => match instruction.args.first() {
+                Some(Operand::Label(callee)) if self.pure_functions.contains(callee) => {}
+                _ => return None,
+            },
+            _ => {}
+        }
+
+        let mut args: Vec<OperandKey> = instruction.args.iter().map(|&arg| arg.into()).collect();
+        if matches!(
+            instruction.op,
+            Opcode::Add | Opcode::Mul | Opcode::Eq | Opcode::Ne
+        ) {
+            args.sort();
+        }
+        Some(Expression {
+            op: instruction.op,
+            typ: instruction.typ,
+            args,
+        })
+    }
+
+    /// Rewrites the block and then every block it immediately dominates,
+    /// with `available` holding the values computed in dominating blocks
+    fn visit_dominator_subtree(
+        &mut self,
+        function: &mut MirFunction,
+        block: BlockId,
+        children: &HashMap<BlockId, Vec<BlockId>>,
+        available: &mut HashMap<Expression, Reg>,
+    ) {
+        let mut defined_here = Vec::new();
+        for instruction in &mut function.block_mut(block).instructions {
+            let Some(expression) = self.expression(instruction) else {
+                continue;
+            };
+            match available.get(&expression) {
+                Some(&reg) => {
+                    instruction.op = Opcode::Copy;
+                    instruction.args = [Operand::Reg(reg)].into();
+                    self.eliminated += 1;
+                }
+                None => {
+                    available.insert(expression.clone(), instruction.dest);
+                    defined_here.push(expression);
+                }
+            }
+        }
+
+        for &child in children.get(&block).into_iter().flatten() {
+            self.visit_dominator_subtree(function, child, children, available);
+        }
+
+        // Values computed here are not available in sibling subtrees
+        for expression in defined_here {
+            available.remove(&expression);
+        }
+    }
+}
+
+impl Default for MirCsePass {
+    fn default() -> Self {
+        MirCsePass::new()
+    }
+}
+
+impl MirVisitor for MirCsePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let cfg = CFGAnalysis::new(function);
+        let mut ssa = MirSSAPass::new();
+        let dominators = ssa.compute_dominators(function, &cfg);
+        let reachable = ssa.compute_reachable(function, &cfg);
+        let idom = ssa.compute_immediate_dominators(&dominators, &reachable);
+
+        let mut children: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        for (&block, &parent) in &idom {
+            children.entry(parent).or_default().push(block);
+        }
+        for blocks in children.values_mut() {
+            blocks.sort();
+        }
+
+        let entry = function.entry;
+        self.visit_dominator_subtree(function, entry, &children, &mut HashMap::new());
+    }
+}
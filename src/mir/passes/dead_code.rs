@@ -0,0 +1,129 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, FuncId, Instruction, MirFunction, MirProgram, Opcode, Operand, Reg};
+use std::collections::{HashMap, HashSet};
+
+/// Removes instructions and phis whose results are never used
+///
+/// An instruction is only removed if computing it has no other effect, so
+/// stores to globals are kept, and so are calls unless the callee is pure.
+/// Removing an instruction can leave the instructions computing its
+/// operands unused as well, so the pass repeats until nothing changes.
+/// Expects SSA form.
+pub struct MirDeadCodePass {
+    diagnostics: DiagnosticCollector,
+    pure_functions: HashSet<FuncId>,
+    removed: usize,
+}
+
+impl MirDeadCodePass {
+    pub fn new() -> Self {
+        MirDeadCodePass {
+            diagnostics: DiagnosticCollector::new(),
+            pure_functions: HashSet::new(),
+            removed: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.pure_functions = pure_functions(program);
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Removed {} dead instruction(s)", self.removed));
+    }
+
+    fn has_effects(&self, instruction: &Instruction) -> bool {
+        match instruction.op {
+            Opcode::Call => !matches!(
+                instruction.args.first(),
+                Some(Operand::Label(callee)) if self.pure_functions.contains(callee)
+            ),
+            Opcode::Store => true,
+            _ => false,
+        }
+    }
+}
+
+impl Default for MirDeadCodePass {
+    fn default() -> Self {
+        MirDeadCodePass::new()
+    }
+}
+
+/// Functions of the program whose calls can be merged or removed
+pub fn pure_functions(program: &MirProgram) -> HashSet<FuncId> {
+    program
+        .functions
+        .iter()
+        .filter(|function| function.pure)
+        .map(|function| function.name)
+        .collect()
+}
+
+/// How often every register is read in the function
+fn use_counts(function: &MirFunction) -> HashMap<Reg, usize> {
+    let mut uses: HashMap<Reg, usize> = HashMap::new();
+    for (_, block) in function.arena.iter() {
+        let operands = block
+            .phi_nodes
+            .iter()
+            .flat_map(|phi| phi.incomings.iter().map(|(_, operand)| operand))
+            .chain(
+                block
+                    .instructions
+                    .iter()
+                    .flat_map(|instruction| &instruction.args),
+            )
+            .chain(block.terminator.operands())
+            .chain(
+                block
+                    .terminator
+                    .edges()
+                    .into_iter()
+                    .flat_map(|(_, args)| args),
+            );
+        for reg in operands.filter_map(Operand::as_reg) {
+            *uses.entry(reg).or_default() += 1;
+        }
+    }
+    uses
+}
+
+impl MirVisitor for MirDeadCodePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        loop {
+            let uses = use_counts(function);
+            let is_used = |reg: &Reg| uses.get(reg).is_some_and(|&count| count > 0);
+            let mut removed = 0;
+
+            for index in 0..function.arena.len() {
+                let block = function.block_mut(BlockId::new(index));
+                let before = block.phi_nodes.len() + block.instructions.len();
+                block.phi_nodes.retain(|phi| is_used(&phi.dest));
+                block.instructions.retain(|instruction| {
+                    is_used(&instruction.dest) || self.has_effects(instruction)
+                });
+                removed += before - block.phi_nodes.len() - block.instructions.len();
+            }
+
+            if removed == 0 {
+                break;
+            }
+            self.removed += removed;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::diagnostics::{Diagnostic, DiagnosticCollector, Lint};
+use crate::intern::Symbol;
+use crate::mir::analysis::call_graph::CallGraph;
+use crate::mir::{Linkage, MirProgram, INIT_FUNCTION};
+
+/// Removes functions that can never be called
+///
+/// Functions are live if they are reachable through the call graph from
+/// `main`, from the initializer of the globals or from a function with
+/// public linkage or marked `@test`, since those are called from outside
+/// the program. Every other function is dropped from the program and
+/// reported as a warning. External functions have no body to remove. A program without `main` is treated as a library whose
+/// functions may all be called from outside, so nothing is removed.
+pub struct MirDeadFunctionPass {
+    diagnostics: DiagnosticCollector,
+}
+
+impl MirDeadFunctionPass {
+    pub fn new() -> Self {
+        MirDeadFunctionPass {
+            diagnostics: DiagnosticCollector::new(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        let call_graph = CallGraph::new(program);
+        let main = Symbol::intern("main");
+        if !call_graph.is_defined(main) {
+            return;
+        }
+
+        let roots = program
+            .functions
+            .iter()
+            .filter(|function| function.linkage == Linkage::Public || function.test)
+            .map(|function| function.name)
+            .chain([main, Symbol::intern(INIT_FUNCTION)]);
+        let live = call_graph.reachable_from(roots);
+        program.functions.retain(|function| {
+            if live.contains(&function.name) {
+                return true;
+            }
+            let location = function
+                .span
+                .map(|span| format!(" at {}", span))
+                .unwrap_or_default();
+            let mut warning = Diagnostic::new(format!(
+                "Function '{}'{} is never called and was removed",
+                function.name, location
+            ))
+            .with_lint(Lint::DeadFunction);
+            if let Some(span) = function.span {
+                warning = warning.with_location(function.source.clone(), span);
+            }
+            self.diagnostics.report_warning(warning);
+            false
+        });
+    }
+}
+
+impl Default for MirDeadFunctionPass {
+    fn default() -> Self {
+        MirDeadFunctionPass::new()
+    }
+}
@@ -0,0 +1,94 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::live_globals::LiveGlobals;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, MirFunction, MirProgram, Opcode, Operand};
+
+/// Removes stores to globals that are overwritten before they are read
+///
+/// A store is dead if no load of the global can see the value it wrote:
+/// every path from it stores to the global again before loading it, calling
+/// a function that might load it or trap, returning or stopping the
+/// program. A trap counts as a read, since an embedder can read the globals
+/// a call left even after it trapped, see [`crate::embed`]. Stores
+/// to locals need no such pass, as locals are registers in SSA form and
+/// [`super::dead_code::MirDeadCodePass`] removes unused ones.
+pub struct MirDeadStorePass {
+    diagnostics: DiagnosticCollector,
+    live_globals: Option<LiveGlobals>,
+    removed: usize,
+}
+
+impl MirDeadStorePass {
+    pub fn new() -> Self {
+        MirDeadStorePass {
+            diagnostics: DiagnosticCollector::new(),
+            live_globals: None,
+            removed: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.live_globals = Some(LiveGlobals::new(program));
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Removed {} dead store(s)", self.removed));
+    }
+}
+
+impl Default for MirDeadStorePass {
+    fn default() -> Self {
+        MirDeadStorePass::new()
+    }
+}
+
+impl MirVisitor for MirDeadStorePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let Some(live_globals) = &self.live_globals else {
+            return;
+        };
+        let results = live_globals.compute(function);
+
+        for index in 0..function.arena.len() {
+            let block_id = BlockId::new(index);
+            let mut live = results.block_exit[&block_id].clone();
+            let mut dead = Vec::new();
+            for (position, instruction) in function.block(block_id).instructions.iter().enumerate().rev() {
+                if let (Opcode::Store, Some(Operand::Global(global))) = (instruction.op, instruction.args.first())
+                    && !live.contains(global)
+                {
+                    dead.push((position, *global, instruction.span));
+                }
+                live_globals.step(instruction, &mut live);
+            }
+
+            // Positions were collected from the end, so removing them in
+            // that order leaves the ones still to remove in place
+            let instructions = &mut function.block_mut(block_id).instructions;
+            for (position, _, _) in &dead {
+                instructions.remove(*position);
+            }
+            for (_, global, span) in dead.iter().rev() {
+                let location = span.map_or(String::new(), |span| format!(" at {}", span));
+                self.diagnostics.info(format!(
+                    "Removed dead store to global '{}'{} in '{}'",
+                    global, location, function.name
+                ));
+            }
+            self.removed += dead.len();
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg};
+use std::collections::HashMap;
+
+/// Simplifies single instructions, looking through copies and the
+/// instructions that compute their operands
+///
+/// Catches the identities lowering itself creates, which the AST
+/// simplifier never sees: negation is lowered to `0 - x`, and copies of
+/// variables hide that two operands are the same value.
+///
+/// Always made, as they give bit-identical results:
+/// - `x - 0`, `x + -0`, `x * 1`, `1 * x` and `x / 1` become a copy of `x`
+/// - `select c, x, x` becomes a copy of `x`
+///
+/// For integers, and for floats only with fast math since `0 - x` is not
+/// `-x` when `x` is zero:
+/// - `0 - (0 - x)` becomes a copy of `x`
+/// - `x + (0 - y)` becomes `x - y`, and `x - (0 - y)` becomes `x + y`
+/// - `x + 0` becomes a copy of `x`, and `x - x` becomes `0`
+///
+/// Expects SSA form.
+pub struct MirInstCombinePass {
+    diagnostics: DiagnosticCollector,
+    fast_math: bool,
+    combined: usize,
+}
+
+/// What an instruction is rewritten to, keeping its destination and type
+struct Combined {
+    op: Opcode,
+    args: Vec<Operand>,
+}
+
+impl Combined {
+    fn copy(operand: Operand) -> Self {
+        Combined {
+            op: Opcode::Copy,
+            args: vec![operand],
+        }
+    }
+}
+
+impl MirInstCombinePass {
+    pub fn new(fast_math: bool) -> Self {
+        MirInstCombinePass {
+            diagnostics: DiagnosticCollector::new(),
+            fast_math,
+            combined: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Combined {} instruction(s)", self.combined));
+    }
+
+    fn combine(&self, instruction: &Instruction, values: &Values) -> Option<Combined> {
+        let typ = instruction.typ;
+        let args: Vec<Operand> = instruction.args.iter().map(|arg| values.resolve(*arg)).collect();
+        // Float identities that do not hold for signed zeros, NaN or
+        // infinities
+        let loose = typ.is_integer() || (typ.is_float() && self.fast_math);
+        let same_type = |operand: &Operand| values.has_type(operand, typ);
+
+        match (instruction.op, args.as_slice()) {
+            (Opcode::Select, [_, a, b]) if a.is_identical(b) => Some(Combined::copy(*a)),
+            (Opcode::Mul, [a, b]) | (Opcode::Mul, [b, a]) if is_constant(b, typ, 1.0) && same_type(a) => {
+                Some(Combined::copy(*a))
+            }
+            (Opcode::Div, [a, b]) if is_constant(b, typ, 1.0) && same_type(a) => Some(Combined::copy(*a)),
+            (Opcode::Sub, [a, b]) if is_constant(b, typ, 0.0) && same_type(a) => Some(Combined::copy(*a)),
+            (Opcode::Add, [a, b]) | (Opcode::Add, [b, a]) if is_constant(b, typ, -0.0) && same_type(a) => {
+                Some(Combined::copy(*a))
+            }
+            _ if !loose => None,
+
+            (Opcode::Add, [a, b]) | (Opcode::Add, [b, a]) if is_constant(b, typ, 0.0) && same_type(a) => {
+                Some(Combined::copy(*a))
+            }
+            (Opcode::Sub, [a, b]) if a == b && a.as_reg().is_some() => Some(Combined::copy(Operand::zero(typ))),
+            (Opcode::Sub, [a, b]) => match (values.negated(a, typ), values.negated(b, typ)) {
+                (_, Some(y)) if is_constant(a, typ, 0.0) && same_type(&y) => Some(Combined::copy(y)),
+                (_, Some(y)) => Some(Combined {
+                    op: Opcode::Add,
+                    args: vec![*a, y],
+                }),
+                _ => None,
+            },
+            (Opcode::Add, [a, b]) => match (values.negated(a, typ), values.negated(b, typ)) {
+                (_, Some(y)) => Some(Combined {
+                    op: Opcode::Sub,
+                    args: vec![*a, y],
+                }),
+                (Some(y), _) => Some(Combined {
+                    op: Opcode::Sub,
+                    args: vec![*b, y],
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// What the pass knows about the registers of a function
+struct Values {
+    /// The operand each copy that keeps its type copies
+    copies: HashMap<Reg, Operand>,
+    /// Each register `0 - x` is computed into, with `x` and the type
+    negations: HashMap<Reg, (Operand, MirType)>,
+    types: Vec<MirType>,
+}
+
+impl Values {
+    fn new(function: &MirFunction) -> Self {
+        let mut values = Values {
+            copies: HashMap::new(),
+            negations: HashMap::new(),
+            types: function.reg_types.clone(),
+        };
+        for (_, block) in function.arena.iter() {
+            for instruction in &block.instructions {
+                match (instruction.op, instruction.args.as_slice()) {
+                    (Opcode::Copy, [arg]) if values.has_type(arg, instruction.typ) => {
+                        values.copies.insert(instruction.dest, *arg);
+                    }
+                    (Opcode::Sub, [zero, arg]) if is_constant(zero, instruction.typ, 0.0) => {
+                        values.negations.insert(instruction.dest, (*arg, instruction.typ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        values
+    }
+
+    /// The operand a copy, or a chain of copies, started from
+    fn resolve(&self, mut operand: Operand) -> Operand {
+        while let Operand::Reg(reg) = operand
+            && let Some(copied) = self.copies.get(&reg)
+        {
+            operand = *copied;
+        }
+        operand
+    }
+
+    /// `x` if the operand is `0 - x` of the type
+    fn negated(&self, operand: &Operand, typ: MirType) -> Option<Operand> {
+        let (negated, negation_type) = self.negations.get(&operand.as_reg()?)?;
+        (*negation_type == typ).then(|| self.resolve(*negated))
+    }
+
+    /// Whether the operand already holds a value of the type, so copying
+    /// it converts nothing
+    fn has_type(&self, operand: &Operand, typ: MirType) -> bool {
+        match operand {
+            Operand::Reg(reg) => self.types.get(*reg) == Some(&typ),
+            immediate => immediate.immediate_fits(typ) == Some(true),
+        }
+    }
+}
+
+/// Whether the operand is the constant, telling `0` and `-0` apart for
+/// floats
+fn is_constant(operand: &Operand, typ: MirType, expected: f64) -> bool {
+    let value = match operand {
+        Operand::ImmI64(value) => *value as f64,
+        float => match float.as_float() {
+            Some(value) => value,
+            None => return false,
+        },
+    };
+    if typ.is_float() {
+        value.to_bits() == expected.to_bits()
+    } else {
+        value == expected
+    }
+}
+
+impl MirVisitor for MirInstCombinePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        // A combined instruction can be a copy or negation that lets
+        // another instruction combine
+        loop {
+            let values = Values::new(function);
+            let mut combined = 0;
+            for index in 0..function.arena.len() {
+                let block = function.block_mut(BlockId::new(index));
+                for instruction in &mut block.instructions {
+                    if matches!(instruction.op, Opcode::Copy | Opcode::Call | Opcode::Load | Opcode::Store) {
+                        continue;
+                    }
+                    if let Some(Combined { op, args }) = self.combine(instruction, &values) {
+                        instruction.op = op;
+                        instruction.args = args.into();
+                        combined += 1;
+                    }
+                }
+            }
+            if combined == 0 {
+                break;
+            }
+            self.combined += combined;
+        }
+    }
+}
@@ -1,2 +1,21 @@
+pub mod checks;
+pub mod compact_registers;
+pub mod constant_pool;
+pub mod coverage;
+pub mod cse;
+pub mod dead_code;
+pub mod dead_store;
+pub mod dead_functions;
+pub mod instcombine;
+pub mod out_of_ssa;
 pub mod print;
+pub mod promotion;
+pub mod reassociation;
+#[cfg(feature = "saturation")]
+pub mod saturation;
+pub mod sccp;
+pub mod select;
 pub mod ssa;
+pub mod strength_reduction;
+pub mod value_range;
+pub mod verify;
@@ -0,0 +1,176 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::block_params::block_params_to_phis;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{
+    BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Opcode, Operand, Reg, Terminator,
+};
+use crate::span::Span;
+use std::collections::BTreeMap;
+
+/// Takes MIR out of SSA form by replacing phi nodes with copies
+///
+/// Critical edges into blocks with phis are split first, so copies for one
+/// successor never execute on the path to another (the lost-copy problem).
+/// The phis of a block then become one parallel copy per predecessor, which
+/// is sequentialized with a temporary whenever the copies form a cycle
+/// (the swap problem). Block parameters are handled by turning them back
+/// into phis first.
+pub struct MirOutOfSSAPass {
+    diagnostics: DiagnosticCollector,
+}
+
+impl MirOutOfSSAPass {
+    pub fn new() -> Self {
+        MirOutOfSSAPass {
+            diagnostics: DiagnosticCollector::new(),
+        }
+    }
+
+    pub fn destruct(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+    }
+
+    /// Splits every edge from a block with several successors into a block with phis
+    fn split_critical_edges(&mut self, function: &mut MirFunction) {
+        for index in 0..function.arena.len() {
+            let block_id = BlockId::new(index);
+            let targets: Vec<BlockId> = function
+                .block(block_id)
+                .terminator
+                .edges()
+                .into_iter()
+                .map(|(target, _)| target)
+                .collect();
+            if targets.len() < 2 {
+                continue;
+            }
+
+            let mut new_targets = targets;
+            for target in &mut new_targets {
+                if function.block(*target).phi_nodes.is_empty() {
+                    continue;
+                }
+                let split = function.arena.alloc(BasicBlock {
+                    instructions: Vec::new(),
+                    terminator: Terminator::br(*target),
+                    phi_nodes: Vec::new(),
+                    params: Vec::new(),
+                    terminator_span: None,
+                });
+                // Each edge owns exactly one incoming value, even if several edges reach the same block
+                for phi in &mut function.block_mut(*target).phi_nodes {
+                    if let Some(incoming) = phi.incomings.iter_mut().find(|(pred, _)| *pred == block_id) {
+                        incoming.0 = split;
+                    }
+                }
+                *target = split;
+            }
+
+            for (target, new_target) in function
+                .block_mut(block_id)
+                .terminator
+                .targets_mut()
+                .into_iter()
+                .zip(new_targets)
+            {
+                *target = new_target;
+            }
+        }
+    }
+
+    /// Orders a parallel copy so that no source is overwritten before it is
+    /// read. The copies are attributed to `span`, the branch they belong to.
+    fn sequentialize(
+        &mut self,
+        function: &mut MirFunction,
+        mut copies: Vec<(Reg, Operand)>,
+        span: Option<Span>,
+    ) -> Vec<Instruction> {
+        let mut sequence = Vec::new();
+        copies.retain(|(dest, src)| *src != Operand::Reg(*dest));
+
+        while !copies.is_empty() {
+            // A copy is safe to emit once no other pending copy still reads its destination
+            let ready = copies.iter().position(|(dest, _)| {
+                !copies
+                    .iter()
+                    .any(|(_, src)| *src == Operand::Reg(*dest))
+            });
+
+            match ready {
+                Some(position) => {
+                    let (dest, src) = copies.remove(position);
+                    sequence.push(copy_instruction(function, dest, src, span));
+                }
+                None => {
+                    // Every remaining destination is still needed: break the cycle by
+                    // saving one destination in a temporary and reading from that instead
+                    let (dest, _) = copies[0];
+                    let temp = function.new_reg(function.reg_types[dest]);
+                    sequence.push(copy_instruction(function, temp, Operand::Reg(dest), span));
+                    for (_, src) in &mut copies {
+                        if *src == Operand::Reg(dest) {
+                            *src = Operand::Reg(temp);
+                        }
+                    }
+                }
+            }
+        }
+        sequence
+    }
+}
+
+impl Default for MirOutOfSSAPass {
+    fn default() -> Self {
+        MirOutOfSSAPass::new()
+    }
+}
+
+fn copy_instruction(
+    function: &MirFunction,
+    dest: Reg,
+    src: Operand,
+    span: Option<Span>,
+) -> Instruction {
+    Instruction {
+        dest,
+        op: Opcode::Copy,
+        typ: function.reg_types[dest],
+        args: [src].into(),
+        span,
+    }
+}
+
+impl MirVisitor for MirOutOfSSAPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        block_params_to_phis(function);
+        self.split_critical_edges(function);
+
+        // Gather the parallel copy each predecessor has to perform
+        let mut copies: BTreeMap<BlockId, Vec<(Reg, Operand)>> = BTreeMap::new();
+        for index in 0..function.arena.len() {
+            let block = function.block_mut(BlockId::new(index));
+            for phi in std::mem::take(&mut block.phi_nodes) {
+                for (pred, value) in phi.incomings {
+                    copies.entry(pred).or_default().push((phi.dest, value));
+                }
+            }
+        }
+
+        for (pred, parallel_copy) in copies {
+            let span = function.block(pred).terminator_span;
+            let sequence = self.sequentialize(function, parallel_copy, span);
+            function.block_mut(pred).instructions.extend(sequence);
+        }
+    }
+}
@@ -1,11 +1,24 @@
 use crate::diagnostics::DiagnosticCollector;
 use crate::mir::visitor::MirVisitor;
-use crate::mir::{BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Operand, Terminator};
+use crate::mir::{
+    BasicBlock, BlockId, InlineHint, Instruction, Linkage, MirFunction, MirProgram, Operand, PhiNode,
+    Terminator, TrapReason,
+};
+use crate::span::Span;
+use crate::term::Painter;
+use crate::value::{format_f32, format_float};
 
-/// Prints the different Functions, Blocks, Instructions and Terminators in the MIR
+/// Prints the different Functions, Blocks, Instructions and Terminators in the MIR.
+/// The text is collected rather than written out, see
+/// [`MirPrintingPass::into_output`].
 pub struct MirPrintingPass {
     diagnostics: DiagnosticCollector,
     indent: usize,
+    output: String,
+    spans: bool,
+    painter: Painter,
+    /// Span of the terminator of the block being printed
+    terminator_span: Option<Span>,
 }
 
 impl MirPrintingPass {
@@ -13,11 +26,53 @@ impl MirPrintingPass {
         MirPrintingPass {
             diagnostics: DiagnosticCollector::new(),
             indent: 0,
+            output: String::new(),
+            spans: false,
+            painter: Painter::default(),
+            terminator_span: None,
         }
     }
 
-    fn print(&self, msg: &str) {
-        println!("{}{}", "  ".repeat(self.indent), msg);
+    /// Annotate instructions and terminators with the source span they were
+    /// lowered from
+    pub fn with_spans(mut self, enabled: bool) -> Self {
+        self.spans = enabled;
+        self
+    }
+
+    /// Style the output with the given painter
+    pub fn with_painter(mut self, painter: Painter) -> Self {
+        self.painter = painter;
+        self
+    }
+
+    /// The text printed so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn into_output(self) -> String {
+        self.output
+    }
+
+    /// Prints a line without indentation
+    fn line(&mut self, msg: &str) {
+        self.output.push_str(msg);
+        self.output.push('\n');
+    }
+
+    fn print(&mut self, msg: &str) {
+        self.line(&format!("{}{}", "  ".repeat(self.indent), msg));
+    }
+
+    /// Prints a line followed by its source span, if spans are enabled
+    fn print_located(&mut self, msg: &str, span: Option<Span>) {
+        match span {
+            Some(span) if self.spans => {
+                self.print(&format!("{}  {}", msg, self.painter.comment(&format!("; {}", span))))
+            }
+            _ => self.print(msg),
+        }
     }
 
     fn indent(&mut self) {
@@ -34,18 +89,36 @@ impl MirPrintingPass {
         match op {
             Operand::Reg(r) => format!("r{}", r),
             Operand::ImmI64(i) => format!("{}", i),
-            Operand::ImmF64(f) => format!("{}", f),
+            Operand::ImmF64(f) => format_float(*f),
+            Operand::ImmF32(f) => format!("{}f32", format_f32(*f)),
             Operand::ImmBool(b) => format!("{}", b),
             Operand::Label(s) => format!("@{}", s),
-            Operand::Pair(block_id, operand) => {
-                format!("[{}, {}]", self.fmt_block(*block_id), self.fmt_operand(operand))
-            }
+            Operand::Global(s) => format!("${}", s),
         }
     }
 
     fn fmt_block(&self, block_id: BlockId) -> String {
         format!("block{}", block_id.index())
     }
+
+    /// Formats a branch target together with the block arguments it passes
+    fn fmt_edge(&self, block_id: BlockId, args: &[Operand]) -> String {
+        if args.is_empty() {
+            return self.fmt_block(block_id);
+        }
+        let args_str = args
+            .iter()
+            .map(|arg| self.fmt_operand(arg))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({})", self.fmt_block(block_id), args_str)
+    }
+}
+
+impl Default for MirPrintingPass {
+    fn default() -> Self {
+        MirPrintingPass::new()
+    }
 }
 
 impl MirVisitor for MirPrintingPass {
@@ -60,33 +133,96 @@ impl MirVisitor for MirPrintingPass {
     }
 
     fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
-        println!(
-            "=== MIR Program ({} functions) ===\n",
-            program.functions.len()
-        );
+        self.line(&format!(
+            "{}\n",
+            self.painter
+                .heading(&format!("=== MIR Program ({} functions) ===", program.functions.len()))
+        ));
+        for global in &program.globals {
+            let export = if global.linkage == Linkage::Public { "@export " } else { "" };
+            self.line(&format!("{}{} ${}: {:?}", export, self.painter.keyword("global"), global.name, global.typ));
+        }
+        let externs: Vec<_> = program.symbols.iter().filter(|f| f.linkage == Linkage::External).collect();
+        for signature in &externs {
+            let params: Vec<String> = signature.params.iter().map(|typ| format!("{:?}", typ)).collect();
+            self.line(&format!(
+                "{} {}({}) -> {:?}",
+                self.painter.keyword("extern"),
+                self.painter.heading(&format!("fn {}", signature.name)),
+                params.join(", "),
+                signature.return_type
+            ));
+        }
+        if !program.globals.is_empty() || !externs.is_empty() {
+            self.line("");
+        }
         self.walk_program(program);
     }
 
     fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
-        println!(
-            "fn {}({} params) -> {:?}:",
-            function.name,
+        let mut attributes = String::new();
+        for (present, name) in [
+            (function.test, "@test "),
+            (function.linkage == Linkage::Public, "@export "),
+            (function.inline == InlineHint::Always, "@inline "),
+            (function.inline == InlineHint::Never, "@noinline "),
+            (function.pure, "@pure "),
+        ] {
+            if present {
+                attributes.push_str(name);
+            }
+        }
+        self.line(&format!(
+            "{}{}({} params) -> {:?}:",
+            attributes,
+            self.painter.heading(&format!("fn {}", function.name)),
             function.params.len(),
             function.return_type
-        );
+        ));
         self.indent();
         self.walk_function(function);
         self.dedent();
-        println!(); // Blank line after function
+        self.line(""); // Blank line after function
     }
 
     fn visit_basicblock(&mut self, block_id: BlockId, block: &mut BasicBlock) -> Self::Output {
-        println!("block{}:", block_id.index());
+        if block.params.is_empty() {
+            self.line(&format!("{}:", self.painter.heading(&self.fmt_block(block_id))));
+        } else {
+            let params_str = block
+                .params
+                .iter()
+                .map(|(reg, typ)| format!("r{}: {:?}", reg, typ))
+                .collect::<Vec<_>>()
+                .join(", ");
+            self.line(&format!("{}({}):", self.painter.heading(&self.fmt_block(block_id)), params_str));
+        }
+        self.terminator_span = block.terminator_span;
         self.indent();
         self.walk_basicblock(block);
         self.dedent();
     }
 
+    fn visit_phi(&mut self, phi: &mut PhiNode) -> Self::Output {
+        let incomings_str = phi
+            .incomings
+            .iter()
+            .map(|(block_id, operand)| {
+                format!("{}: {}", self.fmt_block(*block_id), self.fmt_operand(operand))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        self.print(&format!(
+            "r{} = {} {:?} [{}]",
+            phi.dest,
+            self.painter.keyword("Phi"),
+            phi.typ,
+            incomings_str
+        ));
+        self.walk_phi(phi);
+    }
+
     fn visit_instruction(&mut self, instruction: &mut Instruction) -> Self::Output {
         let args_str = instruction
             .args
@@ -95,36 +231,102 @@ impl MirVisitor for MirPrintingPass {
             .collect::<Vec<_>>()
             .join(", ");
 
-        self.print(&format!(
-            "r{} = {:?} {:?} [{}]",
-            instruction.dest, instruction.op, instruction.typ, args_str
-        ));
+        self.print_located(
+            &format!(
+                "r{} = {} {:?} [{}]",
+                instruction.dest,
+                self.painter.keyword(&format!("{:?}", instruction.op)),
+                instruction.typ,
+                args_str
+            ),
+            instruction.span,
+        );
         self.walk_instruction(instruction);
     }
 
     fn visit_terminator(&mut self, terminator: &mut Terminator) -> Self::Output {
         match terminator {
-            Terminator::Br { target } => {
-                self.print(&format!("br {}", self.fmt_block(*target)));
+            Terminator::Br { target, args } => {
+                self.print_located(
+                    &format!("{} {}", self.painter.keyword("br"), self.fmt_edge(*target, args)),
+                    self.terminator_span,
+                );
             }
             Terminator::BrIf {
                 cond,
                 then_bb,
+                then_args,
                 else_bb,
+                else_args,
+                weights,
             } => {
-                self.print(&format!(
-                    "br_if {}, {}, {}",
-                    self.fmt_operand(cond),
-                    self.fmt_block(*then_bb),
-                    self.fmt_block(*else_bb)
-                ));
+                let weights = match weights {
+                    Some(weights) => format!(", weights {}:{}", weights.then_weight, weights.else_weight),
+                    None => String::new(),
+                };
+                self.print_located(
+                    &format!(
+                        "{} {}, {}, {}{}",
+                        self.painter.keyword("br_if"),
+                        self.fmt_operand(cond),
+                        self.fmt_edge(*then_bb, then_args),
+                        self.fmt_edge(*else_bb, else_args),
+                        weights
+                    ),
+                    self.terminator_span,
+                );
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+                default_args,
+            } => {
+                let cases_str = cases
+                    .iter()
+                    .map(|case| {
+                        format!(
+                            "{}: {}",
+                            self.fmt_operand(&case.value),
+                            self.fmt_edge(case.target, &case.args)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.print_located(
+                    &format!(
+                        "{} {} [{}], default {}",
+                        self.painter.keyword("switch"),
+                        self.fmt_operand(value),
+                        cases_str,
+                        self.fmt_edge(*default, default_args)
+                    ),
+                    self.terminator_span,
+                );
             }
             Terminator::Ret { value } => match value {
-                Some(v) => self.print(&format!("ret {}", self.fmt_operand(v))),
-                None => self.print("ret"),
+                Some(v) => self.print_located(
+                    &format!("{} {}", self.painter.keyword("ret"), self.fmt_operand(v)),
+                    self.terminator_span,
+                ),
+                None => self.print_located(&self.painter.keyword("ret"), self.terminator_span),
             },
+            Terminator::Trap { reason } => {
+                let reason = match reason {
+                    TrapReason::AssertionFailed(Some(message)) => format!("assert {:?}", message),
+                    TrapReason::AssertionFailed(None) => "assert".to_string(),
+                    TrapReason::DivisionByZero => "division_by_zero".to_string(),
+                    TrapReason::UnreachableArm => "unreachable_arm".to_string(),
+                    TrapReason::NotANumber => "nan".to_string(),
+                    TrapReason::UninitializedRead => "uninitialized".to_string(),
+                };
+                self.print_located(
+                    &format!("{} {}", self.painter.keyword("trap"), reason),
+                    self.terminator_span,
+                );
+            }
             Terminator::Unreachable => {
-                self.print("unreachable");
+                self.print_located(&self.painter.keyword("unreachable"), self.terminator_span);
             }
         }
         self.walk_terminator(terminator);
@@ -0,0 +1,171 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::call_graph::CallGraph;
+use crate::mir::analysis::dataflow::{DataflowAnalysis, Direction, solve};
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, FuncId, GlobalId, Instruction, MirFunction, MirProgram, Opcode, Operand, OperandKey};
+use std::collections::{BTreeMap, HashSet};
+
+/// Keeps the values of globals in registers, so loads need not read memory
+///
+/// A global holds the value last stored to it or loaded from it until a
+/// call that may store to it, which is any call unless the callee is
+/// defined in the program and neither it nor anything it calls stores to a
+/// global. A load that every path reaches with the same such value becomes
+/// a copy of it, which later passes propagate. Locals are registers in SSA
+/// form already, so globals are the only memory there is to promote.
+/// Stores are kept for [`super::dead_store::MirDeadStorePass`] to remove.
+/// Expects SSA form.
+pub struct MirPromotionPass {
+    diagnostics: DiagnosticCollector,
+    /// Defined functions that never store to a global, directly or through
+    /// their callees
+    non_writers: HashSet<FuncId>,
+    promoted: usize,
+}
+
+/// The values globals are known to hold, or `None` where no path has
+/// reached yet
+type KnownValues = Option<BTreeMap<GlobalId, OperandKey>>;
+
+impl MirPromotionPass {
+    pub fn new() -> Self {
+        MirPromotionPass {
+            diagnostics: DiagnosticCollector::new(),
+            non_writers: HashSet::new(),
+            promoted: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        let call_graph = CallGraph::new(program);
+        let writers: HashSet<FuncId> = program
+            .functions
+            .iter()
+            .filter(|function| {
+                function
+                    .arena
+                    .iter()
+                    .any(|(_, block)| block.instructions.iter().any(|instruction| instruction.op == Opcode::Store))
+            })
+            .map(|function| function.name)
+            .collect();
+        self.non_writers = program
+            .functions
+            .iter()
+            .map(|function| function.name)
+            .filter(|&function| {
+                call_graph
+                    .reachable_from([function])
+                    .iter()
+                    .all(|callee| call_graph.is_defined(*callee) && !writers.contains(callee))
+            })
+            .collect();
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Promoted {} load(s) of globals", self.promoted));
+    }
+
+    /// Updates the values known after the instruction from those before it.
+    /// A load gives its own register even when it is promoted, and so holds
+    /// the value known before it, which keeps the facts the same whatever
+    /// reached it and lets the analysis settle.
+    fn step(&self, instruction: &Instruction, values: &mut BTreeMap<GlobalId, OperandKey>) {
+        match (instruction.op, instruction.args.as_slice()) {
+            (Opcode::Load, [Operand::Global(global)]) => {
+                values.insert(*global, OperandKey::Reg(instruction.dest));
+            }
+            (Opcode::Store, [Operand::Global(global), value]) => {
+                values.insert(*global, (*value).into());
+            }
+            (Opcode::Call, [Operand::Label(callee), ..]) if self.non_writers.contains(callee) => {}
+            (Opcode::Call, _) => values.clear(),
+            _ => {}
+        }
+    }
+}
+
+impl Default for MirPromotionPass {
+    fn default() -> Self {
+        MirPromotionPass::new()
+    }
+}
+
+impl DataflowAnalysis for MirPromotionPass {
+    type Domain = KnownValues;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _function: &MirFunction) -> Self::Domain {
+        Some(BTreeMap::new())
+    }
+
+    fn initial(&self, _function: &MirFunction) -> Self::Domain {
+        None
+    }
+
+    fn meet(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        match (into.as_mut(), other) {
+            (_, None) => {}
+            (None, Some(other)) => *into = Some(other.clone()),
+            (Some(into), Some(other)) => into.retain(|global, value| other.get(global) == Some(value)),
+        }
+    }
+
+    fn transfer(&self, function: &MirFunction, block_id: BlockId, input: &Self::Domain) -> Self::Domain {
+        let mut values = input.clone()?;
+        for instruction in &function.block(block_id).instructions {
+            self.step(instruction, &mut values);
+        }
+        Some(values)
+    }
+}
+
+impl MirVisitor for MirPromotionPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let results = solve(self, function);
+
+        let mut promoted = Vec::new();
+        for index in 0..function.arena.len() {
+            let block_id = BlockId::new(index);
+            // Blocks no path reaches keep their loads
+            let Some(mut values) = results.block_entry[&block_id].clone() else {
+                continue;
+            };
+            for instruction in &mut function.block_mut(block_id).instructions {
+                let known = match (instruction.op, instruction.args.as_slice()) {
+                    (Opcode::Load, [Operand::Global(global)]) => values.get(global).map(|&value| (*global, value)),
+                    _ => None,
+                };
+                self.step(instruction, &mut values);
+                if let Some((global, value)) = known {
+                    instruction.op = Opcode::Copy;
+                    instruction.args = [value.into()].into();
+                    promoted.push((global, instruction.span));
+                }
+            }
+        }
+
+        for (global, span) in &promoted {
+            let location = span.map_or(String::new(), |span| format!(" at {}", span));
+            self.diagnostics.info(format!(
+                "Promoted load of global '{}'{} in '{}'",
+                global, location, function.name
+            ));
+        }
+        self.promoted += promoted.len();
+    }
+}
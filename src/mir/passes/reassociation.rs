@@ -0,0 +1,319 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::constant_propagation::{ConstValue, ConstantPropagation};
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg};
+use std::collections::{BTreeMap, HashMap};
+
+/// Regroups chains of additions and multiplications so their constants end
+/// up next to each other and fold into one
+///
+/// `(x + 1) + 2` becomes `x + 3`, and `(x + 1) + (y + 2)` becomes
+/// `(x + y) + 3`. Subtracting a constant counts as adding its negation.
+///
+/// Integer arithmetic is always reassociated. Float arithmetic is not
+/// associative, regrouping it can change the rounding of the result, so
+/// floats are only touched when fast math is enabled.
+/// Expects SSA form.
+pub struct MirReassociationPass {
+    diagnostics: DiagnosticCollector,
+    fast_math: bool,
+    reassociated: usize,
+}
+
+/// The two associative and commutative operations the pass regroups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    Add,
+    Mul,
+}
+
+/// A constant in a chain, kept in the representation of the chain's type
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Constant {
+    Int(i64),
+    Float(f64),
+}
+
+/// A single operation with one constant operand: `dest = base <group> constant`
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    group: Group,
+    typ: MirType,
+    base: Operand,
+    constant: Constant,
+}
+
+impl Group {
+    fn opcode(self) -> Opcode {
+        match self {
+            Group::Add => Opcode::Add,
+            Group::Mul => Opcode::Mul,
+        }
+    }
+
+    fn identity(self, typ: MirType) -> Constant {
+        match (self, is_float(typ)) {
+            (Group::Add, false) => Constant::Int(0),
+            (Group::Add, true) => Constant::Float(0.0),
+            (Group::Mul, false) => Constant::Int(1),
+            (Group::Mul, true) => Constant::Float(1.0),
+        }
+    }
+
+    /// Combines two constants, or `None` if integer arithmetic would overflow
+    fn combine(self, a: Constant, b: Constant) -> Option<Constant> {
+        match (self, a, b) {
+            (Group::Add, Constant::Int(a), Constant::Int(b)) => a.checked_add(b).map(Constant::Int),
+            (Group::Mul, Constant::Int(a), Constant::Int(b)) => a.checked_mul(b).map(Constant::Int),
+            (Group::Add, Constant::Float(a), Constant::Float(b)) => Some(Constant::Float(a + b)),
+            (Group::Mul, Constant::Float(a), Constant::Float(b)) => Some(Constant::Float(a * b)),
+            _ => None,
+        }
+    }
+}
+
+impl Constant {
+    /// The constant as an immediate of the type of the chain
+    fn operand(self, typ: MirType) -> Operand {
+        match self {
+            Constant::Int(value) => Operand::ImmI64(value),
+            Constant::Float(value) => Operand::float(value, typ),
+        }
+    }
+}
+
+impl MirReassociationPass {
+    pub fn new(fast_math: bool) -> Self {
+        MirReassociationPass {
+            diagnostics: DiagnosticCollector::new(),
+            fast_math,
+            reassociated: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Reassociated {} instruction(s)", self.reassociated));
+    }
+
+    fn reassociable(&self, typ: MirType) -> bool {
+        match typ {
+            MirType::I8 | MirType::I16 | MirType::I32 | MirType::I64 => true,
+            MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64 => self.fast_math,
+            MirType::I1 | MirType::Void => false,
+        }
+    }
+
+    /// Every eligible instruction that applies a constant to a single other operand
+    fn links(
+        &self,
+        function: &MirFunction,
+        constants: &BTreeMap<Reg, ConstValue>,
+    ) -> HashMap<Reg, Link> {
+        let mut links = HashMap::new();
+        for (_, block) in function.arena.iter() {
+            for instruction in &block.instructions {
+                if !self.reassociable(instruction.typ) {
+                    continue;
+                }
+                let constant = |operand: &Operand| constant(operand, instruction.typ, constants);
+                let link = match (&instruction.op, instruction.args.as_slice()) {
+                    (op @ (Opcode::Add | Opcode::Mul), [lhs, rhs]) => {
+                        let group = match op {
+                            Opcode::Add => Group::Add,
+                            _ => Group::Mul,
+                        };
+                        match (constant(lhs), constant(rhs)) {
+                            (None, Some(c)) => Some((group, *lhs, c)),
+                            (Some(c), None) => Some((group, *rhs, c)),
+                            _ => None,
+                        }
+                    }
+                    (Opcode::Sub, [lhs, rhs]) => match (constant(lhs), constant(rhs)) {
+                        (None, Some(Constant::Int(c))) => c
+                            .checked_neg()
+                            .map(|c| (Group::Add, *lhs, Constant::Int(c))),
+                        (None, Some(Constant::Float(c))) => {
+                            Some((Group::Add, *lhs, Constant::Float(-c)))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some((group, base, constant)) = link {
+                    links.insert(
+                        instruction.dest,
+                        Link {
+                            group,
+                            typ: instruction.typ,
+                            base,
+                            constant,
+                        },
+                    );
+                }
+            }
+        }
+        links
+    }
+
+    /// Rewrites an instruction whose operands carry constants that can be
+    /// combined. Returns a new instruction that has to be inserted before it
+    /// when both operands had a non-constant base.
+    fn reassociate(
+        &mut self,
+        function: &mut MirFunction,
+        instruction: &mut Instruction,
+        links: &HashMap<Reg, Link>,
+        constants: &BTreeMap<Reg, ConstValue>,
+    ) -> Option<Instruction> {
+        if !self.reassociable(instruction.typ) {
+            return None;
+        }
+        let typ = instruction.typ;
+        let (group, lhs, rhs) = match (&instruction.op, instruction.args.as_slice()) {
+            (Opcode::Add, [lhs, rhs]) => (Group::Add, *lhs, *rhs),
+            (Opcode::Mul, [lhs, rhs]) => (Group::Mul, *lhs, *rhs),
+            // `x - c` was recorded as the link `x + (-c)`
+            (Opcode::Sub, [lhs, _]) => {
+                let link = links.get(&instruction.dest)?;
+                (Group::Add, *lhs, link.constant.operand(typ))
+            }
+            _ => return None,
+        };
+
+        // Split each operand into the value it builds on and the constants
+        // applied to it along the way
+        let mut bases = Vec::new();
+        let mut total = group.identity(typ);
+        let mut folded = 0;
+        for operand in [lhs, rhs] {
+            if let Some(c) = constant(&operand, typ, constants) {
+                total = group.combine(total, c)?;
+                folded += 1;
+                continue;
+            }
+            let (base, offset, length) = flatten(operand, group, typ, links)?;
+            total = group.combine(total, offset)?;
+            folded += length;
+            bases.push(base);
+        }
+        // A single constant is already as grouped as it gets
+        if folded < 2 {
+            return None;
+        }
+
+        let identity = total == group.identity(typ);
+        let mut hoisted = None;
+        let (op, args) = match bases.as_slice() {
+            [] => (Opcode::Copy, vec![total.operand(typ)]),
+            [base] if identity => (Opcode::Copy, vec![*base]),
+            [base] => (group.opcode(), vec![*base, total.operand(typ)]),
+            [a, b] if identity => (group.opcode(), vec![*a, *b]),
+            [a, b] => {
+                let dest = function.new_reg(typ);
+                hoisted = Some(Instruction {
+                    dest,
+                    op: group.opcode(),
+                    typ,
+                    args: [*a, *b].into(),
+                    span: instruction.span,
+                });
+                (group.opcode(), vec![Operand::Reg(dest), total.operand(typ)])
+            }
+            _ => unreachable!("an instruction has at most two operands"),
+        };
+        instruction.op = op;
+        instruction.args = args.into();
+        self.reassociated += 1;
+        hoisted
+    }
+}
+
+fn is_float(typ: MirType) -> bool {
+    matches!(
+        typ,
+        MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64
+    )
+}
+
+/// Reads an operand as a constant of the given type, if it is one
+fn constant(
+    operand: &Operand,
+    typ: MirType,
+    constants: &BTreeMap<Reg, ConstValue>,
+) -> Option<Constant> {
+    let value = match operand {
+        Operand::Reg(reg) => constants.get(reg).copied()?,
+        Operand::ImmI64(value) => ConstValue::Int(*value),
+        Operand::ImmF64(value) => ConstValue::Float(*value),
+        Operand::ImmF32(value) => ConstValue::Float(*value as f64),
+        Operand::ImmBool(_) | Operand::Label(_) | Operand::Global(_) => return None,
+    };
+    match (value, is_float(typ)) {
+        (ConstValue::Int(value), false) => Some(Constant::Int(value)),
+        (ConstValue::Int(value), true) => Some(Constant::Float(value as f64)),
+        (ConstValue::Float(value), true) => Some(Constant::Float(value)),
+        _ => None,
+    }
+}
+
+/// Follows a chain of links of the same group and type down to its first
+/// operand that is not itself a link. Returns that operand, the combined
+/// constant and how many constants were combined.
+fn flatten(
+    operand: Operand,
+    group: Group,
+    typ: MirType,
+    links: &HashMap<Reg, Link>,
+) -> Option<(Operand, Constant, usize)> {
+    let mut base = operand;
+    let mut total = group.identity(typ);
+    let mut length = 0;
+    while let Operand::Reg(reg) = base
+        && let Some(link) = links.get(&reg)
+        && link.group == group
+        && link.typ == typ
+    {
+        total = group.combine(total, link.constant)?;
+        base = link.base;
+        length += 1;
+    }
+    Some((base, total, length))
+}
+
+impl MirVisitor for MirReassociationPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let constants = ConstantPropagation::constants(function);
+        let links = self.links(function, &constants);
+
+        for index in 0..function.arena.len() {
+            let block = BlockId::new(index);
+            let mut instructions = std::mem::take(&mut function.block_mut(block).instructions);
+            let mut rewritten = Vec::with_capacity(instructions.len());
+            for mut instruction in instructions.drain(..) {
+                if let Some(hoisted) =
+                    self.reassociate(function, &mut instruction, &links, &constants)
+                {
+                    rewritten.push(hoisted);
+                }
+                rewritten.push(instruction);
+            }
+            function.block_mut(block).instructions = rewritten;
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::egraph::{ClassId, EGraph, ENode};
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{Args, BlockId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg};
+use std::collections::{HashMap, HashSet};
+
+/// Rewrites float arithmetic by equality saturation
+///
+/// The float additions, subtractions, multiplications and divisions of a
+/// block go into an [`EGraph`], rewrite rules add every equivalent form of
+/// them until there are no new ones, and the cheapest form of each value
+/// used outside the block's arithmetic is extracted back into the block.
+/// Since the graph keeps every form at once, no rewrite can block a better
+/// one the way it can when instructions are rewritten one at a time.
+///
+/// Without fast math only rewrites that give bit-identical results are
+/// made: commutativity, `x * 1`, `x / 1`, `x - 0` and `x + -0` to `x`,
+/// `x - c` to `x + -c`, `x * 2` to `x + x`, and folding `F64` constants.
+/// Fast math adds associativity, distributivity, `x + 0`, `x * 0` and
+/// `x - x`.
+///
+/// A block is only rewritten if the estimated cost of its arithmetic goes
+/// down, and blocks with too much arithmetic to saturate quickly are left
+/// alone. Only built with the `saturation` feature.
+/// Expects SSA form.
+pub struct MirSaturationPass {
+    diagnostics: DiagnosticCollector,
+    fast_math: bool,
+    rewritten: usize,
+}
+
+/// Blocks with more arithmetic instructions than this are left alone
+const MAX_BLOCK_ARITHMETIC: usize = 64;
+
+/// Rounds of rewriting before saturation gives up
+const MAX_ITERATIONS: usize = 8;
+
+/// E-nodes in a block's graph before saturation gives up
+const MAX_NODES: usize = 5000;
+
+impl MirSaturationPass {
+    pub fn new(fast_math: bool) -> Self {
+        MirSaturationPass {
+            diagnostics: DiagnosticCollector::new(),
+            fast_math,
+            rewritten: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Rewrote {} block(s) by equality saturation", self.rewritten));
+    }
+
+    /// Saturates the arithmetic of a block and extracts it back, returning
+    /// true if the block changed
+    fn saturate_block(&self, function: &mut MirFunction, block_id: BlockId, roots: &HashSet<Reg>) -> bool {
+        let instructions = &function.block(block_id).instructions;
+        let arithmetic = instructions.iter().filter(|i| is_arithmetic(i)).count();
+        if arithmetic == 0 || arithmetic > MAX_BLOCK_ARITHMETIC {
+            return false;
+        }
+
+        let mut egraph = EGraph::new();
+        let mut classes: HashMap<Reg, ClassId> = HashMap::new();
+        let mut leaves: Vec<(Reg, ClassId)> = Vec::new();
+        for instruction in instructions.iter().filter(|i| is_arithmetic(i)) {
+            let mut class_of = |operand: &Operand| match operand {
+                Operand::Reg(reg) => *classes.entry(*reg).or_insert_with(|| {
+                    let typ = function.reg_type(*reg).unwrap_or(instruction.typ);
+                    let class = egraph.add(ENode::Reg(*reg, typ));
+                    leaves.push((*reg, class));
+                    class
+                }),
+                Operand::ImmF64(value) => egraph.add(ENode::Float(value.to_bits())),
+                _ => unreachable!("arithmetic only reads registers and floats"),
+            };
+            let class = match instruction.op {
+                Opcode::Copy => class_of(&instruction.args[0]),
+                op => {
+                    let args = instruction.args.iter().map(&mut class_of).collect();
+                    egraph.add(ENode::Op { op, typ: instruction.typ, args })
+                }
+            };
+            classes.insert(instruction.dest, class);
+        }
+        let cost_before: u64 = instructions.iter().filter(|i| is_arithmetic(i)).map(|i| op_cost(i.op)).sum();
+
+        for _ in 0..MAX_ITERATIONS {
+            let mut found = Vec::new();
+            'search: for (class, nodes) in egraph.classes() {
+                for node in &nodes {
+                    self.rewrites(&egraph, class, node, &mut found);
+                    if found.len() > MAX_NODES {
+                        break 'search;
+                    }
+                }
+            }
+            let size = (egraph.len(), egraph.class_count());
+            for (class, term) in found {
+                let equal = term.add(&mut egraph);
+                egraph.union(class, equal);
+            }
+            egraph.rebuild();
+            if (egraph.len(), egraph.class_count()) == size || egraph.len() > MAX_NODES {
+                break;
+            }
+        }
+
+        // Values from before the block, and constants, are available from
+        // its start; the results of other instructions once they ran
+        let defined_here: HashSet<Reg> = instructions.iter().map(|i| i.dest).collect();
+        let mut extraction = Extraction {
+            egraph: &egraph,
+            available: HashMap::new(),
+            instructions: Vec::new(),
+        };
+        for &(reg, class) in &leaves {
+            if !defined_here.contains(&reg) {
+                extraction.available.entry(egraph.find(class)).or_insert(Operand::Reg(reg));
+            }
+        }
+        for (class, _) in egraph.classes() {
+            if let Some(value) = egraph.constant(class) {
+                extraction.available.entry(class).or_insert(Operand::ImmF64(value));
+            }
+        }
+
+        for instruction in function.block(block_id).instructions.clone() {
+            if !is_arithmetic(&instruction) {
+                if let Some(&class) = classes.get(&instruction.dest) {
+                    extraction
+                        .available
+                        .entry(egraph.find(class))
+                        .or_insert(Operand::Reg(instruction.dest));
+                }
+                extraction.instructions.push(instruction);
+            } else if roots.contains(&instruction.dest) {
+                let class = classes[&instruction.dest];
+                let costs = extraction.costs();
+                if extraction
+                    .materialize(function, class, Some(&instruction), &costs)
+                    .is_none()
+                {
+                    return false;
+                }
+            }
+        }
+
+        let cost_after: u64 = extraction
+            .instructions
+            .iter()
+            .filter(|i| is_arithmetic(i))
+            .map(|i| op_cost(i.op))
+            .sum();
+        if cost_after >= cost_before {
+            return false;
+        }
+        function.block_mut(block_id).instructions = extraction.instructions;
+        true
+    }
+
+    /// The forms the rules give for an e-node of the class
+    fn rewrites(&self, egraph: &EGraph, class: ClassId, node: &ENode, found: &mut Vec<(ClassId, Term)>) {
+        let ENode::Op { op, typ, args } = node else {
+            return;
+        };
+        let (op, typ, a, b) = (*op, *typ, args[0], args[1]);
+        let is = |class: ClassId, expected: f64| {
+            egraph
+                .constant(class)
+                .is_some_and(|value| value.to_bits() == expected.to_bits())
+        };
+        let of_type = |class: ClassId| egraph.class_type(class).is_none_or(|t| t == typ);
+        let binary = |op: Opcode, lhs: Term, rhs: Term| Term::Op(op, typ, Box::new([lhs, rhs]));
+        let mut rewrite = |term: Term| found.push((class, term));
+
+        if matches!(op, Opcode::Add | Opcode::Mul) {
+            rewrite(binary(op, Term::Class(b), Term::Class(a)));
+        }
+        let identity = match op {
+            Opcode::Mul | Opcode::Div => is(b, 1.0),
+            Opcode::Sub => is(b, 0.0),
+            Opcode::Add => is(b, -0.0),
+            _ => false,
+        };
+        if identity && of_type(a) {
+            rewrite(Term::Class(a));
+        }
+        if op == Opcode::Sub
+            && let Some(value) = egraph.constant(b)
+        {
+            rewrite(binary(Opcode::Add, Term::Class(a), Term::Float(-value)));
+        }
+        if op == Opcode::Mul && is(b, 2.0) {
+            rewrite(binary(Opcode::Add, Term::Class(a), Term::Class(a)));
+        }
+        if typ == MirType::F64
+            && let (Some(x), Some(y)) = (egraph.constant(a), egraph.constant(b))
+        {
+            rewrite(Term::Float(match op {
+                Opcode::Add => x + y,
+                Opcode::Sub => x - y,
+                Opcode::Mul => x * y,
+                _ => x / y,
+            }));
+        }
+
+        if !self.fast_math {
+            return;
+        }
+        let zero = egraph.constant(b) == Some(0.0);
+        if op == Opcode::Add && zero && of_type(a) {
+            rewrite(Term::Class(a));
+        }
+        if (op == Opcode::Mul && zero) || (op == Opcode::Sub && egraph.find(a) == egraph.find(b)) {
+            rewrite(Term::Float(0.0));
+        }
+        let operations = |class: ClassId, of: Opcode| -> Vec<(ClassId, ClassId)> {
+            egraph
+                .nodes(class)
+                .iter()
+                .filter_map(|node| match node {
+                    ENode::Op { op, typ: t, args } if *op == of && *t == typ => Some((args[0], args[1])),
+                    _ => None,
+                })
+                .collect()
+        };
+        if matches!(op, Opcode::Add | Opcode::Mul) {
+            // (x op y) op b to x op (y op b)
+            for (x, y) in operations(a, op) {
+                let right = binary(op, Term::Class(y), Term::Class(b));
+                rewrite(binary(op, Term::Class(x), right));
+            }
+        }
+        if op == Opcode::Add {
+            // x * y + x * z to x * (y + z)
+            for (x, y) in operations(a, Opcode::Mul) {
+                for (other, z) in operations(b, Opcode::Mul) {
+                    if egraph.find(x) == egraph.find(other) {
+                        let sum = binary(Opcode::Add, Term::Class(y), Term::Class(z));
+                        rewrite(binary(Opcode::Mul, Term::Class(x), sum));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A value a rule found equal to a class, to add to the graph
+enum Term {
+    Class(ClassId),
+    Float(f64),
+    Op(Opcode, MirType, Box<[Term; 2]>),
+}
+
+impl Term {
+    fn add(self, egraph: &mut EGraph) -> ClassId {
+        match self {
+            Term::Class(class) => class,
+            Term::Float(value) => egraph.add(ENode::Float(value.to_bits())),
+            Term::Op(op, typ, args) => {
+                let [lhs, rhs] = *args;
+                let args = vec![lhs.add(egraph), rhs.add(egraph)];
+                egraph.add(ENode::Op { op, typ, args })
+            }
+        }
+    }
+}
+
+/// The instructions extracted for a block so far
+struct Extraction<'a> {
+    egraph: &'a EGraph,
+    /// Operands that hold the value of a class where the next instruction
+    /// goes
+    available: HashMap<ClassId, Operand>,
+    instructions: Vec<Instruction>,
+}
+
+impl Extraction<'_> {
+    /// The cheapest way to compute each class from what is available, and
+    /// its cost. Classes that cannot be computed yet are missing.
+    fn costs(&self) -> HashMap<ClassId, (u64, ENode)> {
+        let mut costs: HashMap<ClassId, (u64, ENode)> = HashMap::new();
+        let classes = self.egraph.classes();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (class, nodes) in &classes {
+                if self.available.contains_key(class) {
+                    continue;
+                }
+                for node in nodes {
+                    let ENode::Op { op, args, .. } = node else {
+                        continue;
+                    };
+                    let cost = args.iter().try_fold(op_cost(*op), |total, &arg| {
+                        let arg = self.egraph.find(arg);
+                        if self.available.contains_key(&arg) {
+                            Some(total)
+                        } else {
+                            costs.get(&arg).map(|(cost, _)| total + cost)
+                        }
+                    });
+                    if let Some(cost) = cost
+                        && costs.get(class).is_none_or(|(best, _)| cost < *best)
+                    {
+                        costs.insert(*class, (cost, node.clone()));
+                        changed = true;
+                    }
+                }
+            }
+        }
+        costs
+    }
+
+    /// Emits the instructions that compute the class, into the destination
+    /// of `root` if given and a new register otherwise. Returns the operand
+    /// that holds the value, or `None` if it cannot be computed here.
+    fn materialize(
+        &mut self,
+        function: &mut MirFunction,
+        class: ClassId,
+        root: Option<&Instruction>,
+        costs: &HashMap<ClassId, (u64, ENode)>,
+    ) -> Option<Operand> {
+        let class = self.egraph.find(class);
+        if let Some(&operand) = self.available.get(&class) {
+            let Some(root) = root else {
+                return Some(operand);
+            };
+            self.instructions.push(Instruction {
+                dest: root.dest,
+                op: Opcode::Copy,
+                typ: root.typ,
+                args: [operand].into(),
+                span: root.span,
+            });
+            return Some(Operand::Reg(root.dest));
+        }
+
+        let Some((_, ENode::Op { op, typ, args })) = costs.get(&class) else {
+            return None;
+        };
+        let mut operands = Args::new();
+        for &arg in args {
+            operands.push(self.materialize(function, arg, None, costs)?);
+        }
+        let dest = match root {
+            Some(root) => root.dest,
+            None => function.new_reg(*typ),
+        };
+        self.instructions.push(Instruction {
+            dest,
+            op: *op,
+            typ: *typ,
+            args: operands,
+            span: root.and_then(|root| root.span),
+        });
+        self.available.insert(class, Operand::Reg(dest));
+        Some(Operand::Reg(dest))
+    }
+}
+
+/// Float arithmetic the pass puts in the graph. Constants in the graph
+/// go back into the block as `ImmF64`, so `F32` arithmetic is left out.
+fn is_arithmetic(instruction: &Instruction) -> bool {
+    let operands = instruction
+        .args
+        .iter()
+        .all(|arg| matches!(arg, Operand::Reg(_) | Operand::ImmF64(_)));
+    let arity = match instruction.op {
+        Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div => 2,
+        Opcode::Copy => 1,
+        _ => return false,
+    };
+    instruction.typ.is_float()
+        && instruction.typ != MirType::F32
+        && instruction.args.len() == arity
+        && operands
+}
+
+/// Rough relative cost of an arithmetic instruction
+fn op_cost(op: Opcode) -> u64 {
+    match op {
+        Opcode::Copy => 0,
+        Opcode::Add | Opcode::Sub => 1,
+        Opcode::Mul => 3,
+        _ => 10,
+    }
+}
+
+/// Registers read by anything other than arithmetic in the block that
+/// defines them. Their values have to stay where they are.
+fn roots(function: &MirFunction) -> HashSet<Reg> {
+    let mut defined_in: HashMap<Reg, BlockId> = HashMap::new();
+    for (block_id, block) in function.arena.iter() {
+        for instruction in block.instructions.iter().filter(|i| is_arithmetic(i)) {
+            defined_in.insert(instruction.dest, block_id);
+        }
+    }
+
+    let mut roots = HashSet::new();
+    for (block_id, block) in function.arena.iter() {
+        for instruction in &block.instructions {
+            for reg in instruction.args.iter().filter_map(Operand::as_reg) {
+                if !is_arithmetic(instruction) || defined_in.get(&reg) != Some(&block_id) {
+                    roots.insert(reg);
+                }
+            }
+        }
+        let phis = block.phi_nodes.iter().flat_map(|phi| phi.incomings.iter().map(|(_, operand)| operand));
+        let edges = block.terminator.edges().into_iter().flat_map(|(_, args)| args.iter());
+        for operand in phis.chain(edges).chain(block.terminator.operands()) {
+            roots.extend(operand.as_reg());
+        }
+    }
+    roots
+}
+
+impl MirVisitor for MirSaturationPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let roots = roots(function);
+        for index in 0..function.arena.len() {
+            if self.saturate_block(function, BlockId::new(index), &roots) {
+                self.rewritten += 1;
+            }
+        }
+    }
+}
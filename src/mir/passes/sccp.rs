@@ -0,0 +1,141 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::sccp::ConditionalConstants;
+use crate::mir::cfg;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, MirFunction, MirProgram, Operand, Terminator};
+
+/// Replaces every register that always holds the same constant with the
+/// constant, and removes the code that can never run, with
+/// [`ConditionalConstants`]
+///
+/// The instructions and phis computing a constant are removed, branches
+/// and switches on one jump straight to the block they take, and the
+/// blocks that leaves without predecessors are emptied and end in
+/// `unreachable`. Since a branch that cannot be taken contributes nothing
+/// to the phis it leads to, folding it can make further registers
+/// constant, which the analysis sees in the same run. Expects SSA form.
+pub struct MirSccpPass {
+    diagnostics: DiagnosticCollector,
+    replaced: usize,
+    folded: usize,
+    removed_blocks: usize,
+}
+
+impl MirSccpPass {
+    pub fn new() -> Self {
+        MirSccpPass {
+            diagnostics: DiagnosticCollector::new(),
+            replaced: 0,
+            folded: 0,
+            removed_blocks: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics.info(format!(
+            "Replaced {} register(s) with constants, folded {} branch(es) and removed {} unreachable block(s)",
+            self.replaced, self.folded, self.removed_blocks
+        ));
+    }
+
+    /// Replaces the registers holding constants, and removes what computes them
+    fn substitute(&mut self, function: &mut MirFunction, constants: &ConditionalConstants) {
+        let types = function.reg_types.clone();
+        let constant = |reg: usize| constants.constant(reg).map(|value| value.to_operand(types[reg]));
+        let substitute = |operand: &mut Operand| {
+            if let Some(reg) = operand.as_reg()
+                && let Some(value) = constant(reg)
+            {
+                *operand = value;
+            }
+        };
+
+        for index in 0..function.arena.len() {
+            let block = function.block_mut(BlockId::new(index));
+            let before = block.phi_nodes.len() + block.instructions.len();
+            block.phi_nodes.retain(|phi| constant(phi.dest).is_none());
+            block.instructions.retain(|instruction| constant(instruction.dest).is_none());
+            self.replaced += before - block.phi_nodes.len() - block.instructions.len();
+
+            for phi in &mut block.phi_nodes {
+                for (_, operand) in &mut phi.incomings {
+                    substitute(operand);
+                }
+            }
+            for instruction in &mut block.instructions {
+                instruction.args.iter_mut().for_each(substitute);
+            }
+            block.terminator.operands_mut().into_iter().for_each(substitute);
+            for (_, args) in block.terminator.edges_mut() {
+                args.iter_mut().for_each(substitute);
+            }
+        }
+    }
+
+    /// Replaces every branch and switch on a constant with a branch to the
+    /// block it takes
+    fn fold_branches(&mut self, function: &mut MirFunction) {
+        for index in 0..function.arena.len() {
+            let block_id = BlockId::new(index);
+            let block = function.block(block_id);
+            let (target, args, kind, reason) = match &block.terminator {
+                Terminator::BrIf { cond: Operand::ImmBool(taken), then_bb, then_args, else_bb, else_args, .. } => {
+                    let reason = format!("the condition is always {}", taken);
+                    if *taken {
+                        (*then_bb, then_args.clone(), "branch", reason)
+                    } else {
+                        (*else_bb, else_args.clone(), "branch", reason)
+                    }
+                }
+                Terminator::Switch { value, cases, default, default_args } if value.as_reg().is_none() => {
+                    match cases.iter().position(|case| case.value == *value) {
+                        Some(index) => {
+                            let case = &cases[index];
+                            (case.target, case.args.clone(), "switch", format!("it always takes case {}", index))
+                        }
+                        None => (*default, default_args.clone(), "switch", "it always takes the default".to_string()),
+                    }
+                }
+                _ => continue,
+            };
+
+            let location = block
+                .terminator_span
+                .map_or(String::new(), |span| format!(" at {}", span));
+            self.diagnostics
+                .info(format!("Folded {}{} in '{}': {}", kind, location, function.name, reason));
+            cfg::branch_to(function, block_id, target, args);
+            self.folded += 1;
+        }
+    }
+}
+
+impl Default for MirSccpPass {
+    fn default() -> Self {
+        MirSccpPass::new()
+    }
+}
+
+impl MirVisitor for MirSccpPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let constants = ConditionalConstants::compute(function);
+        self.substitute(function, &constants);
+        self.fold_branches(function);
+        self.removed_blocks += cfg::remove_unreachable_blocks(function);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, Instruction, MirFunction, MirProgram, Opcode, Operand, Terminator};
+
+/// Arms with more instructions than this are left as branches
+const MAX_ARM_INSTRUCTIONS: usize = 3;
+
+/// Replaces small branch diamonds with `Select` instructions
+///
+/// A conditional branch whose arms only compute a few values and then meet
+/// again is flattened: the arm instructions are hoisted into the branching
+/// block and every phi of the merge block becomes a select on the branch
+/// condition. Triangles, where one edge goes straight to the merge block,
+/// are handled the same way. Arms containing calls or stores to globals are
+/// never speculated.
+/// Expects SSA form with phi nodes.
+pub struct MirSelectPass {
+    diagnostics: DiagnosticCollector,
+    converted: usize,
+}
+
+/// A conditional branch from `head` whose edges meet again at `merge`.
+/// An arm is `None` when its edge goes directly to `merge`.
+struct Diamond {
+    head: BlockId,
+    cond: Operand,
+    then_arm: Option<BlockId>,
+    else_arm: Option<BlockId>,
+    merge: BlockId,
+}
+
+impl MirSelectPass {
+    pub fn new() -> Self {
+        MirSelectPass {
+            diagnostics: DiagnosticCollector::new(),
+            converted: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics.info(format!(
+            "Converted {} branch diamond(s) to selects",
+            self.converted
+        ));
+    }
+
+    /// Returns the block an arm jumps to if it is cheap and safe to execute unconditionally
+    fn speculatable_arm(
+        &self,
+        function: &MirFunction,
+        cfg: &CFGAnalysis,
+        head: BlockId,
+        arm: BlockId,
+    ) -> Option<BlockId> {
+        let block = function.block(arm);
+        let Terminator::Br { target, args } = &block.terminator else {
+            return None;
+        };
+
+        let only_from_head = cfg.predecessors[&arm] == [head];
+        let cheap = block.instructions.len() <= MAX_ARM_INSTRUCTIONS
+            && block
+                .instructions
+                .iter()
+                .all(|instruction| !matches!(instruction.op, Opcode::Call | Opcode::Store));
+
+        (only_from_head
+            && cheap
+            && args.is_empty()
+            && block.phi_nodes.is_empty()
+            && block.params.is_empty()
+            && *target != arm
+            && *target != head)
+            .then_some(*target)
+    }
+
+    fn find_diamond(&self, function: &MirFunction, cfg: &CFGAnalysis) -> Option<Diamond> {
+        for (head, block) in function.arena.iter() {
+            let Terminator::BrIf {
+                cond,
+                then_bb,
+                then_args,
+                else_bb,
+                else_args,
+                ..
+            } = &block.terminator
+            else {
+                continue;
+            };
+            if then_bb == else_bb || !then_args.is_empty() || !else_args.is_empty() {
+                continue;
+            }
+
+            let then_target = self.speculatable_arm(function, cfg, head, *then_bb);
+            let else_target = self.speculatable_arm(function, cfg, head, *else_bb);
+            let diamond = match (then_target, else_target) {
+                (Some(then_merge), Some(else_merge)) if then_merge == else_merge => Diamond {
+                    head,
+                    cond: *cond,
+                    then_arm: Some(*then_bb),
+                    else_arm: Some(*else_bb),
+                    merge: then_merge,
+                },
+                (Some(then_merge), _) if then_merge == *else_bb => Diamond {
+                    head,
+                    cond: *cond,
+                    then_arm: Some(*then_bb),
+                    else_arm: None,
+                    merge: then_merge,
+                },
+                (_, Some(else_merge)) if else_merge == *then_bb => Diamond {
+                    head,
+                    cond: *cond,
+                    then_arm: None,
+                    else_arm: Some(*else_bb),
+                    merge: else_merge,
+                },
+                _ => continue,
+            };
+
+            // Every phi needs a value from both arms to select between
+            let then_pred = diamond.then_arm.unwrap_or(head);
+            let else_pred = diamond.else_arm.unwrap_or(head);
+            let complete =
+                function.block(diamond.merge).phi_nodes.iter().all(|phi| {
+                    phi.incoming(then_pred).is_some() && phi.incoming(else_pred).is_some()
+                });
+            if complete {
+                return Some(diamond);
+            }
+        }
+        None
+    }
+
+    fn flatten(&mut self, function: &mut MirFunction, diamond: Diamond) {
+        let Diamond {
+            head,
+            cond,
+            then_arm,
+            else_arm,
+            merge,
+        } = diamond;
+
+        // Hoist the arm instructions; in SSA they cannot clobber anything
+        for arm in [then_arm, else_arm].into_iter().flatten() {
+            let hoisted = std::mem::take(&mut function.block_mut(arm).instructions);
+            function.block_mut(arm).terminator = Terminator::Unreachable;
+            function.block_mut(head).instructions.extend(hoisted);
+        }
+
+        let then_pred = then_arm.unwrap_or(head);
+        let else_pred = else_arm.unwrap_or(head);
+        let mut phis = std::mem::take(&mut function.block_mut(merge).phi_nodes);
+        let span = function.block(head).terminator_span;
+        for phi in &mut phis {
+            let if_true = phi.incoming(then_pred).copied().unwrap();
+            let if_false = phi.incoming(else_pred).copied().unwrap();
+            let value = if if_true.is_identical(&if_false) {
+                if_true
+            } else {
+                let dest = function.new_reg(phi.typ);
+                function.block_mut(head).instructions.push(Instruction {
+                    dest,
+                    op: Opcode::Select,
+                    typ: phi.typ,
+                    args: [cond, if_true, if_false].into(),
+                    span,
+                });
+                Operand::Reg(dest)
+            };
+            phi.incomings
+                .retain(|(pred, _)| *pred != then_pred && *pred != else_pred);
+            phi.incomings.push((head, value));
+            phi.incomings.sort_by_key(|(pred, _)| *pred);
+        }
+
+        // Phis left with a single incoming value are plain copies
+        if phis.iter().all(|phi| phi.incomings.len() == 1) {
+            let copies = phis.drain(..).map(|phi| Instruction {
+                dest: phi.dest,
+                op: Opcode::Copy,
+                typ: phi.typ,
+                args: [phi.incomings[0].1].into(),
+                span,
+            });
+            function.block_mut(merge).instructions.splice(0..0, copies);
+        }
+        function.block_mut(merge).phi_nodes = phis;
+
+        function.block_mut(head).terminator = Terminator::br(merge);
+        self.converted += 1;
+    }
+}
+
+impl Default for MirSelectPass {
+    fn default() -> Self {
+        MirSelectPass::new()
+    }
+}
+
+impl MirVisitor for MirSelectPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        // Flattening one diamond can turn an enclosing one into a candidate
+        loop {
+            let cfg = CFGAnalysis::new(function);
+            let Some(diamond) = self.find_diamond(function, &cfg) else {
+                break;
+            };
+            self.flatten(function, diamond);
+        }
+    }
+}
@@ -1,11 +1,21 @@
 use crate::diagnostics::DiagnosticCollector;
 use crate::mir::cfg::CFGAnalysis;
 use crate::mir::visitor::MirVisitor;
-use crate::mir::{BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Operand, Terminator};
-use std::collections::HashMap;
-use std::collections::HashSet;
+use crate::mir::{BlockId, MirFunction, MirProgram, Operand, PhiNode, Reg};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 /// Converts MIR to SSA Form
+///
+/// Phi nodes are placed on the iterated dominance frontier of every register
+/// that is assigned more than once (semi-pruned: only registers that are
+/// live across a block boundary get phis), after which registers are renamed
+/// along the dominator tree so that every register has a single definition.
+/// The value a register holds on function entry keeps its original number.
+///
+/// Lowering gives every local a register rather than a stack slot, so this
+/// pass does the work of mem2reg for locals. Globals are the only memory,
+/// which [`super::promotion::MirPromotionPass`] keeps in registers where it
+/// can once the program is in SSA form.
 pub struct MirSSAPass {
     diagnostics: DiagnosticCollector,
 }
@@ -69,6 +79,164 @@ impl MirSSAPass {
         }
         dom
     }
+
+    /// Blocks reachable from the entry block
+    pub fn compute_reachable(&self, function: &MirFunction, cfg: &CFGAnalysis) -> HashSet<BlockId> {
+        let mut reachable = HashSet::from([function.entry]);
+        let mut worklist = vec![function.entry];
+        while let Some(block) = worklist.pop() {
+            for &succ in &cfg.successors[&block] {
+                if reachable.insert(succ) {
+                    worklist.push(succ);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// The immediate dominator of every reachable block except the entry.
+    /// It is the strict dominator that is dominated by all other strict dominators,
+    /// i.e. the one with the largest dominator set of its own.
+    pub fn compute_immediate_dominators(
+        &self,
+        dominators: &HashMap<BlockId, HashSet<BlockId>>,
+        reachable: &HashSet<BlockId>,
+    ) -> HashMap<BlockId, BlockId> {
+        let mut idom = HashMap::new();
+        for &node in reachable {
+            let immediate = dominators[&node]
+                .iter()
+                .filter(|&&d| d != node)
+                .max_by_key(|&&d| dominators[&d].len());
+            if let Some(&immediate) = immediate {
+                idom.insert(node, immediate);
+            }
+        }
+        idom
+    }
+
+    /// Dominance frontiers (Cooper, Harvey & Kennedy)
+    pub fn compute_dominance_frontiers(
+        &self,
+        cfg: &CFGAnalysis,
+        idom: &HashMap<BlockId, BlockId>,
+        reachable: &HashSet<BlockId>,
+    ) -> HashMap<BlockId, BTreeSet<BlockId>> {
+        let mut frontiers: HashMap<BlockId, BTreeSet<BlockId>> =
+            reachable.iter().map(|&b| (b, BTreeSet::new())).collect();
+
+        for &node in reachable {
+            let preds: Vec<BlockId> = cfg.predecessors[&node]
+                .iter()
+                .copied()
+                .filter(|p| reachable.contains(p))
+                .collect();
+            if preds.len() < 2 {
+                continue;
+            }
+            for pred in preds {
+                let mut runner = pred;
+                while Some(&runner) != idom.get(&node) {
+                    frontiers.get_mut(&runner).unwrap().insert(node);
+                    match idom.get(&runner) {
+                        Some(&next) => runner = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+impl Default for MirSSAPass {
+    fn default() -> Self {
+        MirSSAPass::new()
+    }
+}
+
+/// Per-function state for placing phis and renaming registers
+struct SsaBuilder<'a> {
+    cfg: &'a CFGAnalysis,
+    /// Children of each block in the dominator tree
+    dom_children: HashMap<BlockId, Vec<BlockId>>,
+    /// Registers that are rewritten into SSA form
+    renamed: BTreeSet<Reg>,
+    /// Original register of each phi, in the same order as the block's phi nodes
+    phi_vars: HashMap<BlockId, Vec<Reg>>,
+    /// Current name of each renamed register
+    stacks: HashMap<Reg, Vec<Reg>>,
+}
+
+impl SsaBuilder<'_> {
+    /// The current SSA name for a register
+    fn current(&self, reg: Reg) -> Reg {
+        self.stacks
+            .get(&reg)
+            .and_then(|stack| stack.last().copied())
+            .unwrap_or(reg)
+    }
+
+    fn rewrite_use(&self, operand: &mut Operand) {
+        if let Operand::Reg(reg) = operand {
+            *reg = self.current(*reg);
+        }
+    }
+
+    /// Gives a definition of `reg` a fresh name and makes it current
+    fn define(&mut self, function: &mut MirFunction, reg: Reg, pushed: &mut Vec<Reg>) -> Reg {
+        let fresh = function.new_reg(function.reg_types[reg]);
+        self.stacks.entry(reg).or_default().push(fresh);
+        pushed.push(reg);
+        fresh
+    }
+
+    fn rename_block(&mut self, function: &mut MirFunction, block_id: BlockId) {
+        let mut pushed = Vec::new();
+
+        let phi_vars = self.phi_vars.get(&block_id).cloned().unwrap_or_default();
+        for (index, var) in phi_vars.into_iter().enumerate() {
+            let fresh = self.define(function, var, &mut pushed);
+            function.block_mut(block_id).phi_nodes[index].dest = fresh;
+        }
+
+        let mut instructions = std::mem::take(&mut function.block_mut(block_id).instructions);
+        for instruction in &mut instructions {
+            for arg in &mut instruction.args {
+                self.rewrite_use(arg);
+            }
+            if self.renamed.contains(&instruction.dest) {
+                instruction.dest = self.define(function, instruction.dest, &mut pushed);
+            }
+        }
+        function.block_mut(block_id).instructions = instructions;
+
+        for operand in function.block_mut(block_id).terminator.operands_mut() {
+            self.rewrite_use(operand);
+        }
+
+        // Fill in the incoming values this block passes to its successors' phis
+        for succ in &self.cfg.successors[&block_id] {
+            let Some(vars) = self.phi_vars.get(succ) else {
+                continue;
+            };
+            for (index, &var) in vars.iter().enumerate() {
+                let value = Operand::Reg(self.current(var));
+                function.block_mut(*succ).phi_nodes[index]
+                    .incomings
+                    .push((block_id, value));
+            }
+        }
+
+        let children = self.dom_children.get(&block_id).cloned().unwrap_or_default();
+        for child in children {
+            self.rename_block(function, child);
+        }
+
+        for var in pushed {
+            self.stacks.get_mut(&var).unwrap().pop();
+        }
+    }
 }
 
 impl MirVisitor for MirSSAPass {
@@ -89,8 +257,113 @@ impl MirVisitor for MirSSAPass {
     fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
         let cfg = CFGAnalysis::new(function);
         let dominators = self.compute_dominators(function, &cfg);
-        for (b, s) in dominators {
-            println!("{:?}; {:?}", b, s);
+        let reachable = self.compute_reachable(function, &cfg);
+        let idom = self.compute_immediate_dominators(&dominators, &reachable);
+        let frontiers = self.compute_dominance_frontiers(&cfg, &idom, &reachable);
+
+        // Collect definition sites and registers used before being defined in a block
+        let mut def_blocks: HashMap<Reg, BTreeSet<BlockId>> = HashMap::new();
+        let mut def_counts: HashMap<Reg, usize> = HashMap::new();
+        let mut live_across: BTreeSet<Reg> = BTreeSet::new();
+        for (block_id, block) in function.arena.iter() {
+            if !reachable.contains(&block_id) {
+                continue;
+            }
+            let mut defined = HashSet::new();
+            let mut note_use = |operand: &Operand, defined: &HashSet<Reg>| {
+                if let Operand::Reg(reg) = operand
+                    && !defined.contains(reg)
+                {
+                    live_across.insert(*reg);
+                }
+            };
+            for instruction in &block.instructions {
+                for arg in &instruction.args {
+                    note_use(arg, &defined);
+                }
+                defined.insert(instruction.dest);
+                def_blocks.entry(instruction.dest).or_default().insert(block_id);
+                *def_counts.entry(instruction.dest).or_default() += 1;
+            }
+            for operand in block.terminator.operands() {
+                note_use(operand, &defined);
+            }
+        }
+
+        // Parameters are defined on entry
+        for (reg, _) in &function.params {
+            *def_counts.entry(*reg).or_default() += 1;
+        }
+
+        let renamed: BTreeSet<Reg> = def_blocks
+            .keys()
+            .copied()
+            .filter(|reg| def_counts[reg] > 1 || live_across.contains(reg))
+            .collect();
+
+        // Place phis on the iterated dominance frontier of each register's definitions
+        let mut phi_vars: HashMap<BlockId, Vec<Reg>> = HashMap::new();
+        for &reg in renamed.intersection(&live_across) {
+            let typ = function.reg_types[reg];
+            let mut worklist: Vec<BlockId> = def_blocks[&reg].iter().copied().collect();
+            worklist.push(function.entry);
+            let mut has_phi: HashSet<BlockId> = HashSet::new();
+            while let Some(block) = worklist.pop() {
+                for &frontier in &frontiers[&block] {
+                    if has_phi.insert(frontier) {
+                        function.block_mut(frontier).phi_nodes.push(PhiNode {
+                            dest: reg,
+                            typ,
+                            incomings: Vec::new(),
+                        });
+                        phi_vars.entry(frontier).or_default().push(reg);
+                        worklist.push(frontier);
+                    }
+                }
+            }
+        }
+
+        let mut dom_children: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        for (&node, &parent) in &idom {
+            dom_children.entry(parent).or_default().push(node);
+        }
+        for children in dom_children.values_mut() {
+            children.sort();
+        }
+
+        let mut builder = SsaBuilder {
+            cfg: &cfg,
+            dom_children,
+            renamed,
+            phi_vars,
+            stacks: HashMap::new(),
+        };
+        builder.rename_block(function, function.entry);
+
+        // Unreachable predecessors still need an incoming value; they keep the original register
+        let unreachable: Vec<BlockId> = function
+            .arena
+            .iter()
+            .map(|(block_id, _)| block_id)
+            .filter(|block_id| !reachable.contains(block_id))
+            .collect();
+        for block_id in unreachable {
+            for succ in &cfg.successors[&block_id] {
+                if let Some(vars) = builder.phi_vars.get(succ) {
+                    for (index, &var) in vars.iter().enumerate() {
+                        function.block_mut(*succ).phi_nodes[index]
+                            .incomings
+                            .push((block_id, Operand::Reg(var)));
+                    }
+                }
+            }
+        }
+
+        // Keep incoming values in predecessor order for stable output
+        for block_id in reachable {
+            for phi in &mut function.block_mut(block_id).phi_nodes {
+                phi.incomings.sort_by_key(|(pred, _)| *pred);
+            }
         }
     }
 }
@@ -0,0 +1,147 @@
+use crate::diagnostics::DiagnosticCollector;
+use crate::mir::analysis::constant_propagation::{ConstValue, ConstantPropagation};
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, Instruction, MirFunction, MirProgram, MirType, Opcode, Operand, Reg};
+use std::collections::BTreeMap;
+
+/// Replaces arithmetic with cheaper equivalents
+///
+/// Only rewrites that give bit-identical results are made:
+/// - `x * 2` becomes `x + x`, and `x * 1` a copy of `x`
+/// - `x / c` becomes `x * (1 / c)` when `c` is a power of two, so the
+///   reciprocal is exact; `x / 1` becomes a copy of `x`
+///
+/// Operands count as constants if constant propagation can prove them
+/// constant, which catches values that only became known after lowering.
+/// Expects SSA form.
+pub struct MirStrengthReductionPass {
+    diagnostics: DiagnosticCollector,
+    reduced: usize,
+}
+
+impl MirStrengthReductionPass {
+    pub fn new() -> Self {
+        MirStrengthReductionPass {
+            diagnostics: DiagnosticCollector::new(),
+            reduced: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics
+            .info(format!("Strength reduced {} instruction(s)", self.reduced));
+    }
+
+    /// Rewrites a multiplication or division, returning true if it changed
+    fn reduce(instruction: &mut Instruction, constants: &BTreeMap<Reg, ConstValue>) -> bool {
+        let value = |operand: &Operand| constant(operand, constants);
+        let [lhs, rhs] = instruction.args.as_slice() else {
+            return false;
+        };
+        let (lhs, rhs) = (*lhs, *rhs);
+
+        match instruction.op {
+            Opcode::Mul => {
+                // Multiplication is commutative, look for the constant on either side
+                let (other, factor) = match (value(&lhs), value(&rhs)) {
+                    (_, Some(factor)) => (lhs, factor),
+                    (Some(factor), None) => (rhs, factor),
+                    _ => return false,
+                };
+                if is_number(factor, 2.0) {
+                    instruction.op = Opcode::Add;
+                    instruction.args = [other, other].into();
+                } else if is_number(factor, 1.0) {
+                    instruction.op = Opcode::Copy;
+                    instruction.args = [other].into();
+                } else {
+                    return false;
+                }
+                true
+            }
+            Opcode::Div => match value(&rhs) {
+                Some(divisor) if is_number(divisor, 1.0) => {
+                    instruction.op = Opcode::Copy;
+                    instruction.args = [lhs].into();
+                    true
+                }
+                Some(ConstValue::Float(divisor))
+                    if has_exact_reciprocal(divisor, instruction.typ) =>
+                {
+                    instruction.op = Opcode::Mul;
+                    instruction.args = [lhs, Operand::float(1.0 / divisor, instruction.typ)].into();
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Default for MirStrengthReductionPass {
+    fn default() -> Self {
+        MirStrengthReductionPass::new()
+    }
+}
+
+fn constant(operand: &Operand, constants: &BTreeMap<Reg, ConstValue>) -> Option<ConstValue> {
+    match operand {
+        Operand::Reg(reg) => constants.get(reg).copied(),
+        Operand::ImmI64(value) => Some(ConstValue::Int(*value)),
+        Operand::ImmF64(value) => Some(ConstValue::Float(*value)),
+        Operand::ImmF32(value) => Some(ConstValue::Float(*value as f64)),
+        Operand::ImmBool(_) | Operand::Label(_) | Operand::Global(_) => None,
+    }
+}
+
+fn is_number(value: ConstValue, expected: f64) -> bool {
+    match value {
+        ConstValue::Float(value) => value == expected,
+        ConstValue::Int(value) => value as f64 == expected,
+        ConstValue::Bool(_) => false,
+    }
+}
+
+/// Returns true if `1 / divisor` is exactly representable in the given type,
+/// which is the case for powers of two whose reciprocal is a normal number
+fn has_exact_reciprocal(divisor: f64, typ: MirType) -> bool {
+    let max_exponent = match typ {
+        MirType::F64 => 1022,
+        MirType::F32 => 126,
+        _ => return false,
+    };
+    const MANTISSA_MASK: u64 = (1 << 52) - 1;
+    let is_power_of_two = divisor.is_normal() && divisor.to_bits() & MANTISSA_MASK == 0;
+    is_power_of_two && (divisor.abs().log2() as i32).abs() <= max_exponent
+}
+
+impl MirVisitor for MirStrengthReductionPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let constants = ConstantPropagation::constants(function);
+
+        for index in 0..function.arena.len() {
+            let block = function.block_mut(BlockId::new(index));
+            for instruction in &mut block.instructions {
+                if Self::reduce(instruction, &constants) {
+                    self.reduced += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::diagnostics::{Diagnostic, DiagnosticCollector, Lint};
+use crate::mir::analysis::value_range::{RangeAnalysis, RangeFacts, arithmetic, range, truth, type_range};
+use crate::mir::cfg;
+use crate::mir::visitor::MirVisitor;
+use crate::mir::{BlockId, MirFunction, MirProgram, MirType, Opcode, Terminator};
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+
+/// Folds branches that [`RangeAnalysis`] decides and warns about integer
+/// arithmetic that overflows whatever its operands are
+///
+/// A branch is folded when the range of its condition is a single value,
+/// as for `x > 0` past a check of `x < 1`, or when its target can be
+/// reached no other way than an edge the analysis ruled out. Blocks nothing
+/// branches to any more are then emptied and end in `unreachable`. This
+/// also removes runtime checks that cannot fail, such as the NaN checks of
+/// `--checks` on arithmetic whose operands are finite and cannot combine
+/// into a NaN, and the division by zero check of a divisor that is never
+/// zero. The language has no arrays yet, so there are no bounds checks to
+/// remove. Expects SSA form.
+pub struct MirValueRangePass {
+    diagnostics: DiagnosticCollector,
+    folded: usize,
+    removed_blocks: usize,
+}
+
+impl MirValueRangePass {
+    pub fn new() -> Self {
+        MirValueRangePass {
+            diagnostics: DiagnosticCollector::new(),
+            folded: 0,
+            removed_blocks: 0,
+        }
+    }
+
+    pub fn run(&mut self, program: &mut MirProgram) {
+        self.visit_program(program);
+        self.diagnostics.info(format!(
+            "Folded {} branch(es) and removed {} unreachable block(s)",
+            self.folded, self.removed_blocks
+        ));
+    }
+
+    /// Warns about every integer `Add`, `Sub`, `Mul`, `Div` and `Mod` whose
+    /// result is out of the range of its type for all of its operands
+    fn check_overflow(&mut self, function: &MirFunction, entry_facts: &[(BlockId, RangeFacts)]) {
+        for (block_id, facts) in entry_facts {
+            let Some(facts) = facts else {
+                continue;
+            };
+            let mut facts = facts.clone();
+            for instruction in &function.block(*block_id).instructions {
+                let noun = match instruction.op {
+                    Opcode::Add => "addition",
+                    Opcode::Sub => "subtraction",
+                    Opcode::Mul => "multiplication",
+                    Opcode::Div => "division",
+                    Opcode::Mod => "remainder",
+                    _ => "",
+                };
+                if !noun.is_empty() && instruction.typ.is_integer() {
+                    let (lhs, rhs) = (range(&facts, &instruction.args[0]), range(&facts, &instruction.args[1]));
+                    let exact = arithmetic(instruction.op, lhs, rhs);
+                    let bounds = type_range(instruction.typ);
+                    if !exact.is_empty() && (exact.min > bounds.max || exact.max < bounds.min) {
+                        self.warn_overflow(function, noun, instruction.typ, instruction.span);
+                    }
+                }
+                RangeAnalysis::step(instruction, &mut facts);
+            }
+        }
+    }
+
+    fn warn_overflow(&mut self, function: &MirFunction, noun: &str, typ: MirType, span: Option<Span>) {
+        let location = span.map_or(String::new(), |span| format!(" at {}", span));
+        let mut warning = Diagnostic::new(format!(
+            "Integer {}{} in '{}' always overflows {:?}",
+            noun, location, function.name, typ
+        ))
+        .with_lint(Lint::IntegerOverflow);
+        if let Some(span) = span {
+            warning = warning.with_location(function.source.clone(), span);
+        }
+        self.diagnostics.report_warning(warning);
+    }
+
+    /// Replaces every decided `BrIf` with a branch to the target it takes
+    fn fold_branches(&mut self, function: &mut MirFunction, entry_facts: &[(BlockId, RangeFacts)], exit_facts: &[(BlockId, RangeFacts)]) {
+        let unreached: HashSet<BlockId> = entry_facts
+            .iter()
+            .filter(|(_, facts)| facts.is_none())
+            .map(|(block_id, _)| *block_id)
+            .collect();
+
+        for (block_id, facts) in exit_facts {
+            let Some(facts) = facts else {
+                continue;
+            };
+            let block = function.block(*block_id);
+            let Terminator::BrIf { cond, then_bb, then_args, else_bb, else_args, .. } = &block.terminator else {
+                continue;
+            };
+            let taken = match truth(range(facts, cond)) {
+                Some(taken) => taken,
+                None if then_bb == else_bb => continue,
+                None => match (unreached.contains(then_bb), unreached.contains(else_bb)) {
+                    (true, false) => false,
+                    (false, true) => true,
+                    _ => continue,
+                },
+            };
+            let (target, args) = if taken {
+                (*then_bb, then_args.clone())
+            } else {
+                (*else_bb, else_args.clone())
+            };
+
+            let location = block
+                .terminator_span
+                .map_or(String::new(), |span| format!(" at {}", span));
+            self.diagnostics.info(format!(
+                "Folded branch{} in '{}': the condition is always {}",
+                location, function.name, taken
+            ));
+            cfg::branch_to(function, *block_id, target, args);
+            self.folded += 1;
+        }
+    }
+}
+
+impl Default for MirValueRangePass {
+    fn default() -> Self {
+        MirValueRangePass::new()
+    }
+}
+
+impl MirVisitor for MirValueRangePass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_program(&mut self, program: &mut MirProgram) -> Self::Output {
+        self.walk_program(program);
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        let results = RangeAnalysis::compute(function);
+        // In block order, so diagnostics come out in the same order every time
+        let in_order = |facts: &HashMap<BlockId, RangeFacts>| {
+            let mut facts: Vec<_> = facts.iter().map(|(block_id, facts)| (*block_id, facts.clone())).collect();
+            facts.sort_by_key(|(block_id, _)| *block_id);
+            facts
+        };
+        let entry_facts = in_order(&results.block_entry);
+        let exit_facts = in_order(&results.block_exit);
+
+        self.check_overflow(function, &entry_facts);
+        self.fold_branches(function, &entry_facts, &exit_facts);
+        self.removed_blocks += cfg::remove_unreachable_blocks(function);
+    }
+}
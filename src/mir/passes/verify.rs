@@ -0,0 +1,398 @@
+use crate::diagnostics::{Diagnostic, DiagnosticCollector};
+use crate::mir::visitor::MirVisitor;
+use crate::mir::cfg::CFGAnalysis;
+use crate::mir::{
+    BlockId, FunctionTable, GlobalId, Instruction, Linkage, MirFunction, MirProgram, MirType, Opcode,
+    Operand, PhiNode, Terminator,
+};
+use crate::span::Span;
+use std::collections::HashMap;
+
+/// Checks that MIR is well-formed: instruction types agree with the
+/// function's register type table and with the types of their operands
+pub struct MirVerifierPass {
+    diagnostics: DiagnosticCollector,
+    forbid_phis: bool,
+    /// Type of every global of the program being verified
+    globals: HashMap<GlobalId, MirType>,
+    /// Signatures calls are checked against
+    symbols: FunctionTable,
+    /// Source span of the instruction or terminator being verified, if known
+    span: Option<Span>,
+}
+
+impl MirVerifierPass {
+    pub fn new() -> Self {
+        MirVerifierPass {
+            diagnostics: DiagnosticCollector::new(),
+            forbid_phis: false,
+            globals: HashMap::new(),
+            symbols: FunctionTable::new(),
+            span: None,
+        }
+    }
+
+    /// Additionally require that no phi nodes or block parameters remain, as after SSA destruction
+    pub fn forbid_phis(mut self) -> Self {
+        self.forbid_phis = true;
+        self
+    }
+
+    pub fn verify(&mut self, program: &mut MirProgram) {
+        self.globals = program
+            .globals
+            .iter()
+            .map(|global| (global.name, global.typ))
+            .collect();
+        self.symbols = program.symbols.clone();
+        self.visit_program(program);
+    }
+
+    /// Reports a failure in `function`, located at the source of the
+    /// instruction or terminator being verified if it has one
+    fn fail(&mut self, function: &MirFunction, message: String) {
+        let error = match self.span {
+            Some(span) => Diagnostic::new(format!(
+                "MIR verification failed in '{}' at {}: {}",
+                function.name, span, message
+            ))
+            .with_location(function.source.clone(), span),
+            None => Diagnostic::new(format!(
+                "MIR verification failed in '{}': {}",
+                function.name, message
+            )),
+        };
+        self.diagnostics.report_error(error);
+    }
+
+    /// Checks that a register operand has the expected type
+    fn check_operand(&mut self, function: &MirFunction, operand: &Operand, expected: MirType, context: &str) {
+        if let Operand::Reg(reg) = operand
+            && let Some(actual) = function.reg_type(*reg)
+            && actual != expected
+        {
+            self.fail(function, format!(
+                "{} uses r{} of type {:?}, expected {:?}",
+                context, reg, actual, expected
+            ));
+        }
+        if operand.immediate_fits(expected) == Some(false) {
+            self.fail(function, format!(
+                "{} uses immediate {:?}, expected {:?}",
+                context, operand, expected
+            ));
+        }
+    }
+
+    fn verify_instruction(&mut self, function: &MirFunction, instruction: &Instruction) {
+        let context = format!("{:?} into r{}", instruction.op, instruction.dest);
+
+        // The destination register must hold the type the instruction produces
+        match function.reg_type(instruction.dest) {
+            Some(dest_type) if dest_type != instruction.typ => {
+                self.fail(function, format!(
+                    "{} produces {:?} but r{} has type {:?}",
+                    context, instruction.typ, instruction.dest, dest_type
+                ));
+            }
+            None if instruction.typ != MirType::Void => {
+                self.fail(function, format!(
+                    "{} writes to r{} which has no type",
+                    context, instruction.dest
+                ));
+            }
+            _ => {}
+        }
+
+        match instruction.op {
+            // Arithmetic and copies operate on values of the result type
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod | Opcode::Copy => {
+                for arg in &instruction.args {
+                    self.check_operand(function, arg, instruction.typ, &context);
+                }
+            }
+            // Powers are only defined for floats
+            Opcode::Pow => {
+                if !instruction.typ.is_float() {
+                    self.fail(function, format!(
+                        "{} must produce a float, found {:?}",
+                        context, instruction.typ
+                    ));
+                }
+                for arg in &instruction.args {
+                    self.check_operand(function, arg, instruction.typ, &context);
+                }
+            }
+            // Comparisons produce a bool from two operands of the same type
+            Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                if instruction.typ != MirType::I1 {
+                    self.fail(function, format!(
+                        "{} must produce I1, found {:?}",
+                        context, instruction.typ
+                    ));
+                }
+                let operand_types: Vec<MirType> = instruction
+                    .args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        Operand::Reg(reg) => function.reg_type(*reg),
+                        _ => None,
+                    })
+                    .collect();
+                if operand_types.windows(2).any(|pair| pair[0] != pair[1]) {
+                    self.fail(function, format!(
+                        "{} compares operands of different types {:?}",
+                        context, operand_types
+                    ));
+                }
+                // Immediates must be of the type of the registers they are compared with
+                if let Some(&compared) = operand_types.first() {
+                    for arg in &instruction.args {
+                        self.check_operand(function, arg, compared, &context);
+                    }
+                }
+            }
+            Opcode::Select => {
+                if instruction.args.len() != 3 {
+                    self.fail(function, format!(
+                        "{} takes 3 operands, found {}",
+                        context,
+                        instruction.args.len()
+                    ));
+                    return;
+                }
+                self.check_operand(function, &instruction.args[0], MirType::I1, &context);
+                for arg in &instruction.args[1..] {
+                    self.check_operand(function, arg, instruction.typ, &context);
+                }
+            }
+            // Conversions take one float and produce a float of another type
+            Opcode::Convert => {
+                let is_float = |typ: Option<MirType>| {
+                    matches!(typ, Some(MirType::F8 | MirType::F16 | MirType::F32 | MirType::F64))
+                };
+                let operand_type = match instruction.args.as_slice() {
+                    [Operand::Reg(reg)] => function.reg_type(*reg),
+                    [Operand::ImmF64(_)] => Some(MirType::F64),
+                    [Operand::ImmF32(_)] => Some(MirType::F32),
+                    _ => None,
+                };
+                if !is_float(Some(instruction.typ)) || !is_float(operand_type) {
+                    self.fail(function, format!(
+                        "{} must convert one float to a float type",
+                        context
+                    ));
+                }
+            }
+            // Calls name a declared function and pass it arguments of its parameter types
+            Opcode::Call => {
+                let Some(Operand::Label(callee)) = instruction.args.first() else {
+                    self.fail(function, format!("{} has no callee", context));
+                    return;
+                };
+                let Some(signature) = self.symbols.get(*callee).cloned() else {
+                    self.fail(function, format!(
+                        "{} calls unknown function '{}'",
+                        context, callee
+                    ));
+                    return;
+                };
+                let args = &instruction.args[1..];
+                if args.len() != signature.params.len() {
+                    self.fail(function, format!(
+                        "{} passes {} argument(s) to '{}', which takes {}",
+                        context,
+                        args.len(),
+                        callee,
+                        signature.params.len()
+                    ));
+                    return;
+                }
+                for (arg, param) in args.iter().zip(&signature.params) {
+                    self.check_operand(function, arg, *param, &context);
+                }
+                if instruction.typ != signature.return_type {
+                    self.fail(function, format!(
+                        "{} produces {:?} but '{}' returns {:?}",
+                        context, instruction.typ, callee, signature.return_type
+                    ));
+                }
+            }
+            // Loads and stores name a global of the program and move values of its type
+            Opcode::Load | Opcode::Store => {
+                let global_type = match instruction.args.first() {
+                    Some(Operand::Global(global)) => self.globals.get(global).copied(),
+                    _ => None,
+                };
+                let Some(global_type) = global_type else {
+                    self.fail(function, format!(
+                        "{} does not name a global of the program",
+                        context
+                    ));
+                    return;
+                };
+                if instruction.op == Opcode::Load {
+                    if instruction.args.len() != 1 || instruction.typ != global_type {
+                        self.fail(function, format!(
+                            "{} must load one {:?} global",
+                            context, global_type
+                        ));
+                    }
+                    return;
+                }
+                let [_, value] = instruction.args.as_slice() else {
+                    self.fail(function, format!(
+                        "{} takes a global and a value",
+                        context
+                    ));
+                    return;
+                };
+                if instruction.typ != MirType::Void {
+                    self.fail(function, format!(
+                        "{} must produce Void, found {:?}",
+                        context, instruction.typ
+                    ));
+                }
+                self.check_operand(function, value, global_type, &context);
+            }
+        }
+    }
+
+    /// Checks a phi's types and that it has exactly one incoming value per predecessor
+    fn verify_phi(&mut self, function: &MirFunction, block_id: BlockId, phi: &PhiNode, preds: &[BlockId]) {
+        let context = format!("Phi into r{}", phi.dest);
+
+        if function.reg_type(phi.dest) != Some(phi.typ) {
+            self.fail(function, format!(
+                "{} produces {:?} but r{} has type {:?}",
+                context, phi.typ, phi.dest, function.reg_type(phi.dest)
+            ));
+        }
+
+        for (_, operand) in &phi.incomings {
+            self.check_operand(function, operand, phi.typ, &context);
+        }
+
+        let mut incoming_blocks: Vec<BlockId> = phi.incomings.iter().map(|(b, _)| *b).collect();
+        let mut expected_blocks = preds.to_vec();
+        incoming_blocks.sort();
+        expected_blocks.sort();
+        if incoming_blocks != expected_blocks {
+            self.fail(function, format!(
+                "{} in block{} has incomings from {:?}, expected one per predecessor {:?}",
+                context, block_id.index(), incoming_blocks, expected_blocks
+            ));
+        }
+    }
+
+    fn verify_terminator(&mut self, function: &MirFunction, terminator: &Terminator) {
+        // Every edge passes exactly one argument of the right type per block parameter
+        for (target, args) in terminator.edges() {
+            let params = &function.block(target).params;
+            if args.len() != params.len() {
+                self.fail(function, format!(
+                    "branch to block{} passes {} argument(s), expected {}",
+                    target.index(),
+                    args.len(),
+                    params.len()
+                ));
+                continue;
+            }
+            for (arg, (reg, typ)) in args.iter().zip(params) {
+                let context = format!("Argument for r{} of block{}", reg, target.index());
+                self.check_operand(function, arg, *typ, &context);
+            }
+        }
+
+        match terminator {
+            Terminator::BrIf { cond, .. } => {
+                self.check_operand(function, cond, MirType::I1, "br_if condition");
+            }
+            Terminator::Switch { value, cases, .. } => {
+                let value_type = match value {
+                    Operand::Reg(reg) => function.reg_type(*reg),
+                    _ => None,
+                };
+                for (index, case) in cases.iter().enumerate() {
+                    let fits = match value_type {
+                        Some(typ) => case.value.immediate_fits(typ) == Some(true),
+                        None => case.value.immediate_fits(MirType::Void).is_some(),
+                    };
+                    if !fits {
+                        self.fail(function, format!(
+                            "switch case {:?} is not an immediate of the switched type {:?}",
+                            case.value, value_type
+                        ));
+                    }
+                    if cases[..index].iter().any(|earlier| earlier.value == case.value) {
+                        self.fail(function, format!(
+                            "switch has duplicate case {:?}",
+                            case.value
+                        ));
+                    }
+                }
+            }
+            Terminator::Ret { value: Some(value) } => {
+                self.check_operand(function, value, function.return_type, "return value");
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for MirVerifierPass {
+    fn default() -> Self {
+        MirVerifierPass::new()
+    }
+}
+
+impl MirVisitor for MirVerifierPass {
+    type Output = ();
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        &self.diagnostics
+    }
+
+    fn diagnostics_mut(&mut self) -> &mut DiagnosticCollector {
+        &mut self.diagnostics
+    }
+
+    fn visit_function(&mut self, function: &mut MirFunction) -> Self::Output {
+        self.span = None;
+        if function.linkage == Linkage::External {
+            self.fail(function, "has a body but external linkage".to_string());
+        }
+        let cfg = CFGAnalysis::new(function);
+        for (block_id, block) in function.arena.iter() {
+            self.span = None;
+            if self.forbid_phis && (!block.phi_nodes.is_empty() || !block.params.is_empty()) {
+                self.fail(function, format!(
+                    "block{} still has {} phi node(s) and {} parameter(s) after SSA destruction",
+                    block_id.index(),
+                    block.phi_nodes.len(),
+                    block.params.len()
+                ));
+            }
+            for (reg, typ) in &block.params {
+                if function.reg_type(*reg) != Some(*typ) {
+                    self.fail(function, format!(
+                        "parameter r{} of block{} is {:?} but has type {:?}",
+                        reg,
+                        block_id.index(),
+                        typ,
+                        function.reg_type(*reg)
+                    ));
+                }
+            }
+            for phi in &block.phi_nodes {
+                self.verify_phi(function, block_id, phi, &cfg.predecessors[&block_id]);
+            }
+            for instruction in &block.instructions {
+                self.span = instruction.span;
+                self.verify_instruction(function, instruction);
+            }
+            self.span = block.terminator_span;
+            self.verify_terminator(function, &block.terminator);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::mir::{BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Operand, Terminator};
+use crate::mir::{
+    BasicBlock, BlockId, Instruction, MirFunction, MirProgram, Operand, PhiNode, Terminator,
+};
 
 // Re-export DiagnosticCollector for convenience
 pub use crate::diagnostics::DiagnosticCollector;
@@ -50,6 +52,9 @@ pub trait MirVisitor {
     }
 
     fn walk_basicblock(&mut self, block: &mut BasicBlock) -> Self::Output {
+        for phi in &mut block.phi_nodes {
+            self.visit_phi(phi);
+        }
         for instruction in &mut block.instructions {
             self.visit_instruction(instruction);
         }
@@ -57,6 +62,18 @@ pub trait MirVisitor {
         Self::Output::default()
     }
 
+    // Phi node
+    fn visit_phi(&mut self, phi: &mut PhiNode) -> Self::Output {
+        self.walk_phi(phi)
+    }
+
+    fn walk_phi(&mut self, phi: &mut PhiNode) -> Self::Output {
+        for (_, operand) in &mut phi.incomings {
+            self.visit_operand(operand);
+        }
+        Self::Output::default()
+    }
+
     // Instruction
     fn visit_instruction(&mut self, instruction: &mut Instruction) -> Self::Output {
         self.walk_instruction(instruction)
@@ -75,16 +92,13 @@ pub trait MirVisitor {
     }
 
     fn walk_terminator(&mut self, terminator: &mut Terminator) -> Self::Output {
-        match terminator {
-            Terminator::BrIf { cond, .. } => {
-                self.visit_operand(cond);
-            }
-            Terminator::Ret {
-                value: Some(operand),
-            } => {
-                self.visit_operand(operand);
+        for operand in terminator.operands_mut() {
+            self.visit_operand(operand);
+        }
+        for (_, args) in terminator.edges_mut() {
+            for arg in args {
+                self.visit_operand(arg);
             }
-            _ => {}
         }
         Self::Output::default()
     }
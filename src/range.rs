@@ -0,0 +1,283 @@
+//! Intervals of numbers, shared by the value range analyses of HIR and MIR.
+
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+/// Everything a numeric value might be.
+///
+/// `min` and `max` bound the non-NaN values, infinities included. Negative
+/// zero compares equal to zero, so whether it can appear is tracked
+/// separately, as is NaN. A range with `min > max` holds no numbers at all.
+/// Booleans are described as the range `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+    pub nan: bool,
+    pub negative_zero: bool,
+}
+
+impl ValueRange {
+    /// Any value at all
+    pub const UNKNOWN: ValueRange = ValueRange {
+        min: f64::NEG_INFINITY,
+        max: f64::INFINITY,
+        nan: true,
+        negative_zero: true,
+    };
+
+    /// No value, the starting point of the fixpoint
+    pub const EMPTY: ValueRange = ValueRange {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+        nan: false,
+        negative_zero: false,
+    };
+
+    pub const BOOLEAN: ValueRange = ValueRange {
+        min: 0.0,
+        max: 1.0,
+        nan: false,
+        negative_zero: false,
+    };
+
+    pub fn constant(value: f64) -> Self {
+        if value.is_nan() {
+            return ValueRange {
+                nan: true,
+                ..Self::EMPTY
+            };
+        }
+        ValueRange {
+            min: value,
+            max: value,
+            nan: false,
+            negative_zero: value == 0.0 && value.is_sign_negative(),
+        }
+    }
+
+    /// Returns true if the value can be neither NaN nor infinite
+    pub fn is_finite(&self) -> bool {
+        !self.nan && (self.is_empty() || (self.min.is_finite() && self.max.is_finite()))
+    }
+
+    /// Returns true if the sign bit of the value is known to be clear,
+    /// which rules out NaN and negative zero as well as negative numbers
+    pub fn is_non_negative(&self) -> bool {
+        !self.nan && !self.negative_zero && (self.is_empty() || self.min >= 0.0)
+    }
+
+    /// Returns true if the range holds no numbers, though it may hold NaN
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    /// Returns true if the value may be zero of either sign
+    pub fn may_be_zero(&self) -> bool {
+        self.negative_zero || (self.min <= 0.0 && self.max >= 0.0)
+    }
+
+    /// Returns true if the value may be an infinity
+    pub fn may_be_infinite(&self) -> bool {
+        !self.is_empty() && (self.min.is_infinite() || self.max.is_infinite())
+    }
+
+    /// Smallest range containing both
+    pub fn join(self, other: ValueRange) -> ValueRange {
+        ValueRange {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            nan: self.nan || other.nan,
+            negative_zero: self.negative_zero || other.negative_zero,
+        }
+    }
+
+    /// Joins `other` and pushes every bound that moved out to infinity
+    pub fn widen(self, other: ValueRange) -> ValueRange {
+        let joined = self.join(other);
+        if self.is_empty() {
+            return joined;
+        }
+        ValueRange {
+            min: if joined.min < self.min {
+                f64::NEG_INFINITY
+            } else {
+                joined.min
+            },
+            max: if joined.max > self.max {
+                f64::INFINITY
+            } else {
+                joined.max
+            },
+            ..joined
+        }
+    }
+
+    /// The interval spanned by the given bounds, with any NaN bound (from
+    /// `inf - inf` or `0 * inf`) replaced by the whole real line
+    fn interval(bounds: [f64; 4], nan: bool, negative_zero: bool) -> ValueRange {
+        if bounds.iter().any(|bound| bound.is_nan()) {
+            return ValueRange {
+                nan,
+                negative_zero,
+                ..Self::UNKNOWN
+            };
+        }
+        ValueRange {
+            min: bounds.iter().copied().fold(f64::INFINITY, f64::min),
+            max: bounds.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            nan,
+            negative_zero,
+        }
+    }
+
+    /// Both ranges, or `None` if either holds no numbers to do arithmetic on
+    fn numbers(self, other: ValueRange) -> Option<(ValueRange, ValueRange)> {
+        (!self.is_empty() && !other.is_empty()).then_some((self, other))
+    }
+
+    /// Treats bounds beyond `limit` in magnitude as overflowed to infinity
+    pub fn saturate(self, limit: f64) -> ValueRange {
+        if self.is_empty() {
+            return self;
+        }
+        ValueRange {
+            min: if self.min < -limit {
+                f64::NEG_INFINITY
+            } else {
+                self.min
+            },
+            max: if self.max > limit {
+                f64::INFINITY
+            } else {
+                self.max
+            },
+            ..self
+        }
+    }
+}
+
+impl Neg for ValueRange {
+    type Output = ValueRange;
+
+    fn neg(self) -> Self::Output {
+        ValueRange {
+            min: -self.max,
+            max: -self.min,
+            nan: self.nan,
+            // -x is negative zero exactly when x is positive zero
+            negative_zero: !self.is_empty() && self.min <= 0.0 && self.max >= 0.0,
+        }
+    }
+}
+
+impl Add for ValueRange {
+    type Output = ValueRange;
+
+    fn add(self, other: ValueRange) -> Self::Output {
+        let nan = self.nan
+            || other.nan
+            || (self.max == f64::INFINITY && other.min == f64::NEG_INFINITY)
+            || (self.min == f64::NEG_INFINITY && other.max == f64::INFINITY);
+        // A sum is only negative zero if both sides are
+        let negative_zero = self.negative_zero && other.negative_zero;
+        match self.numbers(other) {
+            Some((a, b)) => {
+                let (min, max) = (a.min + b.min, a.max + b.max);
+                Self::interval([min, min, max, max], nan, negative_zero)
+            }
+            None => ValueRange { nan, ..Self::EMPTY },
+        }
+    }
+}
+
+impl Sub for ValueRange {
+    type Output = ValueRange;
+
+    fn sub(self, other: ValueRange) -> Self::Output {
+        // IEEE 754 defines x - y as x + (-y), signed zeros included
+        self + -other
+    }
+}
+
+impl Mul for ValueRange {
+    type Output = ValueRange;
+
+    fn mul(self, other: ValueRange) -> Self::Output {
+        let nan = self.nan
+            || other.nan
+            || (self.may_be_zero() && other.may_be_infinite())
+            || (other.may_be_zero() && self.may_be_infinite());
+        match self.numbers(other) {
+            Some((a, b)) => {
+                let mut range = Self::interval(
+                    [a.min * b.min, a.min * b.max, a.max * b.min, a.max * b.max],
+                    nan,
+                    false,
+                );
+                // A product that is negative or zero can round to negative zero
+                range.negative_zero = range.min <= 0.0 || a.negative_zero || b.negative_zero;
+                range
+            }
+            None => ValueRange { nan, ..Self::EMPTY },
+        }
+    }
+}
+
+impl Div for ValueRange {
+    type Output = ValueRange;
+
+    fn div(self, other: ValueRange) -> Self::Output {
+        let nan = self.nan
+            || other.nan
+            || (self.may_be_zero() && other.may_be_zero())
+            || (self.may_be_infinite() && other.may_be_infinite());
+        match self.numbers(other) {
+            // Dividing by something that may be zero can give any infinity
+            Some(_) if other.may_be_zero() => ValueRange {
+                nan,
+                ..Self::UNKNOWN
+            },
+            Some((a, b)) => {
+                let mut range = Self::interval(
+                    [a.min / b.min, a.min / b.max, a.max / b.min, a.max / b.max],
+                    nan,
+                    false,
+                );
+                range.negative_zero = range.min <= 0.0 || a.negative_zero;
+                range
+            }
+            None => ValueRange { nan, ..Self::EMPTY },
+        }
+    }
+}
+
+impl Rem for ValueRange {
+    type Output = ValueRange;
+
+    fn rem(self, other: ValueRange) -> Self::Output {
+        let nan = self.nan || other.nan || other.may_be_zero() || self.may_be_infinite();
+        match self.numbers(other) {
+            Some((a, b)) => {
+                // The result has the sign of the dividend and is smaller in
+                // magnitude than the divisor
+                let bound = b.min.abs().max(b.max.abs());
+                if a.is_non_negative() {
+                    ValueRange {
+                        min: 0.0,
+                        max: bound.min(a.max),
+                        nan,
+                        negative_zero: false,
+                    }
+                } else {
+                    ValueRange {
+                        min: -bound,
+                        max: bound,
+                        nan,
+                        negative_zero: true,
+                    }
+                }
+            }
+            None => ValueRange { nan, ..Self::EMPTY },
+        }
+    }
+}
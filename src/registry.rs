@@ -0,0 +1,196 @@
+//! Passes registered by name, for custom pipelines.
+//!
+//! The optimizations of `-O1` are registered in every [`PassRegistry`]
+//! made with [`PassRegistry::new`], and crates using Iris as a library
+//! register their own next to them. `--passes`, or
+//! [`crate::compiler::Compiler::set_passes`], then picks the passes to run
+//! and their order in place of the default pipeline:
+//!
+//! - HIR passes run on the AST before it is typechecked, where
+//!   [`DEFAULT_HIR_PASSES`] run
+//! - MIR passes run on SSA form, where [`DEFAULT_MIR_PASSES`] run, and the
+//!   verifier checks what they leave
+
+use crate::ast::Program;
+use crate::diagnostics::DiagnosticCollector;
+use crate::hir::passes::ast_simplification::ASTSimplificationPass;
+use crate::hir::passes::cse::HirCsePass;
+use crate::hir::visitor::Visitor;
+use crate::mir::MirProgram;
+use crate::mir::passes::cse::MirCsePass;
+use crate::mir::passes::dead_code::MirDeadCodePass;
+use crate::mir::passes::dead_store::MirDeadStorePass;
+use crate::mir::passes::instcombine::MirInstCombinePass;
+use crate::mir::passes::promotion::MirPromotionPass;
+use crate::mir::passes::reassociation::MirReassociationPass;
+#[cfg(feature = "saturation")]
+use crate::mir::passes::saturation::MirSaturationPass;
+use crate::mir::passes::sccp::MirSccpPass;
+use crate::mir::passes::select::MirSelectPass;
+use crate::mir::passes::strength_reduction::MirStrengthReductionPass;
+use crate::mir::passes::value_range::MirValueRangePass;
+use crate::mir::visitor::MirVisitor;
+use std::fmt;
+
+/// The HIR passes `-O1` runs
+pub const DEFAULT_HIR_PASSES: &[&str] = &["simplify"];
+
+/// The MIR passes `-O1` runs, in order
+pub const DEFAULT_MIR_PASSES: &[&str] =
+    &["promote", "sccp", "instcombine", "reassociation", "strength-reduction", "cse", "value-range", "select", "dead-store", "dead-code"];
+
+/// The MIR passes `-Osat` runs, in order
+pub const SATURATION_MIR_PASSES: &[&str] =
+    &["promote", "sccp", "instcombine", "reassociation", "strength-reduction", "cse", "saturation", "value-range", "select", "dead-store", "dead-code"];
+
+/// A pass that transforms the AST
+pub trait HirPass {
+    fn run(&mut self, program: &mut Program);
+    fn diagnostics(&self) -> &DiagnosticCollector;
+}
+
+/// A pass that transforms MIR in SSA form
+pub trait MirPass {
+    fn run(&mut self, program: &mut MirProgram);
+    fn diagnostics(&self) -> &DiagnosticCollector;
+}
+
+/// What a pass is made with, from the options of the compiler
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PassOptions {
+    pub fast_math: bool,
+}
+
+/// Makes a fresh pass for each compilation
+pub enum PassFactory {
+    Hir(Box<dyn Fn(PassOptions) -> Box<dyn HirPass>>),
+    Mir(Box<dyn Fn(PassOptions) -> Box<dyn MirPass>>),
+}
+
+/// Passes by name
+pub struct PassRegistry {
+    passes: Vec<(String, PassFactory)>,
+}
+
+impl PassRegistry {
+    /// A registry of the passes Iris comes with
+    pub fn new() -> Self {
+        let mut registry = PassRegistry { passes: Vec::new() };
+        registry
+            .register_hir("simplify", |options| Box::new(ASTSimplificationPass::new(options.fast_math)))
+            .register_hir("hir-cse", |_| Box::new(HirCsePass::new()))
+            .register_mir("promote", |_| Box::new(MirPromotionPass::new()))
+            .register_mir("sccp", |_| Box::new(MirSccpPass::new()))
+            .register_mir("instcombine", |options| Box::new(MirInstCombinePass::new(options.fast_math)))
+            .register_mir("reassociation", |options| Box::new(MirReassociationPass::new(options.fast_math)))
+            .register_mir("strength-reduction", |_| Box::new(MirStrengthReductionPass::new()))
+            .register_mir("cse", |_| Box::new(MirCsePass::new()))
+            .register_mir("value-range", |_| Box::new(MirValueRangePass::new()))
+            .register_mir("select", |_| Box::new(MirSelectPass::new()))
+            .register_mir("dead-store", |_| Box::new(MirDeadStorePass::new()))
+            .register_mir("dead-code", |_| Box::new(MirDeadCodePass::new()));
+        #[cfg(feature = "saturation")]
+        registry.register_mir("saturation", |options| Box::new(MirSaturationPass::new(options.fast_math)));
+        registry
+    }
+
+    /// Registers a HIR pass, replacing any pass of the same name
+    pub fn register_hir(
+        &mut self,
+        name: &str,
+        factory: impl Fn(PassOptions) -> Box<dyn HirPass> + 'static,
+    ) -> &mut Self {
+        self.register(name, PassFactory::Hir(Box::new(factory)))
+    }
+
+    /// Registers a MIR pass, replacing any pass of the same name
+    pub fn register_mir(
+        &mut self,
+        name: &str,
+        factory: impl Fn(PassOptions) -> Box<dyn MirPass> + 'static,
+    ) -> &mut Self {
+        self.register(name, PassFactory::Mir(Box::new(factory)))
+    }
+
+    fn register(&mut self, name: &str, factory: PassFactory) -> &mut Self {
+        match self.passes.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => *existing = factory,
+            None => self.passes.push((name.to_string(), factory)),
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PassFactory> {
+        self.passes
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, factory)| factory)
+    }
+
+    /// The names of the registered passes, in the order they were registered
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl Default for PassRegistry {
+    fn default() -> Self {
+        PassRegistry::new()
+    }
+}
+
+impl fmt::Debug for PassRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl HirPass for ASTSimplificationPass {
+    fn run(&mut self, program: &mut Program) {
+        self.visit_program(program);
+    }
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        Visitor::diagnostics(self)
+    }
+}
+
+impl HirPass for HirCsePass {
+    fn run(&mut self, program: &mut Program) {
+        self.visit_program(program);
+    }
+
+    fn diagnostics(&self) -> &DiagnosticCollector {
+        Visitor::diagnostics(self)
+    }
+}
+
+macro_rules! impl_mir_pass {
+    ($($pass:ty),*) => {
+        $(impl MirPass for $pass {
+            fn run(&mut self, program: &mut MirProgram) {
+                <$pass>::run(self, program)
+            }
+
+            fn diagnostics(&self) -> &DiagnosticCollector {
+                MirVisitor::diagnostics(self)
+            }
+        })*
+    };
+}
+
+impl_mir_pass!(
+    MirPromotionPass,
+    MirSccpPass,
+    MirInstCombinePass,
+    MirReassociationPass,
+    MirStrengthReductionPass,
+    MirCsePass,
+    MirValueRangePass,
+    MirSelectPass,
+    MirDeadStorePass,
+    MirDeadCodePass
+);
+
+#[cfg(feature = "saturation")]
+impl_mir_pass!(MirSaturationPass);
@@ -1,5 +1,7 @@
+use std::fmt;
+
 /// Source location representing a span in the source code
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
     pub start_row: usize,
     pub start_column: usize,
@@ -17,15 +19,24 @@ impl Span {
         }
     }
 
+    /// The text the token was lexed from, which for a string literal is
+    /// longer than its lexeme
     pub fn from_token(token: &crate::frontend::Token) -> Self {
         Span {
             start_row: token.row,
             start_column: token.column,
-            end_row: token.row,
-            end_column: token.column + token.lexeme.len(),
+            end_row: token.end_row,
+            end_column: token.end_column,
         }
     }
 
+    /// The text the span covers in `source`, if it lies inside it
+    pub fn text<'a>(&self, source: &'a str) -> Option<&'a str> {
+        let start = offset(source, self.start_row, self.start_column)?;
+        let end = offset(source, self.end_row, self.end_column)?;
+        source.get(start..end)
+    }
+
     pub fn merge(start: &Span, end: &Span) -> Self {
         Span {
             start_row: start.start_row,
@@ -35,3 +46,32 @@ impl Span {
         }
     }
 }
+
+/// Byte offset of a row and a column counted in characters, if it is
+/// inside the source
+fn offset(source: &str, row: usize, column: usize) -> Option<usize> {
+    let start = match row {
+        0 => 0,
+        _ => source.match_indices('\n').nth(row - 1)?.0 + 1,
+    };
+    let line = source[start..].split('\n').next().unwrap_or("");
+    if column == line.chars().count() {
+        return Some(start + line.len());
+    }
+    line.char_indices().nth(column).map(|(i, _)| start + i)
+}
+
+/// Formats as `row:start-end` on a single line, `row:col-row:col` otherwise
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start_row == self.end_row {
+            write!(f, "{}:{}-{}", self.start_row, self.start_column, self.end_column)
+        } else {
+            write!(
+                f,
+                "{}:{}-{}:{}",
+                self.start_row, self.start_column, self.end_row, self.end_column
+            )
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Styling of what the compiler prints to a terminal.
+//!
+//! Everything that is printed for people to read, diagnostics and the dumps
+//! of the AST and the MIR alike, is styled through a [`Painter`]. A painter
+//! that is not enabled returns its text unchanged, so the same code prints
+//! plain text to files and pipes. Output for tools, such as JSON, is never
+//! styled.
+
+use std::io::IsTerminal;
+
+/// When to color output, as chosen with `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    Never,
+    /// Color output going to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+}
+
+/// The stream a [`ColorChoice`] is resolved for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl ColorChoice {
+    /// Whether output written to the stream should be colored
+    pub fn enabled_for(self, stream: Stream) -> bool {
+        match self {
+            ColorChoice::Never => false,
+            ColorChoice::Always => true,
+            ColorChoice::Auto => {
+                // https://no-color.org: set to anything but the empty string
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                let terminal = match stream {
+                    Stream::Stdout => std::io::stdout().is_terminal(),
+                    Stream::Stderr => std::io::stderr().is_terminal(),
+                };
+                !no_color && terminal
+            }
+        }
+    }
+}
+
+/// Wraps text in the escape codes of the role it plays, if enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Painter {
+    enabled: bool,
+}
+
+impl Painter {
+    pub fn new(enabled: bool) -> Self {
+        Painter { enabled }
+    }
+
+    pub fn for_stream(choice: ColorChoice, stream: Stream) -> Self {
+        Painter::new(choice.enabled_for(stream))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// The severity of an error, and failures
+    pub fn error(&self, text: &str) -> String {
+        self.paint("1;31", text)
+    }
+
+    pub fn warning(&self, text: &str) -> String {
+        self.paint("1;33", text)
+    }
+
+    /// Notes and help below a diagnostic
+    pub fn note(&self, text: &str) -> String {
+        self.paint("1;36", text)
+    }
+
+    pub fn success(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    /// Titles, and the names of functions and blocks in dumps
+    pub fn heading(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    /// Opcodes, terminators and the kinds of AST nodes
+    pub fn keyword(&self, text: &str) -> String {
+        self.paint("35", text)
+    }
+
+    /// Source locations and other annotations
+    pub fn comment(&self, text: &str) -> String {
+        self.paint("2", text)
+    }
+}
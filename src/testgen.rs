@@ -0,0 +1,495 @@
+//! Random Iris programs for testing the compiler.
+//!
+//! [`ProgramGenerator`] writes a program from a seed, the same one for the
+//! same seed every time, that parses and typechecks and always ends: loops
+//! count up to a small bound and functions only call the ones defined
+//! before them. Compiling many of them with `--verify-opts` shakes out
+//! crashes, programs rejected that should not be, and optimizations that
+//! change what a program does. `iris testgen` prints or checks them.
+
+/// The types of the values a generated program computes with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    F64,
+    F32,
+    Bool,
+    /// The enum of the program, if it declares one
+    Enum,
+    /// The newtype of `f64` of the program, if it declares one
+    Newtype,
+}
+
+/// The names types are written with in the generated program
+const ENUM_NAME: &str = "Shade";
+const NEWTYPE_NAME: &str = "Meters";
+const VARIANTS: [&str; 4] = ["Red", "Green", "Blue", "Gray"];
+
+/// Blocks and expressions are nested at most this deep
+const MAX_DEPTH: usize = 3;
+
+/// Loops run at most this many times
+const MAX_ITERATIONS: u64 = 4;
+
+struct Function {
+    name: String,
+    params: Vec<Type>,
+    /// `None` for functions that return nothing
+    returns: Option<Type>,
+}
+
+struct Variable {
+    name: String,
+    typ: Type,
+    /// Loop counters are only read, so that the loop ends
+    assignable: bool,
+}
+
+/// A small deterministic random number generator, splitmix64
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    /// True with the given chance in percent
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// Writes a random, well-typed program from a seed
+pub struct ProgramGenerator {
+    seed: u64,
+    rng: Rng,
+    out: String,
+    indent: usize,
+    variants: Vec<&'static str>,
+    newtype: bool,
+    /// Functions written so far, which are the ones that may be called
+    functions: Vec<Function>,
+    globals: Vec<Variable>,
+    scopes: Vec<Vec<Variable>>,
+    next_name: usize,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64) -> Self {
+        ProgramGenerator {
+            seed,
+            rng: Rng(seed),
+            out: String::new(),
+            indent: 0,
+            variants: Vec::new(),
+            newtype: false,
+            functions: Vec::new(),
+            globals: Vec::new(),
+            scopes: Vec::new(),
+            next_name: 0,
+        }
+    }
+
+    /// The source of the program
+    pub fn generate(mut self) -> String {
+        self.line(&format!("# Generated by `iris testgen --seed {}`", self.seed));
+        self.line("");
+
+        if self.rng.chance(60) {
+            let count = 2 + self.rng.below(VARIANTS.len() as u64 - 1) as usize;
+            self.variants = VARIANTS[..count].to_vec();
+            self.line(&format!("enum {} {{ {} }}", ENUM_NAME, self.variants.join(", ")));
+        }
+        if self.rng.chance(40) {
+            self.newtype = true;
+            self.line(&format!("newtype {} = f64", NEWTYPE_NAME));
+        }
+        for _ in 0..self.rng.below(3) {
+            let typ = self.any_type();
+            let name = self.fresh("g");
+            let value = self.literal(typ);
+            self.line(&format!("var {}: {} = {}", name, self.type_name(typ), value));
+            self.globals.push(Variable {
+                name,
+                typ,
+                assignable: true,
+            });
+        }
+        self.line("");
+
+        for _ in 0..1 + self.rng.below(4) {
+            let params = (0..self.rng.below(4)).map(|_| self.any_type()).collect();
+            let returns = if self.rng.chance(80) { Some(self.any_type()) } else { None };
+            let name = self.fresh("func");
+            self.function(&name, None, params, returns);
+        }
+        for _ in 0..self.rng.below(3) {
+            let name = self.fresh("test_");
+            self.function(&name, Some("@test"), Vec::new(), Some(Type::Bool));
+        }
+        self.function("main", None, Vec::new(), Some(Type::F64));
+        self.out
+    }
+
+    fn function(&mut self, name: &str, attribute: Option<&str>, params: Vec<Type>, returns: Option<Type>) {
+        let variables: Vec<Variable> = params
+            .iter()
+            .map(|&typ| Variable {
+                name: self.fresh("p"),
+                typ,
+                assignable: true,
+            })
+            .collect();
+        let signature: Vec<String> = variables
+            .iter()
+            .map(|variable| format!("{}: {}", variable.name, self.type_name(variable.typ)))
+            .collect();
+        let result = returns.map_or(String::new(), |typ| format!(" -> {}", self.type_name(typ)));
+
+        if let Some(attribute) = attribute {
+            self.line(attribute);
+        }
+        self.line(&format!("fn {}({}){} {{", name, signature.join(", "), result));
+        self.indent += 1;
+        self.scopes.push(variables);
+        for _ in 0..1 + self.rng.below(5) {
+            self.statement(MAX_DEPTH);
+        }
+        if let Some(typ) = returns {
+            let value = self.expression(typ, MAX_DEPTH);
+            self.line(&format!("return {}", value));
+        }
+        self.scopes.pop();
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+
+        self.functions.push(Function {
+            name: name.to_string(),
+            params,
+            returns,
+        });
+    }
+
+    /// Writes the statements of a block, which the caller opens and closes
+    fn block(&mut self, depth: usize) {
+        self.indent += 1;
+        self.scopes.push(Vec::new());
+        for _ in 0..1 + self.rng.below(3) {
+            self.statement(depth);
+        }
+        self.scopes.pop();
+        self.indent -= 1;
+    }
+
+    fn statement(&mut self, depth: usize) {
+        let nested = depth > 0;
+        match self.rng.below(10) {
+            3 | 4 if self.assign() => {}
+            5 if nested => self.if_statement(depth - 1),
+            6 if nested => self.loop_statement(depth - 1),
+            7 if nested => self.match_statement(depth - 1),
+            8 if self.call_statement() => {}
+            9 if self.rng.chance(30) => {
+                let condition = self.expression(Type::Bool, 2);
+                self.line(&format!("assert {}", condition));
+            }
+            _ => {
+                let typ = self.any_type();
+                let value = self.expression(typ, MAX_DEPTH);
+                let name = self.declare(typ, true);
+                self.line(&format!("var {}: {} = {}", name, self.type_name(typ), value));
+            }
+        }
+    }
+
+    fn assign(&mut self) -> bool {
+        let targets: Vec<(String, Type)> = self
+            .variables()
+            .filter(|variable| variable.assignable)
+            .map(|variable| (variable.name.clone(), variable.typ))
+            .collect();
+        if targets.is_empty() {
+            return false;
+        }
+        let (name, typ) = self.rng.pick(&targets).clone();
+        let value = self.expression(typ, MAX_DEPTH);
+        self.line(&format!("{} = {}", name, value));
+        true
+    }
+
+    fn call_statement(&mut self) -> bool {
+        let callees: Vec<usize> = (0..self.functions.len())
+            .filter(|&index| self.functions[index].returns.is_none())
+            .collect();
+        if callees.is_empty() {
+            return false;
+        }
+        let callee = *self.rng.pick(&callees);
+        let call = self.call(callee, 2);
+        self.line(&call);
+        true
+    }
+
+    fn if_statement(&mut self, depth: usize) {
+        let condition = self.expression(Type::Bool, 2);
+        self.line(&format!("if {} {{", condition));
+        self.block(depth);
+        if self.rng.chance(50) {
+            self.line("} else {");
+            self.block(depth);
+        }
+        self.line("}");
+    }
+
+    /// A `while` or `do`-`while` loop counting up to a small bound
+    fn loop_statement(&mut self, depth: usize) {
+        let bound = 1 + self.rng.below(MAX_ITERATIONS);
+        let counter = self.declare(Type::F64, false);
+        self.line(&format!("var {} = 0", counter));
+        let do_while = self.rng.chance(30);
+        if do_while {
+            self.line("do {");
+        } else {
+            self.line(&format!("while {} < {} {{", counter, bound));
+        }
+        self.block(depth);
+        self.indent += 1;
+        self.line(&format!("{} = {} + 1", counter, counter));
+        self.indent -= 1;
+        if do_while {
+            self.line(&format!("}} while {} < {}", counter, bound));
+        } else {
+            self.line("}");
+        }
+    }
+
+    fn match_statement(&mut self, depth: usize) {
+        let mut types = vec![Type::F64, Type::Bool];
+        if !self.variants.is_empty() {
+            types.push(Type::Enum);
+        }
+        let typ = *self.rng.pick(&types);
+        let scrutinee = self.expression(typ, 2);
+        let mut patterns: Vec<String> = match typ {
+            Type::Bool => vec!["true".to_string(), "false".to_string()],
+            Type::Enum => self
+                .variants
+                .iter()
+                .map(|variant| format!("{}::{}", ENUM_NAME, variant))
+                .collect(),
+            _ => {
+                let start = self.rng.below(5) as i64 - 2;
+                (start..start + 1 + self.rng.below(3) as i64).map(|value| value.to_string()).collect()
+            }
+        };
+        // Numbers always need a wildcard, the others only if an arm is left out
+        let wildcard = typ == Type::F64 || self.rng.chance(40);
+        if wildcard && typ != Type::F64 {
+            patterns.truncate(patterns.len() - 1);
+        }
+        if wildcard {
+            patterns.push("_".to_string());
+        }
+
+        self.line(&format!("match {} {{", scrutinee));
+        self.indent += 1;
+        for pattern in patterns {
+            self.line(&format!("{} => {{", pattern));
+            self.block(depth);
+            self.line("}");
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn expression(&mut self, typ: Type, depth: usize) -> String {
+        if depth == 0 || self.rng.chance(30) {
+            return self.leaf(typ);
+        }
+        let depth = depth - 1;
+        match typ {
+            Type::Bool => match self.rng.below(6) {
+                0 | 1 => {
+                    let operand = *self.rng.pick(&self.numeric_types());
+                    let op = *self.rng.pick(&["<", ">", "<=", ">=", "==", "!="]);
+                    let (lhs, rhs) = (self.expression(operand, depth), self.expression(operand, depth));
+                    format!("({} {} {})", lhs, op, rhs)
+                }
+                2 => {
+                    let op = *self.rng.pick(&["&&", "||"]);
+                    let (lhs, rhs) = (self.expression(Type::Bool, depth), self.expression(Type::Bool, depth));
+                    format!("({} {} {})", lhs, op, rhs)
+                }
+                3 => format!("!{}", self.parenthesized(Type::Bool, depth)),
+                4 if !self.variants.is_empty() => {
+                    let op = *self.rng.pick(&["==", "!="]);
+                    let (lhs, rhs) = (self.expression(Type::Enum, depth), self.expression(Type::Enum, depth));
+                    format!("({} {} {})", lhs, op, rhs)
+                }
+                4 => {
+                    let hint = *self.rng.pick(&["likely", "unlikely"]);
+                    format!("{}({})", hint, self.expression(Type::Bool, depth))
+                }
+                _ => self.call_returning(typ, depth),
+            },
+            Type::Enum => self.call_returning(typ, depth),
+            Type::F64 | Type::F32 | Type::Newtype => match self.rng.below(6) {
+                0..=2 => {
+                    let op = *self.rng.pick(&["+", "-", "*", "/", "%", "**"]);
+                    let (lhs, rhs) = (self.expression(typ, depth), self.expression(typ, depth));
+                    format!("({} {} {})", lhs, op, rhs)
+                }
+                3 => format!("-{}", self.parenthesized(typ, depth)),
+                4 if typ == Type::Newtype => {
+                    format!("({} as {})", self.parenthesized(Type::F64, depth), NEWTYPE_NAME)
+                }
+                4 if typ == Type::F64 && self.newtype && self.rng.chance(50) => {
+                    format!("({} as f64)", self.parenthesized(Type::Newtype, depth))
+                }
+                4 if typ == Type::F64 => format!("({} as f64)", self.parenthesized(Type::F32, depth)),
+                4 => format!("({} as f32)", self.parenthesized(Type::F64, depth)),
+                _ => self.call_returning(typ, depth),
+            },
+        }
+    }
+
+    /// An expression of the type that is safe to put an operator before
+    fn parenthesized(&mut self, typ: Type, depth: usize) -> String {
+        let expression = self.expression(typ, depth);
+        let name = expression.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if name || expression.starts_with('(') && expression.ends_with(')') {
+            expression
+        } else {
+            format!("({})", expression)
+        }
+    }
+
+    /// A call to a function returning the type, or a leaf if there is none
+    fn call_returning(&mut self, typ: Type, depth: usize) -> String {
+        let callees: Vec<usize> = (0..self.functions.len())
+            .filter(|&index| self.functions[index].returns == Some(typ))
+            .collect();
+        if callees.is_empty() {
+            return self.leaf(typ);
+        }
+        let callee = *self.rng.pick(&callees);
+        self.call(callee, depth)
+    }
+
+    fn call(&mut self, callee: usize, depth: usize) -> String {
+        let params = self.functions[callee].params.clone();
+        let args: Vec<String> = params.iter().map(|&typ| self.expression(typ, depth)).collect();
+        format!("{}({})", self.functions[callee].name, args.join(", "))
+    }
+
+    /// A variable or a literal
+    fn leaf(&mut self, typ: Type) -> String {
+        let names: Vec<String> = self
+            .variables()
+            .filter(|variable| variable.typ == typ)
+            .map(|variable| variable.name.clone())
+            .collect();
+        if !names.is_empty() && self.rng.chance(60) {
+            return self.rng.pick(&names).clone();
+        }
+        self.literal(typ)
+    }
+
+    fn literal(&mut self, typ: Type) -> String {
+        match typ {
+            Type::Bool => self.rng.chance(50).to_string(),
+            Type::Enum => format!("{}::{}", ENUM_NAME, self.rng.pick(&self.variants)),
+            // Number literals are `f64`
+            Type::Newtype => format!("({} as {})", self.number(), NEWTYPE_NAME),
+            Type::F32 => format!("({} as f32)", self.number()),
+            Type::F64 => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> String {
+        let number = match self.rng.below(4) {
+            0 => format!("{}.{}", self.rng.below(10), 1 + self.rng.below(99)),
+            1 => (self.rng.below(1000) * 1000).to_string(),
+            _ => self.rng.below(10).to_string(),
+        };
+        if self.rng.chance(20) {
+            format!("(-{})", number)
+        } else {
+            number
+        }
+    }
+
+    fn any_type(&mut self) -> Type {
+        let mut types = vec![Type::F64, Type::F64, Type::F32, Type::Bool];
+        if !self.variants.is_empty() {
+            types.push(Type::Enum);
+        }
+        if self.newtype {
+            types.push(Type::Newtype);
+        }
+        *self.rng.pick(&types)
+    }
+
+    /// The types arithmetic and ordering work on
+    fn numeric_types(&self) -> Vec<Type> {
+        let mut types = vec![Type::F64, Type::F32];
+        if self.newtype {
+            types.push(Type::Newtype);
+        }
+        types
+    }
+
+    fn type_name(&self, typ: Type) -> &'static str {
+        match typ {
+            Type::F64 => "f64",
+            Type::F32 => "f32",
+            Type::Bool => "bool",
+            Type::Enum => ENUM_NAME,
+            Type::Newtype => NEWTYPE_NAME,
+        }
+    }
+
+    /// Every variable in scope, globals first
+    fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.globals.iter().chain(self.scopes.iter().flatten())
+    }
+
+    /// Adds a variable to the innermost scope and returns its name
+    fn declare(&mut self, typ: Type, assignable: bool) -> String {
+        let name = self.fresh(if assignable { "v" } else { "i" });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Variable {
+                name: name.clone(),
+                typ,
+                assignable,
+            });
+        }
+        name
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.out.push_str(&"    ".repeat(self.indent));
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+}
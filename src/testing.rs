@@ -0,0 +1,158 @@
+//! Discovery and execution of `@test` functions, and comparison of how
+//! two versions of a program run.
+
+use crate::intern::Symbol;
+use crate::mir::interpreter::{Interpreter, Trap};
+use crate::mir::{FuncId, MirProgram, MirType};
+use crate::value::Value;
+
+/// The outcome of running a single `@test` function
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: FuncId,
+    /// Why the test failed, or `None` if it passed
+    pub failure: Option<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Runs every function marked `@test`, in the order they were defined.
+///
+/// A test passes if it returns `true`, zero or nothing. Returning `false`
+/// or a nonzero number fails it, as does a trap. Every test starts from
+/// freshly initialized globals, so tests cannot affect each other.
+pub fn run_tests(program: &MirProgram) -> Vec<TestResult> {
+    run_tests_with_coverage(program).0
+}
+
+/// Runs the tests like [`run_tests`], and also returns the coverage counts
+/// added up over all of them, in the order of [`MirProgram::coverage`]
+pub fn run_tests_with_coverage(program: &MirProgram) -> (Vec<TestResult>, Vec<u64>) {
+    let mut interpreter = Interpreter::new(program);
+    let mut counts = vec![0; program.coverage.len()];
+    let results = program
+        .functions
+        .iter()
+        .filter(|function| function.test)
+        .map(|function| {
+            let result = interpreter
+                .initialize()
+                .and_then(|()| interpreter.call(function.name, &[]));
+            // The counters start from zero again with the next test
+            for (count, counter) in counts.iter_mut().zip(&program.coverage) {
+                if let Some(Value::Int(value)) = interpreter.global(counter.global) {
+                    *count += value as u64;
+                }
+            }
+            let failure = match result {
+                Ok(None) | Ok(Some(Value::Bool(true))) => None,
+                Ok(Some(value)) if value.typ() != MirType::I1 && value.is_zero() => None,
+                Ok(Some(value)) => Some(format!("returned {}", value)),
+                Err(trap) => Some(trap.to_string()),
+            };
+            TestResult {
+                name: function.name,
+                failure,
+            }
+        })
+        .collect();
+    (results, counts)
+}
+
+/// A function that ran differently in two versions of a program
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub name: FuncId,
+    /// How the function ran in the first version
+    pub before: String,
+    /// How it ran in the second
+    pub after: String,
+}
+
+/// Runs `main` and every `@test` function that takes no arguments in both
+/// versions of a program, each from freshly initialized globals, and
+/// returns those whose result or globals differ between them.
+///
+/// Calls that trap agree whatever their messages, since those name
+/// registers the passes renumber, but the globals they leave are still
+/// compared, since an embedder can read them. The counters of
+/// `--coverage` are not compared, since they count calls to pure
+/// functions that optimization may merge or remove. Calls that either
+/// version gives up on, running too long or too deep, are not compared.
+/// Unless `compare_floats` is set, floats only have to agree on which
+/// values are NaN, for optimizations that may change rounding.
+pub fn compare_runs(before: &MirProgram, after: &MirProgram, compare_floats: bool) -> Vec<Divergence> {
+    let main = Symbol::intern("main");
+    let mut interpreters = (Interpreter::new(before), Interpreter::new(after));
+    before
+        .functions
+        .iter()
+        .filter(|function| (function.name == main || function.test) && function.params.is_empty())
+        .filter_map(|function| {
+            let run = |interpreter: &mut Interpreter, program: &MirProgram| {
+                let result = interpreter.initialize().and_then(|()| interpreter.call(function.name, &[]));
+                let globals: Vec<Option<Value>> =
+                    program.globals.iter().map(|global| interpreter.global(global.name)).collect();
+                (result, globals)
+            };
+            let (result_before, globals_before) = run(&mut interpreters.0, before);
+            let (result_after, _) = run(&mut interpreters.1, after);
+            if [&result_before, &result_after].iter().any(|result| result.as_ref().is_err_and(Trap::gave_up)) {
+                return None;
+            }
+
+            let agree = |a: &Option<Value>, b: &Option<Value>| match (a, b) {
+                (Some(a), Some(b)) => same_value(*a, *b, compare_floats),
+                (a, b) => a.is_none() && b.is_none(),
+            };
+            let describe = |result: &Result<Option<Value>, Trap>| match result {
+                Ok(Some(value)) => format!("returned {}", value),
+                Ok(None) => "returned nothing".to_string(),
+                Err(trap) => format!("stopped with a {}", trap),
+            };
+            let (before, after) = match (&result_before, &result_after) {
+                (Ok(a), Ok(b)) if !agree(a, b) => (describe(&result_before), describe(&result_after)),
+                (Ok(_), Err(_)) | (Err(_), Ok(_)) => (describe(&result_before), describe(&result_after)),
+                // Globals the passes removed were never read
+                _ => {
+                    let changed = before.globals.iter().zip(&globals_before).find(|(global, value)| {
+                        if before.coverage.iter().any(|counter| counter.global == global.name) {
+                            return false;
+                        }
+                        let value_after = interpreters.1.global(global.name);
+                        value_after.is_some() && !agree(value, &value_after)
+                    })?;
+                    let (global, value) = changed;
+                    let value_after = interpreters.1.global(global.name);
+                    let show = |value: &Option<Value>| value.map_or("nothing".to_string(), |value| value.to_string());
+                    (
+                        format!("left {} in global '{}'", show(value), global.name),
+                        format!("left {}", show(&value_after)),
+                    )
+                }
+            };
+            Some(Divergence {
+                name: function.name,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+/// Whether two results are the same, NaN matching any NaN
+fn same_value(a: Value, b: Value, compare_floats: bool) -> bool {
+    match (a, b) {
+        (Value::F64(a), Value::F64(b)) => {
+            a.is_nan() == b.is_nan() && (a.is_nan() || !compare_floats || a.to_bits() == b.to_bits())
+        }
+        (Value::F32(a), Value::F32(b)) => {
+            a.is_nan() == b.is_nan() && (a.is_nan() || !compare_floats || a.to_bits() == b.to_bits())
+        }
+        (a, b) => a == b,
+    }
+}
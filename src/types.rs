@@ -1,8 +1,10 @@
-use crate::ast::Block;
+use crate::ast::{Attribute, Block};
 use crate::frontend::TokenType;
+use crate::span::Span;
 use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BaseType {
     F8,
     F16,
@@ -13,10 +15,23 @@ pub enum BaseType {
     Auto,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Base(BaseType),
     PointerType(Box<Type>),
+    /// A type referred to by name, before the typechecker resolves it
+    Named(String),
+    /// A type declared with `newtype`: it has the representation and
+    /// operations of `underlying` but is only equal to itself
+    Newtype {
+        name: String,
+        underlying: Box<Type>,
+    },
+    /// A type declared with `enum`, whose values are its variants
+    Enum {
+        name: String,
+        variants: Vec<String>,
+    },
 }
 
 impl Type {
@@ -27,20 +42,42 @@ impl Type {
             (Type::Base(BaseType::Auto), _) => true,
             (_, Type::Base(BaseType::Auto)) => true,
             // Otherwise check exact equality
-            (Type::Base(a), Type::Base(b)) => match (a, b) {
-                (BaseType::F8, BaseType::F8) => true,
-                (BaseType::F16, BaseType::F16) => true,
-                (BaseType::F32, BaseType::F32) => true,
-                (BaseType::F64, BaseType::F64) => true,
-                (BaseType::Bool, BaseType::Bool) => true,
-                (BaseType::Void, BaseType::Void) => true,
-                _ => false,
-            },
+            (Type::Base(a), Type::Base(b)) => matches!(
+                (a, b),
+                (BaseType::F8, BaseType::F8)
+                    | (BaseType::F16, BaseType::F16)
+                    | (BaseType::F32, BaseType::F32)
+                    | (BaseType::F64, BaseType::F64)
+                    | (BaseType::Bool, BaseType::Bool)
+                    | (BaseType::Void, BaseType::Void)
+            ),
             (Type::PointerType(a), Type::PointerType(b)) => a.is_equal(b),
+            (Type::Named(a), Type::Named(b)) => a == b,
+            (Type::Newtype { name: a, .. }, Type::Newtype { name: b, .. }) => a == b,
+            (Type::Enum { name: a, .. }, Type::Enum { name: b, .. }) => a == b,
             _ => false,
         }
     }
 
+    /// The number of bits of a float type, `None` for other types
+    pub fn float_width(&self) -> Option<u32> {
+        match self {
+            Type::Base(BaseType::F8) => Some(8),
+            Type::Base(BaseType::F16) => Some(16),
+            Type::Base(BaseType::F32) => Some(32),
+            Type::Base(BaseType::F64) => Some(64),
+            _ => None,
+        }
+    }
+
+    /// The representation of the type, with every newtype unwrapped
+    pub fn underlying(&self) -> &Type {
+        match self {
+            Type::Newtype { underlying, .. } => underlying.underlying(),
+            _ => self,
+        }
+    }
+
     /// Check if this type can be used with another in a binary operation
     /// Returns the result type if compatible, None if not
     pub fn binop_result(&self, op: &TokenType, other: &Type) -> Option<Type> {
@@ -49,6 +86,21 @@ impl Type {
             return None;
         }
 
+        // Newtypes support the operations of their underlying type, and
+        // results of that type stay wrapped
+        if let Type::Newtype { underlying, .. } = self {
+            let result = underlying.binop_result(op, underlying)?;
+            return Some(if result.is_equal(underlying) { self.clone() } else { result });
+        }
+
+        // Variants can only be told apart
+        if let Type::Enum { .. } = self {
+            return match op {
+                TokenType::Equal | TokenType::NotEqual => Some(Type::Base(BaseType::Bool)),
+                _ => None,
+            };
+        }
+
         // Determine result type based on operator
         match op {
             // Comparison operators return Bool
@@ -75,6 +127,9 @@ impl Type {
             | TokenType::Slash
             | TokenType::Percent => Some(self.clone()),
 
+            // Powers are only computed for floats
+            TokenType::StarStar => self.float_width().map(|_| self.clone()),
+
             _ => None,
         }
     }
@@ -82,6 +137,15 @@ impl Type {
     /// Check if this type can be used with a unary operation
     /// Returns the result type if compatible, None if not
     pub fn unary_op_result(&self, op: &TokenType) -> Option<Type> {
+        if let Type::Newtype { underlying, .. } = self {
+            let result = underlying.unary_op_result(op)?;
+            return Some(if result.is_equal(underlying) { self.clone() } else { result });
+        }
+
+        if let Type::Enum { .. } = self {
+            return None;
+        }
+
         match op {
             TokenType::Bang => {
                 // ! (not) only works on Bool operands
@@ -100,7 +164,47 @@ impl Type {
     }
 }
 
+/// Types as they are written in source
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Base(base) => {
+                let name = match base {
+                    BaseType::F8 => "f8",
+                    BaseType::F16 => "f16",
+                    BaseType::F32 => "f32",
+                    BaseType::F64 => "f64",
+                    BaseType::Bool => "bool",
+                    BaseType::Void => "void",
+                    BaseType::Auto => "auto",
+                };
+                write!(f, "{}", name)
+            }
+            Type::PointerType(inner) => write!(f, "*{}", inner),
+            Type::Named(name) | Type::Newtype { name, .. } | Type::Enum { name, .. } => {
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
+
+/// A distinct type declared at the top level with `newtype Name = T`
 #[derive(Debug, Clone)]
+pub struct Newtype {
+    pub name: String,
+    pub underlying: Type,
+    pub span: Span,
+}
+
+/// A type declared at the top level with `enum Name { A, B, ... }`
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<String>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Variable {
     pub name: String,
     pub typ: Type,
@@ -124,10 +228,94 @@ impl Scope {
     }
 }
 
+/// A built-in requirement on the types a type parameter may be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// Supports arithmetic: the float types and newtypes of them
+    Numeric,
+    /// Supports ordering with `<` and friends: numeric types and bool
+    Comparable,
+}
+
+impl Bound {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Numeric" => Some(Bound::Numeric),
+            "Comparable" => Some(Bound::Comparable),
+            _ => None,
+        }
+    }
+
+    /// The bound a type parameter needs for `op` to be applied to it
+    pub fn required_by(op: &TokenType) -> Option<Self> {
+        match op {
+            TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Percent
+            | TokenType::StarStar => Some(Bound::Numeric),
+            TokenType::Less
+            | TokenType::Greater
+            | TokenType::LessEqual
+            | TokenType::GreaterEqual => Some(Bound::Comparable),
+            _ => None,
+        }
+    }
+
+    /// Returns true if every type satisfying `self` also satisfies `other`
+    pub fn implies(&self, other: Bound) -> bool {
+        *self == other || (*self == Bound::Numeric && other == Bound::Comparable)
+    }
+
+    pub fn is_satisfied_by(&self, typ: &Type) -> bool {
+        matches!(
+            (self, typ.underlying()),
+            (_, Type::Base(BaseType::F8 | BaseType::F16 | BaseType::F32 | BaseType::F64))
+                | (Bound::Comparable, Type::Base(BaseType::Bool))
+        )
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Numeric => write!(f, "Numeric"),
+            Bound::Comparable => write!(f, "Comparable"),
+        }
+    }
+}
+
+/// A type parameter of a generic function: `T` or `T: Numeric + Comparable`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TypeParam {
+    pub name: String,
+    /// Names of the bounds, as written
+    pub bounds: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
+    /// Type parameters of a generic function, empty otherwise
+    pub type_params: Vec<TypeParam>,
     pub args: Vec<Variable>,
     pub return_type: Type,
+    /// Attributes written before the definition, in source order
+    pub attrs: Vec<Attribute>,
     pub body: Block,
+    pub span: Span,
+    /// Name of the source the function was defined in
+    pub source: String,
+}
+
+impl Function {
+    /// The attribute with the given name, if the function has it
+    pub fn attr(&self, name: &str) -> Option<&Attribute> {
+        self.attrs.iter().find(|attr| attr.name == name)
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attr(name).is_some()
+    }
 }
@@ -0,0 +1,180 @@
+//! Values of Iris programs as Rust sees them.
+//!
+//! The interpreter computes with them, the embedding API passes them to
+//! and from Rust, see [`crate::embed`], and the test runner reports what a
+//! failing test returned with them. Each MIR type is held by one variant,
+//! see [`Value::convert`]; `f8` and `f16` have no Rust type, so they are
+//! held as [`Value::F64`] and computed at that precision.
+//!
+//! Floats are written the same way everywhere the compiler shows them, see
+//! [`format_float`].
+
+use crate::mir::MirType;
+use std::fmt;
+
+/// A value of an Iris program
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    F64(f64),
+    F32(f32),
+    Bool(bool),
+    /// The integer types, which hold the variants of enums and the
+    /// counters of coverage
+    Int(i64),
+}
+
+impl Value {
+    /// The MIR type of the values the variant holds, taking `F64` for the
+    /// narrower floats it also holds and `I64` for every integer type
+    pub fn typ(&self) -> MirType {
+        match self {
+            Value::F64(_) => MirType::F64,
+            Value::F32(_) => MirType::F32,
+            Value::Bool(_) => MirType::I1,
+            Value::Int(_) => MirType::I64,
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Value::F64(value) => value,
+            Value::F32(value) => value as f64,
+            Value::Int(value) => value as f64,
+            Value::Bool(value) => value as u8 as f64,
+        }
+    }
+
+    pub fn as_i64(self) -> i64 {
+        match self {
+            Value::F64(value) => value as i64,
+            Value::F32(value) => value as i64,
+            Value::Int(value) => value,
+            Value::Bool(value) => value as i64,
+        }
+    }
+
+    pub fn as_bool(self) -> bool {
+        match self {
+            Value::F64(value) => value != 0.0,
+            Value::F32(value) => value != 0.0,
+            Value::Int(value) => value != 0,
+            Value::Bool(value) => value,
+        }
+    }
+
+    /// Whether the value is zero or false
+    pub fn is_zero(self) -> bool {
+        !self.as_bool()
+    }
+
+    /// The value represented in the given type. Floats narrower than `F32`
+    /// have no host type, so they are computed at `F64` precision.
+    pub fn convert(self, typ: MirType) -> Value {
+        match typ {
+            MirType::F32 => Value::F32(self.as_f64() as f32),
+            MirType::F8 | MirType::F16 | MirType::F64 => Value::F64(self.as_f64()),
+            MirType::I1 => Value::Bool(self.as_bool()),
+            MirType::I8 => Value::Int(self.as_i64() as i8 as i64),
+            MirType::I16 => Value::Int(self.as_i64() as i16 as i64),
+            MirType::I32 => Value::Int(self.as_i64() as i32 as i64),
+            MirType::I64 => Value::Int(self.as_i64()),
+            MirType::Void => self,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::F64(value) => write!(f, "{}", format_float(*value)),
+            Value::F32(value) => write!(f, "{}", format_f32(*value)),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Writes a float the shortest way that reads back as the same value,
+/// always with a decimal point or an exponent so that it cannot be taken
+/// for an integer: `1.0`, `0.1`, `1e300`, `-0.0`. NaN and the infinities
+/// are written `NaN`, `inf` and `-inf`.
+pub fn format_float(value: f64) -> String {
+    format!("{:?}", value)
+}
+
+/// Writes an `f32` like [`format_float`], the shortest way that reads back
+/// as the same `f32`
+pub fn format_f32(value: f32) -> String {
+    format!("{:?}", value)
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::F64(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::F32(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+/// Takes the value out of the variant of its Rust type, giving back any
+/// other value unchanged
+impl TryFrom<Value> for f64 {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Value> {
+        match value {
+            Value::F64(value) => Ok(value),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<Value> for f32 {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Value> {
+        match value {
+            Value::F32(value) => Ok(value),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Value> {
+        match value {
+            Value::Bool(value) => Ok(value),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Value> {
+        match value {
+            Value::Int(value) => Ok(value),
+            other => Err(other),
+        }
+    }
+}